@@ -0,0 +1,542 @@
+// lib.rs
+//! The workloads behind `cache-bench` and what it reports. A workload is a seeded sequence
+//! of reads and writes over a fixed set of keys, so two runs with the same settings send
+//! the same requests and can be compared release to release.
+use bytes::Bytes;
+use cache_client::CacheClient;
+use futures::future::join_all;
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// A splitmix64 generator; small, fast and the same on every platform.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Sizes of the objects written, e.g. `65536`, `uniform:1024-1048576` or
+/// `log-uniform:1024-104857600`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeDistribution {
+    Fixed(u64),
+    Uniform(u64, u64),
+    /// Every order of magnitude between the bounds is as likely, so small objects are
+    /// common and large ones still turn up.
+    LogUniform(u64, u64),
+}
+
+impl SizeDistribution {
+    pub fn sample(&self, rng: &mut Rng) -> u64 {
+        match *self {
+            SizeDistribution::Fixed(size) => size,
+            SizeDistribution::Uniform(min, max) => min + rng.next_u64() % (max - min + 1),
+            SizeDistribution::LogUniform(min, max) => {
+                let (low, high) = ((min as f64).ln(), (max as f64).ln());
+                ((low + rng.next_f64() * (high - low)).exp() as u64).clamp(min, max)
+            }
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        match *self {
+            SizeDistribution::Fixed(size) => size,
+            SizeDistribution::Uniform(_, max) | SizeDistribution::LogUniform(_, max) => max,
+        }
+    }
+}
+
+impl Default for SizeDistribution {
+    fn default() -> Self {
+        SizeDistribution::Fixed(64 * 1024)
+    }
+}
+
+impl FromStr for SizeDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid size distribution {}", s);
+        let bounds = |range: &str| -> Result<(u64, u64), String> {
+            let (min, max) = range.split_once('-').ok_or_else(invalid)?;
+            let (min, max) = (
+                min.parse().map_err(|_| invalid())?,
+                max.parse().map_err(|_| invalid())?,
+            );
+            if min == 0 || min > max {
+                return Err(format!("{}: need 0 < min <= max", s));
+            }
+            Ok((min, max))
+        };
+        if let Some(range) = s.strip_prefix("uniform:") {
+            let (min, max) = bounds(range)?;
+            return Ok(SizeDistribution::Uniform(min, max));
+        }
+        if let Some(range) = s.strip_prefix("log-uniform:") {
+            let (min, max) = bounds(range)?;
+            return Ok(SizeDistribution::LogUniform(min, max));
+        }
+        match s.strip_prefix("fixed:").unwrap_or(s).parse() {
+            Ok(size) if size > 0 => Ok(SizeDistribution::Fixed(size)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for SizeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SizeDistribution::Fixed(size) => write!(f, "{}", size),
+            SizeDistribution::Uniform(min, max) => write!(f, "uniform:{}-{}", min, max),
+            SizeDistribution::LogUniform(min, max) => write!(f, "log-uniform:{}-{}", min, max),
+        }
+    }
+}
+
+/// What to send, and how much of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    /// Number of distinct keys, named `<prefix><index>`.
+    pub keys: u64,
+    pub prefix: String,
+    /// Skew of key popularity: the key of rank `r` is picked in proportion to
+    /// `1 / r^zipf_exponent`. 0 picks every key alike.
+    pub zipf_exponent: f64,
+    /// Sizes of written objects; reads get whatever the objects are.
+    pub sizes: SizeDistribution,
+    /// Share of operations, from 0 to 1, that are writes.
+    pub write_ratio: f64,
+    /// Requests in flight at once.
+    pub concurrency: usize,
+    /// Operations to send, unless `duration` is set.
+    pub requests: u64,
+    /// Send operations for this long instead of a fixed number.
+    pub duration: Option<Duration>,
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            keys: 1000,
+            prefix: String::from("bench/"),
+            zipf_exponent: 0.99,
+            sizes: SizeDistribution::default(),
+            write_ratio: 0.0,
+            concurrency: 16,
+            requests: 10_000,
+            duration: None,
+            seed: 1,
+        }
+    }
+}
+
+impl Workload {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.keys == 0 {
+            return Err(String::from("keys must be greater than 0"));
+        }
+        if !(self.zipf_exponent >= 0.0 && self.zipf_exponent.is_finite()) {
+            return Err(String::from("zipf exponent must be 0 or more"));
+        }
+        if !(0.0..=1.0).contains(&self.write_ratio) {
+            return Err(String::from("write ratio must be between 0 and 1"));
+        }
+        if self.concurrency == 0 {
+            return Err(String::from("concurrency must be greater than 0"));
+        }
+        Ok(())
+    }
+
+    pub fn key(&self, index: u64) -> String {
+        format!("{}{}", self.prefix, index)
+    }
+}
+
+/// Picks key indexes with Zipfian popularity, the first index being the most popular.
+#[derive(Debug, Clone)]
+pub struct Zipf {
+    /// Cumulative probability of each index.
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    pub fn new(keys: u64, exponent: f64) -> Self {
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = (1..=keys)
+            .map(|rank| {
+                total += 1.0 / (rank as f64).powf(exponent);
+                total
+            })
+            .collect();
+        for p in &mut cdf {
+            *p /= total;
+        }
+        Zipf { cdf }
+    }
+
+    pub fn sample(&self, rng: &mut Rng) -> u64 {
+        let u = rng.next_f64();
+        (self.cdf.partition_point(|&p| p <= u) as u64).min(self.cdf.len() as u64 - 1)
+    }
+}
+
+/// One request of a workload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Read(String),
+    Write(String, u64),
+}
+
+/// The operations of one worker, decided by the workload's seed and the worker's index.
+pub struct OpStream {
+    workload: Arc<Workload>,
+    zipf: Arc<Zipf>,
+    rng: Rng,
+}
+
+impl OpStream {
+    pub fn new(workload: Arc<Workload>, zipf: Arc<Zipf>, worker: u64) -> Self {
+        let mut seeder = Rng::new(workload.seed ^ worker.wrapping_mul(0xd1b5_4a32_d192_ed03));
+        let rng = Rng::new(seeder.next_u64());
+        OpStream {
+            workload,
+            zipf,
+            rng,
+        }
+    }
+}
+
+impl Iterator for OpStream {
+    type Item = Op;
+
+    fn next(&mut self) -> Option<Op> {
+        let key = self.workload.key(self.zipf.sample(&mut self.rng));
+        let write =
+            self.workload.write_ratio > 0.0 && self.rng.next_f64() < self.workload.write_ratio;
+        Some(if write {
+            Op::Write(key, self.workload.sizes.sample(&mut self.rng))
+        } else {
+            Op::Read(key)
+        })
+    }
+}
+
+/// Latency percentiles of one kind of operation, in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub p999_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencySummary {
+    pub fn new(latencies: &mut [Duration]) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+        latencies.sort_unstable();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        // Nearest rank.
+        let at = |q: f64| {
+            let rank = (q * latencies.len() as f64).ceil() as usize;
+            ms(latencies[rank.clamp(1, latencies.len()) - 1])
+        };
+        LatencySummary {
+            count: latencies.len() as u64,
+            mean_ms: ms(latencies.iter().sum::<Duration>()) / latencies.len() as f64,
+            p50_ms: at(0.5),
+            p90_ms: at(0.9),
+            p99_ms: at(0.99),
+            p999_ms: at(0.999),
+            max_ms: ms(latencies[latencies.len() - 1]),
+        }
+    }
+}
+
+/// The counters of a node's `/stats/json` that the hit ratio is taken from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct CacheCounters {
+    #[serde(default)]
+    pub memory_hits: u64,
+    #[serde(default)]
+    pub disk_hits: u64,
+    #[serde(default)]
+    pub misses: u64,
+}
+
+impl CacheCounters {
+    /// Percentage of the requests between `before` and `self` answered from the cache.
+    pub fn hit_ratio_since(&self, before: &CacheCounters) -> Option<f64> {
+        let hits = (self.memory_hits + self.disk_hits)
+            .saturating_sub(before.memory_hits + before.disk_hits);
+        let misses = self.misses.saturating_sub(before.misses);
+        (hits + misses > 0).then(|| hits as f64 * 100.0 / (hits + misses) as f64)
+    }
+}
+
+/// The counters of every node added up; `None` if any node's stats can't be read.
+pub async fn cluster_counters(
+    http: &reqwest::Client,
+    nodes: &[Url],
+    token: Option<&str>,
+) -> Option<CacheCounters> {
+    let answers = join_all(nodes.iter().map(|node| async move {
+        let mut request = http.get(node.join("stats/json").ok()?);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.ok()?.error_for_status().ok()?;
+        response.json::<CacheCounters>().await.ok()
+    }))
+    .await;
+    answers
+        .into_iter()
+        .try_fold(CacheCounters::default(), |total, node| {
+            let node = node?;
+            Some(CacheCounters {
+                memory_hits: total.memory_hits + node.memory_hits,
+                disk_hits: total.disk_hits + node.disk_hits,
+                misses: total.misses + node.misses,
+            })
+        })
+}
+
+/// What a run measured.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BenchReport {
+    pub requests: u64,
+    pub errors: u64,
+    pub first_error: Option<String>,
+    pub elapsed_secs: f64,
+    /// Operations completed per second, failed ones included.
+    pub throughput: f64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Percentage of the cluster's requests during the run answered from the cache, from
+    /// the nodes' stats; unset when they could not be read.
+    pub hit_ratio: Option<f64>,
+    pub reads: LatencySummary,
+    pub writes: LatencySummary,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} requests in {:.2}s: {:.1} req/s, {} errors",
+            self.requests, self.elapsed_secs, self.throughput, self.errors
+        )?;
+        let mib_per_sec =
+            |bytes: u64| bytes as f64 / self.elapsed_secs.max(f64::EPSILON) / 1048576.0;
+        writeln!(
+            f,
+            "read {} bytes ({:.1} MiB/s), wrote {} bytes ({:.1} MiB/s)",
+            self.bytes_read,
+            mib_per_sec(self.bytes_read),
+            self.bytes_written,
+            mib_per_sec(self.bytes_written)
+        )?;
+        match self.hit_ratio {
+            Some(ratio) => writeln!(f, "hit ratio {:.2}%", ratio)?,
+            None => writeln!(f, "hit ratio unknown (node stats unavailable)")?,
+        }
+        writeln!(
+            f,
+            "\n{:<6} | {:<8} | {:<9} | {:<9} | {:<9} | {:<9} | {:<9} | max ms",
+            "Op", "Count", "mean ms", "p50 ms", "p90 ms", "p99 ms", "p99.9 ms"
+        )?;
+        writeln!(f, "{}", "-".repeat(88))?;
+        for (name, latency) in [("read", &self.reads), ("write", &self.writes)] {
+            writeln!(
+                f,
+                "{:<6} | {:<8} | {:<9.2} | {:<9.2} | {:<9.2} | {:<9.2} | {:<9.2} | {:.2}",
+                name,
+                latency.count,
+                latency.mean_ms,
+                latency.p50_ms,
+                latency.p90_ms,
+                latency.p99_ms,
+                latency.p999_ms,
+                latency.max_ms
+            )?;
+        }
+        if let Some(e) = &self.first_error {
+            writeln!(f, "\nfirst error: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// How one operation went.
+struct Sample {
+    write: bool,
+    latency: Duration,
+    result: Result<u64, String>,
+}
+
+/// A body of `max` bytes that written objects are cut from.
+fn payload(workload: &Workload) -> Bytes {
+    let mut rng = Rng::new(workload.seed);
+    let len = workload.sizes.max() as usize;
+    let mut data = Vec::with_capacity(len + 8);
+    while data.len() < len {
+        data.extend_from_slice(&rng.next_u64().to_le_bytes());
+    }
+    data.truncate(len);
+    Bytes::from(data)
+}
+
+async fn perform(client: &CacheClient, op: &Op, payload: &Bytes) -> Result<u64, String> {
+    match op {
+        Op::Read(key) => {
+            let body = client
+                .get_stream(key, None)
+                .await
+                .map_err(|e| e.to_string())?;
+            body.try_fold(
+                0,
+                |read, chunk| async move { Ok(read + chunk.len() as u64) },
+            )
+            .await
+            .map_err(|e| e.to_string())
+        }
+        Op::Write(key, size) => {
+            let body = payload.slice(..*size as usize);
+            client.put(key, body).await.map_err(|e| e.to_string())?;
+            Ok(*size)
+        }
+    }
+}
+
+/// Writes every key of the workload once, `concurrency` at a time, so that reads find
+/// them; not measured. Returns the keys that failed.
+pub async fn populate(client: &CacheClient, workload: &Workload) -> Vec<(String, String)> {
+    let payload = payload(workload);
+    let mut rng = Rng::new(workload.seed);
+    let ops: Vec<Op> = (0..workload.keys)
+        .map(|index| Op::Write(workload.key(index), workload.sizes.sample(&mut rng)))
+        .collect();
+    let mut failed = Vec::new();
+    for batch in ops.chunks(workload.concurrency.max(1)) {
+        let results = join_all(batch.iter().map(|op| perform(client, op, &payload))).await;
+        for (op, result) in batch.iter().zip(results) {
+            if let (Op::Write(key, _), Err(e)) = (op, result) {
+                failed.push((key.clone(), e));
+            }
+        }
+    }
+    failed
+}
+
+/// Runs `workload` through `client`, taking the hit ratio from the stats of `nodes`.
+pub async fn run(
+    client: Arc<CacheClient>,
+    workload: &Workload,
+    nodes: &[Url],
+    token: Option<&str>,
+) -> BenchReport {
+    let http = reqwest::Client::new();
+    let before = cluster_counters(&http, nodes, token).await;
+    let workload = Arc::new(workload.clone());
+    let zipf = Arc::new(Zipf::new(workload.keys, workload.zipf_exponent));
+    let payload = match workload.write_ratio > 0.0 {
+        true => payload(&workload),
+        false => Bytes::new(),
+    };
+    let issued = Arc::new(AtomicU64::new(0));
+    let started = Instant::now();
+    let deadline = workload.duration.map(|duration| started + duration);
+
+    let workers = (0..workload.concurrency as u64).map(|worker| {
+        let ops = OpStream::new(workload.clone(), zipf.clone(), worker);
+        let (client, workload, payload, issued) = (
+            client.clone(),
+            workload.clone(),
+            payload.clone(),
+            issued.clone(),
+        );
+        tokio::spawn(async move {
+            let mut samples = Vec::new();
+            for op in ops {
+                let more = match deadline {
+                    Some(deadline) => Instant::now() < deadline,
+                    None => issued.fetch_add(1, Ordering::Relaxed) < workload.requests,
+                };
+                if !more {
+                    break;
+                }
+                let sent = Instant::now();
+                let result = perform(&client, &op, &payload).await;
+                samples.push(Sample {
+                    write: matches!(op, Op::Write(..)),
+                    latency: sent.elapsed(),
+                    result,
+                });
+            }
+            samples
+        })
+    });
+    let samples: Vec<Sample> = join_all(workers)
+        .await
+        .into_iter()
+        .flat_map(Result::unwrap_or_default)
+        .collect();
+    let elapsed = started.elapsed();
+    let after = cluster_counters(&http, nodes, token).await;
+
+    let mut report = BenchReport {
+        requests: samples.len() as u64,
+        elapsed_secs: elapsed.as_secs_f64(),
+        throughput: samples.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        hit_ratio: before
+            .zip(after)
+            .and_then(|(before, after)| after.hit_ratio_since(&before)),
+        ..BenchReport::default()
+    };
+    let (mut reads, mut writes) = (Vec::new(), Vec::new());
+    for sample in samples {
+        match (&sample.result, sample.write) {
+            (Ok(bytes), false) => report.bytes_read += bytes,
+            (Ok(bytes), true) => report.bytes_written += bytes,
+            (Err(e), _) => {
+                report.errors += 1;
+                report.first_error.get_or_insert_with(|| e.clone());
+            }
+        }
+        if sample.write {
+            writes.push(sample.latency);
+        } else {
+            reads.push(sample.latency);
+        }
+    }
+    report.reads = LatencySummary::new(&mut reads);
+    report.writes = LatencySummary::new(&mut writes);
+    report
+}
@@ -0,0 +1,171 @@
+use cache_bench::{populate, run, SizeDistribution, Workload};
+use cache_client::CacheClient;
+use clap::{App, Arg, ArgMatches};
+use std::process::exit;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+fn fail(e: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", e);
+    exit(2);
+}
+
+/// The value of `name`, or its default.
+fn value<T: FromStr>(matches: &ArgMatches, name: &str) -> T {
+    let raw = matches.value_of(name).unwrap();
+    raw.parse()
+        .unwrap_or_else(|_| fail(format!("invalid --{} {}", name, raw)))
+}
+
+#[tokio::main]
+async fn main() {
+    let defaults = Workload::default();
+    let (keys, zipf, size, write_ratio, concurrency, requests, seed) = (
+        defaults.keys.to_string(),
+        defaults.zipf_exponent.to_string(),
+        defaults.sizes.to_string(),
+        defaults.write_ratio.to_string(),
+        defaults.concurrency.to_string(),
+        defaults.requests.to_string(),
+        defaults.seed.to_string(),
+    );
+    let matches = App::new("cache-bench")
+        .about("Sends a synthetic workload to an istziio cache cluster and measures it")
+        .arg(
+            Arg::with_name("node")
+                .short('n')
+                .long("node")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .required(true)
+                .help("Node URL, e.g. http://localhost:26379; repeat for each node"),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .takes_value(true)
+                .help("Bearer token; writes need the admin token"),
+        )
+        .arg(
+            Arg::with_name("keys")
+                .long("keys")
+                .takes_value(true)
+                .default_value(&keys)
+                .help("Number of distinct keys"),
+        )
+        .arg(
+            Arg::with_name("prefix")
+                .long("prefix")
+                .takes_value(true)
+                .default_value(&defaults.prefix)
+                .help("Keys are named <prefix><index>"),
+        )
+        .arg(
+            Arg::with_name("zipf")
+                .long("zipf")
+                .takes_value(true)
+                .default_value(&zipf)
+                .help("Zipf exponent of key popularity; 0 for uniform"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .default_value(&size)
+                .help(
+                    "Written object sizes: <bytes>, uniform:<min>-<max> or log-uniform:<min>-<max>",
+                ),
+        )
+        .arg(
+            Arg::with_name("write_ratio")
+                .long("write-ratio")
+                .takes_value(true)
+                .default_value(&write_ratio)
+                .help("Share of operations that are writes, from 0 to 1"),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .short('c')
+                .long("concurrency")
+                .takes_value(true)
+                .default_value(&concurrency),
+        )
+        .arg(
+            Arg::with_name("requests")
+                .long("requests")
+                .takes_value(true)
+                .default_value(&requests)
+                .help("Operations to send"),
+        )
+        .arg(
+            Arg::with_name("duration")
+                .long("duration")
+                .takes_value(true)
+                .help("Seconds to send operations for, instead of --requests"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .default_value(&seed),
+        )
+        .arg(
+            Arg::with_name("populate")
+                .long("populate")
+                .help("Write every key once before the run, so that reads find them"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the report as JSON"),
+        )
+        .get_matches();
+
+    let workload = Workload {
+        keys: value(&matches, "keys"),
+        prefix: matches.value_of("prefix").unwrap().to_string(),
+        zipf_exponent: value(&matches, "zipf"),
+        sizes: SizeDistribution::from_str(matches.value_of("size").unwrap())
+            .unwrap_or_else(|e| fail(e)),
+        write_ratio: value(&matches, "write_ratio"),
+        concurrency: value(&matches, "concurrency"),
+        requests: value(&matches, "requests"),
+        duration: matches
+            .is_present("duration")
+            .then(|| Duration::from_secs_f64(value(&matches, "duration"))),
+        seed: value(&matches, "seed"),
+    };
+    workload.validate().unwrap_or_else(|e| fail(e));
+    let nodes: Vec<&str> = matches.values_of("node").unwrap().collect();
+    let urls = nodes
+        .iter()
+        .map(|node| Url::parse(node).map_err(|e| format!("invalid node URL {}: {}", node, e)))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| fail(e));
+    let token = matches.value_of("token");
+    let mut builder = CacheClient::builder(&nodes);
+    if let Some(token) = token {
+        builder = builder.token(token);
+    }
+    let client = Arc::new(builder.build().unwrap_or_else(|e| fail(e)));
+
+    if matches.is_present("populate") {
+        let failed = populate(&client, &workload).await;
+        if let Some((key, e)) = failed.first() {
+            fail(format!(
+                "populating failed for {} keys, e.g. {}: {}",
+                failed.len(),
+                key,
+                e
+            ));
+        }
+    }
+    let report = run(client, &workload, &urls, token).await;
+    if matches.is_present("json") {
+        println!("{}", serde_json::json!(report));
+    } else {
+        print!("{}", report);
+    }
+}
@@ -0,0 +1,221 @@
+use cache_bench::{
+    populate, run, CacheCounters, LatencySummary, Op, OpStream, Rng, SizeDistribution, Workload,
+    Zipf,
+};
+use cache_client::CacheClient;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use url::Url;
+
+#[test]
+fn test_size_distributions() {
+    assert_eq!("4096".parse(), Ok(SizeDistribution::Fixed(4096)));
+    assert_eq!("fixed:10".parse(), Ok(SizeDistribution::Fixed(10)));
+    let uniform: SizeDistribution = "uniform:10-20".parse().unwrap();
+    let log: SizeDistribution = "log-uniform:1024-1048576".parse().unwrap();
+    for bad in ["0", "uniform:20-10", "uniform:0-10", "log-uniform:5", "big"] {
+        assert!(bad.parse::<SizeDistribution>().is_err(), "{}", bad);
+    }
+    assert_eq!(log.to_string(), "log-uniform:1024-1048576");
+    assert_eq!(log.max(), 1048576);
+
+    let mut rng = Rng::new(7);
+    let sizes: Vec<u64> = (0..1000).map(|_| log.sample(&mut rng)).collect();
+    assert!(sizes.iter().all(|&size| (1024..=1048576).contains(&size)));
+    // Half of the draws fall below the geometric mean, 32 KiB.
+    let small = sizes.iter().filter(|&&size| size < 32768).count();
+    assert!((400..600).contains(&small), "{}", small);
+    assert!((0..100).all(|_| (10..=20).contains(&uniform.sample(&mut rng))));
+}
+
+#[test]
+fn test_zipf_popularity() {
+    let zipf = Zipf::new(1000, 0.99);
+    let mut rng = Rng::new(1);
+    let mut counts = vec![0u32; 1000];
+    for _ in 0..100_000 {
+        counts[zipf.sample(&mut rng) as usize] += 1;
+    }
+    // The most popular key gets about 1/H(1000), some 13%, of the draws.
+    assert!((11_000..15_000).contains(&counts[0]), "{}", counts[0]);
+    assert!(counts[0] > counts[1] && counts[1] > counts[10]);
+    assert!(counts[..10].iter().sum::<u32>() > counts[500..].iter().sum::<u32>());
+
+    let uniform = Zipf::new(4, 0.0);
+    let mut counts = [0u32; 4];
+    for _ in 0..40_000 {
+        counts[uniform.sample(&mut rng) as usize] += 1;
+    }
+    assert!(counts.iter().all(|&count| (9_000..11_000).contains(&count)));
+}
+
+#[test]
+fn test_ops_are_reproducible() {
+    let workload = Arc::new(Workload {
+        keys: 100,
+        write_ratio: 0.25,
+        sizes: "uniform:1-100".parse().unwrap(),
+        ..Workload::default()
+    });
+    let zipf = Arc::new(Zipf::new(workload.keys, workload.zipf_exponent));
+    let ops = |worker| -> Vec<Op> {
+        OpStream::new(workload.clone(), zipf.clone(), worker)
+            .take(1000)
+            .collect()
+    };
+    assert_eq!(ops(0), ops(0));
+    assert_ne!(ops(0), ops(1));
+    let writes = ops(0)
+        .iter()
+        .filter(|op| matches!(op, Op::Write(..)))
+        .count();
+    assert!((200..300).contains(&writes), "{}", writes);
+    assert!(ops(1).iter().all(|op| match op {
+        Op::Read(key) | Op::Write(key, _) => key.starts_with("bench/"),
+    }));
+
+    for bad in [
+        Workload {
+            keys: 0,
+            ..Workload::default()
+        },
+        Workload {
+            write_ratio: 1.5,
+            ..Workload::default()
+        },
+        Workload {
+            zipf_exponent: -1.0,
+            ..Workload::default()
+        },
+        Workload {
+            concurrency: 0,
+            ..Workload::default()
+        },
+    ] {
+        assert!(bad.validate().is_err(), "{:?}", bad);
+    }
+}
+
+#[test]
+fn test_latency_summary() {
+    let mut latencies: Vec<Duration> = (1..=1000).rev().map(Duration::from_millis).collect();
+    let summary = LatencySummary::new(&mut latencies);
+    assert_eq!(summary.count, 1000);
+    assert_eq!(summary.p50_ms, 500.0);
+    assert_eq!(summary.p90_ms, 900.0);
+    assert_eq!(summary.p99_ms, 990.0);
+    assert_eq!(summary.p999_ms, 999.0);
+    assert_eq!(summary.max_ms, 1000.0);
+    assert_eq!(summary.mean_ms, 500.5);
+    assert_eq!(LatencySummary::new(&mut []), LatencySummary::default());
+
+    let before = CacheCounters {
+        memory_hits: 1,
+        disk_hits: 2,
+        misses: 3,
+    };
+    let after = CacheCounters {
+        memory_hits: 5,
+        disk_hits: 5,
+        misses: 6,
+    };
+    assert_eq!(after.hit_ratio_since(&before), Some(70.0));
+    assert_eq!(before.hit_ratio_since(&before), None);
+}
+
+/// A node answering objects with ten bytes, writes with 200 and `/stats/json` with
+/// counters that grow by one hit per object read; records the request lines.
+async fn spawn_node() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    let reads = Arc::new(AtomicU64::new(0));
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 1024];
+            while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut chunk).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let text = String::from_utf8_lossy(&buf).to_string();
+            let request_line = text.lines().next().unwrap_or_default().to_string();
+            let body = if request_line.starts_with("GET /stats/json") {
+                format!(
+                    r#"{{"memory_hits":0,"disk_hits":{},"misses":0,"files":3}}"#,
+                    reads.load(Ordering::SeqCst)
+                )
+            } else if request_line.starts_with("GET") {
+                reads.fetch_add(1, Ordering::SeqCst);
+                String::from("xxxxxxxxxx")
+            } else {
+                String::from("accepted")
+            };
+            recorded.lock().unwrap().push(request_line);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    (url, seen)
+}
+
+#[tokio::test]
+async fn test_run_against_a_node() {
+    let (url, seen) = spawn_node().await;
+    let client = Arc::new(CacheClient::new(&[url.as_str()]).unwrap());
+    let workload = Workload {
+        keys: 5,
+        write_ratio: 0.5,
+        sizes: SizeDistribution::Fixed(4),
+        concurrency: 4,
+        requests: 40,
+        ..Workload::default()
+    };
+
+    assert!(populate(&client, &workload).await.is_empty());
+    let puts = |seen: &Mutex<Vec<String>>| {
+        seen.lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.starts_with("PUT"))
+            .count()
+    };
+    assert_eq!(puts(&seen), 5);
+
+    let report = run(client, &workload, &[Url::parse(&url).unwrap()], None).await;
+    assert_eq!(report.requests, 40);
+    assert_eq!(report.errors, 0);
+    assert_eq!(report.reads.count + report.writes.count, 40);
+    assert_eq!(report.bytes_read, report.reads.count * 10);
+    assert_eq!(report.bytes_written, report.writes.count * 4);
+    assert_eq!(puts(&seen), 5 + report.writes.count as usize);
+    // Every read counted as a hit by the node.
+    assert_eq!(report.hit_ratio, Some(100.0));
+    assert!(report.throughput > 0.0);
+    assert!(report.to_string().contains("p99"));
+
+    // Time-bound runs ignore the request count.
+    let timed = Workload {
+        duration: Some(Duration::from_millis(100)),
+        requests: 1,
+        write_ratio: 0.0,
+        ..workload
+    };
+    let client = Arc::new(CacheClient::new(&[url.as_str()]).unwrap());
+    let report = run(client, &timed, &[], None).await;
+    assert!(report.requests > 1);
+    assert!(report.elapsed_secs >= 0.1);
+    assert_eq!(report.hit_ratio, None);
+}
@@ -0,0 +1,335 @@
+// client.rs
+use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use log::debug;
+use reqwest::header::{AUTHORIZATION, LOCATION, RANGE};
+use reqwest::{redirect, Response, StatusCode};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::RwLock;
+use std::time::Duration;
+use url::Url;
+
+use crate::error::{ClientError, Result};
+use crate::range::ByteRange;
+use crate::slot::{initial_owner, key_slot};
+
+/// Clients that know an object is worth caching send `X-Istziio-Admission: force`.
+pub const ADMISSION_HEADER: &str = "X-Istziio-Admission";
+
+/// The body of an object, as it arrives.
+pub type ObjectStream = Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>;
+
+/// Settings for a `CacheClient`; start from `CacheClient::builder`.
+#[derive(Debug, Clone)]
+pub struct CacheClientBuilder {
+    nodes: Vec<String>,
+    token: Option<String>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    max_redirects: usize,
+    timeout: Option<Duration>,
+}
+
+impl CacheClientBuilder {
+    /// Bearer token sent with every request, for nodes that require read tokens.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Further attempts after a connection error, a 429 or a 5xx.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Wait before the first retry; it doubles with every further one.
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Limit on a whole request, body included.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn build(self) -> Result<CacheClient> {
+        if self.nodes.is_empty() {
+            return Err(ClientError::NoNodes);
+        }
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| Url::parse(node).map_err(|_| ClientError::InvalidUrl(node.clone())))
+            .collect::<Result<Vec<_>>>()?;
+        // Redirects are followed by hand, to learn which node owns which slot.
+        let mut http = reqwest::Client::builder().redirect(redirect::Policy::none());
+        if let Some(timeout) = self.timeout {
+            http = http.timeout(timeout);
+        }
+        Ok(CacheClient {
+            http: http.build()?,
+            nodes,
+            owners: RwLock::new(HashMap::new()),
+            token: self.token,
+            max_retries: self.max_retries,
+            retry_backoff: self.retry_backoff,
+            max_redirects: self.max_redirects,
+        })
+    }
+}
+
+/// Reads objects through a cache cluster. Each request goes straight to the node owning
+/// the key's slot when the client knows it, and redirects teach it the owners it
+/// doesn't.
+pub struct CacheClient {
+    http: reqwest::Client,
+    nodes: Vec<Url>,
+    /// Slot owners learned from redirects.
+    owners: RwLock<HashMap<u16, Url>>,
+    token: Option<String>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    max_redirects: usize,
+}
+
+impl CacheClient {
+    /// A client with default settings for the nodes at `nodes`, e.g.
+    /// `http://node1:26379`.
+    pub fn new<S: AsRef<str>>(nodes: &[S]) -> Result<Self> {
+        Self::builder(nodes).build()
+    }
+
+    pub fn builder<S: AsRef<str>>(nodes: &[S]) -> CacheClientBuilder {
+        CacheClientBuilder {
+            nodes: nodes.iter().map(|node| node.as_ref().to_string()).collect(),
+            token: None,
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(100),
+            max_redirects: 3,
+            timeout: None,
+        }
+    }
+
+    /// The node a request for `key` is sent to first.
+    pub fn node_for(&self, key: &str) -> Url {
+        let slot = key_slot(key);
+        match self.owners.read().unwrap().get(&slot) {
+            Some(owner) => owner.clone(),
+            None => self.nodes[initial_owner(slot, self.nodes.len())].clone(),
+        }
+    }
+
+    /// The whole object.
+    pub async fn get(&self, key: &str) -> Result<Bytes> {
+        self.fetch_bytes(key, None).await
+    }
+
+    pub async fn get_range(&self, key: &str, range: ByteRange) -> Result<Bytes> {
+        self.fetch_bytes(key, Some(range)).await
+    }
+
+    /// The object (or a range of it) as a stream of chunks. Only opening the stream is
+    /// retried; an error midway ends it.
+    pub async fn get_stream(&self, key: &str, range: Option<ByteRange>) -> Result<ObjectStream> {
+        let response = self.send(key, range).await?;
+        Ok(Box::pin(response.bytes_stream().map_err(ClientError::from)))
+    }
+
+    /// Fetches `keys` with up to `concurrency` requests in flight, returning the results
+    /// in the order of `keys`.
+    pub async fn get_many<S: AsRef<str>>(
+        &self,
+        keys: &[S],
+        concurrency: usize,
+    ) -> Vec<Result<Bytes>> {
+        stream::iter(keys)
+            .map(|key| self.get(key.as_ref()))
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    /// Makes the owning nodes cache `keys`, bypassing their admission policy. Returns the
+    /// size of each object, in the order of `keys`.
+    pub async fn prefetch<S: AsRef<str>>(
+        &self,
+        keys: &[S],
+        concurrency: usize,
+    ) -> Vec<Result<u64>> {
+        stream::iter(keys)
+            .map(|key| self.prefetch_one(key.as_ref()))
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    async fn prefetch_one(&self, key: &str) -> Result<u64> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.send_once(key, None, true, None).await {
+                Ok(response) => drain(response).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(e) if self.should_retry(key, &e, attempt) => {
+                    self.back_off(attempt).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Writes `body` as the object `key` on the node owning it, which journals it for
+    /// upload to the backing store. Needs nodes with write-back and the admin token.
+    pub async fn put(&self, key: &str, body: Bytes) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.send_once(key, None, false, Some(&body)).await {
+                Err(e) if self.should_retry(key, &e, attempt) => {
+                    self.back_off(attempt).await;
+                    attempt += 1;
+                }
+                result => return result.map(drop),
+            }
+        }
+    }
+
+    /// Like `send`, but also retries when the body breaks off.
+    async fn fetch_bytes(&self, key: &str, range: Option<ByteRange>) -> Result<Bytes> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.send_once(key, range, false, None).await {
+                Ok(response) => response.bytes().await.map_err(ClientError::from),
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(e) if self.should_retry(key, &e, attempt) => {
+                    self.back_off(attempt).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// A successful response for `key`, retrying transient failures.
+    async fn send(&self, key: &str, range: Option<ByteRange>) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            match self.send_once(key, range, false, None).await {
+                Err(e) if self.should_retry(key, &e, attempt) => {
+                    self.back_off(attempt).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn should_retry(&self, key: &str, error: &ClientError, attempt: u32) -> bool {
+        if !error.is_retryable() || attempt >= self.max_retries {
+            return false;
+        }
+        debug!("retrying {} after: {}", key, error);
+        // The learned owner may be the node that failed.
+        self.owners.write().unwrap().remove(&key_slot(key));
+        true
+    }
+
+    async fn back_off(&self, attempt: u32) {
+        tokio::time::sleep(self.retry_backoff * 2u32.saturating_pow(attempt)).await;
+    }
+
+    /// One attempt at `key`, following redirects to the owning node. A `body` is sent
+    /// with a PUT instead of a GET.
+    async fn send_once(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+        force: bool,
+        body: Option<&Bytes>,
+    ) -> Result<Response> {
+        let mut url = object_url(&self.node_for(key), key);
+        for _ in 0..=self.max_redirects {
+            let mut request = match body {
+                Some(body) => self.http.put(url.clone()).body(body.clone()),
+                None => self.http.get(url.clone()),
+            };
+            if let Some(range) = range {
+                request = request.header(RANGE, range.to_string());
+            }
+            if force {
+                request = request.header(ADMISSION_HEADER, "force");
+            }
+            if let Some(token) = &self.token {
+                request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let response = request.send().await?;
+            let status = response.status();
+            if status.is_redirection() {
+                let location = response
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| url.join(location).ok())
+                    .ok_or_else(|| ClientError::Status {
+                        status: status.as_u16(),
+                        message: String::from("redirect without a location"),
+                    })?;
+                debug!("{} redirected to {}", key, location);
+                let mut owner = location.clone();
+                owner.set_path("/");
+                owner.set_query(None);
+                self.owners.write().unwrap().insert(key_slot(key), owner);
+                url = location;
+                continue;
+            }
+            return check_status(key, response).await;
+        }
+        Err(ClientError::TooManyRedirects(self.max_redirects))
+    }
+}
+
+/// `node`'s `/s3/<key>`, with every segment of the key percent-encoded.
+fn object_url(node: &Url, key: &str) -> Url {
+    let mut url = node.clone();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty().push("s3").extend(key.split('/'));
+    }
+    url
+}
+
+async fn check_status(key: &str, response: Response) -> Result<Response> {
+    match response.status() {
+        status if status.is_success() => Ok(response),
+        StatusCode::NOT_FOUND => Err(ClientError::NotFound(key.to_string())),
+        StatusCode::RANGE_NOT_SATISFIABLE => Err(ClientError::RangeNotSatisfiable(key.to_string())),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(ClientError::Unauthorized(
+            response.text().await.unwrap_or_default(),
+        )),
+        status => Err(ClientError::Status {
+            status: status.as_u16(),
+            message: response.text().await.unwrap_or_default(),
+        }),
+    }
+}
+
+/// Reads `response` to the end, returning its length.
+async fn drain(response: Response) -> Result<u64> {
+    let mut body = response.bytes_stream();
+    let mut len = 0;
+    while let Some(chunk) = body.next().await {
+        len += chunk?.len() as u64;
+    }
+    Ok(len)
+}
@@ -0,0 +1,36 @@
+// error.rs
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("range not satisfiable for {0}")]
+    RangeNotSatisfiable(String),
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    /// Any other non-success status, after retries where they apply.
+    #[error("node answered {status}: {message}")]
+    Status { status: u16, message: String },
+    #[error("gave up after {0} redirects")]
+    TooManyRedirects(usize),
+    #[error("invalid node URL {0}")]
+    InvalidUrl(String),
+    #[error("no cache nodes configured")]
+    NoNodes,
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+impl ClientError {
+    /// Whether the same request may succeed if sent again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Status { status, .. } => *status == 429 || *status >= 500,
+            ClientError::Http(e) => e.is_connect() || e.is_timeout() || e.is_body(),
+            _ => false,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
@@ -0,0 +1,20 @@
+//! Async client for an ISTZIIO cache cluster. It sends each key to the node owning its
+//! Redis Cluster slot, follows (and remembers) redirects, and retries transient failures.
+//!
+//! ```no_run
+//! # async fn run() -> cache_client::error::Result<()> {
+//! let client = cache_client::CacheClient::new(&["http://node1:26379", "http://node2:26380"])?;
+//! let footer = client
+//!     .get_range("lineitem.parquet", cache_client::ByteRange::Suffix(8))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+pub mod client;
+pub mod error;
+pub mod range;
+pub mod slot;
+
+pub use client::{CacheClient, CacheClientBuilder, ObjectStream};
+pub use error::ClientError;
+pub use range::ByteRange;
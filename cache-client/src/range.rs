@@ -0,0 +1,23 @@
+// range.rs
+use std::fmt;
+
+/// A single byte range, sent as the `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=start-end`, both ends inclusive.
+    FromTo(u64, u64),
+    /// `bytes=start-`
+    From(u64),
+    /// `bytes=-len`, the last `len` bytes of the object.
+    Suffix(u64),
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteRange::FromTo(start, end) => write!(f, "bytes={}-{}", start, end),
+            ByteRange::From(start) => write!(f, "bytes={}-", start),
+            ByteRange::Suffix(len) => write!(f, "bytes=-{}", len),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// slot.rs
+//! Keys are owned by nodes through Redis Cluster hash slots, so the client computes the
+//! same slot the server asks Redis for with `CLUSTER KEYSLOT`.
+
+pub const SLOT_COUNT: u16 = 16384;
+
+/// The Redis Cluster hash slot of `key`, honoring `{hash tags}`.
+pub fn key_slot(key: &str) -> u16 {
+    let bytes = key.as_bytes();
+    let hashed = match bytes.iter().position(|&b| b == b'{') {
+        Some(open) => match bytes[open + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &bytes[open + 1..open + 1 + len],
+            _ => bytes,
+        },
+        None => bytes,
+    };
+    crc16::State::<crc16::XMODEM>::calculate(hashed) % SLOT_COUNT
+}
+
+/// The node a fresh cluster gives `slot` to: `redis-cli --cluster create` splits the
+/// slots into equal contiguous ranges in node order.
+pub fn initial_owner(slot: u16, node_count: usize) -> usize {
+    slot as usize * node_count / SLOT_COUNT as usize
+}
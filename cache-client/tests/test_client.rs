@@ -0,0 +1,256 @@
+use cache_client::slot::{initial_owner, key_slot, SLOT_COUNT};
+use cache_client::{ByteRange, CacheClient, ClientError};
+use futures::StreamExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// A request as the test node saw it.
+#[derive(Debug, Clone)]
+struct Seen {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Seen {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Status, extra headers and body of a canned response.
+type Reply = (u16, Vec<(String, String)>, Vec<u8>);
+
+/// A one-request-per-connection HTTP server answering with `handler`.
+struct TestNode {
+    url: String,
+    seen: Arc<Mutex<Vec<Seen>>>,
+}
+
+async fn spawn_node<F>(handler: F) -> TestNode
+where
+    F: Fn(&Seen, usize) -> Reply + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    let count = AtomicUsize::new(0);
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 1024];
+            while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut chunk).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let text = String::from_utf8_lossy(&buf).to_string();
+            let mut lines = text.split("\r\n");
+            let mut request_line = lines.next().unwrap_or_default().split(' ');
+            let method = request_line.next().unwrap_or_default().to_string();
+            let path = request_line.next().unwrap_or_default().to_string();
+            let headers = lines
+                .filter_map(|line| line.split_once(": "))
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect();
+            let request = Seen {
+                method,
+                path,
+                headers,
+            };
+            let (status, headers, body) = handler(&request, count.fetch_add(1, Ordering::SeqCst));
+            recorded.lock().unwrap().push(request);
+            let mut response = format!(
+                "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n",
+                status,
+                body.len()
+            );
+            for (name, value) in headers {
+                response.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            response.push_str("\r\n");
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.write_all(&body).await;
+        }
+    });
+    TestNode { url, seen }
+}
+
+fn ok(body: &str) -> Reply {
+    (200, Vec::new(), body.as_bytes().to_vec())
+}
+
+#[test]
+fn test_key_slot() {
+    // Values from the Redis Cluster specification.
+    assert_eq!(key_slot("foo"), 12182);
+    assert_eq!(key_slot("bar"), 5061);
+    assert_eq!(
+        key_slot("{user1000}.following"),
+        key_slot("{user1000}.followers")
+    );
+    assert_eq!(key_slot("{}foo"), key_slot("{}foo"));
+    assert_ne!(key_slot("{}foo"), key_slot("foo"));
+    assert_eq!(initial_owner(0, 3), 0);
+    assert_eq!(initial_owner(SLOT_COUNT - 1, 3), 2);
+}
+
+#[test]
+fn test_byte_range_header() {
+    assert_eq!(ByteRange::FromTo(0, 9).to_string(), "bytes=0-9");
+    assert_eq!(ByteRange::From(10).to_string(), "bytes=10-");
+    assert_eq!(ByteRange::Suffix(8).to_string(), "bytes=-8");
+}
+
+#[tokio::test]
+async fn test_follows_redirect_and_learns_owner() {
+    let owner = spawn_node(|_, _| ok("hello")).await;
+    let location = format!("{}s3/a.parquet", owner.url);
+    let entry = spawn_node(move |_, _| {
+        (
+            303,
+            vec![(String::from("Location"), location.clone())],
+            Vec::new(),
+        )
+    })
+    .await;
+    let client = CacheClient::new(&[entry.url.as_str()]).unwrap();
+
+    assert_eq!(&client.get("a.parquet").await.unwrap()[..], b"hello");
+    assert_eq!(client.node_for("a.parquet").as_str(), owner.url);
+    assert_eq!(&client.get("a.parquet").await.unwrap()[..], b"hello");
+    assert_eq!(entry.seen.lock().unwrap().len(), 1);
+    assert_eq!(owner.seen.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_put_follows_temporary_redirect() {
+    let owner = spawn_node(|_, _| ok("accepted")).await;
+    let location = format!("{}s3/new.csv", owner.url);
+    let entry = spawn_node(move |_, _| {
+        (
+            307,
+            vec![(String::from("Location"), location.clone())],
+            Vec::new(),
+        )
+    })
+    .await;
+    let client = CacheClient::builder(&[entry.url.as_str()])
+        .token("admin")
+        .build()
+        .unwrap();
+
+    client.put("new.csv", "a,b\n".into()).await.unwrap();
+    let seen = owner.seen.lock().unwrap().clone();
+    assert_eq!(
+        (seen[0].method.as_str(), seen[0].path.as_str()),
+        ("PUT", "/s3/new.csv")
+    );
+    assert_eq!(seen[0].header("content-length"), Some("4"));
+    assert_eq!(seen[0].header("authorization"), Some("Bearer admin"));
+    assert_eq!(entry.seen.lock().unwrap()[0].method, "PUT");
+}
+
+#[tokio::test]
+async fn test_sends_range_admission_and_token() {
+    let node = spawn_node(|_, _| ok("abcd")).await;
+    let client = CacheClient::builder(&[node.url.as_str()])
+        .token("secret")
+        .build()
+        .unwrap();
+
+    client
+        .get_range("dir/a b#1", ByteRange::FromTo(0, 3))
+        .await
+        .unwrap();
+    assert_eq!(client.prefetch(&["x"], 4).await[0].as_ref().unwrap(), &4);
+
+    let seen = node.seen.lock().unwrap().clone();
+    assert_eq!(seen[0].path, "/s3/dir/a%20b%231");
+    assert_eq!(seen[0].header("range"), Some("bytes=0-3"));
+    assert_eq!(seen[0].header("authorization"), Some("Bearer secret"));
+    assert_eq!(seen[0].header("x-istziio-admission"), None);
+    assert_eq!(seen[1].header("x-istziio-admission"), Some("force"));
+}
+
+#[tokio::test]
+async fn test_retries_transient_failures() {
+    let node = spawn_node(|_, n| {
+        if n == 0 {
+            (503, Vec::new(), Vec::new())
+        } else {
+            ok("late")
+        }
+    })
+    .await;
+    let client = CacheClient::builder(&[node.url.as_str()])
+        .retry_backoff(Duration::from_millis(1))
+        .build()
+        .unwrap();
+    assert_eq!(&client.get("k").await.unwrap()[..], b"late");
+
+    let node = spawn_node(|_, _| (503, Vec::new(), b"busy".to_vec())).await;
+    let client = CacheClient::builder(&[node.url.as_str()])
+        .max_retries(1)
+        .retry_backoff(Duration::from_millis(1))
+        .build()
+        .unwrap();
+    match client.get("k").await {
+        Err(ClientError::Status { status, message }) => {
+            assert_eq!(status, 503);
+            assert_eq!(message, "busy");
+        }
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+    assert_eq!(node.seen.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_not_found_is_not_retried() {
+    let node = spawn_node(|_, _| (404, Vec::new(), Vec::new())).await;
+    let client = CacheClient::new(&[node.url.as_str()]).unwrap();
+    assert!(matches!(
+        client.get("missing").await,
+        Err(ClientError::NotFound(key)) if key == "missing"
+    ));
+    assert_eq!(node.seen.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_batch_and_stream() {
+    let node = spawn_node(|seen, _| ok(&seen.path)).await;
+    let client = CacheClient::new(&[node.url.as_str()]).unwrap();
+
+    let results = client.get_many(&["a", "b", "c"], 2).await;
+    let bodies: Vec<_> = results.into_iter().map(|r| r.unwrap()).collect();
+    assert_eq!(bodies, vec!["/s3/a", "/s3/b", "/s3/c"]);
+
+    let mut stream = client.get_stream("d", None).await.unwrap();
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk.unwrap());
+    }
+    assert_eq!(body, b"/s3/d");
+}
+
+#[test]
+fn test_rejects_bad_nodes() {
+    assert!(matches!(
+        CacheClient::new::<&str>(&[]),
+        Err(ClientError::NoNodes)
+    ));
+    assert!(matches!(
+        CacheClient::new(&["not a url"]),
+        Err(ClientError::InvalidUrl(_))
+    ));
+}
@@ -0,0 +1,189 @@
+// lib.rs
+//! The operations behind `cachectl`, run against every node of a cluster at once.
+use cache_client::CacheClient;
+use futures::future::join_all;
+use reqwest::Method;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// The nodes of a cluster and the tokens to talk to them with, e.g. in TOML
+/// `nodes = ["http://node1:26379", "http://node2:26380"] admin_token = "..."`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    pub nodes: Vec<String>,
+    /// Needed by `clear` and `invalidate`.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Needed by `stats` and `prefetch` on nodes that require read tokens.
+    #[serde(default)]
+    pub read_token: Option<String>,
+}
+
+impl ClusterConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        if config.nodes.is_empty() {
+            return Err(String::from("no nodes configured"));
+        }
+        for node in &config.nodes {
+            Url::parse(node).map_err(|e| format!("invalid node URL {}: {}", node, e))?;
+        }
+        Ok(config)
+    }
+}
+
+/// What one node answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeReply {
+    pub node: String,
+    pub result: Result<String, String>,
+}
+
+/// Health of one node and how long it took to answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeHealth {
+    pub node: String,
+    pub result: Result<Duration, String>,
+}
+
+/// Outcome of a prefetch across the cluster.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefetchSummary {
+    pub cached: usize,
+    pub bytes: u64,
+    /// Keys that failed, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+/// The keys of a prefetch list: one per line, skipping blank lines and `#` comments.
+pub fn read_keys(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// `node` with `segments` appended to its path, each percent-encoded.
+fn node_url(node: &str, segments: &[&str]) -> Result<Url, String> {
+    let mut url = Url::parse(node).map_err(|e| e.to_string())?;
+    url.path_segments_mut()
+        .map_err(|_| format!("{} cannot be a base URL", node))?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
+
+pub struct Cluster {
+    config: ClusterConfig,
+    http: reqwest::Client,
+}
+
+impl Cluster {
+    pub fn new(config: ClusterConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn stats(&self) -> Vec<NodeReply> {
+        let token = self.config.read_token.as_deref();
+        self.each(Method::GET, &["stats"], token).await
+    }
+
+    pub async fn clear(&self) -> Vec<NodeReply> {
+        let token = self.config.admin_token.as_deref();
+        self.each(Method::POST, &["clear"], token).await
+    }
+
+    /// Drops `key` everywhere; only the node owning it has anything to drop.
+    pub async fn invalidate(&self, key: &str) -> Vec<NodeReply> {
+        let mut segments = vec!["admin", "invalidate"];
+        segments.extend(key.split('/'));
+        let token = self.config.admin_token.as_deref();
+        self.each(Method::POST, &segments, token).await
+    }
+
+    pub async fn nodes(&self) -> Vec<NodeHealth> {
+        join_all(self.config.nodes.iter().map(|node| async move {
+            let started = Instant::now();
+            let result = match self.request(node, Method::GET, &[], None).await {
+                Ok(_) => Ok(started.elapsed()),
+                Err(e) => Err(e),
+            };
+            NodeHealth {
+                node: node.clone(),
+                result,
+            }
+        }))
+        .await
+    }
+
+    /// Has the owning nodes cache `keys`, following redirects like any client would.
+    pub async fn prefetch(
+        &self,
+        keys: &[String],
+        concurrency: usize,
+    ) -> Result<PrefetchSummary, String> {
+        let mut builder = CacheClient::builder(&self.config.nodes);
+        if let Some(token) = &self.config.read_token {
+            builder = builder.token(token.clone());
+        }
+        let client = builder.build().map_err(|e| e.to_string())?;
+        let mut summary = PrefetchSummary::default();
+        for (key, result) in keys.iter().zip(client.prefetch(keys, concurrency).await) {
+            match result {
+                Ok(bytes) => {
+                    summary.cached += 1;
+                    summary.bytes += bytes;
+                }
+                Err(e) => summary.failed.push((key.clone(), e.to_string())),
+            }
+        }
+        Ok(summary)
+    }
+
+    async fn each(&self, method: Method, segments: &[&str], token: Option<&str>) -> Vec<NodeReply> {
+        join_all(self.config.nodes.iter().map(|node| {
+            let method = method.clone();
+            async move {
+                NodeReply {
+                    node: node.clone(),
+                    result: self.request(node, method, segments, token).await,
+                }
+            }
+        }))
+        .await
+    }
+
+    async fn request(
+        &self,
+        node: &str,
+        method: Method,
+        segments: &[&str],
+        token: Option<&str>,
+    ) -> Result<String, String> {
+        let mut request = self.http.request(method, node_url(node, segments)?);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(format!("{}: {}", status, body.trim()))
+        }
+    }
+}
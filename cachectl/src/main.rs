@@ -0,0 +1,127 @@
+use cachectl::{read_keys, Cluster, ClusterConfig, NodeReply};
+use clap::{App, AppSettings, Arg, SubCommand};
+use std::path::Path;
+use std::process::exit;
+
+/// Prints every node's reply and whether they all succeeded.
+fn report(replies: Vec<NodeReply>) -> bool {
+    let mut ok = true;
+    for reply in replies {
+        match reply.result {
+            Ok(body) => println!("== {} ==\n{}", reply.node, body.trim_end()),
+            Err(e) => {
+                ok = false;
+                eprintln!("== {} ==\nerror: {}", reply.node, e);
+            }
+        }
+    }
+    ok
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("cachectl")
+        .about("Administers every node of an istziio cache cluster")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("cluster")
+                .short('c')
+                .long("cluster")
+                .takes_value(true)
+                .default_value("cluster.toml")
+                .help("TOML file listing the nodes and their tokens"),
+        )
+        .subcommand(SubCommand::with_name("stats").about("Shows each node's /stats"))
+        .subcommand(SubCommand::with_name("clear").about("Empties every node's cache"))
+        .subcommand(
+            SubCommand::with_name("invalidate")
+                .about("Drops a key from the cache")
+                .arg(Arg::with_name("key").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("prefetch")
+                .about("Caches the keys listed in a file, one per line")
+                .arg(
+                    Arg::with_name("file")
+                        .short('f')
+                        .long("file")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("concurrency")
+                        .long("concurrency")
+                        .takes_value(true)
+                        .default_value("8"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("nodes").about("Checks that every node is up"))
+        .get_matches();
+
+    let config = ClusterConfig::load(Path::new(matches.value_of("cluster").unwrap()))
+        .unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            exit(2);
+        });
+    let cluster = Cluster::new(config);
+    let ok = match matches.subcommand() {
+        Some(("stats", _)) => report(cluster.stats().await),
+        Some(("clear", _)) => report(cluster.clear().await),
+        Some(("invalidate", args)) => {
+            report(cluster.invalidate(args.value_of("key").unwrap()).await)
+        }
+        Some(("prefetch", args)) => {
+            let file = args.value_of("file").unwrap();
+            let keys = match std::fs::read_to_string(file) {
+                Ok(text) => read_keys(&text),
+                Err(e) => {
+                    eprintln!("error: cannot read {}: {}", file, e);
+                    exit(2);
+                }
+            };
+            let concurrency = args
+                .value_of("concurrency")
+                .unwrap()
+                .parse()
+                .unwrap_or_else(|_| {
+                    eprintln!("error: --concurrency must be a positive number");
+                    exit(2);
+                });
+            match cluster.prefetch(&keys, concurrency).await {
+                Ok(summary) => {
+                    println!(
+                        "cached {} of {} keys ({} bytes)",
+                        summary.cached,
+                        keys.len(),
+                        summary.bytes
+                    );
+                    for (key, e) in &summary.failed {
+                        eprintln!("failed {}: {}", key, e);
+                    }
+                    summary.failed.is_empty()
+                }
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    false
+                }
+            }
+        }
+        Some(("nodes", _)) => {
+            let mut ok = true;
+            for health in cluster.nodes().await {
+                match health.result {
+                    Ok(latency) => println!("{}\tup\t{} ms", health.node, latency.as_millis()),
+                    Err(e) => {
+                        ok = false;
+                        println!("{}\tdown\t{}", health.node, e);
+                    }
+                }
+            }
+            ok
+        }
+        _ => unreachable!("a subcommand is required"),
+    };
+    if !ok {
+        exit(1);
+    }
+}
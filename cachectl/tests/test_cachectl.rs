@@ -0,0 +1,112 @@
+use cachectl::{read_keys, Cluster, ClusterConfig};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// A node answering every request with `body`, recording request lines and the
+/// `Authorization` header.
+async fn spawn_node(body: &'static str) -> (String, Arc<Mutex<Vec<(String, Option<String>)>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 1024];
+            while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut chunk).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let text = String::from_utf8_lossy(&buf).to_string();
+            let request_line = text.lines().next().unwrap_or_default().to_string();
+            let auth = text
+                .lines()
+                .find_map(|line| line.strip_prefix("authorization: "))
+                .map(String::from);
+            recorded.lock().unwrap().push((request_line, auth));
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    (url, seen)
+}
+
+#[test]
+fn test_cluster_config() {
+    let config = ClusterConfig::parse(
+        "nodes = [\"http://node1:26379\", \"http://node2:26380\"]\nadmin_token = \"root\"\n",
+    )
+    .unwrap();
+    assert_eq!(config.nodes.len(), 2);
+    assert_eq!(config.admin_token.as_deref(), Some("root"));
+    assert_eq!(config.read_token, None);
+
+    assert!(ClusterConfig::parse("nodes = []").is_err());
+    assert!(ClusterConfig::parse("nodes = [\"not a url\"]").is_err());
+    assert!(ClusterConfig::parse("nodes = [\"http://a\"]\nport = 1").is_err());
+}
+
+#[test]
+fn test_read_keys() {
+    assert_eq!(
+        read_keys("a.parquet\n\n# warm these first\n  dir/b.parquet  \n"),
+        vec!["a.parquet", "dir/b.parquet"]
+    );
+}
+
+#[tokio::test]
+async fn test_commands_reach_every_node() {
+    let (first, first_seen) = spawn_node("node one").await;
+    let (second, second_seen) = spawn_node("node two").await;
+    let cluster = Cluster::new(ClusterConfig {
+        nodes: vec![first.clone(), second],
+        admin_token: Some(String::from("root")),
+        read_token: None,
+    });
+
+    let replies = cluster.stats().await;
+    assert_eq!(replies[0].node, first);
+    assert_eq!(replies[0].result, Ok(String::from("node one")));
+    assert_eq!(replies[1].result, Ok(String::from("node two")));
+
+    cluster.invalidate("dir/a b.parquet").await;
+    cluster.clear().await;
+    for seen in [first_seen, second_seen] {
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(seen[0], (String::from("GET /stats HTTP/1.1"), None));
+        assert_eq!(
+            seen[1],
+            (
+                String::from("POST /admin/invalidate/dir/a%20b.parquet HTTP/1.1"),
+                Some(String::from("Bearer root"))
+            )
+        );
+        assert_eq!(seen[2].0, "POST /clear HTTP/1.1");
+    }
+}
+
+#[tokio::test]
+async fn test_nodes_reports_down_nodes() {
+    let (up, _) = spawn_node("Healthy\n").await;
+    // Bound and dropped, so nothing listens there.
+    let down = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+    let cluster = Cluster::new(ClusterConfig {
+        nodes: vec![up, down],
+        ..Default::default()
+    });
+    let health = cluster.nodes().await;
+    assert!(health[0].result.is_ok());
+    assert!(health[1].result.is_err());
+}
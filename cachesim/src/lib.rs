@@ -0,0 +1,450 @@
+// lib.rs
+//! Replays recorded accesses against the cache's eviction, admission and placement logic
+//! (`CacheCore`), with no runtime, disk or network, and reports what a cluster would have
+//! hit, fetched and evicted. A trace and a config always give the same report, so policy
+//! changes can be graded against each other. `replay` sends a trace to live nodes instead.
+pub mod replay;
+
+use istziio_server_node::admission::AdmissionPolicy;
+use istziio_server_node::cache::shard_budget;
+use istziio_server_node::cache_core::{shard_index, slot_counts, CacheCore};
+use istziio_server_node::metadata::key_slot;
+use istziio_server_node::metrics::hit_ratio;
+use istziio_server_node::policy::{PolicySet, PrefixPolicy};
+use istziio_server_node::ring::{HashRing, Placement, RingNode, DEFAULT_VNODES_PER_NODE};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The simulated cluster, in the terms of the server's config, e.g. in TOML
+/// `nodes = [1, 1, 2] max_size = 1000000000 admission_policy = "second-hit"`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimConfig {
+    /// Capacity weight of each node; its length is the number of nodes.
+    pub nodes: Vec<u32>,
+    pub placement: Placement,
+    /// Virtual nodes per unit of weight on the ring.
+    pub vnodes_per_node: u32,
+    /// Bytes each node caches.
+    pub max_size: u64,
+    /// Shards per node.
+    pub bucket_size: u64,
+    pub admission_policy: AdmissionPolicy,
+    pub max_cacheable_object_size: Option<u64>,
+    pub policies: Vec<PrefixPolicy>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            nodes: vec![1],
+            placement: Placement::default(),
+            vnodes_per_node: DEFAULT_VNODES_PER_NODE,
+            max_size: 192,
+            bucket_size: 3,
+            admission_policy: AdmissionPolicy::default(),
+            max_cacheable_object_size: None,
+            policies: Vec::new(),
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        if config.nodes.is_empty() || config.nodes.contains(&0) {
+            return Err(String::from("nodes must list at least one non-zero weight"));
+        }
+        if config.bucket_size == 0 || config.max_size < config.bucket_size {
+            return Err(String::from(
+                "max_size must give each of bucket_size (> 0) shards a byte",
+            ));
+        }
+        if config.placement == Placement::Ring && config.vnodes_per_node == 0 {
+            return Err(String::from("vnodes_per_node must be greater than 0"));
+        }
+        Ok(config)
+    }
+}
+
+/// One recorded access: when it happened since the trace started, and the object's size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceAccess {
+    pub at: Duration,
+    pub key: String,
+    pub size: u64,
+}
+
+/// The fields of an access log line the simulator reads.
+#[derive(Deserialize)]
+struct LoggedAccess {
+    ts: String,
+    key: String,
+    status: u16,
+    bytes: u64,
+    #[serde(default)]
+    object_size: Option<u64>,
+}
+
+/// Parses a trace of either
+/// - `<ms> <size> <key>` lines, `ms` counted from any fixed point,
+/// - the `<unix ms>,<status>,<size>,<cache>,<key>` lines of a node's `request_trace`, or
+/// - the JSON lines of a node's `access_log`.
+///
+/// Of recorded requests, only `200` answers and `206` answers of known object size are
+/// replayed, timed from the earliest of them. Blank lines and `#` comments are skipped.
+/// Accesses are sorted by time.
+pub fn parse_trace(text: &str) -> Result<Vec<TraceAccess>, String> {
+    let mut accesses = Vec::new();
+    // Recorded accesses with their Unix time in milliseconds; a streamed answer is
+    // recorded when it ends, so these can be out of order.
+    let mut recorded = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |e: String| format!("line {}: {}", number + 1, e);
+        if line.starts_with('{') {
+            let logged: LoggedAccess =
+                serde_json::from_str(line).map_err(|e| error(e.to_string()))?;
+            let size = match (logged.status, logged.object_size) {
+                (200, size) => size.unwrap_or(logged.bytes),
+                (206, Some(size)) => size,
+                _ => continue,
+            };
+            let ts = chrono::DateTime::parse_from_rfc3339(&logged.ts)
+                .map_err(|e| error(format!("invalid ts: {}", e)))?;
+            recorded.push((ts.timestamp_millis(), logged.key, size));
+            continue;
+        }
+        if line
+            .split(char::is_whitespace)
+            .next()
+            .is_some_and(|field| field.contains(','))
+        {
+            let fields: Vec<&str> = line.splitn(5, ',').collect();
+            if fields.len() < 5 {
+                return Err(error(String::from(
+                    "expected <unix ms>,<status>,<size>,<cache>,<key>",
+                )));
+            }
+            let status: u16 = fields[1].parse().map_err(|e| error(format!("{}", e)))?;
+            if status != 200 && status != 206 {
+                continue;
+            }
+            recorded.push((
+                fields[0].parse().map_err(|e| error(format!("{}", e)))?,
+                fields[4].to_string(),
+                fields[2].parse().map_err(|e| error(format!("{}", e)))?,
+            ));
+            continue;
+        }
+        let mut fields = line.splitn(3, char::is_whitespace);
+        let (ms, size, key) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(ms), Some(size), Some(key)) => (ms, size, key.trim()),
+            _ => return Err(error(String::from("expected <ms> <size> <key>"))),
+        };
+        accesses.push(TraceAccess {
+            at: Duration::from_millis(ms.parse().map_err(|e| error(format!("{}", e)))?),
+            key: key.to_string(),
+            size: size.parse().map_err(|e| error(format!("{}", e)))?,
+        });
+    }
+    let first = recorded
+        .iter()
+        .map(|(ms, _, _)| *ms)
+        .min()
+        .unwrap_or_default();
+    accesses.extend(recorded.into_iter().map(|(ms, key, size)| TraceAccess {
+        at: Duration::from_millis((ms - first) as u64),
+        key,
+        size,
+    }));
+    accesses.sort_by_key(|access| access.at);
+    Ok(accesses)
+}
+
+/// How fast a trace is played back relative to how it was recorded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// `2.0` plays twice as fast, `0.5` at half speed.
+    Factor(f64),
+    /// Every access at once, as fast as they can be served.
+    Max,
+}
+
+impl Speed {
+    /// When an access recorded `at` into the trace is played.
+    pub fn scale(self, at: Duration) -> Duration {
+        match self {
+            Speed::Factor(factor) => at.div_f64(factor),
+            Speed::Max => Duration::ZERO,
+        }
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::Factor(1.0)
+    }
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    /// A positive factor, or `max`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "max" {
+            return Ok(Speed::Max);
+        }
+        match s.parse::<f64>() {
+            Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(Speed::Factor(factor)),
+            _ => Err(format!(
+                "invalid speed {}, expected a positive factor or max",
+                s
+            )),
+        }
+    }
+}
+
+/// What one node did over the trace.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NodeReport {
+    pub node: usize,
+    pub weight: u32,
+    pub accesses: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Percentage of accesses answered from the cache.
+    pub hit_ratio: f64,
+    pub bytes_written: u64,
+    pub bytes_evicted: u64,
+    /// Bytes cached once the trace is over.
+    pub current_size: u64,
+}
+
+/// What the cluster did over the trace.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SimReport {
+    pub accesses: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Percentage of accesses answered from the cache.
+    pub hit_ratio: f64,
+    pub bytes_requested: u64,
+    pub bytes_from_cache: u64,
+    pub bytes_from_s3: u64,
+    /// Bytes written to the cache disks by admitted misses.
+    pub bytes_written: u64,
+    pub bytes_evicted: u64,
+    pub evictions: u64,
+    /// Misses kept out by the admission policy, a prefix policy or the size limit.
+    pub not_admitted: u64,
+    pub nodes: Vec<NodeReport>,
+}
+
+impl fmt::Display for SimReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} accesses, {} hits, {} misses ({:.2}% hit ratio)",
+            self.accesses, self.hits, self.misses, self.hit_ratio
+        )?;
+        writeln!(
+            f,
+            "{} bytes requested: {} from cache, {} from S3",
+            self.bytes_requested, self.bytes_from_cache, self.bytes_from_s3
+        )?;
+        writeln!(
+            f,
+            "{} bytes written, {} bytes evicted in {} evictions, {} misses not admitted",
+            self.bytes_written, self.bytes_evicted, self.evictions, self.not_admitted
+        )?;
+        writeln!(
+            f,
+            "\n{:<6} | {:<6} | {:<10} | {:<10} | {:<8} | {:<14} | {:<14} | Cached",
+            "Node", "Weight", "Accesses", "Hits", "Hit %", "Written", "Evicted"
+        )?;
+        writeln!(f, "{}", "-".repeat(95))?;
+        for node in &self.nodes {
+            writeln!(
+                f,
+                "{:<6} | {:<6} | {:<10} | {:<10} | {:<8.2} | {:<14} | {:<14} | {}",
+                node.node,
+                node.weight,
+                node.accesses,
+                node.hits,
+                node.hit_ratio,
+                node.bytes_written,
+                node.bytes_evicted,
+                node.current_size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// How keys are spread over the nodes.
+enum Layout {
+    /// The first slot past each node's range, in node order.
+    Slots(Vec<usize>),
+    Ring(HashRing, HashMap<String, usize>),
+}
+
+/// A cluster of `CacheCore` shards fed one access at a time.
+pub struct Simulator {
+    layout: Layout,
+    nodes: Vec<Vec<CacheCore>>,
+    /// Where the trace's clock starts, for the admission window.
+    started: Instant,
+    report: SimReport,
+}
+
+impl Simulator {
+    pub fn new(config: &SimConfig) -> Self {
+        let layout = match config.placement {
+            Placement::Slots => Layout::Slots(
+                slot_counts(&config.nodes)
+                    .into_iter()
+                    .scan(0, |end, slots| {
+                        *end += slots;
+                        Some(*end)
+                    })
+                    .collect(),
+            ),
+            Placement::Ring => {
+                let nodes = config
+                    .nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, &weight)| RingNode {
+                        node_id: format!("node-{}", index),
+                        endpoint: String::new(),
+                        port: 0,
+                        vnodes: config.vnodes_per_node * weight,
+                        weight,
+                    })
+                    .collect::<Vec<_>>();
+                let indexes = nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, node)| (node.node_id.clone(), index))
+                    .collect();
+                Layout::Ring(HashRing::new(nodes), indexes)
+            }
+        };
+        let policies = PolicySet::new(config.policies.clone()).per_shard(config.bucket_size);
+        let nodes = config
+            .nodes
+            .iter()
+            .map(|_| {
+                (0..config.bucket_size)
+                    .map(|index| {
+                        CacheCore::new(
+                            shard_budget(config.max_size, config.bucket_size, index),
+                            policies.clone(),
+                            config.admission_policy,
+                        )
+                        .limiting_objects_to(config.max_cacheable_object_size)
+                    })
+                    .collect()
+            })
+            .collect();
+        let report = SimReport {
+            nodes: config
+                .nodes
+                .iter()
+                .enumerate()
+                .map(|(node, &weight)| NodeReport {
+                    node,
+                    weight,
+                    ..NodeReport::default()
+                })
+                .collect(),
+            ..SimReport::default()
+        };
+        Simulator {
+            layout,
+            nodes,
+            started: Instant::now(),
+            report,
+        }
+    }
+
+    /// The node owning `key`.
+    pub fn node_of(&self, key: &str) -> usize {
+        match &self.layout {
+            Layout::Slots(ends) => {
+                let slot = key_slot(key) as usize;
+                ends.iter().position(|&end| slot < end).unwrap_or(0)
+            }
+            Layout::Ring(ring, indexes) => ring
+                .owner(key)
+                .and_then(|node| indexes.get(&node.node_id).copied())
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn replay(&mut self, access: &TraceAccess) {
+        let node = self.node_of(&access.key);
+        let shards = &mut self.nodes[node];
+        let index = shard_index(&access.key, shards.len());
+        let shard = &mut shards[index];
+        let outcome = shard.access(&access.key, access.size, self.started + access.at);
+        let evicted: u64 = outcome.evicted.iter().map(|(_, size)| size).sum();
+
+        let report = &mut self.report;
+        report.accesses += 1;
+        report.bytes_requested += access.size;
+        report.evictions += outcome.evicted.len() as u64;
+        report.bytes_evicted += evicted;
+        let node_report = &mut report.nodes[node];
+        node_report.accesses += 1;
+        node_report.bytes_evicted += evicted;
+        if outcome.hit {
+            report.hits += 1;
+            report.bytes_from_cache += access.size;
+            node_report.hits += 1;
+        } else {
+            report.misses += 1;
+            report.bytes_from_s3 += access.size;
+            node_report.misses += 1;
+            if outcome.admitted {
+                report.bytes_written += access.size;
+                node_report.bytes_written += access.size;
+            } else {
+                report.not_admitted += 1;
+            }
+        }
+    }
+
+    pub fn report(&self) -> SimReport {
+        let mut report = self.report.clone();
+        report.hit_ratio = hit_ratio((report.hits, report.misses));
+        for (node, shards) in report.nodes.iter_mut().zip(&self.nodes) {
+            node.hit_ratio = hit_ratio((node.hits, node.misses));
+            node.current_size = shards.iter().map(CacheCore::current_size).sum();
+        }
+        report
+    }
+}
+
+/// Replays `trace` on a fresh cluster set up like `config`.
+pub fn simulate(config: &SimConfig, trace: &[TraceAccess]) -> SimReport {
+    let mut simulator = Simulator::new(config);
+    for access in trace {
+        simulator.replay(access);
+    }
+    simulator.report()
+}
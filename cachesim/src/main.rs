@@ -0,0 +1,123 @@
+use cache_client::CacheClient;
+use cachesim::replay::replay;
+use cachesim::{parse_trace, simulate, SimConfig, Speed};
+use clap::{App, Arg};
+use std::path::Path;
+use std::process::exit;
+
+fn fail(e: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", e);
+    exit(2);
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new("cachesim")
+        .about("Replays an access trace against simulated cache clusters or live nodes")
+        .arg(
+            Arg::with_name("config")
+                .short('c')
+                .long("config")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("TOML file describing a cluster; repeat to compare several"),
+        )
+        .arg(
+            Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .conflicts_with("config")
+                .help("Node to send the trace to instead of simulating; repeat for each node"),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .takes_value(true)
+                .requires("target")
+                .help("Read token for the target nodes"),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .long("concurrency")
+                .takes_value(true)
+                .default_value("64")
+                .help("Most requests in flight against the target nodes"),
+        )
+        .arg(
+            Arg::with_name("speed")
+                .long("speed")
+                .takes_value(true)
+                .default_value("1")
+                .help("Playback speed, e.g. 2 for twice as fast, or max"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the reports as JSON"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .required(true)
+                .help("`<ms> <size> <key>` lines, or a node's request trace or access log"),
+        )
+        .get_matches();
+
+    let trace_path = matches.value_of("trace").unwrap();
+    let mut trace = std::fs::read_to_string(trace_path)
+        .map_err(|e| format!("cannot read {}: {}", trace_path, e))
+        .and_then(|text| parse_trace(&text))
+        .unwrap_or_else(|e| fail(e));
+    let speed: Speed = matches
+        .value_of("speed")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|e| fail(e));
+
+    if let Some(targets) = matches.values_of("target") {
+        let targets: Vec<&str> = targets.collect();
+        let concurrency = matches
+            .value_of("concurrency")
+            .unwrap()
+            .parse()
+            .unwrap_or_else(|_| fail("--concurrency must be a positive number"));
+        let mut builder = CacheClient::builder(&targets);
+        if let Some(token) = matches.value_of("token") {
+            builder = builder.token(token);
+        }
+        let client = builder.build().unwrap_or_else(|e| fail(e));
+        let report = replay(&client, &trace, speed, concurrency).await;
+        if matches.is_present("json") {
+            println!("{}", serde_json::json!({ "report": report }));
+        } else {
+            print!("{}", report);
+        }
+        if !report.failed.is_empty() {
+            exit(1);
+        }
+        return;
+    }
+
+    // The admission window runs on the trace's clock, so speed matters here too.
+    for access in &mut trace {
+        access.at = speed.scale(access.at);
+    }
+    let configs = match matches.values_of("config") {
+        Some(paths) => paths
+            .map(|path| SimConfig::load(Path::new(path)).map(|config| (path.to_string(), config)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| fail(e)),
+        None => vec![(String::from("default"), SimConfig::default())],
+    };
+    for (name, config) in configs {
+        let report = simulate(&config, &trace);
+        if matches.is_present("json") {
+            println!(
+                "{}",
+                serde_json::json!({ "config": name, "report": report })
+            );
+        } else {
+            println!("== {} ==\n{}", name, report);
+        }
+    }
+}
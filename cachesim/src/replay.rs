@@ -0,0 +1,107 @@
+// replay.rs
+//! Sends a trace to live nodes, at the pace it was recorded or faster, to try a
+//! candidate configuration with production traffic.
+use crate::{Speed, TraceAccess};
+use cache_client::CacheClient;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What replaying a trace against live nodes did.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReplayReport {
+    pub requests: u64,
+    pub bytes: u64,
+    pub elapsed_secs: f64,
+    /// From sending each request until its body has been read.
+    pub mean_latency_ms: f64,
+    pub max_latency_ms: f64,
+    /// Longest a request went out after its turn, once `concurrency` were in flight.
+    pub max_lag_ms: f64,
+    /// Keys that failed, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} requests, {} failed, {} bytes in {:.2}s",
+            self.requests,
+            self.failed.len(),
+            self.bytes,
+            self.elapsed_secs
+        )?;
+        writeln!(
+            f,
+            "latency {:.2} ms mean, {:.2} ms max; requests up to {:.2} ms late",
+            self.mean_latency_ms, self.max_latency_ms, self.max_lag_ms
+        )?;
+        for (key, e) in &self.failed {
+            writeln!(f, "failed {}: {}", key, e)?;
+        }
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Reads every key of `trace` through `client`, each at its time in the trace played at
+/// `speed`, with up to `concurrency` requests in flight.
+pub async fn replay(
+    client: &CacheClient,
+    trace: &[TraceAccess],
+    speed: Speed,
+    concurrency: usize,
+) -> ReplayReport {
+    let started = Instant::now();
+    let results: Vec<_> = stream::iter(trace)
+        .map(|access| async move {
+            let due = started + speed.scale(access.at);
+            tokio::time::sleep_until(due).await;
+            let sent = Instant::now();
+            let result = match client.get_stream(&access.key, None).await {
+                Ok(body) => {
+                    body.try_fold(
+                        0,
+                        |read, chunk| async move { Ok(read + chunk.len() as u64) },
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            (
+                access,
+                sent.saturating_duration_since(due),
+                sent.elapsed(),
+                result,
+            )
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut report = ReplayReport {
+        requests: results.len() as u64,
+        elapsed_secs: started.elapsed().as_secs_f64(),
+        ..ReplayReport::default()
+    };
+    let mut total_latency = Duration::ZERO;
+    for (access, lag, latency, result) in results {
+        total_latency += latency;
+        report.max_latency_ms = report.max_latency_ms.max(millis(latency));
+        report.max_lag_ms = report.max_lag_ms.max(millis(lag));
+        match result {
+            Ok(bytes) => report.bytes += bytes,
+            Err(e) => report.failed.push((access.key.clone(), e.to_string())),
+        }
+    }
+    if report.requests > 0 {
+        report.mean_latency_ms = millis(total_latency) / report.requests as f64;
+    }
+    report
+}
@@ -0,0 +1,220 @@
+use cache_client::CacheClient;
+use cachesim::replay::replay;
+use cachesim::{parse_trace, simulate, SimConfig, Simulator, Speed, TraceAccess};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn trace(keys: &[&str]) -> Vec<TraceAccess> {
+    keys.iter()
+        .enumerate()
+        .map(|(i, key)| TraceAccess {
+            at: Duration::from_millis(i as u64),
+            key: key.to_string(),
+            size: 10,
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_trace() {
+    let text = "# ms size key\n20 10 b\n\n10 5 a/part 0.parquet\n";
+    assert_eq!(
+        parse_trace(text).unwrap(),
+        vec![
+            TraceAccess {
+                at: Duration::from_millis(10),
+                key: String::from("a/part 0.parquet"),
+                size: 5,
+            },
+            TraceAccess {
+                at: Duration::from_millis(20),
+                key: String::from("b"),
+                size: 10,
+            },
+        ]
+    );
+    assert!(parse_trace("10 ten a").unwrap_err().starts_with("line 1: "));
+    assert!(parse_trace("10 10").is_err());
+
+    let log = concat!(
+        r#"{"ts":"2026-10-15T12:00:00+00:00","request_id":"1","client":null,"method":"GET","key":"a","status":200,"bytes":7,"cache":"miss","latency_ms":1.0}"#,
+        "\n",
+        r#"{"ts":"2026-10-15T12:00:02+00:00","request_id":"2","client":null,"method":"GET","key":"a","status":206,"bytes":1,"cache":"disk_hit","latency_ms":1.0}"#,
+        "\n",
+        r#"{"ts":"2026-10-15T12:00:03+00:00","request_id":"3","client":null,"method":"GET","key":"b","status":200,"bytes":9,"cache":"miss","latency_ms":1.0}"#,
+    );
+    let accesses = parse_trace(log).unwrap();
+    assert_eq!(accesses.len(), 2);
+    assert_eq!(accesses[1].at, Duration::from_secs(3));
+    assert_eq!((accesses[1].key.as_str(), accesses[1].size), ("b", 9));
+    let ranged =
+        r#"{"ts":"2026-10-15T12:00:00+00:00","key":"a","status":206,"bytes":1,"object_size":70}"#;
+    assert_eq!(parse_trace(ranged).unwrap()[0].size, 70);
+
+    // A request trace, with a streamed answer recorded after a later one.
+    let recorded = "1760529600500,200,10,disk_hit,b\n1760529600000,206,70,miss,tables/a,1.csv\n\
+                    1760529600700,404,0,-,missing\n";
+    let accesses = parse_trace(recorded).unwrap();
+    assert_eq!(accesses.len(), 2);
+    assert_eq!(accesses[0].at, Duration::ZERO);
+    assert_eq!(
+        (accesses[0].key.as_str(), accesses[0].size),
+        ("tables/a,1.csv", 70)
+    );
+    assert_eq!(accesses[1].at, Duration::from_millis(500));
+    assert!(parse_trace("1760529600000,200,10").is_err());
+}
+
+#[test]
+fn test_speed() {
+    let at = Duration::from_secs(10);
+    assert_eq!(
+        "2".parse::<Speed>().unwrap().scale(at),
+        Duration::from_secs(5)
+    );
+    assert_eq!(Speed::default().scale(at), at);
+    assert_eq!("max".parse::<Speed>().unwrap().scale(at), Duration::ZERO);
+    for bad in ["0", "-1", "fast", "inf"] {
+        assert!(bad.parse::<Speed>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_sim_config() {
+    let config =
+        SimConfig::parse("nodes = [1, 2]\nmax_size = 300\nadmission_policy = \"second-hit:60\"")
+            .unwrap();
+    assert_eq!(config.nodes, [1, 2]);
+    assert_eq!(config.bucket_size, 3);
+    for bad in [
+        "nodes = []",
+        "nodes = [0]",
+        "bucket_size = 0",
+        "max_size = 2",
+        "placement = \"ring\"\nvnodes_per_node = 0",
+        "shadow = 1",
+    ] {
+        assert!(SimConfig::parse(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_capacity_and_admission() {
+    // A working set of four objects, with a one-off scan in the middle.
+    let mut keys = Vec::new();
+    for round in 0..4 {
+        keys.extend(["a", "b", "c", "d"]);
+        if round == 1 {
+            keys.extend(["s1", "s2", "s3", "s4"]);
+        }
+    }
+    let trace = trace(&keys);
+    let small = SimConfig {
+        max_size: 40,
+        bucket_size: 1,
+        ..SimConfig::default()
+    };
+    let report = simulate(&small, &trace);
+    assert_eq!(report.accesses, 20);
+    assert_eq!((report.hits, report.misses), (8, 12));
+    assert_eq!(report.bytes_written, 120);
+    assert_eq!(report.bytes_from_cache + report.bytes_from_s3, 200);
+    assert_eq!(report.bytes_evicted, 80);
+    assert_eq!(report.nodes[0].current_size, 40);
+
+    // Doubling the capacity keeps the working set through the scan.
+    let double = SimConfig {
+        max_size: 80,
+        ..small.clone()
+    };
+    assert_eq!(simulate(&double, &trace).hits, 12);
+
+    // Second-hit admission keeps the scan out, at the cost of a second miss on the
+    // working set.
+    let second_hit = SimConfig {
+        admission_policy: "second-hit".parse().unwrap(),
+        ..small
+    };
+    let report = simulate(&second_hit, &trace);
+    assert_eq!(report.not_admitted, 8);
+    assert_eq!(report.bytes_written, 40);
+    assert_eq!(report.hits, 8);
+    assert_eq!(report.bytes_evicted, 0);
+}
+
+#[test]
+fn test_placement_is_deterministic() {
+    let keys: Vec<String> = (0..200).map(|i| format!("t/part-{}", i)).collect();
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let trace = trace(&keys);
+    for placement in ["slots", "ring"] {
+        let config = SimConfig::parse(&format!(
+            "nodes = [1, 1, 2]\nplacement = \"{}\"\nmax_size = 1000",
+            placement
+        ))
+        .unwrap();
+        let report = simulate(&config, &trace);
+        assert_eq!(report, simulate(&config, &trace));
+        assert_eq!(report.nodes.iter().map(|n| n.accesses).sum::<u64>(), 200);
+        // The heavier node owns more keys.
+        assert!(
+            report.nodes[2].accesses > report.nodes[0].accesses,
+            "{}",
+            placement
+        );
+
+        let simulator = Simulator::new(&config);
+        assert!(keys.iter().all(|key| simulator.node_of(key) < 3));
+    }
+}
+
+/// A node answering every request with ten bytes, recording the request paths.
+async fn spawn_node() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 1024];
+            while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut chunk).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            let text = String::from_utf8_lossy(&buf).to_string();
+            let path = text.split_whitespace().nth(1).unwrap_or_default();
+            recorded.lock().unwrap().push(path.to_string());
+            let response =
+                "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\nxxxxxxxxxx";
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    (url, seen)
+}
+
+#[tokio::test]
+async fn test_replay_against_a_node() {
+    let (url, seen) = spawn_node().await;
+    let client = CacheClient::new(&[url]).unwrap();
+    let mut trace = trace(&["a", "b", "a"]);
+    trace[2].at = Duration::from_millis(200);
+
+    let report = replay(&client, &trace, Speed::Factor(2.0), 4).await;
+    assert_eq!(report.requests, 3);
+    assert_eq!(report.bytes, 30);
+    assert!(report.failed.is_empty());
+    // The last access is due 100 ms in at twice the speed.
+    assert!(report.elapsed_secs >= 0.1, "{}", report.elapsed_secs);
+    assert_eq!(seen.lock().unwrap().len(), 3);
+    assert!(seen.lock().unwrap().contains(&String::from("/s3/b")));
+
+    let report = replay(&client, &trace, Speed::Max, 1).await;
+    assert!(report.elapsed_secs < 0.1, "{}", report.elapsed_secs);
+}
@@ -0,0 +1,103 @@
+//! Tail latency of cheap requests while other requests on the same runtime write and
+//! delete cache files, with blocking `std::fs` calls versus `tokio::fs`.
+//!
+//! Run with `cargo bench --bench fs_tail_latency`.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+
+const WRITERS: usize = 16;
+const FILE_SIZE: usize = 1024 * 1024;
+const PROBES: usize = 400;
+const PROBE_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Blocking,
+    Async,
+}
+
+/// Writes, syncs and deletes a cache-sized file over and over until told to stop.
+async fn churn(mode: Mode, path: PathBuf, stop: Arc<AtomicBool>) {
+    let data = vec![7u8; FILE_SIZE];
+    while !stop.load(Ordering::Relaxed) {
+        match mode {
+            Mode::Blocking => {
+                let mut file = std::fs::File::create(&path).unwrap();
+                file.write_all(&data).unwrap();
+                file.sync_all().unwrap();
+                std::fs::remove_file(&path).unwrap();
+            }
+            Mode::Async => {
+                let mut file = tokio::fs::File::create(&path).await.unwrap();
+                file.write_all(&data).await.unwrap();
+                file.sync_all().await.unwrap();
+                tokio::fs::remove_file(&path).await.unwrap();
+            }
+        }
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Time until a no-op task submitted from outside the runtime gets to run, sampled while
+/// the writers are busy. Stands in for a request that could be answered from memory.
+fn probe_latencies(handle: &tokio::runtime::Handle) -> Vec<Duration> {
+    let mut latencies = Vec::with_capacity(PROBES);
+    for _ in 0..PROBES {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let submitted = Instant::now();
+        handle.spawn(async move { tx.send(submitted.elapsed()).unwrap() });
+        latencies.push(rx.recv().unwrap());
+        std::thread::sleep(PROBE_INTERVAL);
+    }
+    latencies
+}
+
+fn run(mode: Mode, dir: &Path) -> Vec<Duration> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+    let writers = (0..WRITERS)
+        .map(|i| {
+            let path = dir.join(format!("object-{}", i));
+            runtime.spawn(churn(mode, path, stop.clone()))
+        })
+        .collect::<Vec<_>>();
+    let mut latencies = probe_latencies(runtime.handle());
+    stop.store(true, Ordering::Relaxed);
+    runtime.block_on(async {
+        for writer in writers {
+            writer.await.unwrap();
+        }
+    });
+    latencies.sort();
+    latencies
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p) as usize]
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    println!(
+        "{:<10} | {:>10} | {:>10} | {:>10}",
+        "mode", "p50", "p99", "max"
+    );
+    for (name, mode) in [("std::fs", Mode::Blocking), ("tokio::fs", Mode::Async)] {
+        let latencies = run(mode, dir.path());
+        println!(
+            "{:<10} | {:>10?} | {:>10?} | {:>10?}",
+            name,
+            percentile(&latencies, 0.5),
+            percentile(&latencies, 0.99),
+            latencies.last().unwrap()
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! Cost of the bookkeeping a shard does per hit (refreshing the entry) and per admitted
+//! miss (evicting a victim and inserting), as the shard grows. Hits should cost the
+//! same at every size.
+//!
+//! Run with `cargo bench --bench hit_bookkeeping`.
+use istziio_server_node::admission::AdmissionPolicy;
+use istziio_server_node::cache_core::CacheCore;
+use istziio_server_node::policy::PolicySet;
+use std::time::{Duration, Instant};
+
+const OPERATIONS: u64 = 100_000;
+
+fn per_operation(elapsed: Duration, operations: u64) -> Duration {
+    elapsed / operations as u32
+}
+
+/// Time per hit and per miss on a full shard of `entries` entries.
+fn run(entries: u64) -> (Duration, Duration) {
+    let mut core = CacheCore::new(entries, PolicySet::default(), AdmissionPolicy::Always);
+    for i in 0..entries {
+        core.insert(format!("key-{}", i), 1);
+    }
+    let keys: Vec<String> = (0..OPERATIONS)
+        .map(|i| format!("key-{}", i.wrapping_mul(7919) % entries))
+        .collect();
+    let started = Instant::now();
+    for key in &keys {
+        core.touch(key);
+    }
+    let hit = per_operation(started.elapsed(), OPERATIONS);
+
+    let misses = OPERATIONS / 10;
+    let started = Instant::now();
+    for i in 0..misses {
+        let key = format!("new-{}", i);
+        core.make_room(&key, 1);
+        core.insert(key, 1);
+    }
+    (hit, per_operation(started.elapsed(), misses))
+}
+
+fn main() {
+    println!("{:>10} | {:>10} | {:>10}", "entries", "hit", "miss");
+    for entries in [1_000, 10_000, 100_000] {
+        let (hit, miss) = run(entries);
+        println!("{:>10} | {:>10?} | {:>10?}", entries, hit, miss);
+    }
+}
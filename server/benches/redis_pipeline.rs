@@ -0,0 +1,73 @@
+//! Metadata latency per file against a Redis node 200 µs away: opening a connection per
+//! command, as the store used to, against reusing one, and looking up or registering
+//! files one command at a time against pipelining them.
+//!
+//! Run with `cargo bench --bench redis_pipeline`.
+use istziio_server_node::metadata::MetadataStore;
+use istziio_server_node::mock_redis::MockRedis;
+use istziio_server_node::redis::RedisServer;
+use redis::Commands;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const ROUND_TRIP: Duration = Duration::from_micros(200);
+const FILES: usize = 1000;
+
+fn per_file(started: Instant, files: usize) -> Duration {
+    started.elapsed() / files as u32
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let redis = MockRedis::start(ROUND_TRIP).unwrap();
+    let store = RedisServer::new(vec![redis.url()], redis.port(), 1).unwrap();
+    let files: Vec<(String, PathBuf)> = (0..FILES)
+        .map(|i| {
+            (
+                format!("bench/{}.parquet", i),
+                PathBuf::from(format!("{}.bin", i)),
+            )
+        })
+        .collect();
+    let uids: Vec<String> = files.iter().map(|(uid, _)| uid.clone()).collect();
+
+    let client = redis::cluster::ClusterClient::new(vec![redis.url()]).unwrap();
+    let started = Instant::now();
+    for uid in uids.iter().take(100) {
+        let mut conn = client.get_connection().unwrap();
+        let _: Option<String> = conn.get(uid).unwrap();
+    }
+    let fresh = per_file(started, 100);
+
+    let started = Instant::now();
+    for (uid, loc) in &files {
+        store
+            .set_file_cache_loc(uid.clone(), loc.clone())
+            .await
+            .unwrap();
+    }
+    let single_writes = per_file(started, FILES);
+    let started = Instant::now();
+    store.set_file_cache_locs(files.clone()).await.unwrap();
+    let batched_writes = per_file(started, FILES);
+
+    let started = Instant::now();
+    for uid in &uids {
+        assert!(store.get_file(uid.clone()).await.is_some());
+    }
+    let single_reads = per_file(started, FILES);
+    let started = Instant::now();
+    assert!(store.get_files(uids).await.iter().all(Option::is_some));
+    let batched_reads = per_file(started, FILES);
+
+    println!("{:<40} {:>12}", "per file", "latency");
+    for (name, latency) in [
+        ("lookup, connection per command", fresh),
+        ("lookup, pooled connection", single_reads),
+        ("lookup, pipelined", batched_reads),
+        ("registration, pooled connection", single_writes),
+        ("registration, pipelined", batched_writes),
+    ] {
+        println!("{:<40} {:>12?}", name, latency);
+    }
+}
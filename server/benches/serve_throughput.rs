@@ -0,0 +1,104 @@
+//! Throughput of serving a large cached file through Rocket by the chunk size the body is
+//! read in: Rocket's default of 4 KiB versus larger reads, and by disk I/O backend.
+//!
+//! Run with `cargo bench --bench serve_throughput`; set `SERVE_BENCH_BYTES` to change the
+//! object size from 1 GiB. Add `--features io-uring` to compare io_uring as well.
+use istziio_server_node::cache::{CachedFile, SERVE_CHUNK_SIZE};
+use istziio_server_node::disk_io::{DiskBackend, DiskIo};
+use rocket::local::asynchronous::Client;
+use rocket::{get, routes, State};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const CHUNK_SIZES: [usize; 3] = [4096, 64 * 1024, SERVE_CHUNK_SIZE];
+
+struct Dir(PathBuf);
+
+#[get("/files/<name>?<chunk_size>&<backend>")]
+async fn file(
+    name: &str,
+    chunk_size: usize,
+    backend: &str,
+    dir: &State<Dir>,
+    backends: &State<Vec<DiskIo>>,
+) -> Option<CachedFile> {
+    let backend = backend.parse::<DiskBackend>().ok()?;
+    let disk_io = backends
+        .iter()
+        .find(|disk_io| disk_io.backend() == backend)?;
+    let file = CachedFile::open_with(dir.0.join(name), disk_io)
+        .await
+        .ok()?;
+    Some(file.with_chunk_size(chunk_size))
+}
+
+fn write_object(path: &std::path::Path, size: usize) {
+    let mut out = std::fs::File::create(path).unwrap();
+    let block = vec![0x5a; 1 << 20];
+    for written in (0..size).step_by(block.len()) {
+        out.write_all(&block[..block.len().min(size - written)])
+            .unwrap();
+    }
+}
+
+/// Time to read the whole response body, discarding it.
+async fn serve(client: &Client, backend: &str, chunk_size: usize, size: usize) -> Duration {
+    let started = Instant::now();
+    let response = client
+        .get(format!(
+            "/files/big.bin?chunk_size={}&backend={}",
+            chunk_size, backend
+        ))
+        .dispatch()
+        .await;
+    let sent = tokio::io::copy(&mut Box::pin(response), &mut tokio::io::sink())
+        .await
+        .unwrap();
+    assert_eq!(sent as usize, size);
+    started.elapsed()
+}
+
+fn main() {
+    let size: usize = std::env::var("SERVE_BENCH_BYTES")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(1 << 30);
+    let dir = tempfile::tempdir().unwrap();
+    write_object(&dir.path().join("big.bin"), size);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        // Without the feature io_uring falls back to tokio and is left out.
+        let mut backends = vec![DiskIo::new(DiskBackend::Tokio)];
+        let uring = DiskIo::new(DiskBackend::IoUring);
+        if uring.backend() == DiskBackend::IoUring {
+            backends.push(uring);
+        }
+        let names: Vec<&str> = backends
+            .iter()
+            .map(|disk_io| match disk_io.backend() {
+                DiskBackend::Tokio => "tokio",
+                DiskBackend::IoUring => "io-uring",
+            })
+            .collect();
+        let rocket = rocket::build()
+            .manage(Dir(dir.path().to_path_buf()))
+            .manage(backends)
+            .mount("/", routes![file]);
+        let client = Client::untracked(rocket).await.unwrap();
+        println!("{:<10} | {:<10} | {:>12}", "backend", "chunk", "MiB/s");
+        for backend in names {
+            for chunk_size in CHUNK_SIZES {
+                // The first read warms the page cache, so every run reads from memory.
+                serve(&client, backend, chunk_size, size).await;
+                let elapsed = serve(&client, backend, chunk_size, size).await;
+                println!(
+                    "{:<10} | {:<10} | {:>12.1}",
+                    backend,
+                    chunk_size,
+                    size as f64 / elapsed.as_secs_f64() / (1 << 20) as f64
+                );
+            }
+        }
+    });
+}
@@ -1,4 +1,4 @@
 #!/bin/sh
-echo "port ${REDIS_PORT}" >> /usr/local/bin/redis.conf
-redis-server /usr/local/bin/redis.conf &
-/usr/local/bin/istziio_server_node
+# The node runs and supervises its own redis-server. Arguments, e.g.
+# `cluster-init --peers ...`, go to the node.
+exec /usr/local/bin/istziio_server_node --embedded-redis "$@"
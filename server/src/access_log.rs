@@ -0,0 +1,207 @@
+// access_log.rs
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::response::Body;
+use rocket::{Data, Request, Response};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Result as IoResult, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::chunk::total_from_content_range;
+use crate::logging::{RequestId, RequestOutcome};
+
+/// One line of the access log, written as a JSON object.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessEntry {
+    pub ts: String,
+    pub request_id: String,
+    pub client: Option<String>,
+    pub method: String,
+    pub key: String,
+    pub status: u16,
+    /// Body bytes sent; for streamed bodies, as many as the client read before it went away.
+    pub bytes: u64,
+    /// Size of the whole object, when a `Content-Range` answer gives it.
+    pub object_size: Option<u64>,
+    /// `memory_hit`, `disk_hit`, `miss`, `pass_through` or `redirect`; unset when the
+    /// request never reached the cache (e.g. it was refused by auth or rate limiting).
+    pub cache: Option<&'static str>,
+    /// From the request arriving until the response is ready, or for streamed bodies
+    /// until the last byte has been handed to the connection.
+    pub latency_ms: f64,
+}
+
+impl AccessEntry {
+    /// The entry as a request trace line, `<unix ms>,<status>,<size>,<cache>,<key>`, where
+    /// `size` is the object's size when known and the bytes sent otherwise, and `cache` is
+    /// `-` when unset. The key comes last so that it may contain commas.
+    pub fn trace_line(&self) -> String {
+        let ms =
+            chrono::DateTime::parse_from_rfc3339(&self.ts).map_or(0, |ts| ts.timestamp_millis());
+        format!(
+            "{},{},{},{},{}",
+            ms,
+            self.status,
+            self.object_size.unwrap_or(self.bytes),
+            self.cache.unwrap_or("-"),
+            self.key
+        )
+    }
+}
+
+/// How entries are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Trace,
+}
+
+type Writer = Arc<Mutex<LineWriter<File>>>;
+
+fn write_entry(writer: &Writer, format: Format, entry: &AccessEntry) {
+    let line = match format {
+        Format::Json => match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(_) => return,
+        },
+        Format::Trace => entry.trace_line(),
+    };
+    let _ = writeln!(writer.lock().unwrap(), "{}", line);
+}
+
+struct RequestStart(Instant);
+
+/// Fairing appending an `AccessEntry` for every `/s3` request to a file.
+pub struct AccessLog {
+    writer: Writer,
+    format: Format,
+}
+
+impl AccessLog {
+    /// Writes entries as JSON lines.
+    pub fn open(path: &Path) -> IoResult<Self> {
+        Self::open_as(path, Format::Json)
+    }
+
+    /// Writes entries as request trace lines (see `AccessEntry::trace_line`), for
+    /// replaying the traffic later.
+    pub fn trace(path: &Path) -> IoResult<Self> {
+        Self::open_as(path, Format::Trace)
+    }
+
+    fn open_as(path: &Path, format: Format) -> IoResult<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Arc::new(Mutex::new(LineWriter::new(file))),
+            format,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for AccessLog {
+    fn info(&self) -> Info {
+        Info {
+            name: match self.format {
+                Format::Json => "Access log",
+                Format::Trace => "Request trace",
+            },
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if !req.uri().path().as_str().starts_with("/s3/") {
+            return;
+        }
+        let key = req.routed_segments(1..).collect::<Vec<_>>().join("/");
+        let entry = AccessEntry {
+            ts: chrono::Utc::now().to_rfc3339(),
+            request_id: RequestId::of(req).0,
+            client: req.client_ip().map(|ip| ip.to_string()),
+            method: req.method().to_string(),
+            key,
+            status: res.status().code,
+            bytes: 0,
+            object_size: res
+                .headers()
+                .get_one("Content-Range")
+                .and_then(total_from_content_range),
+            cache: RequestOutcome::of(req),
+            latency_ms: 0.0,
+        };
+        let started = req.local_cache(|| RequestStart(Instant::now())).0;
+        // Sized bodies are logged right away; streamed ones once they have been sent.
+        if let Some(size) = res.body_mut().size().await {
+            write_entry(
+                &self.writer,
+                self.format,
+                &AccessEntry {
+                    bytes: size as u64,
+                    latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+                    ..entry
+                },
+            );
+            return;
+        }
+        let max_chunk_size = res.body().max_chunk_size();
+        let body = res.body_mut().take();
+        res.set_streamed_body(CountingBody {
+            inner: body,
+            sent: 0,
+            started,
+            entry: Some(entry),
+            writer: self.writer.clone(),
+            format: self.format,
+        });
+        res.set_max_chunk_size(max_chunk_size);
+    }
+}
+
+/// Streamed response body that writes its access log entry when it is dropped.
+struct CountingBody<'r> {
+    inner: Body<'r>,
+    sent: u64,
+    started: Instant,
+    entry: Option<AccessEntry>,
+    writer: Writer,
+    format: Format,
+}
+
+impl AsyncRead for CountingBody<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.sent += (buf.filled().len() - before) as u64;
+        poll
+    }
+}
+
+impl Drop for CountingBody<'_> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            write_entry(
+                &self.writer,
+                self.format,
+                &AccessEntry {
+                    bytes: self.sent,
+                    latency_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+                    ..entry
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// admission.rs
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::util::FileUid;
+
+/// Upper bound on the number of "seen once" keys remembered by a single controller.
+const MAX_GHOST_ENTRIES: usize = 65536;
+
+/// Decides whether a missed object is worth writing to the disk cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum AdmissionPolicy {
+    /// Every miss is admitted (the original behavior).
+    #[default]
+    Always,
+    /// A key is only admitted on its second miss within `window`, so one-shot scans
+    /// are served straight from S3 without evicting the working set.
+    SecondHit { window: Duration },
+}
+
+impl FromStr for AdmissionPolicy {
+    type Err = String;
+
+    /// Parses `always` or `second-hit[:<window secs>]` (window defaults to 300s).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        match (name, arg) {
+            ("always", None) => Ok(AdmissionPolicy::Always),
+            ("second-hit", window) => {
+                let secs = match window {
+                    Some(w) => w
+                        .parse::<u64>()
+                        .map_err(|e| format!("invalid admission window '{}': {}", w, e))?,
+                    None => 300,
+                };
+                Ok(AdmissionPolicy::SecondHit {
+                    window: Duration::from_secs(secs),
+                })
+            }
+            _ => Err(format!("unknown admission policy '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for AdmissionPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Per-shard admission state. Lives behind the shard lock, so it needs no locking itself.
+pub struct AdmissionController {
+    policy: AdmissionPolicy,
+    ghosts: HashMap<FileUid, Instant>,
+}
+
+impl AdmissionController {
+    pub fn new(policy: AdmissionPolicy) -> Self {
+        Self {
+            policy,
+            ghosts: HashMap::new(),
+        }
+    }
+
+    pub fn policy(&self) -> AdmissionPolicy {
+        self.policy
+    }
+
+    /// Switches policy at runtime. Ghosts recorded under the old policy are dropped.
+    pub fn set_policy(&mut self, policy: AdmissionPolicy) {
+        if policy != self.policy {
+            self.policy = policy;
+            self.ghosts.clear();
+        }
+    }
+
+    /// Records a miss for `uid` and returns whether it should be cached.
+    pub fn admit(&mut self, uid: &str) -> bool {
+        self.admit_at(uid, Instant::now())
+    }
+
+    pub fn admit_at(&mut self, uid: &str, now: Instant) -> bool {
+        match self.policy {
+            AdmissionPolicy::Always => true,
+            AdmissionPolicy::SecondHit { window } => {
+                if let Some(seen_at) = self.ghosts.remove(uid) {
+                    if now.saturating_duration_since(seen_at) <= window {
+                        return true;
+                    }
+                }
+                if self.ghosts.len() >= MAX_GHOST_ENTRIES {
+                    self.ghosts
+                        .retain(|_, seen_at| now.saturating_duration_since(*seen_at) <= window);
+                    if self.ghosts.len() >= MAX_GHOST_ENTRIES {
+                        self.ghosts.clear();
+                    }
+                }
+                self.ghosts.insert(uid.to_string(), now);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// auth.rs
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+/// Tokens checked by the request guards below; managed as Rocket state. Both are sent as
+/// `Authorization: Bearer <token>`.
+#[derive(Debug, Clone, Default)]
+pub struct AuthConfig {
+    /// Required on `/clear` and `/admin/*`, which are refused outright while it is unset.
+    pub admin_token: Option<String>,
+    /// Any of these (or the admin token) grants access to the data and stats routes.
+    /// Those routes are open when the list is empty.
+    pub read_tokens: Vec<String>,
+}
+
+impl AuthConfig {
+    /// Whether `token` is the admin token or one of the read tokens.
+    pub fn recognizes(&self, token: &str) -> bool {
+        self.read_tokens
+            .iter()
+            .map(String::as_str)
+            .chain(self.admin_token.as_deref())
+            .fold(false, |granted, expected| {
+                tokens_match(token, expected) | granted
+            })
+    }
+}
+
+/// Request guard for admin routes.
+pub struct AdminAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminAccess {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = match req
+            .rocket()
+            .state::<AuthConfig>()
+            .and_then(|auth| auth.admin_token.as_deref())
+        {
+            Some(token) => token,
+            None => return Outcome::Error((Status::Forbidden, "admin API is disabled")),
+        };
+        match bearer_token(req) {
+            Some(token) if tokens_match(token, expected) => Outcome::Success(AdminAccess),
+            _ => Outcome::Error((Status::Unauthorized, "missing or invalid admin token")),
+        }
+    }
+}
+
+/// Request guard for the data and stats routes.
+pub struct ReadAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReadAccess {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let auth = match req.rocket().state::<AuthConfig>() {
+            Some(auth) if !auth.read_tokens.is_empty() => auth,
+            _ => return Outcome::Success(ReadAccess),
+        };
+        let token = match bearer_token(req) {
+            Some(token) => token,
+            None => return Outcome::Error((Status::Unauthorized, "missing read token")),
+        };
+        if auth.recognizes(token) {
+            Outcome::Success(ReadAccess)
+        } else {
+            Outcome::Error((Status::Unauthorized, "invalid read token"))
+        }
+    }
+}
+
+pub(crate) fn bearer_token<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    req.headers()
+        .get_one("Authorization")?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Compares in time independent of where the first mismatch is.
+pub fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,218 @@
+// bootstrap.rs
+//! Forms the Redis cluster the nodes share, in place of the `redis-cli --cluster create`
+//! run by hand. Every node is given the same list of peers; the node whose Redis is the
+//! first peer splits the slots evenly between them and introduces them to each other,
+//! and every node waits until its own Redis sees all slots served before it starts.
+//! Running it again on a formed cluster changes nothing.
+use log::{info, warn};
+use std::collections::HashMap;
+use std::net::ToSocketAddrs;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::cache_core::slot_counts;
+use crate::metadata::SLOT_COUNT;
+
+/// Upper bound on one Redis command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait before asking a peer again.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// How long a bootstrap may take by default, from the first peer starting up to the
+/// cluster serving every slot.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Error)]
+pub enum BootstrapError {
+    #[error("no peers given")]
+    NoPeers,
+    #[error("peer '{0}' is not a host:port")]
+    BadPeer(String),
+    #[error("peer {peer}: {source}")]
+    Redis {
+        peer: String,
+        source: redis::RedisError,
+    },
+    #[error("gave up after {0:?} waiting for {1}")]
+    Timeout(Duration, String),
+}
+
+/// The `(host, port)` of a `host:port` peer.
+pub fn parse_peer(peer: &str) -> Result<(String, u16), BootstrapError> {
+    let bad = || BootstrapError::BadPeer(peer.to_string());
+    let (host, port) = peer.rsplit_once(':').ok_or_else(bad)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(bad());
+    }
+    Ok((host.to_string(), port.parse().map_err(|_| bad())?))
+}
+
+struct Peer {
+    address: String,
+    conn: redis::Connection,
+}
+
+impl Peer {
+    /// Connects to `address`, retrying until it answers or `deadline` passes.
+    fn connect(address: &str, deadline: Instant) -> Result<Self, BootstrapError> {
+        let (host, port) = parse_peer(address)?;
+        let url = format!("redis://{}:{}", host, port);
+        let attempt = || -> redis::RedisResult<redis::Connection> {
+            let mut conn =
+                redis::Client::open(url.as_str())?.get_connection_with_timeout(COMMAND_TIMEOUT)?;
+            conn.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+            redis::cmd("PING").query::<String>(&mut conn)?;
+            Ok(conn)
+        };
+        let conn = retry(deadline, &format!("{} to answer", address), attempt)?;
+        Ok(Peer {
+            address: address.to_string(),
+            conn,
+        })
+    }
+
+    fn query<T: redis::FromRedisValue>(&mut self, cmd: &redis::Cmd) -> Result<T, BootstrapError> {
+        cmd.query(&mut self.conn)
+            .map_err(|source| BootstrapError::Redis {
+                peer: self.address.clone(),
+                source,
+            })
+    }
+
+    fn id(&mut self) -> Result<String, BootstrapError> {
+        let id: String = self.query(redis::cmd("CLUSTER").arg("MYID"))?;
+        Ok(id.trim().to_string())
+    }
+
+    /// The fields of `CLUSTER INFO`.
+    fn info(&mut self) -> Result<HashMap<String, String>, BootstrapError> {
+        let info: String = self.query(redis::cmd("CLUSTER").arg("INFO"))?;
+        Ok(info
+            .lines()
+            .filter_map(|line| line.trim().split_once(':'))
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect())
+    }
+
+    /// Whether the peer's Redis serves any slots itself.
+    fn owns_slots(&mut self) -> Result<bool, BootstrapError> {
+        let nodes: String = self.query(redis::cmd("CLUSTER").arg("NODES"))?;
+        // `<id> <addr> <flags> <master> <ping> <pong> <epoch> <link> <slot>...`
+        Ok(nodes
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .any(|fields| fields.len() > 8 && fields[2].split(',').any(|f| f == "myself")))
+    }
+}
+
+/// Calls `attempt` until it succeeds, giving up once `deadline` passes.
+fn retry<T, E: std::fmt::Display>(
+    deadline: Instant,
+    what: &str,
+    mut attempt: impl FnMut() -> Result<T, E>,
+) -> Result<T, BootstrapError> {
+    let started = Instant::now();
+    loop {
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(e) if Instant::now() >= deadline => {
+                warn!("Still waiting for {}: {}", what, e);
+                return Err(BootstrapError::Timeout(started.elapsed(), what.to_string()));
+            }
+            Err(_) => std::thread::sleep(RETRY_DELAY),
+        }
+    }
+}
+
+fn field<'a>(info: &'a HashMap<String, String>, name: &str) -> &'a str {
+    info.get(name).map_or("", String::as_str)
+}
+
+/// Forms the cluster of `peers` if this node's Redis, on `local_port`, is the first of
+/// them, then waits until the cluster serves every slot. Blocks for up to `timeout`.
+pub fn cluster_init(
+    peers: &[String],
+    local_port: u16,
+    timeout: Duration,
+) -> Result<(), BootstrapError> {
+    let deadline = Instant::now() + timeout;
+    let Some(first) = peers.first() else {
+        return Err(BootstrapError::NoPeers);
+    };
+    for peer in peers {
+        parse_peer(peer)?;
+    }
+    let mut local = Peer::connect(&format!("127.0.0.1:{}", local_port), deadline)?;
+    let mut leader = Peer::connect(first, deadline)?;
+    if local.id()? == leader.id()? {
+        form(&mut leader, peers, deadline)?;
+    }
+    let what = format!("the cluster of {} nodes to serve every slot", peers.len());
+    retry(deadline, &what, || {
+        let info = local.info().map_err(|e| e.to_string())?;
+        let known = field(&info, "cluster_known_nodes").parse().unwrap_or(0);
+        let assigned = field(&info, "cluster_slots_assigned").parse().unwrap_or(0);
+        if field(&info, "cluster_state") == "ok" && known >= peers.len() && assigned == SLOT_COUNT {
+            return Ok(());
+        }
+        Err(format!(
+            "cluster_state is {}, with {} nodes known and {} slots assigned",
+            field(&info, "cluster_state"),
+            known,
+            assigned
+        ))
+    })?;
+    info!(
+        "Redis cluster of {} nodes is serving every slot",
+        peers.len()
+    );
+    Ok(())
+}
+
+/// Gives each peer that serves no slots its even share, then has `leader` meet them all.
+fn form(leader: &mut Peer, peers: &[String], deadline: Instant) -> Result<(), BootstrapError> {
+    let first = &peers[0];
+    let info = leader.info()?;
+    if field(&info, "cluster_slots_assigned") == SLOT_COUNT.to_string()
+        && field(&info, "cluster_known_nodes") == peers.len().to_string()
+    {
+        info!("Redis cluster is already formed");
+        return Ok(());
+    }
+    info!("Forming a Redis cluster of {} nodes", peers.len());
+    let mut first_slot = 0;
+    for (address, count) in peers.iter().zip(slot_counts(&vec![1; peers.len()])) {
+        let last_slot = first_slot + count - 1;
+        let mut peer = Peer::connect(address, deadline)?;
+        if !peer.owns_slots()? {
+            peer.query::<()>(
+                redis::cmd("CLUSTER")
+                    .arg("ADDSLOTSRANGE")
+                    .arg(first_slot)
+                    .arg(last_slot),
+            )?;
+            info!("Assigned slots {}-{} to {}", first_slot, last_slot, address);
+        }
+        first_slot = last_slot + 1;
+        if address == first {
+            continue;
+        }
+        // Redis only meets peers by IP address.
+        let (host, port) = parse_peer(address)?;
+        let ip = (host.as_str(), port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| BootstrapError::BadPeer(address.clone()))?
+            .ip();
+        leader.query::<()>(
+            redis::cmd("CLUSTER")
+                .arg("MEET")
+                .arg(ip.to_string())
+                .arg(port),
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+// breaker.rs
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Stops calling a dependency that keeps failing. After `threshold` failures in a row the
+/// breaker opens and `allow` turns calls away for `cooldown`; the first call after that
+/// is a trial whose outcome closes or re-opens it.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Whether a call may go through now.
+    pub fn allow(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .open_until
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    /// Whether the breaker has tripped and not yet seen a success since.
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open_until.is_some()
+    }
+
+    /// Records a successful call; returns true if this closed an open breaker.
+    pub fn record_success(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.open_until.take().is_some()
+    }
+
+    /// Records a failed call; returns true if this opened a closed breaker.
+    pub fn record_failure(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if state.failures < self.threshold {
+            return false;
+        }
+        let was_open = state.open_until.is_some();
+        state.open_until = Some(Instant::now() + self.cooldown);
+        !was_open
+    }
+}
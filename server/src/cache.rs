@@ -1,262 +1,3383 @@
 // cache.rs
-use chrono;
-use log::{debug, info};
-use rocket::{fs::NamedFile, response::Redirect};
-use std::collections::VecDeque;
-use std::fs;
-use std::io::Result as IoResult;
+use bytes::Bytes;
+use log::{debug, info, warn};
+use rocket::futures::{stream, StreamExt};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::Redirect;
+use rocket::response::{self, Responder, Response};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, Cursor, Read, Result as IoResult, Seek, SeekFrom};
 use std::net::IpAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{Mutex, MutexGuard, Notify, OwnedMutexGuard, RwLock, RwLockReadGuard};
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::{info_span, Instrument};
 use url::Url;
 
-use crate::redis::RedisServer;
-use crate::storage::storage_connector::StorageConnector;
-use crate::util::hash;
+use crate::admission::AdmissionPolicy;
+use crate::cache_core::{shard_index, CacheCore, CacheIndex};
+use crate::chunk::{chunk_bounds, chunk_key, ByteRange};
+use crate::compression::{
+    compress_file, compress_sealed_file, decompress_stream, reader_stream, CompressionCodec,
+    CompressionConfig,
+};
+use crate::conditional::{NotModified, Preconditions};
+use crate::costs::with_tenant;
+use crate::deadline::{bounded, is_timeout, Stage, TimeoutConfig};
+use crate::disk_io::{DiskFile, DiskIo};
+use crate::disks::{
+    is_disk_error, stripe_budgets, CacheDirConfig, DiskHealth, DiskStatus, PROBE_FILE,
+};
+use crate::download::{is_abandoned, DisconnectPolicy, Download};
+use crate::encryption::{
+    plaintext_len, seal, seal_stream, sealed_len, DecryptingReader, EncryptionKey,
+};
+use crate::error::CacheError;
+use crate::eviction::{EvictionConfig, EVICTION_BATCH};
+use crate::footer::{footer_key, footer_len, is_parquet_key, FOOTER_TAIL_LEN};
+use crate::hotkeys::{merge_top, HotKey, HotKeys, HotKeysReport};
+use crate::identity::NODE_ID_FILE;
+use crate::listing::{shared_key, ListedObject, Listing, ListingCache};
+use crate::logging::{record_outcome, record_shard};
+use crate::memory_cache::MemoryCache;
+use crate::metadata::{MetadataStore, StoreError};
+use crate::metrics::{
+    hit_ratio, EfficiencyRow, EfficiencyTotals, EfficiencyWindows, LatencyWindow, RollingCounts,
+    EFFICIENCY_WINDOWS,
+};
+use crate::policy::PolicySet;
+use crate::prefixes::{object_key, usage_by_prefix, PrefixAccesses, PrefixUsage};
+use crate::priority::{is_batch, with_priority, RequestPriority};
+use crate::ring::{moved_share, HashRing, RingError, RingOp, RingPlacement};
+use crate::scrub::{scan_dir, ScrubReport, ORPHAN_GRACE};
+use crate::shadow::{ShadowCache, ShadowConfig, ShadowStats};
+use crate::shard_runtime::{ShardRuntimeConfig, ShardRuntimes};
+use crate::storage::storage_connector::{
+    read_stream_to_end, ListRequest, ObjectListing, ObjectStream, ObjectVersion, StorageConnector,
+};
+use crate::tenant::{TenantStats, Tenants};
+use crate::util::{disk_name, temp_name};
+use crate::versioning::split_version;
 
 // Constants
 pub const PORT_OFFSET_TO_WEB_SERVER: u16 = 20000;
+/// How many trailing bytes of a Parquet object are read when looking for its footer.
+pub const DEFAULT_FOOTER_PREFETCH: u64 = 64 * 1024;
+/// Bytes read per chunk of a response body. Rocket's default of 4 KiB costs a trip to the
+/// blocking pool, and a write to the socket, for every 4 KiB of a large object.
+pub const SERVE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Shared access to the metadata store, held while a request is served.
+type MetadataGuard<'a> = RwLockReadGuard<'a, Box<dyn MetadataStore>>;
+
+/// The metadata store, for work that outlives the request.
+type SharedMetadata = Arc<RwLock<Box<dyn MetadataStore>>>;
+
 // Cache Structures -----------------------------------------------------------
 
 pub struct ConcurrentDiskCache {
     shards: Vec<Arc<Mutex<DiskCache>>>,
-    pub redis: Arc<RwLock<RedisServer>>,
-    redis_port: u16,
+    pub metadata: SharedMetadata,
+    redirect_scheme: &'static str,
+    tenants: Tenants,
+    /// Set when keys are placed on the hash ring rather than by slot.
+    ring: Option<RingPlacement>,
+    /// Signalled when keys changed owner, slot or ring.
+    ownership_changed: Notify,
+    /// Web server addresses the members advertised, by their Redis `(endpoint, port)`;
+    /// read along with the mapping.
+    addresses: std::sync::RwLock<HashMap<(String, u16), String>>,
+    /// Cache directories; shard `i` lives on disk `i % disks.len()`.
+    disks: Vec<Disk>,
+    /// What every scrub since startup repaired.
+    scrubbed: std::sync::Mutex<ScrubReport>,
+    /// How long a request may wait to learn which node owns its key.
+    metadata_timeout: Option<Duration>,
+    /// Pages of the backing store's listings served by `/list` and the S3 API.
+    listings: ListingCache,
+    /// Whether listing pages are also kept in the metadata store, for every node to use.
+    share_listings: bool,
+    /// One single-threaded runtime per shard, when requests run on their shard's thread.
+    runtimes: Option<ShardRuntimes>,
+    /// The entries of every shard, for disk hits served without the shard lock.
+    unlocked: Vec<UnlockedShard>,
+    /// Whether hits can skip the shard lock: not when they go through the memory tier,
+    /// decoding, hedging or chunk bookkeeping.
+    unlocked_hits: bool,
+    /// Whether a rule has a TTL or revalidation clock, which hits check under the shard
+    /// lock; rules change at runtime.
+    clocks: AtomicBool,
+}
+
+/// Hits above this many waiting to be recorded take the shard lock to record them, if
+/// it is free.
+const UNLOCKED_HITS_BACKLOG: usize = 1024;
+
+/// Chunks of a borrowed body read ahead of the staged file being written.
+const STAGING_DEPTH: usize = 4;
+
+/// A disk hit served without the shard lock, recorded into the shard's stats and usage
+/// reports once the lock is next taken.
+struct UnlockedHit {
+    uid: String,
+    tenant: Option<String>,
+    bytes: u64,
+    latency: Duration,
+}
+
+type UnlockedHits = Arc<std::sync::Mutex<Vec<UnlockedHit>>>;
+/// Keys soft-invalidated since they were last checked against S3, with when they were.
+type StaleKeys = Arc<std::sync::Mutex<HashMap<String, Instant>>>;
+
+/// What serving a hit without the shard lock takes of a shard.
+struct UnlockedShard {
+    index: Arc<CacheIndex>,
+    cache_dir: PathBuf,
+    disk_io: DiskIo,
+    hits: UnlockedHits,
+    stale: StaleKeys,
+}
+
+/// A cache directory with the capacity it was configured with.
+struct Disk {
+    capacity: u64,
+    disk_io: DiskIo,
+}
+
+impl Disk {
+    fn health(&self) -> &Arc<DiskHealth> {
+        self.disk_io
+            .health()
+            .expect("cache disks track their health")
+    }
 }
 
 pub struct DiskCache {
     cache_dir: PathBuf,
-    max_size: u64,
-    current_size: u64,
-    access_order: VecDeque<(String, u64)>,
+    /// Entries in eviction order with the budget, policies and admission.
+    core: CacheCore,
+    memory: MemoryCache,
+    stats: ShardStats,
+    hot_keys: HotKeys,
+    prefix_accesses: PrefixAccesses,
+    /// Simulations of other policies and sizes, fed this shard's accesses.
+    shadows: Vec<ShadowCache>,
+    /// When entries under a TTL policy were fetched.
+    fetched_at: HashMap<String, Instant>,
+    /// Entries being fetched again ahead of their TTL.
+    refreshing: HashSet<String>,
+    /// Budgets of this shard's share of every tenant.
+    tenants: Tenants,
+    /// Tenant each entry is charged to.
+    owners: HashMap<String, String>,
+    /// On-disk bytes charged to each tenant, kept with `owners`.
+    tenant_bytes: HashMap<String, u64>,
+    tenant_stats: HashMap<String, TenantStats>,
+    chunk_size: Option<u64>,
+    object_sizes: HashMap<String, u64>,
+    /// Versions S3 reported for cached objects, by key; chunked objects keep theirs like
+    /// `object_sizes`.
+    versions: HashMap<String, ObjectVersion>,
+    /// When the versions of objects under a revalidation policy were last known current.
+    validated_at: HashMap<String, Instant>,
+    /// Keys that stay servable but are revalidated before they are next served.
+    stale: StaleKeys,
+    parquet_footer_prefetch: Option<u64>,
+    compression: Option<CompressionConfig>,
+    /// Compressed entries with their codec and logical (uncompressed) size. Every other
+    /// size tracked by the shard is the physical on-disk size.
+    compressed: HashMap<String, (CompressionCodec, u64)>,
+    /// Keys being downloaded right now; a miss waits on the key's mutex instead of
+    /// fetching the same object again.
+    in_flight: HashMap<String, Arc<Mutex<()>>>,
+    /// Objects of at least this size are sent to the client while they are downloaded.
+    stream_fetches_from: Option<u64>,
+    /// Disk hits slower than this to start are raced against S3.
+    hedge_after: Option<Duration>,
+    on_client_disconnect: DisconnectPolicy,
+    /// Downloads clients are reading while they land; a miss joins the running download.
+    downloads: HashMap<String, Download>,
+    disk_io: DiskIo,
+    encryption: Option<Arc<EncryptionKey>>,
+    /// Set when entries are evicted in the background between watermarks.
+    eviction: Option<EvictionConfig>,
+    /// Hits served by `ConcurrentDiskCache` without the lock, not recorded yet.
+    unlocked_hits: UnlockedHits,
+}
+
+/// Tunables applied to every shard of a `ConcurrentDiskCache`.
+#[derive(Debug, Clone, Default)]
+pub struct CacheOptions {
+    pub admission_policy: AdmissionPolicy,
+    pub max_cacheable_object_size: Option<u64>,
+    /// Total bytes of the in-memory hot tier across all shards; 0 disables it.
+    pub memory_tier_size: u64,
+    /// Only objects up to this size are promoted to the memory tier.
+    pub memory_tier_max_object_size: u64,
+    /// When set, Range requests are served from fixed-size chunks of this many bytes.
+    pub chunk_size: Option<u64>,
+    /// When set, footers of `.parquet` objects are cached eagerly, reading this many
+    /// trailing bytes on the first attempt.
+    pub parquet_footer_prefetch: Option<u64>,
+    /// Transparent on-disk compression of whole cached objects.
+    pub compression: Option<CompressionConfig>,
+    /// Redirect clients to peers over `https` (the nodes terminate TLS).
+    pub redirect_https: bool,
+    /// Key sealing cached files on disk. Files are sealed as they are written, including
+    /// staged copies, and compressed (sealed again) before they are recorded in the cache.
+    pub encryption: Option<Arc<EncryptionKey>>,
+    /// Per-prefix TTL, eviction priority, admission and quota rules. Quotas are per node
+    /// here and divided between the shards by `ConcurrentDiskCache::new`.
+    pub policies: PolicySet,
+    /// Tenants with their node-wide budgets, divided between shards like `policies`.
+    pub tenants: Tenants,
+    /// When set, keys are placed on the hash ring kept in the metadata store instead of
+    /// by slot, and nodes join it with this many virtual nodes.
+    pub ring_vnodes: Option<u32>,
+    /// Syncs the ring with the metadata store's members whenever it is loaded, so that
+    /// nodes join and leave it without `/admin/ring`.
+    pub sync_ring: bool,
+    /// When set, misses on objects of at least this many bytes are answered while the
+    /// object is still being downloaded, instead of once it is on disk.
+    pub stream_fetches_from: Option<u64>,
+    /// When set, a disk hit whose first chunk takes longer than this to read is also
+    /// requested from S3, and the client gets whichever copy arrives first.
+    pub hedge_after: Option<Duration>,
+    /// What a streamed miss does when every client reading it disconnects.
+    pub on_client_disconnect: DisconnectPolicy,
+    /// Reads and writes cached files on disk.
+    pub disk_io: DiskIo,
+    /// Bounds the owner lookup in the metadata store; other stages are bounded by the
+    /// connectors and `disk_io`.
+    pub timeouts: TimeoutConfig,
+    /// How long `/list` keeps pages of the backing store's listings; not at all when
+    /// unset.
+    pub listing_ttl: Option<Duration>,
+    /// Also keep listing pages in the metadata store, so that a page one node listed is
+    /// served by the others until `listing_ttl` runs out.
+    pub share_listings: bool,
+    /// When set, shards are spread over these directories instead of all living in the
+    /// cache directory, and their capacities weight the budget of the shards on them.
+    pub cache_dirs: Vec<CacheDirConfig>,
+    /// When set, this many bytes of each cache directory's file system are kept free for
+    /// other processes by `ConcurrentDiskCache::reserve_free_space`.
+    pub free_space_reserve: Option<u64>,
+    /// When set, shards past the high watermark are evicted down to the low watermark by
+    /// `ConcurrentDiskCache::evict_in_background` rather than on the write path.
+    pub eviction: Option<EvictionConfig>,
+    /// Eviction policies and sizes simulated alongside the cache; their budgets are per
+    /// node here and divided between the shards like `max_size`.
+    pub shadow_caches: Vec<ShadowConfig>,
+    /// When set, every shard gets a runtime on a thread of its own, which
+    /// `ConcurrentDiskCache::on_shard_of` runs requests on.
+    pub shard_runtimes: Option<ShardRuntimeConfig>,
+}
+
+/// What emptying the cache dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClearReport {
+    /// Entries whose file was deleted, and the bytes freed with them.
+    pub files: u64,
+    pub bytes: u64,
+    /// Entries whose file could not be deleted; its bytes stay charged.
+    pub undeleted_files: u64,
+}
+
+impl ClearReport {
+    pub fn add(&mut self, other: &ClearReport) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.undeleted_files += other.undeleted_files;
+    }
+}
+
+impl fmt::Display for ClearReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cleared {} files, freed {} bytes",
+            self.files, self.bytes
+        )?;
+        if self.undeleted_files > 0 {
+            write!(f, ", {} files could not be deleted", self.undeleted_files)?;
+        }
+        Ok(())
+    }
+}
+
+/// Request outcome counters of a single shard.
+#[derive(Debug, Clone, Default)]
+pub struct ShardStats {
+    pub memory_hits: u64,
+    pub disk_hits: u64,
+    pub misses: u64,
+    /// Hits and misses of the last few minutes.
+    pub recent: RollingCounts,
+    /// Logical bytes answered from the memory tier or disk.
+    pub bytes_from_cache: u64,
+    /// Bytes downloaded from S3 into the cache.
+    pub bytes_from_s3: u64,
+    pub evictions: u64,
+    /// Evictions on the write path while background eviction is on, i.e. when it fell
+    /// behind.
+    pub emergency_evictions: u64,
+    /// Version checks sent to S3 for hits due for revalidation.
+    pub revalidations: u64,
+    /// Revalidations that found the object changed (or gone) in S3.
+    pub stale_revalidations: u64,
+    /// Hits served stale while the entry was refreshed in the background.
+    pub stale_hits: u64,
+    /// Entries fetched again in the background shortly before their TTL ran out.
+    pub refreshes_ahead: u64,
+    /// Disk hits that were slow enough to also be requested from S3.
+    pub hedged_reads: u64,
+    /// Hedged reads answered from S3 because it beat the disk.
+    pub hedges_won: u64,
+    /// Streamed misses stopped because their clients disconnected.
+    pub aborted_downloads: u64,
+    /// Time to find a cached object and start serving it.
+    pub hit_latency: LatencyWindow,
+    /// Time to download an object (or chunk) from S3 onto disk.
+    pub s3_fetch_latency: LatencyWindow,
+    /// Hits, misses and downloads over the windows of `/stats/efficiency`.
+    pub efficiency: EfficiencyWindows,
+}
+
+impl ShardStats {
+    fn ratio(&self, count: u64) -> f64 {
+        let total = self.memory_hits + self.disk_hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            count as f64 / total as f64 * 100.0
+        }
+    }
+
+    fn record_hit(&mut self, bytes: u64, started: Instant) {
+        self.record_hit_in(bytes, started.elapsed());
+    }
+
+    fn record_hit_in(&mut self, bytes: u64, latency: Duration) {
+        self.recent.record(true);
+        self.bytes_from_cache += bytes;
+        self.hit_latency.record(latency);
+        self.efficiency.record_hit(bytes, latency);
+    }
+
+    fn record_miss(&mut self) {
+        self.misses += 1;
+        self.recent.record(false);
+        self.efficiency.record_miss();
+    }
+
+    fn record_fetch(&mut self, bytes: u64, started: Instant) {
+        let latency = started.elapsed();
+        self.bytes_from_s3 += bytes;
+        self.s3_fetch_latency.record(latency);
+        self.efficiency.record_fetch(bytes, latency);
+    }
+}
+
+/// Point-in-time view of one cache directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskSnapshot {
+    pub path: PathBuf,
+    /// Byte budget of the shards on the disk.
+    pub max_size: u64,
+    /// Bytes cached on the disk.
+    pub current_size: u64,
+    pub status: DiskStatus,
+    /// Failed reads and writes since startup.
+    pub errors: u64,
+}
+
+/// Point-in-time view of one shard, for reports that render outside the shard lock.
+#[derive(Debug, Clone, Default)]
+pub struct ShardSnapshot {
+    pub index: usize,
+    pub current_size: u64,
+    pub logical_size: u64,
+    pub max_size: u64,
+    pub files: usize,
+    pub memory_size: u64,
+    pub memory_files: usize,
+    pub stats: ShardStats,
+    /// Most accessed keys, most accessed first.
+    pub hot_keys: Vec<HotKey>,
+    /// What the shard's shadow caches would have done.
+    pub shadows: Vec<ShadowStats>,
+}
+
+/// A node of the cluster, as seen in this node's slot mapping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterMember {
+    pub node_id: String,
+    pub endpoint: String,
+    pub port: u16,
+    pub slots: usize,
+    /// Relative capacity the node registered; it owns keys in proportion to it.
+    pub weight: u32,
+    /// `host:port` the node's web server is reached at, if the node advertised one;
+    /// otherwise it is found at its Redis port plus `PORT_OFFSET_TO_WEB_SERVER`.
+    pub address: Option<String>,
+    pub is_self: bool,
+}
+
+/// Everything the dashboard shows, taken shard by shard.
+#[derive(Debug, Clone)]
+pub struct CacheSnapshot {
+    pub taken_at: chrono::DateTime<chrono::Utc>,
+    /// Shards that could not be locked within the timeout are left out.
+    pub shards: Vec<ShardSnapshot>,
+    pub members: Vec<ClusterMember>,
+    pub disks: Vec<DiskSnapshot>,
+    /// What every scrub since startup repaired.
+    pub scrubbed: ScrubReport,
+}
+
+/// Body of an object that is served straight from S3 without being cached.
+pub struct PassThrough(pub ObjectStream);
+
+/// A copy of an object written by `ConcurrentDiskCache::stage`, in the cache directory and
+/// sealed like the cached files, waiting to be ingested.
+pub struct Staged {
+    path: PathBuf,
+    /// Size of the content.
+    size: u64,
+    /// Size on disk.
+    stored_size: u64,
+    encryption: Option<Arc<EncryptionKey>>,
+}
+
+impl Staged {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The content of the staged copy, decrypted as it is read.
+    pub async fn open(&self) -> IoResult<ObjectStream> {
+        let (encryption, path) = (self.encryption.clone(), self.path.clone());
+        let reader = tokio::task::spawn_blocking(move || open_cached_file(encryption, None, &path))
+            .await
+            .map_err(io::Error::other)??;
+        Ok(reader_stream(reader))
+    }
+
+    /// Deletes the staged copy.
+    pub async fn discard(self) {
+        let _ = tokio::fs::remove_file(&self.path).await;
+    }
+}
+
+impl<'r> Responder<'r, 'static> for PassThrough {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(ContentType::Binary)
+            .streamed_body(StreamReader::new(self.0))
+            .max_chunk_size(SERVE_CHUNK_SIZE)
+            .ok()
+    }
+}
+
+/// Content type by the extension of `path`, `application/octet-stream` if unknown.
+fn content_type_of(path: &Path) -> ContentType {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary)
+}
+
+/// A cached file sent from disk with its length, `SERVE_CHUNK_SIZE` bytes at a time.
+pub struct CachedFile {
+    file: Box<dyn DiskFile>,
+    len: u64,
+    content_type: ContentType,
+    chunk_size: usize,
+}
+
+impl CachedFile {
+    pub async fn open(path: impl AsRef<Path>) -> IoResult<Self> {
+        Self::open_with(path, &DiskIo::default()).await
+    }
+
+    /// Opens `path` for reading through `disk_io`.
+    pub async fn open_with(path: impl AsRef<Path>, disk_io: &DiskIo) -> IoResult<Self> {
+        let (file, len) = disk_io.open(path.as_ref(), SERVE_CHUNK_SIZE).await?;
+        Ok(Self {
+            file,
+            len,
+            content_type: content_type_of(path.as_ref()),
+            chunk_size: SERVE_CHUNK_SIZE,
+        })
+    }
+
+    /// Opens `path` through `disk_io` and reads its first chunk before returning.
+    pub async fn open_primed(path: impl AsRef<Path>, disk_io: &DiskIo) -> IoResult<Self> {
+        let (file, len) = disk_io.open_primed(path.as_ref(), SERVE_CHUNK_SIZE).await?;
+        Ok(Self {
+            file,
+            len,
+            content_type: content_type_of(path.as_ref()),
+            chunk_size: SERVE_CHUNK_SIZE,
+        })
+    }
+
+    /// Reads `chunk_size` bytes per chunk instead, e.g. to compare throughput.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.file.set_chunk_size(chunk_size);
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<'r> Responder<'r, 'static> for CachedFile {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(self.content_type)
+            .sized_body(usize::try_from(self.len).ok(), self.file)
+            .max_chunk_size(self.chunk_size)
+            .ok()
+    }
+}
+
+/// A small object served from the memory tier.
+pub struct MemoryHit {
+    data: Bytes,
+    content_type: ContentType,
+}
+
+impl MemoryHit {
+    fn new(uid: &str, data: Bytes) -> Self {
+        Self {
+            data,
+            content_type: content_type_of(Path::new(uid)),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for MemoryHit {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(self.content_type)
+            .sized_body(self.data.len(), Cursor::new(self.data))
+            .ok()
+    }
+}
+
+/// A byte range of an object, answered with `206 Partial Content`.
+pub struct PartialContent {
+    body: Box<dyn AsyncRead + Send + Unpin>,
+    start: u64,
+    end: u64,
+    total: u64,
+}
+
+impl<'r> Responder<'r, 'static> for PartialContent {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .status(Status::PartialContent)
+            .header(ContentType::Binary)
+            .header(Header::new("Accept-Ranges", "bytes"))
+            .header(Header::new(
+                "Content-Range",
+                format!("bytes {}-{}/{}", self.start, self.end, self.total),
+            ))
+            .streamed_body(self.body)
+            .max_chunk_size(SERVE_CHUNK_SIZE)
+            .ok()
+    }
+}
+
+/// Per-request knobs supplied by the client.
+#[derive(Debug, Clone, Default)]
+pub struct GetFileOptions {
+    /// Skip the admission policy and always cache the object on a miss.
+    pub force_admit: bool,
+    /// Byte range requested through the `Range` header.
+    pub range: Option<ByteRange>,
+    /// The client's `Accept-Encoding` header.
+    pub accept_encoding: Option<String>,
+    /// Tenant named by the request's bearer token; otherwise the key decides.
+    pub tenant: Option<String>,
+    /// Path to redirect to on the node owning the key; `s3/<uid>` when unset.
+    pub redirect_path: Option<String>,
+    /// `If-None-Match` and `If-Modified-Since`, checked against the cached version.
+    pub preconditions: Preconditions,
+    /// Sent as `X-Istziio-Priority`; see `priority`.
+    pub priority: RequestPriority,
 }
 
 #[derive(rocket::Responder)]
 pub enum GetFileResult {
     #[response(status = 200)]
-    Hit(NamedFile),
+    Hit(CachedFile),
+    #[response(status = 200)]
+    MemoryHit(MemoryHit),
+    #[response(status = 200)]
+    Encoded(CachedFile, Header<'static>),
+    /// Compressed bytes decrypted on the fly, sent with their `Content-Encoding`.
+    #[response(status = 200)]
+    EncodedStream(PassThrough, Header<'static>),
+    #[response(status = 200)]
+    PassThrough(PassThrough),
+    /// An object sent as it is being downloaded into the cache.
+    #[response(status = 200)]
+    Streaming(PassThrough),
+    #[response(status = 206)]
+    Partial(PartialContent),
+    /// The client holds the cached version already.
+    #[response(status = 304)]
+    NotModified(NotModified),
     #[response(status = 303)]
     Redirect(Box<Redirect>), // Box this Redirect to avoid [warn] clippy::large_enum_variant
-    #[response(status = 404)]
-    NotFoundOnS3(String),
-    #[response(status = 500)]
-    InitFailed(String),
+    /// Answered with the error's own status and a JSON body.
+    Error(CacheError),
+}
+
+/// Bytes of an object read without answering an HTTP request.
+pub type ObjectReader = Box<dyn AsyncRead + Send + Unpin>;
+
+impl GetFileResult {
+    /// The body of the object, for callers outside Rocket. Results that only make sense
+    /// over HTTP, such as redirects and encoded bodies, are errors; they don't occur
+    /// without a cluster, an `Accept-Encoding` or preconditions.
+    pub fn into_reader(self) -> IoResult<ObjectReader> {
+        match self {
+            GetFileResult::Hit(file) => Ok(Box::new(file.file)),
+            GetFileResult::MemoryHit(hit) => Ok(Box::new(Cursor::new(hit.data))),
+            GetFileResult::PassThrough(body) | GetFileResult::Streaming(body) => {
+                Ok(Box::new(StreamReader::new(body.0)))
+            }
+            GetFileResult::Partial(partial) => Ok(partial.body),
+            GetFileResult::Error(e) => Err(e.into()),
+            GetFileResult::Encoded(..) | GetFileResult::EncodedStream(..) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the object was answered with a Content-Encoding",
+            )),
+            GetFileResult::NotModified(_) => Err(io::Error::other("the object was not modified")),
+            GetFileResult::Redirect(_) => Err(io::Error::other("the key is owned by another node")),
+        }
+    }
+
+    /// The bytes in `range` of the object, which a node leaves to Rocket unless it caches
+    /// chunks. Bodies streamed from S3 are errors: their size is not known up front.
+    pub async fn into_range_reader(self, range: ByteRange) -> IoResult<ObjectReader> {
+        let not_satisfiable = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Requested range not satisfiable",
+            )
+        };
+        match self {
+            GetFileResult::Hit(mut file) => {
+                let (start, end) = range.resolve(file.len).ok_or_else(not_satisfiable)?;
+                file.file.seek(SeekFrom::Start(start)).await?;
+                Ok(Box::new(file.file.take(end - start + 1)))
+            }
+            GetFileResult::MemoryHit(hit) => {
+                let (start, end) = range
+                    .resolve(hit.data.len() as u64)
+                    .ok_or_else(not_satisfiable)?;
+                let data = hit.data.slice(start as usize..=end as usize);
+                Ok(Box::new(Cursor::new(data)))
+            }
+            GetFileResult::PassThrough(_) | GetFileResult::Streaming(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the object is not cached as a whole",
+            )),
+            result => result.into_reader(),
+        }
+    }
+}
+
+async fn read_local_footer(path: &Path) -> IoResult<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let file_len = file.metadata().await?.len();
+    if file_len < FOOTER_TAIL_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a Parquet file",
+        ));
+    }
+    let mut tail = [0u8; FOOTER_TAIL_LEN as usize];
+    file.seek(SeekFrom::End(-(FOOTER_TAIL_LEN as i64))).await?;
+    file.read_exact(&mut tail).await?;
+    let len = footer_len(&tail)
+        .filter(|len| *len <= file_len)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a Parquet file"))?;
+    let mut footer = vec![0u8; len as usize];
+    file.seek(SeekFrom::End(-(len as i64))).await?;
+    file.read_exact(&mut footer).await?;
+    Ok(footer)
+}
+
+async fn fetch_footer(
+    uid: &str,
+    prefetch: u64,
+    connector: &Arc<dyn StorageConnector + Send + Sync>,
+) -> IoResult<Vec<u8>> {
+    let object = connector
+        .fetch_range(uid, ByteRange::Suffix(prefetch))
+        .await?;
+    let tail = read_stream_to_end(object.stream).await?;
+    let len = footer_len(&tail)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a Parquet file"))?;
+    if len <= tail.len() as u64 {
+        return Ok(tail[tail.len() - len as usize..].to_vec());
+    }
+    // The metadata is larger than the prefetch, so read exactly the whole footer.
+    let object = connector.fetch_range(uid, ByteRange::Suffix(len)).await?;
+    read_stream_to_end(object.stream).await
+}
+
+/// Compresses a freshly cached object of `file_size` bytes, now `physical_size` bytes on
+/// disk, if the compression settings cover it. Returns the size it occupies on disk and
+/// the codec used, if any.
+async fn compress_cached_file(
+    compression: Option<&CompressionConfig>,
+    encryption: Option<&Arc<EncryptionKey>>,
+    uid: &str,
+    path: &Path,
+    file_size: u64,
+    physical_size: u64,
+) -> (u64, Option<CompressionCodec>) {
+    let codec = match compression {
+        Some(config) if config.applies_to(uid, file_size) => config.codec,
+        _ => return (physical_size, None),
+    };
+    let path = path.to_path_buf();
+    let key = encryption.cloned();
+    let compressed = tokio::task::spawn_blocking(move || match key {
+        Some(key) => compress_sealed_file(codec, key, &path),
+        None => compress_file(codec, &path),
+    });
+    match compressed.await {
+        Ok(Ok(Some(compressed_size))) => {
+            debug!(
+                "Compressed {} from {} to {} bytes",
+                uid, file_size, compressed_size
+            );
+            (compressed_size, Some(codec))
+        }
+        Ok(Ok(None)) => (physical_size, None),
+        Ok(Err(e)) => {
+            info!("Failed to compress {}: {}", uid, e);
+            (physical_size, None)
+        }
+        Err(e) => {
+            info!("Failed to compress {}: {}", uid, e);
+            (physical_size, None)
+        }
+    }
+}
+
+/// Seals `stream` when encryption at rest is on, so that it reaches the disk encrypted.
+fn sealing(encryption: Option<&Arc<EncryptionKey>>, stream: ObjectStream) -> ObjectStream {
+    match encryption {
+        Some(key) => seal_stream(key.clone(), stream),
+        None => stream,
+    }
+}
+
+/// Size of the content of a cache file of `physical_size` bytes, before any compression.
+fn unsealed_len(encryption: Option<&Arc<EncryptionKey>>, physical_size: u64) -> u64 {
+    match encryption {
+        Some(_) => plaintext_len(physical_size),
+        None => physical_size,
+    }
+}
+
+/// Opens an encrypted cache file for plaintext reads starting at `offset`.
+async fn open_sealed(
+    key: Arc<EncryptionKey>,
+    path: PathBuf,
+    offset: u64,
+) -> IoResult<DecryptingReader<File>> {
+    tokio::task::spawn_blocking(move || {
+        let mut reader = DecryptingReader::open(key, &path)?;
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(reader)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Opens a cached file for plaintext reads, undoing encryption and compression. Blocking.
+fn open_cached_file(
+    encryption: Option<Arc<EncryptionKey>>,
+    codec: Option<CompressionCodec>,
+    path: &Path,
+) -> IoResult<Box<dyn Read + Send>> {
+    let mut reader: Box<dyn Read + Send> = match encryption {
+        Some(key) => Box::new(DecryptingReader::open(key, path)?),
+        None => Box::new(File::open(path)?),
+    };
+    if let Some(codec) = codec {
+        reader = codec.decoder(reader)?;
+    }
+    Ok(reader)
+}
+
+/// Reads a whole cached file into memory, undoing encryption and compression. Blocking.
+fn read_cached_file(
+    encryption: Option<Arc<EncryptionKey>>,
+    codec: Option<CompressionCodec>,
+    path: &Path,
+) -> IoResult<Bytes> {
+    let mut reader = open_cached_file(encryption, codec, path)?;
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(Bytes::from(data))
 }
 
-// DiskCache Implementation ---------------------------------------------------
+/// Serves an encrypted cache file, decrypting (and decompressing unless the client accepts
+/// the codec) on the blocking pool.
+async fn serve_sealed(
+    key: Arc<EncryptionKey>,
+    uid: String,
+    path: PathBuf,
+    codec: Option<CompressionCodec>,
+    accepted: bool,
+) -> GetFileResult {
+    let reader = match open_sealed(key, path, 0).await {
+        Ok(reader) => Box::new(reader),
+        Err(e) => return read_error(uid, e),
+    };
+    match codec {
+        Some(codec) if accepted => GetFileResult::EncodedStream(
+            PassThrough(reader_stream(reader)),
+            Header::new("Content-Encoding", codec.content_encoding()),
+        ),
+        Some(codec) => match codec.decoder(reader) {
+            Ok(decoder) => GetFileResult::PassThrough(PassThrough(reader_stream(decoder))),
+            Err(e) => read_error(uid, e),
+        },
+        None => GetFileResult::PassThrough(PassThrough(reader_stream(reader))),
+    }
+}
+
+/// A cached copy that could not be read back.
+fn read_error(uid: String, e: io::Error) -> GetFileResult {
+    info!("Failed to read {}: {}", uid, e);
+    GetFileResult::Error(CacheError::Internal {
+        key: uid,
+        reason: e.to_string(),
+    })
+}
+
+/// `uid` as a key; one that isn't UTF-8 can't name an object.
+fn key_of(uid: PathBuf) -> Result<String, CacheError> {
+    uid.into_os_string()
+        .into_string()
+        .map_err(|uid| CacheError::InvalidKey {
+            key: uid.to_string_lossy().into_owned(),
+        })
+}
+
+fn fetch_error(uid: String, e: io::Error) -> GetFileResult {
+    info!("{}", e);
+    let key = uid;
+    GetFileResult::Error(if is_timeout(&e) {
+        CacheError::Timeout {
+            key,
+            reason: e.to_string(),
+        }
+    } else {
+        match e.kind() {
+            io::ErrorKind::NotFound => CacheError::NotFound { key },
+            io::ErrorKind::InvalidInput => CacheError::RangeNotSatisfiable { key },
+            io::ErrorKind::TimedOut => CacheError::Overloaded { key },
+            _ => CacheError::Upstream {
+                key,
+                reason: e.to_string(),
+            },
+        }
+    })
+}
+
+// DiskCache Implementation ---------------------------------------------------
+
+impl DiskCache {
+    pub fn new(cache_dir: PathBuf, max_size: u64, options: &CacheOptions) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            cache_dir,
+            core: CacheCore::new(max_size, options.policies.clone(), options.admission_policy)
+                .limiting_objects_to(options.max_cacheable_object_size),
+            memory: MemoryCache::new(
+                options.memory_tier_size,
+                options.memory_tier_max_object_size,
+            ),
+            stats: ShardStats::default(),
+            hot_keys: HotKeys::default(),
+            prefix_accesses: PrefixAccesses::default(),
+            shadows: options.shadow_caches.iter().map(ShadowCache::new).collect(),
+            fetched_at: HashMap::new(),
+            refreshing: HashSet::new(),
+            tenants: options.tenants.clone(),
+            owners: HashMap::new(),
+            tenant_bytes: HashMap::new(),
+            tenant_stats: HashMap::new(),
+            chunk_size: options.chunk_size,
+            object_sizes: HashMap::new(),
+            versions: HashMap::new(),
+            validated_at: HashMap::new(),
+            stale: StaleKeys::default(),
+            parquet_footer_prefetch: options.parquet_footer_prefetch,
+            compression: options.compression.clone(),
+            compressed: HashMap::new(),
+            in_flight: HashMap::new(),
+            stream_fetches_from: options.stream_fetches_from,
+            hedge_after: options.hedge_after,
+            on_client_disconnect: options.on_client_disconnect,
+            downloads: HashMap::new(),
+            disk_io: options.disk_io.clone(),
+            encryption: options.encryption.clone(),
+            eviction: options.eviction,
+            unlocked_hits: UnlockedHits::default(),
+        }))
+    }
+
+    /// Records the hits served without the shard lock since it was last held.
+    fn record_unlocked_hits(&mut self) {
+        let hits = std::mem::take(&mut *self.unlocked_hits.lock().unwrap());
+        for hit in hits {
+            self.stats.disk_hits += 1;
+            self.stats.record_hit_in(hit.bytes, hit.latency);
+            let tenant = self.tenants.resolve(hit.tenant.as_deref(), &hit.uid);
+            self.record_access(&hit.uid, tenant.as_deref(), true, hit.bytes);
+        }
+    }
+
+    pub async fn get_file(
+        cache: Arc<Mutex<Self>>,
+        uid: PathBuf,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+        metadata: &MetadataGuard<'_>,
+        store: &SharedMetadata,
+        options: &GetFileOptions,
+    ) -> GetFileResult {
+        let started = Instant::now();
+        let uid_str = match key_of(uid) {
+            Ok(key) => key,
+            Err(e) => return GetFileResult::Error(e),
+        };
+        let mut shard = cache.lock().await;
+        shard.record_unlocked_hits();
+        let overdue = shard
+            .ttl_overdue(&uid_str)
+            .max(shard.revalidation_overdue(&uid_str));
+        match overdue {
+            Some(overdue) if shard.serves_stale(&uid_str, overdue) => {
+                debug!("serving stale while refreshing");
+                shard.stats.stale_hits += 1;
+                Self::refresh_in_background(&cache, &uid_str, &connector, store);
+            }
+            // Past the window, an expired entry is dropped by `expire_stale` below.
+            _ if shard.revalidation_due(&uid_str) => {
+                shard = Self::revalidate(&cache, shard, &uid_str, &connector, metadata).await;
+            }
+            _ => {}
+        }
+        if shard.refresh_ahead_due(&uid_str) {
+            shard.refreshing.insert(uid_str.clone());
+            Self::refresh_ahead(&cache, &uid_str, &connector, store);
+        }
+        if !options.preconditions.is_empty() && shard.versions.contains_key(&uid_str) {
+            shard.expire_stale(&uid_str, metadata).await;
+            let held = shard
+                .versions
+                .get(&uid_str)
+                .filter(|version| options.preconditions.not_modified(version))
+                .cloned();
+            if let Some(version) = held {
+                debug!("client holds the cached version");
+                record_outcome("not_modified");
+                shard.stats.disk_hits += 1;
+                shard.stats.record_hit(0, started);
+                shard.touch(&uid_str);
+                return GetFileResult::NotModified(NotModified(version));
+            }
+        }
+        if let (Some(range), Some(chunk_size)) = (options.range, shard.chunk_size) {
+            // Chunk bookkeeping is interleaved with the ranged fetches, so ranges are still
+            // assembled under the shard lock.
+            return shard
+                .get_range(uid_str, range, chunk_size, connector, metadata, options)
+                .await;
+        }
+        let tenant = shard.tenants.resolve(options.tenant.as_deref(), &uid_str);
+        shard.expire_stale(&uid_str, metadata).await;
+        if let Some(data) = shard.memory.get(&uid_str) {
+            debug!("found in memory tier");
+            record_outcome("memory_hit");
+            shard.stats.memory_hits += 1;
+            shard.stats.record_hit(data.len() as u64, started);
+            shard.record_access(&uid_str, tenant.as_deref(), true, data.len() as u64);
+            shard.touch(&uid_str);
+            return GetFileResult::MemoryHit(MemoryHit::new(&uid_str, data));
+        }
+        let mut hit = false;
+        let file_name = if let Some(file_name) = metadata
+            .get_file(uid_str.clone())
+            .instrument(info_span!("redis_lookup"))
+            .await
+        {
+            debug!("found in cache");
+            record_outcome("disk_hit");
+            shard.stats.disk_hits += 1;
+            hit = true;
+            file_name
+        } else {
+            shard.stats.record_miss();
+            record_outcome("miss");
+            if let Some(download) = shard.downloads.get(&uid_str) {
+                // Opened under the shard lock, before the download can be compressed or
+                // sealed in place.
+                if let Ok(body) = download.reader().await {
+                    debug!("joining the download in progress");
+                    record_outcome("streamed");
+                    shard.record_access(&uid_str, tenant.as_deref(), false, 0);
+                    return GetFileResult::Streaming(PassThrough(body));
+                }
+            }
+            let admitted = shard
+                .core
+                .admits(&uid_str, options.force_admit, Instant::now());
+            let in_flight = shard.in_flight.entry(uid_str.clone()).or_default().clone();
+            // The download happens without the shard lock so that one slow object doesn't
+            // hold up every other request hashed to this shard.
+            drop(shard);
+            let fetched = if admitted {
+                Self::fetch_into_cache(
+                    &cache,
+                    &uid_str,
+                    tenant.as_deref(),
+                    &in_flight,
+                    &connector,
+                    metadata,
+                    store,
+                )
+                .await
+            } else {
+                debug!("not admitted, streaming from S3");
+                record_outcome("pass_through");
+                Err(match connector.fetch_stream(&uid_str).await {
+                    Ok(object) => GetFileResult::PassThrough(PassThrough(object.stream)),
+                    Err(e) => fetch_error(uid_str.clone(), e),
+                })
+            };
+            shard = cache.lock().await;
+            // A streaming download lets go of the key itself once the object is cached.
+            if !matches!(fetched, Err(GetFileResult::Streaming(_))) {
+                shard.release_in_flight(&uid_str, &in_flight);
+            }
+            match fetched {
+                Ok(file_name) => file_name,
+                Err(result) => {
+                    if let GetFileResult::PassThrough(_) | GetFileResult::Streaming(_) = result {
+                        shard.record_access(&uid_str, tenant.as_deref(), false, 0);
+                    }
+                    return result;
+                }
+            }
+        };
+        let shard_lock = cache.clone();
+        async move {
+            let file_name_str = file_name.to_str().unwrap_or_default().to_string();
+            debug!(file = file_name_str.as_str(); "serving from disk");
+            shard.touch(&uid_str);
+            let size = shard.cached_size(&uid_str);
+            if hit {
+                shard.stats.record_hit(size, started);
+            }
+            shard.record_access(&uid_str, tenant.as_deref(), hit, size);
+            let cache_file_path = shard.cache_dir.join(file_name);
+            if let Some(data) = shard.promote_to_memory(&uid_str, &cache_file_path).await {
+                return GetFileResult::MemoryHit(MemoryHit::new(&uid_str, data));
+            }
+            let compressed = shard.compressed.get(&uid_str).copied();
+            let encryption = shard.encryption.clone();
+            let disk_io = shard.disk_io.clone();
+            let hedge_after = shard.hedge_after;
+            drop(shard);
+            let accepted = compressed.is_some_and(|(codec, _)| {
+                options
+                    .accept_encoding
+                    .as_deref()
+                    .is_some_and(|accept| codec.accepted_by(accept))
+            });
+            if let Some(key) = encryption {
+                let codec = compressed.map(|(codec, _)| codec);
+                return serve_sealed(key, uid_str, cache_file_path, codec, accepted).await;
+            }
+            if let Some((codec, _)) = compressed {
+                if accepted {
+                    return match CachedFile::open_with(cache_file_path, &disk_io).await {
+                        Ok(x) => GetFileResult::Encoded(
+                            x,
+                            Header::new("Content-Encoding", codec.content_encoding()),
+                        ),
+                        Err(e) => Self::bypass_disk(uid_str, e, &connector).await,
+                    };
+                }
+                return match decompress_stream(codec, &cache_file_path) {
+                    Ok(stream) => GetFileResult::PassThrough(PassThrough(stream)),
+                    Err(e) => Self::bypass_disk(uid_str, e, &connector).await,
+                };
+            }
+            if let Some(after) = hedge_after {
+                return Self::hedged_open(
+                    &shard_lock,
+                    uid_str,
+                    cache_file_path,
+                    &disk_io,
+                    after,
+                    &connector,
+                )
+                .await;
+            }
+            match CachedFile::open_with(cache_file_path, &disk_io).await {
+                Ok(x) => GetFileResult::Hit(x),
+                Err(e) => Self::bypass_disk(uid_str, e, &connector).await,
+            }
+        }
+        .instrument(info_span!("serve"))
+        .await
+    }
+
+    /// Serves the cached copy of `uid` at `path` once its first chunk has been read. If that
+    /// takes longer than `after`, e.g. because the disk stalls, the object is requested from
+    /// S3 as well and the client gets whichever copy starts arriving first. S3 is the only
+    /// other copy: peers don't hold keys they don't own.
+    async fn hedged_open(
+        cache: &Arc<Mutex<Self>>,
+        uid: String,
+        path: PathBuf,
+        disk_io: &DiskIo,
+        after: Duration,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+    ) -> GetFileResult {
+        let local = CachedFile::open_primed(path, disk_io);
+        tokio::pin!(local);
+        let opened = match tokio::time::timeout(after, &mut local).await {
+            Ok(opened) => opened,
+            Err(_) => {
+                debug!(after_ms = after.as_millis() as u64; "slow disk read, hedging to S3");
+                cache.lock().await.stats.hedged_reads += 1;
+                let remote = connector.fetch_stream(&uid);
+                tokio::pin!(remote);
+                tokio::select! {
+                    opened = &mut local => opened,
+                    fetched = &mut remote => match fetched {
+                        Ok(object) => {
+                            record_outcome("hedged");
+                            cache.lock().await.stats.hedges_won += 1;
+                            return GetFileResult::PassThrough(PassThrough(object.stream));
+                        }
+                        Err(e) => {
+                            debug!("hedged fetch failed, waiting for the disk: {}", e);
+                            local.await
+                        }
+                    },
+                }
+            }
+        };
+        match opened {
+            Ok(x) => GetFileResult::Hit(x),
+            Err(e) => Self::bypass_disk(uid, e, connector).await,
+        }
+    }
+
+    /// Streams `uid` from S3 after the cache disk failed to read or write it, so that the
+    /// client doesn't see the disk's error.
+    async fn bypass_disk(
+        uid: String,
+        e: io::Error,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+    ) -> GetFileResult {
+        warn!("Cache disk failed on {}, streaming it from S3: {}", uid, e);
+        record_outcome("pass_through");
+        match connector.fetch_stream(&uid).await {
+            Ok(object) => GetFileResult::PassThrough(PassThrough(object.stream)),
+            Err(e) => fetch_error(uid, e),
+        }
+    }
+
+    /// Downloads `uid` into the cache directory and records it, returning its local file
+    /// name. Only the bookkeeping at the end takes the shard lock; concurrent misses on the
+    /// same key wait on `in_flight` and then find the object in Redis. `Err` carries a
+    /// response to send instead, e.g. when the object is served without being cached or
+    /// while it is still being downloaded.
+    async fn fetch_into_cache(
+        cache: &Arc<Mutex<Self>>,
+        uid: &str,
+        tenant: Option<&str>,
+        in_flight: &Arc<Mutex<()>>,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        metadata: &MetadataGuard<'_>,
+        store: &SharedMetadata,
+    ) -> Result<PathBuf, GetFileResult> {
+        let downloading = in_flight.clone().lock_owned().await;
+        if let Some(file_name) = metadata.get_file(uid.to_string()).await {
+            debug!("cached by a concurrent request");
+            return Ok(file_name);
+        }
+        let (cache_dir, disk_io, max_cacheable_size, stream_fetches_from, encryption) = {
+            let shard = cache.lock().await;
+            (
+                shard.cache_dir.clone(),
+                shard.disk_io.clone(),
+                shard.core.max_cacheable_size().min(
+                    tenant
+                        .and_then(|tenant| shard.tenants.budget(tenant))
+                        .unwrap_or(u64::MAX),
+                ),
+                // Readers follow a streamed download in the file, so it can't be sealed.
+                shard
+                    .stream_fetches_from
+                    .filter(|_| shard.encryption.is_none()),
+                shard.encryption.clone(),
+            )
+        };
+        let started = Instant::now();
+        let object = match connector
+            .fetch_stream(uid)
+            .instrument(info_span!("s3_fetch"))
+            .await
+        {
+            Ok(object) => object,
+            Err(e) => return Err(fetch_error(uid.to_string(), e)),
+        };
+        if !disk_io.is_up() {
+            debug!("cache disk is down, streaming from S3");
+            record_outcome("pass_through");
+            return Err(GetFileResult::PassThrough(PassThrough(object.stream)));
+        }
+        if object
+            .content_length
+            .is_some_and(|len| len > max_cacheable_size)
+        {
+            debug!(limit = max_cacheable_size; "too large to cache, streaming from S3");
+            record_outcome("pass_through");
+            return Err(GetFileResult::PassThrough(PassThrough(object.stream)));
+        }
+        let version = object.version.clone();
+        if object
+            .content_length
+            .zip(stream_fetches_from)
+            .is_some_and(|(len, from)| len >= from)
+        {
+            debug!("streaming while it is cached");
+            record_outcome("streamed");
+            return Err(Self::stream_into_cache(
+                cache,
+                uid,
+                tenant,
+                downloading,
+                connector,
+                store,
+                object.stream,
+                version,
+                started,
+            )
+            .await);
+        }
+        let cache_file_path = cache_dir.join(disk_name(uid));
+        let file_size = match disk_io
+            .write_stream(
+                sealing(encryption.as_ref(), object.stream),
+                &cache_file_path,
+            )
+            .instrument(info_span!("disk_write"))
+            .await
+        {
+            Ok(size) => unsealed_len(encryption.as_ref(), size),
+            Err(e) if is_disk_error(&e) => {
+                return Err(Self::bypass_disk(uid.to_string(), e, connector).await);
+            }
+            Err(e) => return Err(fetch_error(uid.to_string(), e)),
+        };
+        if file_size > max_cacheable_size {
+            // The store did not report a length up front, so the object only turned out to
+            // be too large once on disk: serve this copy once and drop it.
+            debug!(size = file_size; "turned out too large to cache");
+            if let Some(key) = encryption {
+                let result =
+                    serve_sealed(key, uid.to_string(), cache_file_path.clone(), None, false).await;
+                let _ = tokio::fs::remove_file(&cache_file_path).await;
+                return Err(result);
+            }
+            let result = CachedFile::open_with(&cache_file_path, &disk_io).await;
+            let _ = tokio::fs::remove_file(&cache_file_path).await;
+            return Err(match result {
+                Ok(x) => GetFileResult::Hit(x),
+                Err(e) => read_error(uid.to_string(), e),
+            });
+        }
+        debug!(size = file_size; "fetched from S3");
+        Self::admit_download(
+            cache, uid, tenant, version, file_size, started, connector, metadata,
+        )
+        .await
+    }
+
+    /// Compresses the object just downloaded to the file named `uid`, then records
+    /// it in the shard and the metadata store. Returns its local file name.
+    #[allow(clippy::too_many_arguments)]
+    async fn admit_download(
+        cache: &Arc<Mutex<Self>>,
+        uid: &str,
+        tenant: Option<&str>,
+        version: ObjectVersion,
+        file_size: u64,
+        started: Instant,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        metadata: &MetadataGuard<'_>,
+    ) -> Result<PathBuf, GetFileResult> {
+        let (cache_dir, compression, encryption) = {
+            let shard = cache.lock().await;
+            (
+                shard.cache_dir.clone(),
+                shard.compression.clone(),
+                shard.encryption.clone(),
+            )
+        };
+        let local_file_name = PathBuf::from(disk_name(uid));
+        let cache_file_path = cache_dir.join(&local_file_name);
+        let stored_size = match encryption {
+            Some(_) => sealed_len(file_size),
+            None => file_size,
+        };
+        let (physical_size, codec) = compress_cached_file(
+            compression.as_ref(),
+            encryption.as_ref(),
+            uid,
+            &cache_file_path,
+            file_size,
+            stored_size,
+        )
+        .await;
+
+        let mut shard = cache.lock().await;
+        shard.stats.record_fetch(file_size, started);
+        if let Some(codec) = codec {
+            shard.compressed.insert(uid.to_string(), (codec, file_size));
+        }
+        shard
+            .insert_entry(metadata, uid.to_string(), physical_size, tenant)
+            .await;
+        shard.record_version(uid, version);
+        let _ = metadata
+            .set_file_cache_loc(uid.to_string(), local_file_name.clone())
+            .await;
+        if shard.parquet_footer_prefetch.is_some() && is_parquet_key(uid) {
+            if let Err(e) = shard.ensure_footer(uid, tenant, connector, metadata).await {
+                info!("Failed to cache footer of {}: {}", uid, e);
+            }
+        }
+        Ok(local_file_name)
+    }
+
+    /// Writes `stream` into the cache in the background and answers with the bytes as they
+    /// land. Misses on `uid` join this download until it is on disk, after which they
+    /// wait on `downloading` and find the object cached.
+    #[allow(clippy::too_many_arguments)]
+    async fn stream_into_cache(
+        cache: &Arc<Mutex<Self>>,
+        uid: &str,
+        tenant: Option<&str>,
+        downloading: OwnedMutexGuard<()>,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        store: &SharedMetadata,
+        stream: ObjectStream,
+        version: ObjectVersion,
+        started: Instant,
+    ) -> GetFileResult {
+        let mut shard = cache.lock().await;
+        let path = shard.file_path(uid);
+        let on_disconnect = shard.on_client_disconnect;
+        let started_download = match Download::create(&path).await {
+            Ok((download, writer)) => download
+                .reader()
+                .await
+                .map(|body| (download, writer.on_disconnect(on_disconnect), body)),
+            Err(e) => Err(e),
+        };
+        let (download, writer, body) = match started_download {
+            Ok(started_download) => started_download,
+            Err(e) => {
+                warn!("Cannot cache {}, streaming it from S3: {}", uid, e);
+                return GetFileResult::PassThrough(PassThrough(stream));
+            }
+        };
+        shard.downloads.insert(uid.to_string(), download);
+        drop(shard);
+
+        let cache = cache.clone();
+        let uid = uid.to_string();
+        let tenant = tenant.map(String::from);
+        let connector = connector.clone();
+        let store = store.clone();
+        tokio::spawn(async move {
+            let written = writer.write(stream).await;
+            // Readers that haven't opened the file yet must not see it compressed, so the
+            // download is retired before the file is touched.
+            cache.lock().await.downloads.remove(&uid);
+            match written {
+                Ok(file_size) => {
+                    debug!(size = file_size; "fetched from S3 while streaming");
+                    let metadata = store.read().await;
+                    let _ = Self::admit_download(
+                        &cache,
+                        &uid,
+                        tenant.as_deref(),
+                        version,
+                        file_size,
+                        started,
+                        &connector,
+                        &metadata,
+                    )
+                    .await;
+                }
+                Err(e) if is_abandoned(&e) => {
+                    debug!("Stopped downloading {}: {}", uid, e);
+                    let _ = tokio::fs::remove_file(&path).await;
+                    cache.lock().await.stats.aborted_downloads += 1;
+                }
+                Err(e) => {
+                    info!("Failed to download {}: {}", uid, e);
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+            }
+            let in_flight = OwnedMutexGuard::mutex(&downloading).clone();
+            cache.lock().await.release_in_flight(&uid, &in_flight);
+        });
+        GetFileResult::Streaming(PassThrough(body))
+    }
+
+    /// Forgets the download lock of `uid` unless a newer one took its place.
+    fn release_in_flight(&mut self, uid: &str, in_flight: &Arc<Mutex<()>>) {
+        if self
+            .in_flight
+            .get(uid)
+            .is_some_and(|entry| Arc::ptr_eq(entry, in_flight))
+        {
+            self.in_flight.remove(uid);
+        }
+    }
+
+    /// Serves only the footer (file metadata) of a Parquet object.
+    pub async fn get_parquet_metadata(
+        cache: Arc<Mutex<Self>>,
+        uid: PathBuf,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+        metadata: &MetadataGuard<'_>,
+    ) -> GetFileResult {
+        let uid_str = match key_of(uid) {
+            Ok(key) => key,
+            Err(e) => return GetFileResult::Error(e),
+        };
+        let mut cache = cache.lock().await;
+        if !is_parquet_key(&uid_str) {
+            return GetFileResult::Error(CacheError::not_found(uid_str));
+        }
+        let tenant = cache.tenants.by_key(&uid_str).map(String::from);
+        match cache
+            .ensure_footer(&uid_str, tenant.as_deref(), &connector, metadata)
+            .await
+        {
+            Ok(footer_path) => match cache.encryption.clone() {
+                Some(key) => serve_sealed(key, uid_str, footer_path, None, false).await,
+                None => match CachedFile::open_with(footer_path, &cache.disk_io).await {
+                    Ok(x) => GetFileResult::Hit(x),
+                    Err(e) => read_error(uid_str, e),
+                },
+            },
+            Err(e) => fetch_error(uid_str, e),
+        }
+    }
+
+    /// Makes sure the footer of `uid` is cached as its own entry and returns its path. The
+    /// footer is cut from the local copy when the whole object is cached, otherwise it is
+    /// fetched with suffix range requests.
+    async fn ensure_footer(
+        &mut self,
+        uid: &str,
+        tenant: Option<&str>,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        metadata: &MetadataGuard<'_>,
+    ) -> IoResult<PathBuf> {
+        let key = footer_key(uid);
+        if self.is_tracked(&key) {
+            self.touch(&key);
+            return Ok(self.file_path(&key));
+        }
+        let footer = if self.is_tracked(uid)
+            && !self.compressed.contains_key(uid)
+            && self.encryption.is_none()
+        {
+            read_local_footer(&self.file_path(uid)).await?
+        } else {
+            let prefetch = self
+                .parquet_footer_prefetch
+                .unwrap_or(DEFAULT_FOOTER_PREFETCH)
+                .max(FOOTER_TAIL_LEN);
+            fetch_footer(uid, prefetch, connector).await?
+        };
+        let footer_path = self.file_path(&key);
+        let footer = match &self.encryption {
+            Some(key) => seal(key.clone(), &footer)?,
+            None => footer,
+        };
+        tokio::fs::write(&footer_path, &footer).await?;
+        let size = footer.len() as u64;
+        debug!("Cached footer of {} ({} bytes)", uid, size);
+        self.insert_entry(metadata, key.clone(), size, tenant).await;
+        let _ = metadata
+            .set_file_cache_loc(key.clone(), PathBuf::from(disk_name(&key)))
+            .await;
+        Ok(footer_path)
+    }
+
+    /// Serves `range` of `uid` from cached chunks, fetching only the missing chunks from S3
+    /// with ranged GETs.
+    async fn get_range(
+        &mut self,
+        uid: String,
+        range: ByteRange,
+        chunk_size: u64,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+        metadata: &MetadataGuard<'_>,
+        options: &GetFileOptions,
+    ) -> GetFileResult {
+        let started = Instant::now();
+        let tenant = self.tenants.resolve(options.tenant.as_deref(), &uid);
+        let mut admitted = false;
+        let total = match self.object_sizes.get(&uid) {
+            Some(total) => *total,
+            None => {
+                if !self.disk_io.is_up()
+                    || !self.core.admits(&uid, options.force_admit, Instant::now())
+                {
+                    return Self::pass_through_range(uid, range, connector).await;
+                }
+                admitted = true;
+                // The object size is learned from the first chunk we fetch.
+                let index = range.start_hint().map_or(0, |start| start / chunk_size);
+                if let Err(e) = self
+                    .load_chunk(
+                        &uid,
+                        tenant.as_deref(),
+                        index,
+                        chunk_size,
+                        &connector,
+                        metadata,
+                    )
+                    .instrument(info_span!("load_chunk", index))
+                    .await
+                {
+                    if is_disk_error(&e) {
+                        warn!(
+                            "Cannot cache a chunk of {}, streaming it from S3: {}",
+                            uid, e
+                        );
+                        return Self::pass_through_range(uid, range, connector).await;
+                    }
+                    return fetch_error(uid, e);
+                }
+                match self.object_sizes.get(&uid) {
+                    Some(total) => *total,
+                    None => {
+                        return GetFileResult::Error(CacheError::Internal {
+                            key: uid,
+                            reason: String::from("the size of the object is unknown"),
+                        })
+                    }
+                }
+            }
+        };
+        let (start, end) = match range.resolve(total) {
+            Some(bounds) => bounds,
+            None => return GetFileResult::Error(CacheError::RangeNotSatisfiable { key: uid }),
+        };
+
+        let indices = (start / chunk_size)..=(end / chunk_size);
+        for index in indices.clone() {
+            self.expire_stale(&chunk_key(&uid, index), metadata).await;
+        }
+        let missing = indices
+            .clone()
+            .filter(|index| !self.is_tracked(&chunk_key(&uid, *index)))
+            .collect::<Vec<_>>();
+        let hit = missing.is_empty();
+        if hit {
+            self.stats.disk_hits += 1;
+            record_outcome("disk_hit");
+        } else {
+            self.stats.record_miss();
+            record_outcome("miss");
+            if !self.disk_io.is_up()
+                || (!admitted && !self.core.admits(&uid, options.force_admit, Instant::now()))
+            {
+                return Self::pass_through_range(uid, range, connector).await;
+            }
+        }
+        for index in missing {
+            if let Err(e) = self
+                .load_chunk(
+                    &uid,
+                    tenant.as_deref(),
+                    index,
+                    chunk_size,
+                    &connector,
+                    metadata,
+                )
+                .instrument(info_span!("load_chunk", index))
+                .await
+            {
+                if is_disk_error(&e) {
+                    warn!(
+                        "Cannot cache a chunk of {}, streaming it from S3: {}",
+                        uid, e
+                    );
+                    return Self::pass_through_range(uid, range, connector).await;
+                }
+                return fetch_error(uid, e);
+            }
+        }
+        if self.parquet_footer_prefetch.is_some() && is_parquet_key(&uid) {
+            if let Err(e) = self
+                .ensure_footer(&uid, tenant.as_deref(), &connector, metadata)
+                .await
+            {
+                info!("Failed to cache footer of {}: {}", &uid, e);
+            }
+        }
+
+        // Open every chunk up front: once a file is open, a later eviction can't pull it away.
+        let mut body: Box<dyn AsyncRead + Send + Unpin> = Box::new(tokio::io::empty());
+        for index in indices {
+            let key = chunk_key(&uid, index);
+            self.touch(&key);
+            let (chunk_start, chunk_end) = chunk_bounds(index, chunk_size, total);
+            let skip = start.saturating_sub(chunk_start);
+            let take = end.min(chunk_end) + 1 - chunk_start.max(start);
+            let path = self.file_path(&key);
+            if let Some(encryption) = &self.encryption {
+                let reader = match open_sealed(encryption.clone(), path, skip).await {
+                    Ok(reader) => reader,
+                    Err(e) => return read_error(uid, e),
+                };
+                let chunk = StreamReader::new(reader_stream(Box::new(reader.take(take))));
+                body = Box::new(body.chain(chunk));
+                continue;
+            }
+            let mut file = match tokio::fs::File::open(path).await {
+                Ok(file) => file,
+                Err(e) => return read_error(uid, e),
+            };
+            if let Err(e) = file.seek(SeekFrom::Start(skip)).await {
+                return read_error(uid, e);
+            }
+            body = Box::new(body.chain(file.take(take)));
+        }
+        if hit {
+            self.stats.record_hit(end + 1 - start, started);
+        }
+        self.record_access(&uid, tenant.as_deref(), hit, end + 1 - start);
+        GetFileResult::Partial(PartialContent {
+            body,
+            start,
+            end,
+            total,
+        })
+    }
+
+    /// Fetches chunk `index` of `uid` into the cache and records the object size.
+    async fn load_chunk(
+        &mut self,
+        uid: &str,
+        tenant: Option<&str>,
+        index: u64,
+        chunk_size: u64,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        metadata: &MetadataGuard<'_>,
+    ) -> IoResult<()> {
+        let started = Instant::now();
+        let first = index * chunk_size;
+        let object = connector
+            .fetch_range(uid, ByteRange::FromTo(first, first + chunk_size - 1))
+            .await?;
+        if let Some(total) = object.object_size {
+            self.object_sizes.insert(uid.to_string(), total);
+        }
+        self.record_version(uid, object.version.clone());
+        let key = chunk_key(uid, index);
+        let local_file_name = PathBuf::from(disk_name(&key));
+        let physical_size = self
+            .disk_io
+            .write_stream(
+                sealing(self.encryption.as_ref(), object.stream),
+                &self.cache_dir.join(&local_file_name),
+            )
+            .await?;
+        let file_size = unsealed_len(self.encryption.as_ref(), physical_size);
+        debug!(chunk = key.as_str(), size = file_size; "fetched chunk from S3");
+        self.stats.record_fetch(file_size, started);
+        self.insert_entry(metadata, key.clone(), physical_size, tenant)
+            .await;
+        let _ = metadata.set_file_cache_loc(key, local_file_name).await;
+        Ok(())
+    }
+
+    /// Streams `range` of a non-admitted object straight from S3.
+    async fn pass_through_range(
+        uid: String,
+        range: ByteRange,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+    ) -> GetFileResult {
+        debug!(range = range.to_string().as_str(); "not admitted, streaming from S3");
+        record_outcome("pass_through");
+        let object = match connector.fetch_range(&uid, range).await {
+            Ok(object) => object,
+            Err(e) => return fetch_error(uid, e),
+        };
+        match object
+            .object_size
+            .and_then(|total| range.resolve(total).map(|(start, end)| (start, end, total)))
+        {
+            Some((start, end, total)) => GetFileResult::Partial(PartialContent {
+                body: Box::new(StreamReader::new(object.stream)),
+                start,
+                end,
+                total,
+            }),
+            None => GetFileResult::Error(CacheError::RangeNotSatisfiable { key: uid }),
+        }
+    }
+
+    fn is_tracked(&self, file_name: &str) -> bool {
+        self.core.contains(file_name)
+    }
+
+    /// Where the entry `key` is cached.
+    fn file_path(&self, key: &str) -> PathBuf {
+        self.cache_dir.join(disk_name(key))
+    }
+
+    /// Loads a small on-disk object into the memory tier, returning its bytes on success.
+    async fn promote_to_memory(&mut self, uid: &str, path: &Path) -> Option<Bytes> {
+        let compressed = self.compressed.get(uid).copied();
+        let size = match compressed {
+            Some((_, logical_size)) => logical_size,
+            // Slightly over the plaintext size for encrypted files, which errs on the side
+            // of not promoting.
+            None => tokio::fs::metadata(path).await.ok()?.len(),
+        };
+        if !self.memory.accepts(size) {
+            return None;
+        }
+        let data = if compressed.is_none() && self.encryption.is_none() {
+            Bytes::from(tokio::fs::read(path).await.ok()?)
+        } else {
+            let encryption = self.encryption.clone();
+            let codec = compressed.map(|(codec, _)| codec);
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || read_cached_file(encryption, codec, &path))
+                .await
+                .ok()?
+                .ok()?
+        };
+        self.memory.insert(uid, data.clone());
+        Some(data)
+    }
+
+    /// Feeds an access that served `bytes` of `uid` into the usage reports.
+    /// A miss served before the object's size is known passes 0.
+    fn record_access(&mut self, uid: &str, tenant: Option<&str>, hit: bool, bytes: u64) {
+        self.hot_keys.record(uid, bytes);
+        self.prefix_accesses.record(uid);
+        if !self.shadows.is_empty() {
+            let size = self
+                .object_sizes
+                .get(uid)
+                .copied()
+                .or((bytes > 0).then_some(bytes));
+            for shadow in &mut self.shadows {
+                shadow.record(uid, size);
+            }
+        }
+        if let Some(tenant) = tenant {
+            let stats = self.tenant_stats.entry(tenant.to_string()).or_default();
+            if hit {
+                stats.hits += 1;
+            } else {
+                stats.misses += 1;
+            }
+            stats.bytes_served += bytes;
+        }
+    }
+
+    /// On-disk bytes charged to `tenant`.
+    fn tenant_usage(&self, tenant: &str) -> u64 {
+        self.tenant_bytes.get(tenant).copied().unwrap_or(0)
+    }
+
+    /// Charges the entry `name` of `size` bytes to `tenant`.
+    fn own(&mut self, name: &str, tenant: String, size: u64) {
+        *self.tenant_bytes.entry(tenant.clone()).or_default() += size;
+        self.owners.insert(name.to_string(), tenant);
+    }
+
+    /// Stops charging the entry `name` of `size` bytes to its tenant, which is returned.
+    fn disown(&mut self, name: &str, size: u64) -> Option<String> {
+        let tenant = self.owners.remove(name)?;
+        if let Some(bytes) = self.tenant_bytes.get_mut(&tenant) {
+            *bytes = bytes.saturating_sub(size);
+            if *bytes == 0 {
+                self.tenant_bytes.remove(&tenant);
+            }
+        }
+        Some(tenant)
+    }
+
+    /// Evicts the least recently used entries of `tenant` until `new_file_size` more
+    /// bytes fit in its budget, so that a tenant only ever displaces its own data once
+    /// it is full.
+    async fn ensure_tenant_budget(
+        &mut self,
+        metadata: &MetadataGuard<'_>,
+        tenant: &str,
+        new_file_size: u64,
+    ) {
+        let budget = match self.tenants.budget(tenant) {
+            Some(budget) => budget,
+            None => return,
+        };
+        while self.tenant_usage(tenant) + new_file_size > budget {
+            let victim = self
+                .core
+                .find(|name| self.owners.get(name).is_some_and(|owner| owner == tenant));
+            match victim {
+                Some(victim) => {
+                    if self.remove_entry(&victim, metadata).await {
+                        self.stats.evictions += 1;
+                    }
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Uncompressed size of a tracked file.
+    fn cached_size(&self, name: &str) -> u64 {
+        match self.compressed.get(name) {
+            Some((_, logical_size)) => *logical_size,
+            None => self.core.size_of(name).unwrap_or(0),
+        }
+    }
+
+    /// Uncompressed size of everything held by the shard.
+    fn logical_size(&self) -> u64 {
+        self.compressed
+            .iter()
+            .filter_map(|(name, (_, logical_size))| {
+                self.core
+                    .size_of(name)
+                    .map(|physical_size| logical_size - physical_size)
+            })
+            .sum::<u64>()
+            + self.core.current_size()
+    }
+
+    async fn ensure_capacity(&mut self, metadata: &MetadataGuard<'_>, new_file_size: u64) {
+        while self.core.overflows(new_file_size) {
+            // Pinned entries stay; once only they are left the shard runs over budget.
+            let victim = match self.core.eviction_victim() {
+                Some(victim) => victim,
+                None => break,
+            };
+            if self.remove_entry(&victim, metadata).await {
+                self.stats.evictions += 1;
+                if self.eviction.is_some() {
+                    self.stats.emergency_evictions += 1;
+                }
+            }
+        }
+    }
+
+    /// Whether the shard is past its high watermark; never without background eviction.
+    fn above_high_watermark(&self) -> bool {
+        self.eviction.is_some_and(|eviction| {
+            self.core.current_size() > eviction.high_mark(self.core.max_size())
+        })
+    }
+
+    /// Evicts up to `batch` entries while the shard is above its low watermark. Returns
+    /// the bytes freed, and whether the shard is done: down to the watermark, or left
+    /// with pinned entries only.
+    async fn evict_to_low_watermark(
+        &mut self,
+        metadata: &MetadataGuard<'_>,
+        batch: usize,
+    ) -> (u64, bool) {
+        let low_mark = match self.eviction {
+            Some(eviction) => eviction.low_mark(self.core.max_size()),
+            None => return (0, true),
+        };
+        let before = self.core.current_size();
+        for _ in 0..batch {
+            if self.core.current_size() <= low_mark {
+                return (before - self.core.current_size(), true);
+            }
+            let victim = match self.core.eviction_victim() {
+                Some(victim) => victim,
+                None => return (before - self.core.current_size(), true),
+            };
+            if self.remove_entry(&victim, metadata).await {
+                self.stats.evictions += 1;
+            }
+        }
+        let after = self.core.current_size();
+        (before - after, after <= low_mark)
+    }
+
+    /// Evicts entries until `bytes` have been freed or only pinned ones are left. Returns
+    /// the bytes freed.
+    async fn evict_bytes(&mut self, metadata: &MetadataGuard<'_>, bytes: u64) -> u64 {
+        let before = self.core.current_size();
+        while before - self.core.current_size() < bytes {
+            let victim = match self.core.eviction_victim() {
+                Some(victim) => victim,
+                None => break,
+            };
+            if self.remove_entry(&victim, metadata).await {
+                self.stats.evictions += 1;
+            }
+        }
+        before - self.core.current_size()
+    }
+
+    /// Evicts least recently used entries under the same policy as `name` until
+    /// `new_file_size` more bytes fit in that policy's quota.
+    async fn ensure_quota(&mut self, metadata: &MetadataGuard<'_>, name: &str, new_file_size: u64) {
+        while let Some(victim) = self.core.quota_victim(name, new_file_size) {
+            if self.remove_entry(&victim, metadata).await {
+                self.stats.evictions += 1;
+            }
+        }
+    }
+
+    /// Drops the entries whose TTL has run out.
+    async fn expire_all(&mut self, metadata: &MetadataGuard<'_>) {
+        let expired = self
+            .fetched_at
+            .keys()
+            .filter(|name| self.is_expired(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in expired {
+            self.expire_stale(&name, metadata).await;
+        }
+    }
+
+    /// Drops `name` if its TTL has run out, so that the next lookup misses.
+    async fn expire_stale(&mut self, name: &str, metadata: &MetadataGuard<'_>) {
+        if !self.is_expired(name) {
+            return;
+        }
+        if !self.core.contains(name) {
+            self.fetched_at.remove(name);
+        } else if self.remove_entry(name, metadata).await {
+            debug!(entry = name; "expired");
+        }
+    }
+
+    /// Remembers the version S3 reported for `uid`, which starts its revalidation clock.
+    fn record_version(&mut self, uid: &str, version: ObjectVersion) {
+        if !version.is_known() {
+            return;
+        }
+        // A pinned version never changes.
+        if self.core.policies().revalidate_after(uid).is_some() && split_version(uid).1.is_none() {
+            self.validated_at.insert(uid.to_string(), Instant::now());
+        }
+        self.versions.insert(uid.to_string(), version);
+    }
+
+    /// Whether a hit on `uid` must check the object's version in S3 first.
+    fn revalidation_due(&self, uid: &str) -> bool {
+        self.revalidation_overdue(uid).is_some()
+    }
+
+    /// How long ago `uid` became due for revalidation, if it is.
+    fn revalidation_overdue(&self, uid: &str) -> Option<Duration> {
+        let marked = self.stale.lock().unwrap().get(uid).map(Instant::elapsed);
+        let aged = match (
+            self.validated_at.get(uid),
+            self.core.policies().revalidate_after(uid),
+        ) {
+            (Some(validated_at), Some(after)) => validated_at.elapsed().checked_sub(after),
+            _ => None,
+        };
+        marked.max(aged)
+    }
+
+    /// How long ago `uid` ran past its TTL, if it did.
+    fn ttl_overdue(&self, uid: &str) -> Option<Duration> {
+        match (self.fetched_at.get(uid), self.core.policies().ttl(uid)) {
+            (Some(fetched_at), Some(ttl)) => fetched_at.elapsed().checked_sub(ttl),
+            _ => None,
+        }
+    }
+
+    /// Whether a hit on `uid`, due for a refresh for `overdue`, is served as it is while
+    /// the refresh runs in the background.
+    fn serves_stale(&self, uid: &str, overdue: Duration) -> bool {
+        self.core
+            .policies()
+            .stale_while_revalidate(uid)
+            .is_some_and(|window| overdue < window)
+    }
+
+    /// Refreshes `uid` in the background: refetches it if it ran past its TTL, revalidates
+    /// it otherwise and refetches it if it changed. Hits are served the stale copy
+    /// meanwhile; chunked objects are left to fetch their chunks on the next reads.
+    fn refresh_in_background(
+        cache: &Arc<Mutex<Self>>,
+        uid: &str,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        store: &SharedMetadata,
+    ) {
+        let cache = cache.clone();
+        let uid = uid.to_string();
+        let connector = connector.clone();
+        let store = store.clone();
+        // Boxed, since the refetch goes through `get_file`, which starts refreshes.
+        let refresh: Pin<Box<dyn Future<Output = ()> + Send>> = Box::pin(async move {
+            let metadata = store.read().await;
+            let mut shard = cache.lock().await;
+            let whole = shard.core.contains(&uid);
+            if shard.ttl_overdue(&uid).is_some() {
+                shard.invalidate(|key| key == uid, &metadata).await;
+            } else if shard.revalidation_due(&uid) {
+                shard = Self::revalidate(&cache, shard, &uid, &connector, &metadata).await;
+            } else {
+                // Another hit's refresh got here first.
+                return;
+            }
+            let dropped = whole && !shard.core.contains(&uid);
+            drop(shard);
+            if dropped {
+                let options = GetFileOptions::default();
+                let _ = Self::get_file(
+                    cache.clone(),
+                    PathBuf::from(&uid),
+                    connector,
+                    &metadata,
+                    &store,
+                    &options,
+                )
+                .await;
+            }
+        });
+        tokio::spawn(refresh.instrument(info_span!("refresh")));
+    }
+
+    /// Asks S3 for the current version of `uid` and drops the cached object if it changed
+    /// or is gone, so that the lookup that follows fetches it again. The shard lock is
+    /// released during the request. A soft-invalidated object whose version was never
+    /// known is dropped too, and one S3 can't be asked about stays marked.
+    async fn revalidate<'a>(
+        cache: &'a Arc<Mutex<Self>>,
+        mut shard: MutexGuard<'a, Self>,
+        uid: &str,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        metadata: &MetadataGuard<'_>,
+    ) -> MutexGuard<'a, Self> {
+        // Restarting the clock up front keeps concurrent hits from checking too; they are
+        // served the cached copy meanwhile.
+        shard.validated_at.insert(uid.to_string(), Instant::now());
+        let marked = shard.stale.lock().unwrap().remove(uid);
+        let soft = marked.is_some();
+        shard.stats.revalidations += 1;
+        drop(shard);
+        let current = connector
+            .head_object(uid)
+            .instrument(info_span!("revalidate"))
+            .await;
+        let mut shard = cache.lock().await;
+        let stale = match current {
+            Ok(info) => match shard.versions.get(uid) {
+                Some(held) => held.superseded_by(&info.version()),
+                None => soft,
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+            Err(e) => {
+                info!("Failed to revalidate {}: {}", uid, e);
+                if let Some(marked) = marked {
+                    shard.stale.lock().unwrap().insert(uid.to_string(), marked);
+                }
+                false
+            }
+        };
+        if stale {
+            debug!("changed in S3, fetching again");
+            shard.stats.stale_revalidations += 1;
+            shard.invalidate(|key| key == uid, metadata).await;
+        }
+        shard
+    }
+
+    /// Whether a hit on `uid` should fetch it again ahead of its TTL: it is a whole entry
+    /// within `refresh_ahead` of expiring, and not being fetched again already.
+    fn refresh_ahead_due(&self, uid: &str) -> bool {
+        let policies = self.core.policies();
+        let (Some(fetched_at), Some(ttl), Some(ahead)) = (
+            self.fetched_at.get(uid),
+            policies.ttl(uid),
+            policies.refresh_ahead(uid),
+        ) else {
+            return false;
+        };
+        let age = fetched_at.elapsed();
+        age < ttl && age + ahead >= ttl && self.core.contains(uid) && !self.refreshing.contains(uid)
+    }
+
+    /// Fetches `uid` again in the background and swaps the copy in for the cached one,
+    /// which serves hits until then.
+    fn refresh_ahead(
+        cache: &Arc<Mutex<Self>>,
+        uid: &str,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        store: &SharedMetadata,
+    ) {
+        let cache = cache.clone();
+        let uid = uid.to_string();
+        let connector = connector.clone();
+        let store = store.clone();
+        let refresh = async move {
+            if let Err(e) = Self::refetch(&cache, &uid, &connector, &store).await {
+                info!("Failed to refresh {} ahead of its TTL: {}", uid, e);
+            }
+            cache.lock().await.refreshing.remove(&uid);
+        };
+        tokio::spawn(refresh.instrument(info_span!("refresh_ahead")));
+    }
+
+    /// Downloads `uid` next to its cached copy, then replaces the copy with it unless the
+    /// entry was dropped meanwhile.
+    async fn refetch(
+        cache: &Arc<Mutex<Self>>,
+        uid: &str,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+        store: &SharedMetadata,
+    ) -> IoResult<()> {
+        let started = Instant::now();
+        let (staged, disk_io, compression, encryption) = {
+            let shard = cache.lock().await;
+            (
+                shard.cache_dir.join(temp_name(&disk_name(uid), "refresh")),
+                shard.disk_io.clone(),
+                shard.compression.clone(),
+                shard.encryption.clone(),
+            )
+        };
+        let object = connector.fetch_stream(uid).await?;
+        let version = object.version.clone();
+        let prepared = async {
+            let stored_size = disk_io
+                .write_stream(sealing(encryption.as_ref(), object.stream), &staged)
+                .await?;
+            let file_size = unsealed_len(encryption.as_ref(), stored_size);
+            let (physical_size, codec) = compress_cached_file(
+                compression.as_ref(),
+                encryption.as_ref(),
+                uid,
+                &staged,
+                file_size,
+                stored_size,
+            )
+            .await;
+            IoResult::Ok((file_size, physical_size, codec))
+        };
+        let (file_size, physical_size, codec) = match prepared.await {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&staged).await;
+                return Err(e);
+            }
+        };
+
+        let metadata = store.read().await;
+        let mut shard = cache.lock().await;
+        // The entry's charge goes first, so that making room for the new copy can't
+        // evict the entry itself.
+        let Some((_, old_size)) = shard.core.remove(uid) else {
+            // Dropped meanwhile, e.g. invalidated: the old copy must not come back.
+            drop(shard);
+            let _ = tokio::fs::remove_file(&staged).await;
+            return Ok(());
+        };
+        let tenant = shard.disown(uid, old_size);
+        if let Err(e) = tokio::fs::rename(&staged, shard.file_path(uid)).await {
+            shard.core.insert(uid.to_string(), old_size);
+            if let Some(tenant) = tenant {
+                shard.own(uid, tenant, old_size);
+            }
+            drop(shard);
+            let _ = tokio::fs::remove_file(&staged).await;
+            return Err(e);
+        }
+        match codec {
+            Some(codec) => shard.compressed.insert(uid.to_string(), (codec, file_size)),
+            None => shard.compressed.remove(uid),
+        };
+        shard.memory.remove(uid);
+        shard.stats.record_fetch(file_size, started);
+        shard.stats.refreshes_ahead += 1;
+        shard
+            .insert_entry(&metadata, uid.to_string(), physical_size, tenant.as_deref())
+            .await;
+        shard.record_version(uid, version);
+        debug!(size = file_size; "refreshed ahead of its TTL");
+        Ok(())
+    }
+
+    /// Whether `name` ran past its TTL and any stale-while-revalidate window after it.
+    fn is_expired(&self, name: &str) -> bool {
+        let window = self.core.policies().stale_while_revalidate(name);
+        match self.ttl_overdue(name) {
+            Some(overdue) => window.is_none_or(|window| overdue >= window),
+            None => false,
+        }
+    }
+
+    /// Makes room for and records a file just written to the cache directory.
+    async fn insert_entry(
+        &mut self,
+        metadata: &MetadataGuard<'_>,
+        name: String,
+        size: u64,
+        tenant: Option<&str>,
+    ) {
+        self.expire_all(metadata).await;
+        if let Some(tenant) = tenant {
+            self.ensure_tenant_budget(metadata, tenant, size).await;
+        }
+        self.ensure_quota(metadata, &name, size).await;
+        self.ensure_capacity(metadata, size).await;
+        // A copy ingested while another was being written replaces its charge.
+        if let Some(replaced) = self.core.size_of(&name) {
+            self.disown(&name, replaced);
+        }
+        if let Some(tenant) = tenant {
+            self.own(&name, tenant.to_string(), size);
+        }
+        if self.core.policies().ttl(&name).is_some() {
+            self.fetched_at.insert(name.clone(), Instant::now());
+        }
+        for shadow in &mut self.shadows {
+            shadow.sized(&name, size);
+        }
+        // A copy ingested while another was being written replaces it on disk.
+        if is_batch() {
+            self.core.insert_cold(name, size);
+        } else {
+            self.core.insert(name, size);
+        }
+    }
+
+    /// Marks `name` as used by the current request; batch reads leave it as it was, so
+    /// that a backfill doesn't keep its data ahead of interactive reads.
+    fn touch(&self, name: &str) {
+        if !is_batch() {
+            self.core.touch(name);
+        }
+    }
+
+    /// Deletes the entry `name` from disk, the memory tier and Redis. Returns whether the
+    /// file could be deleted.
+    async fn remove_entry(&mut self, name: &str, metadata: &MetadataGuard<'_>) -> bool {
+        match self.delete_entry(name).await {
+            Some(evicted_file_name) => {
+                let _ = metadata.remove_file(evicted_file_name.clone()).await;
+                info!("Evicted file: {}", evicted_file_name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Like `remove_entry`, but leaves the file's location in the metadata store alone;
+    /// returns the name of the deleted file.
+    async fn delete_entry(&mut self, name: &str) -> Option<String> {
+        let (evicted_file_name, evicted_file_size) = self.core.remove(name)?;
+        self.stale.lock().unwrap().remove(&evicted_file_name);
+        self.fetched_at.remove(&evicted_file_name);
+        self.disown(&evicted_file_name, evicted_file_size);
+        self.versions.remove(&evicted_file_name);
+        self.validated_at.remove(&evicted_file_name);
+        let evicted_path = self.file_path(&evicted_file_name);
+        if tokio::fs::remove_file(&evicted_path).await.is_ok() {
+            self.memory.remove(&evicted_file_name);
+            self.compressed.remove(&evicted_file_name);
+            Some(evicted_file_name)
+        } else {
+            log::warn!(path = evicted_path.display().to_string().as_str(); "failed to delete evicted file");
+            // The file still takes up the disk.
+            self.core.charge(evicted_file_size);
+            None
+        }
+    }
+    /// Drops the entry `name` from the shard's bookkeeping alone, for a file that is gone
+    /// already. Returns its name.
+    fn forget_entry(&mut self, name: &str) -> Option<String> {
+        let (name, size) = self.core.remove(name)?;
+        self.stale.lock().unwrap().remove(&name);
+        self.fetched_at.remove(&name);
+        self.disown(&name, size);
+        self.versions.remove(&name);
+        self.validated_at.remove(&name);
+        self.memory.remove(&name);
+        self.compressed.remove(&name);
+        Some(name)
+    }
+
+    /// Checks every entry against its file and its location in the metadata store.
+    /// Entries whose file is gone are dropped, those whose file has another size than
+    /// recorded are deleted, and missing locations are recorded again.
+    async fn scrub(&mut self, metadata: &MetadataGuard<'_>) -> ScrubReport {
+        let mut report = ScrubReport::default();
+        let mut intact = Vec::new();
+        for (name, size) in self.core.entries() {
+            match tokio::fs::metadata(self.file_path(&name)).await {
+                Ok(file) if file.len() == size => intact.push(name),
+                Ok(file) => {
+                    warn!(
+                        "Cached file {} has {} bytes instead of {}, dropping it",
+                        name,
+                        file.len(),
+                        size
+                    );
+                    if !self.remove_entry(&name, metadata).await {
+                        // The entry is gone either way; don't leave its bytes charged.
+                        self.core.refund(size);
+                    }
+                    report.corrupt_files += 1;
+                }
+                Err(e) => {
+                    warn!("Cached file {} is unreadable ({}), dropping it", name, e);
+                    self.forget_entry(&name);
+                    let _ = metadata.remove_file(name).await;
+                    report.missing_files += 1;
+                }
+            }
+        }
+        let locations = metadata.get_files(intact.clone()).await;
+        let unrecorded: Vec<(String, PathBuf)> = intact
+            .into_iter()
+            .zip(locations)
+            .filter(|(_, loc)| loc.is_none())
+            .map(|(name, _)| {
+                let loc = PathBuf::from(disk_name(&name));
+                (name, loc)
+            })
+            .collect();
+        if !unrecorded.is_empty() {
+            report.locations_restored += unrecorded.len() as u64;
+            let _ = metadata.set_file_cache_locs(unrecorded).await;
+        }
+        report
+    }
+
+    /// Checks the shard's bookkeeping against itself and the cache directory: see
+    /// `CacheCore::debug_validate`, and every entry's file must exist.
+    async fn debug_validate(&self) -> Result<(), String> {
+        self.core.debug_validate()?;
+        let mut tenant_bytes = HashMap::new();
+        for (name, tenant) in &self.owners {
+            *tenant_bytes.entry(tenant.clone()).or_default() +=
+                self.core.size_of(name).unwrap_or(0);
+        }
+        tenant_bytes.retain(|_, bytes: &mut u64| *bytes > 0);
+        if tenant_bytes != self.tenant_bytes {
+            return Err(format!(
+                "tenant totals {:?} don't match the entries, {:?}",
+                self.tenant_bytes, tenant_bytes
+            ));
+        }
+        for (name, _) in self.core.entries() {
+            if tokio::fs::metadata(self.file_path(&name)).await.is_err() {
+                return Err(format!("the file of {} is missing", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the shard budget, evicting right away if the shard no longer fits.
+    /// Returns the number of bytes evicted.
+    async fn set_max_size(&mut self, metadata: &MetadataGuard<'_>, max_size: u64) -> u64 {
+        let before = self.core.current_size();
+        self.core.set_max_size(max_size);
+        self.ensure_capacity(metadata, 0).await;
+        before - self.core.current_size()
+    }
+
+    /// Drops every object whose key satisfies `matches`, with its chunk and footer
+    /// entries; returns the on-disk bytes freed.
+    async fn invalidate(
+        &mut self,
+        matches: impl Fn(&str) -> bool,
+        metadata: &MetadataGuard<'_>,
+    ) -> u64 {
+        self.object_sizes.retain(|uid, _| !matches(uid));
+        self.versions.retain(|uid, _| !matches(uid));
+        self.validated_at.retain(|uid, _| !matches(uid));
+        self.stale.lock().unwrap().retain(|uid, _| !matches(uid));
+        let mut freed = 0;
+        while let Some(name) = self.core.find(|name| matches(object_key(name))) {
+            let size = self.core.size_of(&name).unwrap_or(0);
+            if self.remove_entry(&name, metadata).await {
+                freed += size;
+            }
+        }
+        freed
+    }
+
+    /// Drops every entry of the shard. Their locations are removed from the metadata
+    /// store before the files are deleted, so that peers stop redirecting to them first.
+    async fn empty(&mut self, metadata: &MetadataGuard<'_>) -> ClearReport {
+        self.memory.clear();
+        self.object_sizes.clear();
+        self.versions.clear();
+        self.validated_at.clear();
+        self.stale.lock().unwrap().clear();
+        self.compressed.clear();
+        self.hot_keys.clear();
+        self.unlocked_hits.lock().unwrap().clear();
+        self.prefix_accesses.clear();
+        self.shadows.iter_mut().for_each(ShadowCache::clear);
+        self.fetched_at.clear();
+        self.owners.clear();
+        self.tenant_bytes.clear();
+        self.tenant_stats.clear();
+        let entries = self.core.clear();
+        let names = entries.iter().map(|(name, _)| name.clone()).collect();
+        let _ = metadata.remove_files(names).await;
+        let mut report = ClearReport::default();
+        for (name, size) in entries {
+            let path = self.file_path(&name);
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {
+                    report.files += 1;
+                    report.bytes += size;
+                }
+                // Nothing left to free.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    warn!("Failed to delete {}: {}", path.display(), e);
+                    // The file still takes up the disk.
+                    self.core.charge(size);
+                    report.undeleted_files += 1;
+                }
+            }
+        }
+        report
+    }
+}
+
+// ConcurrentDiskCache Implementation -----------------------------------------
+
+/// Share of `total` bytes given to shard `index`; the remainder of the division goes to
+/// the first shards so that the budgets add up to `total`.
+/// The shadow caches next to the real cache's hit ratio since startup.
+fn shadow_table(shadows: &[ShadowStats], hit_ratio: f64) -> String {
+    let mut table = format!(
+        "\n{:<15} | {:<8} | {:<14} | {:<12} | {:<12} | {:<12} | {}\n",
+        "Shadow", "Policy", "Budget", "Hits", "Misses", "Hit %", "vs Cache"
+    );
+    table.push_str(&"-".repeat(105));
+    table.push('\n');
+    for shadow in shadows {
+        table.push_str(&format!(
+            "{:<15} | {:<8} | {:<14} | {:<12} | {:<12} | {:<12.2} | {:+.2}\n",
+            shadow.name,
+            shadow.policy,
+            shadow.max_size,
+            shadow.hits,
+            shadow.misses,
+            shadow.hit_ratio,
+            shadow.hit_ratio - hit_ratio
+        ));
+    }
+    table
+}
+
+pub fn shard_budget(total: u64, shard_count: u64, index: u64) -> u64 {
+    total / shard_count + u64::from(index < total % shard_count)
+}
+
+impl ConcurrentDiskCache {
+    pub fn new(
+        cache_dir: PathBuf,
+        max_size: u64,
+        bucket_size: u64,
+        metadata: Box<dyn MetadataStore>,
+        options: CacheOptions,
+    ) -> Self {
+        let dirs = match options.cache_dirs.is_empty() {
+            true => vec![(cache_dir, max_size)],
+            false => options
+                .cache_dirs
+                .iter()
+                .map(|dir| (PathBuf::from(&dir.path), dir.max_size))
+                .collect(),
+        };
+        let disks: Vec<Disk> = dirs
+            .into_iter()
+            .map(|(dir, capacity)| {
+                let _ = std::fs::create_dir_all(&dir);
+                Disk {
+                    capacity,
+                    disk_io: options
+                        .disk_io
+                        .on_disk(DiskHealth::new(dir).reserving(options.free_space_reserve)),
+                }
+            })
+            .collect();
+        let capacities: Vec<u64> = disks.iter().map(|disk| disk.capacity).collect();
+        let metadata = Arc::new(RwLock::new(metadata));
+        let tenants = options.tenants.clone();
+        let ring = options
+            .ring_vnodes
+            .map(|vnodes| RingPlacement::new(vnodes).syncing(options.sync_ring));
+        let runtimes = options.shard_runtimes.map(|config| {
+            ShardRuntimes::start(bucket_size as usize, config)
+                .unwrap_or_else(|e| panic!("Failed to start the shard runtimes: {}", e))
+        });
+        let unlocked_hits = options.memory_tier_size == 0
+            && options.compression.is_none()
+            && options.encryption.is_none()
+            && options.hedge_after.is_none()
+            && options.chunk_size.is_none();
+        let clocks = AtomicBool::new(options.policies.has_clocks());
+        let shard_options = CacheOptions {
+            memory_tier_size: options.memory_tier_size / bucket_size,
+            policies: options.policies.per_shard(bucket_size),
+            tenants: options.tenants.per_shard(bucket_size),
+            ..options
+        };
+        let (shards, unlocked) = stripe_budgets(&capacities, bucket_size, max_size)
+            .into_iter()
+            .enumerate()
+            .map(|(index, shard_max_size)| {
+                let disk = &disks[index % disks.len()];
+                let options = CacheOptions {
+                    disk_io: disk.disk_io.clone(),
+                    shadow_caches: shard_options
+                        .shadow_caches
+                        .iter()
+                        .map(|shadow| shadow.per_shard(shard_max_size, max_size))
+                        .collect(),
+                    ..shard_options.clone()
+                };
+                let shard =
+                    DiskCache::new(disk.health().path().to_path_buf(), shard_max_size, &options);
+                let unlocked = {
+                    let shard = shard.try_lock().expect("a new shard is not locked");
+                    UnlockedShard {
+                        index: shard.core.index().clone(),
+                        cache_dir: shard.cache_dir.clone(),
+                        disk_io: shard.disk_io.clone(),
+                        hits: shard.unlocked_hits.clone(),
+                        stale: shard.stale.clone(),
+                    }
+                };
+                (shard, unlocked)
+            })
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+
+        Self {
+            shards,
+            metadata,
+            redirect_scheme: if options.redirect_https {
+                "https"
+            } else {
+                "http"
+            },
+            tenants,
+            ring,
+            ownership_changed: Notify::new(),
+            addresses: std::sync::RwLock::default(),
+            disks,
+            scrubbed: std::sync::Mutex::default(),
+            metadata_timeout: options.timeouts.metadata(),
+            listings: ListingCache::new(options.listing_ttl),
+            share_listings: options.share_listings && options.listing_ttl.is_some(),
+            runtimes,
+            unlocked,
+            unlocked_hits,
+            clocks,
+        }
+    }
+    /// Builds the slot-to-node mapping on first use.
+    async fn ensure_mapping_initialized(&self) -> Result<(), StoreError> {
+        // Use read lock for read operations
+        let metadata = self.metadata.read().await; // Acquiring a read lock
+        if !metadata.is_initialized() {
+            drop(metadata); // Drop read lock before acquiring write lock
+
+            let mut metadata_write = self.metadata.write().await; // Acquiring a write lock
+            metadata_write.initialize().await?;
+            drop(metadata_write);
+            debug!("Initialization complete, dropped metadata write lock");
+            self.load_addresses(&self.metadata.read().await);
+        } else {
+            drop(metadata);
+        }
+        if self.ring.as_ref().is_some_and(|ring| !ring.is_loaded()) {
+            if let Err(e) = self.load_ring(&self.metadata.read().await).await {
+                warn!("Loading the placement ring failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the metadata store is unreachable and the node is serving on its own.
+    pub async fn is_degraded(&self) -> bool {
+        self.metadata.read().await.is_degraded()
+    }
+
+    /// Builds the slot-to-node mapping if it isn't yet, then checks that the metadata
+    /// store answers.
+    pub async fn check_metadata(&self) -> Result<(), StoreError> {
+        self.ensure_mapping_initialized().await?;
+        self.metadata.read().await.ping().await
+    }
+
+    /// Writes and reads back a probe file on every cache directory; a disk that was
+    /// down and passes is brought back. Returns each directory with whether it passed.
+    pub async fn check_disks(&self) -> Vec<(PathBuf, bool)> {
+        let mut results = Vec::with_capacity(self.disks.len());
+        for disk in &self.disks {
+            let health = disk.health();
+            results.push((health.path().to_path_buf(), health.probe().await));
+        }
+        results
+    }
+
+    /// Whether every shard, and the metadata store's lock, can be taken within `timeout`;
+    /// one that can't is held by something that is stuck.
+    pub async fn is_responsive(&self, timeout: Duration) -> bool {
+        let locks = async {
+            drop(self.metadata.read().await);
+            for shard in &self.shards {
+                drop(shard.lock().await);
+            }
+        };
+        tokio::time::timeout(timeout, locks).await.is_ok()
+    }
+
+    /// Like `ensure_mapping_initialized`, but a node that cannot reach its metadata store
+    /// still serves: without a mapping it owns every key, as in local-only mode.
+    async fn ensure_mapping_initialized_or_serve_locally(&self) {
+        if let Err(e) = self.ensure_mapping_initialized().await {
+            debug!(
+                "Error updating slot-to-node mapping: {}; serving from local state only",
+                e
+            );
+        }
+    }
+
+    /// Re-reads slot ownership from the metadata store, e.g. after the cluster was resized.
+    /// Requests wait for the write lock while the store is queried; a mapping that was
+    /// never built is left to be built on first use. Returns how many slots changed owner.
+    pub async fn refresh_mapping(&self) -> Result<usize, StoreError> {
+        if !self.metadata.read().await.is_initialized() {
+            return Ok(0);
+        }
+        let moved = self.metadata.write().await.refresh().await?;
+        if moved > 0 {
+            info!(moved_slots = moved; "Slot ownership changed");
+            self.ownership_changed.notify_one();
+        }
+        self.load_addresses(&self.metadata.read().await);
+        if let Err(e) = self.load_ring(&self.metadata.read().await).await {
+            warn!("Reloading the placement ring failed: {}", e);
+        }
+        Ok(moved)
+    }
+
+    /// Takes the web server addresses the members advertised.
+    fn load_addresses(&self, metadata: &MetadataGuard<'_>) {
+        *self.addresses.write().unwrap() = metadata
+            .members()
+            .into_iter()
+            .filter_map(|member| Some(((member.endpoint, member.port), member.address?)))
+            .collect();
+    }
+
+    /// Reads the ring from the metadata store into this node's copy. A store without a
+    /// ring gets one with every current member on it, as does every load when the ring
+    /// follows the members. Does nothing under slot placement.
+    async fn load_ring(&self, metadata: &MetadataGuard<'_>) -> Result<(), RingError> {
+        let placement = match &self.ring {
+            Some(placement) => placement,
+            None => return Ok(()),
+        };
+        let members = metadata.members();
+        let ring = match metadata.load_ring().await? {
+            // A store that lists no members, e.g. one not read yet, would empty the ring.
+            Some(encoded) if placement.syncs && !members.is_empty() => {
+                let stored = HashRing::decode(&encoded)?;
+                let mut ring = stored.clone();
+                ring.apply(RingOp::Sync, &members, placement.vnodes)?;
+                if ring != stored {
+                    metadata.save_ring(&ring.encode()).await?;
+                    info!("Synced the placement ring to {:?}", ring.nodes());
+                }
+                ring
+            }
+            Some(encoded) => HashRing::decode(&encoded)?,
+            None => {
+                let mut ring = HashRing::default();
+                ring.apply(RingOp::Sync, &members, placement.vnodes)?;
+                metadata.save_ring(&ring.encode()).await?;
+                info!(
+                    "Created the placement ring with {} nodes",
+                    ring.nodes().len()
+                );
+                ring
+            }
+        };
+        let moved = placement.set(ring, &members);
+        if moved > 0.0 {
+            info!(moved_share = moved; "Ring ownership changed");
+            self.ownership_changed.notify_one();
+        }
+        Ok(())
+    }
+
+    /// This node's copy of the placement ring.
+    pub async fn ring(&self) -> Result<HashRing, RingError> {
+        let placement = self.ring.as_ref().ok_or(RingError::Disabled)?;
+        self.ensure_mapping_initialized().await?;
+        Ok(placement.ring().unwrap_or_default())
+    }
+
+    /// Applies `op` to the ring in the metadata store and takes the result as this node's
+    /// copy; the other nodes follow on their next mapping refresh. Returns the share of
+    /// the key space that changed owner.
+    pub async fn rebalance_ring(&self, op: RingOp) -> Result<f64, RingError> {
+        let placement = self.ring.as_ref().ok_or(RingError::Disabled)?;
+        self.ensure_mapping_initialized().await?;
+        // Held throughout, so that a concurrent refresh cannot interleave.
+        let metadata = self.metadata.write().await;
+        let mut ring = match metadata.load_ring().await? {
+            Some(encoded) => HashRing::decode(&encoded)?,
+            None => HashRing::default(),
+        };
+        let before = ring.clone();
+        let members = metadata.members();
+        ring.apply(op, &members, placement.vnodes)?;
+        metadata.save_ring(&ring.encode()).await?;
+        info!("Placement ring is now {:?}", ring.nodes());
+        placement.set(ring.clone(), &members);
+        let moved = moved_share(&before, &ring);
+        if moved > 0.0 {
+            self.ownership_changed.notify_one();
+        }
+        Ok(moved)
+    }
+
+    /// The `(endpoint, port)` of the node owning `uid`, or `None` if this node owns it.
+    async fn owner_of(&self, uid: &str, metadata: &MetadataGuard<'_>) -> Option<(String, u16)> {
+        match &self.ring {
+            // Without the metadata store the ring may be stale, so serve locally as well.
+            Some(_) if metadata.is_degraded() => None,
+            Some(placement) => placement.lookup(uid),
+            None => metadata.location_lookup(uid.to_string()).await,
+        }
+    }
+
+    /// Builds a redirect to `path` on the node owning `uid`, or `None` if this node owns it.
+    async fn redirect_for(
+        &self,
+        uid: &str,
+        path: &str,
+        metadata: &MetadataGuard<'_>,
+    ) -> Option<GetFileResult> {
+        let (x, p) = self.owner_of(uid, metadata).await?;
+        let Some(url) = self.peer_url(&x, p, path) else {
+            return Some(GetFileResult::Error(CacheError::Internal {
+                key: uid.to_string(),
+                reason: format!("the owner node {}:{} has no web server address", x, p),
+            }));
+        };
+        debug!(location = url.as_str(); "redirecting to owner node");
+        record_outcome("redirect");
+        Some(GetFileResult::Redirect(Box::new(Redirect::to(
+            url.to_string(),
+        ))))
+    }
+
+    /// `path`, which may carry a query, on the web server of the node whose Redis listens
+    /// on `endpoint:port`: at the address the node advertised, or else at its Redis port
+    /// plus `PORT_OFFSET_TO_WEB_SERVER`. `None` if neither gives a valid URL, e.g. for a
+    /// Redis port too high to add the offset to.
+    pub fn peer_url(&self, endpoint: &str, port: u16, path: &str) -> Option<Url> {
+        let advertised = self
+            .addresses
+            .read()
+            .unwrap()
+            .get(&(endpoint.to_string(), port))
+            .and_then(|address| {
+                Url::parse(&format!("{}://{}", self.redirect_scheme, address)).ok()
+            });
+        let mut url = match advertised {
+            Some(url) => url,
+            None => self.offset_url(endpoint, port)?,
+        };
+        match path.split_once('?') {
+            Some((path, query)) => {
+                url.set_path(path);
+                url.set_query(Some(query));
+            }
+            None => url.set_path(path),
+        }
+        Some(url)
+    }
+
+    /// The web server of a node that advertised no address, at its Redis port plus
+    /// `PORT_OFFSET_TO_WEB_SERVER`.
+    fn offset_url(&self, endpoint: &str, port: u16) -> Option<Url> {
+        let Some(web_port) = port.checked_add(PORT_OFFSET_TO_WEB_SERVER) else {
+            warn!(
+                "Node {}:{} advertised no address, and its port is too high for the offset",
+                endpoint, port
+            );
+            return None;
+        };
+        let mut url = Url::parse(&format!("{}://localhost", self.redirect_scheme)).ok()?;
+        // An IPv4 address a dual-stack node reported in IPv6 form is written as IPv4.
+        let host = match endpoint
+            .parse::<IpAddr>()
+            .map(|address| address.to_canonical())
+        {
+            Ok(address) if address.is_loopback() => url.set_host(Some("localhost")).ok(),
+            Ok(address) => url.set_ip_host(address).ok(),
+            // A hostname, e.g. from a store that registered one.
+            Err(_) => url.set_host(Some(endpoint)).ok(),
+        };
+        if host.is_none() {
+            warn!("Node endpoint '{}' is not a valid host", endpoint);
+            return None;
+        }
+        url.set_port(Some(web_port)).ok()?;
+        Some(url)
+    }
+
+    fn shard_index(&self, uid: &str) -> usize {
+        let shard_index = shard_index(uid, self.shards.len());
+        record_shard(shard_index);
+        shard_index
+    }
+
+    fn shard_for(&self, uid: &str) -> &Arc<Mutex<DiskCache>> {
+        &self.shards[self.shard_index(uid)]
+    }
+
+    /// Runs `task` on the thread of the shard owning `uid` with thread-per-shard runtimes,
+    /// and where it is called otherwise.
+    pub async fn on_shard_of<F>(&self, uid: &str, task: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        // Boxed, so that the two arms don't each hold the task inline.
+        let task = Box::pin(task);
+        match &self.runtimes {
+            Some(runtimes) => {
+                runtimes
+                    .run(shard_index(uid, self.shards.len()), task)
+                    .await
+            }
+            None => task.await,
+        }
+    }
+
+    /// Reads the owner of `uid` from the metadata store within the metadata timeout,
+    /// returning the held store with a redirect to `path` on the owner, if it isn't this
+    /// node.
+    async fn route(
+        &self,
+        uid: &str,
+        path: &str,
+    ) -> Result<(MetadataGuard<'_>, Option<GetFileResult>), GetFileResult> {
+        let lookup = async {
+            self.ensure_mapping_initialized_or_serve_locally().await;
+            let metadata = self.metadata.read().await;
+            let redirect = self.redirect_for(uid, path, &metadata).await;
+            (metadata, redirect)
+        };
+        bounded(Stage::Metadata, self.metadata_timeout, lookup)
+            .instrument(info_span!("location_lookup"))
+            .await
+            .map_err(|e| fetch_error(uid.to_string(), e))
+    }
+
+    /// `path` on the node owning `uid`, or `None` if this node owns it or the owner has no
+    /// address to reach it at.
+    pub async fn owner_url(&self, uid: &str, path: &str) -> Option<Url> {
+        self.ensure_mapping_initialized_or_serve_locally().await;
+        let metadata = self.metadata.read().await;
+        let (endpoint, port) = self.owner_of(uid, &metadata).await?;
+        self.peer_url(&endpoint, port, path)
+    }
+
+    pub async fn get_file(
+        &self,
+        uid: PathBuf,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+        options: GetFileOptions,
+    ) -> GetFileResult {
+        let priority = options.priority;
+        let tenant = options.tenant.clone();
+        let scoped = Box::pin(self.get_file_scoped(uid, connector, options));
+        with_priority(priority, with_tenant(tenant, scoped)).await
+    }
+
+    async fn get_file_scoped(
+        &self,
+        uid: PathBuf,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+        options: GetFileOptions,
+    ) -> GetFileResult {
+        let uid = match key_of(uid) {
+            Ok(key) => key,
+            Err(e) => return GetFileResult::Error(e),
+        };
+        let path = options
+            .redirect_path
+            .clone()
+            .unwrap_or_else(|| format!("s3/{}", &uid));
+        let metadata = match self.route(&uid, &path).await {
+            Ok((_, Some(redirect))) | Err(redirect) => return redirect,
+            Ok((metadata, None)) => metadata,
+        };
+        let shard_index = self.shard_index(&uid);
+        if let Some(hit) = self
+            .unlocked_hit(shard_index, &uid, &metadata, &options)
+            .await
+        {
+            return hit;
+        }
+        let result = DiskCache::get_file(
+            self.shards[shard_index].clone(),
+            uid.into(),
+            connector.clone(),
+            &metadata,
+            &self.metadata,
+            &options,
+        )
+        .instrument(info_span!("shard", index = shard_index))
+        .await;
+        drop(metadata);
+        debug!("{}", self.get_stats().await);
+        result
+    }
 
-impl DiskCache {
-    pub fn new(cache_dir: PathBuf, max_size: u64) -> Arc<Mutex<Self>> {
-        let current_size = 0; // Start with an empty cache for simplicity
-        Arc::new(Mutex::new(Self {
-            cache_dir,
-            max_size,
-            current_size,
-            access_order: VecDeque::new(),
-        }))
+    /// Serves a disk hit on `uid` without the shard lock, so that hits don't queue behind
+    /// misses evicting and inserting under it. `None` leaves the request to the shard:
+    /// `uid` isn't cached, or the hit needs the shard's state.
+    async fn unlocked_hit(
+        &self,
+        shard_index: usize,
+        uid: &str,
+        metadata: &MetadataGuard<'_>,
+        options: &GetFileOptions,
+    ) -> Option<GetFileResult> {
+        let started = Instant::now();
+        let shard = &self.unlocked[shard_index];
+        if !self.unlocked_hits
+            || self.clocks.load(Ordering::Acquire)
+            || !options.preconditions.is_empty()
+            || !shard.index.contains(uid)
+        {
+            return None;
+        }
+        // Revalidating takes the shard.
+        if shard.stale.lock().unwrap().contains_key(uid) {
+            return None;
+        }
+        let file_name = metadata
+            .get_file(uid.to_string())
+            .instrument(info_span!("redis_lookup"))
+            .await?;
+        // A file evicted since the lookup is left to the shard to miss on.
+        let file = CachedFile::open_with(shard.cache_dir.join(file_name), &shard.disk_io)
+            .await
+            .ok()?;
+        debug!("found in cache");
+        record_outcome("disk_hit");
+        if !is_batch() {
+            shard.index.touch(uid);
+        }
+        let backlog = {
+            let mut hits = shard.hits.lock().unwrap();
+            hits.push(UnlockedHit {
+                uid: uid.to_string(),
+                tenant: options.tenant.clone(),
+                bytes: shard.index.size_of(uid).unwrap_or(0),
+                latency: started.elapsed(),
+            });
+            hits.len()
+        };
+        if backlog > UNLOCKED_HITS_BACKLOG {
+            if let Ok(mut shard) = self.shards[shard_index].try_lock() {
+                shard.record_unlocked_hits();
+            }
+        }
+        Some(GetFileResult::Hit(file))
     }
 
-    pub async fn get_file(
-        cache: Arc<Mutex<Self>>,
+    pub async fn get_parquet_metadata(
+        &self,
         uid: PathBuf,
         connector: Arc<dyn StorageConnector + Send + Sync>,
-        redis_read: &RwLockReadGuard<'_, RedisServer>,
     ) -> GetFileResult {
-        let uid_str = uid.into_os_string().into_string().unwrap();
-        let mut cache = cache.lock().await;
-        let redirect = redis_read.location_lookup(uid_str.clone()).await;
-        if let Some((x, p)) = redirect {
-            let mut url = Url::parse("http://localhost").unwrap();
-            let address: IpAddr = x.parse().unwrap();
-            if address.is_loopback() {
-                url.set_host(Some("localhost")).unwrap();
-            } else {
-                url.set_ip_host(address).unwrap();
+        let uid = match key_of(uid) {
+            Ok(key) => key,
+            Err(e) => return GetFileResult::Error(e),
+        };
+        let path = format!("parquet/{}/metadata", &uid);
+        let metadata = match self.route(&uid, &path).await {
+            Ok((_, Some(redirect))) | Err(redirect) => return redirect,
+            Ok((metadata, None)) => metadata,
+        };
+        let shard = self.shard_for(&uid);
+        DiskCache::get_parquet_metadata(shard.clone(), uid.into(), connector, &metadata).await
+    }
+
+    /// Copies the state of every shard and the cluster membership, with up to `hot_keys`
+    /// keys per shard.
+    pub async fn snapshot(&self, hot_keys: usize) -> CacheSnapshot {
+        let taken_at = chrono::Utc::now();
+        let mut shards = Vec::new();
+        for (index, shard) in self.shards.iter().enumerate() {
+            if let Ok(mut shard_guard) =
+                tokio::time::timeout(std::time::Duration::from_secs(5), shard.lock()).await
+            {
+                shard_guard.record_unlocked_hits();
+                shards.push(ShardSnapshot {
+                    index,
+                    current_size: shard_guard.core.current_size(),
+                    logical_size: shard_guard.logical_size(),
+                    max_size: shard_guard.core.max_size(),
+                    files: shard_guard.core.len(),
+                    memory_size: shard_guard.memory.current_size(),
+                    memory_files: shard_guard.memory.len(),
+                    stats: shard_guard.stats.clone(),
+                    hot_keys: shard_guard.hot_keys.by_accesses(hot_keys),
+                    shadows: shard_guard.shadows.iter().map(ShadowCache::stats).collect(),
+                });
             }
-            url.set_port(Some(p + PORT_OFFSET_TO_WEB_SERVER)).unwrap();
-            url.set_path(&format!("s3/{}", &uid_str)[..]);
-            debug!("tell client to redirect to {}", url.to_string());
-            return GetFileResult::Redirect(Box::new(Redirect::to(url.to_string())));
         }
-        let file_name = if let Some(redis_res) = redis_read.get_file(uid_str.clone()).await {
-            debug!("{} found in cache", &uid_str);
-            redis_res
-        } else {
-            match cache.get_s3_file_to_cache(&uid_str, connector).await {
-                Ok((local_file_name, file_size)) => {
-                    debug!("{} fetched from S3", &uid_str);
-                    debug!("File size: {} bytes", file_size);
-                    cache.ensure_capacity(&redis_read, file_size).await;
-                    cache.current_size += file_size;
-                    cache
-                        .access_order
-                        .push_back((uid_str.clone(), file_size.clone()));
-                    let _ = redis_read
-                        .set_file_cache_loc(uid_str.clone(), local_file_name.clone())
-                        .await;
-                    local_file_name
+        // The slot mapping is loaded by the first request; until then membership is empty.
+        let members = self.metadata.read().await.members();
+        let disks = self.disk_snapshots().await;
+        CacheSnapshot {
+            taken_at,
+            shards,
+            members,
+            disks,
+            scrubbed: *self.scrubbed.lock().unwrap(),
+        }
+    }
+
+    /// Budget, usage and health of every cache directory.
+    pub async fn disk_snapshots(&self) -> Vec<DiskSnapshot> {
+        let mut disks: Vec<DiskSnapshot> = self
+            .disks
+            .iter()
+            .map(|disk| DiskSnapshot {
+                path: disk.health().path().to_path_buf(),
+                max_size: 0,
+                current_size: 0,
+                status: disk.health().status(),
+                errors: disk.health().errors(),
+            })
+            .collect();
+        for (index, shard) in self.shards.iter().enumerate() {
+            let shard = shard.lock().await;
+            let disk = &mut disks[index % self.disks.len()];
+            disk.max_size += shard.core.max_size();
+            disk.current_size += shard.core.current_size();
+        }
+        disks
+    }
+
+    /// Brings every shard past its high watermark down to its low watermark, a batch of
+    /// entries per shard lock. Returns the bytes freed.
+    pub async fn evict_in_background(&self) -> u64 {
+        let mut freed = 0;
+        for shard in &self.shards {
+            if !shard.lock().await.above_high_watermark() {
+                continue;
+            }
+            loop {
+                let metadata = self.metadata.read().await;
+                let (bytes, done) = shard
+                    .lock()
+                    .await
+                    .evict_to_low_watermark(&metadata, EVICTION_BATCH)
+                    .await;
+                freed += bytes;
+                if done {
+                    break;
                 }
+                drop(metadata);
+                tokio::task::yield_now().await;
+            }
+        }
+        freed
+    }
+
+    /// Checks the file system of every cache directory against the reserve. A disk short
+    /// of it takes no new entries, and its shards evict the missing bytes between them;
+    /// one that has the reserve free again is probed and brought back. Returns the bytes
+    /// evicted.
+    pub async fn reserve_free_space(&self) -> u64 {
+        let mut evicted = 0;
+        for (index, disk) in self.disks.iter().enumerate() {
+            let health = disk.health();
+            let deficit = match health.free_space_deficit() {
+                Ok(deficit) => deficit,
                 Err(e) => {
-                    info!("{}", e.to_string());
-                    return GetFileResult::NotFoundOnS3(uid_str);
+                    warn!(
+                        "Checking free space on {} failed: {}",
+                        health.path().display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            if deficit == 0 {
+                if health.status() == DiskStatus::Full {
+                    health.probe().await;
                 }
+                continue;
+            }
+            let shards: Vec<_> = self
+                .shards
+                .iter()
+                .skip(index)
+                .step_by(self.disks.len())
+                .collect();
+            let share = deficit.div_ceil(shards.len() as u64);
+            for shard in shards {
+                let metadata = self.metadata.read().await;
+                evicted += shard.lock().await.evict_bytes(&metadata, share).await;
             }
-        };
-        let file_name_str = file_name.to_str().unwrap_or_default().to_string();
-        debug!("get_file: {}", file_name_str);
-        cache.update_access(&file_name_str);
-        let cache_file_path = cache.cache_dir.join(file_name);
-        match NamedFile::open(cache_file_path).await {
-            Ok(x) => GetFileResult::Hit(x),
-            Err(_) => GetFileResult::NotFoundOnS3(uid_str),
         }
+        evicted
     }
 
-    async fn get_s3_file_to_cache(
-        &mut self,
-        s3_file_name: &str,
-        connector: Arc<dyn StorageConnector + Send + Sync>,
-    ) -> IoResult<(PathBuf, u64)> {
-        connector
-            .fetch_and_cache_file(s3_file_name, &self.cache_dir)
-            .await
+    /// The version S3 reported for `uid` when it was cached here.
+    pub async fn object_version(&self, uid: &str) -> Option<ObjectVersion> {
+        self.shard_for(uid).lock().await.versions.get(uid).cloned()
     }
 
-    async fn ensure_capacity(
-        &mut self,
-        redis_read: &RwLockReadGuard<'_, RedisServer>,
-        new_file_size: u64,
-    ) {
-        while self.current_size + new_file_size > self.max_size && !self.access_order.is_empty() {
-            if let Some((evicted_file_name, evicted_file_size)) = self.access_order.pop_front() {
-                let evicted_path = self.cache_dir.join(&evicted_file_name);
-                if fs::remove_file(&evicted_path).is_ok() {
-                    self.current_size -= evicted_file_size;
-                    let _ = redis_read.remove_file(evicted_file_name.clone()).await;
-                    info!("Evicted file: {}", evicted_file_name);
-                } else {
-                    eprintln!("Failed to delete file: {}", evicted_path.display());
+    /// Reconciles every cache directory with the shards on it and the metadata store,
+    /// repairing what is out of step; returns what was repaired. Untracked files are
+    /// deleted once they are `ORPHAN_GRACE` old.
+    pub async fn scrub(&self) -> ScrubReport {
+        let mut report = ScrubReport::default();
+        for (index, disk) in self.disks.iter().enumerate() {
+            let dir = disk.health().path();
+            // Listed before the shards are checked, so that a file written in between
+            // is tracked by the time it is compared.
+            let files = match scan_dir(dir).await {
+                Ok(files) => files,
+                Err(e) => {
+                    warn!("Scrubbing {} failed: {}", dir.display(), e);
+                    continue;
                 }
+            };
+            let mut tracked = HashSet::new();
+            for shard in self.shards.iter().skip(index).step_by(self.disks.len()) {
+                let metadata = self.metadata.read().await;
+                let mut shard = shard.lock().await;
+                report.add(&shard.scrub(&metadata).await);
+                tracked.extend(shard.core.entries().iter().map(|(key, _)| disk_name(key)));
             }
-        }
-    }
-    // Update a file's position in the access order
-    fn update_access(&mut self, file_name: &str) {
-        let mut file_size: Option<u64> = None;
-        self.access_order.retain(|(name, size)| {
-            if name == file_name {
-                file_size = Some(*size);
-                false
-            } else {
-                true
+            for file in files {
+                if tracked.contains(&file.name)
+                    || file.name == PROBE_FILE
+                    || file.name == NODE_ID_FILE
+                    || file.age < ORPHAN_GRACE
+                {
+                    continue;
+                }
+                let path = dir.join(&file.name);
+                match tokio::fs::remove_file(&path).await {
+                    Ok(()) => {
+                        info!("Deleted untracked cache file {}", path.display());
+                        report.orphaned_files += 1;
+                        report.orphaned_bytes += file.len;
+                    }
+                    Err(e) => warn!("Failed to delete {}: {}", path.display(), e),
+                }
             }
-        });
-        if let Some(size) = file_size {
-            self.access_order
-                .push_back((file_name.to_string(), size.clone()));
         }
+        self.scrubbed.lock().unwrap().add(&report);
+        report
     }
 
-    async fn empty(&mut self, redis_read: &RwLockReadGuard<'_, RedisServer>) {
-        self.current_size = 0;
-        while let Some((x, _)) = self.access_order.pop_front() {
-            let evicted_path = self.cache_dir.join(&x);
-            let _ = fs::remove_file(&evicted_path);
-            let _ = redis_read.remove_file(x).await;
+    /// Checks the bookkeeping of every shard, for tests hunting for accounting drift.
+    /// Unlike `scrub` it repairs nothing.
+    pub async fn debug_validate(&self) -> Result<(), String> {
+        for (index, shard) in self.shards.iter().enumerate() {
+            shard
+                .lock()
+                .await
+                .debug_validate()
+                .await
+                .map_err(|e| format!("shard {}: {}", index, e))?;
         }
-        redis_read.flush_all();
+        Ok(())
     }
-}
 
-// ConcurrentDiskCache Implementation -----------------------------------------
+    /// Probes the disks that are down, bringing back those that work again. Returns how
+    /// many disks are down afterwards.
+    pub async fn probe_disks(&self) -> usize {
+        let mut down = 0;
+        for disk in &self.disks {
+            let health = disk.health();
+            if !health.is_up() && !health.probe().await {
+                down += 1;
+            }
+        }
+        down
+    }
 
-impl ConcurrentDiskCache {
-    pub fn new(
-        cache_dir: PathBuf,
-        max_size: u64,
-        bucket_size: u64,
-        redis_addrs: Vec<String>,
-        redis_port: u16,
-    ) -> Self {
-        let _ = std::fs::create_dir_all(cache_dir.clone());
-        let shard_max_size = max_size / bucket_size as u64;
-        let redis_server = RedisServer::new(redis_addrs).unwrap();
-        let redis = Arc::new(RwLock::new(redis_server));
-        let shards = (0..bucket_size)
-            .map(|_| DiskCache::new(cache_dir.clone(), shard_max_size))
-            .collect::<Vec<_>>();
+    /// Every node of the cluster, loading the slot mapping if no request has yet.
+    pub async fn members(&self) -> Result<Vec<ClusterMember>, StoreError> {
+        self.ensure_mapping_initialized().await?;
+        Ok(self.metadata.read().await.members())
+    }
 
-        Self {
-            shards,
-            redis,
-            redis_port,
+    /// The `n` most accessed keys and the `n` keys that served the most bytes.
+    pub async fn hot_keys(&self, n: usize) -> HotKeysReport {
+        let mut by_accesses = Vec::new();
+        let mut by_bytes = Vec::new();
+        for shard in &self.shards {
+            let mut shard = shard.lock().await;
+            shard.record_unlocked_hits();
+            by_accesses.push(shard.hot_keys.by_accesses(n));
+            by_bytes.push(shard.hot_keys.by_bytes(n));
+        }
+        HotKeysReport {
+            by_accesses: merge_top(by_accesses, n, |key| key.accesses),
+            by_bytes: merge_top(by_bytes, n, |key| key.bytes),
         }
     }
-    pub async fn get_file(
-        &self,
-        uid: PathBuf,
-        connector: Arc<dyn StorageConnector + Send + Sync>,
-    ) -> GetFileResult {
-        let uid = uid.into_os_string().into_string().unwrap();
-        // Use read lock for read operations
-        let redis_read = self.redis.read().await; // Acquiring a read lock
-        if !redis_read.mapping_initialized {
-            drop(redis_read); // Drop read lock before acquiring write lock
-
-            let mut redis_write = self.redis.write().await; // Acquiring a write lock
-            if let Err(e) = redis_write.update_slot_to_node_mapping().await {
-                return GetFileResult::InitFailed(format!(
-                    "Error updating slot-to-node mapping: {:?}",
-                    e
-                ));
+
+    /// Bytes served from the cache against bytes fetched from the origin, and hit against
+    /// miss latency, over each of `EFFICIENCY_WINDOWS`.
+    pub async fn efficiency(&self) -> Vec<EfficiencyRow> {
+        let mut totals = vec![EfficiencyTotals::default(); EFFICIENCY_WINDOWS.len()];
+        for shard in &self.shards {
+            let mut shard = shard.lock().await;
+            shard.record_unlocked_hits();
+            for (total, shard_total) in totals.iter_mut().zip(shard.stats.efficiency.totals()) {
+                total.add(&shard_total);
             }
-            redis_write.get_myid(self.redis_port);
-            redis_write.mapping_initialized = true;
-            drop(redis_write);
-            debug!("Initialization complete, dropped Redis write lock");
-        } else {
-            drop(redis_read);
-        }
-        let redis_read = self.redis.read().await;
-        let shard_index = hash(&uid) % self.shards.len(); // Hash UID to select a shard
-        let shard = &self.shards[shard_index];
-        // Debug message showing shard selection
-        debug!("Selected shard index: {} for uid: {}", shard_index, &uid);
-        let result =
-            DiskCache::get_file(shard.clone(), uid.into(), connector.clone(), &redis_read).await;
-        drop(redis_read);
-        debug!("{}", self.get_stats().await);
-        result
+        }
+        EFFICIENCY_WINDOWS
+            .iter()
+            .zip(&totals)
+            .map(|((name, _, _), totals)| EfficiencyRow::new(name, totals))
+            .collect()
+    }
+
+    /// Cached bytes and accesses grouped by the first `depth` directories of the keys.
+    pub async fn prefix_usage(&self, depth: usize) -> Vec<PrefixUsage> {
+        let mut entries = Vec::new();
+        let mut accesses = Vec::new();
+        for shard in &self.shards {
+            let mut shard = shard.lock().await;
+            shard.record_unlocked_hits();
+            entries.extend(shard.core.entries());
+            accesses.extend(
+                shard
+                    .prefix_accesses
+                    .at_depth(depth)
+                    .map(|(prefix, count)| (prefix.to_string(), count)),
+            );
+        }
+        usage_by_prefix(
+            depth,
+            entries.iter().map(|(name, size)| (name.as_str(), *size)),
+            accesses
+                .iter()
+                .map(|(prefix, count)| (prefix.as_str(), *count)),
+        )
     }
 
     pub async fn get_stats(&self) -> String {
         let current_time = chrono::Utc::now();
         let mut stats_summary = format!("Cache Stats at {}\n", current_time.to_rfc3339());
         stats_summary.push_str(&format!(
-            "{:<15} | {:<12} | {:<12} | {:<10} | {}\n",
-            "Shard", "Curr Size", "% Used", "Total Files", "Files"
+            "{:<15} | {:<12} | {:<12} | {:<12} | {:<10} | {}\n",
+            "Shard", "Curr Size", "Logical Size", "% Used", "Total Files", "Files"
         ));
-        stats_summary.push_str(&"-".repeat(80));
+        stats_summary.push_str(&"-".repeat(95));
         stats_summary.push('\n');
 
         for (index, shard) in self.shards.iter().enumerate() {
             match tokio::time::timeout(std::time::Duration::from_secs(5), shard.lock()).await {
                 Ok(shard_guard) => {
                     let files_in_shard: Vec<_> = shard_guard
-                        .access_order
+                        .core
+                        .entries()
                         .iter()
                         .map(|(name, size)| format!("{} ({}B)", name, size))
                         .collect::<Vec<String>>();
                     let total_files = files_in_shard.len();
-                    let calculated_current_size: u64 =
-                        shard_guard.access_order.iter().map(|(_, size)| size).sum();
-                    let used_capacity_pct =
-                        (calculated_current_size as f64 / shard_guard.max_size as f64) * 100.0;
+                    let calculated_current_size: u64 = shard_guard
+                        .core
+                        .entries()
+                        .iter()
+                        .map(|(_, size)| size)
+                        .sum();
+                    let used_capacity_pct = (calculated_current_size as f64
+                        / shard_guard.core.max_size() as f64)
+                        * 100.0;
                     stats_summary.push_str(&format!(
-                        "{:<15} | {:<12} | {:<12.2} | {:<10} | {:?}\n",
+                        "{:<15} | {:<12} | {:<12} | {:<12.2} | {:<10} | {:?}\n",
                         format!("Shard {}", index),
-                        shard_guard.current_size,
+                        shard_guard.core.current_size(),
+                        shard_guard.logical_size(),
                         used_capacity_pct,
                         total_files,
                         files_in_shard
@@ -269,23 +3390,662 @@ impl ConcurrentDiskCache {
             }
         }
 
+        stats_summary.push('\n');
+        stats_summary.push_str(&format!(
+            "{:<15} | {:<12} | {:<12} | {:<12} | {:<12} | {:<12} | {}\n",
+            "Shard", "Mem Hits", "Disk Hits", "Misses", "Mem Hit %", "Disk Hit %", "Mem Size"
+        ));
+        stats_summary.push_str(&"-".repeat(100));
+        stats_summary.push('\n');
+        for (index, shard) in self.shards.iter().enumerate() {
+            if let Ok(mut shard_guard) =
+                tokio::time::timeout(std::time::Duration::from_secs(5), shard.lock()).await
+            {
+                shard_guard.record_unlocked_hits();
+                let stats = &shard_guard.stats;
+                stats_summary.push_str(&format!(
+                    "{:<15} | {:<12} | {:<12} | {:<12} | {:<12.2} | {:<12.2} | {} ({} files)\n",
+                    format!("Shard {}", index),
+                    stats.memory_hits,
+                    stats.disk_hits,
+                    stats.misses,
+                    stats.ratio(stats.memory_hits),
+                    stats.ratio(stats.disk_hits),
+                    shard_guard.memory.current_size(),
+                    shard_guard.memory.len()
+                ));
+            }
+        }
+
+        stats_summary.push('\n');
+        stats_summary.push_str(&format!(
+            "{:<15} | {:<12} | {:<14} | {:<14} | {:<10} | {:<22} | {}\n",
+            "Shard",
+            "Recent Hit %",
+            "From Cache",
+            "From S3",
+            "Evictions",
+            "Hit p50/p95/p99 ms",
+            "S3 p50/p95/p99 ms"
+        ));
+        stats_summary.push_str(&"-".repeat(125));
+        stats_summary.push('\n');
+        let mut all_stats = Vec::new();
+        for (index, shard) in self.shards.iter().enumerate() {
+            if let Ok(mut shard_guard) =
+                tokio::time::timeout(std::time::Duration::from_secs(5), shard.lock()).await
+            {
+                shard_guard.record_unlocked_hits();
+                all_stats.push((format!("Shard {}", index), shard_guard.stats.clone()));
+            }
+        }
+        let total = ShardStats {
+            recent: RollingCounts::default(),
+            bytes_from_cache: all_stats.iter().map(|(_, s)| s.bytes_from_cache).sum(),
+            bytes_from_s3: all_stats.iter().map(|(_, s)| s.bytes_from_s3).sum(),
+            evictions: all_stats.iter().map(|(_, s)| s.evictions).sum(),
+            hit_latency: LatencyWindow::merged(all_stats.iter().map(|(_, s)| &s.hit_latency)),
+            s3_fetch_latency: LatencyWindow::merged(
+                all_stats.iter().map(|(_, s)| &s.s3_fetch_latency),
+            ),
+            ..ShardStats::default()
+        };
+        let recent_total = all_stats
+            .iter()
+            .map(|(_, s)| s.recent.totals())
+            .fold((0, 0), |(hits, misses), (h, m)| (hits + h, misses + m));
+        let rows = all_stats
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats, stats.recent.totals()))
+            .chain(std::iter::once(("Total", &total, recent_total)));
+        for (name, stats, recent) in rows {
+            stats_summary.push_str(&format!(
+                "{:<15} | {:<12.2} | {:<14} | {:<14} | {:<10} | {:<22} | {}\n",
+                name,
+                hit_ratio(recent),
+                stats.bytes_from_cache,
+                stats.bytes_from_s3,
+                stats.evictions,
+                stats.hit_latency.summary(),
+                stats.s3_fetch_latency.summary()
+            ));
+        }
+
+        if !self.tenants.is_empty() {
+            stats_summary.push_str(&self.tenant_table().await);
+        }
+        if self.disks.len() > 1 {
+            stats_summary.push_str(&self.disk_table().await);
+        }
+        let shadows = self.shadow_stats().await;
+        if !shadows.is_empty() {
+            let hits = all_stats
+                .iter()
+                .map(|(_, s)| s.memory_hits + s.disk_hits)
+                .sum();
+            let misses = all_stats.iter().map(|(_, s)| s.misses).sum();
+            stats_summary.push_str(&shadow_table(&shadows, hit_ratio((hits, misses))));
+        }
         stats_summary
     }
 
-    pub async fn empty(&self) {
+    /// What every shadow cache would have done, summed over the shards.
+    pub async fn shadow_stats(&self) -> Vec<ShadowStats> {
+        let mut stats = Vec::new();
+        for shard in &self.shards {
+            let mut shard = shard.lock().await;
+            shard.record_unlocked_hits();
+            stats.extend(shard.shadows.iter().map(ShadowCache::stats));
+        }
+        ShadowStats::merge(&stats)
+    }
+
+    /// Usage and health of every cache directory.
+    async fn disk_table(&self) -> String {
+        let mut table = format!(
+            "\n{:<30} | {:<14} | {:<14} | {:<12} | {:<10} | {}\n",
+            "Disk", "Cached", "Budget", "% Used", "Errors", "Status"
+        );
+        table.push_str(&"-".repeat(105));
+        table.push('\n');
+        for disk in self.disk_snapshots().await {
+            table.push_str(&format!(
+                "{:<30} | {:<14} | {:<14} | {:<12.2} | {:<10} | {}\n",
+                disk.path.display(),
+                disk.current_size,
+                disk.max_size,
+                disk.current_size as f64 / disk.max_size.max(1) as f64 * 100.0,
+                disk.errors,
+                disk.status
+            ));
+        }
+        table
+    }
+
+    /// Per-tenant counters and usage summed over the shards.
+    async fn tenant_table(&self) -> String {
+        let mut totals: HashMap<String, (TenantStats, u64)> = HashMap::new();
+        for shard in &self.shards {
+            let mut shard = shard.lock().await;
+            shard.record_unlocked_hits();
+            for tenant in self.tenants.iter() {
+                let (stats, cached) = totals.entry(tenant.name.clone()).or_default();
+                if let Some(shard_stats) = shard.tenant_stats.get(&tenant.name) {
+                    stats.hits += shard_stats.hits;
+                    stats.misses += shard_stats.misses;
+                    stats.bytes_served += shard_stats.bytes_served;
+                }
+                *cached += shard.tenant_usage(&tenant.name);
+            }
+        }
+        let mut table = format!(
+            "\n{:<15} | {:<12} | {:<12} | {:<12} | {:<14} | {:<14} | {}\n",
+            "Tenant", "Hits", "Misses", "Hit %", "Served", "Cached", "Budget"
+        );
+        table.push_str(&"-".repeat(105));
+        table.push('\n');
+        for tenant in self.tenants.iter() {
+            let (stats, cached) = &totals[&tenant.name];
+            table.push_str(&format!(
+                "{:<15} | {:<12} | {:<12} | {:<12.2} | {:<14} | {:<14} | {}\n",
+                tenant.name,
+                stats.hits,
+                stats.misses,
+                hit_ratio((stats.hits, stats.misses)),
+                stats.bytes_served,
+                cached,
+                tenant
+                    .max_bytes
+                    .map_or(String::from("-"), |budget| budget.to_string())
+            ));
+        }
+        table
+    }
+
+    /// Drops `uid` from this node, e.g. after the object changed in S3. Returns the
+    /// on-disk bytes freed.
+    pub async fn invalidate(&self, uid: &str) -> u64 {
+        self.listings.invalidate(uid);
+        let metadata = self.metadata.read().await;
+        metadata.forget_cached_locations(uid);
+        self.drop_shared_listings(&metadata).await;
+        self.shard_for(uid)
+            .lock()
+            .await
+            .invalidate(|key| key == uid, &metadata)
+            .await
+    }
+
+    /// Soft-invalidates `uid`: it stays cached, but is revalidated against S3 before it is
+    /// next served, and fetched again only if it changed. Returns whether it was cached.
+    pub async fn mark_stale(&self, uid: &str) -> bool {
+        self.listings.invalidate(uid);
+        let shard = self.shard_for(uid).lock().await;
+        let cached = shard.core.contains(uid) || shard.object_sizes.contains_key(uid);
+        if cached {
+            shard
+                .stale
+                .lock()
+                .unwrap()
+                .insert(uid.to_string(), Instant::now());
+        }
+        cached
+    }
+
+    /// One page of the backing store's listing for `request`. Pages come from the listing
+    /// cache while they are fresh, then from the metadata store when listings are shared.
+    pub async fn list_page(
+        &self,
+        request: &ListRequest,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+    ) -> IoResult<ObjectListing> {
+        if let Some(page) = self.listings.get(request) {
+            return Ok(page);
+        }
+        let key = shared_key(request);
+        if self.share_listings {
+            let loaded = self.metadata.read().await.load_listing(&key).await;
+            match loaded.map(|page| page.map(|page| serde_json::from_str::<ObjectListing>(&page))) {
+                Ok(Some(Ok(page))) => {
+                    self.listings.insert(request.clone(), page.clone());
+                    return Ok(page);
+                }
+                Ok(Some(Err(e))) => warn!("Ignoring an unreadable shared listing page: {}", e),
+                Ok(None) => {}
+                Err(e) => debug!("Shared listing cache unavailable: {}", e),
+            }
+        }
+        let page = connector.list_objects(request).await?;
+        self.listings.insert(request.clone(), page.clone());
+        if let (true, Some(ttl)) = (self.share_listings, self.listings.ttl()) {
+            let encoded = serde_json::to_string(&page).map_err(io::Error::other)?;
+            let saved = self
+                .metadata
+                .read()
+                .await
+                .save_listing(&key, &encoded, ttl)
+                .await;
+            if let Err(e) = saved {
+                debug!("Cannot share a listing page: {}", e);
+            }
+        }
+        Ok(page)
+    }
+
+    /// Drops the listing pages kept in the metadata store. A store can't tell which pages
+    /// could list a key, so every invalidation drops them all.
+    async fn drop_shared_listings(&self, metadata: &MetadataGuard<'_>) {
+        if self.share_listings {
+            if let Err(e) = metadata.drop_listings().await {
+                warn!("Cannot drop shared listing pages: {}", e);
+            }
+        }
+    }
+
+    /// One page of the backing store's listing for `request`, with every object marked
+    /// as cached or not.
+    pub async fn list(
+        &self,
+        request: &ListRequest,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+    ) -> IoResult<Listing> {
+        let page = self.list_page(request, connector).await?;
+        let keys = page
+            .objects
+            .iter()
+            .map(|object| object.key.clone())
+            .collect();
+        let locations = self.metadata.read().await.get_files(keys).await;
+        let objects = page
+            .objects
+            .into_iter()
+            .zip(locations)
+            .map(|(object, loc)| ListedObject {
+                cached: loc.is_some(),
+                key: object.key,
+                size: object.size,
+                last_modified: object.last_modified,
+                e_tag: object.e_tag,
+            })
+            .collect();
+        Ok(Listing {
+            prefix: request.prefix.clone(),
+            objects,
+            common_prefixes: page.common_prefixes,
+            next_continuation_token: page.next_continuation_token,
+        })
+    }
+
+    /// Pages the listing cache holds.
+    pub fn cached_listings(&self) -> usize {
+        self.listings.len()
+    }
+
+    /// Publishes `message` on `channel`; returns the number of subscribers.
+    pub async fn publish(&self, channel: &str, message: &str) -> Result<i64, StoreError> {
+        self.metadata.read().await.publish(channel, message)
+    }
+
+    /// Drops every object whose key starts with `prefix` from this node. Keys are spread
+    /// over all shards, so each one is searched. Returns the on-disk bytes freed.
+    pub async fn invalidate_prefix(&self, prefix: &str) -> u64 {
+        self.listings.invalidate_prefix(prefix);
+        let metadata = self.metadata.read().await;
+        metadata.forget_cached_locations(prefix);
+        self.drop_shared_listings(&metadata).await;
+        drop(metadata);
+        let mut freed = 0;
         for shard in self.shards.iter() {
-            let redis_read = self.redis.read().await;
-            let _ = shard.lock().await.empty(&redis_read).await;
+            let metadata = self.metadata.read().await;
+            freed += shard
+                .lock()
+                .await
+                .invalidate(|key| key.starts_with(prefix), &metadata)
+                .await;
         }
+        freed
     }
-    /*
-    pub async fn set_max_size(cache: Arc<Mutex<Self>>, new_size: u64) {
-        let mut cache = cache.lock().await;
-        cache.max_size = new_size;
-        // Optionally trigger capacity enforcement immediately
-        Self::ensure_capacity(&mut *cache).await;
+
+    /// Waits until keys change owner, whether by slot or on the ring.
+    pub async fn ownership_changed(&self) {
+        self.ownership_changed.notified().await
+    }
+
+    /// Whole objects cached here that another node owns, with the owner's `(endpoint,
+    /// port)`. Chunks and footers are left to age out.
+    pub async fn misplaced_objects(&self) -> Vec<(String, String, u16)> {
+        self.ensure_mapping_initialized_or_serve_locally().await;
+        let mut names = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().await;
+            names.extend(
+                shard
+                    .core
+                    .entries()
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .filter(|name| object_key(name) == name),
+            );
+        }
+        let metadata = self.metadata.read().await;
+        let mut misplaced = Vec::new();
+        for name in names {
+            if let Some((endpoint, port)) = self.owner_of(&name, &metadata).await {
+                misplaced.push((name, endpoint, port));
+            }
+        }
+        misplaced
+    }
+
+    /// Whole objects cached here with the versions S3 reported, shard by shard, each
+    /// shard's least recently used first.
+    pub async fn cached_objects(&self) -> Vec<(String, Option<ObjectVersion>)> {
+        let mut objects = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().await;
+            objects.extend(
+                shard
+                    .core
+                    .entries()
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .filter(|name| object_key(name) == name.as_str())
+                    .map(|name| {
+                        let version = shard.versions.get(&name).cloned();
+                        (name, version)
+                    }),
+            );
+        }
+        objects
+    }
+
+    /// Whether this node owns `uid`.
+    pub async fn owns(&self, uid: &str) -> bool {
+        self.ensure_mapping_initialized_or_serve_locally().await;
+        let metadata = self.metadata.read().await;
+        self.owner_of(uid, &metadata).await.is_none()
+    }
+
+    /// Whether `uid` is cached on this node, without touching its recency.
+    pub async fn is_cached(&self, uid: &str) -> bool {
+        self.shard_for(uid).lock().await.is_tracked(uid)
+    }
+
+    /// Remembers `version` as the one S3 reported for the cached `uid`, e.g. after
+    /// `ingest` took a copy from elsewhere.
+    pub async fn record_object_version(&self, uid: &str, version: ObjectVersion) {
+        let mut shard = self.shard_for(uid).lock().await;
+        if shard.is_tracked(uid) {
+            shard.record_version(uid, version);
+        }
+    }
+
+    /// The plaintext of the cached object `uid`, or `None` if it is not cached here.
+    pub async fn open_object(&self, uid: &str) -> Option<ObjectStream> {
+        self.open_object_sized(uid).await.map(|(body, _)| body)
+    }
+
+    /// Like `open_object`, along with the size of the plaintext.
+    pub async fn open_object_sized(&self, uid: &str) -> Option<(ObjectStream, u64)> {
+        let shard = self.shard_for(uid).lock().await;
+        if !shard.is_tracked(uid) {
+            return None;
+        }
+        let codec = shard.compressed.get(uid).map(|(codec, _)| *codec);
+        let size = match codec {
+            Some(_) => shard.cached_size(uid),
+            None => unsealed_len(shard.encryption.as_ref(), shard.cached_size(uid)),
+        };
+        let encryption = shard.encryption.clone();
+        let path = shard.file_path(uid);
+        drop(shard);
+        let reader =
+            tokio::task::spawn_blocking(move || open_cached_file(encryption, codec, &path))
+                .await
+                .ok()?
+                .ok()?;
+        Some((reader_stream(reader), size))
     }
 
+    /// Deletes `uid` from this node only, leaving its location in the metadata store to
+    /// the node that now holds it. Returns the on-disk bytes freed.
+    pub async fn forget(&self, uid: &str) -> u64 {
+        let mut shard = self.shard_for(uid).lock().await;
+        let freed = match shard.core.size_of(uid) {
+            Some(size) => shard.delete_entry(uid).await.map_or(0, |_| size),
+            None => 0,
+        };
+        drop(shard);
+        if freed > 0 {
+            self.metadata
+                .read()
+                .await
+                .release_file(uid.to_string())
+                .await;
+        }
+        freed
+    }
+
+    /// Renews the leases on the locations of every file cached here, so that only those of
+    /// files this node lost track of expire. Returns how many were renewed.
+    pub async fn renew_location_leases(&self) -> Result<usize, StoreError> {
+        let mut files = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().await;
+            files.extend(shard.core.entries().into_iter().map(|(name, _)| {
+                let loc = PathBuf::from(disk_name(&name));
+                (name, loc)
+            }));
+        }
+        self.metadata.read().await.renew_leases(files).await
+    }
+
+    /// Drops the file locations this node registered, as it shuts down.
+    pub async fn forget_own_locations(&self) {
+        match self.metadata.read().await.forget_own_files().await {
+            Ok(()) => info!("Dropped this node's file locations"),
+            Err(e) => warn!("Failed to drop this node's file locations: {}", e),
+        }
+    }
+
+    /// Largest object the shard of `uid` takes.
+    pub async fn max_cacheable_size(&self, uid: &str) -> u64 {
+        self.shard_for(uid).lock().await.core.max_cacheable_size()
+    }
+
+    /// Where an incoming copy of `uid` is written before `ingest` takes it.
+    async fn staging_path(&self, uid: &str) -> PathBuf {
+        let shard = self.shard_for(uid).lock().await;
+        shard.cache_dir.join(temp_name(&disk_name(uid), "incoming"))
+    }
+
+    /// Writes `body` as the incoming copy of `uid`, sealed as it goes when encryption at
+    /// rest is on, for `ingest` to take. Nothing is left behind if writing fails.
+    pub async fn stage(&self, uid: &str, body: ObjectStream) -> IoResult<Staged> {
+        let path = self.staging_path(uid).await;
+        let (disk_io, encryption) = {
+            let shard = self.shard_for(uid).lock().await;
+            (shard.disk_io.clone(), shard.encryption.clone())
+        };
+        match disk_io
+            .write_stream(sealing(encryption.as_ref(), body), &path)
+            .await
+        {
+            Ok(stored_size) => Ok(Staged {
+                path,
+                size: unsealed_len(encryption.as_ref(), stored_size),
+                stored_size,
+                encryption,
+            }),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `stage`, for bodies that borrow from their caller, such as a request body.
+    pub async fn stage_reader(
+        &self,
+        uid: &str,
+        reader: impl AsyncRead + Send + Unpin,
+    ) -> IoResult<Staged> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(STAGING_DEPTH);
+        let pump = async move {
+            let mut chunks = ReaderStream::new(reader);
+            while let Some(chunk) = chunks.next().await {
+                if sender.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let body = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        });
+        let ((), staged) = tokio::join!(pump, self.stage(uid, Box::pin(body)));
+        staged
+    }
+
+    /// Caches the staged copy of `uid`, as if it had just been fetched from S3, and
+    /// records its location. Returns false (and drops the copy) if `uid` is cached here
+    /// already.
+    pub async fn ingest(&self, uid: &str, staged: Staged) -> IoResult<bool> {
+        // A written object may be new to S3.
+        self.listings.invalidate(uid);
+        let shard_lock = self.shard_for(uid);
+        let (cache_dir, compression, encryption) = {
+            let shard = shard_lock.lock().await;
+            if shard.is_tracked(uid) {
+                drop(shard);
+                staged.discard().await;
+                return Ok(false);
+            }
+            (
+                shard.cache_dir.clone(),
+                shard.compression.clone(),
+                shard.encryption.clone(),
+            )
+        };
+        let (file_size, stored_size) = (staged.size, staged.stored_size);
+        let local_file_name = PathBuf::from(disk_name(uid));
+        let cache_file_path = cache_dir.join(&local_file_name);
+        if let Err(e) = tokio::fs::rename(&staged.path, &cache_file_path).await {
+            staged.discard().await;
+            return Err(e);
+        }
+        let (physical_size, codec) = compress_cached_file(
+            compression.as_ref(),
+            encryption.as_ref(),
+            uid,
+            &cache_file_path,
+            file_size,
+            stored_size,
+        )
+        .await;
+
+        let metadata = self.metadata.read().await;
+        self.drop_shared_listings(&metadata).await;
+        let mut shard = shard_lock.lock().await;
+        if let Some(codec) = codec {
+            shard.compressed.insert(uid.to_string(), (codec, file_size));
+        }
+        let tenant = shard.tenants.by_key(uid).map(String::from);
+        shard
+            .insert_entry(&metadata, uid.to_string(), physical_size, tenant.as_deref())
+            .await;
+        let _ = metadata
+            .set_file_cache_loc(uid.to_string(), local_file_name)
+            .await;
+        Ok(true)
+    }
+
+    /// Drops every entry of every shard, returning what was freed. Only this node's files
+    /// and locations go; those of the other nodes are left alone.
+    pub async fn empty(&self) -> ClearReport {
+        self.listings.clear();
+        self.drop_shared_listings(&self.metadata.read().await).await;
+        let mut report = ClearReport::default();
+        for shard in self.shards.iter() {
+            let metadata = self.metadata.read().await;
+            report.add(&shard.lock().await.empty(&metadata).await);
+        }
+        info!("Cache emptied: {}", report);
+        report
+    }
+    /// Resizes the cache to `max_size` bytes in total without dropping the working set.
+    /// Shrinking evicts least recently used entries until every shard fits again; returns
+    /// the number of bytes evicted.
+    pub async fn set_max_size(&self, max_size: u64) -> u64 {
+        let capacities: Vec<u64> = self.disks.iter().map(|disk| disk.capacity).collect();
+        let budgets = stripe_budgets(&capacities, self.shards.len() as u64, max_size);
+        let mut evicted = 0;
+        for (shard, shard_max_size) in self.shards.iter().zip(budgets) {
+            let metadata = self.metadata.read().await;
+            evicted += shard
+                .lock()
+                .await
+                .set_max_size(&metadata, shard_max_size)
+                .await;
+        }
+        info!(
+            "Cache resized to {} bytes, evicted {} bytes",
+            max_size, evicted
+        );
+        evicted
+    }
+
+    pub async fn set_admission_policy(&self, policy: AdmissionPolicy) {
+        for shard in self.shards.iter() {
+            shard.lock().await.core.set_admission_policy(policy);
+        }
+    }
+
+    /// Only affects objects fetched from now on; entries already cached are kept.
+    pub async fn set_max_cacheable_object_size(&self, limit: Option<u64>) {
+        for shard in self.shards.iter() {
+            shard.lock().await.core.set_max_cacheable_object_size(limit);
+        }
+    }
+
+    pub async fn set_memory_tier_size(&self, memory_tier_size: u64) {
+        let shard_memory_size = memory_tier_size / self.shards.len() as u64;
+        for shard in self.shards.iter() {
+            shard.lock().await.memory.set_max_size(shard_memory_size);
+        }
+    }
+
+    /// Replaces the per-prefix rules, with every quota divided between the shards.
+    pub async fn set_policies(&self, policies: PolicySet) {
+        // Before the shards take a clock, so that no hit skips checking it.
+        if policies.has_clocks() {
+            self.clocks.store(true, Ordering::Release);
+        }
+        let per_shard = policies.per_shard(self.shards.len() as u64);
+        for shard in self.shards.iter() {
+            shard.lock().await.core.set_policies(per_shard.clone());
+        }
+        self.clocks.store(policies.has_clocks(), Ordering::Release);
+    }
+
+    /// The background eviction settings, if it is on.
+    pub async fn eviction(&self) -> Option<EvictionConfig> {
+        self.shards[0].lock().await.eviction
+    }
+
+    /// Sets the background eviction watermarks. Returns whether background eviction was
+    /// off, in which case the caller starts it.
+    pub async fn set_eviction(&self, eviction: EvictionConfig) -> bool {
+        let mut was_off = false;
+        for (index, shard) in self.shards.iter().enumerate() {
+            let previous = shard.lock().await.eviction.replace(eviction);
+            // The first shard decides, so that of two concurrent updates only one starts it.
+            if index == 0 {
+                was_off = previous.is_none();
+            }
+        }
+        was_off
+    }
+    /*
     pub async fn scale_out(cache: Arc<Mutex<Self>>) {
         let mut cache = cache.lock().await;
         let to_move = cache.redis.yield_keyslots(0.01).await;
@@ -0,0 +1,542 @@
+// cache_core.rs
+//! The decisions of a cache shard without any I/O: which entries it holds, which one is
+//! evicted next, which misses are admitted. `DiskCache` keeps one next to its files and
+//! the metadata store; the simulator drives it with recorded traces, so that a change to
+//! a policy can be measured reproducibly before it ships.
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::admission::{AdmissionController, AdmissionPolicy};
+use crate::footer::is_footer_key;
+use crate::metadata::SLOT_COUNT;
+use crate::policy::{PolicySet, Priority};
+use crate::util::hash;
+
+/// Entries compared to pick an eviction victim, like Redis' `maxmemory-samples`. A shard
+/// with no more entries than this compares all of them, which is exact LRU.
+pub const EVICTION_SAMPLES: usize = 5;
+
+/// What `CacheCore::access` did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Access {
+    pub hit: bool,
+    /// Whether a miss was cached.
+    pub admitted: bool,
+    /// Entries evicted to make room, with their sizes.
+    pub evicted: Vec<(String, u64)>,
+}
+
+struct IndexEntry {
+    size: u64,
+    /// Tick of the index clock at the last use.
+    last_access: AtomicU64,
+    /// Position of the key in `CacheIndex::keys`.
+    slot: usize,
+}
+
+/// The entries of one shard with the bytes charged to them, safe to read and touch
+/// without the shard lock: hits look up and refresh their entry here while a miss holds
+/// the lock to evict and insert.
+#[derive(Default)]
+pub struct CacheIndex {
+    entries: DashMap<String, IndexEntry>,
+    /// Every key, in no particular order, so that eviction samples entries in constant
+    /// time. Held while entries are inserted or removed, never by hits.
+    keys: Mutex<Vec<String>>,
+    current_size: AtomicU64,
+    /// Bytes charged outside any entry; part of `current_size`.
+    stray_size: AtomicU64,
+    /// Counts uses, so that entries compare by recency.
+    clock: AtomicU64,
+    /// State of the generator placing eviction samples; the same on every run, so that
+    /// the simulator stays reproducible.
+    sampler: AtomicU64,
+}
+
+impl CacheIndex {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn size_of(&self, name: &str) -> Option<u64> {
+        self.entries.get(name).map(|entry| entry.size)
+    }
+
+    pub fn current_size(&self) -> u64 {
+        self.current_size.load(Ordering::SeqCst)
+    }
+
+    pub fn stray_size(&self) -> u64 {
+        self.stray_size.load(Ordering::SeqCst)
+    }
+
+    /// Ticks start at 1, so that cold entries, at 0, are older than any other.
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Marks `name` as the most recently used entry.
+    pub fn touch(&self, name: &str) {
+        if let Some(entry) = self.entries.get(name) {
+            entry.last_access.store(self.tick(), Ordering::Relaxed);
+        }
+    }
+
+    /// Records an entry as the most recently used, replacing one of the same name.
+    pub fn insert(&self, name: String, size: u64) {
+        self.insert_at(name, size, self.tick());
+    }
+
+    /// Records an entry as the least recently used, replacing one of the same name.
+    pub fn insert_cold(&self, name: String, size: u64) {
+        self.insert_at(name, size, 0);
+    }
+
+    fn insert_at(&self, name: String, size: u64, tick: u64) {
+        let mut keys = self.keys.lock().unwrap();
+        let last_access = AtomicU64::new(tick);
+        self.current_size.fetch_add(size, Ordering::SeqCst);
+        if let Some(mut replaced) = self.entries.get_mut(&name) {
+            self.current_size.fetch_sub(replaced.size, Ordering::SeqCst);
+            replaced.size = size;
+            replaced.last_access = last_access;
+            return;
+        }
+        keys.push(name.clone());
+        let entry = IndexEntry {
+            size,
+            last_access,
+            slot: keys.len() - 1,
+        };
+        self.entries.insert(name, entry);
+    }
+
+    /// Forgets `name` and the bytes charged to it.
+    pub fn remove(&self, name: &str) -> Option<(String, u64)> {
+        let mut keys = self.keys.lock().unwrap();
+        let (name, entry) = self.entries.remove(name)?;
+        keys.swap_remove(entry.slot);
+        if let Some(moved) = keys.get(entry.slot) {
+            if let Some(mut moved) = self.entries.get_mut(moved) {
+                moved.slot = entry.slot;
+            }
+        }
+        self.current_size.fetch_sub(entry.size, Ordering::SeqCst);
+        Some((name, entry.size))
+    }
+
+    fn charge(&self, bytes: u64) {
+        self.current_size.fetch_add(bytes, Ordering::SeqCst);
+        self.stray_size.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    fn refund(&self, bytes: u64) {
+        let refunded = self
+            .stray_size
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |stray| {
+                Some(stray - bytes.min(stray))
+            })
+            .map_or(0, |stray| bytes.min(stray));
+        self.current_size.fetch_sub(refunded, Ordering::SeqCst);
+    }
+
+    /// Every entry with its size and the tick it was last used at, in no particular order.
+    pub fn scan(&self) -> impl Iterator<Item = (String, u64, u64)> + '_ {
+        self.entries.iter().map(|entry| {
+            (
+                entry.key().clone(),
+                entry.size,
+                entry.last_access.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    /// Every entry with its size, least recently used first.
+    pub fn entries(&self) -> Vec<(String, u64)> {
+        let mut entries: Vec<_> = self.scan().collect();
+        entries.sort_by_key(|(_, _, last_access)| *last_access);
+        entries
+            .into_iter()
+            .map(|(name, size, _)| (name, size))
+            .collect()
+    }
+
+    /// `count` entries drawn pseudo-randomly, possibly more than once, with the tick they
+    /// were last used at; every entry if there are no more than `count`.
+    pub fn sample(&self, count: usize) -> Vec<(String, u64)> {
+        let keys = self.keys.lock().unwrap();
+        let last_access = |key: &String| {
+            let entry = self.entries.get(key)?;
+            Some((key.clone(), entry.last_access.load(Ordering::Relaxed)))
+        };
+        if keys.len() <= count {
+            return keys.iter().filter_map(last_access).collect();
+        }
+        (0..count)
+            .filter_map(|_| last_access(&keys[(self.draw() % keys.len() as u64) as usize]))
+            .collect()
+    }
+
+    /// The next number of a splitmix64 sequence.
+    fn draw(&self) -> u64 {
+        const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut z = self
+            .sampler
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Checks that every entry, and nothing else, is where its slot says in `keys`.
+    fn validate_slots(&self) -> Result<(), String> {
+        let keys = self.keys.lock().unwrap();
+        if keys.len() != self.entries.len() {
+            return Err(format!(
+                "{} keys listed for {} entries",
+                keys.len(),
+                self.entries.len()
+            ));
+        }
+        match keys
+            .iter()
+            .enumerate()
+            .find(|(slot, key)| self.entries.get(*key).map(|entry| entry.slot) != Some(*slot))
+        {
+            Some((slot, key)) => Err(format!("{} is listed in the wrong slot {}", key, slot)),
+            None => Ok(()),
+        }
+    }
+
+    fn clear(&self) -> Vec<(String, u64)> {
+        let mut keys = self.keys.lock().unwrap();
+        let entries = self.entries();
+        keys.clear();
+        self.entries.clear();
+        // Stray bytes are still on disk.
+        self.current_size
+            .store(self.stray_size.load(Ordering::SeqCst), Ordering::SeqCst);
+        entries
+    }
+}
+
+/// The entries of one shard with the shard's budget, policies and admission.
+pub struct CacheCore {
+    max_size: u64,
+    index: Arc<CacheIndex>,
+    policies: PolicySet,
+    /// Bytes of the entries under each rule of `policies`, by rule index.
+    rule_bytes: Vec<u64>,
+    admission: AdmissionController,
+    max_cacheable_object_size: Option<u64>,
+}
+
+impl CacheCore {
+    pub fn new(max_size: u64, policies: PolicySet, admission_policy: AdmissionPolicy) -> Self {
+        Self {
+            max_size,
+            index: Arc::default(),
+            rule_bytes: vec![0; policies.len()],
+            policies,
+            admission: AdmissionController::new(admission_policy),
+            max_cacheable_object_size: None,
+        }
+    }
+
+    /// Refuses objects larger than `limit`, on top of the shard budget.
+    pub fn limiting_objects_to(mut self, limit: Option<u64>) -> Self {
+        self.max_cacheable_object_size = limit;
+        self
+    }
+
+    /// The entries, for lookups that don't hold the shard lock.
+    pub fn index(&self) -> &Arc<CacheIndex> {
+        &self.index
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Changes the budget; the caller evicts what no longer fits.
+    pub fn set_max_size(&mut self, max_size: u64) {
+        self.max_size = max_size;
+    }
+
+    pub fn current_size(&self) -> u64 {
+        self.index.current_size()
+    }
+
+    /// Bytes given to `charge` and not refunded yet.
+    pub fn stray_size(&self) -> u64 {
+        self.index.stray_size()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Every entry with its size, least recently used first.
+    pub fn entries(&self) -> Vec<(String, u64)> {
+        self.index.entries()
+    }
+
+    /// The least recently used entry whose name satisfies `matches`.
+    pub fn find(&self, matches: impl Fn(&str) -> bool) -> Option<String> {
+        self.index
+            .scan()
+            .filter(|(name, _, _)| matches(name))
+            .min_by_key(|(_, _, last_access)| *last_access)
+            .map(|(name, _, _)| name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains(name)
+    }
+
+    pub fn size_of(&self, name: &str) -> Option<u64> {
+        self.index.size_of(name)
+    }
+
+    pub fn policies(&self) -> &PolicySet {
+        &self.policies
+    }
+
+    /// Replaces the rules; quotas that shrink are enforced on the next insert under them.
+    pub fn set_policies(&mut self, policies: PolicySet) {
+        self.policies = policies;
+        self.rule_bytes = self.tally_rules();
+    }
+
+    /// Bytes of the entries under each rule, counted afresh.
+    fn tally_rules(&self) -> Vec<u64> {
+        let mut rule_bytes = vec![0; self.policies.len()];
+        for (name, size, _) in self.index.scan() {
+            if let Some(rule) = self.policies.rule_index(&name) {
+                rule_bytes[rule] += size;
+            }
+        }
+        rule_bytes
+    }
+
+    /// Moves `added` bytes of `name` onto its rule's total and `removed` off it.
+    fn count_under_rule(&mut self, name: &str, added: u64, removed: u64) {
+        if let Some(rule) = self.policies.rule_index(name) {
+            self.rule_bytes[rule] = (self.rule_bytes[rule] + added).saturating_sub(removed);
+        }
+    }
+
+    pub fn admission_policy(&self) -> AdmissionPolicy {
+        self.admission.policy()
+    }
+
+    pub fn set_admission_policy(&mut self, policy: AdmissionPolicy) {
+        self.admission.set_policy(policy);
+    }
+
+    pub fn set_max_cacheable_object_size(&mut self, limit: Option<u64>) {
+        self.max_cacheable_object_size = limit;
+    }
+
+    /// Largest object this shard will keep: the configured limit, capped by the shard budget
+    /// so a single object can never flush the whole shard.
+    pub fn max_cacheable_size(&self) -> u64 {
+        self.max_cacheable_object_size
+            .map_or(self.max_size, |limit| limit.min(self.max_size))
+    }
+
+    /// Whether a missed `uid` may be cached, recording the miss with the admission policy.
+    /// Policies that refuse a prefix win over forced admission.
+    pub fn admits(&mut self, uid: &str, force_admit: bool, now: Instant) -> bool {
+        self.policies.admits(uid) && (force_admit || self.admission.admit_at(uid, now))
+    }
+
+    /// Marks `name` as the most recently used entry.
+    pub fn touch(&self, name: &str) {
+        self.index.touch(name);
+    }
+
+    /// Records an entry as the most recently used, replacing one of the same name; room
+    /// is made beforehand.
+    pub fn insert(&mut self, name: String, size: u64) {
+        let replaced = self.index.size_of(&name).unwrap_or(0);
+        self.count_under_rule(&name, size, replaced);
+        self.index.insert(name, size);
+    }
+
+    /// Records an entry as the first to evict, e.g. one a batch request fetched.
+    pub fn insert_cold(&mut self, name: String, size: u64) {
+        let replaced = self.index.size_of(&name).unwrap_or(0);
+        self.count_under_rule(&name, size, replaced);
+        self.index.insert_cold(name, size);
+    }
+
+    /// Forgets the entry `name` and the bytes charged to it.
+    pub fn remove(&mut self, name: &str) -> Option<(String, u64)> {
+        let removed = self.index.remove(name)?;
+        self.count_under_rule(&removed.0, 0, removed.1);
+        Some(removed)
+    }
+
+    /// Charges bytes that stay on disk outside any entry, e.g. of a file that could not
+    /// be deleted.
+    pub fn charge(&mut self, bytes: u64) {
+        self.index.charge(bytes);
+    }
+
+    /// Stops charging bytes given to `charge`.
+    pub fn refund(&mut self, bytes: u64) {
+        self.index.refund(bytes);
+    }
+
+    /// Forgets every entry, returning them least recently used first. Stray bytes stay
+    /// charged.
+    pub fn clear(&mut self) -> Vec<(String, u64)> {
+        self.rule_bytes.iter_mut().for_each(|bytes| *bytes = 0);
+        self.index.clear()
+    }
+
+    /// Checks the bookkeeping: the bytes charged are those of the entries plus the stray
+    /// ones, and every entry can be sampled.
+    pub fn debug_validate(&self) -> Result<(), String> {
+        self.index.validate_slots()?;
+        let entry_size: u64 = self.index.scan().map(|(_, size, _)| size).sum();
+        let (current_size, stray_size) = (self.current_size(), self.stray_size());
+        if current_size != entry_size + stray_size {
+            return Err(format!(
+                "{} bytes charged, but entries take {} and {} are stray",
+                current_size, entry_size, stray_size
+            ));
+        }
+        if self.rule_bytes != self.tally_rules() {
+            return Err(format!(
+                "rule totals {:?} don't match the entries, {:?}",
+                self.rule_bytes,
+                self.tally_rules()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether `new_size` more bytes overflow the budget.
+    pub fn overflows(&self, new_size: u64) -> bool {
+        self.current_size() + new_size > self.max_size
+    }
+
+    /// The least recently used entry of the lowest priority among `candidates`, pinned
+    /// entries excluded.
+    fn victim_among(&self, candidates: Vec<(String, u64)>) -> Option<String> {
+        candidates
+            .into_iter()
+            .filter_map(|(name, last_access)| {
+                let mut priority = self.policies.priority(&name);
+                // Parquet footers are touched by every query plan, so they go late.
+                if is_footer_key(&name) {
+                    priority = priority.max(Priority::High);
+                }
+                (priority != Priority::Pinned).then_some((priority, last_access, name))
+            })
+            .min()
+            .map(|(_, _, name)| name)
+    }
+
+    /// The entry to evict next: the least recently used of the lowest priority among
+    /// `EVICTION_SAMPLES` entries, or among all of them if the sample is pinned entries
+    /// only. `None` once only pinned entries are left.
+    pub fn eviction_victim(&self) -> Option<String> {
+        self.victim_among(self.index.sample(EVICTION_SAMPLES))
+            .or_else(|| {
+                (self.len() > EVICTION_SAMPLES)
+                    .then(|| self.victim_among(self.index.sample(usize::MAX)))
+                    .flatten()
+            })
+    }
+
+    /// The least recently used entry under the same policy as `name`, if `new_size` more
+    /// bytes don't fit in that policy's quota. Only a policy over its quota looks through
+    /// the entries.
+    pub fn quota_victim(&self, name: &str, new_size: u64) -> Option<String> {
+        let rule = self.policies.rule_index(name)?;
+        let quota = self.policies.for_key(name)?.max_bytes?;
+        if self.rule_bytes[rule] + new_size <= quota {
+            return None;
+        }
+        self.find(|entry| self.policies.rule_index(entry) == Some(rule))
+    }
+
+    /// Evicts as `DiskCache` does before writing `size` bytes of `name`: within the
+    /// policy's quota first, then within the budget. Pinned entries stay, even if the
+    /// shard runs over budget.
+    pub fn make_room(&mut self, name: &str, size: u64) -> Vec<(String, u64)> {
+        let mut evicted = Vec::new();
+        while let Some(victim) = self.quota_victim(name, size) {
+            evicted.extend(self.remove(&victim));
+        }
+        while self.overflows(size) {
+            match self.eviction_victim() {
+                Some(victim) => evicted.extend(self.remove(&victim)),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Serves an access to an object of `size` bytes at `now`: a hit refreshes it, an
+    /// admitted miss that fits is inserted after making room.
+    pub fn access(&mut self, key: &str, size: u64, now: Instant) -> Access {
+        if self.contains(key) {
+            self.touch(key);
+            return Access {
+                hit: true,
+                ..Access::default()
+            };
+        }
+        if !self.admits(key, false, now) || size > self.max_cacheable_size() {
+            return Access::default();
+        }
+        let evicted = self.make_room(key, size);
+        self.insert(key.to_string(), size);
+        Access {
+            hit: false,
+            admitted: true,
+            evicted,
+        }
+    }
+}
+
+/// The shard of `shard_count` a key hashes to.
+pub fn shard_index(key: &str, shard_count: usize) -> usize {
+    hash(&key.to_string()) % shard_count
+}
+
+/// Slots given to each node, in proportion to its weight; nodes own consecutive ranges
+/// in this order.
+pub fn slot_counts(weights: &[u32]) -> Vec<usize> {
+    let total: u64 = weights.iter().map(|&weight| u64::from(weight)).sum();
+    let (mut weight_so_far, mut assigned) = (0, 0);
+    weights
+        .iter()
+        .map(|&weight| {
+            weight_so_far += u64::from(weight);
+            let end = (SLOT_COUNT as u64 * weight_so_far / total.max(1)) as usize;
+            let count = end - assigned;
+            assigned = end;
+            count
+        })
+        .collect()
+}
@@ -0,0 +1,228 @@
+// cache_service.rs
+//! `istziio.cache.v1.Cache`, the data plane of `proto/cache.proto` over gRPC. It serves
+//! the same `ConcurrentDiskCache` as the HTTP routes, but answers keys owned by another
+//! node with that node's address instead of a redirect.
+use bytes::Bytes;
+use rocket::futures::StreamExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::cache::{ConcurrentDiskCache, GetFileOptions, GetFileResult, ObjectReader};
+use crate::chunk::ByteRange;
+use crate::grpc::{Code, Replies, Service, Status};
+use crate::protobuf::{self, fields};
+use crate::read_through::check_bucket_key;
+use crate::s3_api::connector_for;
+use crate::storage::storage_connector::StorageConnector;
+use crate::warmup::warmed_bytes;
+
+/// Bytes of an object per `GetObjectResponse`.
+pub const CHUNK_LEN: usize = 1024 * 1024;
+
+pub struct CacheService {
+    cache: Arc<ConcurrentDiskCache>,
+    connectors: Vec<Arc<dyn StorageConnector + Send + Sync>>,
+    grpc_port_offset: u16,
+}
+
+impl CacheService {
+    pub fn new(
+        cache: Arc<ConcurrentDiskCache>,
+        connectors: Vec<Arc<dyn StorageConnector + Send + Sync>>,
+        grpc_port_offset: u16,
+    ) -> Self {
+        CacheService {
+            cache,
+            connectors,
+            grpc_port_offset,
+        }
+    }
+
+    /// The encoded `NodeAddress` of the node owning `key`, if that is not this node.
+    async fn owner(&self, key: &str) -> Option<Vec<u8>> {
+        let url = self.cache.owner_url(key, "/").await?;
+        let http_port = url.port_or_known_default()?;
+        let mut address = Vec::new();
+        protobuf::put_string(&mut address, 1, url.host_str()?);
+        protobuf::put_uint64(
+            &mut address,
+            2,
+            http_port.saturating_add(self.grpc_port_offset) as u64,
+        );
+        protobuf::put_uint64(&mut address, 3, http_port as u64);
+        Some(address)
+    }
+
+    async fn get_object(&self, request: &[u8], replies: &mut Replies) -> Result<(), Status> {
+        let (mut key, mut range, mut force_admit) = ("", None, false);
+        for field in fields(request) {
+            match field? {
+                (1, value) => key = value.as_str()?,
+                (2, value) => range = Some(decode_range(value.as_bytes()?)?),
+                (3, value) => force_admit = value.as_bool()?,
+                _ => {}
+            }
+        }
+        check_bucket_key(key)?;
+        if let Some(owner) = self.owner(key).await {
+            let mut response = Vec::new();
+            protobuf::put_message(&mut response, 2, &owner);
+            return replies.send(&response).await;
+        }
+        let connector = connector_for(key, &self.connectors);
+        let reader = read_object(&self.cache, connector, key, range, force_admit).await?;
+        let mut chunks = ReaderStream::with_capacity(reader, CHUNK_LEN);
+        while let Some(chunk) = chunks.next().await {
+            let mut response = Vec::new();
+            protobuf::put_bytes(&mut response, 1, &chunk?);
+            replies.send(&response).await?;
+        }
+        Ok(())
+    }
+
+    async fn probe(&self, request: &[u8]) -> Result<Vec<u8>, Status> {
+        let key = key_of(request)?;
+        let mut response = Vec::new();
+        match self.owner(key).await {
+            Some(owner) => {
+                protobuf::put_bool(&mut response, 1, false);
+                protobuf::put_message(&mut response, 2, &owner);
+            }
+            None => protobuf::put_bool(&mut response, 1, self.cache.is_cached(key).await),
+        }
+        Ok(response)
+    }
+
+    async fn prefetch(&self, request: &[u8]) -> Result<Vec<u8>, Status> {
+        let mut keys = Vec::new();
+        for field in fields(request) {
+            if let (1, value) = field? {
+                let key = value.as_str()?;
+                check_bucket_key(key)?;
+                keys.push(key);
+            }
+        }
+        let mut response = Vec::new();
+        for key in keys {
+            let connector = connector_for(key, &self.connectors).clone();
+            let options = GetFileOptions {
+                force_admit: true,
+                ..GetFileOptions::default()
+            };
+            let result = self
+                .cache
+                .get_file(PathBuf::from(key), connector, options)
+                .await;
+            let field = match warmed_bytes(result).await {
+                Ok(Some(_)) => 1,
+                Ok(None) => 2,
+                Err(e) => {
+                    log::warn!("Prefetching {} failed: {}", key, e);
+                    3
+                }
+            };
+            protobuf::put_string(&mut response, field, key);
+        }
+        Ok(response)
+    }
+}
+
+/// The bytes of `key`, or of `range` of it, read through the cache.
+pub(crate) async fn read_object(
+    cache: &ConcurrentDiskCache,
+    connector: &Arc<dyn StorageConnector + Send + Sync>,
+    key: &str,
+    range: Option<ByteRange>,
+    force_admit: bool,
+) -> Result<ObjectReader, Status> {
+    let options = GetFileOptions {
+        force_admit,
+        range,
+        ..GetFileOptions::default()
+    };
+    let result = cache
+        .get_file(PathBuf::from(key), connector.clone(), options)
+        .await;
+    if let GetFileResult::Error(e) = result {
+        return Err(e.into());
+    }
+    let range = match range {
+        Some(range) => range,
+        None => return Ok(result.into_reader()?),
+    };
+    if let GetFileResult::PassThrough(_) | GetFileResult::Streaming(_) = result {
+        // Not cached as a whole; only the range is worth fetching.
+        drop(result);
+        let object = connector.fetch_range(key, range).await?;
+        return Ok(Box::new(StreamReader::new(object.stream)));
+    }
+    result
+        .into_range_reader(range)
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::InvalidInput => Status::new(Code::OutOfRange, e.to_string()),
+            _ => e.into(),
+        })
+}
+
+fn key_of(request: &[u8]) -> Result<&str, Status> {
+    let mut key = "";
+    for field in fields(request) {
+        if let (1, value) = field? {
+            key = value.as_str()?;
+        }
+    }
+    Ok(key)
+}
+
+fn decode_range(message: &[u8]) -> Result<ByteRange, Status> {
+    let (mut start, mut end) = (0, None);
+    for field in fields(message) {
+        match field? {
+            (1, value) => start = value.as_u64()?,
+            (2, value) => end = Some(value.as_u64()?),
+            _ => {}
+        }
+    }
+    match end {
+        Some(end) if end < start => Err(Status::new(
+            Code::OutOfRange,
+            "the range ends before it starts",
+        )),
+        Some(end) => Ok(ByteRange::FromTo(start, end)),
+        None => Ok(ByteRange::From(start)),
+    }
+}
+
+#[rocket::async_trait]
+impl Service for CacheService {
+    fn name(&self) -> &'static str {
+        "istziio.cache.v1.Cache"
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        request: Bytes,
+        replies: &mut Replies,
+    ) -> Result<(), Status> {
+        let response = match method {
+            "GetObject" => return self.get_object(&request, replies).await,
+            "Probe" => self.probe(&request).await?,
+            "Prefetch" => self.prefetch(&request).await?,
+            "Stats" => {
+                let mut response = Vec::new();
+                protobuf::put_string(&mut response, 1, &self.cache.get_stats().await);
+                response
+            }
+            _ => {
+                return Err(Status::new(
+                    Code::Unimplemented,
+                    format!("no method {}", method),
+                ))
+            }
+        };
+        replies.send(&response).await
+    }
+}
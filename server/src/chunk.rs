@@ -0,0 +1,107 @@
+// chunk.rs
+use std::fmt;
+use std::str::FromStr;
+
+use crate::util::FileUid;
+
+/// A single HTTP byte range as sent in a `Range: bytes=...` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=start-end`, both ends inclusive.
+    FromTo(u64, u64),
+    /// `bytes=start-`
+    From(u64),
+    /// `bytes=-len`, the last `len` bytes of the object.
+    Suffix(u64),
+}
+
+impl FromStr for ByteRange {
+    type Err = String;
+
+    /// Parses a single-range `Range` header value. Multi-range requests are rejected.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s
+            .trim()
+            .strip_prefix("bytes=")
+            .ok_or_else(|| format!("unsupported range unit in '{}'", s))?;
+        if spec.contains(',') {
+            return Err(format!("multiple ranges are not supported: '{}'", s));
+        }
+        let (start, end) = spec
+            .split_once('-')
+            .ok_or_else(|| format!("malformed range '{}'", s))?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u64>()
+                .map_err(|e| format!("malformed range '{}': {}", s, e))
+        };
+        match (start.trim().is_empty(), end.trim().is_empty()) {
+            (false, false) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(format!("range start after end in '{}'", s));
+                }
+                Ok(ByteRange::FromTo(start, end))
+            }
+            (false, true) => Ok(ByteRange::From(parse(start)?)),
+            (true, false) => Ok(ByteRange::Suffix(parse(end)?)),
+            (true, true) => Err(format!("malformed range '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ByteRange {
+    /// Formats the range as a `Range` header value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ByteRange::FromTo(start, end) => write!(f, "bytes={}-{}", start, end),
+            ByteRange::From(start) => write!(f, "bytes={}-", start),
+            ByteRange::Suffix(len) => write!(f, "bytes=-{}", len),
+        }
+    }
+}
+
+impl ByteRange {
+    /// Offset of the first byte, if it can be known without the object size.
+    pub fn start_hint(&self) -> Option<u64> {
+        match *self {
+            ByteRange::FromTo(start, _) | ByteRange::From(start) => Some(start),
+            ByteRange::Suffix(_) => None,
+        }
+    }
+
+    /// Resolves the range against an object of `total` bytes into inclusive `(start, end)`
+    /// offsets, or `None` if the range is not satisfiable.
+    pub fn resolve(&self, total: u64) -> Option<(u64, u64)> {
+        if total == 0 {
+            return None;
+        }
+        let (start, end) = match *self {
+            ByteRange::FromTo(start, end) => (start, end.min(total - 1)),
+            ByteRange::From(start) => (start, total - 1),
+            ByteRange::Suffix(0) => return None,
+            ByteRange::Suffix(len) => (total.saturating_sub(len), total - 1),
+        };
+        if start >= total {
+            None
+        } else {
+            Some((start, end))
+        }
+    }
+}
+
+/// Cache key of the `index`-th chunk of `uid`. Chunks are tracked like regular entries.
+pub fn chunk_key(uid: &str, index: u64) -> FileUid {
+    format!("{}@chunk-{}", uid, index)
+}
+
+/// Inclusive byte offsets covered by chunk `index` of an object of `total` bytes.
+pub fn chunk_bounds(index: u64, chunk_size: u64, total: u64) -> (u64, u64) {
+    let start = index * chunk_size;
+    (start, (start + chunk_size).min(total) - 1)
+}
+
+/// Parses the object size out of a `Content-Range: bytes a-b/total` header value.
+pub fn total_from_content_range(value: &str) -> Option<u64> {
+    value.rsplit_once('/')?.1.trim().parse().ok()
+}
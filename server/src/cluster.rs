@@ -0,0 +1,422 @@
+// cluster.rs
+use rocket::futures::future::join_all;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use serde::{Deserialize, Serialize};
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info_span, Instrument};
+use url::Url;
+
+use crate::auth::{bearer_token, AdminAccess, ReadAccess};
+use crate::cache::{CacheSnapshot, ClusterMember, ConcurrentDiskCache};
+use crate::disks::DiskStatus;
+use crate::metrics::hit_ratio;
+use crate::scrub::ScrubReport;
+use crate::shadow::ShadowStats;
+use crate::telemetry::{TraceContext, TRACEPARENT_HEADER};
+use crate::tls::TlsConfig;
+
+#[cfg(feature = "fault-injection")]
+use crate::faults::{inject, FaultPoint};
+
+/// How long a fan-out waits for each peer.
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Node-wide totals, as served by `/stats/json` and summed by `/cluster/stats`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeStats {
+    pub memory_hits: u64,
+    pub disk_hits: u64,
+    pub misses: u64,
+    /// Percentage of requests answered from the memory tier or disk.
+    pub hit_ratio: f64,
+    pub bytes_from_cache: u64,
+    pub bytes_from_s3: u64,
+    pub evictions: u64,
+    /// Evictions on the write path because background eviction fell behind.
+    #[serde(default)]
+    pub emergency_evictions: u64,
+    /// Hits that checked the object's version in S3, and those that found it changed.
+    #[serde(default)]
+    pub revalidations: u64,
+    #[serde(default)]
+    pub stale_revalidations: u64,
+    /// Hits served stale while the entry was refreshed in the background.
+    #[serde(default)]
+    pub stale_hits: u64,
+    /// Entries fetched again in the background shortly before their TTL ran out.
+    #[serde(default)]
+    pub refreshes_ahead: u64,
+    /// Slow disk hits also requested from S3, and those S3 answered first.
+    #[serde(default)]
+    pub hedged_reads: u64,
+    #[serde(default)]
+    pub hedges_won: u64,
+    /// Streamed misses stopped because their clients disconnected.
+    #[serde(default)]
+    pub aborted_downloads: u64,
+    /// On-disk bytes of the cached entries.
+    pub current_size: u64,
+    pub logical_size: u64,
+    pub max_size: u64,
+    pub files: u64,
+    /// Cache directories out of use because they failed or filled up.
+    #[serde(default)]
+    pub disks_down: u64,
+    /// Failed reads and writes on the cache directories since startup.
+    #[serde(default)]
+    pub disk_errors: u64,
+    /// What the scrubber repaired since startup.
+    #[serde(default)]
+    pub scrubbed: ScrubReport,
+    /// What the shadow caches would have done; summed by name across nodes.
+    #[serde(default)]
+    pub shadows: Vec<ShadowStats>,
+}
+
+impl NodeStats {
+    pub fn from_snapshot(snapshot: &CacheSnapshot) -> Self {
+        let mut total = Self::default();
+        for shard in &snapshot.shards {
+            total.add(&Self {
+                memory_hits: shard.stats.memory_hits,
+                disk_hits: shard.stats.disk_hits,
+                misses: shard.stats.misses,
+                bytes_from_cache: shard.stats.bytes_from_cache,
+                bytes_from_s3: shard.stats.bytes_from_s3,
+                evictions: shard.stats.evictions,
+                emergency_evictions: shard.stats.emergency_evictions,
+                revalidations: shard.stats.revalidations,
+                stale_revalidations: shard.stats.stale_revalidations,
+                stale_hits: shard.stats.stale_hits,
+                refreshes_ahead: shard.stats.refreshes_ahead,
+                hedged_reads: shard.stats.hedged_reads,
+                hedges_won: shard.stats.hedges_won,
+                aborted_downloads: shard.stats.aborted_downloads,
+                current_size: shard.current_size,
+                logical_size: shard.logical_size,
+                max_size: shard.max_size,
+                files: shard.files as u64,
+                ..Self::default()
+            });
+        }
+        for disk in &snapshot.disks {
+            total.disks_down += u64::from(disk.status != DiskStatus::Up);
+            total.disk_errors += disk.errors;
+        }
+        total.scrubbed = snapshot.scrubbed;
+        total.shadows = ShadowStats::merge(snapshot.shards.iter().flat_map(|s| &s.shadows));
+        total
+    }
+
+    pub fn add(&mut self, other: &NodeStats) {
+        self.memory_hits += other.memory_hits;
+        self.disk_hits += other.disk_hits;
+        self.misses += other.misses;
+        self.bytes_from_cache += other.bytes_from_cache;
+        self.bytes_from_s3 += other.bytes_from_s3;
+        self.evictions += other.evictions;
+        self.emergency_evictions += other.emergency_evictions;
+        self.revalidations += other.revalidations;
+        self.stale_revalidations += other.stale_revalidations;
+        self.stale_hits += other.stale_hits;
+        self.refreshes_ahead += other.refreshes_ahead;
+        self.hedged_reads += other.hedged_reads;
+        self.hedges_won += other.hedges_won;
+        self.aborted_downloads += other.aborted_downloads;
+        self.current_size += other.current_size;
+        self.logical_size += other.logical_size;
+        self.max_size += other.max_size;
+        self.files += other.files;
+        self.disks_down += other.disks_down;
+        self.disk_errors += other.disk_errors;
+        self.scrubbed.add(&other.scrubbed);
+        self.shadows = ShadowStats::merge(self.shadows.iter().chain(&other.shadows));
+        self.hit_ratio = hit_ratio((self.memory_hits + self.disk_hits, self.misses));
+    }
+}
+
+/// One node's row of `/cluster/stats`; `stats` is missing when the node didn't answer.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeReport {
+    pub node_id: String,
+    /// Web server address, `host:port`.
+    pub address: String,
+    pub is_self: bool,
+    pub stats: Option<NodeStats>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClusterStats {
+    pub nodes: Vec<NodeReport>,
+    /// Sum over the nodes that answered.
+    pub total: NodeStats,
+}
+
+impl ClusterStats {
+    pub fn new(nodes: Vec<NodeReport>) -> Self {
+        let mut total = NodeStats::default();
+        for stats in nodes.iter().filter_map(|node| node.stats.as_ref()) {
+            total.add(stats);
+        }
+        Self { nodes, total }
+    }
+}
+
+/// One node's answer to a cluster-wide operation: the node's reply, or why it failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NodeOutcome {
+    pub node_id: String,
+    pub address: String,
+    pub is_self: bool,
+    pub reply: Option<String>,
+    pub error: Option<String>,
+}
+
+/// The request's bearer token, passed on to peers so they grant the same access.
+pub struct ForwardedAuth(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ForwardedAuth {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(ForwardedAuth(bearer_token(req).map(String::from)))
+    }
+}
+
+/// HTTP client for requests to peer nodes; managed as Rocket state.
+#[derive(Clone)]
+pub struct PeerClient {
+    http: reqwest::Client,
+}
+
+impl Default for PeerClient {
+    fn default() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(PEER_TIMEOUT)
+                .build()
+                .unwrap(),
+        }
+    }
+}
+
+impl PeerClient {
+    /// A client that reaches peers behind `tls`; see `TlsConfig::peer_client`.
+    pub fn with_tls(tls: &TlsConfig) -> IoResult<Self> {
+        Ok(Self {
+            http: tls.peer_client(reqwest::Client::builder().timeout(PEER_TIMEOUT))?,
+        })
+    }
+
+    /// Sends `method` to every URL at once, as part of `trace`; the body of each answer, in
+    /// order.
+    pub async fn fan_out(
+        &self,
+        method: reqwest::Method,
+        urls: &[Url],
+        token: Option<&str>,
+        trace: &TraceContext,
+    ) -> Vec<Result<String, String>> {
+        join_all(urls.iter().map(|url| {
+            let span = info_span!("peer_request", trace_id = %trace.trace_id, url = %url);
+            let mut request = self.http.request(method.clone(), url.clone());
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            // Names the span below as the parent of the peer's.
+            request = request.header(TRACEPARENT_HEADER, span.in_scope(|| trace.traceparent()));
+            async move {
+                #[cfg(feature = "fault-injection")]
+                inject(FaultPoint::Peer).await.map_err(|e| e.to_string())?;
+                let response = request.send().await.map_err(|e| e.to_string())?;
+                let status = response.status();
+                let body = response.text().await.map_err(|e| e.to_string())?;
+                if status.is_success() {
+                    Ok(body)
+                } else {
+                    Err(format!("{}: {}", status, body.trim()))
+                }
+            }
+            .instrument(span)
+        }))
+        .await
+    }
+}
+
+fn address(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+/// Every member with `path` on its web server; `None` for a member with no address.
+pub fn member_urls(
+    cache: &ConcurrentDiskCache,
+    members: &[ClusterMember],
+    path: &str,
+) -> Vec<Option<Url>> {
+    members
+        .iter()
+        .map(|member| cache.peer_url(&member.endpoint, member.port, path))
+        .collect()
+}
+
+/// Sends `method` to `path` on every member but this one. Each member comes back with its
+/// web server address and, for peers, their answer.
+async fn ask_peers(
+    cache: &ConcurrentDiskCache,
+    peers: &PeerClient,
+    method: reqwest::Method,
+    path: &str,
+    token: Option<&str>,
+    trace: &TraceContext,
+) -> Result<Vec<(ClusterMember, String, Option<Result<String, String>>)>, Custom<String>> {
+    let members = cache.members().await.map_err(|_| {
+        Custom(
+            Status::InternalServerError,
+            String::from("cluster membership unavailable"),
+        )
+    })?;
+    let urls = member_urls(cache, &members, path);
+    let remote: Vec<Url> = members
+        .iter()
+        .zip(&urls)
+        .filter(|(member, _)| !member.is_self)
+        .filter_map(|(_, url)| url.clone())
+        .collect();
+    let mut answers = peers
+        .fan_out(method, &remote, token, trace)
+        .await
+        .into_iter();
+    Ok(members
+        .into_iter()
+        .zip(&urls)
+        .map(|(member, url)| {
+            let answer = match url {
+                _ if member.is_self => None,
+                Some(_) => answers.next(),
+                None => Some(Err(String::from("no web server address"))),
+            };
+            let address = url.as_ref().map(address).unwrap_or_default();
+            (member, address, answer)
+        })
+        .collect())
+}
+
+/// Runs an admin operation here and on every peer; answers 502 if any node failed.
+async fn broadcast(
+    cache: &ConcurrentDiskCache,
+    peers: &PeerClient,
+    path: &str,
+    token: Option<&str>,
+    trace: &TraceContext,
+    local: String,
+) -> Result<Custom<Json<Vec<NodeOutcome>>>, Custom<String>> {
+    let answers = ask_peers(cache, peers, reqwest::Method::POST, path, token, trace).await?;
+    let outcomes: Vec<NodeOutcome> = answers
+        .into_iter()
+        .map(|(member, address, answer)| {
+            let (reply, error) = match answer.unwrap_or_else(|| Ok(local.clone())) {
+                Ok(reply) => (Some(reply.trim_end().to_string()), None),
+                Err(e) => (None, Some(e)),
+            };
+            NodeOutcome {
+                node_id: member.node_id,
+                address,
+                is_self: member.is_self,
+                reply,
+                error,
+            }
+        })
+        .collect();
+    let status = if outcomes.iter().all(|outcome| outcome.error.is_none()) {
+        Status::Ok
+    } else {
+        Status::BadGateway
+    };
+    Ok(Custom(status, Json(outcomes)))
+}
+
+/// Each node's totals and their sum. This node answers from memory, the others over HTTP.
+#[get("/cluster/stats")]
+pub async fn cluster_stats(
+    _auth: ReadAccess,
+    auth: ForwardedAuth,
+    trace: TraceContext,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    peers: &State<PeerClient>,
+) -> Result<Json<ClusterStats>, Custom<String>> {
+    let answers = ask_peers(
+        cache,
+        peers,
+        reqwest::Method::GET,
+        "/stats/json",
+        auth.0.as_deref(),
+        &trace,
+    )
+    .await?;
+    let local = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    let mut nodes = Vec::new();
+    for (member, address, answer) in answers {
+        let answer = match answer {
+            None => Ok(local.clone()),
+            Some(answer) => {
+                answer.and_then(|body| serde_json::from_str(&body).map_err(|e| e.to_string()))
+            }
+        };
+        let (stats, error) = match answer {
+            Ok(stats) => (Some(stats), None),
+            Err(e) => (None, Some(e)),
+        };
+        nodes.push(NodeReport {
+            node_id: member.node_id,
+            address,
+            is_self: member.is_self,
+            stats,
+            error,
+        });
+    }
+    Ok(Json(ClusterStats::new(nodes)))
+}
+
+/// Empties the cache of every node, e.g. after a data reload.
+#[post("/cluster/clear")]
+pub async fn cluster_clear(
+    _admin: AdminAccess,
+    auth: ForwardedAuth,
+    trace: TraceContext,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    peers: &State<PeerClient>,
+) -> Result<Custom<Json<Vec<NodeOutcome>>>, Custom<String>> {
+    let local = cache.empty().await.to_string();
+    broadcast(cache, peers, "/clear", auth.0.as_deref(), &trace, local).await
+}
+
+/// Drops every key starting with `prefix` on every node, e.g. after a table's schema
+/// changed.
+#[post("/cluster/invalidate/<prefix..>")]
+pub async fn cluster_invalidate(
+    _admin: AdminAccess,
+    auth: ForwardedAuth,
+    trace: TraceContext,
+    prefix: PathBuf,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    peers: &State<PeerClient>,
+) -> Result<Custom<Json<Vec<NodeOutcome>>>, Custom<String>> {
+    let prefix = prefix.to_string_lossy();
+    let freed = cache.invalidate_prefix(&prefix).await;
+    let local = format!("invalidated {}*, freed {} bytes", prefix, freed);
+    let path = format!("/admin/invalidate-prefix/{}", prefix);
+    broadcast(cache, peers, &path, auth.0.as_deref(), &trace, local).await
+}
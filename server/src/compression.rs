@@ -0,0 +1,224 @@
+// compression.rs
+use bytes::Bytes;
+use rocket::futures::stream;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Result as IoResult, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::encryption::{DecryptingReader, EncryptingWriter, EncryptionKey};
+use crate::storage::storage_connector::ObjectStream;
+use crate::util::temp_name;
+
+const DECODE_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Codec used to compress cached files on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum CompressionCodec {
+    Zstd,
+    Lz4,
+}
+
+impl FromStr for CompressionCodec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(CompressionCodec::Zstd),
+            "lz4" => Ok(CompressionCodec::Lz4),
+            _ => Err(format!("unknown compression codec '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for CompressionCodec {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl CompressionCodec {
+    /// Token used for this codec in `Accept-Encoding` / `Content-Encoding`.
+    pub fn content_encoding(&self) -> &'static str {
+        match self {
+            CompressionCodec::Zstd => "zstd",
+            CompressionCodec::Lz4 => "lz4",
+        }
+    }
+
+    /// Whether an `Accept-Encoding` header value lets us send the compressed bytes as-is.
+    pub fn accepted_by(&self, accept_encoding: &str) -> bool {
+        accept_encoding.split(',').any(|token| {
+            let name = token.split(';').next().unwrap_or_default().trim();
+            name.eq_ignore_ascii_case(self.content_encoding())
+        })
+    }
+
+    /// Wraps `reader`, which yields the compressed bytes, in a decoder.
+    pub fn decoder(&self, reader: Box<dyn Read + Send>) -> IoResult<Box<dyn Read + Send>> {
+        Ok(match self {
+            CompressionCodec::Zstd => Box::new(zstd::Decoder::new(reader)?),
+            CompressionCodec::Lz4 => {
+                Box::new(lz4_flex::frame::FrameDecoder::new(BufReader::new(reader)))
+            }
+        })
+    }
+}
+
+/// Which cached objects get compressed, and how.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub codec: CompressionCodec,
+    /// Objects smaller than this are stored as-is; the codec overhead isn't worth it.
+    #[serde(default = "default_min_size")]
+    pub min_size: u64,
+    /// Extensions of formats that are already compressed (e.g. `parquet`, `gz`).
+    #[serde(default = "default_skip_extensions")]
+    pub skip_extensions: Vec<String>,
+}
+
+fn default_min_size() -> u64 {
+    4096
+}
+
+fn default_skip_extensions() -> Vec<String> {
+    ["gz", "zst", "lz4", "zip", "snappy"]
+        .iter()
+        .map(|ext| ext.to_string())
+        .collect()
+}
+
+impl CompressionConfig {
+    pub fn new(codec: CompressionCodec) -> Self {
+        Self {
+            codec,
+            min_size: default_min_size(),
+            skip_extensions: default_skip_extensions(),
+        }
+    }
+
+    pub fn applies_to(&self, uid: &str, size: u64) -> bool {
+        if size < self.min_size {
+            return false;
+        }
+        match Path::new(uid).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => !self
+                .skip_extensions
+                .iter()
+                .any(|skip| skip.eq_ignore_ascii_case(ext)),
+            None => true,
+        }
+    }
+}
+
+/// Compresses `path` in place. Returns the new on-disk size, or `None` if compression
+/// didn't make the file smaller, in which case the original is kept untouched.
+/// Blocking; run it on the blocking pool.
+pub fn compress_file(codec: CompressionCodec, path: &Path) -> IoResult<Option<u64>> {
+    replace_if_smaller(path, |tmp_path| {
+        let mut input = BufReader::new(File::open(path)?);
+        let output = BufWriter::new(File::create(tmp_path)?);
+        compress_into(codec, &mut input, output)?.flush()
+    })
+}
+
+/// Like `compress_file`, for a file sealed with `key`. The content is compressed and
+/// sealed again in one pass, so that it never reaches the disk in the clear.
+pub fn compress_sealed_file(
+    codec: CompressionCodec,
+    key: Arc<EncryptionKey>,
+    path: &Path,
+) -> IoResult<Option<u64>> {
+    replace_if_smaller(path, |tmp_path| {
+        let mut input = DecryptingReader::open(key.clone(), path)?;
+        let output = EncryptingWriter::new(key, BufWriter::new(File::create(tmp_path)?))?;
+        compress_into(codec, &mut input, output)?.finish()?;
+        Ok(())
+    })
+}
+
+fn compress_into<W: Write>(
+    codec: CompressionCodec,
+    input: &mut impl Read,
+    output: W,
+) -> IoResult<W> {
+    match codec {
+        CompressionCodec::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(input, &mut encoder)?;
+            encoder.finish()
+        }
+        CompressionCodec::Lz4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(output);
+            io::copy(input, &mut encoder)?;
+            encoder.finish().map_err(io::Error::other)
+        }
+    }
+}
+
+/// Has `write` produce a new version of `path` next to it, and keeps it if it is smaller.
+fn replace_if_smaller(
+    path: &Path,
+    write: impl FnOnce(&Path) -> IoResult<()>,
+) -> IoResult<Option<u64>> {
+    let original_size = fs::metadata(path)?.len();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = path.with_file_name(temp_name(&name, "compressing"));
+    if let Err(e) = write(&tmp_path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    let compressed_size = fs::metadata(&tmp_path)?.len();
+    if compressed_size >= original_size {
+        fs::remove_file(&tmp_path)?;
+        return Ok(None);
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(Some(compressed_size))
+}
+
+/// Decompresses a whole (small) cached file into memory. Blocking.
+pub fn decompress_file(codec: CompressionCodec, path: &Path) -> IoResult<Bytes> {
+    let mut data = Vec::new();
+    codec
+        .decoder(Box::new(File::open(path)?))?
+        .read_to_end(&mut data)?;
+    Ok(Bytes::from(data))
+}
+
+/// Streams the decompressed content of `path`, decoding on the blocking pool.
+pub fn decompress_stream(codec: CompressionCodec, path: &Path) -> IoResult<ObjectStream> {
+    Ok(reader_stream(codec.decoder(Box::new(File::open(path)?))?))
+}
+
+/// Streams whatever `reader` yields, reading on the blocking pool.
+pub fn reader_stream(mut reader: Box<dyn Read + Send>) -> ObjectStream {
+    let (tx, rx) = mpsc::channel::<IoResult<Bytes>>(4);
+    tokio::task::spawn_blocking(move || loop {
+        let mut buf = vec![0u8; DECODE_BUFFER_SIZE];
+        let item = match reader.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => {
+                buf.truncate(n);
+                Ok(Bytes::from(buf))
+            }
+            Err(e) => Err(e),
+        };
+        let failed = item.is_err();
+        // The receiver is gone once the client disconnects; stop decoding then.
+        if tx.blocking_send(item).is_err() || failed {
+            return;
+        }
+    });
+    Box::pin(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    }))
+}
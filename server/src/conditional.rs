@@ -0,0 +1,109 @@
+// conditional.rs
+//! Conditional GETs. Cached objects keep the ETag and modification time S3 reported when
+//! they were fetched; serves carry them, and a client that already holds that version
+//! gets `304 Not Modified` without the body.
+use chrono::{DateTime, TimeZone, Utc};
+use rocket::http::{Header, HeaderMap, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+
+use crate::cache::GetFileResult;
+use crate::storage::storage_connector::ObjectVersion;
+
+/// `secs` since the Unix epoch as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(secs: i64) -> Option<String> {
+    Utc.timestamp_opt(secs, 0)
+        .single()
+        .map(|time| time.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+/// Seconds since the Unix epoch of an HTTP date.
+pub fn parse_http_date(date: &str) -> Option<i64> {
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|time| time.timestamp())
+}
+
+/// ETags are compared weakly, as `If-None-Match` asks: `W/"x"` matches `"x"`.
+fn weak_tag(tag: &str) -> &str {
+    let tag = tag.trim();
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// The validators of a conditional GET.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preconditions {
+    /// `If-None-Match`: `*` or a list of ETags.
+    pub if_none_match: Option<String>,
+    /// `If-Modified-Since`, in seconds since the Unix epoch; malformed dates are ignored.
+    pub if_modified_since: Option<i64>,
+}
+
+impl Preconditions {
+    pub fn from_headers(headers: &HeaderMap<'_>) -> Self {
+        Preconditions {
+            if_none_match: headers.get_one("If-None-Match").map(String::from),
+            if_modified_since: headers
+                .get_one("If-Modified-Since")
+                .and_then(parse_http_date),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.if_none_match.is_none() && self.if_modified_since.is_none()
+    }
+
+    /// Whether a client holding these validators already has `version`. `If-None-Match`
+    /// wins over `If-Modified-Since` when both are sent.
+    pub fn not_modified(&self, version: &ObjectVersion) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            let e_tag = match &version.e_tag {
+                Some(e_tag) => weak_tag(e_tag),
+                None => return false,
+            };
+            return if_none_match
+                .split(',')
+                .any(|tag| tag.trim() == "*" || weak_tag(tag) == e_tag);
+        }
+        match (self.if_modified_since, version.last_modified) {
+            (Some(since), Some(modified)) => modified <= since,
+            _ => false,
+        }
+    }
+}
+
+fn set_version_headers(response: &mut Response<'_>, version: &ObjectVersion) {
+    if let Some(e_tag) = &version.e_tag {
+        response.set_header(Header::new("ETag", e_tag.clone()));
+    }
+    if let Some(date) = version.last_modified.and_then(http_date) {
+        response.set_header(Header::new("Last-Modified", date));
+    }
+}
+
+/// A `304 Not Modified` with the validators of the version the client holds.
+#[derive(Debug)]
+pub struct NotModified(pub ObjectVersion);
+
+impl<'r> Responder<'r, 'static> for NotModified {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build().status(Status::NotModified).finalize();
+        set_version_headers(&mut response, &self.0);
+        Ok(response)
+    }
+}
+
+/// A cache response carrying the version of the object served, if it is known.
+pub struct Versioned(pub GetFileResult, pub Option<ObjectVersion>);
+
+impl<'r> Responder<'r, 'static> for Versioned {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.0.respond_to(req)?;
+        if let Some(version) = self.1 {
+            if response.status().class().is_success() {
+                set_version_headers(&mut response, &version);
+            }
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,952 @@
+// config.rs
+use log::LevelFilter;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::admission::AdmissionPolicy;
+use crate::egress::EgressConfig;
+use crate::embedded_redis::EmbeddedRedisConfig;
+use crate::encryption::EncryptionConfig;
+use crate::etcd::EtcdConfig;
+use crate::eviction::EvictionConfig;
+use crate::location_cache::LocationCacheConfig;
+use crate::metadata::MetadataBackend;
+use crate::policy::PrefixPolicy;
+use crate::read_through::parse_source;
+use crate::rebalance::RebalanceConfig;
+use crate::redis::RedisMode;
+use crate::ring::Placement;
+use crate::server::ServerConfig;
+use crate::shard_runtime::ShardRuntimeConfig;
+use crate::snapshot::{valid_name, SnapshotConfig};
+use crate::storage::mock_storage_connector::MockS3Config;
+use crate::telemetry::TracingConfig;
+use crate::tls::TLS_BACKEND_PORT_OFFSET;
+use crate::util::split_host_port;
+use crate::warmup::WarmUpConfig;
+use crate::writeback::WriteBackConfig;
+
+/// Prefix of every environment variable that overrides a config file entry,
+/// e.g. `ISTZIIO_MAX_SIZE=1073741824`.
+pub const ENV_PREFIX: &str = "ISTZIIO_";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid value '{value}' for environment variable {var}: {reason}")]
+    Env {
+        var: String,
+        value: String,
+        reason: String,
+    },
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+/// Loads a TOML config file, applies `ISTZIIO_*` environment overrides and validates the
+/// result. Keys missing from the file keep their `ServerConfig::default()` value.
+pub fn load_config(path: &Path) -> Result<ServerConfig, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut config = parse_config(&content).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    apply_env_overrides(&mut config, |var| std::env::var(var).ok())?;
+    config.validate()?;
+    Ok(config)
+}
+
+pub fn parse_config(content: &str) -> Result<ServerConfig, toml::de::Error> {
+    toml::from_str(content)
+}
+
+/// Overrides config entries from the environment. `lookup` is `std::env::var` outside tests.
+pub fn apply_env_overrides<F>(config: &mut ServerConfig, lookup: F) -> Result<(), ConfigError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let get = |name: &str| lookup(&format!("{}{}", ENV_PREFIX, name));
+    if let Some(v) = get("SERVER_IP") {
+        config.server_ip = v;
+    }
+    if let Some(v) = get("REDIS_PORT") {
+        config.redis_port = parse_env("REDIS_PORT", &v)?;
+    }
+    if let Some(v) = get("WEB_PORT") {
+        config.web_port = Some(parse_env("WEB_PORT", &v)?);
+    }
+    if let Some(v) = get("ADVERTISE_ADDR") {
+        config.advertise_addr = Some(v);
+    }
+    if let Some(v) = get("ADVERTISE_HOST") {
+        config.advertise_host = Some(v);
+    }
+    if let Some(v) = get("REDIS_ADDRS") {
+        config.redis_addrs = v
+            .split(',')
+            .map(|addr| addr.trim().to_string())
+            .filter(|addr| !addr.is_empty())
+            .collect();
+    }
+    if let Some(v) = get("REDIS_MODE") {
+        config.redis_mode = parse_env("REDIS_MODE", &v)?;
+    }
+    if let Some(v) = get("EMBEDDED_REDIS") {
+        config.embedded_redis = parse_env::<bool>("EMBEDDED_REDIS", &v)?
+            .then(|| config.embedded_redis.clone().unwrap_or_default());
+    }
+    if let Some(binary) = get("EMBEDDED_REDIS_BINARY") {
+        config
+            .embedded_redis
+            .get_or_insert_with(EmbeddedRedisConfig::default)
+            .binary = binary;
+    }
+    if let Some(dir) = get("EMBEDDED_REDIS_DIR") {
+        config
+            .embedded_redis
+            .get_or_insert_with(EmbeddedRedisConfig::default)
+            .dir = dir;
+    }
+    if let Some(v) = get("METADATA_STORE") {
+        config.metadata_store = parse_env("METADATA_STORE", &v)?;
+    }
+    if let Some(v) = get("ETCD_ENDPOINTS") {
+        config
+            .etcd
+            .get_or_insert_with(EtcdConfig::default)
+            .endpoints = v
+            .split(',')
+            .map(|endpoint| endpoint.trim().to_string())
+            .filter(|endpoint| !endpoint.is_empty())
+            .collect();
+    }
+    if let Some(v) = get("SLOT_REFRESH_INTERVAL_SECS") {
+        config.slot_refresh_interval_secs = parse_env("SLOT_REFRESH_INTERVAL_SECS", &v)?;
+    }
+    if let Some(v) = get("PLACEMENT") {
+        config.placement = parse_env("PLACEMENT", &v)?;
+    }
+    if let Some(v) = get("VNODES_PER_NODE") {
+        config.vnodes_per_node = parse_env("VNODES_PER_NODE", &v)?;
+    }
+    if let Some(v) = get("CAPACITY_WEIGHT") {
+        config.capacity_weight = parse_env("CAPACITY_WEIGHT", &v)?;
+    }
+    if let Some(v) = get("REBALANCE_MAX_BYTES_PER_SEC") {
+        config
+            .rebalance
+            .get_or_insert_with(RebalanceConfig::default)
+            .max_bytes_per_sec = parse_env("REBALANCE_MAX_BYTES_PER_SEC", &v)?;
+    }
+    if let Some(v) = get("CACHE_DIR") {
+        config.cache_dir = v;
+    }
+    if let Some(v) = get("CACHE_DIRS") {
+        config.cache_dirs = v
+            .split(',')
+            .map(|dir| parse_env("CACHE_DIRS", dir))
+            .collect::<Result<_, _>>()?;
+    }
+    if let Some(v) = get("MAX_SIZE") {
+        config.max_size = parse_env("MAX_SIZE", &v)?;
+    }
+    if let Some(v) = get("BUCKET_SIZE") {
+        config.bucket_size = parse_env("BUCKET_SIZE", &v)?;
+    }
+    if let Some(v) = get("THREAD_PER_SHARD") {
+        config.shard_runtimes = parse_env::<bool>("THREAD_PER_SHARD", &v)?
+            .then(|| config.shard_runtimes.unwrap_or_default());
+    }
+    if let Some(v) = get("PIN_SHARD_THREADS") {
+        config
+            .shard_runtimes
+            .get_or_insert_with(ShardRuntimeConfig::default)
+            .pin_cores = parse_env("PIN_SHARD_THREADS", &v)?;
+    }
+    if let Some(v) = get("BUCKET") {
+        config.bucket = Some(v);
+    }
+    if let Some(v) = get("REGION") {
+        config.region_name = Some(v);
+    }
+    // Standard AWS variables are honored too, with the prefixed ones taking precedence.
+    if let Some(v) = get("ACCESS_KEY").or_else(|| lookup("AWS_ACCESS_KEY_ID")) {
+        config.access_key = Some(v);
+    }
+    if let Some(v) = get("SECRET_KEY").or_else(|| lookup("AWS_SECRET_ACCESS_KEY")) {
+        config.secret_key = Some(v);
+    }
+    if let Some(v) = get("INVALIDATION_CHANNEL") {
+        config.invalidation_channel = Some(v);
+    }
+    if let Some(v) = get("REDIS_NAMESPACE") {
+        config.redis_namespace = Some(v);
+    }
+    if let Some(v) = get("LOCATION_LEASE_SECS") {
+        config.location_lease_secs = parse_env("LOCATION_LEASE_SECS", &v)?;
+    }
+    if let Some(v) = get("LOCATION_CACHE_CAPACITY") {
+        config
+            .location_cache
+            .get_or_insert_with(LocationCacheConfig::default)
+            .capacity = parse_env("LOCATION_CACHE_CAPACITY", &v)?;
+    }
+    if let Some(v) = get("LOCATION_CACHE_TTL_MS") {
+        config
+            .location_cache
+            .get_or_insert_with(LocationCacheConfig::default)
+            .ttl_ms = parse_env("LOCATION_CACHE_TTL_MS", &v)?;
+    }
+    if let Some(v) = get("ADMIN_TOKEN") {
+        config.admin_token = Some(v);
+    }
+    if let Some(v) = get("READ_TOKENS") {
+        config.read_tokens = v
+            .split(',')
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .collect();
+    }
+    if let Some(v) = get("MOCK_S3_ENDPOINT") {
+        config.use_mock_s3_endpoint = Some(v);
+    }
+    if let Some(v) = get("MOCK_S3_LATENCY_MS") {
+        config
+            .mock_s3
+            .get_or_insert_with(MockS3Config::default)
+            .first_byte_latency_ms = parse_env("MOCK_S3_LATENCY_MS", &v)?;
+    }
+    if let Some(v) = get("MOCK_S3_BYTES_PER_SEC") {
+        config
+            .mock_s3
+            .get_or_insert_with(MockS3Config::default)
+            .bytes_per_sec = Some(parse_env("MOCK_S3_BYTES_PER_SEC", &v)?);
+    }
+    if let Some(v) = get("MOCK_S3_ERROR_RATE") {
+        config
+            .mock_s3
+            .get_or_insert_with(MockS3Config::default)
+            .error_rate = parse_env("MOCK_S3_ERROR_RATE", &v)?;
+    }
+    if let Some(v) = get("MOCK_S3_GENERATE") {
+        config
+            .mock_s3
+            .get_or_insert_with(MockS3Config::default)
+            .generate = Some(parse_env("MOCK_S3_GENERATE", &v)?);
+    }
+    if let Some(v) = get("ADMISSION_POLICY") {
+        config.admission_policy = parse_env("ADMISSION_POLICY", &v)?;
+    }
+    if let Some(v) = get("MAX_CACHEABLE_OBJECT_SIZE") {
+        config.max_cacheable_object_size = Some(parse_env("MAX_CACHEABLE_OBJECT_SIZE", &v)?);
+    }
+    if let Some(v) = get("MEMORY_TIER_SIZE") {
+        config.memory_tier_size = parse_env("MEMORY_TIER_SIZE", &v)?;
+    }
+    if let Some(v) = get("MAX_CONCURRENT_S3_FETCHES") {
+        config.max_concurrent_s3_fetches = Some(parse_env("MAX_CONCURRENT_S3_FETCHES", &v)?);
+    }
+    if let Some(v) = get("S3_FETCH_QUEUE_TIMEOUT_MS") {
+        config.s3_fetch_queue_timeout_ms = parse_env("S3_FETCH_QUEUE_TIMEOUT_MS", &v)?;
+    }
+    if let Some(v) = get("MAX_BATCH_S3_FETCHES") {
+        config.max_batch_s3_fetches = Some(parse_env("MAX_BATCH_S3_FETCHES", &v)?);
+    }
+    if let Some(v) = get("METADATA_TIMEOUT_MS") {
+        config.timeouts.metadata_ms = Some(parse_env("METADATA_TIMEOUT_MS", &v)?);
+    }
+    if let Some(v) = get("S3_FETCH_TIMEOUT_MS") {
+        config.timeouts.s3_fetch_ms = Some(parse_env("S3_FETCH_TIMEOUT_MS", &v)?);
+    }
+    if let Some(v) = get("DISK_READ_TIMEOUT_MS") {
+        config.timeouts.disk_read_ms = Some(parse_env("DISK_READ_TIMEOUT_MS", &v)?);
+    }
+    if let Some(v) = get("CHUNK_SIZE") {
+        config.chunk_size = Some(parse_env("CHUNK_SIZE", &v)?);
+    }
+    if let Some(v) = get("STREAM_FETCHES_FROM") {
+        config.stream_fetches_from = Some(parse_env("STREAM_FETCHES_FROM", &v)?);
+    }
+    if let Some(v) = get("ON_CLIENT_DISCONNECT") {
+        config.on_client_disconnect = parse_env("ON_CLIENT_DISCONNECT", &v)?;
+    }
+    if let Some(v) = get("EGRESS_PER_REQUEST_BYTES_PER_SEC") {
+        config
+            .egress
+            .get_or_insert_with(EgressConfig::default)
+            .per_request_bytes_per_sec = Some(parse_env("EGRESS_PER_REQUEST_BYTES_PER_SEC", &v)?);
+    }
+    if let Some(v) = get("EGRESS_INTERACTIVE_BYTES_PER_SEC") {
+        config
+            .egress
+            .get_or_insert_with(EgressConfig::default)
+            .interactive_bytes_per_sec = Some(parse_env("EGRESS_INTERACTIVE_BYTES_PER_SEC", &v)?);
+    }
+    if let Some(v) = get("EGRESS_BATCH_BYTES_PER_SEC") {
+        config
+            .egress
+            .get_or_insert_with(EgressConfig::default)
+            .batch_bytes_per_sec = Some(parse_env("EGRESS_BATCH_BYTES_PER_SEC", &v)?);
+    }
+    if let Some(v) = get("HEDGE_AFTER_MS") {
+        config.hedge_after_ms = Some(parse_env("HEDGE_AFTER_MS", &v)?);
+    }
+    if let Some(v) = get("DISK_IO") {
+        config.disk_io = parse_env("DISK_IO", &v)?;
+    }
+    if let Some(v) = get("DROP_PAGE_CACHE_FROM") {
+        config.drop_page_cache_from = Some(parse_env("DROP_PAGE_CACHE_FROM", &v)?);
+    }
+    if let Some(v) = get("EVICTION_HIGH_WATERMARK") {
+        config
+            .eviction
+            .get_or_insert_with(EvictionConfig::default)
+            .high_watermark = parse_env("EVICTION_HIGH_WATERMARK", &v)?;
+    }
+    if let Some(v) = get("EVICTION_LOW_WATERMARK") {
+        config
+            .eviction
+            .get_or_insert_with(EvictionConfig::default)
+            .low_watermark = parse_env("EVICTION_LOW_WATERMARK", &v)?;
+    }
+    if let Some(v) = get("EVICTION_INTERVAL_MS") {
+        config
+            .eviction
+            .get_or_insert_with(EvictionConfig::default)
+            .interval_ms = parse_env("EVICTION_INTERVAL_MS", &v)?;
+    }
+    if let Some(v) = get("FREE_SPACE_RESERVE") {
+        config.free_space_reserve = Some(parse_env("FREE_SPACE_RESERVE", &v)?);
+    }
+    if let Some(v) = get("SELF_TEST") {
+        config.self_test = parse_env("SELF_TEST", &v)?;
+    }
+    if let Some(v) = get("SCRUB_INTERVAL_SECS") {
+        config.scrub_interval_secs = parse_env("SCRUB_INTERVAL_SECS", &v)?;
+    }
+    if let Some(dir) = get("WRITE_BACK_DIR") {
+        config
+            .write_back
+            .get_or_insert_with(WriteBackConfig::default)
+            .dir = dir;
+    }
+    if let Some(v) = get("WRITE_BACK_WORKERS") {
+        config
+            .write_back
+            .get_or_insert_with(WriteBackConfig::default)
+            .workers = parse_env("WRITE_BACK_WORKERS", &v)?;
+    }
+    if let Some(path) = get("WARM_UP_MANIFEST") {
+        config
+            .warm_up
+            .get_or_insert_with(WarmUpConfig::default)
+            .manifest = Some(path);
+    }
+    if let Some(name) = get("RESTORE_SNAPSHOT") {
+        config
+            .snapshots
+            .get_or_insert_with(SnapshotConfig::default)
+            .restore_from = Some(name);
+    }
+    if let Some(v) = get("FETCH_SOURCES") {
+        config.fetch_sources = v
+            .split(',')
+            .map(|source| source.trim().to_string())
+            .filter(|source| !source.is_empty())
+            .collect();
+    }
+    if let Some(v) = get("LIST_CACHE_TTL_SECS") {
+        config.list_cache_ttl_secs = parse_env("LIST_CACHE_TTL_SECS", &v)?;
+    }
+    if let Some(v) = get("SHARE_LIST_CACHE") {
+        config.share_list_cache = parse_env("SHARE_LIST_CACHE", &v)?;
+    }
+    if let Some(path) = get("ACCESS_LOG") {
+        config.access_log = Some(path);
+    }
+    if let Some(path) = get("REQUEST_TRACE") {
+        config.request_trace = Some(path);
+    }
+    if let Some(v) = get("LOG_FORMAT") {
+        config.log_format = parse_env("LOG_FORMAT", &v)?;
+    }
+    if let Some(v) = get("TRACE_MIN_SPAN_MS") {
+        config
+            .tracing
+            .get_or_insert_with(TracingConfig::default)
+            .min_span_ms = parse_env("TRACE_MIN_SPAN_MS", &v)?;
+    }
+    if let Some(endpoint) = get("OTLP_ENDPOINT") {
+        config
+            .tracing
+            .get_or_insert_with(TracingConfig::default)
+            .otlp_endpoint = Some(endpoint);
+    }
+    if let Some(key_file) = get("ENCRYPTION_KEY_FILE") {
+        config.encryption = Some(EncryptionConfig { key_file });
+    }
+    Ok(())
+}
+
+fn parse_env<T>(name: &str, value: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: ToString,
+{
+    value.parse::<T>().map_err(|e| ConfigError::Env {
+        var: format!("{}{}", ENV_PREFIX, name),
+        value: value.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+impl ServerConfig {
+    /// Bytes the node caches: the sum of `cache_dirs` when set, `max_size` otherwise.
+    pub fn total_max_size(&self) -> u64 {
+        match self.cache_dirs.is_empty() {
+            true => self.max_size,
+            false => self.cache_dirs.iter().map(|dir| dir.max_size).sum(),
+        }
+    }
+
+    /// Checks the settings for internal consistency. Checks that need the outside world
+    /// (disk, Redis, S3) are left to startup.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        if self.bucket_size == 0 {
+            return invalid("bucket_size (number of shards) must be at least 1".into());
+        }
+        if let Some(address) = &self.advertise_addr {
+            let valid = split_host_port(address).is_some();
+            if !valid {
+                return invalid(format!(
+                    "advertise_addr must be a host:port, not '{}'",
+                    address
+                ));
+            }
+        }
+        if let Some(host) = &self.advertise_host {
+            if url::Host::parse(host).is_err() {
+                return invalid(format!("advertise_host must be a hostname, not '{}'", host));
+            }
+        }
+        if self.cache_dirs.is_empty() && self.max_size < self.bucket_size {
+            return invalid(format!(
+                "max_size ({} bytes) must give every one of the {} shards at least one byte",
+                self.max_size, self.bucket_size
+            ));
+        }
+        if self.cache_dir.is_empty() {
+            return invalid("cache_dir must not be empty".into());
+        }
+        if let Some(grpc) = &self.grpc {
+            let taken = grpc.port_offset == 0
+                || (self.tls.is_some() && grpc.port_offset == TLS_BACKEND_PORT_OFFSET);
+            if taken {
+                return invalid(format!(
+                    "grpc.port_offset {} would put gRPC on a port the HTTP server uses",
+                    grpc.port_offset
+                ));
+            }
+        }
+        if !self.cache_dirs.is_empty() {
+            let disks = self.cache_dirs.len() as u64;
+            if self.bucket_size < disks {
+                return invalid(format!(
+                    "bucket_size ({}) must be at least the number of cache_dirs ({})",
+                    self.bucket_size, disks
+                ));
+            }
+            let mut paths = HashSet::new();
+            for (index, dir) in self.cache_dirs.iter().enumerate() {
+                if dir.path.is_empty() || !paths.insert(dir.path.as_str()) {
+                    return invalid(format!(
+                        "cache_dirs paths must be set and distinct, '{}' is not",
+                        dir.path
+                    ));
+                }
+                let shards =
+                    self.bucket_size / disks + u64::from((index as u64) < self.bucket_size % disks);
+                if dir.max_size < shards {
+                    return invalid(format!(
+                        "max_size of cache dir {} must give each of its {} shards at least one byte",
+                        dir.path, shards
+                    ));
+                }
+            }
+        }
+        // With backends configured the top-level bucket is optional: it only serves the
+        // keys no backend claims.
+        if self.use_mock_s3_endpoint.is_none()
+            && (self.backends.is_empty() || self.bucket.is_some())
+        {
+            let missing = [
+                ("bucket", &self.bucket),
+                ("region_name", &self.region_name),
+                ("access_key", &self.access_key),
+                ("secret_key", &self.secret_key),
+            ]
+            .iter()
+            .filter(|(_, value)| value.as_deref().is_none_or(str::is_empty))
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                return invalid(format!(
+                    "{} required unless use_mock_s3_endpoint is set",
+                    missing.join(", ")
+                ));
+            }
+        }
+        for backend in &self.backends {
+            if backend.prefix.is_empty() {
+                return invalid("backend prefixes must not be empty".into());
+            }
+            if backend.use_mock_s3_endpoint.is_some() {
+                continue;
+            }
+            let missing = [
+                ("bucket", &backend.bucket, &None),
+                ("region_name", &backend.region_name, &self.region_name),
+                ("access_key", &backend.access_key, &self.access_key),
+                ("secret_key", &backend.secret_key, &self.secret_key),
+            ]
+            .iter()
+            .filter(|(_, own, inherited)| {
+                own.as_deref()
+                    .or(inherited.as_deref())
+                    .is_none_or(str::is_empty)
+            })
+            .map(|(name, _, _)| *name)
+            .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                return invalid(format!(
+                    "backend '{}': {} required unless use_mock_s3_endpoint is set",
+                    backend.prefix,
+                    missing.join(", ")
+                ));
+            }
+        }
+        if let Some(mock_s3) = &self.mock_s3 {
+            if !(0.0..=1.0).contains(&mock_s3.error_rate) {
+                return invalid("mock_s3.error_rate must be between 0 and 1".into());
+            }
+            if mock_s3.bytes_per_sec == Some(0) {
+                return invalid("mock_s3.bytes_per_sec must be greater than 0".into());
+            }
+            if let Some(generate) = &mock_s3.generate {
+                if generate.min_size > generate.max_size {
+                    return invalid("mock_s3.generate needs min_size <= max_size".into());
+                }
+            }
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            if !(rate_limit.requests_per_sec > 0.0 && rate_limit.burst >= 1.0) {
+                return invalid(
+                    "rate_limit needs requests_per_sec > 0 and a burst of at least 1".into(),
+                );
+            }
+        }
+        let prices = [
+            self.costs.get_per_1000,
+            self.costs.put_per_1000,
+            self.costs.transfer_per_gb,
+        ];
+        if prices.iter().any(|price| price.is_nan() || *price < 0.0) {
+            return invalid("costs prices must not be negative".into());
+        }
+        if let Some(egress) = &self.egress {
+            let limits = [
+                egress.per_request_bytes_per_sec,
+                egress.interactive_bytes_per_sec,
+                egress.batch_bytes_per_sec,
+            ];
+            if limits.contains(&Some(0)) {
+                return invalid("egress limits must be greater than 0".into());
+            }
+        }
+        if self.max_concurrent_s3_fetches == Some(0) {
+            return invalid("max_concurrent_s3_fetches must be greater than 0".into());
+        }
+        if let Some(max_batch) = self.max_batch_s3_fetches {
+            if max_batch == 0 {
+                return invalid("max_batch_s3_fetches must be greater than 0".into());
+            }
+            if self
+                .max_concurrent_s3_fetches
+                .is_some_and(|max| max_batch > max)
+            {
+                return invalid(
+                    "max_batch_s3_fetches must not exceed max_concurrent_s3_fetches".into(),
+                );
+            }
+        }
+        if self.invalidation_channel.as_deref() == Some("") {
+            return invalid("invalidation_channel must not be empty".into());
+        }
+        if self.metadata_store == MetadataBackend::Etcd {
+            match &self.etcd {
+                Some(etcd) if !etcd.endpoints.is_empty() => {
+                    if etcd.lease_ttl_secs == 0 {
+                        return invalid("etcd.lease_ttl_secs must be greater than 0".into());
+                    }
+                }
+                _ => return invalid("metadata_store = \"etcd\" needs etcd.endpoints".into()),
+            }
+            if self.advertised_ip().parse::<IpAddr>().is_err() {
+                return invalid(format!(
+                    "server_ip must be an IP address with the etcd store, not '{}'",
+                    self.server_ip
+                ));
+            }
+        }
+        if self.invalidation_channel.is_some() && self.metadata_store != MetadataBackend::Redis {
+            return invalid("invalidation_channel needs the redis metadata store".into());
+        }
+        if let Some(namespace) = &self.redis_namespace {
+            if namespace.is_empty() {
+                return invalid("redis_namespace must not be empty".into());
+            }
+            if self.metadata_store != MetadataBackend::Redis {
+                return invalid("redis_namespace needs the redis metadata store".into());
+            }
+        }
+        if self.location_lease_secs > 0 {
+            if self.location_lease_secs < 3 {
+                return invalid("location_lease_secs must be at least 3".into());
+            }
+            if self.metadata_store != MetadataBackend::Redis {
+                return invalid("location_lease_secs needs the redis metadata store".into());
+            }
+        }
+        if self.redis_mode == RedisMode::Standalone {
+            if self.metadata_store != MetadataBackend::Redis {
+                return invalid(
+                    "redis_mode = \"standalone\" needs the redis metadata store".into(),
+                );
+            }
+            if self.redis_addrs.len() > 1 {
+                return invalid(
+                    "redis_mode = \"standalone\" takes a single redis_addrs entry".into(),
+                );
+            }
+            // Nodes renew their membership on every refresh.
+            if self.slot_refresh_interval_secs == 0 {
+                return invalid(
+                    "redis_mode = \"standalone\" needs slot_refresh_interval_secs above 0".into(),
+                );
+            }
+        }
+        if let Some(embedded_redis) = &self.embedded_redis {
+            if embedded_redis.binary.is_empty() || embedded_redis.dir.is_empty() {
+                return invalid("embedded_redis needs a binary and a dir".into());
+            }
+            if embedded_redis.health_check_interval_ms == 0
+                || embedded_redis.max_failed_health_checks == 0
+            {
+                return invalid(
+                    "embedded_redis needs health_check_interval_ms and max_failed_health_checks above 0"
+                        .into(),
+                );
+            }
+        }
+        if let Some(location_cache) = &self.location_cache {
+            if location_cache.capacity == 0 || location_cache.ttl_ms == 0 {
+                return invalid("location_cache needs a capacity and ttl_ms above 0".into());
+            }
+            if self.metadata_store != MetadataBackend::Redis {
+                return invalid("location_cache needs the redis metadata store".into());
+            }
+        }
+        if self.share_list_cache {
+            if self.metadata_store != MetadataBackend::Redis {
+                return invalid("share_list_cache needs the redis metadata store".into());
+            }
+            if self.list_cache_ttl_secs == 0 {
+                return invalid("share_list_cache needs list_cache_ttl_secs".into());
+            }
+        }
+        if self.placement() == Placement::Ring && self.vnodes_per_node == 0 {
+            return invalid("vnodes_per_node must be greater than 0".into());
+        }
+        if self.capacity_weight == 0 {
+            return invalid("capacity_weight must be at least 1".into());
+        }
+        if let Some(rebalance) = &self.rebalance {
+            if self.admin_token.is_none() {
+                return invalid("rebalance needs admin_token to hand objects to peers".into());
+            }
+            if rebalance.max_bytes_per_sec == 0 {
+                return invalid("rebalance.max_bytes_per_sec must be greater than 0".into());
+            }
+        }
+        if let Some(warm_up) = &self.warm_up {
+            if warm_up.manifest.is_none() && warm_up.prefixes.is_empty() {
+                return invalid("warm_up needs a manifest or prefixes".into());
+            }
+            if warm_up.concurrency == 0 {
+                return invalid("warm_up.concurrency must be greater than 0".into());
+            }
+            if warm_up.max_bytes_per_sec == 0 {
+                return invalid("warm_up.max_bytes_per_sec must be greater than 0".into());
+            }
+        }
+        if let Some(snapshots) = &self.snapshots {
+            if snapshots.prefix.is_empty() || !snapshots.prefix.ends_with('/') {
+                return invalid("snapshots.prefix must end with '/'".into());
+            }
+            if snapshots.concurrency == 0 {
+                return invalid("snapshots.concurrency must be greater than 0".into());
+            }
+            if let Some(name) = &snapshots.restore_from {
+                if !valid_name(name) {
+                    return invalid(format!("invalid snapshots.restore_from '{}'", name));
+                }
+            }
+        }
+        if let Some(write_back) = &self.write_back {
+            if self.admin_token.is_none() {
+                return invalid("write_back needs admin_token, which PUTs must send".into());
+            }
+            if write_back.workers == 0 {
+                return invalid("write_back.workers must be greater than 0".into());
+            }
+            if write_back.retry_delay_ms == 0
+                || write_back.max_retry_delay_ms < write_back.retry_delay_ms
+            {
+                return invalid("write_back needs 0 < retry_delay_ms <= max_retry_delay_ms".into());
+            }
+            let journal = Path::new(&write_back.dir);
+            let cache_dirs = std::iter::once(self.cache_dir.as_str())
+                .chain(self.cache_dirs.iter().map(|dir| dir.path.as_str()));
+            if cache_dirs
+                .map(Path::new)
+                .any(|dir| journal.starts_with(dir))
+            {
+                return invalid(format!(
+                    "write_back.dir {} must not be in a cache directory",
+                    write_back.dir
+                ));
+            }
+        }
+        if let Some(endpoint) = self
+            .tracing
+            .as_ref()
+            .and_then(|tracing| tracing.otlp_endpoint.as_ref())
+        {
+            let valid = url::Url::parse(endpoint)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+            if !valid {
+                return invalid(format!(
+                    "tracing.otlp_endpoint {} must be an http(s) URL",
+                    endpoint
+                ));
+            }
+        }
+        for source in &self.fetch_sources {
+            if let Err(e) = parse_source(source) {
+                return invalid(format!("invalid fetch_sources entry {}", e));
+            }
+        }
+        if let Some(eviction) = &self.eviction {
+            validate_eviction(eviction)?;
+        }
+        let mut shadow_names = HashSet::new();
+        for shadow in &self.shadow_caches {
+            if shadow.name.is_empty() || !shadow_names.insert(shadow.name.as_str()) {
+                return invalid(format!(
+                    "shadow cache name '{}' is empty or repeated",
+                    shadow.name
+                ));
+            }
+            if shadow
+                .max_size
+                .is_some_and(|max_size| max_size < self.bucket_size)
+            {
+                return invalid(format!(
+                    "max_size of shadow cache '{}' must give every shard at least one byte",
+                    shadow.name
+                ));
+            }
+        }
+        if self.chunk_size == Some(0) {
+            return invalid("chunk_size must be greater than 0".into());
+        }
+        if self.hedge_after_ms == Some(0) {
+            return invalid("hedge_after_ms must be greater than 0".into());
+        }
+        let timeouts = &self.timeouts;
+        if [
+            timeouts.metadata_ms,
+            timeouts.s3_fetch_ms,
+            timeouts.disk_read_ms,
+        ]
+        .contains(&Some(0))
+        {
+            return invalid("timeouts must be greater than 0".into());
+        }
+        validate_policies(&self.policies, self.bucket_size)?;
+        let mut tenant_names = std::collections::HashSet::new();
+        for tenant in &self.tenants {
+            if tenant.name.is_empty() || !tenant_names.insert(tenant.name.as_str()) {
+                return invalid(format!(
+                    "tenant name '{}' is empty or repeated",
+                    tenant.name
+                ));
+            }
+            if tenant.tokens.iter().any(String::is_empty) {
+                return invalid(format!("tenant '{}' has an empty token", tenant.name));
+            }
+            if tenant
+                .max_bytes
+                .is_some_and(|budget| budget < self.bucket_size)
+            {
+                return invalid(format!(
+                    "max_bytes of tenant '{}' must give every shard at least one byte",
+                    tenant.name
+                ));
+            }
+        }
+        if self.memory_tier_size > self.max_size {
+            return invalid(format!(
+                "memory_tier_size ({}) must not exceed max_size ({})",
+                self.memory_tier_size, self.max_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the per-prefix rules of a node with `bucket_size` shards.
+fn validate_policies(policies: &[PrefixPolicy], bucket_size: u64) -> Result<(), ConfigError> {
+    let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+    for policy in policies {
+        if policy.pattern.is_empty() {
+            return invalid("policy patterns must not be empty".into());
+        }
+        if policy.ttl_secs == Some(0) {
+            return invalid(format!(
+                "ttl_secs of policy '{}' must be > 0",
+                policy.pattern
+            ));
+        }
+        if policy.revalidate_after_secs == Some(0) {
+            return invalid(format!(
+                "revalidate_after_secs of policy '{}' must be > 0",
+                policy.pattern
+            ));
+        }
+        if policy.stale_while_revalidate_secs == Some(0) {
+            return invalid(format!(
+                "stale_while_revalidate_secs of policy '{}' must be > 0",
+                policy.pattern
+            ));
+        }
+        if let Some(ahead) = policy.refresh_ahead_secs {
+            if ahead == 0 || policy.ttl_secs.is_none_or(|ttl| ahead >= ttl) {
+                return invalid(format!(
+                    "refresh_ahead_secs of policy '{}' must be > 0 and below its ttl_secs",
+                    policy.pattern
+                ));
+            }
+        }
+        if policy.max_bytes.is_some_and(|quota| quota < bucket_size) {
+            return invalid(format!(
+                "max_bytes of policy '{}' must give every shard at least one byte",
+                policy.pattern
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_eviction(eviction: &EvictionConfig) -> Result<(), ConfigError> {
+    let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+    let (low, high) = (eviction.low_watermark, eviction.high_watermark);
+    if !(0.0 < low && low < high && high <= 1.0) {
+        return invalid(format!(
+            "eviction watermarks need 0 < low_watermark < high_watermark <= 1, got {} and {}",
+            low, high
+        ));
+    }
+    if eviction.interval_ms == 0 {
+        return invalid("eviction.interval_ms must be greater than 0".into());
+    }
+    Ok(())
+}
+
+/// Settings that can be changed on a running node through `POST /admin/config`.
+/// Absent fields are left as they are.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigUpdate {
+    pub max_size: Option<u64>,
+    pub admission_policy: Option<AdmissionPolicy>,
+    /// `0` lifts the limit.
+    pub max_cacheable_object_size: Option<u64>,
+    pub memory_tier_size: Option<u64>,
+    /// Replaces the per-prefix rules, and with them the TTLs. A TTL counts from when an
+    /// entry was fetched, so entries fetched under no TTL keep none until fetched again.
+    pub policies: Option<Vec<PrefixPolicy>>,
+    /// Background eviction watermarks; starts background eviction on a node without it.
+    pub eviction: Option<EvictionConfig>,
+    /// One of `off`, `error`, `warn`, `info`, `debug`, `trace`.
+    pub log_level: Option<String>,
+}
+
+impl ConfigUpdate {
+    /// Checks the update against the shard count of the running node before anything is
+    /// applied, so a bad request never leaves the node half reconfigured.
+    pub fn validate(&self, bucket_size: u64) -> Result<(), ConfigError> {
+        if let Some(max_size) = self.max_size {
+            if max_size < bucket_size {
+                return Err(ConfigError::Invalid(format!(
+                    "max_size ({} bytes) must give every one of the {} shards at least one byte",
+                    max_size, bucket_size
+                )));
+            }
+        }
+        if let (Some(memory_tier_size), Some(max_size)) = (self.memory_tier_size, self.max_size) {
+            if memory_tier_size > max_size {
+                return Err(ConfigError::Invalid(format!(
+                    "memory_tier_size ({}) must not exceed max_size ({})",
+                    memory_tier_size, max_size
+                )));
+            }
+        }
+        if let Some(policies) = &self.policies {
+            validate_policies(policies, bucket_size)?;
+        }
+        if let Some(eviction) = &self.eviction {
+            validate_eviction(eviction)?;
+        }
+        self.log_level_filter()?;
+        Ok(())
+    }
+
+    pub fn log_level_filter(&self) -> Result<Option<LevelFilter>, ConfigError> {
+        self.log_level
+            .as_deref()
+            .map(|level| {
+                level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| ConfigError::Invalid(format!("unknown log level '{}'", level)))
+            })
+            .transpose()
+    }
+}
@@ -0,0 +1,220 @@
+// costs.rs
+//! What the node costs and saves in backing-store traffic, by tenant and top-level
+//! prefix. `MeteredStorageConnector` counts the requests sent to S3 and the bytes they
+//! move; hits count as the GETs and the bytes the cache saved. Both are priced under a
+//! `CostConfig` for `/stats/costs`.
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Request, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::bearer_token;
+use crate::logging::RequestOutcome;
+use crate::prefixes::prefix_of;
+use crate::tenant::Tenants;
+
+/// Upper bound on tracked (tenant, prefix) pairs; further prefixes are counted under
+/// `OTHER_PREFIX`.
+const MAX_TRACKED_PREFIXES: usize = 4096;
+const OTHER_PREFIX: &str = "(other)";
+const BYTES_PER_GB: f64 = (1u64 << 30) as f64;
+
+/// Outcomes that answered a request without going to S3.
+const SAVING_OUTCOMES: [&str; 3] = ["disk_hit", "memory_hit", "not_modified"];
+
+/// Prices in dollars, by default those of S3 Standard; transfer within a region is free.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CostConfig {
+    /// Per 1000 GET and HEAD requests.
+    pub get_per_1000: f64,
+    /// Per 1000 PUT and LIST requests.
+    pub put_per_1000: f64,
+    /// Per GB fetched from the backing store.
+    pub transfer_per_gb: f64,
+}
+
+impl Default for CostConfig {
+    fn default() -> Self {
+        Self {
+            get_per_1000: 0.0004,
+            put_per_1000: 0.005,
+            transfer_per_gb: 0.0,
+        }
+    }
+}
+
+/// Backing-store traffic of one tenant under one prefix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Traffic {
+    pub gets: u64,
+    pub heads: u64,
+    pub lists: u64,
+    pub puts: u64,
+    /// Bytes of the object bodies fetched.
+    pub bytes_fetched: u64,
+    pub bytes_uploaded: u64,
+    /// Requests answered from the cache, each a GET not sent.
+    pub hits: u64,
+    /// Bytes served from the cache, not fetched again.
+    pub bytes_saved: u64,
+}
+
+/// One row of `/stats/costs`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CostRow {
+    pub tenant: Option<String>,
+    pub prefix: String,
+    #[serde(flatten)]
+    pub traffic: Traffic,
+    /// Dollars spent on the traffic.
+    pub cost: f64,
+    /// Dollars the hits saved.
+    pub savings: f64,
+}
+
+/// What `/stats/costs` answers: the rows, costliest first, and their sum.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CostReport {
+    pub rows: Vec<CostRow>,
+    pub total: CostRow,
+}
+
+/// Traffic by tenant and top-level prefix since startup.
+pub struct CostLedger {
+    config: CostConfig,
+    tenants: Tenants,
+    traffic: Mutex<HashMap<(Option<String>, String), Traffic>>,
+}
+
+tokio::task_local! {
+    static TOKEN_TENANT: Option<String>;
+}
+
+/// Runs `fut` with `tenant`, named by the request's bearer token, charged for the
+/// backing-store traffic it causes; otherwise the key's prefix decides.
+pub async fn with_tenant<F: Future>(tenant: Option<String>, fut: F) -> F::Output {
+    TOKEN_TENANT.scope(tenant, fut).await
+}
+
+/// The tenant named by the current request's bearer token, if any.
+pub fn current_tenant() -> Option<String> {
+    TOKEN_TENANT.try_with(Clone::clone).ok().flatten()
+}
+
+impl CostLedger {
+    pub fn new(config: CostConfig, tenants: Tenants) -> Self {
+        Self {
+            config,
+            tenants,
+            traffic: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Adds to the traffic of the current request's tenant under the prefix of `key`.
+    pub fn charge(&self, key: &str, update: impl FnOnce(&mut Traffic)) {
+        self.charge_to(current_tenant().as_deref(), key, update);
+    }
+
+    /// Adds to the traffic of `token_tenant`, or the tenant owning `key`, under the
+    /// prefix of `key`.
+    pub fn charge_to(
+        &self,
+        token_tenant: Option<&str>,
+        key: &str,
+        update: impl FnOnce(&mut Traffic),
+    ) {
+        let tenant = self.tenants.resolve(token_tenant, key);
+        let mut prefix = prefix_of(key, 1).to_string();
+        let mut traffic = self.traffic.lock().unwrap();
+        if traffic.len() >= MAX_TRACKED_PREFIXES
+            && !traffic.contains_key(&(tenant.clone(), prefix.clone()))
+        {
+            prefix = String::from(OTHER_PREFIX);
+        }
+        update(traffic.entry((tenant, prefix)).or_default());
+    }
+
+    fn row(&self, tenant: Option<String>, prefix: String, traffic: Traffic) -> CostRow {
+        let CostConfig {
+            get_per_1000,
+            put_per_1000,
+            transfer_per_gb,
+        } = self.config;
+        let cost = (traffic.gets + traffic.heads) as f64 / 1000.0 * get_per_1000
+            + (traffic.lists + traffic.puts) as f64 / 1000.0 * put_per_1000
+            + traffic.bytes_fetched as f64 / BYTES_PER_GB * transfer_per_gb;
+        let savings = traffic.hits as f64 / 1000.0 * get_per_1000
+            + traffic.bytes_saved as f64 / BYTES_PER_GB * transfer_per_gb;
+        CostRow {
+            tenant,
+            prefix,
+            traffic,
+            cost,
+            savings,
+        }
+    }
+
+    pub fn report(&self) -> CostReport {
+        let traffic = self.traffic.lock().unwrap().clone();
+        let mut total = Traffic::default();
+        let mut rows = traffic
+            .into_iter()
+            .map(|((tenant, prefix), traffic)| {
+                total.gets += traffic.gets;
+                total.heads += traffic.heads;
+                total.lists += traffic.lists;
+                total.puts += traffic.puts;
+                total.bytes_fetched += traffic.bytes_fetched;
+                total.bytes_uploaded += traffic.bytes_uploaded;
+                total.hits += traffic.hits;
+                total.bytes_saved += traffic.bytes_saved;
+                self.row(tenant, prefix, traffic)
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| {
+            b.cost
+                .total_cmp(&a.cost)
+                .then_with(|| (&a.tenant, &a.prefix).cmp(&(&b.tenant, &b.prefix)))
+        });
+        CostReport {
+            rows,
+            total: self.row(None, String::new(), total),
+        }
+    }
+}
+
+/// Counts the hits of `/s3` and S3 API `GetObject` requests as savings.
+pub struct CostFairing(pub Arc<CostLedger>);
+
+#[rocket::async_trait]
+impl Fairing for CostFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Cost accounting",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let routed = req
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .is_some_and(|name| name == "get_file" || name == "get_object");
+        if !routed || !RequestOutcome::of(req).is_some_and(|o| SAVING_OUTCOMES.contains(&o)) {
+            return;
+        }
+        let key = req.routed_segments(1..).collect::<Vec<_>>().join("/");
+        let token_tenant = match (req.rocket().state::<Tenants>(), bearer_token(req)) {
+            (Some(tenants), Some(token)) => tenants.by_token(token),
+            _ => None,
+        };
+        let size = res.body_mut().size().await.unwrap_or(0) as u64;
+        self.0.charge_to(token_tenant, &key, |traffic| {
+            traffic.hits += 1;
+            traffic.bytes_saved += size;
+        });
+    }
+}
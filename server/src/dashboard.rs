@@ -0,0 +1,197 @@
+// dashboard.rs
+use std::fmt::Write;
+
+use crate::cache::{CacheSnapshot, ShardSnapshot};
+use crate::metrics::hit_ratio;
+
+/// Keys listed per shard under "Hottest keys".
+pub const DASHBOARD_KEYS_PER_SHARD: usize = 5;
+/// The page reloads itself this often.
+const REFRESH_SECS: u32 = 10;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:2em}\
+th,td{border:1px solid #ccc;padding:4px 10px;text-align:right}\
+th{background:#f0f0f0}td.key{text-align:left;font-family:monospace}\
+.bar{background:#eee;width:120px;height:10px;display:inline-block}\
+.bar span{background:#4a90d9;height:10px;display:block}";
+
+/// Escapes text for use in HTML (or XML) content and attribute values.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `bytes` with a binary unit, e.g. `1.5 MiB`.
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn utilization(shard: &ShardSnapshot) -> f64 {
+    if shard.max_size == 0 {
+        0.0
+    } else {
+        shard.current_size as f64 / shard.max_size as f64 * 100.0
+    }
+}
+
+fn shard_table(page: &mut String, snapshot: &CacheSnapshot) {
+    page.push_str(
+        "<h2>Shards</h2><table><tr><th>Shard</th><th>Utilization</th><th>On disk</th>\
+         <th>Logical</th><th>Budget</th><th>Files</th><th>Memory tier</th></tr>",
+    );
+    for shard in &snapshot.shards {
+        let used = utilization(shard);
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td><span class=\"bar\"><span style=\"width:{:.0}%\"></span></span> \
+             {:.1}%</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} ({} files)</td></tr>",
+            shard.index,
+            used.min(100.0),
+            used,
+            human_bytes(shard.current_size),
+            human_bytes(shard.logical_size),
+            human_bytes(shard.max_size),
+            shard.files,
+            human_bytes(shard.memory_size),
+            shard.memory_files
+        );
+    }
+    page.push_str("</table>");
+}
+
+fn hit_table(page: &mut String, snapshot: &CacheSnapshot) {
+    page.push_str(
+        "<h2>Hit ratios</h2><table><tr><th>Shard</th><th>Memory hits</th><th>Disk hits</th>\
+         <th>Misses</th><th>Hit %</th><th>Last 5 min %</th><th>From cache</th><th>From S3</th>\
+         <th>Evictions</th><th>Hit p50/p95/p99 ms</th><th>S3 p50/p95/p99 ms</th></tr>",
+    );
+    for shard in &snapshot.shards {
+        let stats = &shard.stats;
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1}</td><td>{:.1}</td>\
+             <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            shard.index,
+            stats.memory_hits,
+            stats.disk_hits,
+            stats.misses,
+            hit_ratio((stats.memory_hits + stats.disk_hits, stats.misses)),
+            hit_ratio(stats.recent.totals()),
+            human_bytes(stats.bytes_from_cache),
+            human_bytes(stats.bytes_from_s3),
+            stats.evictions,
+            stats.hit_latency.summary(),
+            stats.s3_fetch_latency.summary()
+        );
+    }
+    page.push_str("</table>");
+}
+
+fn key_table(page: &mut String, snapshot: &CacheSnapshot) {
+    page.push_str(
+        "<h2>Hottest keys</h2><table><tr><th>Shard</th><th>Key</th><th>Accesses</th>\
+         <th>Bytes served</th></tr>",
+    );
+    for shard in &snapshot.shards {
+        for hot in &shard.hot_keys {
+            let _ = write!(
+                page,
+                "<tr><td>{}</td><td class=\"key\">{}</td><td>{}</td><td>{}</td></tr>",
+                shard.index,
+                escape(&hot.key),
+                hot.accesses,
+                human_bytes(hot.bytes)
+            );
+        }
+    }
+    page.push_str("</table>");
+}
+
+/// Only shown when shards are spread over several cache directories.
+fn disk_table(page: &mut String, snapshot: &CacheSnapshot) {
+    if snapshot.disks.len() < 2 {
+        return;
+    }
+    page.push_str(
+        "<h2>Disks</h2><table><tr><th>Directory</th><th>Cached</th><th>Budget</th>\
+         <th>Errors</th><th>Status</th></tr>",
+    );
+    for disk in &snapshot.disks {
+        let _ = write!(
+            page,
+            "<tr><td class=\"key\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&disk.path.display().to_string()),
+            human_bytes(disk.current_size),
+            human_bytes(disk.max_size),
+            disk.errors,
+            disk.status
+        );
+    }
+    page.push_str("</table>");
+}
+
+fn member_table(page: &mut String, snapshot: &CacheSnapshot) {
+    page.push_str("<h2>Cluster membership</h2>");
+    if snapshot.members.is_empty() {
+        page.push_str("<p>Slot mapping not loaded yet; it is fetched on the first request.</p>");
+        return;
+    }
+    page.push_str(
+        "<table><tr><th>Node</th><th>Endpoint</th><th>Redis port</th><th>Slots</th>\
+         <th>Weight</th></tr>",
+    );
+    for member in &snapshot.members {
+        let _ = write!(
+            page,
+            "<tr><td class=\"key\">{}{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&member.node_id),
+            if member.is_self { " (this node)" } else { "" },
+            escape(&member.endpoint),
+            member.port,
+            member.slots,
+            member.weight
+        );
+    }
+    page.push_str("</table>");
+}
+
+/// Renders `snapshot` as a self-contained HTML page.
+pub fn render(snapshot: &CacheSnapshot) -> String {
+    let mut page = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"{}\"><title>istziio cache</title>\
+         <style>{}</style></head><body><h1>istziio cache</h1><p>As of {}</p>",
+        REFRESH_SECS,
+        STYLE,
+        snapshot.taken_at.to_rfc3339()
+    );
+    shard_table(&mut page, snapshot);
+    disk_table(&mut page, snapshot);
+    hit_table(&mut page, snapshot);
+    key_table(&mut page, snapshot);
+    member_table(&mut page, snapshot);
+    page.push_str("</body></html>");
+    page
+}
@@ -0,0 +1,127 @@
+// deadline.rs
+//! Time limits on the stages of serving a request (the metadata lookup, S3 fetches, disk
+//! reads) and the deadline a client can put on the whole request with the
+//! `X-Istziio-Timeout-Ms` header. Each stage gets whichever is shorter: its own limit or
+//! the time left before the deadline. A stage that runs out fails with a `TimedOut` error
+//! the cache answers with a 504.
+use serde::Deserialize;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, Result as IoResult};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::logging::request_deadline;
+
+/// Milliseconds the client is willing to wait for the response to start.
+pub const TIMEOUT_HEADER: &str = "X-Istziio-Timeout-Ms";
+
+/// Per-stage limits in milliseconds; a stage without one waits as long as it takes (or
+/// until the client's deadline).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Looking up the node owning a key in the metadata store.
+    pub metadata_ms: Option<u64>,
+    /// Until S3 starts answering a fetch, and between two chunks of its body.
+    pub s3_fetch_ms: Option<u64>,
+    /// Opening a cached file, including the first read of a hedged hit.
+    pub disk_read_ms: Option<u64>,
+}
+
+impl TimeoutConfig {
+    pub fn metadata(&self) -> Option<Duration> {
+        self.metadata_ms.map(Duration::from_millis)
+    }
+
+    pub fn s3_fetch(&self) -> Option<Duration> {
+        self.s3_fetch_ms.map(Duration::from_millis)
+    }
+
+    pub fn disk_read(&self) -> Option<Duration> {
+        self.disk_read_ms.map(Duration::from_millis)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Metadata,
+    S3Fetch,
+    DiskRead,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Metadata => "metadata lookup",
+            Stage::S3Fetch => "S3 fetch",
+            Stage::DiskRead => "disk read",
+        })
+    }
+}
+
+/// A stage that ran out of time, either its own or the client's.
+#[derive(Debug)]
+struct StageTimeout {
+    stage: Stage,
+    after: Duration,
+    deadline: bool,
+}
+
+impl fmt::Display for StageTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.deadline {
+            true => write!(f, "request deadline passed during {}", self.stage),
+            false => write!(
+                f,
+                "{} timed out after {} ms",
+                self.stage,
+                self.after.as_millis()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StageTimeout {}
+
+/// `stage` ran out of time after `after`; `deadline` if it was cut short by the client.
+pub fn timeout_error(stage: Stage, after: Duration, deadline: bool) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        StageTimeout {
+            stage,
+            after,
+            deadline,
+        },
+    )
+}
+
+/// Whether `e` is a stage running out of time, as opposed to e.g. a full fetch queue.
+pub fn is_timeout(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<StageTimeout>())
+}
+
+/// Parses the value of `TIMEOUT_HEADER`.
+pub fn parse_timeout(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_millis)
+}
+
+/// Runs `fut`, giving up once `limit` has passed or the current request's deadline is
+/// reached, whichever comes first.
+pub async fn bounded<F: Future>(
+    stage: Stage,
+    limit: Option<Duration>,
+    fut: F,
+) -> IoResult<F::Output> {
+    let left =
+        request_deadline().map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    let (after, deadline) = match (limit, left) {
+        (Some(limit), Some(left)) if left < limit => (left, true),
+        (Some(limit), _) => (limit, false),
+        (None, Some(left)) => (left, true),
+        (None, None) => return Ok(fut.await),
+    };
+    tokio::time::timeout(after, fut)
+        .await
+        .map_err(|_| timeout_error(stage, after, deadline))
+}
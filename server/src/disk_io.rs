@@ -0,0 +1,510 @@
+// disk_io.rs
+//! How cached files are read from and written to disk: through tokio's blocking pool, or
+//! through io_uring on Linux when the server is built with the `io-uring` feature. Large
+//! files can also be kept out of the OS page cache, which otherwise holds a second copy of
+//! everything streamed through the node and crowds out the memory tier.
+use bytes::Bytes;
+use rocket::futures::StreamExt;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{Result as IoResult, SeekFrom};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::ready;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncWriteExt, BufWriter, ReadBuf};
+
+use crate::deadline::{bounded, is_timeout, Stage};
+use crate::disks::{disk_error, DiskHealth};
+use crate::priority::is_batch;
+use crate::storage::storage_connector::ObjectStream;
+
+#[cfg(feature = "fault-injection")]
+use crate::faults::{inject, FaultPoint};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::uring::{Ring, RingFile};
+
+/// Bytes read between two hints that the pages behind the reader can be dropped.
+const DROP_BEHIND_WINDOW: u64 = 8 * 1024 * 1024;
+
+/// Bytes handed to the ring per write; smaller chunks from the store are gathered first.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+const WRITE_BATCH: usize = 1024 * 1024;
+
+/// Which implementation does disk reads and writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DiskBackend {
+    /// Blocking reads and writes on tokio's blocking pool; works everywhere.
+    #[default]
+    Tokio,
+    /// One io_uring per node, Linux only.
+    IoUring,
+}
+
+impl FromStr for DiskBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokio" => Ok(DiskBackend::Tokio),
+            "io-uring" => Ok(DiskBackend::IoUring),
+            _ => Err(format!("unknown disk I/O backend '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for DiskBackend {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A file opened for serving.
+pub trait DiskFile: AsyncRead + AsyncSeek + Send + Unpin {
+    /// Reads up to `chunk_size` bytes from disk at once.
+    fn set_chunk_size(&mut self, chunk_size: usize);
+}
+
+impl DiskFile for File {
+    fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.set_max_buf_size(chunk_size);
+    }
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+impl DiskFile for RingFile {
+    fn set_chunk_size(&mut self, chunk_size: usize) {
+        RingFile::set_chunk_size(self, chunk_size);
+    }
+}
+
+/// The backend chosen at startup, shared by every shard.
+#[derive(Clone, Default)]
+pub struct DiskIo {
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    ring: Option<Arc<Ring>>,
+    /// Files of at least this size are dropped from the page cache behind readers and
+    /// writers.
+    drop_page_cache_from: Option<u64>,
+    /// Health of the disk this handle reads and writes, when it is tracked.
+    health: Option<Arc<DiskHealth>>,
+    /// Opening a file for serving gives up after this long.
+    read_timeout: Option<Duration>,
+}
+
+impl fmt::Debug for DiskIo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiskIo")
+            .field("backend", &self.backend())
+            .field("drop_page_cache_from", &self.drop_page_cache_from)
+            .field("read_timeout", &self.read_timeout)
+            .finish()
+    }
+}
+
+impl DiskIo {
+    /// Sets up `backend`, falling back to tokio (with a warning) where io_uring is not
+    /// compiled in, not supported by the platform or not permitted by the kernel.
+    pub fn new(backend: DiskBackend) -> Self {
+        match backend {
+            DiskBackend::Tokio => Self::default(),
+            DiskBackend::IoUring => Self::io_uring(),
+        }
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn io_uring() -> Self {
+        match Ring::new() {
+            Ok(ring) => DiskIo {
+                ring: Some(Arc::new(ring)),
+                ..Self::default()
+            },
+            Err(e) => {
+                log::warn!("cannot set up io_uring, using tokio for disk I/O: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+    fn io_uring() -> Self {
+        log::warn!("io_uring is not available in this build, using tokio for disk I/O");
+        Self::default()
+    }
+
+    /// Keeps files of at least `size` bytes out of the OS page cache: pages are dropped as
+    /// soon as they have been read or written back. Only Linux honours the hint.
+    pub fn dropping_page_cache_from(mut self, size: Option<u64>) -> Self {
+        self.drop_page_cache_from = size;
+        self
+    }
+
+    /// Fails opening (and priming) files for serving that takes longer than `timeout`, e.g.
+    /// on a stalled disk, with a `TimedOut` error; so does reaching the request's deadline.
+    pub fn timing_out_reads_after(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// The same backend for a disk, tracking that disk's health.
+    pub fn on_disk(&self, health: DiskHealth) -> Self {
+        DiskIo {
+            health: Some(Arc::new(health)),
+            ..self.clone()
+        }
+    }
+
+    pub fn health(&self) -> Option<&Arc<DiskHealth>> {
+        self.health.as_ref()
+    }
+
+    /// Whether the disk can be used; untracked disks always can.
+    pub fn is_up(&self) -> bool {
+        self.health.as_ref().is_none_or(|health| health.is_up())
+    }
+
+    fn record<T>(&self, result: &IoResult<T>) {
+        if let Some(health) = &self.health {
+            health.record(result.as_ref().map(|_| ()));
+        }
+    }
+
+    /// Files of batch requests are kept out of the page cache whatever their size.
+    fn drops_page_cache(&self, size: u64) -> bool {
+        is_batch() || self.drop_page_cache_from.is_some_and(|from| size >= from)
+    }
+
+    /// The backend actually in use.
+    pub fn backend(&self) -> DiskBackend {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if self.ring.is_some() {
+            return DiskBackend::IoUring;
+        }
+        DiskBackend::Tokio
+    }
+
+    /// Opens `path` for reading `chunk_size` bytes at a time, with its length.
+    pub async fn open(&self, path: &Path, chunk_size: usize) -> IoResult<(Box<dyn DiskFile>, u64)> {
+        let opened: IoResult<_> = bounded(Stage::DiskRead, self.read_timeout, async {
+            let file = File::open(path).await?;
+            let len = file.metadata().await?.len();
+            let advise = match self.drops_page_cache(len) {
+                true => Some(file.try_clone().await?.into_std().await),
+                false => None,
+            };
+            Ok((file, len, advise))
+        })
+        .await
+        .and_then(|opened| opened);
+        // A missing file says nothing about the disk, nor does the client running out of
+        // time.
+        if !matches!(&opened, Err(e) if e.kind() == std::io::ErrorKind::NotFound || is_timeout(e)) {
+            self.record(&opened);
+        }
+        let (file, len, advise) = opened?;
+        let file = self.reader(file, chunk_size).await;
+        Ok(match advise {
+            Some(advise) => (Box::new(DropBehind::new(file, advise)), len),
+            None => (file, len),
+        })
+    }
+
+    /// Opens `path` like `open` and reads its first `chunk_size` bytes right away, so that
+    /// the caller can time the disk before it commits to serving from it.
+    pub async fn open_primed(
+        &self,
+        path: &Path,
+        chunk_size: usize,
+    ) -> IoResult<(Box<dyn DiskFile>, u64)> {
+        let (mut file, len) = self.open(path, chunk_size).await?;
+        let mut head = Vec::with_capacity(chunk_size.min(len as usize));
+        let read = bounded(
+            Stage::DiskRead,
+            self.read_timeout,
+            (&mut file).take(chunk_size as u64).read_to_end(&mut head),
+        )
+        .await?;
+        self.record(&read);
+        read.map_err(disk_error)?;
+        Ok((Box::new(Primed::new(head, file)), len))
+    }
+
+    async fn reader(&self, mut file: File, chunk_size: usize) -> Box<dyn DiskFile> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(ring) = &self.ring {
+            return Box::new(RingFile::new(
+                ring.clone(),
+                file.into_std().await,
+                chunk_size,
+            ));
+        }
+        file.set_max_buf_size(chunk_size);
+        Box::new(file)
+    }
+
+    /// Writes `stream` to a new file at `path`, returning its size. Errors of the disk
+    /// (rather than the stream) are marked as such; see `disks::is_disk_error`.
+    pub async fn write_stream(&self, stream: ObjectStream, path: &Path) -> IoResult<u64> {
+        // Errors from the stream are the store's, not the disk's.
+        let upstream_failed = Arc::new(AtomicBool::new(false));
+        let failed = upstream_failed.clone();
+        let stream = Box::pin(stream.inspect(move |chunk| {
+            if chunk.is_err() {
+                failed.store(true, Ordering::Relaxed);
+            }
+        }));
+        let written = self.write_file(stream, path).await;
+        if upstream_failed.load(Ordering::Relaxed) {
+            return written.map(|(size, _)| size);
+        }
+        self.record(&written);
+        let (size, file) = written.map_err(disk_error)?;
+        if self.drops_page_cache(size) {
+            // Dirty pages can't be dropped, and waiting for writeback would hold up the
+            // fetch, so the pages are dropped in the background.
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = write_back(&file).and_then(|()| drop_pages(&file, 0, 0)) {
+                    log::debug!("cannot drop written pages from the page cache: {}", e);
+                }
+            });
+        }
+        Ok(size)
+    }
+
+    async fn write_file(
+        &self,
+        mut stream: ObjectStream,
+        path: &Path,
+    ) -> IoResult<(u64, Arc<std::fs::File>)> {
+        #[cfg(feature = "fault-injection")]
+        inject(FaultPoint::DiskWrite).await?;
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(ring) = &self.ring {
+            return write_with_ring(ring, stream, path).await;
+        }
+        // Every write to a tokio `File` is a round trip to the blocking pool, so batch them.
+        let mut file = BufWriter::new(File::create(path).await?);
+        let mut size = 0u64;
+        while let Some(chunk) = stream.next().await {
+            let data: Bytes = chunk?;
+            size += data.len() as u64;
+            file.write_all(&data).await?;
+        }
+        file.flush().await?;
+        Ok((size, Arc::new(file.into_inner().into_std().await)))
+    }
+}
+
+/// Hints that the `len` bytes of `file` from `offset` (all of it for 0) won't be read again.
+#[cfg(target_os = "linux")]
+fn drop_pages(file: &std::fs::File, offset: u64, len: u64) -> IoResult<()> {
+    let (offset, len) = (offset as libc::off_t, len as libc::off_t);
+    // SAFETY: advice on a file descriptor we hold open.
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), offset, len, libc::POSIX_FADV_DONTNEED) } {
+        0 => Ok(()),
+        e => Err(std::io::Error::from_raw_os_error(e)),
+    }
+}
+
+/// Waits until the dirty pages of `file` are on disk, so that they can be dropped.
+#[cfg(target_os = "linux")]
+fn write_back(file: &std::fs::File) -> IoResult<()> {
+    let flags = libc::SYNC_FILE_RANGE_WAIT_BEFORE
+        | libc::SYNC_FILE_RANGE_WRITE
+        | libc::SYNC_FILE_RANGE_WAIT_AFTER;
+    // SAFETY: a sync of the whole of a file descriptor we hold open.
+    match unsafe { libc::sync_file_range(file.as_raw_fd(), 0, 0, flags) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_pages(_file: &std::fs::File, _offset: u64, _len: u64) -> IoResult<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_back(_file: &std::fs::File) -> IoResult<()> {
+    Ok(())
+}
+
+/// Drops the pages of a file being served once the reader is past them, every
+/// `DROP_BEHIND_WINDOW` bytes and when it is done.
+struct DropBehind {
+    inner: Box<dyn DiskFile>,
+    file: std::fs::File,
+    position: u64,
+    /// Pages before this offset have been dropped (or were skipped by a seek).
+    dropped_to: u64,
+}
+
+impl DropBehind {
+    fn new(inner: Box<dyn DiskFile>, file: std::fs::File) -> Self {
+        DropBehind {
+            inner,
+            file,
+            position: 0,
+            dropped_to: 0,
+        }
+    }
+
+    fn drop_behind(&mut self) {
+        if self.position > self.dropped_to {
+            let _ = drop_pages(&self.file, self.dropped_to, self.position - self.dropped_to);
+        }
+        self.dropped_to = self.position;
+    }
+}
+
+impl AsyncRead for DropBehind {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let this = &mut *self;
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = (buf.filled().len() - before) as u64;
+        this.position += read;
+        if read == 0 || this.position - this.dropped_to >= DROP_BEHIND_WINDOW {
+            this.drop_behind();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for DropBehind {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> IoResult<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<u64>> {
+        let this = &mut *self;
+        let position = ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+        if position != this.position {
+            this.drop_behind();
+            this.position = position;
+            this.dropped_to = position;
+        }
+        Poll::Ready(Ok(position))
+    }
+}
+
+impl DiskFile for DropBehind {
+    fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.inner.set_chunk_size(chunk_size);
+    }
+}
+
+impl Drop for DropBehind {
+    fn drop(&mut self) {
+        self.drop_behind();
+    }
+}
+
+/// A file whose first bytes were read ahead; they are served before the rest.
+struct Primed {
+    head: Vec<u8>,
+    /// Bytes of `head` already served.
+    served: usize,
+    inner: Box<dyn DiskFile>,
+}
+
+impl Primed {
+    fn new(head: Vec<u8>, inner: Box<dyn DiskFile>) -> Self {
+        Primed {
+            head,
+            served: 0,
+            inner,
+        }
+    }
+}
+
+impl AsyncRead for Primed {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let this = &mut *self;
+        if this.served < this.head.len() {
+            let n = buf.remaining().min(this.head.len() - this.served);
+            buf.put_slice(&this.head[this.served..this.served + n]);
+            this.served += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for Primed {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> IoResult<()> {
+        let this = &mut *self;
+        // The inner file is ahead of the reader by what is left of the head.
+        let position = match position {
+            SeekFrom::Current(offset) => {
+                SeekFrom::Current(offset - (this.head.len() - this.served) as i64)
+            }
+            position => position,
+        };
+        this.served = this.head.len();
+        Pin::new(&mut this.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+impl DiskFile for Primed {
+    fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.inner.set_chunk_size(chunk_size);
+    }
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+async fn write_with_ring(
+    ring: &Ring,
+    mut stream: ObjectStream,
+    path: &Path,
+) -> IoResult<(u64, Arc<std::fs::File>)> {
+    let file = Arc::new(File::create(path).await?.into_std().await);
+    let mut size = 0u64;
+    let mut batch = Vec::with_capacity(WRITE_BATCH);
+    loop {
+        let chunk = stream.next().await.transpose()?;
+        if let Some(data) = &chunk {
+            batch.extend_from_slice(data);
+            if batch.len() < WRITE_BATCH {
+                continue;
+            }
+        }
+        // Short writes hand back the buffer; retry with what is left of it.
+        while !batch.is_empty() {
+            let (written, mut rest) = ring.write(file.clone(), size, batch).await;
+            let written = written?;
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            size += written as u64;
+            rest.drain(..written);
+            batch = rest;
+        }
+        if chunk.is_none() {
+            return Ok((size, file));
+        }
+    }
+}
@@ -0,0 +1,311 @@
+// disks.rs
+//! Cache directories on several devices, so that one mount point doesn't cap throughput.
+//! Shards are spread over the directories round-robin; objects hash to shards, so they are
+//! striped over the disks, and each directory's capacity is split between its shards. A
+//! disk that keeps failing, or whose file system runs short of space, is left out of new
+//! fetches until a probe succeeds on it again.
+use log::{info, warn};
+use serde::Deserialize;
+use std::fmt;
+use std::io::{self, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Consecutive failed operations after which a disk is marked down.
+pub const FAILURES_BEFORE_DOWN: u32 = 3;
+
+/// How often disks that are down are probed.
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Free bytes a full disk needs before a probe brings it back, unless a reserve is set.
+pub const MIN_FREE_SPACE: u64 = 64 * 1024 * 1024;
+
+/// How often the file systems of the cache directories are checked against the reserve.
+pub const FREE_SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// File the probe writes and removes again in a cache directory.
+pub const PROBE_FILE: &str = ".istziio-probe";
+
+/// Whether a disk takes new entries, and if not why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskStatus {
+    Up,
+    /// Down after `FAILURES_BEFORE_DOWN` I/O errors in a row.
+    Failing,
+    /// Down after running out of space.
+    Full,
+}
+
+impl fmt::Display for DiskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DiskStatus::Up => "up",
+            DiskStatus::Failing => "failing",
+            DiskStatus::Full => "full",
+        })
+    }
+}
+
+/// An error of the cache disk itself, as opposed to one from the object store that was
+/// being written to it.
+#[derive(Debug)]
+struct DiskError(io::Error);
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cache disk: {}", self.0)
+    }
+}
+
+impl std::error::Error for DiskError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Marks `e` as coming from the cache disk, keeping its kind.
+pub fn disk_error(e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), DiskError(e))
+}
+
+/// Whether `e` came from the cache disk rather than the object store.
+pub fn is_disk_error(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<DiskError>())
+}
+
+/// A cache directory, normally the mount point of a device of its own.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CacheDirConfig {
+    pub path: String,
+    /// Bytes of cached objects kept in this directory.
+    pub max_size: u64,
+}
+
+/// Parses `path:max_size`, as in `ISTZIIO_CACHE_DIRS`.
+impl FromStr for CacheDirConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, max_size) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected path:max_size, got '{}'", s))?;
+        let max_size = max_size
+            .parse()
+            .map_err(|_| format!("invalid size '{}' for {}", max_size, path))?;
+        Ok(CacheDirConfig {
+            path: path.to_string(),
+            max_size,
+        })
+    }
+}
+
+/// Whether a disk is usable, judged by the outcome of recent operations on it.
+#[derive(Debug)]
+pub struct DiskHealth {
+    path: PathBuf,
+    status: Mutex<DiskStatus>,
+    consecutive_failures: AtomicU32,
+    /// Failed operations since startup.
+    errors: AtomicU64,
+    /// Bytes of the file system kept free for other processes.
+    reserve: Option<u64>,
+}
+
+impl DiskHealth {
+    pub fn new(path: PathBuf) -> Self {
+        DiskHealth {
+            path,
+            status: Mutex::new(DiskStatus::Up),
+            consecutive_failures: AtomicU32::new(0),
+            errors: AtomicU64::new(0),
+            reserve: None,
+        }
+    }
+
+    /// Keeps `reserve` bytes of the disk's file system free: `free_space_deficit` marks
+    /// the disk full while less is free, and a probe only brings it back with that much.
+    pub fn reserving(mut self, reserve: Option<u64>) -> Self {
+        self.reserve = reserve;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn status(&self) -> DiskStatus {
+        *self.status.lock().unwrap()
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.status() == DiskStatus::Up
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Counts an operation on the disk. Running out of space marks it down right away,
+    /// other errors after `FAILURES_BEFORE_DOWN` in a row. Only a probe brings it back.
+    pub fn record(&self, result: Result<(), &io::Error>) {
+        let e = match result {
+            Ok(()) => {
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                return;
+            }
+            Err(e) => e,
+        };
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if e.kind() == io::ErrorKind::StorageFull {
+            self.mark_down(DiskStatus::Full, e);
+        } else if failures >= FAILURES_BEFORE_DOWN {
+            self.mark_down(DiskStatus::Failing, e);
+        }
+    }
+
+    fn mark_down(&self, status: DiskStatus, reason: &dyn fmt::Display) {
+        let mut current = self.status.lock().unwrap();
+        if *current == DiskStatus::Up {
+            *current = status;
+            warn!(
+                "Cache disk {} is {} ({}), taking it out of use",
+                self.path.display(),
+                status,
+                reason
+            );
+        }
+    }
+
+    /// Bytes the disk's file system is short of the reserve, marking the disk full while
+    /// it is short; always 0 without a reserve.
+    pub fn free_space_deficit(&self) -> IoResult<u64> {
+        let reserve = match self.reserve {
+            Some(reserve) => reserve,
+            None => return Ok(0),
+        };
+        let free = free_space(&self.path)?;
+        let deficit = reserve.saturating_sub(free);
+        if deficit > 0 {
+            self.mark_down(
+                DiskStatus::Full,
+                &format_args!("{} bytes free, {} reserved", free, reserve),
+            );
+        }
+        Ok(deficit)
+    }
+
+    /// Writes, reads back and removes a small file on the disk, marking it up if that
+    /// works (and, if it was full, if it has its reserve or `MIN_FREE_SPACE` free again).
+    /// Returns whether the disk is up.
+    pub async fn probe(&self) -> bool {
+        let needs_space = match self.status() {
+            DiskStatus::Full => Some(self.reserve.unwrap_or(MIN_FREE_SPACE)),
+            _ => None,
+        };
+        match probe(&self.path, needs_space).await {
+            Ok(()) => {
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                let mut status = self.status.lock().unwrap();
+                if *status != DiskStatus::Up {
+                    *status = DiskStatus::Up;
+                    info!("Cache disk {} is back up", self.path.display());
+                }
+                true
+            }
+            Err(e) => {
+                warn!("Probing cache disk {} failed: {}", self.path.display(), e);
+                false
+            }
+        }
+    }
+}
+
+async fn probe(dir: &Path, needs_space: Option<u64>) -> IoResult<()> {
+    if let Some(needs_space) = needs_space {
+        let free = free_space(dir)?;
+        if free < needs_space {
+            return Err(io::Error::other(format!("only {} bytes free", free)));
+        }
+    }
+    let path = dir.join(PROBE_FILE);
+    let contents = chrono::Utc::now().to_rfc3339();
+    tokio::fs::write(&path, &contents).await?;
+    let read = tokio::fs::read(&path).await;
+    let _ = tokio::fs::remove_file(&path).await;
+    if read? != contents.as_bytes() {
+        return Err(io::Error::other("probe file read back differently"));
+    }
+    Ok(())
+}
+
+/// Bytes available to unprivileged writers on the file system holding `dir`.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // the statvfs field types vary between platforms
+pub fn free_space(dir: &Path) -> IoResult<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: path is NUL-terminated and stats is only read after statvfs filled it in.
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stats = unsafe { stats.assume_init() };
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_dir: &Path) -> IoResult<u64> {
+    Ok(u64::MAX)
+}
+
+/// Size in bytes of the file system holding `dir`.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // the statvfs field types vary between platforms
+pub fn capacity(dir: &Path) -> IoResult<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: path is NUL-terminated and stats is only read after statvfs filled it in.
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stats = unsafe { stats.assume_init() };
+    Ok(stats.f_blocks as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn capacity(_dir: &Path) -> IoResult<u64> {
+    Ok(u64::MAX)
+}
+
+/// Budget of each of `shard_count` shards when `max_size` bytes are spread over disks of
+/// the given capacities, shard `i` living on disk `i % capacities.len()`. Each disk gets a
+/// share of `max_size` proportional to its capacity, split evenly between its shards.
+pub fn stripe_budgets(capacities: &[u64], shard_count: u64, max_size: u64) -> Vec<u64> {
+    let disk_count = capacities.len() as u64;
+    let total: u128 = capacities
+        .iter()
+        .map(|&capacity| u128::from(capacity))
+        .sum();
+    let mut shares: Vec<u64> = capacities
+        .iter()
+        .map(|&capacity| match total {
+            0 => max_size / disk_count,
+            _ => (u128::from(max_size) * u128::from(capacity) / total) as u64,
+        })
+        .collect();
+    // Rounding leaves a few bytes over; the first disk takes them so the budgets add up.
+    shares[0] += max_size - shares.iter().sum::<u64>();
+    (0..shard_count)
+        .map(|index| {
+            let disk = index % disk_count;
+            let shards_on_disk =
+                shard_count / disk_count + u64::from(disk < shard_count % disk_count);
+            crate::cache::shard_budget(shares[disk as usize], shards_on_disk, index / disk_count)
+        })
+        .collect()
+}
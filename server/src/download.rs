@@ -0,0 +1,222 @@
+// download.rs
+//! Objects that clients read while they are still being written to the cache directory,
+//! so that a miss on a large object doesn't wait for the whole download to land on disk.
+use bytes::Bytes;
+use rocket::futures::{stream, StreamExt};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::sync::watch;
+
+use crate::storage::storage_connector::ObjectStream;
+
+/// Bytes written between two progress updates; readers see data in steps of this size.
+const PUBLISH_EVERY: usize = 256 * 1024;
+
+/// Largest chunk a reader sends at once.
+const READ_CHUNK: u64 = 64 * 1024;
+
+/// How far the download of an object has got, in bytes on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    Writing(u64),
+    Done(u64),
+    Failed,
+}
+
+/// What happens to a download once every client reading it has gone away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DisconnectPolicy {
+    /// The object is still downloaded and cached, in the background.
+    #[default]
+    Fill,
+    /// The download stops and the bytes written so far are dropped.
+    Abort,
+}
+
+impl FromStr for DisconnectPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fill" => Ok(DisconnectPolicy::Fill),
+            "abort" => Ok(DisconnectPolicy::Abort),
+            _ => Err(format!("unknown client disconnect policy '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for DisconnectPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A download stopped because nobody was reading it any more.
+#[derive(Debug)]
+struct Abandoned;
+
+impl fmt::Display for Abandoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("every client reading the download went away")
+    }
+}
+
+impl std::error::Error for Abandoned {}
+
+/// Whether a download failed with `e` because its readers went away.
+pub fn is_abandoned(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<Abandoned>())
+}
+
+/// An object being written to disk, which any number of clients can read as it lands.
+#[derive(Debug, Clone)]
+pub struct Download {
+    path: PathBuf,
+    progress: watch::Receiver<Progress>,
+    /// Reader streams not dropped yet.
+    readers: Arc<AtomicUsize>,
+}
+
+/// The writing end of a `Download`.
+pub struct DownloadWriter {
+    file: BufWriter<File>,
+    progress: watch::Sender<Progress>,
+    readers: Arc<AtomicUsize>,
+    on_disconnect: DisconnectPolicy,
+}
+
+/// Counts a reader stream for as long as it lives.
+struct ReaderGuard(Arc<AtomicUsize>);
+
+impl ReaderGuard {
+    fn new(readers: &Arc<AtomicUsize>) -> Self {
+        readers.fetch_add(1, Ordering::SeqCst);
+        ReaderGuard(readers.clone())
+    }
+}
+
+impl Drop for ReaderGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Download {
+    /// Creates (or truncates) the file at `path` for a new download.
+    pub async fn create(path: &Path) -> IoResult<(Download, DownloadWriter)> {
+        let file = File::create(path).await?;
+        let (sender, progress) = watch::channel(Progress::Writing(0));
+        let readers = Arc::new(AtomicUsize::new(0));
+        let download = Download {
+            path: path.to_path_buf(),
+            progress,
+            readers: readers.clone(),
+        };
+        let writer = DownloadWriter {
+            file: BufWriter::with_capacity(PUBLISH_EVERY, file),
+            progress: sender,
+            readers,
+            on_disconnect: DisconnectPolicy::default(),
+        };
+        Ok((download, writer))
+    }
+
+    pub fn progress(&self) -> Progress {
+        *self.progress.borrow()
+    }
+
+    /// Reader streams still open.
+    pub fn readers(&self) -> usize {
+        self.readers.load(Ordering::SeqCst)
+    }
+
+    /// Streams the object from the start, waiting for bytes that haven't landed yet. The
+    /// file is opened right away, so the stream keeps reading these bytes even if the file
+    /// is replaced (compressed, encrypted) or evicted afterwards.
+    pub async fn reader(&self) -> IoResult<ObjectStream> {
+        let file = File::open(&self.path).await?;
+        let guard = ReaderGuard::new(&self.readers);
+        let state = (file, self.progress.clone(), 0u64, guard);
+        Ok(Box::pin(stream::try_unfold(
+            state,
+            |(mut file, mut progress, read, guard)| async move {
+                loop {
+                    let (written, done) = match *progress.borrow_and_update() {
+                        Progress::Writing(written) => (written, false),
+                        Progress::Done(written) => (written, true),
+                        Progress::Failed => return Err(io::Error::other("download failed")),
+                    };
+                    if read < written {
+                        let mut chunk = vec![0; (written - read).min(READ_CHUNK) as usize];
+                        file.read_exact(&mut chunk).await?;
+                        let read = read + chunk.len() as u64;
+                        return Ok(Some((Bytes::from(chunk), (file, progress, read, guard))));
+                    }
+                    if done {
+                        return Ok(None);
+                    }
+                    if progress.changed().await.is_err() {
+                        return Err(io::Error::other("download abandoned"));
+                    }
+                }
+            },
+        )))
+    }
+}
+
+impl DownloadWriter {
+    /// Sets what `write` does when no reader is left before the download is done.
+    pub fn on_disconnect(mut self, policy: DisconnectPolicy) -> Self {
+        self.on_disconnect = policy;
+        self
+    }
+
+    /// Writes `stream` to the file, letting readers know after every `PUBLISH_EVERY` bytes.
+    /// Returns the size of the object. Under `DisconnectPolicy::Abort`, fails with an
+    /// error `is_abandoned` recognizes once no reader is left, dropping `stream`.
+    pub async fn write(mut self, mut stream: ObjectStream) -> IoResult<u64> {
+        let mut written = 0u64;
+        let mut unpublished = 0;
+        let result = async {
+            while let Some(chunk) = stream.next().await {
+                if self.on_disconnect == DisconnectPolicy::Abort
+                    && self.readers.load(Ordering::SeqCst) == 0
+                {
+                    return Err(io::Error::new(io::ErrorKind::ConnectionAborted, Abandoned));
+                }
+                let data = chunk?;
+                self.file.write_all(&data).await?;
+                written += data.len() as u64;
+                unpublished += data.len();
+                if unpublished >= PUBLISH_EVERY {
+                    self.file.flush().await?;
+                    self.progress.send_replace(Progress::Writing(written));
+                    unpublished = 0;
+                }
+            }
+            self.file.flush().await
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                self.progress.send_replace(Progress::Done(written));
+                Ok(written)
+            }
+            Err(e) => {
+                self.progress.send_replace(Progress::Failed);
+                Err(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// egress.rs
+//! Bandwidth shaping on the bodies the node serves. Each response can be held to a rate
+//! of its own, and all responses of a priority class to a rate they share, so that one
+//! client exporting a whole prefix can't take the NIC from latency-sensitive readers.
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::response::Body;
+use rocket::{Request, Response};
+use serde::Deserialize;
+use std::future::Future;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, SeekFrom};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::time::Sleep;
+
+use crate::priority::{RequestPriority, PRIORITY_HEADER};
+use crate::rebalance::Throttle;
+
+/// Routes whose bodies are shaped: `/s3` and the S3-compatible `GetObject`.
+const SHAPED_ROUTES: [&str; 2] = ["get_file", "get_object"];
+
+/// Egress limits in bytes per second; unset ones don't apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EgressConfig {
+    /// Cap on each response.
+    pub per_request_bytes_per_sec: Option<u64>,
+    /// Cap shared by all interactive responses.
+    pub interactive_bytes_per_sec: Option<u64>,
+    /// Cap shared by all batch responses.
+    pub batch_bytes_per_sec: Option<u64>,
+}
+
+/// Paces the responses of a class together: a response sends its next chunk only once
+/// the chunks sent before it, by any response of the class, are due at the class rate.
+struct SharedPacer {
+    bytes_per_sec: u64,
+    next_free: Mutex<Instant>,
+}
+
+impl SharedPacer {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Accounts for `bytes` just sent and says how long to wait before sending more.
+    fn delay(&self, bytes: u64) -> Duration {
+        let now = Instant::now();
+        let mut next_free = self.next_free.lock().unwrap();
+        let start = (*next_free).max(now);
+        *next_free = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        next_free.saturating_duration_since(now)
+    }
+}
+
+/// What one priority class is sending.
+struct ClassUsage {
+    limit: Option<u64>,
+    pacer: Option<SharedPacer>,
+    bytes: AtomicU64,
+    active: AtomicU64,
+    /// Bytes sent in the current and the previous whole second since `EgressShaper::started`.
+    window: Mutex<(u64, u64, u64)>,
+}
+
+impl ClassUsage {
+    fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            pacer: limit.map(SharedPacer::new),
+            bytes: AtomicU64::new(0),
+            active: AtomicU64::new(0),
+            window: Mutex::new((0, 0, 0)),
+        }
+    }
+
+    fn record(&self, bytes: u64, second: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let mut window = self.window.lock().unwrap();
+        roll(&mut window, second);
+        window.1 += bytes;
+    }
+
+    fn rate(&self, second: u64) -> u64 {
+        let mut window = self.window.lock().unwrap();
+        roll(&mut window, second);
+        window.2
+    }
+}
+
+/// Moves `window` on to `second`.
+fn roll(window: &mut (u64, u64, u64), second: u64) {
+    if window.0 == second {
+        return;
+    }
+    window.2 = if window.0 + 1 == second { window.1 } else { 0 };
+    *window = (second, 0, window.2);
+}
+
+/// Egress of one priority class, as `/stats` shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EgressUsage {
+    /// Bytes sent in the last whole second.
+    pub bytes_per_sec: u64,
+    pub limit: Option<u64>,
+    /// Shaped responses still sending.
+    pub active: u64,
+    /// Bytes sent since startup.
+    pub total_bytes: u64,
+}
+
+/// Shapes the bodies of data responses under an `EgressConfig`. Attached as a fairing and
+/// managed as Rocket state when `[egress]` is configured.
+pub struct EgressShaper {
+    per_request: Option<u64>,
+    interactive: ClassUsage,
+    batch: ClassUsage,
+    started: Instant,
+}
+
+impl EgressShaper {
+    pub fn new(config: EgressConfig) -> Self {
+        Self {
+            per_request: config.per_request_bytes_per_sec,
+            interactive: ClassUsage::new(config.interactive_bytes_per_sec),
+            batch: ClassUsage::new(config.batch_bytes_per_sec),
+            started: Instant::now(),
+        }
+    }
+
+    fn class(&self, priority: RequestPriority) -> &ClassUsage {
+        match priority {
+            RequestPriority::Interactive => &self.interactive,
+            RequestPriority::Batch => &self.batch,
+        }
+    }
+
+    fn second(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    /// Accounts for `bytes` sent by a response of `priority` and says how long it waits
+    /// before sending more.
+    fn sent(
+        &self,
+        priority: RequestPriority,
+        throttle: Option<&mut Throttle>,
+        bytes: u64,
+    ) -> Duration {
+        let class = self.class(priority);
+        class.record(bytes, self.second());
+        let own = throttle.map_or(Duration::ZERO, |throttle| throttle.delay(bytes));
+        let shared = class
+            .pacer
+            .as_ref()
+            .map_or(Duration::ZERO, |pacer| pacer.delay(bytes));
+        own.max(shared)
+    }
+
+    pub fn usage(&self, priority: RequestPriority) -> EgressUsage {
+        let class = self.class(priority);
+        EgressUsage {
+            bytes_per_sec: class.rate(self.second()),
+            limit: class.limit,
+            active: class.active.load(Ordering::Relaxed),
+            total_bytes: class.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// `body`, sent no faster than the limits of its class allow.
+    pub fn shape<'r>(
+        self: &Arc<Self>,
+        priority: RequestPriority,
+        body: Body<'r>,
+    ) -> ShapedBody<'r> {
+        self.class(priority).active.fetch_add(1, Ordering::Relaxed);
+        ShapedBody {
+            inner: body,
+            shaper: self.clone(),
+            priority,
+            throttle: self.per_request.map(Throttle::new),
+            pause: None,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let line = |name: &str, usage: EgressUsage| {
+            format!(
+                "Egress ({}): {} B/s of {}, {} responses, {} bytes sent\n",
+                name,
+                usage.bytes_per_sec,
+                usage
+                    .limit
+                    .map_or_else(|| String::from("unlimited"), |limit| limit.to_string()),
+                usage.active,
+                usage.total_bytes
+            )
+        };
+        line("interactive", self.usage(RequestPriority::Interactive))
+            + &line("batch", self.usage(RequestPriority::Batch))
+    }
+}
+
+/// A response body read no faster than its `EgressShaper` allows.
+pub struct ShapedBody<'r> {
+    inner: Body<'r>,
+    shaper: Arc<EgressShaper>,
+    priority: RequestPriority,
+    throttle: Option<Throttle>,
+    /// Wait before the next chunk.
+    pause: Option<Pin<Box<Sleep>>>,
+}
+
+impl AsyncRead for ShapedBody<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        if let Some(pause) = self.pause.as_mut() {
+            if pause.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.pause = None;
+        }
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        if read > 0 {
+            let this = &mut *self;
+            let delay = this
+                .shaper
+                .sent(this.priority, this.throttle.as_mut(), read);
+            if !delay.is_zero() {
+                this.pause = Some(Box::pin(tokio::time::sleep(delay)));
+            }
+        }
+        poll
+    }
+}
+
+/// Shaped bodies keep the size of the body they wrap, so Rocket never seeks them.
+impl AsyncSeek for ShapedBody<'_> {
+    fn start_seek(self: Pin<&mut Self>, _: SeekFrom) -> IoResult<()> {
+        Err(IoError::new(
+            ErrorKind::Unsupported,
+            "shaped bodies can't seek",
+        ))
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<IoResult<u64>> {
+        Poll::Ready(Err(IoError::new(
+            ErrorKind::Unsupported,
+            "shaped bodies can't seek",
+        )))
+    }
+}
+
+impl Drop for ShapedBody<'_> {
+    fn drop(&mut self) {
+        self.shaper
+            .class(self.priority)
+            .active
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Wraps the bodies of data responses in a `ShapedBody`.
+pub struct EgressFairing(pub Arc<EgressShaper>);
+
+#[rocket::async_trait]
+impl Fairing for EgressFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Egress shaping",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let shaped = req
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .is_some_and(|name| SHAPED_ROUTES.contains(&name));
+        if !shaped || res.body().is_none() {
+            return;
+        }
+        let priority = req
+            .headers()
+            .get_one(PRIORITY_HEADER)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        // The size is kept, so that the response still carries its `Content-Length`.
+        let size = res.body_mut().size().await;
+        let max_chunk_size = res.body().max_chunk_size();
+        let body = self.0.shape(priority, res.body_mut().take());
+        match size {
+            Some(size) => res.set_sized_body(size, body),
+            None => res.set_streamed_body(body),
+        }
+        res.set_max_chunk_size(max_chunk_size);
+    }
+}
@@ -0,0 +1,128 @@
+// embedded.rs
+//! The cache as a library, for an execution engine that keeps it inside its worker
+//! process: no Rocket, no Redis and no peers. Misses are read through the connector and
+//! cached on local disk as on a node, with the same eviction, admission and tiers.
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use tokio_util::io::StreamReader;
+
+use crate::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult, ObjectReader,
+};
+use crate::chunk::ByteRange;
+use crate::metadata::InProcessStore;
+use crate::storage::storage_connector::StorageConnector;
+
+/// Shards of a `Cache::new`; each is locked on its own.
+pub const DEFAULT_SHARDS: u64 = 4;
+
+/// A cache of the objects behind one connector, kept in `cache_dir`.
+pub struct Cache {
+    cache: ConcurrentDiskCache,
+    connector: Arc<dyn StorageConnector + Send + Sync>,
+}
+
+impl Cache {
+    pub fn new(
+        cache_dir: impl Into<PathBuf>,
+        max_size: u64,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+    ) -> Self {
+        Self::with_options(
+            cache_dir,
+            max_size,
+            DEFAULT_SHARDS,
+            connector,
+            CacheOptions::default(),
+        )
+    }
+
+    /// A cache of `shards` shards, each given an equal part of `max_size`, with the
+    /// knobs a node takes from its config.
+    pub fn with_options(
+        cache_dir: impl Into<PathBuf>,
+        max_size: u64,
+        shards: u64,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+        options: CacheOptions,
+    ) -> Self {
+        let shards = shards.clamp(1, max_size.max(1));
+        Self {
+            cache: ConcurrentDiskCache::new(
+                cache_dir.into(),
+                max_size,
+                shards,
+                // Stands in for Redis: this process owns every key.
+                Box::new(InProcessStore::new(0)),
+                options,
+            ),
+            connector,
+        }
+    }
+
+    /// The cache underneath, e.g. for its stats.
+    pub fn inner(&self) -> &ConcurrentDiskCache {
+        &self.cache
+    }
+
+    /// Reads `key`, from the cache or, on a miss, from the backing store.
+    pub async fn get(&self, key: &str) -> IoResult<ObjectReader> {
+        self.cache
+            .get_file(
+                PathBuf::from(key),
+                self.connector.clone(),
+                GetFileOptions::default(),
+            )
+            .await
+            .into_reader()
+    }
+
+    /// Reads the bytes of `key` in `range`.
+    pub async fn get_range(&self, key: &str, range: ByteRange) -> IoResult<ObjectReader> {
+        let options = GetFileOptions {
+            range: Some(range),
+            ..GetFileOptions::default()
+        };
+        let result = self
+            .cache
+            .get_file(PathBuf::from(key), self.connector.clone(), options)
+            .await;
+        if let GetFileResult::PassThrough(_) | GetFileResult::Streaming(_) = result {
+            // Not cached as a whole; only the range is worth fetching.
+            drop(result);
+            let object = self.connector.fetch_range(key, range).await?;
+            return Ok(Box::new(StreamReader::new(object.stream)));
+        }
+        result.into_range_reader(range).await
+    }
+
+    /// Writes `body` as `key` to the backing store, then caches it in place of any older
+    /// copy. Returns the object's size.
+    pub async fn put(&self, key: &str, body: impl AsyncRead + Send + Unpin) -> IoResult<u64> {
+        let staged = self.cache.stage_reader(key, body).await?;
+        let size = staged.size();
+        let uploaded = match staged.open().await {
+            Ok(plaintext) => self.connector.put_stream(key, plaintext, size).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = uploaded {
+            staged.discard().await;
+            return Err(e);
+        }
+        self.cache.invalidate(key).await;
+        if size <= self.cache.max_cacheable_size(key).await {
+            self.cache.ingest(key, staged).await?;
+        } else {
+            staged.discard().await;
+        }
+        Ok(size)
+    }
+
+    /// Drops `key` from the cache; the next `get` reads it from the backing store again.
+    /// Returns the bytes freed.
+    pub async fn invalidate(&self, key: &str) -> u64 {
+        self.cache.invalidate(key).await
+    }
+}
@@ -0,0 +1,279 @@
+// embedded_redis.rs
+//! The node's own `redis-server`, run as a child process so that a cache node is one unit
+//! to deploy. The node writes its `redis.conf`, starts the server before anything talks
+//! to Redis, pings it, restarts it when it exits or stops answering, and stops it when
+//! the node shuts down.
+use log::{info, warn};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::io::Result as IoResult;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::redis::RedisMode;
+
+/// How long a stopping server may take to exit before it is killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bound on one health check.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbeddedRedisConfig {
+    /// The `redis-server` executable, looked up in `PATH` unless it is a path.
+    pub binary: String,
+    /// Directory of the generated `redis.conf`, the cluster state file and the log.
+    pub dir: String,
+    /// Further `redis.conf` lines, e.g. `maxmemory 1gb`.
+    pub extra_config: Vec<String>,
+    /// How often the server is pinged.
+    pub health_check_interval_ms: u64,
+    /// Failed pings in a row after which a server that is still running is restarted.
+    pub max_failed_health_checks: u32,
+    /// Wait before restarting a server that exited.
+    pub restart_delay_ms: u64,
+}
+
+impl Default for EmbeddedRedisConfig {
+    fn default() -> Self {
+        EmbeddedRedisConfig {
+            binary: String::from("redis-server"),
+            dir: String::from("./redis"),
+            extra_config: Vec::new(),
+            health_check_interval_ms: 1000,
+            max_failed_health_checks: 5,
+            restart_delay_ms: 1000,
+        }
+    }
+}
+
+impl EmbeddedRedisConfig {
+    /// The `redis.conf` of a server listening on `port`, a cluster node unless `mode`
+    /// is standalone.
+    pub fn redis_conf(&self, port: u16, mode: RedisMode) -> String {
+        let mut conf = format!(
+            "port {}\n\
+             appendonly no\n\
+             save \"\"\n\
+             protected-mode no\n\
+             logfile redis.log\n",
+            port
+        );
+        if mode == RedisMode::Cluster {
+            conf.push_str(
+                "cluster-enabled yes\n\
+                 cluster-config-file nodes.conf\n\
+                 cluster-node-timeout 5000\n",
+            );
+        }
+        for line in &self.extra_config {
+            conf.push_str(line);
+            conf.push('\n');
+        }
+        conf
+    }
+}
+
+/// The state of the server, as `/stats` shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbeddedRedisStatus {
+    /// Process id of the running server; `None` between a crash and its restart.
+    pub pid: Option<u32>,
+    /// Whether the last health check got an answer.
+    pub healthy: bool,
+    /// Restarts since the node started.
+    pub restarts: u64,
+}
+
+struct Supervised {
+    config: EmbeddedRedisConfig,
+    port: u16,
+    conf_path: PathBuf,
+    child: Mutex<Option<Child>>,
+    healthy: AtomicBool,
+    restarts: AtomicU64,
+}
+
+impl Supervised {
+    fn spawn(&self) -> IoResult<()> {
+        let child = Command::new(&self.config.binary)
+            .arg(&self.conf_path)
+            .current_dir(&self.config.dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()?;
+        info!(
+            "Started {} (pid {}) on port {}",
+            self.config.binary,
+            child.id(),
+            self.port
+        );
+        *self.child.lock().unwrap() = Some(child);
+        Ok(())
+    }
+
+    fn ping(&self) -> bool {
+        let attempt = || -> redis::RedisResult<String> {
+            let client = redis::Client::open(format!("redis://127.0.0.1:{}", self.port))?;
+            let mut conn = client.get_connection_with_timeout(PING_TIMEOUT)?;
+            conn.set_read_timeout(Some(PING_TIMEOUT))?;
+            redis::cmd("PING").query(&mut conn)
+        };
+        attempt().is_ok()
+    }
+
+    /// Asks the server to exit, and kills it if it hasn't after `STOP_TIMEOUT`.
+    fn terminate(&self) {
+        let Some(mut child) = self.child.lock().unwrap().take() else {
+            return;
+        };
+        if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+            // SAFETY: the child has not been waited for, so its pid is still its own.
+            unsafe {
+                libc::kill(pid, libc::SIGTERM);
+            }
+        }
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while Instant::now() < deadline {
+            if !matches!(child.try_wait(), Ok(None)) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        warn!("{} did not stop in time; killing it", self.config.binary);
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    /// Pings the server every `health_check_interval_ms` until `stop` is dropped,
+    /// restarting it when it exited or stopped answering.
+    fn supervise(&self, stop: mpsc::Receiver<()>) {
+        let interval = Duration::from_millis(self.config.health_check_interval_ms);
+        let restart_delay = Duration::from_millis(self.config.restart_delay_ms);
+        let mut failed = 0;
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+            let running = match self.child.lock().unwrap().as_mut() {
+                Some(child) => match child.try_wait() {
+                    Ok(Some(status)) => {
+                        warn!("{} exited with {}", self.config.binary, status);
+                        false
+                    }
+                    _ => true,
+                },
+                // The last restart failed.
+                None => false,
+            };
+            if !running {
+                self.healthy.store(false, Ordering::Relaxed);
+                self.child.lock().unwrap().take();
+                if !matches!(
+                    stop.recv_timeout(restart_delay),
+                    Err(RecvTimeoutError::Timeout)
+                ) {
+                    return;
+                }
+                if let Err(e) = self.spawn() {
+                    warn!("Failed to restart {}: {}", self.config.binary, e);
+                }
+                self.restarts.fetch_add(1, Ordering::Relaxed);
+                failed = 0;
+                continue;
+            }
+            if self.ping() {
+                failed = 0;
+                self.healthy.store(true, Ordering::Relaxed);
+                continue;
+            }
+            failed += 1;
+            self.healthy.store(false, Ordering::Relaxed);
+            if failed >= self.config.max_failed_health_checks {
+                warn!(
+                    "{} missed {} health checks in a row; restarting it",
+                    self.config.binary, failed
+                );
+                // Seen as exited, and restarted, on the next check.
+                if let Some(child) = self.child.lock().unwrap().as_mut() {
+                    let _ = child.kill();
+                }
+                failed = 0;
+            }
+        }
+    }
+}
+
+/// A running `redis-server`, stopped when dropped.
+pub struct EmbeddedRedis {
+    shared: Arc<Supervised>,
+    stop: Option<Sender<()>>,
+    supervisor: Option<JoinHandle<()>>,
+}
+
+impl EmbeddedRedis {
+    /// Writes the server's `redis.conf` into `config.dir`, starts it on `port` and starts
+    /// supervising it.
+    pub fn start(config: EmbeddedRedisConfig, port: u16, mode: RedisMode) -> IoResult<Self> {
+        std::fs::create_dir_all(&config.dir)?;
+        let conf_path = Path::new(&config.dir).canonicalize()?.join("redis.conf");
+        std::fs::write(&conf_path, config.redis_conf(port, mode))?;
+        let shared = Arc::new(Supervised {
+            config,
+            port,
+            conf_path,
+            child: Mutex::new(None),
+            healthy: AtomicBool::new(false),
+            restarts: AtomicU64::new(0),
+        });
+        shared.spawn()?;
+        let (stop, stopped) = mpsc::channel();
+        let supervised = shared.clone();
+        let supervisor = std::thread::Builder::new()
+            .name(String::from("redis-supervisor"))
+            .spawn(move || supervised.supervise(stopped))?;
+        Ok(EmbeddedRedis {
+            shared,
+            stop: Some(stop),
+            supervisor: Some(supervisor),
+        })
+    }
+
+    pub fn status(&self) -> EmbeddedRedisStatus {
+        EmbeddedRedisStatus {
+            pid: self.shared.child.lock().unwrap().as_ref().map(Child::id),
+            healthy: self.shared.healthy.load(Ordering::Relaxed),
+            restarts: self.shared.restarts.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let status = self.status();
+        format!(
+            "Embedded Redis: {}, {}, {} restarts\n",
+            status
+                .pid
+                .map_or_else(|| String::from("not running"), |pid| format!("pid {}", pid)),
+            match status.healthy {
+                true => "healthy",
+                false => "unhealthy",
+            },
+            status.restarts
+        )
+    }
+}
+
+impl Drop for EmbeddedRedis {
+    fn drop(&mut self) {
+        // Wakes the supervisor, which exits before the server is stopped.
+        self.stop.take();
+        if let Some(supervisor) = self.supervisor.take() {
+            let _ = supervisor.join();
+        }
+        self.shared.terminate();
+        info!("Stopped {}", self.shared.config.binary);
+    }
+}
@@ -0,0 +1,376 @@
+// encryption.rs
+use bytes::Bytes;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM};
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::futures::{stream, StreamExt};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Result as IoResult, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::storage::storage_connector::ObjectStream;
+
+/// Files start with this magic followed by an 8-byte random nonce prefix.
+const MAGIC: &[u8; 4] = b"IZE1";
+const HEADER_LEN: u64 = 12;
+/// Plaintext bytes per sealed segment. Segments are sealed independently so that a reader
+/// can seek without decrypting everything before the target offset.
+pub const SEGMENT_SIZE: u64 = 64 * 1024;
+const TAG_LEN: u64 = 16;
+
+/// Where the encryption-at-rest key comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionConfig {
+    /// File holding the 256-bit AES key as 64 hex digits.
+    pub key_file: String,
+}
+
+impl EncryptionConfig {
+    pub fn load_key(&self) -> IoResult<Arc<EncryptionKey>> {
+        let hex_key = fs::read_to_string(&self.key_file)?;
+        EncryptionKey::from_hex(hex_key.trim()).map(Arc::new)
+    }
+}
+
+/// AES-256-GCM key used to seal cached files.
+pub struct EncryptionKey(LessSafeKey);
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl EncryptionKey {
+    pub fn from_bytes(bytes: &[u8]) -> IoResult<Self> {
+        UnboundKey::new(&AES_256_GCM, bytes)
+            .map(|key| Self(LessSafeKey::new(key)))
+            .map_err(|_| invalid_data("encryption key must be 32 bytes"))
+    }
+
+    pub fn from_hex(hex_key: &str) -> IoResult<Self> {
+        let bytes = hex::decode(hex_key)
+            .map_err(|e| invalid_data(&format!("malformed encryption key: {}", e)))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn nonce(prefix: &[u8; 8], segment: u64) -> IoResult<Nonce> {
+    let segment = u32::try_from(segment).map_err(|_| invalid_data("file too large"))?;
+    let mut bytes = [0u8; 12];
+    bytes[..8].copy_from_slice(prefix);
+    bytes[8..].copy_from_slice(&segment.to_be_bytes());
+    Ok(Nonce::assume_unique_for_key(bytes))
+}
+
+/// Binds each segment to its position and marks the last one, so that segments can't be
+/// reordered and the file can't be truncated at a segment boundary.
+fn aad(segment: u64, last: bool) -> [u8; 9] {
+    let mut bytes = [0u8; 9];
+    bytes[..8].copy_from_slice(&segment.to_be_bytes());
+    bytes[8] = last as u8;
+    bytes
+}
+
+/// Fills `buf` as far as the reader allows, returning the number of bytes read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> IoResult<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// On-disk size of `plaintext_len` bytes once sealed.
+pub fn sealed_len(plaintext_len: u64) -> u64 {
+    let segments = plaintext_len.div_ceil(SEGMENT_SIZE).max(1);
+    HEADER_LEN + plaintext_len + segments * TAG_LEN
+}
+
+/// Size of the content of a sealed file of `sealed_len` bytes.
+pub fn plaintext_len(sealed_len: u64) -> u64 {
+    let body_len = sealed_len.saturating_sub(HEADER_LEN);
+    let segments = body_len.div_ceil(SEGMENT_SIZE + TAG_LEN);
+    body_len.saturating_sub(segments * TAG_LEN)
+}
+
+/// Seals plaintext as it is pushed in. A full segment is only sealed once more data
+/// arrives, since the last segment is sealed differently.
+struct Sealer {
+    key: Arc<EncryptionKey>,
+    prefix: [u8; 8],
+    segment: u64,
+    pending: Vec<u8>,
+    sealed: Vec<u8>,
+}
+
+impl Sealer {
+    fn new(key: Arc<EncryptionKey>) -> IoResult<Self> {
+        let mut prefix = [0u8; 8];
+        SystemRandom::new()
+            .fill(&mut prefix)
+            .map_err(|_| io::Error::other("no randomness available"))?;
+        let mut sealed = Vec::with_capacity(HEADER_LEN as usize);
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&prefix);
+        Ok(Self {
+            key,
+            prefix,
+            segment: 0,
+            pending: Vec::with_capacity(SEGMENT_SIZE as usize),
+            sealed,
+        })
+    }
+
+    fn push(&mut self, mut data: &[u8]) -> IoResult<()> {
+        while !data.is_empty() {
+            if self.pending.len() == SEGMENT_SIZE as usize {
+                self.seal_pending(false)?;
+            }
+            let n = data.len().min(SEGMENT_SIZE as usize - self.pending.len());
+            self.pending.extend_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        self.seal_pending(true)
+    }
+
+    fn seal_pending(&mut self, last: bool) -> IoResult<()> {
+        let start = self.sealed.len();
+        self.sealed.extend_from_slice(&self.pending);
+        self.pending.clear();
+        let tag = self
+            .key
+            .0
+            .seal_in_place_separate_tag(
+                nonce(&self.prefix, self.segment)?,
+                Aad::from(aad(self.segment, last)),
+                &mut self.sealed[start..],
+            )
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.sealed.extend_from_slice(tag.as_ref());
+        self.segment += 1;
+        Ok(())
+    }
+
+    /// Takes what has been sealed so far.
+    fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.sealed)
+    }
+}
+
+/// Writes sealed what is written to it; `finish` seals the last segment.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    sealer: Sealer,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(key: Arc<EncryptionKey>, inner: W) -> IoResult<Self> {
+        Ok(Self {
+            inner,
+            sealer: Sealer::new(key)?,
+        })
+    }
+
+    pub fn finish(mut self) -> IoResult<W> {
+        self.sealer.finish()?;
+        self.inner.write_all(&self.sealer.take())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> IoResult<usize> {
+        self.sealer.push(data)?;
+        self.inner.write_all(&self.sealer.take())?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+/// Seals `data` in memory.
+pub fn seal(key: Arc<EncryptionKey>, data: &[u8]) -> IoResult<Vec<u8>> {
+    let mut sealer = Sealer::new(key)?;
+    sealer.push(data)?;
+    sealer.finish()?;
+    Ok(sealer.take())
+}
+
+/// Seals `stream` as it goes, so that it can be written to disk as it arrives.
+pub fn seal_stream(key: Arc<EncryptionKey>, stream: ObjectStream) -> ObjectStream {
+    let sealer = match Sealer::new(key) {
+        Ok(sealer) => sealer,
+        Err(e) => return Box::pin(stream::once(async { Err(e) })),
+    };
+    Box::pin(stream::unfold(Some((sealer, stream)), |state| async move {
+        let (mut sealer, mut stream) = state?;
+        loop {
+            let sealed = match stream.next().await {
+                Some(Ok(data)) => sealer.push(&data).map(|()| sealer.take()),
+                Some(Err(e)) => Err(e),
+                None => {
+                    let sealed = sealer.finish().map(|()| Bytes::from(sealer.take()));
+                    return Some((sealed, None));
+                }
+            };
+            match sealed {
+                Ok(sealed) if sealed.is_empty() => continue,
+                Ok(sealed) => return Some((Ok(Bytes::from(sealed)), Some((sealer, stream)))),
+                Err(e) => return Some((Err(e), None)),
+            }
+        }
+    }))
+}
+
+/// Seals the file at `source` into a new file at `dest`, returning its size. Blocking;
+/// run it on the blocking pool.
+pub fn seal_file(key: Arc<EncryptionKey>, source: &Path, dest: &Path) -> IoResult<u64> {
+    let mut input = File::open(source)?;
+    let mut output = EncryptingWriter::new(key, BufWriter::new(File::create(dest)?))?;
+    io::copy(&mut input, &mut output)?;
+    output.finish()?;
+    Ok(fs::metadata(dest)?.len())
+}
+
+/// Plaintext view of a sealed file, with random access.
+pub struct DecryptingReader<R> {
+    inner: R,
+    key: Arc<EncryptionKey>,
+    prefix: [u8; 8],
+    segments: u64,
+    plaintext_len: u64,
+    next_segment: u64,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl DecryptingReader<File> {
+    pub fn open(key: Arc<EncryptionKey>, path: &Path) -> IoResult<Self> {
+        Self::new(key, File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> DecryptingReader<R> {
+    pub fn new(key: Arc<EncryptionKey>, mut inner: R) -> IoResult<Self> {
+        let file_len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        if file_len < HEADER_LEN + TAG_LEN || read_full(&mut inner, &mut header)? < header.len() {
+            return Err(invalid_data("not an encrypted cache file"));
+        }
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not an encrypted cache file"));
+        }
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&header[4..]);
+        let segments = (file_len - HEADER_LEN).div_ceil(SEGMENT_SIZE + TAG_LEN);
+        Ok(Self {
+            inner,
+            key,
+            prefix,
+            segments,
+            plaintext_len: plaintext_len(file_len),
+            next_segment: 0,
+            buf: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Size of the decrypted content.
+    pub fn plaintext_len(&self) -> u64 {
+        self.plaintext_len
+    }
+
+    fn load_segment(&mut self, segment: u64) -> IoResult<()> {
+        let sealed_len = if segment + 1 == self.segments {
+            self.plaintext_len - segment * SEGMENT_SIZE + TAG_LEN
+        } else {
+            SEGMENT_SIZE + TAG_LEN
+        };
+        self.inner.seek(SeekFrom::Start(
+            HEADER_LEN + segment * (SEGMENT_SIZE + TAG_LEN),
+        ))?;
+        self.buf.resize(sealed_len as usize, 0);
+        if read_full(&mut self.inner, &mut self.buf)? < self.buf.len() {
+            return Err(invalid_data("encrypted cache file is truncated"));
+        }
+        let last = segment + 1 == self.segments;
+        let plaintext_len = self
+            .key
+            .0
+            .open_in_place(
+                nonce(&self.prefix, segment)?,
+                Aad::from(aad(segment, last)),
+                &mut self.buf,
+            )
+            .map_err(|_| invalid_data("encrypted cache file failed authentication"))?
+            .len();
+        self.buf.truncate(plaintext_len);
+        self.pos = 0;
+        self.next_segment = segment + 1;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for DecryptingReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> IoResult<usize> {
+        while self.pos == self.buf.len() {
+            if self.next_segment >= self.segments {
+                return Ok(0);
+            }
+            self.load_segment(self.next_segment)?;
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for DecryptingReader<R> {
+    fn seek(&mut self, target: SeekFrom) -> IoResult<u64> {
+        let target = match target {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.plaintext_len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => {
+                let current =
+                    (self.next_segment.saturating_sub(1)) * SEGMENT_SIZE + self.pos as u64;
+                current.checked_add_signed(delta)
+            }
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start"))?;
+        if target >= self.plaintext_len {
+            self.buf.clear();
+            self.pos = 0;
+            self.next_segment = self.segments;
+            return Ok(target);
+        }
+        let segment = target / SEGMENT_SIZE;
+        if self.next_segment != segment + 1 || self.buf.is_empty() {
+            self.load_segment(segment)?;
+        }
+        self.pos = (target % SEGMENT_SIZE) as usize;
+        Ok(target)
+    }
+}
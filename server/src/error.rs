@@ -0,0 +1,117 @@
+// error.rs
+//! Why a key could not be served. Each error answers with its status code and a JSON body
+//! naming the error and the key, e.g.
+//! `{"code": "not_found", "key": "orders.csv", "message": "..."}`, so that clients can
+//! tell a missing object from a failing backing store or a busy node.
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use serde::Serialize;
+use std::io::Cursor;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CacheError {
+    #[error("{key} does not exist in the backing store")]
+    NotFound { key: String },
+    /// The key can't name an object, e.g. because it isn't UTF-8; shown lossily.
+    #[error("{key} is not a valid key")]
+    InvalidKey { key: String },
+    #[error("the requested range of {key} is not satisfiable")]
+    RangeNotSatisfiable { key: String },
+    /// The backing store failed for a reason other than the object missing.
+    #[error("fetching {key} from the backing store failed: {reason}")]
+    Upstream { key: String, reason: String },
+    /// The S3 fetch queue is full; the client should retry later.
+    #[error("too many fetches are waiting to fetch {key}")]
+    Overloaded { key: String },
+    /// A stage ran out of time, or the client's deadline passed.
+    #[error("{key}: {reason}")]
+    Timeout { key: String, reason: String },
+    /// The object was fetched but could not be stored or read back.
+    #[error("{key} could not be cached: {reason}")]
+    Internal { key: String, reason: String },
+}
+
+impl CacheError {
+    pub fn not_found(key: impl Into<String>) -> Self {
+        CacheError::NotFound { key: key.into() }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            CacheError::NotFound { .. } => Status::NotFound,
+            CacheError::InvalidKey { .. } => Status::BadRequest,
+            CacheError::RangeNotSatisfiable { .. } => Status::RangeNotSatisfiable,
+            CacheError::Upstream { .. } => Status::BadGateway,
+            CacheError::Overloaded { .. } => Status::ServiceUnavailable,
+            CacheError::Timeout { .. } => Status::GatewayTimeout,
+            CacheError::Internal { .. } => Status::InternalServerError,
+        }
+    }
+
+    /// The stable name of the error in response bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CacheError::NotFound { .. } => "not_found",
+            CacheError::InvalidKey { .. } => "invalid_key",
+            CacheError::RangeNotSatisfiable { .. } => "range_not_satisfiable",
+            CacheError::Upstream { .. } => "upstream_error",
+            CacheError::Overloaded { .. } => "overloaded",
+            CacheError::Timeout { .. } => "timeout",
+            CacheError::Internal { .. } => "internal_error",
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        match self {
+            CacheError::NotFound { key }
+            | CacheError::InvalidKey { key }
+            | CacheError::RangeNotSatisfiable { key }
+            | CacheError::Upstream { key, .. }
+            | CacheError::Overloaded { key }
+            | CacheError::Timeout { key, .. }
+            | CacheError::Internal { key, .. } => key,
+        }
+    }
+}
+
+/// For callers reading objects without HTTP, e.g. through `embedded::Cache`.
+impl From<CacheError> for std::io::Error {
+    fn from(e: CacheError) -> Self {
+        let kind = match e {
+            CacheError::NotFound { .. } => std::io::ErrorKind::NotFound,
+            CacheError::InvalidKey { .. } | CacheError::RangeNotSatisfiable { .. } => {
+                std::io::ErrorKind::InvalidInput
+            }
+            CacheError::Overloaded { .. } => std::io::ErrorKind::WouldBlock,
+            CacheError::Timeout { .. } => std::io::ErrorKind::TimedOut,
+            CacheError::Upstream { .. } | CacheError::Internal { .. } => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, e)
+    }
+}
+
+/// The JSON body of an error response.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody<'a> {
+    pub code: &'a str,
+    pub key: &'a str,
+    pub message: String,
+}
+
+impl<'r> Responder<'r, 'static> for CacheError {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let body = ErrorBody {
+            code: self.code(),
+            key: self.key(),
+            message: self.to_string(),
+        };
+        let json = serde_json::to_vec(&body).map_err(|_| Status::InternalServerError)?;
+        Response::build()
+            .status(self.status())
+            .header(ContentType::JSON)
+            .sized_body(json.len(), Cursor::new(json))
+            .ok()
+    }
+}
@@ -0,0 +1,464 @@
+// etcd.rs
+//! Metadata in etcd, spoken to through its v3 JSON gateway. Each node registers itself
+//! under a lease it keeps alive, so crashed nodes drop out after the lease TTL; a watch on
+//! the node keys keeps every node's view of the membership current. Slots are split into
+//! contiguous ranges over the live nodes in address order, as `redis-cli --cluster create`
+//! would, each sized by the node's registered weight.
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::{debug, info, warn};
+use rocket::futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::cache::ClusterMember;
+use crate::cache_core::slot_counts;
+use crate::metadata::{default_weight, key_slot, MetadataStore, StoreError};
+use crate::util::{host_port, FileUid};
+
+/// How long a single etcd request may take; watches are exempt.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before re-establishing a broken watch or keep-alive.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EtcdConfig {
+    /// Gateway URLs, e.g. `http://etcd:2379`; tried in order.
+    pub endpoints: Vec<String>,
+    /// Prefix of every key this cluster writes.
+    pub prefix: String,
+    /// Seconds a node stays a member after its last keep-alive.
+    pub lease_ttl_secs: u64,
+}
+
+impl Default for EtcdConfig {
+    fn default() -> Self {
+        EtcdConfig {
+            endpoints: Vec::new(),
+            prefix: String::from("/istziio"),
+            lease_ttl_secs: 10,
+        }
+    }
+}
+
+/// What a node registers about itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NodeRecord {
+    endpoint: String,
+    port: u16,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    /// `host:port` of the node's web server, if it advertised one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+}
+
+/// The `range_end` that makes a range request cover every key starting with `prefix`.
+fn prefix_end(prefix: &str) -> Vec<u8> {
+    let mut end = prefix.as_bytes().to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    vec![0]
+}
+
+/// Decodes a base64 field of a gateway response.
+fn decode(value: &Value) -> Option<Vec<u8>> {
+    BASE64.decode(value.as_str()?).ok()
+}
+
+/// Members sorted by address, each with a share of the slots proportional to its weight.
+fn assign_slots(mut members: Vec<ClusterMember>) -> Vec<ClusterMember> {
+    members.sort_by(|a, b| (&a.endpoint, a.port).cmp(&(&b.endpoint, b.port)));
+    let weights: Vec<u32> = members.iter().map(|m| m.weight).collect();
+    for (member, slots) in members.iter_mut().zip(slot_counts(&weights)) {
+        member.slots = slots;
+    }
+    members
+}
+
+/// Talks to the gateway; cheap to clone into background tasks.
+#[derive(Clone)]
+struct Gateway {
+    http: reqwest::Client,
+    endpoints: Vec<String>,
+}
+
+impl Gateway {
+    /// Posts `body` to `path` on the first endpoint that answers.
+    async fn call(&self, path: &str, body: &Value) -> Result<Value, StoreError> {
+        let mut last_error = String::from("no etcd endpoints configured");
+        for endpoint in &self.endpoints {
+            let url = format!("{}{}", endpoint.trim_end_matches('/'), path);
+            let response = self
+                .http
+                .post(&url)
+                .json(body)
+                .timeout(REQUEST_TIMEOUT)
+                .send()
+                .await;
+            match response {
+                Ok(response) if response.status().is_success() => {
+                    return response
+                        .json()
+                        .await
+                        .map_err(|e| StoreError::Backend(e.to_string()));
+                }
+                Ok(response) => last_error = format!("{}: {}", url, response.status()),
+                Err(e) => last_error = format!("{}: {}", url, e),
+            }
+        }
+        Err(StoreError::Backend(last_error))
+    }
+
+    /// Every key and value under `prefix`.
+    async fn range(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, StoreError> {
+        let body = json!({
+            "key": BASE64.encode(prefix),
+            "range_end": BASE64.encode(prefix_end(prefix)),
+        });
+        let response = self.call("/v3/kv/range", &body).await?;
+        let kvs = response["kvs"].as_array().cloned().unwrap_or_default();
+        Ok(kvs
+            .iter()
+            .filter_map(|kv| {
+                let key = String::from_utf8(decode(&kv["key"])?).ok()?;
+                Some((key, decode(&kv["value"]).unwrap_or_default()))
+            })
+            .collect())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let body = json!({ "key": BASE64.encode(key) });
+        let response = self.call("/v3/kv/range", &body).await?;
+        Ok(response["kvs"]
+            .as_array()
+            .and_then(|kvs| kvs.first())
+            .and_then(|kv| decode(&kv["value"])))
+    }
+
+    async fn put(&self, key: &str, value: &[u8], lease: Option<&str>) -> Result<(), StoreError> {
+        let mut body = json!({ "key": BASE64.encode(key), "value": BASE64.encode(value) });
+        if let Some(lease) = lease {
+            body["lease"] = json!(lease);
+        }
+        self.call("/v3/kv/put", &body).await.map(|_| ())
+    }
+
+    async fn delete(&self, key: &str, range_end: Option<Vec<u8>>) -> Result<(), StoreError> {
+        let mut body = json!({ "key": BASE64.encode(key) });
+        if let Some(end) = range_end {
+            body["range_end"] = json!(BASE64.encode(end));
+        }
+        self.call("/v3/kv/deleterange", &body).await.map(|_| ())
+    }
+
+    /// Grants a lease of `ttl` seconds; the gateway encodes its 64-bit ID as a string.
+    async fn grant_lease(&self, ttl: u64) -> Result<String, StoreError> {
+        let response = self.call("/v3/lease/grant", &json!({ "TTL": ttl })).await?;
+        match &response["ID"] {
+            Value::String(id) => Ok(id.clone()),
+            Value::Number(id) => Ok(id.to_string()),
+            _ => Err(StoreError::Backend(String::from(
+                "lease grant returned no ID",
+            ))),
+        }
+    }
+
+    async fn keep_alive(&self, lease: &str) -> Result<(), StoreError> {
+        let response = self
+            .call("/v3/lease/keepalive", &json!({ "ID": lease }))
+            .await?;
+        // An expired lease comes back with a TTL of zero (or none at all).
+        let ttl = &response["result"]["TTL"];
+        match ttl.as_i64().or_else(|| ttl.as_str()?.parse().ok()) {
+            Some(ttl) if ttl > 0 => Ok(()),
+            _ => Err(StoreError::Backend(format!("lease {} expired", lease))),
+        }
+    }
+
+    /// Blocks until something under `prefix` changes, or the watch breaks. Successive
+    /// `attempt`s go to successive endpoints.
+    async fn wait_for_change(&self, prefix: &str, attempt: usize) -> Result<(), StoreError> {
+        if self.endpoints.is_empty() {
+            return Err(StoreError::Backend(String::from(
+                "no etcd endpoints configured",
+            )));
+        }
+        let endpoint = &self.endpoints[attempt % self.endpoints.len()];
+        let body = json!({
+            "create_request": {
+                "key": BASE64.encode(prefix),
+                "range_end": BASE64.encode(prefix_end(prefix)),
+            }
+        });
+        let url = format!("{}/v3/watch", endpoint.trim_end_matches('/'));
+        let response = self
+            .http
+            .post(&url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        let mut stream = response.bytes_stream();
+        let mut buffer = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            buffer.extend_from_slice(&chunk.map_err(|e| StoreError::Backend(e.to_string()))?);
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let message: Value = match serde_json::from_slice(&line) {
+                    Ok(message) => message,
+                    Err(_) => continue,
+                };
+                // The first message only confirms that the watch was created.
+                if message["result"]["events"]
+                    .as_array()
+                    .is_some_and(|e| !e.is_empty())
+                {
+                    return Ok(());
+                }
+            }
+        }
+        Err(StoreError::Backend(String::from("watch stream ended")))
+    }
+}
+
+pub struct EtcdStore {
+    gateway: Gateway,
+    prefix: String,
+    lease_ttl_secs: u64,
+    me: NodeRecord,
+    members: Arc<RwLock<Vec<ClusterMember>>>,
+    initialized: bool,
+}
+
+impl EtcdStore {
+    /// A store registering this node as `endpoint:redis_port` with `weight`; `endpoint`
+    /// must be an IP address the other nodes can reach.
+    pub fn new(config: &EtcdConfig, endpoint: &str, redis_port: u16, weight: u32) -> Self {
+        EtcdStore {
+            gateway: Gateway {
+                http: reqwest::Client::new(),
+                endpoints: config.endpoints.clone(),
+            },
+            prefix: config.prefix.trim_end_matches('/').to_string(),
+            lease_ttl_secs: config.lease_ttl_secs,
+            me: NodeRecord {
+                endpoint: endpoint.to_string(),
+                port: redis_port,
+                weight,
+                address: None,
+            },
+            members: Arc::new(RwLock::new(Vec::new())),
+            initialized: false,
+        }
+    }
+
+    /// Advertises `address`, a `host:port`, as where the other nodes reach this one's web
+    /// server.
+    pub fn with_advertised_address(mut self, address: String) -> Self {
+        self.me.address = Some(address);
+        self
+    }
+
+    fn node_id(&self) -> String {
+        host_port(&self.me.endpoint, self.me.port)
+    }
+
+    fn nodes_prefix(&self) -> String {
+        format!("{}/nodes/", self.prefix)
+    }
+
+    fn file_key(&self, uid: &str) -> String {
+        format!("{}/files/{}", self.prefix, uid)
+    }
+
+    fn ring_key(&self) -> String {
+        format!("{}/ring", self.prefix)
+    }
+
+    /// Registers this node under a fresh lease.
+    async fn register(
+        gateway: &Gateway,
+        key: &str,
+        me: &NodeRecord,
+        ttl: u64,
+    ) -> Result<String, StoreError> {
+        let lease = gateway.grant_lease(ttl).await?;
+        let record = serde_json::to_vec(me).unwrap();
+        gateway.put(key, &record, Some(&lease)).await?;
+        Ok(lease)
+    }
+
+    /// Re-reads the registered nodes into `members`.
+    async fn refresh(
+        gateway: &Gateway,
+        nodes_prefix: &str,
+        node_id: &str,
+        members: &RwLock<Vec<ClusterMember>>,
+    ) -> Result<(), StoreError> {
+        let records = gateway.range(nodes_prefix).await?;
+        let found: Vec<ClusterMember> = records
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let record: NodeRecord = serde_json::from_slice(&value).ok()?;
+                let id = key.strip_prefix(nodes_prefix)?.to_string();
+                Some(ClusterMember {
+                    is_self: id == node_id,
+                    node_id: id,
+                    endpoint: record.endpoint,
+                    port: record.port,
+                    slots: 0,
+                    weight: record.weight.max(1),
+                    address: record.address,
+                })
+            })
+            .collect();
+        let updated = if found.is_empty() {
+            found
+        } else {
+            assign_slots(found)
+        };
+        let mut current = members.write().unwrap();
+        if *current != updated {
+            info!(
+                "Cluster membership changed: {:?}",
+                updated.iter().map(|m| &m.node_id).collect::<Vec<_>>()
+            );
+            *current = updated;
+        }
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl MetadataStore for EtcdStore {
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Registers this node, reads the membership and starts the keep-alive and watch
+    /// tasks that keep both current.
+    async fn initialize(&mut self) -> Result<(), StoreError> {
+        let node_key = format!("{}{}", self.nodes_prefix(), self.node_id());
+        let ttl = self.lease_ttl_secs;
+        let mut lease = Self::register(&self.gateway, &node_key, &self.me, ttl).await?;
+        Self::refresh(
+            &self.gateway,
+            &self.nodes_prefix(),
+            &self.node_id(),
+            &self.members,
+        )
+        .await?;
+
+        let gateway = self.gateway.clone();
+        let me = self.me.clone();
+        tokio::spawn(async move {
+            let interval = Duration::from_secs((ttl / 3).max(1));
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(e) = gateway.keep_alive(&lease).await {
+                    warn!("Keeping lease {} alive failed: {}", lease, e);
+                    match Self::register(&gateway, &node_key, &me, ttl).await {
+                        Ok(fresh) => lease = fresh,
+                        Err(e) => warn!("Re-registering with etcd failed: {}", e),
+                    }
+                }
+            }
+        });
+
+        let gateway = self.gateway.clone();
+        let (nodes_prefix, node_id) = (self.nodes_prefix(), self.node_id());
+        let members = self.members.clone();
+        tokio::spawn(async move {
+            let mut attempt = 0;
+            loop {
+                if let Err(e) = gateway.wait_for_change(&nodes_prefix, attempt).await {
+                    debug!("etcd watch interrupted: {}", e);
+                    attempt += 1;
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+                if let Err(e) = Self::refresh(&gateway, &nodes_prefix, &node_id, &members).await {
+                    warn!("Refreshing the membership from etcd failed: {}", e);
+                }
+            }
+        });
+
+        self.initialized = true;
+        Ok(())
+    }
+
+    async fn location_lookup(&self, uid: FileUid) -> Option<(String, u16)> {
+        let members = self.members.read().unwrap();
+        if members.is_empty() {
+            return None;
+        }
+        let slot = key_slot(&uid) as usize;
+        let mut range_end = 0;
+        let owner = members.iter().find(|member| {
+            range_end += member.slots;
+            slot < range_end
+        })?;
+        if owner.is_self {
+            None
+        } else {
+            Some((owner.endpoint.clone(), owner.port))
+        }
+    }
+
+    async fn get_file(&self, uid: FileUid) -> Option<PathBuf> {
+        let value = self.gateway.get(&self.file_key(&uid)).await.ok()??;
+        String::from_utf8(value).ok().map(PathBuf::from)
+    }
+
+    async fn set_file_cache_loc(&self, uid: FileUid, loc: PathBuf) -> Result<(), ()> {
+        let loc = loc.into_os_string().into_string().map_err(|_| ())?;
+        self.gateway
+            .put(&self.file_key(&uid), loc.as_bytes(), None)
+            .await
+            .map_err(|e| warn!("Failed to record {} in etcd: {}", uid, e))
+    }
+
+    async fn remove_file(&self, uid: FileUid) -> Result<(), ()> {
+        self.gateway
+            .delete(&self.file_key(&uid), None)
+            .await
+            .map_err(|e| warn!("Failed to remove {} from etcd: {}", uid, e))
+    }
+
+    async fn flush_all(&self) {
+        let files = format!("{}/files/", self.prefix);
+        if let Err(e) = self.gateway.delete(&files, Some(prefix_end(&files))).await {
+            warn!("Failed to flush file locations from etcd: {}", e);
+        }
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.gateway.get(&self.ring_key()).await.map(|_| ())
+    }
+
+    fn members(&self) -> Vec<ClusterMember> {
+        self.members.read().unwrap().clone()
+    }
+
+    async fn load_ring(&self) -> Result<Option<String>, StoreError> {
+        let value = self.gateway.get(&self.ring_key()).await?;
+        value
+            .map(|value| String::from_utf8(value).map_err(|e| StoreError::Backend(e.to_string())))
+            .transpose()
+    }
+
+    async fn save_ring(&self, ring: &str) -> Result<(), StoreError> {
+        self.gateway
+            .put(&self.ring_key(), ring.as_bytes(), None)
+            .await
+    }
+}
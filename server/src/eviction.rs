@@ -0,0 +1,60 @@
+// eviction.rs
+//! Background eviction between watermarks. Once a shard fills past its high watermark, a
+//! background task evicts its least valuable entries until the shard is down to the low
+//! watermark, so misses find room waiting for them instead of evicting before they can be
+//! written. A miss still evicts inline when the shard is full anyway, e.g. while a burst
+//! of fetches outruns the task; those evictions are counted as emergency evictions.
+use log::info;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::ConcurrentDiskCache;
+
+/// Entries evicted per shard lock, so that requests to the shard get in between.
+pub const EVICTION_BATCH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvictionConfig {
+    /// Fraction of a shard's budget above which background eviction starts.
+    pub high_watermark: f64,
+    /// Fraction of a shard's budget background eviction brings it down to.
+    pub low_watermark: f64,
+    /// How often the shards are checked against the high watermark.
+    pub interval_ms: u64,
+}
+
+impl Default for EvictionConfig {
+    fn default() -> Self {
+        EvictionConfig {
+            high_watermark: 0.9,
+            low_watermark: 0.8,
+            interval_ms: 1000,
+        }
+    }
+}
+
+impl EvictionConfig {
+    /// Bytes of a `max_size` shard past which background eviction starts.
+    pub fn high_mark(&self, max_size: u64) -> u64 {
+        (max_size as f64 * self.high_watermark) as u64
+    }
+
+    /// Bytes of a `max_size` shard background eviction brings it down to.
+    pub fn low_mark(&self, max_size: u64) -> u64 {
+        (max_size as f64 * self.low_watermark) as u64
+    }
+}
+
+/// Checks the shards every `interval_ms` for as long as the node runs, taking changes to
+/// the config as they are made.
+pub async fn run(cache: Arc<ConcurrentDiskCache>) {
+    while let Some(config) = cache.eviction().await {
+        tokio::time::sleep(Duration::from_millis(config.interval_ms)).await;
+        let freed = cache.evict_in_background().await;
+        if freed > 0 {
+            info!("Background eviction freed {} bytes", freed);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// faults.rs
+//! Failures injected on purpose, to test how a node copes with a flaky backing store,
+//! a failing disk, a slow Redis or slow peers. Only built with the `fault-injection`
+//! feature; faults are set at run time through `/admin/faults` and apply to the whole
+//! process.
+use rocket::response::status::BadRequest;
+use rocket::serde::json::Json;
+use rocket::{delete, get, put};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::auth::AdminAccess;
+
+/// Where a fault can be injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultPoint {
+    /// Every request to the backing store.
+    S3,
+    /// Writing a fetched object to the cache disk.
+    DiskWrite,
+    /// Every Redis command; failures show up as timeouts.
+    Redis,
+    /// Every request to a peer node.
+    Peer,
+}
+
+impl fmt::Display for FaultPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FaultPoint::S3 => "s3",
+            FaultPoint::DiskWrite => "disk_write",
+            FaultPoint::Redis => "redis",
+            FaultPoint::Peer => "peer",
+        })
+    }
+}
+
+/// What happens at one fault point.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fault {
+    /// Chance, from 0 to 1, that an operation fails.
+    pub error_rate: f64,
+    /// Delay added before every operation, failing or not.
+    pub latency_ms: u64,
+}
+
+/// The faults of every point, e.g. as JSON
+/// `{"s3": {"error_rate": 0.1}, "peer": {"latency_ms": 2000}}`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultConfig {
+    pub s3: Fault,
+    pub disk_write: Fault,
+    pub redis: Fault,
+    pub peer: Fault,
+}
+
+impl FaultConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (point, fault) in [
+            (FaultPoint::S3, self.s3),
+            (FaultPoint::DiskWrite, self.disk_write),
+            (FaultPoint::Redis, self.redis),
+            (FaultPoint::Peer, self.peer),
+        ] {
+            if !(0.0..=1.0).contains(&fault.error_rate) {
+                return Err(format!("{}.error_rate must be between 0 and 1", point));
+            }
+        }
+        Ok(())
+    }
+
+    fn fault(&self, point: FaultPoint) -> Fault {
+        match point {
+            FaultPoint::S3 => self.s3,
+            FaultPoint::DiskWrite => self.disk_write,
+            FaultPoint::Redis => self.redis,
+            FaultPoint::Peer => self.peer,
+        }
+    }
+}
+
+static FAULTS: RwLock<Option<FaultConfig>> = RwLock::new(None);
+/// State of the generator deciding which operations fail.
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+/// Replaces the faults in effect.
+pub fn set(config: FaultConfig) {
+    *FAULTS.write().unwrap() = Some(config);
+}
+
+/// Stops injecting faults.
+pub fn clear() {
+    *FAULTS.write().unwrap() = None;
+}
+
+pub fn current() -> FaultConfig {
+    FAULTS.read().unwrap().unwrap_or_default()
+}
+
+/// A uniform draw from [0, 1), from a splitmix64 sequence seeded by the clock.
+fn draw() -> f64 {
+    const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+    if SEED.load(Ordering::Relaxed) == 0 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let seed = now.as_nanos() as u64 | 1;
+        let _ = SEED.compare_exchange(0, seed, Ordering::Relaxed, Ordering::Relaxed);
+    }
+    let mut z = SEED.fetch_add(GAMMA, Ordering::Relaxed).wrapping_add(GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// The delay to add at `point`, and whether the operation fails.
+fn roll(point: FaultPoint) -> (Duration, Option<IoError>) {
+    let fault = match *FAULTS.read().unwrap() {
+        Some(config) => config.fault(point),
+        None => return (Duration::ZERO, None),
+    };
+    let fails = fault.error_rate > 0.0 && draw() < fault.error_rate;
+    let error = fails.then(|| {
+        let kind = match point {
+            FaultPoint::Redis | FaultPoint::Peer => ErrorKind::TimedOut,
+            FaultPoint::S3 | FaultPoint::DiskWrite => ErrorKind::Other,
+        };
+        IoError::new(kind, format!("injected {} fault", point))
+    });
+    (Duration::from_millis(fault.latency_ms), error)
+}
+
+/// Waits out the latency configured for `point`, then fails as often as configured.
+pub async fn inject(point: FaultPoint) -> IoResult<()> {
+    let (delay, error) = roll(point);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    error.map_or(Ok(()), Err)
+}
+
+/// `inject` for blocking callers, such as the Redis client.
+pub fn inject_blocking(point: FaultPoint) -> IoResult<()> {
+    let (delay, error) = roll(point);
+    if !delay.is_zero() {
+        std::thread::sleep(delay);
+    }
+    error.map_or(Ok(()), Err)
+}
+
+/// The faults in effect.
+#[get("/admin/faults")]
+pub async fn show_faults(_admin: AdminAccess) -> Json<FaultConfig> {
+    Json(current())
+}
+
+/// Replaces the faults in effect; points left out of the body stop failing.
+#[put("/admin/faults", data = "<config>")]
+pub async fn set_faults(
+    _admin: AdminAccess,
+    config: Json<FaultConfig>,
+) -> Result<Json<FaultConfig>, BadRequest<String>> {
+    let config = config.into_inner();
+    config.validate().map_err(BadRequest)?;
+    log::warn!("Injecting faults: {:?}", config);
+    set(config);
+    Ok(Json(config))
+}
+
+#[delete("/admin/faults")]
+pub async fn clear_faults(_admin: AdminAccess) -> &'static str {
+    clear();
+    "cleared\n"
+}
@@ -0,0 +1,304 @@
+// flight.rs
+//! `arrow.flight.protocol.FlightService/DoGet` for Parquet objects, on the gRPC listener.
+//! The node decodes the requested row groups and columns itself and streams Arrow record
+//! batches, so that callers skip both the decode and the bytes of columns they don't
+//! read. The Parquet bytes come through the cache: a miss fetches the whole object as
+//! usual, and the decoder then reads the footer and column chunks as byte ranges of it.
+//! Only `DoGet` is served; tickets are JSON, as `FlightTicket` describes.
+use arrow::datatypes::SchemaRef;
+use arrow::ipc::writer::{DictionaryTracker, EncodedData, IpcDataGenerator, IpcWriteOptions};
+use bytes::Bytes;
+use parquet::arrow::async_reader::{
+    fetch_parquet_metadata, AsyncFileReader, ParquetRecordBatchStreamBuilder,
+};
+use parquet::arrow::ProjectionMask;
+use parquet::errors::ParquetError;
+use parquet::file::metadata::ParquetMetaData;
+use rocket::futures::future::BoxFuture;
+use rocket::futures::{FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+use crate::cache::{ConcurrentDiskCache, GetFileOptions, GetFileResult};
+use crate::cache_service::read_object;
+use crate::chunk::ByteRange;
+use crate::footer::FOOTER_TAIL_LEN;
+use crate::grpc::{Code, Replies, Service, Status};
+use crate::protobuf::{self, fields};
+use crate::read_through::check_bucket_key;
+use crate::s3_api::connector_for;
+use crate::storage::storage_connector::StorageConnector;
+use crate::warmup::warmed_bytes;
+
+/// What a `DoGet` ticket asks for, as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlightTicket {
+    /// Key of a Parquet object.
+    pub key: String,
+    /// Indices of the row groups to read; all of them when unset.
+    #[serde(default)]
+    pub row_groups: Option<Vec<usize>>,
+    /// Names of the top-level columns to read; all of them when unset.
+    #[serde(default)]
+    pub columns: Option<Vec<String>>,
+}
+
+pub struct FlightService {
+    cache: Arc<ConcurrentDiskCache>,
+    connectors: Vec<Arc<dyn StorageConnector + Send + Sync>>,
+    grpc_port_offset: u16,
+}
+
+impl FlightService {
+    pub fn new(
+        cache: Arc<ConcurrentDiskCache>,
+        connectors: Vec<Arc<dyn StorageConnector + Send + Sync>>,
+        grpc_port_offset: u16,
+    ) -> Self {
+        FlightService {
+            cache,
+            connectors,
+            grpc_port_offset,
+        }
+    }
+
+    async fn do_get(&self, request: &[u8], replies: &mut Replies) -> Result<(), Status> {
+        let mut ticket = &[][..];
+        for field in fields(request) {
+            if let (1, value) = field? {
+                ticket = value.as_bytes()?;
+            }
+        }
+        let ticket: FlightTicket = serde_json::from_slice(ticket)
+            .map_err(|e| Status::new(Code::InvalidArgument, format!("bad ticket: {}", e)))?;
+        check_bucket_key(&ticket.key)?;
+        if let Some(url) = self.cache.owner_url(&ticket.key, "/").await {
+            let port = url.port_or_known_default().unwrap_or_default();
+            return Err(Status::new(
+                Code::FailedPrecondition,
+                format!(
+                    "{} is owned by {}:{}",
+                    ticket.key,
+                    url.host_str().unwrap_or_default(),
+                    port.saturating_add(self.grpc_port_offset)
+                ),
+            ));
+        }
+        let connector = connector_for(&ticket.key, &self.connectors).clone();
+        let file = CachedParquet::open(self.cache.clone(), connector, &ticket.key).await?;
+        let builder = ParquetRecordBatchStreamBuilder::new(file)
+            .await
+            .map_err(parquet_error)?;
+        let projection = match &ticket.columns {
+            Some(columns) => {
+                let schema = builder.schema();
+                let roots = columns
+                    .iter()
+                    .map(|name| {
+                        schema.index_of(name).map_err(|_| {
+                            Status::new(Code::InvalidArgument, format!("no column {}", name))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                ProjectionMask::roots(builder.parquet_schema(), roots)
+            }
+            None => ProjectionMask::all(),
+        };
+        let mut builder = builder.with_projection(projection);
+        if let Some(row_groups) = ticket.row_groups {
+            let count = builder.metadata().num_row_groups();
+            if let Some(index) = row_groups.iter().find(|index| **index >= count) {
+                return Err(Status::new(
+                    Code::OutOfRange,
+                    format!("{} has {} row groups, not {}", ticket.key, count, index + 1),
+                ));
+            }
+            builder = builder.with_row_groups(row_groups);
+        }
+        let mut batches = builder.build().map_err(parquet_error)?;
+        let mut encoder = FlightEncoder::new(batches.schema().clone());
+        replies.send(&encoder.schema()).await?;
+        while let Some(batch) = batches.next().await {
+            for message in encoder.batch(&batch.map_err(parquet_error)?)? {
+                replies.send(&message).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parquet_error(e: ParquetError) -> Status {
+    match e {
+        ParquetError::External(e) => match e.downcast::<Status>() {
+            Ok(status) => *status,
+            Err(e) => Status::new(Code::Internal, e.to_string()),
+        },
+        e => Status::new(Code::FailedPrecondition, e.to_string()),
+    }
+}
+
+/// Encodes record batches as `FlightData` messages: the schema first, then each batch
+/// after the dictionaries it needs.
+struct FlightEncoder {
+    schema: SchemaRef,
+    generator: IpcDataGenerator,
+    dictionaries: DictionaryTracker,
+    options: IpcWriteOptions,
+}
+
+impl FlightEncoder {
+    fn new(schema: SchemaRef) -> Self {
+        FlightEncoder {
+            schema,
+            generator: IpcDataGenerator::default(),
+            dictionaries: DictionaryTracker::new(false),
+            options: IpcWriteOptions::default(),
+        }
+    }
+
+    fn schema(&self) -> Vec<u8> {
+        flight_data(self.generator.schema_to_bytes(&self.schema, &self.options))
+    }
+
+    fn batch(&mut self, batch: &arrow::record_batch::RecordBatch) -> Result<Vec<Vec<u8>>, Status> {
+        let (dictionaries, batch) = self
+            .generator
+            .encoded_batch(batch, &mut self.dictionaries, &self.options)
+            .map_err(|e| Status::new(Code::Internal, e.to_string()))?;
+        Ok(dictionaries
+            .into_iter()
+            .chain(std::iter::once(batch))
+            .map(flight_data)
+            .collect())
+    }
+}
+
+/// `FlightData` with `data_header` (2) and `data_body` (1000).
+fn flight_data(encoded: EncodedData) -> Vec<u8> {
+    let mut message = Vec::with_capacity(encoded.ipc_message.len() + encoded.arrow_data.len() + 16);
+    protobuf::put_bytes(&mut message, 2, &encoded.ipc_message);
+    if !encoded.arrow_data.is_empty() {
+        protobuf::put_bytes(&mut message, 1000, &encoded.arrow_data);
+    }
+    message
+}
+
+/// A Parquet object read as byte ranges through the cache.
+struct CachedParquet {
+    cache: Arc<ConcurrentDiskCache>,
+    connector: Arc<dyn StorageConnector + Send + Sync>,
+    key: String,
+    size: u64,
+}
+
+impl CachedParquet {
+    /// Fetches `key` into the cache unless it is there already, so that the ranges read
+    /// next are local.
+    async fn open(
+        cache: Arc<ConcurrentDiskCache>,
+        connector: Arc<dyn StorageConnector + Send + Sync>,
+        key: &str,
+    ) -> Result<Self, Status> {
+        if !cache.is_cached(key).await {
+            let result = cache
+                .get_file(
+                    PathBuf::from(key),
+                    connector.clone(),
+                    GetFileOptions::default(),
+                )
+                .await;
+            if let GetFileResult::Error(e) = result {
+                return Err(e.into());
+            }
+            warmed_bytes(result)
+                .await
+                .map_err(|e| Status::new(Code::Unavailable, e))?;
+        }
+        let size = match cache.open_object_sized(key).await {
+            Some((_, size)) => size,
+            // Not admitted; ask the backing store instead.
+            None => connector
+                .fetch_range(key, ByteRange::Suffix(FOOTER_TAIL_LEN))
+                .await?
+                .object_size
+                .ok_or_else(|| Status::new(Code::Internal, "the object size is unknown"))?,
+        };
+        Ok(CachedParquet {
+            cache,
+            connector,
+            key: key.to_string(),
+            size,
+        })
+    }
+}
+
+async fn read_range(
+    cache: Arc<ConcurrentDiskCache>,
+    connector: Arc<dyn StorageConnector + Send + Sync>,
+    key: String,
+    range: Range<usize>,
+) -> parquet::errors::Result<Bytes> {
+    if range.is_empty() {
+        return Ok(Bytes::new());
+    }
+    let range = ByteRange::FromTo(range.start as u64, range.end as u64 - 1);
+    let external = |e: Status| ParquetError::External(Box::new(e));
+    let mut reader = read_object(&cache, &connector, &key, Some(range), false)
+        .await
+        .map_err(external)?;
+    let mut data = Vec::new();
+    reader
+        .read_to_end(&mut data)
+        .await
+        .map_err(|e| external(e.into()))?;
+    Ok(data.into())
+}
+
+impl AsyncFileReader for CachedParquet {
+    fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, parquet::errors::Result<Bytes>> {
+        read_range(
+            self.cache.clone(),
+            self.connector.clone(),
+            self.key.clone(),
+            range,
+        )
+        .boxed()
+    }
+
+    fn get_metadata(&mut self) -> BoxFuture<'_, parquet::errors::Result<Arc<ParquetMetaData>>> {
+        let (cache, connector, key) =
+            (self.cache.clone(), self.connector.clone(), self.key.clone());
+        let size = self.size as usize;
+        async move {
+            let fetch = |range| read_range(cache.clone(), connector.clone(), key.clone(), range);
+            Ok(Arc::new(fetch_parquet_metadata(fetch, size, None).await?))
+        }
+        .boxed()
+    }
+}
+
+#[rocket::async_trait]
+impl Service for FlightService {
+    fn name(&self) -> &'static str {
+        "arrow.flight.protocol.FlightService"
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        request: Bytes,
+        replies: &mut Replies,
+    ) -> Result<(), Status> {
+        match method {
+            "DoGet" => self.do_get(&request, replies).await,
+            _ => Err(Status::new(
+                Code::Unimplemented,
+                format!("only DoGet is served, not {}", method),
+            )),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// footer.rs
+use std::convert::TryInto;
+
+use crate::util::FileUid;
+use crate::versioning::split_version;
+
+/// Trailing magic bytes of every Parquet file.
+pub const PARQUET_MAGIC: &[u8; 4] = b"PAR1";
+/// A Parquet file ends with a 4-byte little-endian metadata length followed by the magic.
+pub const FOOTER_TAIL_LEN: u64 = 8;
+
+pub fn is_parquet_key(uid: &str) -> bool {
+    split_version(uid).0.ends_with(".parquet")
+}
+
+/// Cache key under which the footer of `uid` is stored, next to the object's own entries.
+pub fn footer_key(uid: &str) -> FileUid {
+    format!("{}@footer", uid)
+}
+
+pub fn is_footer_key(file_name: &str) -> bool {
+    file_name.ends_with("@footer")
+}
+
+/// Given the last bytes of a Parquet file, returns the length of the whole footer
+/// (metadata plus the 8-byte tail), or `None` if the bytes are not a Parquet tail.
+pub fn footer_len(tail: &[u8]) -> Option<u64> {
+    if (tail.len() as u64) < FOOTER_TAIL_LEN {
+        return None;
+    }
+    let tail = &tail[tail.len() - FOOTER_TAIL_LEN as usize..];
+    if &tail[4..] != PARQUET_MAGIC {
+        return None;
+    }
+    let metadata_len = u32::from_le_bytes(tail[..4].try_into().ok()?);
+    Some(metadata_len as u64 + FOOTER_TAIL_LEN)
+}
@@ -0,0 +1,335 @@
+// grpc.rs
+//! gRPC next to the HTTP API, served by hand over hyper's HTTP/2: the framing is five
+//! bytes before each message, and the status goes in the trailers. Services handle
+//! unary and server-streaming methods, which is all `proto/` defines; a request is a
+//! single message. Requests carry the same bearer tokens as the HTTP routes, in the
+//! `authorization` metadata.
+use bytes::{BufMut, Bytes, BytesMut};
+use hyper::body::{HttpBody, Sender};
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{debug, info};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+use crate::auth::AuthConfig;
+use crate::error::CacheError;
+use crate::protobuf::DecodeError;
+
+/// Largest request message taken, the default limit of gRPC implementations.
+pub const MAX_REQUEST_LEN: usize = 4 * 1024 * 1024;
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// The gRPC port of every node is its web port plus this offset, so that a node can
+    /// tell callers where the owner of a key takes gRPC requests.
+    pub port_offset: u16,
+    /// Also serve Arrow Flight `DoGet` for Parquet objects; see `flight`.
+    pub flight: bool,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        GrpcConfig {
+            port_offset: 2000,
+            flight: false,
+        }
+    }
+}
+
+/// Status codes, as numbered by gRPC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    Ok = 0,
+    Cancelled = 1,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 16,
+}
+
+/// How a call ended, sent in the trailers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub code: Code,
+    pub message: String,
+}
+
+impl Status {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for Status {}
+
+impl From<DecodeError> for Status {
+    fn from(e: DecodeError) -> Self {
+        Status::new(Code::InvalidArgument, e.to_string())
+    }
+}
+
+impl From<io::Error> for Status {
+    fn from(e: io::Error) -> Self {
+        let code = match e.kind() {
+            io::ErrorKind::NotFound => Code::NotFound,
+            io::ErrorKind::InvalidInput => Code::InvalidArgument,
+            io::ErrorKind::TimedOut => Code::DeadlineExceeded,
+            io::ErrorKind::WouldBlock => Code::Unavailable,
+            _ => Code::Internal,
+        };
+        Status::new(code, e.to_string())
+    }
+}
+
+impl From<CacheError> for Status {
+    fn from(e: CacheError) -> Self {
+        let code = match e {
+            CacheError::NotFound { .. } => Code::NotFound,
+            CacheError::InvalidKey { .. } => Code::InvalidArgument,
+            CacheError::RangeNotSatisfiable { .. } => Code::OutOfRange,
+            CacheError::Upstream { .. } | CacheError::Overloaded { .. } => Code::Unavailable,
+            CacheError::Timeout { .. } => Code::DeadlineExceeded,
+            CacheError::Internal { .. } => Code::Internal,
+        };
+        Status::new(code, e.to_string())
+    }
+}
+
+/// The response stream of a call.
+pub struct Replies {
+    sender: Sender,
+}
+
+impl Replies {
+    /// Sends `message`; fails once the caller has gone away.
+    pub async fn send(&mut self, message: &[u8]) -> Result<(), Status> {
+        let mut frame = BytesMut::with_capacity(5 + message.len());
+        frame.put_u8(0);
+        frame.put_u32(message.len() as u32);
+        frame.put_slice(message);
+        self.sender
+            .send_data(frame.freeze())
+            .await
+            .map_err(|_| Status::new(Code::Cancelled, "the caller went away"))
+    }
+}
+
+/// A gRPC service: the methods of one `service` of a `.proto`.
+#[rocket::async_trait]
+pub trait Service: Send + Sync {
+    /// Full name of the service, e.g. `istziio.cache.v1.Cache`.
+    fn name(&self) -> &'static str;
+
+    /// Handles `method` with the encoded `request`, sending the responses to `replies`.
+    async fn call(&self, method: &str, request: Bytes, replies: &mut Replies)
+        -> Result<(), Status>;
+}
+
+/// The services of one listener, behind the node's read tokens.
+pub struct GrpcServer {
+    services: Vec<Arc<dyn Service>>,
+    auth: AuthConfig,
+}
+
+impl GrpcServer {
+    pub fn new(auth: AuthConfig) -> Self {
+        GrpcServer {
+            services: Vec::new(),
+            auth,
+        }
+    }
+
+    pub fn with_service(mut self, service: Arc<dyn Service>) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        if self.auth.read_tokens.is_empty() {
+            return true;
+        }
+        headers
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| self.auth.recognizes(token))
+    }
+
+    /// Answers one HTTP/2 request as a gRPC call.
+    pub async fn handle(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        let is_grpc = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(GRPC_CONTENT_TYPE));
+        if request.method() != Method::POST || !is_grpc {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            return response;
+        }
+        let (sender, body) = Body::channel();
+        let mut response = Response::new(body);
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_CONTENT_TYPE));
+        tokio::spawn(async move {
+            let mut replies = Replies { sender };
+            let status = match self.dispatch(request, &mut replies).await {
+                Ok(()) => Status::new(Code::Ok, ""),
+                Err(status) => status,
+            };
+            let _ = replies.sender.send_trailers(trailers(&status)).await;
+        });
+        response
+    }
+
+    async fn dispatch(&self, request: Request<Body>, replies: &mut Replies) -> Result<(), Status> {
+        let path = request.uri().path().trim_start_matches('/').to_string();
+        let (service, method) = path
+            .split_once('/')
+            .ok_or_else(|| Status::new(Code::Unimplemented, format!("no method {}", path)))?;
+        let service = self
+            .services
+            .iter()
+            .find(|candidate| candidate.name() == service)
+            .ok_or_else(|| Status::new(Code::Unimplemented, format!("no service {}", service)))?;
+        if !self.authorized(request.headers()) {
+            return Err(Status::new(
+                Code::Unauthenticated,
+                "missing or invalid read token",
+            ));
+        }
+        let message = read_message(request.into_body()).await?;
+        service.call(method, message, replies).await
+    }
+}
+
+/// Reads the one message of a request body.
+async fn read_message(mut body: Body) -> Result<Bytes, Status> {
+    let mut data = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| Status::new(Code::Cancelled, e.to_string()))?;
+        if data.len() + chunk.len() > MAX_REQUEST_LEN + 5 {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                format!("requests are limited to {} bytes", MAX_REQUEST_LEN),
+            ));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if data.len() < 5 {
+        return Err(Status::new(Code::InvalidArgument, "no request message"));
+    }
+    if data[0] != 0 {
+        return Err(Status::new(
+            Code::Unimplemented,
+            "compressed messages are not supported",
+        ));
+    }
+    let len = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    if data.len() != 5 + len {
+        return Err(Status::new(
+            Code::InvalidArgument,
+            "the request is not exactly one message",
+        ));
+    }
+    Ok(data.freeze().split_off(5))
+}
+
+fn trailers(status: &Status) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(status.code as u32));
+    if !status.message.is_empty() {
+        // Always valid: every byte outside printable ASCII is percent-encoded.
+        if let Ok(message) = HeaderValue::from_str(&percent_encode(&status.message)) {
+            trailers.insert("grpc-message", message);
+        }
+    }
+    trailers
+}
+
+/// `grpc-message` is percent-encoded UTF-8.
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Serves `server` on `addr` until the listener fails to bind, over TLS when `tls` is
+/// set.
+pub async fn serve(server: Arc<GrpcServer>, addr: String, tls: Option<TlsAcceptor>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Failed to bind the gRPC listener on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("gRPC listener on {}", addr);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                debug!("gRPC accept failed: {}", e);
+                continue;
+            }
+        };
+        let (server, tls) = (server.clone(), tls.clone());
+        tokio::spawn(async move {
+            match tls {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(tls_stream) => serve_connection(server, tls_stream).await,
+                    Err(e) => debug!("TLS handshake with gRPC client {} failed: {}", peer, e),
+                },
+                None => serve_connection(server, stream).await,
+            }
+        });
+    }
+}
+
+async fn serve_connection<S>(server: Arc<GrpcServer>, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |request| {
+        let server = server.clone();
+        async move { Ok::<_, Infallible>(server.handle(request).await) }
+    });
+    if let Err(e) = Http::new()
+        .http2_only(true)
+        .serve_connection(stream, service)
+        .await
+    {
+        debug!("gRPC connection failed: {}", e);
+    }
+}
@@ -0,0 +1,173 @@
+// health.rs
+//! Probes for orchestrators and load balancers. Liveness asks whether the process still
+//! makes progress, so that a stuck node is restarted; readiness asks whether the node can
+//! serve, so that traffic is held back from it until it can. Both answer `200` or `503`
+//! with the result of each check.
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{get, State};
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::cache::ConcurrentDiskCache;
+use crate::storage::storage_connector::StorageConnector;
+use crate::warmup::WarmUp;
+
+/// Key readiness looks up in the backing store. It need not exist: being told it doesn't
+/// is enough to show that the store is reachable and takes the credentials.
+pub const S3_PROBE_KEY: &str = ".istziio-readiness-probe";
+/// How long a backing store check is reused, so that probes don't each cost a request.
+pub const S3_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// How long liveness waits for a lock before deciding its holder is stuck.
+pub const LIVENESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<(), String>) -> Self {
+        Check {
+            name,
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl HealthReport {
+    fn new(checks: Vec<Check>) -> Self {
+        HealthReport {
+            ok: checks.iter().all(|check| check.ok),
+            checks,
+        }
+    }
+}
+
+/// Runs the checks, remembering the last backing store check for `S3_CHECK_INTERVAL`.
+#[derive(Default)]
+pub struct HealthProbes {
+    last_s3_check: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+impl HealthProbes {
+    /// Whether every shard and the metadata store's lock can still be taken.
+    pub async fn liveness(&self, cache: &ConcurrentDiskCache) -> HealthReport {
+        let responsive = match cache.is_responsive(LIVENESS_TIMEOUT).await {
+            true => Ok(()),
+            false => Err(format!("a lock was held for over {:?}", LIVENESS_TIMEOUT)),
+        };
+        HealthReport::new(vec![Check::new("shards", responsive)])
+    }
+
+    /// Whether the warm-up lets traffic in, the metadata store answers with the slot
+    /// mapping built, a cache directory takes writes, and the backing store takes the
+    /// credentials.
+    pub async fn readiness(
+        &self,
+        cache: &ConcurrentDiskCache,
+        connector: Option<&Arc<dyn StorageConnector + Send + Sync>>,
+        warm_up: Option<&WarmUp>,
+    ) -> HealthReport {
+        let mut checks = Vec::new();
+        if let Some(warm_up) = warm_up {
+            let warmed = match warm_up.holds_traffic() {
+                true => Err(String::from("warming up")),
+                false => Ok(()),
+            };
+            checks.push(Check::new("warm_up", warmed));
+        }
+        checks.push(Check::new(
+            "metadata",
+            cache.check_metadata().await.map_err(|e| e.to_string()),
+        ));
+        let disks = cache.check_disks().await;
+        let failing: Vec<String> = disks
+            .iter()
+            .filter(|(_, ok)| !ok)
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        // A node with one disk left still serves, from that disk and S3.
+        let writable = match failing.len() < disks.len() {
+            true => Ok(()),
+            false => Err(format!(
+                "no cache directory is writable: {}",
+                failing.join(", ")
+            )),
+        };
+        checks.push(Check::new("disks", writable));
+        if let Some(connector) = connector {
+            checks.push(Check::new("s3", self.check_s3(connector).await));
+        }
+        HealthReport::new(checks)
+    }
+
+    async fn check_s3(
+        &self,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+    ) -> Result<(), String> {
+        let mut last = self.last_s3_check.lock().await;
+        if let Some((at, result)) = last.as_ref() {
+            if at.elapsed() < S3_CHECK_INTERVAL {
+                return result.clone();
+            }
+        }
+        let result = check_backing_store(connector.as_ref()).await;
+        *last = Some((Instant::now(), result.clone()));
+        result
+    }
+}
+
+/// Looks up `S3_PROBE_KEY` in the backing store; finding it missing is a success.
+pub async fn check_backing_store(
+    connector: &(dyn StorageConnector + Send + Sync),
+) -> Result<(), String> {
+    match connector.head_object(S3_PROBE_KEY).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn respond(report: HealthReport) -> Custom<Json<HealthReport>> {
+    let status = match report.ok {
+        true => Status::Ok,
+        false => Status::ServiceUnavailable,
+    };
+    Custom(status, Json(report))
+}
+
+#[get("/healthz/live")]
+pub async fn live(
+    probes: &State<HealthProbes>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Custom<Json<HealthReport>> {
+    respond(probes.liveness(cache).await)
+}
+
+#[get("/healthz/ready")]
+pub async fn ready(
+    probes: &State<HealthProbes>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+    warm_up: &State<Option<Arc<WarmUp>>>,
+) -> Custom<Json<HealthReport>> {
+    let report = probes
+        .readiness(cache, s3_connectors.first(), warm_up.as_deref())
+        .await;
+    respond(report)
+}
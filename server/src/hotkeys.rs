@@ -0,0 +1,230 @@
+// hotkeys.rs
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// Keys each shard keeps candidates for, per ranking.
+pub const HOT_KEYS_PER_SHARD: usize = 64;
+const SKETCH_WIDTH: usize = 2048;
+const SKETCH_DEPTH: usize = 4;
+
+/// Count-min sketch: estimates never undercount, and overcount by little as long as the
+/// width is large compared to the number of heavy keys.
+#[derive(Debug, Clone)]
+pub struct CountMinSketch {
+    width: usize,
+    rows: Vec<Vec<u64>>,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        Self {
+            width,
+            rows: vec![vec![0; width]; depth],
+        }
+    }
+
+    fn column(&self, key: &str, row: usize) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.width
+    }
+
+    /// Adds `amount` to `key` and returns its new estimate.
+    pub fn add(&mut self, key: &str, amount: u64) -> u64 {
+        let mut estimate = u64::MAX;
+        for row in 0..self.rows.len() {
+            let column = self.column(key, row);
+            let counter = &mut self.rows[row][column];
+            *counter = counter.saturating_add(amount);
+            estimate = estimate.min(*counter);
+        }
+        estimate
+    }
+
+    pub fn estimate(&self, key: &str) -> u64 {
+        (0..self.rows.len())
+            .map(|row| self.rows[row][self.column(key, row)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn clear(&mut self) {
+        for row in &mut self.rows {
+            row.iter_mut().for_each(|counter| *counter = 0);
+        }
+    }
+}
+
+/// The `k` keys with the largest estimates seen so far. Scores only grow, so a min-heap
+/// over the candidates finds the one to replace; entries left behind by score updates
+/// are skipped when they surface and dropped when the heap is rebuilt.
+#[derive(Debug, Clone)]
+pub struct TopK {
+    k: usize,
+    scores: HashMap<String, u64>,
+    heap: BinaryHeap<Reverse<(u64, String)>>,
+}
+
+impl TopK {
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            scores: HashMap::new(),
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    /// Offers `key` with its current estimate.
+    pub fn offer(&mut self, key: &str, score: u64) {
+        if self.k == 0 {
+            return;
+        }
+        if let Some(current) = self.scores.get_mut(key) {
+            *current = score;
+        } else if self.scores.len() < self.k {
+            self.scores.insert(key.to_string(), score);
+        } else {
+            match self.min_score() {
+                Some(min) if score > min => {
+                    if let Some(Reverse((_, evicted))) = self.heap.pop() {
+                        self.scores.remove(&evicted);
+                    }
+                    self.scores.insert(key.to_string(), score);
+                }
+                _ => return,
+            }
+        }
+        self.heap.push(Reverse((score, key.to_string())));
+        if self.heap.len() > 4 * self.k {
+            self.heap = self
+                .scores
+                .iter()
+                .map(|(key, score)| Reverse((*score, key.clone())))
+                .collect();
+        }
+    }
+
+    /// Lowest current score, after discarding outdated heap entries.
+    fn min_score(&mut self) -> Option<u64> {
+        while let Some(Reverse((score, key))) = self.heap.peek() {
+            if self.scores.get(key) == Some(score) {
+                return Some(*score);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    /// Candidates, highest score first.
+    pub fn ranked(&self) -> Vec<(String, u64)> {
+        let mut ranked = self
+            .scores
+            .iter()
+            .map(|(key, score)| (key.clone(), *score))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked
+    }
+
+    pub fn clear(&mut self) {
+        self.scores.clear();
+        self.heap.clear();
+    }
+}
+
+/// A key with its estimated access count and bytes served.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HotKey {
+    pub key: String,
+    pub accesses: u64,
+    pub bytes: u64,
+}
+
+/// Per-shard tracker of the most accessed keys and the keys serving the most bytes.
+#[derive(Debug, Clone)]
+pub struct HotKeys {
+    accesses: CountMinSketch,
+    bytes: CountMinSketch,
+    top_accesses: TopK,
+    top_bytes: TopK,
+}
+
+impl Default for HotKeys {
+    fn default() -> Self {
+        Self::new(HOT_KEYS_PER_SHARD)
+    }
+}
+
+impl HotKeys {
+    pub fn new(k: usize) -> Self {
+        Self {
+            accesses: CountMinSketch::new(SKETCH_WIDTH, SKETCH_DEPTH),
+            bytes: CountMinSketch::new(SKETCH_WIDTH, SKETCH_DEPTH),
+            top_accesses: TopK::new(k),
+            top_bytes: TopK::new(k),
+        }
+    }
+
+    /// Counts one access to `key` that served `bytes`.
+    pub fn record(&mut self, key: &str, bytes: u64) {
+        let accesses = self.accesses.add(key, 1);
+        self.top_accesses.offer(key, accesses);
+        let bytes = self.bytes.add(key, bytes);
+        self.top_bytes.offer(key, bytes);
+    }
+
+    fn hot_key(&self, key: String) -> HotKey {
+        HotKey {
+            accesses: self.accesses.estimate(&key),
+            bytes: self.bytes.estimate(&key),
+            key,
+        }
+    }
+
+    /// Up to `n` keys by access count, most accessed first.
+    pub fn by_accesses(&self, n: usize) -> Vec<HotKey> {
+        self.top_accesses
+            .ranked()
+            .into_iter()
+            .take(n)
+            .map(|(key, _)| self.hot_key(key))
+            .collect()
+    }
+
+    /// Up to `n` keys by bytes served, largest first.
+    pub fn by_bytes(&self, n: usize) -> Vec<HotKey> {
+        self.top_bytes
+            .ranked()
+            .into_iter()
+            .take(n)
+            .map(|(key, _)| self.hot_key(key))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.accesses.clear();
+        self.bytes.clear();
+        self.top_accesses.clear();
+        self.top_bytes.clear();
+    }
+}
+
+/// The top `n` of per-shard lists, each already sorted by `score`. Keys are hashed to a
+/// single shard, so the lists never overlap.
+pub fn merge_top(lists: Vec<Vec<HotKey>>, n: usize, score: fn(&HotKey) -> u64) -> Vec<HotKey> {
+    let mut merged = lists.into_iter().flatten().collect::<Vec<_>>();
+    merged.sort_by(|a, b| score(b).cmp(&score(a)).then_with(|| a.key.cmp(&b.key)));
+    merged.truncate(n);
+    merged
+}
+
+/// Response of `/stats/hotkeys`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HotKeysReport {
+    pub by_accesses: Vec<HotKey>,
+    pub by_bytes: Vec<HotKey>,
+}
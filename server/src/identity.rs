@@ -0,0 +1,64 @@
+// identity.rs
+//! The node's own id, kept in its cache directory so that it outlives both the node
+//! process and its Redis. A Redis that restarts without its `nodes.conf` comes back with
+//! a new cluster id; the entries the node registers under this id stay its own.
+use log::info;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fs;
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::path::Path;
+
+/// File in the cache directory holding the node id; scrubbing leaves it alone.
+pub const NODE_ID_FILE: &str = ".istziio-node-id";
+
+/// A random (version 4) UUID.
+pub fn new_uuid() -> IoResult<String> {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| Error::other("no randomness available"))?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// Whether `s` is a UUID in the lowercase form `new_uuid` writes.
+pub fn is_uuid(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_digit() || ('a'..='f').contains(&c),
+        })
+}
+
+/// The node id kept in `dir`, created there on the node's first start.
+pub fn load_or_create(dir: &Path) -> IoResult<String> {
+    let path = dir.join(NODE_ID_FILE);
+    match fs::read_to_string(&path) {
+        Ok(id) if is_uuid(id.trim()) => return Ok(id.trim().to_string()),
+        Ok(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} does not hold a node id", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let id = new_uuid()?;
+    fs::create_dir_all(dir)?;
+    // Renamed into place, so that a crash never leaves half an id behind.
+    let staged = dir.join(format!("{}.tmp", NODE_ID_FILE));
+    fs::write(&staged, format!("{}\n", id))?;
+    fs::rename(&staged, &path)?;
+    info!("Created node id {} in {}", id, path.display());
+    Ok(id)
+}
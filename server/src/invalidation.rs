@@ -0,0 +1,150 @@
+// invalidation.rs
+//! Invalidation over Redis pub/sub. Every node subscribes to one channel and drops the
+//! keys named in the messages, so whoever rewrites data (a node, or an ingestion job with
+//! `redis-cli PUBLISH`) needn't know which nodes hold what.
+use log::{info, warn};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::{post, State};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::auth::AdminAccess;
+use crate::cache::ConcurrentDiskCache;
+
+pub const DEFAULT_CHANNEL: &str = "istziio:invalidate";
+
+/// How long the subscriber waits before reconnecting after losing Redis.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// A message on the channel: `key <key>` or `prefix <prefix>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+    Key(String),
+    Prefix(String),
+}
+
+impl Invalidation {
+    pub fn parse(message: &str) -> Option<Self> {
+        match message.split_once(' ') {
+            Some(("key", key)) if !key.is_empty() => Some(Invalidation::Key(key.to_string())),
+            Some(("prefix", prefix)) => Some(Invalidation::Prefix(prefix.to_string())),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        match self {
+            Invalidation::Key(key) => format!("key {}", key),
+            Invalidation::Prefix(prefix) => format!("prefix {}", prefix),
+        }
+    }
+
+    /// Drops the matching entries from this node; returns the on-disk bytes freed.
+    pub async fn apply(&self, cache: &ConcurrentDiskCache) -> u64 {
+        match self {
+            Invalidation::Key(key) => cache.invalidate(key).await,
+            Invalidation::Prefix(prefix) => cache.invalidate_prefix(prefix).await,
+        }
+    }
+}
+
+/// The channel this node publishes to and listens on; managed as Rocket state.
+pub struct InvalidationBus {
+    pub channel: Option<String>,
+}
+
+/// Listens on `channel` for as long as the process runs. PUBLISH reaches every node of a
+/// Redis cluster, so one connection suffices; on errors it moves on to the next address.
+pub fn subscribe(addrs: Vec<String>, channel: String, cache: Arc<ConcurrentDiskCache>) {
+    let (tx, mut rx) = mpsc::channel::<Invalidation>(1024);
+    std::thread::Builder::new()
+        .name(String::from("invalidation-bus"))
+        .spawn(move || {
+            for addr in addrs.iter().cycle() {
+                if let Err(e) = listen(addr, &channel, &tx) {
+                    warn!("Invalidation bus on {}: {}", addr, e);
+                }
+                if tx.is_closed() {
+                    return;
+                }
+                std::thread::sleep(RECONNECT_DELAY);
+            }
+        })
+        .expect("failed to start the invalidation bus thread");
+    tokio::spawn(async move {
+        while let Some(invalidation) = rx.recv().await {
+            let freed = invalidation.apply(&cache).await;
+            info!(
+                "Invalidated {:?} from the bus, freed {} bytes",
+                invalidation, freed
+            );
+        }
+    });
+}
+
+fn listen(
+    addr: &str,
+    channel: &str,
+    tx: &mpsc::Sender<Invalidation>,
+) -> Result<(), redis::RedisError> {
+    let mut conn = redis::Client::open(addr)?.get_connection()?;
+    let mut pubsub = conn.as_pubsub();
+    pubsub.subscribe(channel)?;
+    info!("Listening for invalidations on {} via {}", channel, addr);
+    loop {
+        let payload: String = pubsub.get_message()?.get_payload()?;
+        match Invalidation::parse(&payload) {
+            Some(invalidation) => {
+                if tx.blocking_send(invalidation).is_err() {
+                    return Ok(());
+                }
+            }
+            None => warn!("Ignoring malformed invalidation message {:?}", payload),
+        }
+    }
+}
+
+async fn publish(
+    bus: &InvalidationBus,
+    cache: &ConcurrentDiskCache,
+    invalidation: Invalidation,
+) -> Result<String, Custom<String>> {
+    let channel = bus.channel.as_deref().ok_or_else(|| {
+        Custom(
+            Status::NotFound,
+            String::from("invalidation bus is not configured"),
+        )
+    })?;
+    let receivers = cache
+        .publish(channel, &invalidation.encode())
+        .await
+        .map_err(|e| Custom(Status::ServiceUnavailable, e.to_string()))?;
+    Ok(format!("published to {} subscribers\n", receivers))
+}
+
+/// Has every subscribed node drop `key`.
+#[post("/admin/broadcast/invalidate/<key..>")]
+pub async fn broadcast_invalidate(
+    _admin: AdminAccess,
+    key: PathBuf,
+    bus: &State<InvalidationBus>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<String, Custom<String>> {
+    let key = key.to_string_lossy().into_owned();
+    publish(bus, cache, Invalidation::Key(key)).await
+}
+
+/// Has every subscribed node drop the keys starting with `prefix`.
+#[post("/admin/broadcast/invalidate-prefix/<prefix..>")]
+pub async fn broadcast_invalidate_prefix(
+    _admin: AdminAccess,
+    prefix: PathBuf,
+    bus: &State<InvalidationBus>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<String, Custom<String>> {
+    let prefix = prefix.to_string_lossy().into_owned();
+    publish(bus, cache, Invalidation::Prefix(prefix)).await
+}
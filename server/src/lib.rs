@@ -1,5 +1,68 @@
+pub mod access_log;
+pub mod admission;
+pub mod auth;
+pub mod bootstrap;
+pub mod breaker;
 pub mod cache;
+pub mod cache_core;
+pub mod cache_service;
+pub mod chunk;
+pub mod cluster;
+pub mod compression;
+pub mod conditional;
+pub mod config;
+pub mod costs;
+pub mod dashboard;
+pub mod deadline;
+pub mod disk_io;
+pub mod disks;
+pub mod download;
+pub mod egress;
+pub mod embedded;
+pub mod embedded_redis;
+pub mod encryption;
+pub mod error;
+pub mod etcd;
+pub mod eviction;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
+pub mod flight;
+pub mod footer;
+pub mod grpc;
+pub mod health;
+pub mod hotkeys;
+pub mod identity;
+pub mod invalidation;
+pub mod listing;
+pub mod location_cache;
+pub mod logging;
+pub mod memory_cache;
+pub mod metadata;
+pub mod metrics;
+pub mod mock_redis;
+pub mod policy;
+pub mod prefixes;
+pub mod priority;
+pub mod protobuf;
+pub mod rate_limit;
+pub mod read_through;
+pub mod rebalance;
 pub mod redis;
+pub mod ring;
+pub mod s3_api;
+pub mod scrub;
+pub mod selftest;
 pub mod server;
+pub mod shadow;
+pub mod shard_runtime;
+pub mod snapshot;
 pub mod storage;
+pub mod telemetry;
+pub mod tenant;
+pub mod tls;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
 pub mod util;
+pub mod versioning;
+pub mod warmup;
+pub mod writeback;
@@ -0,0 +1,181 @@
+// listing.rs
+//! Directory-style listings. `GET /list/<prefix..>` answers one page of the backing
+//! store's listing under `<prefix>/`, with each key marked as cached or not, for query
+//! engines pruning partitions. Partitions are listed far more often than they change, so
+//! with `list_cache_ttl_secs` set, pages are kept that long, for `/list` and the S3 API's
+//! ListObjectsV2 alike; with `share_list_cache`, in Redis too. Invalidating a key, or
+//! writing one through write-back, drops the pages that could list it.
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{get, State};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::ReadAccess;
+use crate::cache::ConcurrentDiskCache;
+use crate::rate_limit::ClientQuota;
+use crate::s3_api::{connector_for, MAX_KEYS};
+use crate::storage::storage_connector::{ListRequest, ObjectListing, StorageConnector};
+
+/// Pages kept at most; the oldest is dropped to make room.
+pub const MAX_CACHED_LISTINGS: usize = 1024;
+
+/// An object of a listing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListedObject {
+    pub key: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch.
+    pub last_modified: Option<i64>,
+    pub e_tag: Option<String>,
+    /// Whether the metadata store knows of a cached copy, on this node or another.
+    pub cached: bool,
+}
+
+/// A page of `GET /list`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Listing {
+    pub prefix: String,
+    pub objects: Vec<ListedObject>,
+    pub common_prefixes: Vec<String>,
+    /// Pass as `continuation_token` for the next page; the listing is complete when unset.
+    pub next_continuation_token: Option<String>,
+}
+
+/// Pages of the backing store's listings, kept for a while.
+pub struct ListingCache {
+    ttl: Option<Duration>,
+    pages: Mutex<HashMap<ListRequest, (Instant, ObjectListing)>>,
+}
+
+impl ListingCache {
+    /// Keeps pages for `ttl`; keeps none when unset.
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            ttl,
+            pages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long pages are kept.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// The page for `request`, if one was stored less than the TTL ago.
+    pub fn get(&self, request: &ListRequest) -> Option<ObjectListing> {
+        let ttl = self.ttl?;
+        let pages = self.pages.lock().unwrap();
+        let (stored_at, listing) = pages.get(request)?;
+        (stored_at.elapsed() < ttl).then(|| listing.clone())
+    }
+
+    pub fn insert(&self, request: ListRequest, listing: ObjectListing) {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let mut pages = self.pages.lock().unwrap();
+        pages.retain(|_, (stored_at, _)| stored_at.elapsed() < ttl);
+        if pages.len() >= MAX_CACHED_LISTINGS {
+            let oldest = pages
+                .iter()
+                .min_by_key(|(_, (stored_at, _))| *stored_at)
+                .map(|(request, _)| request.clone());
+            if let Some(oldest) = oldest {
+                pages.remove(&oldest);
+            }
+        }
+        pages.insert(request, (Instant::now(), listing));
+    }
+
+    /// Drops the pages that could list `key`.
+    pub fn invalidate(&self, key: &str) {
+        self.pages
+            .lock()
+            .unwrap()
+            .retain(|request, _| !key.starts_with(&request.prefix));
+    }
+
+    /// Drops the pages that could list a key starting with `prefix`.
+    pub fn invalidate_prefix(&self, prefix: &str) {
+        self.pages.lock().unwrap().retain(|request, _| {
+            !prefix.starts_with(&request.prefix) && !request.prefix.starts_with(prefix)
+        });
+    }
+
+    pub fn clear(&self) {
+        self.pages.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The name a page for `request` is kept under in the metadata store.
+pub fn shared_key(request: &ListRequest) -> String {
+    let fields = [
+        Some(request.prefix.as_str()),
+        request.delimiter.as_deref(),
+        request.continuation_token.as_deref(),
+        request.start_after.as_deref(),
+    ];
+    let mut encoded = request.max_keys.to_string();
+    for field in fields {
+        // Length-prefixed, and telling an unset field from an empty one.
+        match field {
+            Some(field) => encoded.push_str(&format!(",{}:{}", field.len(), field)),
+            None => encoded.push_str(",-"),
+        }
+    }
+    hex::encode(ring::digest::digest(
+        &ring::digest::SHA256,
+        encoded.as_bytes(),
+    ))
+}
+
+/// Lists the directory `<prefix>/`, or the top level for `/list/`. `delimiter` defaults
+/// to `/`; an empty one lists every key under the prefix.
+#[allow(clippy::too_many_arguments)]
+#[get("/list/<prefix..>?<delimiter>&<continuation_token>&<max_keys>")]
+pub async fn list(
+    _auth: ReadAccess,
+    _quota: ClientQuota,
+    prefix: PathBuf,
+    delimiter: Option<String>,
+    continuation_token: Option<String>,
+    max_keys: Option<u32>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> Result<Json<Listing>, Custom<String>> {
+    let mut prefix = prefix.to_string_lossy().into_owned();
+    if !prefix.is_empty() {
+        prefix.push('/');
+    }
+    let request = ListRequest {
+        prefix,
+        delimiter: Some(delimiter.unwrap_or_else(|| String::from("/"))).filter(|d| !d.is_empty()),
+        continuation_token,
+        start_after: None,
+        max_keys: max_keys.unwrap_or(MAX_KEYS).min(MAX_KEYS),
+    };
+    let connector = connector_for(&request.prefix, s3_connectors);
+    match cache.list(&request, connector).await {
+        Ok(listing) => Ok(Json(listing)),
+        Err(e) => Err(match e.kind() {
+            io::ErrorKind::Unsupported => Custom(Status::NotImplemented, e.to_string()),
+            io::ErrorKind::NotFound => Custom(Status::NotFound, e.to_string()),
+            _ => Custom(Status::BadGateway, e.to_string()),
+        }),
+    }
+}
@@ -0,0 +1,154 @@
+// location_cache.rs
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::util::FileUid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocationCacheConfig {
+    /// Answers kept at most; the least recently used go first.
+    pub capacity: usize,
+    /// How long an answer is trusted, which bounds how stale it gets when another node
+    /// changes the location.
+    pub ttl_ms: u64,
+}
+
+impl Default for LocationCacheConfig {
+    fn default() -> Self {
+        LocationCacheConfig {
+            capacity: 100_000,
+            ttl_ms: 1000,
+        }
+    }
+}
+
+/// In-process LRU of recent file location lookups, in front of the metadata store.
+///
+/// Both answers are kept: a location, and the absence of one. Writes through this node
+/// update the answer right away; anything else ages out after the TTL.
+pub struct LocationCache {
+    capacity: usize,
+    ttl: Duration,
+    tick: u64,
+    entries: HashMap<FileUid, Answer>,
+    recency: BTreeMap<u64, FileUid>,
+    hits: u64,
+    misses: u64,
+}
+
+struct Answer {
+    loc: Option<PathBuf>,
+    expires: Instant,
+    tick: u64,
+}
+
+impl LocationCache {
+    pub fn new(config: LocationCacheConfig) -> Self {
+        Self {
+            capacity: config.capacity,
+            ttl: Duration::from_millis(config.ttl_ms),
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// The cached answer for `uid`: `None` if there is none or it expired, `Some(None)`
+    /// if `uid` is known to have no location.
+    pub fn get(&mut self, uid: &str) -> Option<Option<PathBuf>> {
+        let now = Instant::now();
+        match self.entries.get(uid) {
+            Some(answer) if answer.expires > now => {
+                let loc = answer.loc.clone();
+                self.touch(uid);
+                self.hits += 1;
+                Some(loc)
+            }
+            Some(_) => {
+                self.remove(uid);
+                self.misses += 1;
+                None
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Records `loc` as the answer for `uid`, evicting the least recently used answer if
+    /// the cache is full.
+    pub fn insert(&mut self, uid: FileUid, loc: Option<PathBuf>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&uid);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.recency.insert(self.tick, uid.clone());
+        self.entries.insert(
+            uid,
+            Answer {
+                loc,
+                expires: Instant::now() + self.ttl,
+                tick: self.tick,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, uid: &str) {
+        if let Some(answer) = self.entries.remove(uid) {
+            self.recency.remove(&answer.tick);
+        }
+    }
+
+    /// Drops the answers for every key starting with `prefix`.
+    pub fn remove_prefix(&mut self, prefix: &str) {
+        let entries = &mut self.entries;
+        self.recency.retain(|_, uid| {
+            let keep = !uid.starts_with(prefix);
+            if !keep {
+                entries.remove(uid.as_str());
+            }
+            keep
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Lookups answered from the cache, and those that had to go to the store.
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    fn touch(&mut self, uid: &str) {
+        let Some(answer) = self.entries.get_mut(uid) else {
+            return;
+        };
+        self.recency.remove(&answer.tick);
+        self.tick += 1;
+        answer.tick = self.tick;
+        self.recency.insert(self.tick, uid.to_string());
+    }
+}
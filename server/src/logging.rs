@@ -0,0 +1,265 @@
+// logging.rs
+use log::kv::{self, Key, Value, VisitSource};
+use log::Record;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::fmt::{self, Write};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+use crate::deadline::{parse_timeout, TIMEOUT_HEADER};
+use crate::telemetry::{random_hex, TraceContext};
+
+/// Shape of the lines written to stdout and `output.log`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum LogFormat {
+    /// `[target][LEVEL] message key=value ...`
+    #[default]
+    Pretty,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for LogFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// What every log line emitted while serving a request is tagged with. As a request guard
+/// it picks up the request ID and trace context; routes fill in the key.
+#[derive(Debug)]
+pub struct RequestContext {
+    pub request_id: String,
+    pub trace: TraceContext,
+    pub key: String,
+    /// When the client stops waiting, from its `X-Istziio-Timeout-Ms` header.
+    pub deadline: Option<Instant>,
+    shard: Cell<Option<usize>>,
+    outcome: RequestOutcome,
+}
+
+/// How a request was answered by the cache (`hit`, `miss`, `redirect`, ...). Shared with
+/// the request's local cache so that response fairings can read it.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOutcome(Arc<Mutex<Option<&'static str>>>);
+
+impl RequestOutcome {
+    /// The outcome recorded while handling `req`, if its route recorded one.
+    pub fn of(req: &Request<'_>) -> Option<&'static str> {
+        req.local_cache(RequestOutcome::default).get()
+    }
+
+    pub fn get(&self) -> Option<&'static str> {
+        *self.0.lock().unwrap()
+    }
+
+    fn set(&self, outcome: &'static str) {
+        *self.0.lock().unwrap() = Some(outcome);
+    }
+}
+
+tokio::task_local! {
+    static REQUEST: RequestContext;
+}
+
+impl RequestContext {
+    pub fn new(request_id: String, trace: TraceContext, key: String) -> Self {
+        Self {
+            request_id,
+            trace,
+            key,
+            deadline: None,
+            shard: Cell::new(None),
+            outcome: RequestOutcome::default(),
+        }
+    }
+
+    /// Runs `fut` with this context attached to its log lines, then logs how the request
+    /// ended. `fut` is boxed: request futures nest several scopes deep, and inline they
+    /// outgrow a thread's stack.
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        let fut = Box::pin(fut);
+        REQUEST
+            .scope(self, async move {
+                let output = fut.await;
+                log::info!(target: "request", "request finished");
+                output
+            })
+            .await
+    }
+}
+
+/// The deadline of the current request, if its client set one.
+pub fn request_deadline() -> Option<Instant> {
+    REQUEST.try_with(|ctx| ctx.deadline).ok().flatten()
+}
+
+/// Notes the shard serving the current request, if any.
+pub fn record_shard(index: usize) {
+    let _ = REQUEST.try_with(|ctx| ctx.shard.set(Some(index)));
+}
+
+/// Notes how the current request was answered, e.g. `hit` or `redirect`.
+pub fn record_outcome(outcome: &'static str) {
+    let _ = REQUEST.try_with(|ctx| ctx.outcome.set(outcome));
+}
+
+/// Context fields followed by the record's own key-values, in order.
+pub fn structured_fields(record: &Record) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let _ = REQUEST.try_with(|ctx| {
+        fields.push((String::from("request_id"), ctx.request_id.clone()));
+        fields.push((String::from("trace_id"), ctx.trace.trace_id.clone()));
+        fields.push((String::from("key"), ctx.key.clone()));
+        if let Some(shard) = ctx.shard.get() {
+            fields.push((String::from("shard"), shard.to_string()));
+        }
+        if let Some(outcome) = ctx.outcome.get() {
+            fields.push((String::from("outcome"), outcome.to_string()));
+        }
+    });
+    let _ = record.key_values().visit(&mut FieldCollector(&mut fields));
+    fields
+}
+
+struct FieldCollector<'a>(&'a mut Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+/// Renders `record` as a pretty line.
+pub fn format_pretty(message: &fmt::Arguments, record: &Record) -> String {
+    let mut line = format!("[{}][{}] {}", record.target(), record.level(), message);
+    for (key, value) in structured_fields(record) {
+        let _ = write!(line, " {}={}", key, value);
+    }
+    line
+}
+
+/// Renders `record` as a JSON object tagged with `node_id`.
+pub fn format_json(node_id: &str, message: &fmt::Arguments, record: &Record) -> String {
+    let mut object = Map::new();
+    object.insert(
+        String::from("ts"),
+        JsonValue::from(chrono::Utc::now().to_rfc3339()),
+    );
+    object.insert(
+        String::from("level"),
+        JsonValue::from(record.level().as_str()),
+    );
+    object.insert(String::from("target"), JsonValue::from(record.target()));
+    object.insert(String::from("node"), JsonValue::from(node_id));
+    object.insert(String::from("msg"), JsonValue::from(message.to_string()));
+    for (key, value) in structured_fields(record) {
+        object.insert(key, JsonValue::from(value));
+    }
+    JsonValue::Object(object).to_string()
+}
+
+pub fn setup_logger(format: LogFormat, node_id: String) -> Result<(), fern::InitError> {
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            let line = match format {
+                LogFormat::Pretty => format_pretty(message, record),
+                LogFormat::Json => format_json(&node_id, message, record),
+            };
+            out.finish(format_args!("{}", line))
+        })
+        // Filtered by `log::set_max_level` instead, which `POST /admin/config` can raise.
+        .level(log::LevelFilter::Trace)
+        .chain(std::io::stdout())
+        .chain(fern::log_file("output.log")?)
+        .filter(move |metadata| {
+            // Exclude specific debug logs from hyper::proto::h1::io
+            !(metadata.target() == "hyper::proto::h1::io" && metadata.level() == log::Level::Debug)
+        })
+        .apply()?;
+    log::set_max_level(log::LevelFilter::Debug);
+    Ok(())
+}
+
+/// Header carrying the request ID; a well-formed incoming value is kept, so that IDs
+/// assigned by a client or proxy carry through.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Request guard yielding the ID this request is logged under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestContext {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        let trace = match TraceContext::from_request(req).await {
+            Outcome::Success(trace) => trace,
+            _ => TraceContext::new(),
+        };
+        let mut context = RequestContext::new(RequestId::of(req).0, trace, String::new());
+        context.outcome = req.local_cache(RequestOutcome::default).clone();
+        context.deadline = req
+            .headers()
+            .get_one(TIMEOUT_HEADER)
+            .and_then(parse_timeout)
+            .map(|timeout| Instant::now() + timeout);
+        Outcome::Success(context)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(RequestId::of(req))
+    }
+}
+
+impl RequestId {
+    /// The ID of `req`, assigned on first use and cached for the response fairing.
+    pub fn of(req: &Request<'_>) -> Self {
+        req.local_cache(|| {
+            let id = req
+                .headers()
+                .get_one(REQUEST_ID_HEADER)
+                .filter(|id| is_valid_request_id(id))
+                .map(String::from)
+                .unwrap_or_else(|| random_hex(8));
+            RequestId(id)
+        })
+        .clone()
+    }
+}
@@ -1,39 +1,69 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use istziio_server_node::admission::AdmissionPolicy;
+use istziio_server_node::bootstrap::{cluster_init, BootstrapError, DEFAULT_TIMEOUT};
+use istziio_server_node::compression::{CompressionCodec, CompressionConfig};
+use istziio_server_node::config::load_config;
+use istziio_server_node::disk_io::DiskBackend;
+use istziio_server_node::embedded_redis::EmbeddedRedisConfig;
+use istziio_server_node::encryption::EncryptionConfig;
+use istziio_server_node::grpc::GrpcConfig;
+use istziio_server_node::logging::{setup_logger, LogFormat};
+use istziio_server_node::rate_limit::RateLimitConfig;
+use istziio_server_node::ring::DEFAULT_VNODES_PER_NODE;
 use istziio_server_node::server::{ServerConfig, ServerNode};
-
-fn setup_logger() -> Result<(), fern::InitError> {
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{}][{}] {}",
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Debug)
-        .chain(std::io::stdout())
-        .chain(fern::log_file("output.log")?)
-        .filter(move |metadata| {
-            // Exclude specific debug logs from hyper::proto::h1::io
-            !(metadata.target() == "hyper::proto::h1::io" && metadata.level() == log::Level::Debug)
-        })
-        .apply()?;
-    Ok(())
-}
+use istziio_server_node::shard_runtime::ShardRuntimeConfig;
+use istziio_server_node::storage::mock_storage_connector::{GeneratedObjects, MockS3Config};
+use istziio_server_node::telemetry::TracingConfig;
+use istziio_server_node::tls::TlsConfig;
+use istziio_server_node::util::host_port;
+use std::time::Duration;
 
 #[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
+async fn main() -> Result<(), Box<rocket::Error>> {
     let matches = App::new("istziio-server-node")
         .version("1.0")
         .author("istziio")
         .about("A distributed server node to serve as cache to S3")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("TOML config file; when given, the other flags are ignored"),
+        )
         .arg(
             Arg::with_name("server_ip")
                 .long("server-ip")
                 .takes_value(true)
                 .default_value("localhost"),
         )
+        .arg(
+            Arg::with_name("web_port")
+                .long("web-port")
+                .takes_value(true)
+                .help("Port the web server listens on [default: Redis port + 20000]"),
+        )
+        .arg(
+            Arg::with_name("advertise_addr")
+                .long("advertise-addr")
+                .takes_value(true)
+                .help("host:port other nodes and redirected clients reach this node at"),
+        )
+        .arg(
+            Arg::with_name("advertise_host")
+                .long("advertise-host")
+                .takes_value(true)
+                .help("Hostname other nodes and redirected clients reach this node at"),
+        )
+        .arg(
+            Arg::with_name("embedded_redis")
+                .long("embedded-redis")
+                .help("Run and supervise this node's redis-server, configured in ./redis"),
+        )
+        .arg(
+            Arg::with_name("skip_self_test")
+                .long("skip-self-test")
+                .help("Start without checking the cache directories, Redis and S3 first"),
+        )
         .arg(
             Arg::with_name("use_mock_s3")
                 .long("use-mock-s3")
@@ -46,6 +76,30 @@ async fn main() -> Result<(), rocket::Error> {
                 .default_value("http://0.0.0.0:6333")
                 .help("Endpoint for mock S3 storage"),
         )
+        .arg(
+            Arg::with_name("mock_s3_latency_ms")
+                .long("mock-s3-latency-ms")
+                .takes_value(true)
+                .help("Milliseconds the mock S3 connector waits before the first byte"),
+        )
+        .arg(
+            Arg::with_name("mock_s3_bytes_per_sec")
+                .long("mock-s3-bytes-per-sec")
+                .takes_value(true)
+                .help("Bandwidth cap of every mock S3 response"),
+        )
+        .arg(
+            Arg::with_name("mock_s3_error_rate")
+                .long("mock-s3-error-rate")
+                .takes_value(true)
+                .help("Fraction of mock S3 fetches that fail"),
+        )
+        .arg(
+            Arg::with_name("mock_s3_generate")
+                .long("mock-s3-generate")
+                .takes_value(true)
+                .help("Generate mock S3 objects of <min>-<max> bytes instead of fetching them"),
+        )
         .arg(
             Arg::with_name("bucket")
                 .long("bucket")
@@ -86,10 +140,249 @@ async fn main() -> Result<(), rocket::Error> {
                 .default_value("3")
                 .help("Bucket size for cache management"),
         )
+        .arg(
+            Arg::with_name("admission_policy")
+                .long("admission-policy")
+                .takes_value(true)
+                .default_value("always")
+                .help("Admission policy for missed objects: always | second-hit[:<window secs>]"),
+        )
+        .arg(
+            Arg::with_name("thread_per_shard")
+                .long("thread-per-shard")
+                .help("Run every shard's requests on a single-threaded runtime of its own"),
+        )
+        .arg(
+            Arg::with_name("pin_cores")
+                .long("pin-cores")
+                .requires("thread_per_shard")
+                .help("Pin every shard thread to a core"),
+        )
+        .arg(
+            Arg::with_name("max_cacheable_object_size")
+                .long("max-cacheable-object-size")
+                .takes_value(true)
+                .help("Objects larger than this many bytes are streamed without being cached"),
+        )
+        .arg(
+            Arg::with_name("memory_tier_size")
+                .long("memory-tier-size")
+                .takes_value(true)
+                .default_value("0")
+                .help("Bytes of RAM for the hot object tier, 0 disables it"),
+        )
+        .arg(
+            Arg::with_name("memory_tier_max_object_size")
+                .long("memory-tier-max-object-size")
+                .takes_value(true)
+                .default_value("1048576")
+                .help("Largest object in bytes kept in the memory tier"),
+        )
+        .arg(
+            Arg::with_name("chunk_size")
+                .long("chunk-size")
+                .takes_value(true)
+                .help("Serve Range requests from cached chunks of this many bytes"),
+        )
+        .arg(
+            Arg::with_name("parquet_footer_prefetch")
+                .long("parquet-footer-prefetch")
+                .takes_value(true)
+                .help("Eagerly cache Parquet footers, reading this many trailing bytes first"),
+        )
+        .arg(
+            Arg::with_name("stream_fetches_from")
+                .long("stream-fetches-from")
+                .takes_value(true)
+                .help("Send misses on objects of at least this many bytes while they download"),
+        )
+        .arg(
+            Arg::with_name("disk_io")
+                .long("disk-io")
+                .takes_value(true)
+                .possible_values(["tokio", "io-uring"])
+                .default_value("tokio")
+                .help("Disk I/O backend for cached files"),
+        )
+        .arg(
+            Arg::with_name("drop_page_cache_from")
+                .long("drop-page-cache-from")
+                .takes_value(true)
+                .help("Keep cached files of at least this many bytes out of the page cache"),
+        )
+        .arg(
+            Arg::with_name("compression")
+                .long("compression")
+                .takes_value(true)
+                .possible_values(["zstd", "lz4"])
+                .help("Compress cached objects on disk with this codec"),
+        )
+        .arg(
+            Arg::with_name("compression_min_size")
+                .long("compression-min-size")
+                .takes_value(true)
+                .help("Objects smaller than this many bytes are stored uncompressed"),
+        )
+        .arg(
+            Arg::with_name("compression_skip_extensions")
+                .long("compression-skip-extensions")
+                .takes_value(true)
+                .help("Comma-separated extensions of already-compressed formats to store as-is"),
+        )
+        .arg(
+            Arg::with_name("max_concurrent_s3_fetches")
+                .long("max-concurrent-s3-fetches")
+                .takes_value(true)
+                .help("Limit on S3 fetches open at once; unlimited when omitted"),
+        )
+        .arg(
+            Arg::with_name("s3_fetch_queue_timeout_ms")
+                .long("s3-fetch-queue-timeout-ms")
+                .takes_value(true)
+                .default_value("10000")
+                .help("How long a miss may wait for a free S3 fetch slot before failing with 503"),
+        )
+        .arg(
+            Arg::with_name("max_batch_s3_fetches")
+                .long("max-batch-s3-fetches")
+                .takes_value(true)
+                .help(
+                    "Limit on S3 fetches batch requests may hold at once; all of them when omitted",
+                ),
+        )
+        .arg(
+            Arg::with_name("rate_limit")
+                .long("rate-limit")
+                .takes_value(true)
+                .help("Requests per second allowed to each client on the data routes"),
+        )
+        .arg(
+            Arg::with_name("rate_limit_burst")
+                .long("rate-limit-burst")
+                .takes_value(true)
+                .help("Burst size of the per-client rate limit; defaults to one second's worth"),
+        )
+        .arg(
+            Arg::with_name("tls_cert")
+                .long("tls-cert")
+                .takes_value(true)
+                .requires("tls_key")
+                .help("PEM certificate chain; serves HTTPS when given"),
+        )
+        .arg(
+            Arg::with_name("tls_key")
+                .long("tls-key")
+                .takes_value(true)
+                .requires("tls_cert")
+                .help("PEM private key matching --tls-cert"),
+        )
+        .arg(
+            Arg::with_name("tls_client_ca")
+                .long("tls-client-ca")
+                .takes_value(true)
+                .requires("tls_cert")
+                .help("PEM CA bundle; clients and peers must present a certificate it signed"),
+        )
+        .arg(
+            Arg::with_name("trace_spans")
+                .long("trace-spans")
+                .help("Log the duration of each step of a request, tagged with its trace ID"),
+        )
+        .arg(
+            Arg::with_name("trace_min_span_ms")
+                .long("trace-min-span-ms")
+                .takes_value(true)
+                .requires("trace_spans")
+                .help("Only log spans that take at least this long"),
+        )
+        .arg(
+            Arg::with_name("otlp_endpoint")
+                .long("otlp-endpoint")
+                .takes_value(true)
+                .requires("trace_spans")
+                .help("OpenTelemetry collector to export spans to over OTLP/HTTP, e.g. http://localhost:4318"),
+        )
+        .arg(
+            Arg::with_name("grpc")
+                .long("grpc")
+                .help("Serve the gRPC data plane on the web port plus 2000"),
+        )
+        .arg(
+            Arg::with_name("flight")
+                .long("flight")
+                .requires("grpc")
+                .help("Also serve Arrow Flight DoGet for Parquet objects over gRPC"),
+        )
+        .arg(
+            Arg::with_name("encryption_key_file")
+                .long("encryption-key-file")
+                .takes_value(true)
+                .help("File with a hex AES-256 key; cached files are encrypted on disk when given"),
+        )
+        .arg(
+            Arg::with_name("access_log")
+                .long("access-log")
+                .takes_value(true)
+                .help("File to append a JSON line to for every /s3 request"),
+        )
+        .arg(
+            Arg::with_name("request_trace")
+                .long("request-trace")
+                .takes_value(true)
+                .help("File to append a replayable trace line to for every /s3 request"),
+        )
+        .arg(
+            Arg::with_name("log_format")
+                .long("log-format")
+                .takes_value(true)
+                .default_value("pretty")
+                .help("Log line format: pretty or json"),
+        )
+        .subcommand(
+            App::new("cluster-init")
+                .about("Forms the Redis cluster of the given peers, then starts the node")
+                .arg(
+                    Arg::with_name("peers")
+                        .long("peers")
+                        .takes_value(true)
+                        .required(true)
+                        .use_value_delimiter(true)
+                        .help(
+                            "Comma-separated Redis host:port of every node, in the same order \
+                             on each; the node whose Redis comes first forms the cluster",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("timeout_secs")
+                        .long("timeout-secs")
+                        .takes_value(true)
+                        .help("How long to wait for the cluster to serve every slot [default: 60]"),
+                ),
+        )
         .get_matches();
-    let _ = setup_logger();
     let _ = std::fs::create_dir_all("/data/cache");
+    if let Some(path) = matches.value_of("config") {
+        let mut config = match load_config(path.as_ref()) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        if matches.is_present("skip_self_test") {
+            config.self_test = false;
+        }
+        let _ = setup_logger(
+            config.log_format,
+            host_port(&config.server_ip, config.redis_port),
+        );
+        return run(&matches, config).await;
+    }
     let use_mock_s3 = matches.is_present("use_mock_s3");
+    let self_test = !matches.is_present("skip_self_test");
+    let embedded_redis = matches
+        .is_present("embedded_redis")
+        .then(EmbeddedRedisConfig::default);
     let redis_port = std::env::var("REDIS_PORT")
         .unwrap_or(String::from("6379"))
         .parse::<u16>()
@@ -98,11 +391,38 @@ async fn main() -> Result<(), rocket::Error> {
         .value_of("server_ip")
         .unwrap_or_default()
         .to_string();
+    let web_port = matches
+        .value_of("web_port")
+        .map(|port| port.parse::<u16>().unwrap());
+    let advertise_addr = matches.value_of("advertise_addr").map(String::from);
+    let advertise_host = matches.value_of("advertise_host").map(String::from);
     let cache_dir = std::env::var("CACHE_DIR").unwrap_or(format!("./cache_{}", redis_port));
     let s3_endpoint = matches.value_of("s3_endpoint").unwrap_or_default();
     let bucket = matches.value_of("bucket").unwrap_or("istziio-bucket");
     let region_name = matches.value_of("region").unwrap_or("us-east-1");
     let access_key = matches.value_of("access_key").unwrap_or_default();
+    let mock_s3 = [
+        "mock_s3_latency_ms",
+        "mock_s3_bytes_per_sec",
+        "mock_s3_error_rate",
+        "mock_s3_generate",
+    ]
+    .iter()
+    .any(|arg| matches.is_present(arg))
+    .then(|| MockS3Config {
+        first_byte_latency_ms: matches
+            .value_of("mock_s3_latency_ms")
+            .map_or(0, |ms| ms.parse::<u64>().unwrap()),
+        bytes_per_sec: matches
+            .value_of("mock_s3_bytes_per_sec")
+            .map(|rate| rate.parse::<u64>().unwrap()),
+        error_rate: matches
+            .value_of("mock_s3_error_rate")
+            .map_or(0.0, |rate| rate.parse::<f64>().unwrap()),
+        generate: matches
+            .value_of("mock_s3_generate")
+            .map(|sizes| sizes.parse::<GeneratedObjects>().unwrap()),
+    });
     let secret_key = matches.value_of("secret_key").unwrap_or_default();
     let max_size = matches
         .value_of("max_size")
@@ -114,34 +434,225 @@ async fn main() -> Result<(), rocket::Error> {
         .unwrap()
         .parse::<u64>()
         .unwrap();
-    let config = if use_mock_s3 {
-        ServerConfig {
-            server_ip,
-            redis_port,
-            cache_dir,
-            bucket: Some(String::from(bucket)),
-            region_name: Some(String::from(region_name)),
-            access_key: Some(String::from(access_key)),
-            secret_key: Some(String::from(secret_key)),
-            use_mock_s3_endpoint: Some(String::from(s3_endpoint)),
-            max_size,
-            bucket_size,
+    let admission_policy = matches
+        .value_of("admission_policy")
+        .unwrap()
+        .parse::<AdmissionPolicy>()
+        .unwrap();
+    let shard_runtimes = matches
+        .is_present("thread_per_shard")
+        .then(|| ShardRuntimeConfig {
+            pin_cores: matches.is_present("pin_cores"),
+        });
+    let max_cacheable_object_size = matches
+        .value_of("max_cacheable_object_size")
+        .map(|size| size.parse::<u64>().unwrap());
+    let memory_tier_size = matches
+        .value_of("memory_tier_size")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+    let chunk_size = matches
+        .value_of("chunk_size")
+        .map(|size| size.parse::<u64>().unwrap());
+    let parquet_footer_prefetch = matches
+        .value_of("parquet_footer_prefetch")
+        .map(|size| size.parse::<u64>().unwrap());
+    let stream_fetches_from = matches
+        .value_of("stream_fetches_from")
+        .map(|size| size.parse::<u64>().unwrap());
+    let disk_io = matches
+        .value_of("disk_io")
+        .unwrap()
+        .parse::<DiskBackend>()
+        .unwrap();
+    let drop_page_cache_from = matches
+        .value_of("drop_page_cache_from")
+        .map(|size| size.parse::<u64>().unwrap());
+    let compression = matches.value_of("compression").map(|codec| {
+        let mut config = CompressionConfig::new(codec.parse::<CompressionCodec>().unwrap());
+        if let Some(min_size) = matches.value_of("compression_min_size") {
+            config.min_size = min_size.parse::<u64>().unwrap();
+        }
+        if let Some(extensions) = matches.value_of("compression_skip_extensions") {
+            config.skip_extensions = extensions.split(',').map(String::from).collect();
+        }
+        config
+    });
+    let memory_tier_max_object_size = matches
+        .value_of("memory_tier_max_object_size")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+    let admin_token = std::env::var("ISTZIIO_ADMIN_TOKEN").ok();
+    let read_tokens: Vec<String> = std::env::var("ISTZIIO_READ_TOKENS")
+        .map(|tokens| tokens.split(',').map(String::from).collect())
+        .unwrap_or_default();
+    let tls = matches.value_of("tls_cert").map(|certs| TlsConfig {
+        certs: certs.to_string(),
+        key: matches.value_of("tls_key").unwrap().to_string(),
+        client_ca: matches.value_of("tls_client_ca").map(String::from),
+    });
+    let encryption = matches
+        .value_of("encryption_key_file")
+        .map(|key_file| EncryptionConfig {
+            key_file: key_file.to_string(),
+        });
+    let log_format = matches
+        .value_of("log_format")
+        .unwrap()
+        .parse::<LogFormat>()
+        .unwrap();
+    let _ = setup_logger(log_format, host_port(&server_ip, redis_port));
+    let access_log = matches.value_of("access_log").map(String::from);
+    let request_trace = matches.value_of("request_trace").map(String::from);
+    let tracing = matches.is_present("trace_spans").then(|| TracingConfig {
+        min_span_ms: matches
+            .value_of("trace_min_span_ms")
+            .map_or(0, |ms| ms.parse::<u64>().unwrap()),
+        otlp_endpoint: matches.value_of("otlp_endpoint").map(String::from),
+        service_name: None,
+    });
+    let grpc = matches.is_present("grpc").then(|| GrpcConfig {
+        flight: matches.is_present("flight"),
+        ..GrpcConfig::default()
+    });
+    let rate_limit = matches.value_of("rate_limit").map(|rate| {
+        let mut config = RateLimitConfig::new(rate.parse::<f64>().unwrap());
+        if let Some(burst) = matches.value_of("rate_limit_burst") {
+            config.burst = burst.parse::<f64>().unwrap();
         }
-    } else {
-        ServerConfig {
-            server_ip,
-            redis_port,
-            cache_dir,
-            bucket: Some(String::from(bucket)),
-            region_name: Some(String::from(region_name)),
-            access_key: Some(String::from(access_key)),
-            secret_key: Some(String::from(secret_key)),
-            use_mock_s3_endpoint: None,
-            max_size,
-            bucket_size,
+        config
+    });
+    let max_concurrent_s3_fetches = matches
+        .value_of("max_concurrent_s3_fetches")
+        .map(|limit| limit.parse::<usize>().unwrap());
+    let max_batch_s3_fetches = matches
+        .value_of("max_batch_s3_fetches")
+        .map(|limit| limit.parse::<usize>().unwrap());
+    let s3_fetch_queue_timeout_ms = matches
+        .value_of("s3_fetch_queue_timeout_ms")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+    let config = ServerConfig {
+        server_ip,
+        redis_port,
+        web_port,
+        advertise_addr,
+        advertise_host,
+        redis_addrs: Vec::new(),
+        redis_mode: Default::default(),
+        embedded_redis,
+        metadata_store: Default::default(),
+        etcd: None,
+        slot_refresh_interval_secs: 30,
+        placement: Default::default(),
+        vnodes_per_node: DEFAULT_VNODES_PER_NODE,
+        capacity_weight: 1,
+        rebalance: None,
+        cache_dir,
+        cache_dirs: Vec::new(),
+        bucket: Some(String::from(bucket)),
+        region_name: Some(String::from(region_name)),
+        access_key: Some(String::from(access_key)),
+        secret_key: Some(String::from(secret_key)),
+        use_mock_s3_endpoint: use_mock_s3.then(|| String::from(s3_endpoint)),
+        mock_s3: mock_s3.filter(|_| use_mock_s3),
+        max_size,
+        bucket_size,
+        shard_runtimes,
+        admission_policy,
+        max_cacheable_object_size,
+        memory_tier_size,
+        memory_tier_max_object_size,
+        chunk_size,
+        parquet_footer_prefetch,
+        stream_fetches_from,
+        hedge_after_ms: None,
+        on_client_disconnect: Default::default(),
+        disk_io,
+        drop_page_cache_from,
+        eviction: None,
+        shadow_caches: Vec::new(),
+        free_space_reserve: None,
+        scrub_interval_secs: 0,
+        write_back: None,
+        warm_up: None,
+        snapshots: None,
+        fetch_sources: Vec::new(),
+        list_cache_ttl_secs: 0,
+        share_list_cache: false,
+        compression,
+        invalidation_channel: None,
+        redis_namespace: None,
+        location_lease_secs: 0,
+        location_cache: None,
+        self_test,
+        admin_token,
+        read_tokens,
+        max_concurrent_s3_fetches,
+        s3_fetch_queue_timeout_ms,
+        max_batch_s3_fetches,
+        timeouts: Default::default(),
+        rate_limit,
+        egress: None,
+        costs: Default::default(),
+        tls,
+        grpc,
+        encryption,
+        tracing,
+        log_format,
+        access_log,
+        request_trace,
+        policies: Vec::new(),
+        tenants: Vec::new(),
+        backends: Vec::new(),
+    };
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    run(&matches, config).await
+}
+
+/// Starts the node, once the Redis cluster is formed when started with `cluster-init`.
+async fn run(matches: &ArgMatches, config: ServerConfig) -> Result<(), Box<rocket::Error>> {
+    let redis_port = config.redis_port;
+    // Starts the embedded Redis, if any, which the cluster is formed from.
+    let server_node = match ServerNode::new(config) {
+        Ok(server_node) => server_node,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     };
-    let server_node = ServerNode::new(config);
+    if let Err(e) = init_cluster(matches, redis_port).await {
+        eprintln!("Failed to form the Redis cluster: {}", e);
+        // Stops the embedded Redis, which exiting would leave running.
+        drop(server_node);
+        std::process::exit(1);
+    }
     server_node.build().launch().await?;
     Ok(())
 }
+
+/// Forms the Redis cluster when started with `cluster-init`.
+async fn init_cluster(matches: &ArgMatches, redis_port: u16) -> Result<(), BootstrapError> {
+    let Some(matches) = matches.subcommand_matches("cluster-init") else {
+        return Ok(());
+    };
+    let peers: Vec<String> = matches
+        .values_of("peers")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    let timeout = matches
+        .value_of("timeout_secs")
+        .map_or(DEFAULT_TIMEOUT, |secs| {
+            Duration::from_secs(secs.parse().expect("--timeout-secs must be a number"))
+        });
+    tokio::task::spawn_blocking(move || cluster_init(&peers, redis_port, timeout))
+        .await
+        .expect("cluster-init panicked")
+}
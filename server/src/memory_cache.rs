@@ -0,0 +1,111 @@
+// memory_cache.rs
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::util::FileUid;
+
+/// In-memory LRU of small, hot objects sitting above a shard's disk cache.
+///
+/// The tier is inclusive: everything held here also lives on disk, so dropping an entry
+/// never loses data and the disk tier stays the source of truth for eviction and Redis.
+pub struct MemoryCache {
+    max_size: u64,
+    max_object_size: u64,
+    current_size: u64,
+    tick: u64,
+    entries: HashMap<FileUid, (Bytes, u64)>,
+    recency: BTreeMap<u64, FileUid>,
+}
+
+impl MemoryCache {
+    pub fn new(max_size: u64, max_object_size: u64) -> Self {
+        Self {
+            max_size,
+            max_object_size,
+            current_size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_size > 0
+    }
+
+    /// Whether an object of `size` bytes is small enough to be kept in memory.
+    pub fn accepts(&self, size: u64) -> bool {
+        self.is_enabled() && size <= self.max_object_size && size <= self.max_size
+    }
+
+    /// Changes the tier budget, evicting least recently used entries until it fits.
+    pub fn set_max_size(&mut self, max_size: u64) {
+        self.max_size = max_size;
+        self.evict_to(max_size);
+    }
+
+    pub fn current_size(&self) -> u64 {
+        self.current_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&mut self, uid: &str) -> Option<Bytes> {
+        let tick = self.next_tick();
+        let (data, last_used) = self.entries.get_mut(uid)?;
+        self.recency.remove(last_used);
+        *last_used = tick;
+        self.recency.insert(tick, uid.to_string());
+        Some(data.clone())
+    }
+
+    pub fn insert(&mut self, uid: &str, data: Bytes) {
+        let size = data.len() as u64;
+        if !self.accepts(size) {
+            return;
+        }
+        self.remove(uid);
+        self.evict_to(self.max_size - size);
+        let tick = self.next_tick();
+        self.current_size += size;
+        self.recency.insert(tick, uid.to_string());
+        self.entries.insert(uid.to_string(), (data, tick));
+    }
+
+    pub fn remove(&mut self, uid: &str) {
+        if let Some((data, last_used)) = self.entries.remove(uid) {
+            self.recency.remove(&last_used);
+            self.current_size -= data.len() as u64;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.current_size = 0;
+    }
+
+    fn evict_to(&mut self, target: u64) {
+        while self.current_size > target {
+            match self.recency.pop_first() {
+                Some((_, victim)) => {
+                    if let Some((evicted, _)) = self.entries.remove(&victim) {
+                        self.current_size -= evicted.len() as u64;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
@@ -0,0 +1,275 @@
+// metadata.rs
+//! Where cached files live and which node owns which keys. A Redis or etcd cluster shares
+//! this across nodes; a single node can keep it in process instead.
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::cache::ClusterMember;
+use crate::util::{FileUid, KeyslotId};
+
+/// Number of hash slots keys are spread over, as in Redis Cluster.
+pub const SLOT_COUNT: KeyslotId = 16384;
+
+/// Weight of a node that registered none.
+pub fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error(transparent)]
+    Redis(#[from] redis::RedisError),
+    #[error("{0}")]
+    Backend(String),
+    #[error("{0} is not supported by this metadata store")]
+    Unsupported(&'static str),
+}
+
+/// The slot of `key`, computed like Redis Cluster's `CLUSTER KEYSLOT`: CRC16 of the key,
+/// or of the part between the first `{` and the next `}` when that part is not empty.
+pub fn key_slot(key: &str) -> KeyslotId {
+    let bytes = key.as_bytes();
+    let hashed = match bytes.iter().position(|&b| b == b'{') {
+        Some(open) => match bytes[open + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &bytes[open + 1..open + 1 + len],
+            _ => bytes,
+        },
+        None => bytes,
+    };
+    (crc16::State::<crc16::XMODEM>::calculate(hashed) % SLOT_COUNT as u16) as KeyslotId
+}
+
+/// Location lookup, file locations and membership for `ConcurrentDiskCache`.
+#[rocket::async_trait]
+pub trait MetadataStore: Send + Sync {
+    /// Whether `initialize` has run successfully.
+    fn is_initialized(&self) -> bool;
+    /// Learns the cluster membership; the cache calls this before the first lookup.
+    async fn initialize(&mut self) -> Result<(), StoreError>;
+    /// Re-reads slot ownership after `initialize`; returns how many slots changed owner.
+    /// Stores that follow membership changes on their own keep the default.
+    async fn refresh(&mut self) -> Result<usize, StoreError> {
+        Ok(0)
+    }
+    /// The `(endpoint, port)` of the node owning `uid`, or `None` if this node owns it.
+    async fn location_lookup(&self, uid: FileUid) -> Option<(String, u16)>;
+    async fn get_file(&self, uid: FileUid) -> Option<PathBuf>;
+    async fn set_file_cache_loc(&self, uid: FileUid, loc: PathBuf) -> Result<(), ()>;
+    /// Looks up the locations of `uids` at once, in order; stores that can batch the
+    /// lookups override this.
+    async fn get_files(&self, uids: Vec<FileUid>) -> Vec<Option<PathBuf>> {
+        let mut locations = Vec::with_capacity(uids.len());
+        for uid in uids {
+            locations.push(self.get_file(uid).await);
+        }
+        locations
+    }
+    /// Records the locations of `files` at once.
+    async fn set_file_cache_locs(&self, files: Vec<(FileUid, PathBuf)>) -> Result<(), ()> {
+        for (uid, loc) in files {
+            self.set_file_cache_loc(uid, loc).await?;
+        }
+        Ok(())
+    }
+    async fn remove_file(&self, uid: FileUid) -> Result<(), ()>;
+    /// Forgets the locations of `uids` at once.
+    async fn remove_files(&self, uids: Vec<FileUid>) -> Result<(), ()> {
+        for uid in uids {
+            self.remove_file(uid).await?;
+        }
+        Ok(())
+    }
+    /// Forgets every file location.
+    async fn flush_all(&self);
+    /// Renews the leases on the locations of `files`, every file this node holds, and
+    /// returns how many were renewed. Stores whose locations don't expire renew nothing.
+    async fn renew_leases(&self, _files: Vec<(FileUid, PathBuf)>) -> Result<usize, StoreError> {
+        Ok(0)
+    }
+    /// Drops the lookups of keys starting with `prefix` that the store answers from
+    /// memory, so that the next ones ask the backend. Stores that don't cache lookups
+    /// have nothing to drop.
+    fn forget_cached_locations(&self, _prefix: &str) {}
+    /// Drops this node's location of `uid` once another node holds the file. Stores
+    /// where the nodes share one location per key leave it to the new holder.
+    async fn release_file(&self, _uid: FileUid) {}
+    /// Drops every file location this node registered, e.g. as it shuts down. Stores
+    /// that don't keep locations per node have nothing to drop.
+    async fn forget_own_files(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+    /// Whether the store is unreachable and the node is serving from local state alone.
+    fn is_degraded(&self) -> bool {
+        false
+    }
+    /// Checks that the store answers. Stores held in the process always do.
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+    /// The nodes of the cluster, with the number of slots each owns.
+    fn members(&self) -> Vec<ClusterMember>;
+    /// Publishes `message` on `channel`; returns the number of subscribers that got it.
+    fn publish(&self, _channel: &str, _message: &str) -> Result<i64, StoreError> {
+        Err(StoreError::Unsupported("pub/sub"))
+    }
+    /// The encoded placement ring last saved with `save_ring`, if any.
+    async fn load_ring(&self) -> Result<Option<String>, StoreError> {
+        Err(StoreError::Unsupported("placement rings"))
+    }
+    async fn save_ring(&self, _ring: &str) -> Result<(), StoreError> {
+        Err(StoreError::Unsupported("placement rings"))
+    }
+    /// The listing page saved under `key` with `save_listing`, unless it has expired or
+    /// `drop_listings` ran since.
+    async fn load_listing(&self, _key: &str) -> Result<Option<String>, StoreError> {
+        Err(StoreError::Unsupported("shared listings"))
+    }
+    async fn save_listing(
+        &self,
+        _key: &str,
+        _page: &str,
+        _ttl: Duration,
+    ) -> Result<(), StoreError> {
+        Err(StoreError::Unsupported("shared listings"))
+    }
+    /// Drops every saved listing page.
+    async fn drop_listings(&self) -> Result<(), StoreError> {
+        Err(StoreError::Unsupported("shared listings"))
+    }
+}
+
+/// Which `MetadataStore` a node uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum MetadataBackend {
+    /// The Redis cluster at `redis_addrs`.
+    #[default]
+    Redis,
+    /// A single node owning every key, with nothing to set up.
+    InProcess,
+    /// The etcd cluster configured under `etcd`.
+    Etcd,
+}
+
+impl FromStr for MetadataBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(MetadataBackend::Redis),
+            "in-process" => Ok(MetadataBackend::InProcess),
+            "etcd" => Ok(MetadataBackend::Etcd),
+            _ => Err(format!("unknown metadata store '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for MetadataBackend {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Metadata for a node that is the whole cluster: it owns every slot and keeps file
+/// locations in memory.
+pub struct InProcessStore {
+    member: ClusterMember,
+    files: Mutex<HashMap<FileUid, PathBuf>>,
+    ring: Mutex<Option<String>>,
+    listings: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl InProcessStore {
+    /// `redis_port` is only used to derive the node's web server port, as with Redis.
+    pub fn new(redis_port: u16) -> Self {
+        Self {
+            member: ClusterMember {
+                node_id: String::from("local"),
+                endpoint: String::from("127.0.0.1"),
+                port: redis_port,
+                slots: SLOT_COUNT as usize,
+                weight: default_weight(),
+                address: None,
+                is_self: true,
+            },
+            files: Mutex::new(HashMap::new()),
+            ring: Mutex::new(None),
+            listings: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl MetadataStore for InProcessStore {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    async fn initialize(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn location_lookup(&self, _uid: FileUid) -> Option<(String, u16)> {
+        None
+    }
+
+    async fn get_file(&self, uid: FileUid) -> Option<PathBuf> {
+        self.files.lock().unwrap().get(&uid).cloned()
+    }
+
+    async fn set_file_cache_loc(&self, uid: FileUid, loc: PathBuf) -> Result<(), ()> {
+        self.files.lock().unwrap().insert(uid, loc);
+        Ok(())
+    }
+
+    async fn remove_file(&self, uid: FileUid) -> Result<(), ()> {
+        self.files.lock().unwrap().remove(&uid);
+        Ok(())
+    }
+
+    async fn flush_all(&self) {
+        self.files.lock().unwrap().clear();
+    }
+
+    fn members(&self) -> Vec<ClusterMember> {
+        vec![self.member.clone()]
+    }
+
+    async fn load_ring(&self) -> Result<Option<String>, StoreError> {
+        Ok(self.ring.lock().unwrap().clone())
+    }
+
+    async fn save_ring(&self, ring: &str) -> Result<(), StoreError> {
+        *self.ring.lock().unwrap() = Some(ring.to_string());
+        Ok(())
+    }
+
+    async fn load_listing(&self, key: &str) -> Result<Option<String>, StoreError> {
+        let listings = self.listings.lock().unwrap();
+        Ok(listings
+            .get(key)
+            .filter(|(expires_at, _)| Instant::now() < *expires_at)
+            .map(|(_, page)| page.clone()))
+    }
+
+    async fn save_listing(&self, key: &str, page: &str, ttl: Duration) -> Result<(), StoreError> {
+        let mut listings = self.listings.lock().unwrap();
+        let now = Instant::now();
+        listings.retain(|_, (expires_at, _)| now < *expires_at);
+        listings.insert(key.to_string(), (now + ttl, page.to_string()));
+        Ok(())
+    }
+
+    async fn drop_listings(&self) -> Result<(), StoreError> {
+        self.listings.lock().unwrap().clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+// metrics.rs
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Latency samples kept per window; older ones are dropped first.
+pub const LATENCY_SAMPLES: usize = 1024;
+/// The rolling hit ratio covers this many one-minute slots.
+pub const RECENT_WINDOW_MINUTES: u64 = 5;
+/// Windows of `/stats/efficiency` as `(name, slot seconds, slots)`; each slides by a slot.
+pub const EFFICIENCY_WINDOWS: [(&str, u64, u64); 3] =
+    [("1m", 5, 12), ("1h", 60, 60), ("24h", 900, 96)];
+
+/// The most recent latency samples of one kind of operation.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyWindow {
+    samples: VecDeque<Duration>,
+}
+
+impl LatencyWindow {
+    pub fn record(&mut self, latency: Duration) {
+        if self.samples.len() == LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// All samples of `windows` together, e.g. to summarize every shard at once.
+    pub fn merged<'a>(windows: impl IntoIterator<Item = &'a LatencyWindow>) -> Self {
+        Self {
+            samples: windows
+                .into_iter()
+                .flat_map(|window| window.samples.iter().copied())
+                .collect(),
+        }
+    }
+
+    /// Nearest-rank percentiles for each of `ranks` (in percent), or `None` without samples.
+    pub fn percentiles(&self, ranks: &[f64]) -> Option<Vec<Duration>> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted = self.samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        Some(
+            ranks
+                .iter()
+                .map(|rank| {
+                    let position = (rank / 100.0 * sorted.len() as f64).ceil() as usize;
+                    sorted[position.clamp(1, sorted.len()) - 1]
+                })
+                .collect(),
+        )
+    }
+
+    /// `p50/p95/p99` in milliseconds, or `-` without samples.
+    pub fn summary(&self) -> String {
+        match self.percentiles(&[50.0, 95.0, 99.0]) {
+            Some(p) => format!(
+                "{:.2}/{:.2}/{:.2}",
+                p[0].as_secs_f64() * 1000.0,
+                p[1].as_secs_f64() * 1000.0,
+                p[2].as_secs_f64() * 1000.0
+            ),
+            None => String::from("-"),
+        }
+    }
+}
+
+/// Hits and misses over the last `RECENT_WINDOW_MINUTES` minutes.
+#[derive(Debug, Clone)]
+pub struct RollingCounts {
+    origin: Instant,
+    /// `(minute since origin, hits, misses)`, oldest first.
+    slots: VecDeque<(u64, u64, u64)>,
+}
+
+impl Default for RollingCounts {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+            slots: VecDeque::new(),
+        }
+    }
+}
+
+impl RollingCounts {
+    pub fn record(&mut self, hit: bool) {
+        self.record_at(hit, Instant::now());
+    }
+
+    pub fn record_at(&mut self, hit: bool, now: Instant) {
+        let minute = self.minute(now);
+        self.expire(minute);
+        if self.slots.back().is_none_or(|(last, _, _)| *last != minute) {
+            self.slots.push_back((minute, 0, 0));
+        }
+        let slot = self.slots.back_mut().unwrap();
+        if hit {
+            slot.1 += 1;
+        } else {
+            slot.2 += 1;
+        }
+    }
+
+    /// `(hits, misses)` within the window ending at `now`.
+    pub fn totals_at(&self, now: Instant) -> (u64, u64) {
+        let minute = self.minute(now);
+        self.slots
+            .iter()
+            .filter(|(slot, _, _)| slot + RECENT_WINDOW_MINUTES > minute)
+            .fold((0, 0), |(hits, misses), (_, h, m)| (hits + h, misses + m))
+    }
+
+    pub fn totals(&self) -> (u64, u64) {
+        self.totals_at(Instant::now())
+    }
+
+    fn minute(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.origin).as_secs() / 60
+    }
+
+    fn expire(&mut self, minute: u64) {
+        while self
+            .slots
+            .front()
+            .is_some_and(|(slot, _, _)| slot + RECENT_WINDOW_MINUTES <= minute)
+        {
+            self.slots.pop_front();
+        }
+    }
+}
+
+/// Hit percentage of `(hits, misses)`; 0 without requests.
+pub fn hit_ratio((hits, misses): (u64, u64)) -> f64 {
+    if hits + misses == 0 {
+        0.0
+    } else {
+        hits as f64 / (hits + misses) as f64 * 100.0
+    }
+}
+
+/// What the cache served and what it fetched from the origin in one slot of time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EfficiencyTotals {
+    pub hits: u64,
+    pub misses: u64,
+    pub bytes_from_cache: u64,
+    pub bytes_from_origin: u64,
+    /// Sum over the hits.
+    pub hit_latency: Duration,
+    /// Downloads from the origin, and their summed latency.
+    pub fetches: u64,
+    pub fetch_latency: Duration,
+}
+
+impl EfficiencyTotals {
+    pub fn add(&mut self, other: &EfficiencyTotals) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.bytes_from_cache += other.bytes_from_cache;
+        self.bytes_from_origin += other.bytes_from_origin;
+        self.hit_latency += other.hit_latency;
+        self.fetches += other.fetches;
+        self.fetch_latency += other.fetch_latency;
+    }
+}
+
+/// `EfficiencyTotals` over a sliding window of `slot_count` slots of `slot_secs` each.
+#[derive(Debug, Clone)]
+pub struct SlidingTotals {
+    origin: Instant,
+    slot_secs: u64,
+    slot_count: u64,
+    /// `(slot since origin, totals)`, oldest first.
+    slots: VecDeque<(u64, EfficiencyTotals)>,
+}
+
+impl SlidingTotals {
+    pub fn new(slot_secs: u64, slot_count: u64) -> Self {
+        Self {
+            origin: Instant::now(),
+            slot_secs: slot_secs.max(1),
+            slot_count,
+            slots: VecDeque::new(),
+        }
+    }
+
+    pub fn record_at(&mut self, now: Instant, update: impl FnOnce(&mut EfficiencyTotals)) {
+        let slot = self.slot(now);
+        while self
+            .slots
+            .front()
+            .is_some_and(|(first, _)| first + self.slot_count <= slot)
+        {
+            self.slots.pop_front();
+        }
+        if self.slots.back().is_none_or(|(last, _)| *last != slot) {
+            self.slots.push_back((slot, EfficiencyTotals::default()));
+        }
+        update(&mut self.slots.back_mut().unwrap().1);
+    }
+
+    /// Totals within the window ending at `now`.
+    pub fn totals_at(&self, now: Instant) -> EfficiencyTotals {
+        let slot = self.slot(now);
+        let mut totals = EfficiencyTotals::default();
+        for (_, slot_totals) in self
+            .slots
+            .iter()
+            .filter(|(first, _)| first + self.slot_count > slot)
+        {
+            totals.add(slot_totals);
+        }
+        totals
+    }
+
+    fn slot(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.origin).as_secs() / self.slot_secs
+    }
+}
+
+/// `SlidingTotals` for each of `EFFICIENCY_WINDOWS`.
+#[derive(Debug, Clone)]
+pub struct EfficiencyWindows {
+    windows: Vec<SlidingTotals>,
+}
+
+impl Default for EfficiencyWindows {
+    fn default() -> Self {
+        Self {
+            windows: EFFICIENCY_WINDOWS
+                .iter()
+                .map(|(_, slot_secs, slot_count)| SlidingTotals::new(*slot_secs, *slot_count))
+                .collect(),
+        }
+    }
+}
+
+impl EfficiencyWindows {
+    fn record(&mut self, update: impl Fn(&mut EfficiencyTotals)) {
+        let now = Instant::now();
+        for window in &mut self.windows {
+            window.record_at(now, &update);
+        }
+    }
+
+    pub fn record_hit(&mut self, bytes: u64, latency: Duration) {
+        self.record(|totals| {
+            totals.hits += 1;
+            totals.bytes_from_cache += bytes;
+            totals.hit_latency += latency;
+        });
+    }
+
+    pub fn record_miss(&mut self) {
+        self.record(|totals| totals.misses += 1);
+    }
+
+    pub fn record_fetch(&mut self, bytes: u64, latency: Duration) {
+        self.record(|totals| {
+            totals.fetches += 1;
+            totals.bytes_from_origin += bytes;
+            totals.fetch_latency += latency;
+        });
+    }
+
+    /// Totals of each window as of now, in the order of `EFFICIENCY_WINDOWS`.
+    pub fn totals(&self) -> Vec<EfficiencyTotals> {
+        let now = Instant::now();
+        self.windows
+            .iter()
+            .map(|window| window.totals_at(now))
+            .collect()
+    }
+}
+
+/// One window of `/stats/efficiency`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EfficiencyRow {
+    pub window: String,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_ratio: f64,
+    pub bytes_from_cache: u64,
+    pub bytes_from_origin: u64,
+    /// Share of the bytes served that came from the cache, in percent.
+    pub byte_hit_ratio: f64,
+    /// Every hit is a GET the origin didn't get.
+    pub origin_requests_avoided: u64,
+    /// Time to find a cached object and start serving it; `None` without hits.
+    pub avg_hit_latency_ms: Option<f64>,
+    /// Time to download an object from the origin; `None` without downloads.
+    pub avg_miss_latency_ms: Option<f64>,
+}
+
+impl EfficiencyRow {
+    pub fn new(window: &str, totals: &EfficiencyTotals) -> Self {
+        let average = |sum: Duration, count: u64| {
+            (count > 0).then(|| sum.as_secs_f64() * 1000.0 / count as f64)
+        };
+        Self {
+            window: window.to_string(),
+            hits: totals.hits,
+            misses: totals.misses,
+            hit_ratio: hit_ratio((totals.hits, totals.misses)),
+            bytes_from_cache: totals.bytes_from_cache,
+            bytes_from_origin: totals.bytes_from_origin,
+            byte_hit_ratio: hit_ratio((totals.bytes_from_cache, totals.bytes_from_origin)),
+            origin_requests_avoided: totals.hits,
+            avg_hit_latency_ms: average(totals.hit_latency, totals.hits),
+            avg_miss_latency_ms: average(totals.fetch_latency, totals.fetches),
+        }
+    }
+}
@@ -0,0 +1,409 @@
+// mock_redis.rs
+//! A Redis cluster in process, for tests and benchmarks that have no Redis to talk to, or
+//! a single Redis without cluster support. It knows the commands the metadata store
+//! sends, ignores expiries, and answers every batch of commands read at once after one
+//! simulated round trip, so that pipelining shows up as it would over a network. The nodes of a cluster split the slots between
+//! them but share one keyspace, so that none of them ever answers `MOVED`.
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Result as IoResult, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Id the first node reports for itself; the others count up from it.
+pub const MOCK_NODE_ID: &str = "0000000000000000000000000000000000000001";
+
+/// Slots of a Redis cluster.
+const SLOTS: usize = 16384;
+
+enum Entry {
+    Value(Vec<u8>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+}
+
+#[derive(Default)]
+struct State {
+    entries: Mutex<HashMap<Vec<u8>, Entry>>,
+    /// Port and id of every node, in slot order.
+    nodes: Mutex<Vec<(u16, String)>>,
+    round_trip: Duration,
+    /// Set when the node answers `CLUSTER` commands as a Redis without cluster support.
+    standalone: bool,
+    round_trips: AtomicU64,
+    commands: AtomicU64,
+}
+
+/// A running node; it stops accepting connections when the process exits.
+pub struct MockRedis {
+    port: u16,
+    id: String,
+    state: Arc<State>,
+}
+
+impl MockRedis {
+    /// Starts a cluster of one node on a free port of the loopback interface, answering
+    /// each batch of commands after `round_trip`.
+    pub fn start(round_trip: Duration) -> IoResult<Self> {
+        Ok(Self::start_cluster(1, round_trip)?.remove(0))
+    }
+
+    /// Starts a single Redis without cluster support, as the nodes share in standalone
+    /// mode.
+    pub fn start_standalone(round_trip: Duration) -> IoResult<Self> {
+        let state = Arc::new(State {
+            round_trip,
+            standalone: true,
+            ..State::default()
+        });
+        Self::listen(&state)
+    }
+
+    /// Starts a cluster of `nodes` nodes, each on a free port and owning an even share
+    /// of the slots.
+    pub fn start_cluster(nodes: usize, round_trip: Duration) -> IoResult<Vec<Self>> {
+        let state = Arc::new(State {
+            round_trip,
+            ..State::default()
+        });
+        (0..nodes.max(1)).map(|_| Self::listen(&state)).collect()
+    }
+
+    /// Starts one more node in this node's cluster. The slots are split evenly again, so
+    /// some of every node's move to the new one.
+    pub fn add_node(&self) -> IoResult<Self> {
+        Self::listen(&self.state)
+    }
+
+    fn listen(state: &Arc<State>) -> IoResult<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let id = {
+            let mut nodes = state.nodes.lock().unwrap();
+            let id = format!("{:040x}", nodes.len() + 1);
+            nodes.push((port, id.clone()));
+            id
+        };
+        let shared = state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = shared.clone();
+                std::thread::spawn(move || {
+                    let _ = serve(stream, port, &state);
+                });
+            }
+        });
+        Ok(Self {
+            port,
+            id,
+            state: state.clone(),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The id the node answers `CLUSTER MYID` with.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn url(&self) -> String {
+        format!("redis://127.0.0.1:{}", self.port)
+    }
+
+    /// Batches of commands answered so far, connection checks included.
+    pub fn round_trips(&self) -> u64 {
+        self.state.round_trips.load(Ordering::Relaxed)
+    }
+
+    /// Commands answered so far.
+    pub fn commands(&self) -> u64 {
+        self.state.commands.load(Ordering::Relaxed)
+    }
+}
+
+fn serve(stream: TcpStream, port: u16, state: &State) -> IoResult<()> {
+    stream.set_nodelay(true)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut replies = Vec::new();
+        // Everything the client sent at once is one round trip.
+        loop {
+            let command = read_command(&mut reader)?;
+            replies.extend(state.execute(&command, port));
+            state.commands.fetch_add(1, Ordering::Relaxed);
+            if reader.buffer().is_empty() && !more_sent(&mut reader)? {
+                break;
+            }
+        }
+        if !state.round_trip.is_zero() {
+            std::thread::sleep(state.round_trip);
+        }
+        state.round_trips.fetch_add(1, Ordering::Relaxed);
+        writer.write_all(&replies)?;
+    }
+}
+
+/// Whether the client sent more without waiting for a reply, as the rest of a pipeline
+/// too long for one read would be.
+fn more_sent(reader: &mut BufReader<TcpStream>) -> IoResult<bool> {
+    reader.get_ref().set_nonblocking(true)?;
+    let more = match reader.fill_buf() {
+        Ok(buf) => !buf.is_empty(),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => false,
+        Err(e) => return Err(e),
+    };
+    reader.get_ref().set_nonblocking(false)?;
+    Ok(more)
+}
+
+/// Reads one command, an array of bulk strings.
+fn read_command(reader: &mut BufReader<TcpStream>) -> IoResult<Vec<Vec<u8>>> {
+    let count = read_header(reader, b'*')?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len = read_header(reader, b'$')?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg)?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn read_header(reader: &mut BufReader<TcpStream>, kind: u8) -> IoResult<usize> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    line.strip_prefix(kind as char)
+        .and_then(|n| n.trim_end().parse().ok())
+        .ok_or_else(|| std::io::Error::other(format!("unexpected line {:?}", line)))
+}
+
+fn bulk(value: Option<&[u8]>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut reply = format!("${}\r\n", value.len()).into_bytes();
+            reply.extend_from_slice(value);
+            reply.extend_from_slice(b"\r\n");
+            reply
+        }
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+fn array(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", items.len()).into_bytes();
+    items.into_iter().for_each(|item| reply.extend(item));
+    reply
+}
+
+fn int(n: usize) -> Vec<u8> {
+    format!(":{}\r\n", n).into_bytes()
+}
+
+const WRONG_TYPE: &[u8] = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+impl State {
+    /// `(first slot, last slot, port, id)` of every node.
+    fn slot_ranges(&self) -> Vec<(usize, usize, u16, String)> {
+        let nodes = self.nodes.lock().unwrap();
+        let count = nodes.len();
+        nodes
+            .iter()
+            .enumerate()
+            .map(|(i, (port, id))| {
+                (
+                    i * SLOTS / count,
+                    (i + 1) * SLOTS / count - 1,
+                    *port,
+                    id.clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn execute(&self, command: &[Vec<u8>], port: u16) -> Vec<u8> {
+        let name = command
+            .first()
+            .map(|name| String::from_utf8_lossy(name).to_ascii_uppercase())
+            .unwrap_or_default();
+        let args = command.get(1..).unwrap_or_default();
+        let mut entries = self.entries.lock().unwrap();
+        match (name.as_str(), args) {
+            ("PING", _) => b"+PONG\r\n".to_vec(),
+            ("CLUSTER", _) if self.standalone => {
+                b"-ERR This instance has cluster support disabled\r\n".to_vec()
+            }
+            ("CLUSTER", [sub, ..]) if sub.eq_ignore_ascii_case(b"SLOTS") => array(
+                self.slot_ranges()
+                    .into_iter()
+                    .map(|(first, last, port, id)| {
+                        let node = array(vec![
+                            bulk(Some(b"127.0.0.1")),
+                            int(port as usize),
+                            bulk(Some(id.as_bytes())),
+                        ]);
+                        array(vec![int(first), int(last), node])
+                    })
+                    .collect(),
+            ),
+            ("CLUSTER", [sub, ..]) if sub.eq_ignore_ascii_case(b"SHARDS") => array(
+                self.slot_ranges()
+                    .into_iter()
+                    .map(|(first, last, port, id)| {
+                        let node = array(vec![
+                            bulk(Some(b"id")),
+                            bulk(Some(id.as_bytes())),
+                            bulk(Some(b"port")),
+                            int(port as usize),
+                            bulk(Some(b"ip")),
+                            bulk(Some(b"127.0.0.1")),
+                            bulk(Some(b"role")),
+                            bulk(Some(b"master")),
+                        ]);
+                        array(vec![
+                            bulk(Some(b"slots")),
+                            array(vec![int(first), int(last)]),
+                            bulk(Some(b"nodes")),
+                            array(vec![node]),
+                        ])
+                    })
+                    .collect(),
+            ),
+            ("CLUSTER", [sub, ..]) if sub.eq_ignore_ascii_case(b"INFO") => {
+                let info = format!(
+                    "cluster_state:ok\r\ncluster_slots_assigned:{}\r\ncluster_known_nodes:{}\r\n",
+                    SLOTS,
+                    self.nodes.lock().unwrap().len()
+                );
+                bulk(Some(info.as_bytes()))
+            }
+            ("CLUSTER", [sub, ..]) if sub.eq_ignore_ascii_case(b"NODES") => {
+                let nodes: String = self
+                    .slot_ranges()
+                    .into_iter()
+                    .map(|(first, last, node_port, id)| {
+                        let flags = match node_port == port {
+                            true => "myself,master",
+                            false => "master",
+                        };
+                        format!(
+                            "{} 127.0.0.1:{}@{} {} - 0 0 1 connected {}-{}\n",
+                            id,
+                            node_port,
+                            u32::from(node_port) + 10000,
+                            flags,
+                            first,
+                            last
+                        )
+                    })
+                    .collect();
+                bulk(Some(nodes.as_bytes()))
+            }
+            ("CLUSTER", [sub, ..]) if sub.eq_ignore_ascii_case(b"MYID") => {
+                let nodes = self.nodes.lock().unwrap();
+                let id = nodes
+                    .iter()
+                    .find(|(node_port, _)| *node_port == port)
+                    .map_or(MOCK_NODE_ID, |(_, id)| id.as_str());
+                bulk(Some(id.as_bytes()))
+            }
+            ("GET", [key]) => match entries.get(key) {
+                Some(Entry::Value(value)) => bulk(Some(value)),
+                Some(Entry::Hash(_)) => WRONG_TYPE.to_vec(),
+                None => bulk(None),
+            },
+            ("SET", [key, value, ..]) | ("SETEX", [key, _, value]) => {
+                entries.insert(key.clone(), Entry::Value(value.clone()));
+                b"+OK\r\n".to_vec()
+            }
+            ("INCR", [key]) => {
+                let n = match entries.get(key) {
+                    Some(Entry::Value(value)) => {
+                        String::from_utf8_lossy(value).parse::<usize>().unwrap_or(0)
+                    }
+                    _ => 0,
+                } + 1;
+                entries.insert(key.clone(), Entry::Value(n.to_string().into_bytes()));
+                int(n)
+            }
+            ("DEL", keys) => int(keys
+                .iter()
+                .filter(|key| entries.remove(*key).is_some())
+                .count()),
+            ("EXPIRE", [key, ..]) => int(entries.contains_key(key) as usize),
+            ("FLUSHALL", _) => {
+                entries.clear();
+                b"+OK\r\n".to_vec()
+            }
+            // Nobody subscribes to the mock.
+            ("PUBLISH", [_, _]) => int(0),
+            ("HSET", [key, fields @ ..]) if !fields.is_empty() && fields.len() % 2 == 0 => {
+                let entry = entries
+                    .entry(key.clone())
+                    .or_insert_with(|| Entry::Hash(HashMap::new()));
+                let Entry::Hash(hash) = entry else {
+                    return WRONG_TYPE.to_vec();
+                };
+                let added = fields
+                    .chunks(2)
+                    .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+                    .count();
+                int(added)
+            }
+            ("HMSET", [key, fields @ ..]) if !fields.is_empty() && fields.len() % 2 == 0 => {
+                let entry = entries
+                    .entry(key.clone())
+                    .or_insert_with(|| Entry::Hash(HashMap::new()));
+                let Entry::Hash(hash) = entry else {
+                    return WRONG_TYPE.to_vec();
+                };
+                for pair in fields.chunks(2) {
+                    hash.insert(pair[0].clone(), pair[1].clone());
+                }
+                b"+OK\r\n".to_vec()
+            }
+            ("HGET", [key, field]) => match entries.get(key) {
+                Some(Entry::Hash(hash)) => bulk(hash.get(field).map(Vec::as_slice)),
+                Some(Entry::Value(_)) => WRONG_TYPE.to_vec(),
+                None => bulk(None),
+            },
+            ("HDEL", [key, fields @ ..]) => {
+                let Some(Entry::Hash(hash)) = entries.get_mut(key) else {
+                    return int(0);
+                };
+                let removed = fields
+                    .iter()
+                    .filter(|field| hash.remove(*field).is_some())
+                    .count();
+                if hash.is_empty() {
+                    entries.remove(key);
+                }
+                int(removed)
+            }
+            ("HKEYS", [key]) | ("HGETALL", [key]) => {
+                let with_values = name == "HGETALL";
+                let items = match entries.get(key) {
+                    Some(Entry::Hash(hash)) => hash
+                        .iter()
+                        .flat_map(|(field, value)| {
+                            let mut items = vec![bulk(Some(field))];
+                            if with_values {
+                                items.push(bulk(Some(value)));
+                            }
+                            items
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                array(items)
+            }
+            _ => format!("-ERR unknown command '{}'\r\n", name).into_bytes(),
+        }
+    }
+}
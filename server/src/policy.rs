@@ -0,0 +1,200 @@
+// policy.rs
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::prefixes::object_key;
+use crate::versioning::split_version;
+
+/// Eviction order between entries: lower priorities go first, and pinned entries are
+/// only evicted to keep their own rule under its quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Pinned,
+}
+
+fn default_admit() -> bool {
+    true
+}
+
+/// Cache behavior for the keys matching `pattern`, e.g. in TOML
+/// `[[policies]] pattern = "tmp/*" ttl_secs = 600 priority = "low"`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrefixPolicy {
+    /// A key prefix, or a glob if it contains `*` or `?`. `*` also matches `/`.
+    pub pattern: String,
+    /// Cached entries are dropped this long after they were fetched.
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub priority: Priority,
+    /// `false` serves the matching keys straight from S3, even when admission is forced.
+    #[serde(default = "default_admit")]
+    pub admit: bool,
+    /// Bytes the matching entries may occupy on the node, split evenly between shards.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Hits on entries validated longer ago than this check the object's version in S3
+    /// first, and fetch it again if it changed.
+    #[serde(default)]
+    pub revalidate_after_secs: Option<u64>,
+    /// Entries past their TTL, due for revalidation or soft-invalidated are still served
+    /// for up to this long while they are refreshed in the background.
+    #[serde(default)]
+    pub stale_while_revalidate_secs: Option<u64>,
+    /// Hits on entries this close to their TTL fetch them again in the background, so
+    /// that keys still in use don't expire.
+    #[serde(default)]
+    pub refresh_ahead_secs: Option<u64>,
+}
+
+impl PrefixPolicy {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            ttl_secs: None,
+            priority: Priority::default(),
+            admit: true,
+            max_bytes: None,
+            revalidate_after_secs: None,
+            stale_while_revalidate_secs: None,
+            refresh_ahead_secs: None,
+        }
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        if self.pattern.contains(['*', '?']) {
+            glob_matches(self.pattern.as_bytes(), key.as_bytes())
+        } else {
+            key.starts_with(&self.pattern)
+        }
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl_secs.map(Duration::from_secs)
+    }
+
+    pub fn revalidate_after(&self) -> Option<Duration> {
+        self.revalidate_after_secs.map(Duration::from_secs)
+    }
+
+    pub fn stale_while_revalidate(&self) -> Option<Duration> {
+        self.stale_while_revalidate_secs.map(Duration::from_secs)
+    }
+
+    pub fn refresh_ahead(&self) -> Option<Duration> {
+        self.refresh_ahead_secs.map(Duration::from_secs)
+    }
+}
+
+/// Matches `text` against a glob where `*` is any run of bytes and `?` any single byte.
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*`: pattern position and the text it has consumed.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, consumed)) => {
+                    p = star + 1;
+                    t = consumed + 1;
+                    backtrack = Some((star, consumed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Ordered policy rules; the first rule matching a key applies to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicySet {
+    rules: Vec<PrefixPolicy>,
+}
+
+impl PolicySet {
+    pub fn new(rules: Vec<PrefixPolicy>) -> Self {
+        Self { rules }
+    }
+
+    /// The rules with every quota divided between `shard_count` shards.
+    pub fn per_shard(&self, shard_count: u64) -> Self {
+        Self {
+            rules: self
+                .rules
+                .iter()
+                .map(|rule| PrefixPolicy {
+                    max_bytes: rule.max_bytes.map(|bytes| bytes / shard_count),
+                    ..rule.clone()
+                })
+                .collect(),
+        }
+    }
+
+    /// Number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Index of the rule governing a cache entry; chunks, footers and pinned versions
+    /// follow their object.
+    pub fn rule_index(&self, name: &str) -> Option<usize> {
+        let (key, _) = split_version(object_key(name));
+        self.rules.iter().position(|rule| rule.matches(key))
+    }
+
+    pub fn for_key(&self, name: &str) -> Option<&PrefixPolicy> {
+        self.rule_index(name).map(|index| &self.rules[index])
+    }
+
+    pub fn admits(&self, name: &str) -> bool {
+        self.for_key(name).is_none_or(|rule| rule.admit)
+    }
+
+    pub fn priority(&self, name: &str) -> Priority {
+        self.for_key(name)
+            .map_or(Priority::default(), |rule| rule.priority)
+    }
+
+    /// Whether any rule expires or revalidates entries.
+    pub fn has_clocks(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.ttl_secs.is_some() || rule.revalidate_after_secs.is_some())
+    }
+
+    pub fn ttl(&self, name: &str) -> Option<Duration> {
+        self.for_key(name).and_then(PrefixPolicy::ttl)
+    }
+
+    pub fn revalidate_after(&self, name: &str) -> Option<Duration> {
+        self.for_key(name).and_then(PrefixPolicy::revalidate_after)
+    }
+
+    pub fn stale_while_revalidate(&self, name: &str) -> Option<Duration> {
+        self.for_key(name)
+            .and_then(PrefixPolicy::stale_while_revalidate)
+    }
+
+    pub fn refresh_ahead(&self, name: &str) -> Option<Duration> {
+        self.for_key(name).and_then(PrefixPolicy::refresh_ahead)
+    }
+}
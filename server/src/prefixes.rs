@@ -0,0 +1,97 @@
+// prefixes.rs
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Access counts are kept for prefixes up to this many directory levels; deeper
+/// requests are answered at this depth.
+pub const MAX_PREFIX_DEPTH: usize = 8;
+
+/// The first `depth` directories of `key`, with a trailing `/`. Keys with fewer
+/// directories yield their whole directory, and top-level keys the empty prefix.
+pub fn prefix_of(key: &str, depth: usize) -> &str {
+    let mut end = 0;
+    for (position, _) in key.match_indices('/').take(depth) {
+        end = position + 1;
+    }
+    &key[..end]
+}
+
+/// The object a cache entry belongs to: chunk and footer entries carry a suffix.
+pub fn object_key(name: &str) -> &str {
+    match name.rsplit_once('@') {
+        Some((object, suffix)) if suffix == "footer" || suffix.starts_with("chunk-") => object,
+        _ => name,
+    }
+}
+
+/// Accesses per key prefix, at every depth up to `MAX_PREFIX_DEPTH`. Only prefixes are
+/// kept, so memory grows with the number of directories rather than keys.
+#[derive(Debug, Clone, Default)]
+pub struct PrefixAccesses {
+    counts: HashMap<(usize, String), u64>,
+}
+
+impl PrefixAccesses {
+    pub fn record(&mut self, key: &str) {
+        for depth in 0..=MAX_PREFIX_DEPTH {
+            let prefix = prefix_of(key, depth).to_string();
+            *self.counts.entry((depth, prefix)).or_default() += 1;
+        }
+    }
+
+    /// Access counts of the prefixes at `depth`.
+    pub fn at_depth(&self, depth: usize) -> impl Iterator<Item = (&str, u64)> {
+        let depth = depth.min(MAX_PREFIX_DEPTH);
+        self.counts
+            .iter()
+            .filter(move |((d, _), _)| *d == depth)
+            .map(|((_, prefix), count)| (prefix.as_str(), *count))
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+}
+
+/// One row of `/stats/prefixes`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PrefixUsage {
+    pub prefix: String,
+    /// On-disk bytes of the cached entries under the prefix.
+    pub cached_bytes: u64,
+    /// Cached entries (whole objects, chunks and footers) under the prefix.
+    pub entries: u64,
+    pub accesses: u64,
+}
+
+/// Folds cached entries and access counts into one row per prefix, largest first.
+pub fn usage_by_prefix<'a>(
+    depth: usize,
+    entries: impl IntoIterator<Item = (&'a str, u64)>,
+    accesses: impl IntoIterator<Item = (&'a str, u64)>,
+) -> Vec<PrefixUsage> {
+    let depth = depth.min(MAX_PREFIX_DEPTH);
+    let mut rows: HashMap<&str, PrefixUsage> = HashMap::new();
+    for (name, size) in entries {
+        let prefix = prefix_of(object_key(name), depth);
+        let row = rows.entry(prefix).or_default();
+        row.cached_bytes += size;
+        row.entries += 1;
+    }
+    for (prefix, count) in accesses {
+        rows.entry(prefix).or_default().accesses += count;
+    }
+    let mut rows = rows
+        .into_iter()
+        .map(|(prefix, row)| PrefixUsage {
+            prefix: prefix.to_string(),
+            ..row
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| {
+        (b.cached_bytes, b.accesses)
+            .cmp(&(a.cached_bytes, a.accesses))
+            .then_with(|| a.prefix.cmp(&b.prefix))
+    });
+    rows
+}
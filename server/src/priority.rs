@@ -0,0 +1,54 @@
+// priority.rs
+//! Request priorities. A client marks a request `batch` with the `X-Istziio-Priority`
+//! header when nobody waits on it, e.g. a backfill. Batch misses only get S3 fetch slots
+//! that no interactive miss is waiting for, keep their reads and writes out of the page
+//! cache, and enter the cache as the first entries to evict; batch hits don't make an
+//! entry more recent.
+use std::future::Future;
+use std::str::FromStr;
+
+/// `interactive` (the default) or `batch`.
+pub const PRIORITY_HEADER: &str = "X-Istziio-Priority";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestPriority {
+    /// Someone is waiting on the answer.
+    #[default]
+    Interactive,
+    /// Backfills and other bulk reads that may wait behind interactive ones.
+    Batch,
+}
+
+impl FromStr for RequestPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "interactive" => Ok(Self::Interactive),
+            "batch" => Ok(Self::Batch),
+            _ => Err(format!(
+                "unknown priority {:?}; expected interactive or batch",
+                s
+            )),
+        }
+    }
+}
+
+tokio::task_local! {
+    static PRIORITY: RequestPriority;
+}
+
+/// Runs `fut` with `priority` as the priority of the fetches and disk I/O it does.
+pub async fn with_priority<F: Future>(priority: RequestPriority, fut: F) -> F::Output {
+    PRIORITY.scope(priority, fut).await
+}
+
+/// The priority of the current request; interactive outside of one.
+pub fn current_priority() -> RequestPriority {
+    PRIORITY.try_with(|priority| *priority).unwrap_or_default()
+}
+
+/// Whether the current request is a batch one.
+pub fn is_batch() -> bool {
+    current_priority() == RequestPriority::Batch
+}
@@ -0,0 +1,167 @@
+// protobuf.rs
+//! Just enough of the protobuf wire format for the gRPC services: varints and
+//! length-delimited fields, written by hand for the few messages in `proto/`. Fields a
+//! decoder does not know are skipped, as protobuf requires.
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
+const VARINT: u32 = 0;
+const FIXED64: u32 = 1;
+const LENGTH_DELIMITED: u32 = 2;
+const FIXED32: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError(pub String);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed protobuf message: {}", self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn malformed(what: &str) -> DecodeError {
+    DecodeError(what.to_string())
+}
+
+pub fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    put_varint(buf, ((field as u64) << 3) | wire_type as u64);
+}
+
+/// Writes `value` even when it is 0, as `optional` fields need.
+pub fn put_uint64(buf: &mut Vec<u8>, field: u32, value: u64) {
+    put_key(buf, field, VARINT);
+    put_varint(buf, value);
+}
+
+pub fn put_bool(buf: &mut Vec<u8>, field: u32, value: bool) {
+    put_uint64(buf, field, value as u64);
+}
+
+pub fn put_bytes(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    put_key(buf, field, LENGTH_DELIMITED);
+    put_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+pub fn put_string(buf: &mut Vec<u8>, field: u32, value: &str) {
+    put_bytes(buf, field, value.as_bytes());
+}
+
+/// An embedded message is written like bytes holding its encoding.
+pub fn put_message(buf: &mut Vec<u8>, field: u32, message: &[u8]) {
+    put_bytes(buf, field, message);
+}
+
+/// The value of a field, by wire type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed64(u64),
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    pub fn as_u64(self) -> Result<u64, DecodeError> {
+        match self {
+            Value::Varint(value) | Value::Fixed64(value) => Ok(value),
+            Value::Fixed32(value) => Ok(value as u64),
+            Value::Bytes(_) => Err(malformed("expected a number")),
+        }
+    }
+
+    pub fn as_bool(self) -> Result<bool, DecodeError> {
+        self.as_u64().map(|value| value != 0)
+    }
+
+    pub fn as_bytes(self) -> Result<&'a [u8], DecodeError> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(malformed("expected a length-delimited field")),
+        }
+    }
+
+    pub fn as_str(self) -> Result<&'a str, DecodeError> {
+        std::str::from_utf8(self.as_bytes()?).map_err(|_| malformed("string is not UTF-8"))
+    }
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, DecodeError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or_else(|| malformed("truncated"))?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(malformed("varint is too long"))
+}
+
+fn read_fixed<const N: usize>(data: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+    if data.len() < N {
+        return Err(malformed("truncated"));
+    }
+    let (value, rest) = data.split_at(N);
+    *data = rest;
+    Ok(value.try_into().unwrap())
+}
+
+/// The fields of an encoded message, in the order they were written.
+pub struct Fields<'a> {
+    data: &'a [u8],
+}
+
+pub fn fields(data: &[u8]) -> Fields<'_> {
+    Fields { data }
+}
+
+impl<'a> Fields<'a> {
+    fn read(&mut self) -> Result<(u32, Value<'a>), DecodeError> {
+        let key = read_varint(&mut self.data)?;
+        let field = u32::try_from(key >> 3).map_err(|_| malformed("field number"))?;
+        let value = match (key & 7) as u32 {
+            VARINT => Value::Varint(read_varint(&mut self.data)?),
+            FIXED64 => Value::Fixed64(u64::from_le_bytes(read_fixed(&mut self.data)?)),
+            LENGTH_DELIMITED => {
+                let len = read_varint(&mut self.data)?;
+                if len > self.data.len() as u64 {
+                    return Err(malformed("truncated"));
+                }
+                let (value, rest) = self.data.split_at(len as usize);
+                self.data = rest;
+                Value::Bytes(value)
+            }
+            FIXED32 => Value::Fixed32(u32::from_le_bytes(read_fixed(&mut self.data)?)),
+            _ => return Err(malformed("unsupported wire type")),
+        };
+        Ok((field, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, Value<'a>), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let field = self.read();
+        if field.is_err() {
+            // Nothing after a malformed field can be trusted.
+            self.data = &[];
+        }
+        Some(field)
+    }
+}
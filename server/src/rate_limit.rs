@@ -0,0 +1,159 @@
+// rate_limit.rs
+use crate::auth::{bearer_token, AuthConfig};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bound on tracked clients. Clients that arrive while this many are tracked share
+/// one bucket until a sweep makes room.
+pub const MAX_TRACKED_CLIENTS: usize = 65536;
+
+/// How often idle (full) buckets are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Per-client token bucket limits for the data routes.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second allowed to each client.
+    pub requests_per_sec: f64,
+    /// Requests a client may send in a burst after being idle.
+    pub burst: f64,
+}
+
+impl RateLimitConfig {
+    pub fn new(requests_per_sec: f64) -> Self {
+        Self {
+            requests_per_sec,
+            burst: requests_per_sec.max(1.0),
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(config: &RateLimitConfig, now: Instant) -> Self {
+        Self {
+            tokens: config.burst,
+            refilled_at: now,
+        }
+    }
+
+    /// Tokens in the bucket at `now`, which may be more than it holds.
+    fn tokens_at(&self, config: &RateLimitConfig, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens + elapsed.as_secs_f64() * config.requests_per_sec
+    }
+
+    /// Refills the bucket up to `now` and takes a token if there is one.
+    fn take(&mut self, config: &RateLimitConfig, now: Instant) -> bool {
+        self.tokens = self.tokens_at(config, now).min(config.burst);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct Buckets {
+    clients: HashMap<String, TokenBucket>,
+    /// Shared by the clients that arrive while `clients` is full.
+    overflow: TokenBucket,
+    swept_at: Instant,
+}
+
+/// Token buckets keyed by client. Managed as Rocket state when rate limiting is enabled.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            config,
+            buckets: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                overflow: TokenBucket::full(&config, now),
+                swept_at: now,
+            }),
+        }
+    }
+
+    /// Takes a token from `client`'s bucket, returning whether the request may proceed.
+    pub fn check(&self, client: &str) -> bool {
+        self.check_at(client, Instant::now())
+    }
+
+    pub fn check_at(&self, client: &str, now: Instant) -> bool {
+        let config = &self.config;
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = &mut *buckets;
+        // Full buckets are the same as none; dropping them in one pass per interval
+        // keeps the cost of a request constant however many clients come and go.
+        if now.saturating_duration_since(buckets.swept_at) >= SWEEP_INTERVAL {
+            buckets
+                .clients
+                .retain(|_, bucket| bucket.tokens_at(config, now) < config.burst);
+            buckets.swept_at = now;
+        }
+        if let Some(bucket) = buckets.clients.get_mut(client) {
+            return bucket.take(config, now);
+        }
+        if buckets.clients.len() >= MAX_TRACKED_CLIENTS {
+            return buckets.overflow.take(config, now);
+        }
+        let mut bucket = TokenBucket::full(config, now);
+        let allowed = bucket.take(config, now);
+        buckets.clients.insert(client.to_string(), bucket);
+        allowed
+    }
+
+    /// Clients with a bucket of their own.
+    pub fn tracked_clients(&self) -> usize {
+        self.buckets.lock().unwrap().clients.len()
+    }
+}
+
+/// Request guard charging the client's bucket. Clients are told apart by their bearer
+/// token when it is one the node knows, and by IP address otherwise: keying on any token
+/// would let a client take a fresh bucket with every request.
+pub struct ClientQuota;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientQuota {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        let limiter = match req.rocket().state::<RateLimiter>() {
+            Some(limiter) => limiter,
+            None => return Outcome::Success(ClientQuota),
+        };
+        let auth = req.rocket().state::<AuthConfig>();
+        let client = match bearer_token(req) {
+            Some(token) if auth.is_some_and(|auth| auth.recognizes(token)) => {
+                format!("token:{}", token)
+            }
+            _ => match req.client_ip() {
+                Some(ip) => format!("ip:{}", ip),
+                None => String::from("unknown"),
+            },
+        };
+        if limiter.check(&client) {
+            Outcome::Success(ClientQuota)
+        } else {
+            Outcome::Error((Status::TooManyRequests, ()))
+        }
+    }
+}
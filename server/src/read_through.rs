@@ -0,0 +1,161 @@
+// read_through.rs
+//! Caching objects read from URLs rather than the bucket. `GET /fetch?src=<url>` serves
+//! `src` like `/s3/<key>` serves a key, but a miss is fetched from `src`, e.g. an S3
+//! presigned URL. Only URLs under one of `fetch_sources` are read, so that clients can't
+//! make the node request arbitrary hosts. The object is cached under a key derived from
+//! `src` without its query, so that URLs signed again hit the same entry, in a namespace
+//! the bucket's keys are kept out of: content from a URL is never served as a bucket key.
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::{get, State};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+use crate::auth::ReadAccess;
+use crate::cache::{ConcurrentDiskCache, GetFileOptions, GetFileResult};
+use crate::conditional::Versioned;
+use crate::error::CacheError;
+use crate::logging::RequestContext;
+use crate::rate_limit::ClientQuota;
+use crate::server::serve_object_from;
+use crate::storage::storage_connector::StorageConnector;
+use crate::storage::throttled_storage_connector::{FetchLimiter, ThrottledStorageConnector};
+use crate::storage::timeout_storage_connector::TimeoutStorageConnector;
+use crate::storage::url_storage_connector::UrlStorageConnector;
+
+/// Parses an entry of `fetch_sources`: an `http` or `https` URL naming a host.
+pub fn parse_source(source: &str) -> Result<Url, String> {
+    let url = Url::parse(source).map_err(|e| format!("{}: {}", source, e))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(format!("{}: not an http(s) URL with a host", source));
+    }
+    Ok(url)
+}
+
+/// Prefix of the cache keys of objects read from URLs.
+pub const FETCH_KEY_PREFIX: &str = "@fetch/";
+
+/// Cache key of the object at `src`: its scheme, host, port and path.
+pub fn fetch_key(src: &Url) -> String {
+    format!(
+        "{}{}/{}:{}{}",
+        FETCH_KEY_PREFIX,
+        src.scheme(),
+        src.host_str().unwrap_or_default(),
+        src.port_or_known_default().unwrap_or_default(),
+        src.path()
+    )
+}
+
+/// Whether `key` is in the namespace of `/fetch`. Requests for bucket keys must not name
+/// one, or they could read what a URL put there, or put bucket content there.
+pub fn is_fetch_key(key: &str) -> bool {
+    key.starts_with(FETCH_KEY_PREFIX)
+}
+
+/// Refuses `key` unless it can name an object of the bucket.
+pub fn check_bucket_key(key: &str) -> Result<(), CacheError> {
+    match is_fetch_key(key) {
+        true => Err(CacheError::InvalidKey {
+            key: key.to_string(),
+        }),
+        false => Ok(()),
+    }
+}
+
+/// Whether `path` is `source` or below it, on a segment boundary: `/daily` covers
+/// `/daily/a.csv` but not `/daily-private/a.csv`.
+fn is_under(path: &str, source: &str) -> bool {
+    match path.strip_prefix(source) {
+        Some(rest) => rest.is_empty() || source.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The URLs `/fetch` may read from, and how it reads them.
+pub struct ReadThrough {
+    sources: Vec<Url>,
+    client: reqwest::Client,
+    fetch_limiter: Option<Arc<FetchLimiter>>,
+    timeout: Option<Duration>,
+}
+
+impl ReadThrough {
+    /// Fetches share the node's S3 fetch queue and time limit. Sources that don't parse
+    /// are left out; `ServerConfig::validate` rejects them.
+    pub fn new(
+        sources: &[String],
+        fetch_limiter: Option<Arc<FetchLimiter>>,
+        timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            sources: sources
+                .iter()
+                .filter_map(|source| parse_source(source).ok())
+                .collect(),
+            client: reqwest::Client::new(),
+            fetch_limiter,
+            timeout,
+        }
+    }
+
+    /// Whether `src` is on the host of a source, under its path.
+    pub fn is_allowed(&self, src: &Url) -> bool {
+        self.sources.iter().any(|source| {
+            source.scheme() == src.scheme()
+                && source.host_str() == src.host_str()
+                && source.port_or_known_default() == src.port_or_known_default()
+                && is_under(src.path(), source.path())
+        })
+    }
+
+    /// A connector reading `src`, whatever key it is asked for.
+    pub fn connector(&self, src: Url) -> Arc<dyn StorageConnector + Send + Sync> {
+        let mut connector: Arc<dyn StorageConnector + Send + Sync> =
+            Arc::new(UrlStorageConnector::new(self.client.clone(), src));
+        if let Some(limiter) = &self.fetch_limiter {
+            connector = Arc::new(ThrottledStorageConnector::new(connector, limiter.clone()));
+        }
+        Arc::new(TimeoutStorageConnector::new(connector, self.timeout))
+    }
+}
+
+/// Serves the object at `src`, fetching a miss from there.
+#[get("/fetch?<src>")]
+pub async fn fetch(
+    _auth: ReadAccess,
+    _quota: ClientQuota,
+    context: RequestContext,
+    src: &str,
+    mut options: GetFileOptions,
+    read_through: &State<ReadThrough>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<Versioned, Custom<String>> {
+    if read_through.sources.is_empty() {
+        return Err(Custom(
+            Status::NotFound,
+            String::from("no fetch_sources are configured\n"),
+        ));
+    }
+    let url = Url::parse(src)
+        .map_err(|e| Custom(Status::BadRequest, format!("invalid src {}: {}\n", src, e)))?;
+    if !read_through.is_allowed(&url) {
+        return Err(Custom(
+            Status::Forbidden,
+            format!("{} is not under any of fetch_sources\n", url),
+        ));
+    }
+    let key = fetch_key(&url);
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("src", src)
+        .finish();
+    options.redirect_path = Some(format!("fetch?{}", query));
+    let connector = read_through.connector(url);
+    let result = serve_object_from(context, key.clone(), options, cache, connector).await;
+    let version = match result {
+        GetFileResult::Redirect(_) => None,
+        _ => cache.object_version(&key).await,
+    };
+    Ok(Versioned(result, version))
+}
@@ -0,0 +1,367 @@
+// rebalance.rs
+//! Moves cached objects to their new owner after keys changed hands, so that resizing the
+//! cluster doesn't send every moved key back to S3. Each pass sends the whole objects this
+//! node no longer owns to their owners, throttled, and deletes each one here once its owner
+//! has it. Objects that could not be sent stay put for the next pass, so an interrupted
+//! migration resumes where it stopped.
+use log::{info, warn};
+use rocket::data::{Data, ToByteUnit};
+use rocket::futures::StreamExt;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{get, post, put, State};
+use serde::{Deserialize, Serialize};
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info_span, Instrument};
+
+use crate::auth::AdminAccess;
+use crate::cache::ConcurrentDiskCache;
+use crate::storage::storage_connector::ObjectStream;
+use crate::telemetry::{TraceContext, TRACEPARENT_HEADER};
+use crate::tls::TlsConfig;
+
+#[cfg(feature = "fault-injection")]
+use crate::faults::{inject, FaultPoint};
+
+/// How long to wait before retrying objects that could not be sent.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long to wait for a peer to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RebalanceConfig {
+    /// Upper bound on the bytes sent to peers per second, so that migration doesn't
+    /// crowd out client traffic.
+    pub max_bytes_per_sec: u64,
+}
+
+impl Default for RebalanceConfig {
+    fn default() -> Self {
+        RebalanceConfig {
+            max_bytes_per_sec: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Progress of the current (or last) pass, plus totals since the node started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RebalanceStatus {
+    pub running: bool,
+    pub passes: u64,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    /// Objects the pass found on the wrong node.
+    pub found: u64,
+    pub moved: u64,
+    pub failed: u64,
+    pub bytes_moved: u64,
+    pub last_error: Option<String>,
+    pub total_moved: u64,
+    pub total_bytes_moved: u64,
+}
+
+/// Keeps the bytes sent over time under a rate.
+pub(crate) struct Throttle {
+    bytes_per_sec: u64,
+    started: Instant,
+    sent: u64,
+}
+
+impl Throttle {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        Throttle {
+            bytes_per_sec: bytes_per_sec.max(1),
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    /// Accounts for `bytes` more and says how long to wait before sending them.
+    pub(crate) fn delay(&mut self, bytes: u64) -> Duration {
+        self.sent += bytes;
+        let due = Duration::from_secs_f64(self.sent as f64 / self.bytes_per_sec as f64);
+        due.saturating_sub(self.started.elapsed())
+    }
+}
+
+/// Why an object was not moved.
+enum SendError {
+    /// The owner won't take the object; it is dropped here as well.
+    Refused(String),
+    /// Worth another try on the next pass.
+    Failed(String),
+}
+
+pub struct Rebalancer {
+    config: RebalanceConfig,
+    /// Admin token the peers expect on `/admin/migrate`.
+    token: Option<String>,
+    http: reqwest::Client,
+    wake: Notify,
+    status: Mutex<RebalanceStatus>,
+}
+
+impl Rebalancer {
+    pub fn new(config: RebalanceConfig, token: Option<String>) -> Self {
+        Rebalancer {
+            config,
+            token,
+            http: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .build()
+                .unwrap(),
+            wake: Notify::new(),
+            status: Mutex::new(RebalanceStatus::default()),
+        }
+    }
+
+    /// Reaches peers behind `tls`; see `TlsConfig::peer_client`.
+    pub fn with_tls(mut self, tls: &TlsConfig) -> IoResult<Self> {
+        self.http = tls.peer_client(reqwest::Client::builder().connect_timeout(CONNECT_TIMEOUT))?;
+        Ok(self)
+    }
+
+    pub fn status(&self) -> RebalanceStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Starts a pass now, or right after the running one.
+    pub fn trigger(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Runs a pass at startup and then whenever ownership changes or a pass is triggered;
+    /// after a pass with failures, also once `RETRY_DELAY` has passed.
+    pub async fn run(self: Arc<Self>, cache: Arc<ConcurrentDiskCache>) {
+        loop {
+            let failed = self.pass(&cache).await;
+            let retry = async {
+                match failed {
+                    0 => std::future::pending().await,
+                    _ => tokio::time::sleep(RETRY_DELAY).await,
+                }
+            };
+            tokio::select! {
+                _ = cache.ownership_changed() => {}
+                _ = self.wake.notified() => {}
+                _ = retry => {}
+            }
+        }
+    }
+
+    /// Sends every misplaced object to its owner; returns how many could not be sent.
+    pub async fn pass(&self, cache: &ConcurrentDiskCache) -> u64 {
+        let misplaced = cache.misplaced_objects().await;
+        self.update(|status| {
+            status.running = true;
+            status.passes += 1;
+            status.started_at = Some(chrono::Utc::now().to_rfc3339());
+            status.finished_at = None;
+            status.found = misplaced.len() as u64;
+            status.moved = 0;
+            status.failed = 0;
+            status.bytes_moved = 0;
+            status.last_error = None;
+        });
+        if !misplaced.is_empty() {
+            info!("Moving {} objects to their new owners", misplaced.len());
+        }
+        let throttle = Arc::new(Mutex::new(Throttle::new(self.config.max_bytes_per_sec)));
+        // One trace per pass, which the owners' spans for each object join.
+        let trace = TraceContext::new();
+        for (uid, endpoint, port) in misplaced {
+            // Evicted or invalidated since the pass started.
+            let body = match cache.open_object(&uid).await {
+                Some(body) => body,
+                None => continue,
+            };
+            let sent = Arc::new(AtomicU64::new(0));
+            let body = throttled(body, throttle.clone(), sent.clone());
+            let sent_to = match cache.peer_url(&endpoint, port, &format!("/admin/migrate/{}", uid))
+            {
+                Some(url) => {
+                    let span = info_span!("migrate", trace_id = %trace.trace_id, uid = %uid);
+                    self.send(url, body, &trace).instrument(span).await
+                }
+                None => Err(SendError::Failed(String::from(
+                    "the owner has no web server address",
+                ))),
+            };
+            match sent_to {
+                Ok(()) => {
+                    cache.forget(&uid).await;
+                    let bytes = sent.load(Ordering::Relaxed);
+                    self.update(|status| {
+                        status.moved += 1;
+                        status.bytes_moved += bytes;
+                        status.total_moved += 1;
+                        status.total_bytes_moved += bytes;
+                    });
+                }
+                Err(SendError::Refused(e)) => {
+                    info!("{}:{} refused {}: {}; dropping it", endpoint, port, uid, e);
+                    cache.invalidate(&uid).await;
+                }
+                Err(SendError::Failed(e)) => {
+                    warn!("Moving {} to {}:{} failed: {}", uid, endpoint, port, e);
+                    self.update(|status| {
+                        status.failed += 1;
+                        status.last_error = Some(e);
+                    });
+                }
+            }
+        }
+        let mut status = self.status.lock().unwrap();
+        status.running = false;
+        status.finished_at = Some(chrono::Utc::now().to_rfc3339());
+        status.failed
+    }
+
+    async fn send(
+        &self,
+        url: url::Url,
+        body: ObjectStream,
+        trace: &TraceContext,
+    ) -> Result<(), SendError> {
+        #[cfg(feature = "fault-injection")]
+        inject(FaultPoint::Peer)
+            .await
+            .map_err(|e| SendError::Failed(e.to_string()))?;
+        let mut request = self
+            .http
+            .put(url)
+            .header(TRACEPARENT_HEADER, trace.traceparent())
+            .body(reqwest::Body::wrap_stream(body));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| SendError::Failed(e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = response.text().await.unwrap_or_default();
+        let error = format!("{}: {}", status, body.trim());
+        if status == reqwest::StatusCode::PAYLOAD_TOO_LARGE {
+            Err(SendError::Refused(error))
+        } else {
+            Err(SendError::Failed(error))
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut RebalanceStatus)) {
+        change(&mut self.status.lock().unwrap());
+    }
+}
+
+/// `body`, paced by `throttle`, counting the bytes that went through into `sent`.
+fn throttled(
+    body: ObjectStream,
+    throttle: Arc<Mutex<Throttle>>,
+    sent: Arc<AtomicU64>,
+) -> ObjectStream {
+    Box::pin(body.then(move |chunk| {
+        let delay = chunk
+            .as_ref()
+            .map(|bytes| {
+                sent.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                throttle.lock().unwrap().delay(bytes.len() as u64)
+            })
+            .unwrap_or_default();
+        async move {
+            tokio::time::sleep(delay).await;
+            chunk
+        }
+    }))
+}
+
+/// Progress of the migration on this node.
+#[get("/admin/rebalance/status")]
+pub async fn rebalance_status(
+    _admin: AdminAccess,
+    rebalancer: &State<Option<Arc<Rebalancer>>>,
+) -> Result<Json<RebalanceStatus>, Custom<String>> {
+    match rebalancer.inner() {
+        Some(rebalancer) => Ok(Json(rebalancer.status())),
+        None => Err(Custom(
+            Status::NotFound,
+            String::from("rebalancing is not configured"),
+        )),
+    }
+}
+
+/// Starts a pass without waiting for ownership to change, e.g. to retry failures now.
+#[post("/admin/rebalance")]
+pub async fn start_rebalance(
+    _admin: AdminAccess,
+    rebalancer: &State<Option<Arc<Rebalancer>>>,
+) -> Result<String, Custom<String>> {
+    match rebalancer.inner() {
+        Some(rebalancer) => {
+            rebalancer.trigger();
+            Ok(String::from("rebalance started\n"))
+        }
+        None => Err(Custom(
+            Status::NotFound,
+            String::from("rebalancing is not configured"),
+        )),
+    }
+}
+
+/// Takes an object a peer no longer owns into this node's cache.
+#[put("/admin/migrate/<key..>", data = "<body>")]
+pub async fn receive(
+    _admin: AdminAccess,
+    trace: TraceContext,
+    key: PathBuf,
+    body: Data<'_>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<String, Custom<String>> {
+    let key = key.to_string_lossy().into_owned();
+    let span = info_span!(
+        "receive_migration",
+        trace_id = %trace.trace_id,
+        remote_parent = trace.parent_id.as_deref().unwrap_or_default(),
+        uid = %key,
+    );
+    receive_object(key, body, cache).instrument(span).await
+}
+
+async fn receive_object(
+    key: String,
+    body: Data<'_>,
+    cache: &ConcurrentDiskCache,
+) -> Result<String, Custom<String>> {
+    let internal = |e: std::io::Error| Custom(Status::InternalServerError, e.to_string());
+    let limit = cache.max_cacheable_size(&key).await;
+    // One byte over the limit tells a body that is too large from one that just fits.
+    let staged = cache
+        .stage_reader(&key, body.open((limit + 1).bytes()))
+        .await
+        .map_err(internal)?;
+    if staged.size() > limit {
+        staged.discard().await;
+        return Err(Custom(
+            Status::PayloadTooLarge,
+            format!(
+                "{} is larger than the {} bytes this node caches",
+                key, limit
+            ),
+        ));
+    }
+    match cache.ingest(&key, staged).await.map_err(internal)? {
+        true => Ok(format!("received {}\n", key)),
+        false => Ok(format!("{} is cached here already\n", key)),
+    }
+}
@@ -1,9 +1,55 @@
 //redis.rs
-use log::debug;
-use redis::Commands;
+use log::{debug, info, warn};
+use redis::cluster::{cluster_pipe, ClusterClient, ClusterConnection};
+use redis::{Cmd, Commands, ConnectionLike, FromRedisValue};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{collections::HashMap, path::PathBuf};
 
-use crate::util::{FileUid, KeyslotId};
+use crate::breaker::CircuitBreaker;
+use crate::cache::{ClusterMember, PORT_OFFSET_TO_WEB_SERVER};
+use crate::cache_core::shard_index;
+use crate::location_cache::{LocationCache, LocationCacheConfig};
+use crate::metadata::{default_weight, key_slot, MetadataStore, StoreError};
+use crate::util::{host_port, split_host_port, FileUid, KeyslotId};
+
+#[cfg(feature = "fault-injection")]
+use crate::faults::{inject_blocking, FaultPoint};
+
+/// Consecutive failed Redis calls after which the node stops trying for a while.
+const BREAKER_THRESHOLD: u32 = 3;
+/// How long the node serves from local state alone before trying Redis again.
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(5);
+/// Upper bound on one Redis command, so that an unreachable cluster fails fast.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+/// Prefix of the keys shared by the nodes when no namespace is configured.
+const DEFAULT_NAMESPACE: &str = "istziio";
+/// Key holding the placement ring.
+const RING_KEY: &str = "ring";
+/// Counter bumped by `drop_listings`; listing pages are saved under the current value,
+/// so bumping it orphans them until they expire.
+const LISTINGS_GENERATION_KEY: &str = "listings:generation";
+/// Hash of node id to the weight the node registered.
+const WEIGHTS_KEY: &str = "weights";
+/// Hash of node id to the `host:port` the node's web server is reached at.
+const ADDRESSES_KEY: &str = "addresses";
+/// Hash of node id to the number of shards whose file locations the node registered,
+/// in a namespace.
+const NODES_KEY: &str = "nodes";
+/// Hash of node id, its `host:port`, to the Unix time it last registered, in standalone
+/// mode.
+const MEMBERS_KEY: &str = "members";
+/// Hash of a node's stable id to the Redis id it last ran under.
+const IDENTITIES_KEY: &str = "identities";
+/// Commands sent per pipeline.
+const PIPELINE_BATCH: usize = 1000;
+/// Connections kept open for later calls; opening one takes several round trips.
+const POOLED_CONNECTIONS: usize = 16;
 
 #[derive(Debug, Clone)]
 pub struct NodeInfo {
@@ -12,49 +58,578 @@ pub struct NodeInfo {
     pub port: u16,
 }
 
+/// Where a listing page saved in `generation` lives, relative to the namespace.
+fn listing_key(generation: u64, key: &str) -> String {
+    format!("listings:{}:{}", generation, key)
+}
+
+/// Where a node keeps the file locations of one of its shards in a namespace.
+pub fn files_key(namespace: &str, node_id: &str, shard: usize) -> String {
+    format!("{}:files:{}:{}", namespace, node_id, shard)
+}
+
+/// Number of slots owned by a different node (or by none) in `after` than in `before`.
+pub fn moved_slots(
+    before: &HashMap<KeyslotId, NodeInfo>,
+    after: &HashMap<KeyslotId, NodeInfo>,
+) -> usize {
+    let owner = |mapping: &HashMap<KeyslotId, NodeInfo>, slot| {
+        mapping
+            .get(slot)
+            .map(|info: &NodeInfo| info.node_id.clone())
+    };
+    before
+        .keys()
+        .chain(after.keys().filter(|slot| !before.contains_key(slot)))
+        .filter(|slot| owner(before, slot) != owner(after, slot))
+        .count()
+}
+
+/// What kind of Redis the nodes share.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum RedisMode {
+    /// A Redis cluster, whose slot owners place the keys.
+    #[default]
+    Cluster,
+    /// A single Redis without cluster support. The nodes register themselves in it and
+    /// keys are placed on the hash ring of the registered nodes.
+    Standalone,
+}
+
+impl FromStr for RedisMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cluster" => Ok(RedisMode::Cluster),
+            "standalone" => Ok(RedisMode::Standalone),
+            _ => Err(format!("unknown Redis mode '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for RedisMode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Where the store's connections come from.
+pub enum RedisClient {
+    Cluster(ClusterClient),
+    Standalone(redis::Client),
+}
+
+/// A connection opened by a `RedisClient`.
+enum Connection {
+    Cluster(ClusterConnection),
+    Standalone(redis::Connection),
+}
+
+impl Connection {
+    /// Sends `commands` as one pipeline; a cluster connection splits it by node.
+    fn pipeline<T: FromRedisValue>(&mut self, commands: Vec<Cmd>) -> redis::RedisResult<T> {
+        match self {
+            Connection::Cluster(conn) => {
+                let mut pipe = cluster_pipe();
+                for command in commands {
+                    pipe.add_command(command);
+                }
+                pipe.query(conn)
+            }
+            Connection::Standalone(conn) => {
+                let mut pipe = redis::pipe();
+                for command in commands {
+                    pipe.add_command(command);
+                }
+                pipe.query(conn)
+            }
+        }
+    }
+}
+
+impl ConnectionLike for Connection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> redis::RedisResult<redis::Value> {
+        match self {
+            Connection::Cluster(conn) => conn.req_packed_command(cmd),
+            Connection::Standalone(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> redis::RedisResult<Vec<redis::Value>> {
+        match self {
+            Connection::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+            Connection::Standalone(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Connection::Cluster(conn) => conn.get_db(),
+            Connection::Standalone(conn) => conn.get_db(),
+        }
+    }
+
+    fn check_connection(&mut self) -> bool {
+        match self {
+            Connection::Cluster(conn) => conn.check_connection(),
+            Connection::Standalone(conn) => conn.check_connection(),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        match self {
+            Connection::Cluster(conn) => conn.is_open(),
+            Connection::Standalone(conn) => conn.is_open(),
+        }
+    }
+}
+
+/// The membership of the nodes sharing a standalone Redis.
+struct Standalone {
+    /// How long a node stays a member after it last registered.
+    member_timeout: Duration,
+    /// The members as last read, sorted by id.
+    members: Vec<NodeInfo>,
+}
+
 pub struct RedisServer {
-    pub client: redis::cluster::ClusterClient,
+    pub client: RedisClient,
     pub myid: String,
     pub slot_to_node_mapping: HashMap<KeyslotId, NodeInfo>,
     pub mapping_initialized: bool,
+    /// Port of the local Redis node, to ask it for its id.
+    pub redis_port: u16,
+    /// Weight this node registers under its id.
+    pub weight: u32,
+    /// Weights the nodes registered, read along with the slot mapping.
+    weights: HashMap<String, u32>,
+    /// Address this node advertises for its web server, if any.
+    address: Option<String>,
+    /// Addresses the nodes advertised, read along with the slot mapping.
+    addresses: HashMap<String, String>,
+    breaker: CircuitBreaker,
+    connections: Mutex<Vec<Connection>>,
+    local: Mutex<LocalFiles>,
+    /// Set when file locations are kept per node and shard, with every other key under
+    /// the namespace, so that clusters can share Redis and a node can drop its own.
+    namespace: Option<Namespace>,
+    /// Seconds a location lives unless renewed; forever when unset.
+    lease_secs: Option<u64>,
+    /// Recent lookups, answered without a round trip while fresh.
+    lookups: Option<Mutex<LocationCache>>,
+    /// Set when Redis is a single instance rather than a cluster.
+    standalone: Option<Standalone>,
+    /// Stable id of this node, under which it registers its file locations instead of
+    /// the Redis id, which changes when Redis loses its cluster state.
+    node_id: Option<String>,
+}
+
+struct Namespace {
+    name: String,
+    /// Shards of this node; locations are kept in one hash per shard.
+    shards: usize,
+    /// Whether the locations left behind by an earlier run of this node are gone.
+    cleaned: bool,
+}
+
+/// This node's own file locations, kept so that it can go on serving its cached files
+/// while Redis is unreachable.
+#[derive(Default)]
+struct LocalFiles {
+    files: HashMap<FileUid, PathBuf>,
+    /// Writes Redis missed, replayed once it is back: a location, or `None` for removal.
+    pending: HashMap<FileUid, Option<PathBuf>>,
 }
 
 impl RedisServer {
-    pub fn new(addrs: Vec<String>) -> Result<Self, redis::RedisError> {
-        let client = redis::cluster::ClusterClient::new(addrs)?;
-        let server = RedisServer {
+    pub fn new(
+        addrs: Vec<String>,
+        redis_port: u16,
+        weight: u32,
+    ) -> Result<Self, redis::RedisError> {
+        let client = RedisClient::Cluster(ClusterClient::new(addrs)?);
+        Ok(Self::with_client(client, redis_port, weight))
+    }
+
+    /// A store on the single Redis at `addr`, which has no cluster support. The node
+    /// registers as `endpoint:redis_port`, renewing its registration on every refresh,
+    /// and drops out of the membership once it has not for `member_timeout`.
+    pub fn standalone(
+        addr: &str,
+        endpoint: &str,
+        redis_port: u16,
+        weight: u32,
+        member_timeout: Duration,
+    ) -> Result<Self, redis::RedisError> {
+        let client = RedisClient::Standalone(redis::Client::open(addr)?);
+        let mut server = Self::with_client(client, redis_port, weight);
+        // Known up front, so `get_myid` never asks Redis.
+        server.myid = host_port(endpoint, redis_port);
+        server.standalone = Some(Standalone {
+            member_timeout,
+            members: Vec::new(),
+        });
+        Ok(server)
+    }
+
+    fn with_client(client: RedisClient, redis_port: u16, weight: u32) -> Self {
+        RedisServer {
             client,
             myid: String::from(""),
             slot_to_node_mapping: HashMap::new(),
             mapping_initialized: false,
+            redis_port,
+            weight,
+            weights: HashMap::new(),
+            address: None,
+            addresses: HashMap::new(),
+            breaker: CircuitBreaker::new(BREAKER_THRESHOLD, BREAKER_COOLDOWN),
+            connections: Mutex::new(Vec::new()),
+            local: Mutex::new(LocalFiles::default()),
+            namespace: None,
+            lease_secs: None,
+            lookups: None,
+            standalone: None,
+            node_id: None,
+        }
+    }
+
+    /// Advertises `address`, a `host:port`, as where the other nodes reach this one's web
+    /// server.
+    pub fn with_advertised_address(mut self, address: String) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    /// Answers repeated lookups of a file from memory for a short while.
+    pub fn with_location_cache(mut self, config: LocationCacheConfig) -> Self {
+        self.lookups = Some(Mutex::new(LocationCache::new(config)));
+        self
+    }
+
+    /// Runs `update` on the lookup cache, if there is one.
+    fn remember(&self, update: impl FnOnce(&mut LocationCache)) {
+        if let Some(lookups) = &self.lookups {
+            update(&mut lookups.lock().unwrap());
+        }
+    }
+
+    fn cached_lookup(&self, uid: &str) -> Option<Option<PathBuf>> {
+        self.lookups.as_ref()?.lock().unwrap().get(uid)
+    }
+
+    /// Lookups answered from memory, and those that went to Redis, since startup.
+    pub fn location_cache_hits_and_misses(&self) -> Option<(u64, u64)> {
+        Some(self.lookups.as_ref()?.lock().unwrap().hits_and_misses())
+    }
+
+    /// Makes every location this node writes expire after `lease_secs` unless renewed
+    /// with `renew_leases`, so that those of files it lost track of go away by themselves.
+    pub fn with_location_lease(mut self, lease_secs: u64) -> Self {
+        self.lease_secs = Some(lease_secs.max(1));
+        self
+    }
+
+    /// Keeps this node's file locations in one hash per shard under `namespace`, and
+    /// the keys shared with other nodes under it too.
+    pub fn with_namespace(mut self, namespace: String, shards: usize) -> Self {
+        self.namespace = Some(Namespace {
+            name: namespace,
+            shards: shards.max(1),
+            cleaned: false,
+        });
+        self
+    }
+
+    /// Registers this node's file locations under `node_id`, which outlives the Redis id,
+    /// and drops what the node registered under the Redis id it ran under before.
+    pub fn with_node_id(mut self, node_id: String) -> Self {
+        self.node_id = Some(node_id);
+        self
+    }
+
+    /// The id this node's file locations are registered under.
+    fn owner(&self) -> &str {
+        self.node_id.as_deref().unwrap_or(&self.myid)
+    }
+
+    /// `name` under the namespace, or under the default prefix without one.
+    fn key(&self, name: &str) -> String {
+        let namespace = self
+            .namespace
+            .as_ref()
+            .map_or(DEFAULT_NAMESPACE, |namespace| namespace.name.as_str());
+        format!("{}:{}", namespace, name)
+    }
+
+    /// The hash holding the location of `uid`: that of the shard the file is cached on.
+    fn files_key_of(&self, namespace: &Namespace, uid: &str) -> String {
+        let shard = shard_index(uid, namespace.shards);
+        files_key(&namespace.name, self.owner(), shard)
+    }
+
+    fn read_location(
+        &self,
+        conn: &mut Connection,
+        uid: &str,
+    ) -> redis::RedisResult<Option<String>> {
+        match &self.namespace {
+            Some(namespace) => conn.hget(self.files_key_of(namespace, uid), uid),
+            None => conn.get(uid),
+        }
+    }
+
+    /// Looks up the locations of `uids`, one pipeline per batch.
+    fn read_locations(
+        &self,
+        conn: &mut Connection,
+        uids: &[FileUid],
+    ) -> redis::RedisResult<Vec<Option<String>>> {
+        let mut locations = Vec::with_capacity(uids.len());
+        for batch in uids.chunks(PIPELINE_BATCH) {
+            let commands = batch
+                .iter()
+                .map(|uid| match &self.namespace {
+                    Some(namespace) => Cmd::hget(self.files_key_of(namespace, uid), uid),
+                    None => Cmd::get(uid),
+                })
+                .collect();
+            locations.extend(conn.pipeline::<Vec<Option<String>>>(commands)?);
+        }
+        Ok(locations)
+    }
+
+    /// Adds the commands recording `loc` as the location of `uid`, or dropping the
+    /// location if `loc` is `None`, to `commands`.
+    fn queue_location(&self, commands: &mut Vec<Cmd>, uid: &str, loc: Option<&Path>) {
+        let loc = loc.map(|loc| loc.to_string_lossy());
+        match (&self.namespace, loc) {
+            (Some(namespace), Some(loc)) => {
+                let key = self.files_key_of(namespace, uid);
+                commands.push(Cmd::hset(&key, uid, loc.as_ref()));
+                // Fields can't expire on their own: the whole hash does, and renewing
+                // rewrites it.
+                if let Some(lease_secs) = self.lease_secs {
+                    commands.push(Cmd::expire(&key, lease_secs as i64));
+                }
+            }
+            (Some(namespace), None) => {
+                commands.push(Cmd::hdel(self.files_key_of(namespace, uid), uid));
+            }
+            (None, Some(loc)) => commands.push(match self.lease_secs {
+                Some(lease_secs) => Cmd::set_ex(uid, loc.as_ref(), lease_secs),
+                None => Cmd::set(uid, loc.as_ref()),
+            }),
+            (None, None) => commands.push(Cmd::del(uid)),
+        }
+    }
+
+    /// Records or drops the location of every file in `changes`, one pipeline per batch.
+    fn write_locations(
+        &self,
+        conn: &mut Connection,
+        changes: &[(FileUid, Option<PathBuf>)],
+    ) -> redis::RedisResult<()> {
+        for batch in changes.chunks(PIPELINE_BATCH) {
+            let mut commands = Vec::with_capacity(batch.len());
+            for (uid, loc) in batch {
+                self.queue_location(&mut commands, uid, loc.as_deref());
+            }
+            conn.pipeline::<()>(commands)?;
+        }
+        Ok(())
+    }
+
+    /// Brings every location hash of this node in line with `files` and renews its lease:
+    /// fields of files no longer held are dropped, the others set again.
+    fn renew_hashes(
+        &self,
+        conn: &mut Connection,
+        namespace: &Namespace,
+        files: &[(FileUid, PathBuf)],
+        lease_secs: u64,
+    ) -> redis::RedisResult<()> {
+        let mut by_shard: Vec<Vec<(&str, String)>> = vec![Vec::new(); namespace.shards];
+        for (uid, loc) in files {
+            let shard = shard_index(uid, namespace.shards);
+            by_shard[shard].push((uid.as_str(), loc.to_string_lossy().into_owned()));
+        }
+        for (shard, fields) in by_shard.iter().enumerate() {
+            let key = files_key(&namespace.name, self.owner(), shard);
+            let held: HashSet<&str> = fields.iter().map(|(uid, _)| *uid).collect();
+            let stale: Vec<String> = conn
+                .hkeys::<_, Vec<String>>(&key)?
+                .into_iter()
+                .filter(|uid| !held.contains(uid.as_str()))
+                .collect();
+            if !stale.is_empty() {
+                conn.hdel::<_, _, ()>(&key, &stale)?;
+            }
+            for batch in fields.chunks(PIPELINE_BATCH) {
+                conn.hset_multiple::<_, _, _, ()>(&key, batch)?;
+            }
+            if !fields.is_empty() {
+                conn.expire::<_, ()>(&key, lease_secs as i64)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the location hashes of `node_id`, which registered `shards` shards.
+    fn delete_node_files(
+        conn: &mut Connection,
+        namespace: &str,
+        node_id: &str,
+        shards: usize,
+    ) -> redis::RedisResult<()> {
+        // One key at a time: the hashes hash to different slots.
+        for shard in 0..shards {
+            conn.del::<_, ()>(files_key(namespace, node_id, shard))?;
+        }
+        Ok(())
+    }
+
+    /// Drops the locations an earlier run of this node left behind, which point at files
+    /// it no longer tracks, and registers the shards it keeps locations for.
+    fn clean_own_files(&mut self) {
+        let Some(namespace) = self
+            .namespace
+            .as_ref()
+            .filter(|namespace| !namespace.cleaned)
+        else {
+            return;
         };
-        Ok(server)
+        let nodes_key = self.key(NODES_KEY);
+        let cleaned = self.call(|conn| {
+            let shards = conn
+                .hget::<_, _, Option<usize>>(&nodes_key, self.owner())?
+                .unwrap_or(0)
+                .max(namespace.shards);
+            Self::delete_node_files(conn, &namespace.name, self.owner(), shards)?;
+            conn.hset::<_, _, _, ()>(&nodes_key, self.owner(), namespace.shards)
+        });
+        match cleaned {
+            Some(()) => {
+                info!("Dropped the file locations of this node's previous run");
+                if let Some(namespace) = self.namespace.as_mut() {
+                    namespace.cleaned = true;
+                }
+            }
+            None => warn!("Failed to drop the file locations of this node's previous run"),
+        }
+    }
+
+    /// Points this node's stable id at its current Redis id. When it ran under another
+    /// one before, e.g. because its Redis restarted without `nodes.conf`, drops the weight,
+    /// address and file locations registered under that one, which nothing else would
+    /// ever claim.
+    fn adopt_node_id(&self) {
+        let Some(node_id) = &self.node_id else {
+            return;
+        };
+        let identities_key = self.key(IDENTITIES_KEY);
+        let previous = self.call(|conn| {
+            let previous: Option<String> = conn.hget(&identities_key, node_id)?;
+            let previous = previous.filter(|previous| *previous != self.myid);
+            if let Some(previous) = &previous {
+                conn.hdel::<_, _, ()>(self.key(WEIGHTS_KEY), previous)?;
+                conn.hdel::<_, _, ()>(self.key(ADDRESSES_KEY), previous)?;
+                if let Some(namespace) = &self.namespace {
+                    let nodes_key = self.key(NODES_KEY);
+                    let shards: Option<usize> = conn.hget(&nodes_key, previous)?;
+                    if let Some(shards) = shards {
+                        Self::delete_node_files(conn, &namespace.name, previous, shards)?;
+                        conn.hdel::<_, _, ()>(&nodes_key, previous)?;
+                    }
+                }
+            }
+            conn.hset::<_, _, _, ()>(&identities_key, node_id, &self.myid)?;
+            Ok(previous)
+        });
+        match previous {
+            Some(Some(previous)) => info!(
+                "Node {} now runs under Redis id {}; dropped what it registered as {}",
+                node_id, self.myid, previous
+            ),
+            Some(None) => {}
+            None => warn!("Failed to register the Redis id of node {}", node_id),
+        }
+    }
+
+    /// Drops the locations registered by nodes that are no longer in the cluster, e.g.
+    /// because they crashed and were replaced. Returns how many nodes were collected.
+    fn collect_departed_nodes(&self) -> usize {
+        let Some(namespace) = &self.namespace else {
+            return 0;
+        };
+        let members: Vec<&str> = match &self.standalone {
+            Some(standalone) => standalone
+                .members
+                .iter()
+                .map(|info| info.node_id.as_str())
+                .collect(),
+            None => self
+                .slot_to_node_mapping
+                .values()
+                .map(|info| info.node_id.as_str())
+                .collect(),
+        };
+        let nodes_key = self.key(NODES_KEY);
+        let identities_key = self.key(IDENTITIES_KEY);
+        self.call(|conn| {
+            let registered: HashMap<String, usize> = conn.hgetall(&nodes_key)?;
+            // Nodes with a stable id are members under the Redis id they run under.
+            let identities: HashMap<String, String> = conn.hgetall(&identities_key)?;
+            let mut collected = 0;
+            for (node_id, shards) in registered {
+                let redis_id = identities.get(&node_id).unwrap_or(&node_id);
+                if members.contains(&redis_id.as_str()) {
+                    continue;
+                }
+                Self::delete_node_files(conn, &namespace.name, &node_id, shards)?;
+                conn.hdel::<_, _, ()>(&nodes_key, &node_id)?;
+                conn.hdel::<_, _, ()>(&identities_key, &node_id)?;
+                collected += 1;
+            }
+            Ok(collected)
+        })
+        .unwrap_or(0)
     }
-    pub fn get_myid(&mut self, redis_port: u16) -> &String {
+    pub fn get_myid(&mut self, redis_port: u16) -> redis::RedisResult<&String> {
         // self.myid cannot be determined at the instantiation moment because the cluster is formed
         // via an external script running redis-cli command. This is a workaround to keep cluster
-        // id inside the struct.
-        if self.myid.len() == 0 {
-            let result = std::process::Command::new("redis-cli")
-                .arg("-c")
-                .arg("-p")
-                .arg(redis_port.to_string())
-                .arg("cluster")
-                .arg("myid")
-                .output()
-                .expect("redis command failed to start");
-            self.myid = String::from_utf8(result.stdout).unwrap();
-            self.myid = String::from(self.myid.trim());
-        }
-        &self.myid
+        // id inside the struct. The local Redis node is asked directly, as redis-cli would.
+        if self.myid.is_empty() {
+            let client = redis::Client::open(format!("redis://127.0.0.1:{}", redis_port))?;
+            let mut conn = client.get_connection_with_timeout(COMMAND_TIMEOUT)?;
+            conn.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+            let myid: String = redis::cmd("CLUSTER").arg("MYID").query(&mut conn)?;
+            self.myid = String::from(myid.trim());
+        }
+        Ok(&self.myid)
     }
     // Function to update the slot-to-node mapping
     pub async fn update_slot_to_node_mapping(&mut self) -> Result<(), redis::RedisError> {
-        let mut conn = self.client.get_connection().unwrap();
-        let shards = redis::cmd("CLUSTER")
-            .arg("SHARDS")
-            .query::<Vec<Vec<redis::Value>>>(&mut conn)?;
+        let shards = self
+            .call(|conn| {
+                redis::cmd("CLUSTER")
+                    .arg("SHARDS")
+                    .query::<Vec<Vec<redis::Value>>>(conn)
+            })
+            .ok_or_else(|| {
+                redis::RedisError::from(std::io::Error::other("Redis cluster unreachable"))
+            })?;
         let mut new_mapping: HashMap<KeyslotId, NodeInfo> = HashMap::new();
+        let mut hostnames: HashMap<String, String> = HashMap::new();
 
         for shard_info in shards {
             if let [_, redis::Value::Bulk(slot_ranges), _, redis::Value::Bulk(nodes_info)] =
@@ -65,6 +640,7 @@ impl RedisServer {
                     let mut node_id = String::new();
                     let mut endpoint = String::new();
                     let mut port: u16 = 0;
+                    let mut hostname = String::new();
 
                     // Iterate through the node_info array
                     let mut iter = node_info.iter();
@@ -93,6 +669,12 @@ impl RedisServer {
                                         debug!("Port: {}", port);
                                     }
                                 }
+                                // Set by `cluster-announce-hostname`, empty otherwise.
+                                "hostname" => {
+                                    if let Some(redis::Value::Data(value)) = iter.next() {
+                                        hostname = String::from_utf8_lossy(value).into_owned();
+                                    }
+                                }
                                 _ => {
                                     iter.next();
                                 } // Ignore other keys
@@ -102,13 +684,19 @@ impl RedisServer {
 
                     // Check if we have both id and endpoint
                     if !node_id.is_empty() && !endpoint.is_empty() {
+                        if !hostname.is_empty() {
+                            hostnames.insert(
+                                node_id.clone(),
+                                host_port(&hostname, port + PORT_OFFSET_TO_WEB_SERVER),
+                            );
+                        }
                         for slots in slot_ranges.chunks(2) {
                             if let [redis::Value::Int(start), redis::Value::Int(end)] = slots {
                                 for slot in *start..=*end {
                                     let info = NodeInfo {
                                         node_id: node_id.clone(),
                                         endpoint: endpoint.clone(),
-                                        port: port.clone(),
+                                        port,
                                     };
                                     new_mapping.insert(slot as KeyslotId, info);
                                 }
@@ -127,64 +715,488 @@ impl RedisServer {
         }
 
         self.slot_to_node_mapping = new_mapping;
+        self.read_registrations();
+        // Nodes that advertise nothing are reached by the hostname Redis announces for them.
+        for (node_id, address) in hostnames {
+            self.addresses.entry(node_id).or_insert(address);
+        }
         debug!(
             "Updated slot-to-node mapping: {:?}",
             self.slot_to_node_mapping
         );
         Ok(())
     }
-    // Location lookup function that uses the updated mapping
-    pub async fn location_lookup(&self, uid: FileUid) -> Option<(String, u16)> {
-        let slot = self.which_slot(uid).await;
-        debug!("Looking up location for slot: {}", slot);
 
-        self.slot_to_node_mapping
-            .get(&slot)
-            .map(|node_info| {
-                if node_info.node_id == self.myid {
-                    debug!("Slot {} is local to this node", slot);
-                    None // If the slot is local, we do not need to redirect.
-                } else {
-                    debug!(
-                        "Redirecting slot {} to node ID {} at {}:{}",
-                        slot, node_info.node_id, node_info.endpoint, node_info.port
-                    );
-                    Some((node_info.endpoint.clone(), node_info.port.clone()))
+    /// `info` as a member serving `slots` slots, with what it registered.
+    fn member(&self, info: &NodeInfo, slots: usize) -> ClusterMember {
+        ClusterMember {
+            node_id: info.node_id.clone(),
+            endpoint: info.endpoint.clone(),
+            port: info.port,
+            slots,
+            weight: self
+                .weights
+                .get(&info.node_id)
+                .copied()
+                .unwrap_or_else(default_weight),
+            address: self.addresses.get(&info.node_id).cloned(),
+            is_self: info.node_id == self.myid,
+        }
+    }
+
+    /// Reads the weights and addresses the nodes registered.
+    fn read_registrations(&mut self) {
+        if let Some(weights) = self.call(|conn| conn.hgetall(self.key(WEIGHTS_KEY))) {
+            self.weights = weights;
+        }
+        if let Some(addresses) = self.call(|conn| conn.hgetall(self.key(ADDRESSES_KEY))) {
+            self.addresses = addresses;
+        }
+    }
+
+    /// Registers this node in a standalone Redis, or renews its registration, and reads
+    /// back the members: the nodes that registered within the member timeout. Nodes that
+    /// did not are dropped.
+    fn update_members(&mut self) -> Result<(), redis::RedisError> {
+        let Some(standalone) = &self.standalone else {
+            return Ok(());
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let timeout = standalone.member_timeout.as_secs();
+        let members_key = self.key(MEMBERS_KEY);
+        let registered = self
+            .call(|conn| {
+                conn.hset::<_, _, _, ()>(&members_key, &self.myid, now)?;
+                let registered: HashMap<String, u64> = conn.hgetall(&members_key)?;
+                let (live, departed): (Vec<_>, Vec<_>) = registered
+                    .into_iter()
+                    .partition(|(_, seen)| now.saturating_sub(*seen) <= timeout);
+                if !departed.is_empty() {
+                    let ids: Vec<&String> = departed.iter().map(|(node_id, _)| node_id).collect();
+                    info!("Dropping nodes that stopped registering: {:?}", ids);
+                    conn.hdel::<_, _, ()>(&members_key, ids)?;
                 }
+                Ok(live)
             })
-            .flatten()
+            .ok_or_else(|| {
+                redis::RedisError::from(std::io::Error::other("standalone Redis unreachable"))
+            })?;
+        let mut members: Vec<NodeInfo> = registered
+            .into_iter()
+            .filter_map(|(node_id, _)| {
+                let (endpoint, port) = split_host_port(&node_id)?;
+                Some(NodeInfo {
+                    endpoint: endpoint.to_string(),
+                    port,
+                    node_id,
+                })
+            })
+            .collect();
+        members.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        if let Some(standalone) = self.standalone.as_mut() {
+            standalone.members = members;
+        }
+        self.read_registrations();
+        Ok(())
+    }
+
+    /// Reads who serves which keys: the slot owners of a cluster, or the members
+    /// registered in a standalone Redis, whose keys are placed on the ring instead.
+    async fn update_mapping(&mut self) -> Result<(), redis::RedisError> {
+        match self.standalone {
+            Some(_) => self.update_members(),
+            None => self.update_slot_to_node_mapping().await,
+        }
+    }
+    /// Runs `command` unless the breaker is open. `None` means Redis could not be reached
+    /// and the caller should fall back to local state.
+    fn call<T>(&self, command: impl FnOnce(&mut Connection) -> redis::RedisResult<T>) -> Option<T> {
+        if !self.breaker.allow() {
+            return None;
+        }
+        let pooled = self.connections.lock().unwrap().pop();
+        let result = pooled
+            .map_or_else(|| self.connect(), Ok)
+            .and_then(|mut conn| {
+                #[cfg(feature = "fault-injection")]
+                inject_blocking(FaultPoint::Redis)?;
+                let value = command(&mut conn)?;
+                Ok((conn, value))
+            });
+        match result {
+            Ok((mut conn, value)) => {
+                if self.breaker.record_success() {
+                    info!("Redis is reachable again, leaving local-only mode");
+                }
+                self.reconcile(&mut conn);
+                let mut connections = self.connections.lock().unwrap();
+                if connections.len() < POOLED_CONNECTIONS {
+                    connections.push(conn);
+                }
+                Some(value)
+            }
+            // A connection that failed is dropped rather than pooled.
+            Err(e) => {
+                if self.breaker.record_failure() {
+                    warn!("Redis unreachable, serving from local state only: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    fn connect(&self) -> redis::RedisResult<Connection> {
+        match &self.client {
+            RedisClient::Cluster(client) => {
+                let conn = client.get_connection()?;
+                conn.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+                conn.set_write_timeout(Some(COMMAND_TIMEOUT))?;
+                Ok(Connection::Cluster(conn))
+            }
+            RedisClient::Standalone(client) => {
+                let conn = client.get_connection_with_timeout(COMMAND_TIMEOUT)?;
+                conn.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+                conn.set_write_timeout(Some(COMMAND_TIMEOUT))?;
+                Ok(Connection::Standalone(conn))
+            }
+        }
+    }
+
+    /// Replays the writes Redis missed while it was unreachable.
+    fn reconcile(&self, conn: &mut Connection) {
+        let pending: Vec<_> = std::mem::take(&mut self.local.lock().unwrap().pending)
+            .into_iter()
+            .collect();
+        if pending.is_empty() {
+            return;
+        }
+        info!("Replaying {} file locations into Redis", pending.len());
+        if self.write_locations(conn, &pending).is_ok() {
+            return;
+        }
+        // Replaying the batches that went through again is harmless.
+        let mut local = self.local.lock().unwrap();
+        for (uid, loc) in pending {
+            // A newer write made while replaying wins.
+            local.pending.entry(uid).or_insert(loc);
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl MetadataStore for RedisServer {
+    fn is_initialized(&self) -> bool {
+        self.mapping_initialized
+    }
+
+    async fn initialize(&mut self) -> Result<(), StoreError> {
+        self.get_myid(self.redis_port)?;
+        let weights_key = self.key(WEIGHTS_KEY);
+        let registered =
+            self.call(|conn| conn.hset::<_, _, _, ()>(&weights_key, &self.myid, self.weight));
+        if registered.is_none() {
+            warn!("Failed to register the weight of node {}", self.myid);
+        }
+        let addresses_key = self.key(ADDRESSES_KEY);
+        // A node that no longer advertises an address drops the one it had.
+        let registered = self.call(|conn| match &self.address {
+            Some(address) => conn.hset::<_, _, _, ()>(&addresses_key, &self.myid, address),
+            None => conn.hdel::<_, _, ()>(&addresses_key, &self.myid),
+        });
+        if registered.is_none() {
+            warn!("Failed to register the address of node {}", self.myid);
+        }
+        self.adopt_node_id();
+        // Nothing is cached yet: the cache starts empty.
+        self.clean_own_files();
+        self.update_mapping().await?;
+        let collected = self.collect_departed_nodes();
+        if collected > 0 {
+            info!(
+                "Dropped the file locations of {} nodes that left the cluster",
+                collected
+            );
+        }
+        self.mapping_initialized = true;
+        Ok(())
+    }
+
+    async fn refresh(&mut self) -> Result<usize, StoreError> {
+        let before = self.slot_to_node_mapping.clone();
+        self.update_mapping().await?;
+        Ok(moved_slots(&before, &self.slot_to_node_mapping))
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.breaker.is_open()
     }
-    pub async fn get_file(&self, uid: FileUid) -> Option<PathBuf> {
-        let mut conn = self.client.get_connection().unwrap();
-        conn.get(uid).map(|u: String| PathBuf::from(u)).ok()
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.call(|conn| redis::cmd("PING").query::<String>(conn))
+            .map(|_| ())
+            .ok_or_else(|| StoreError::Backend(String::from("Redis cluster unreachable")))
+    }
+
+    // Location lookup function that uses the updated mapping. Without Redis the node serves
+    // every key itself rather than trusting a mapping that may have gone stale.
+    async fn location_lookup(&self, uid: FileUid) -> Option<(String, u16)> {
+        if self.breaker.is_open() {
+            return None;
+        }
+        let slot = key_slot(&uid);
+        debug!("Looking up location for slot: {}", slot);
+
+        self.slot_to_node_mapping.get(&slot).and_then(|node_info| {
+            if node_info.node_id == self.myid {
+                debug!("Slot {} is local to this node", slot);
+                None // If the slot is local, we do not need to redirect.
+            } else {
+                debug!(
+                    "Redirecting slot {} to node ID {} at {}:{}",
+                    slot, node_info.node_id, node_info.endpoint, node_info.port
+                );
+                Some((node_info.endpoint.clone(), node_info.port))
+            }
+        })
+    }
+    async fn get_file(&self, uid: FileUid) -> Option<PathBuf> {
+        if let Some(loc) = self.cached_lookup(&uid) {
+            return loc;
+        }
+        match self.call(|conn| self.read_location(conn, &uid)) {
+            Some(loc) => {
+                let loc = loc.map(PathBuf::from);
+                self.remember(|lookups| lookups.insert(uid, loc.clone()));
+                loc
+            }
+            None => self.local.lock().unwrap().files.get(&uid).cloned(),
+        }
+    }
+    async fn get_files(&self, uids: Vec<FileUid>) -> Vec<Option<PathBuf>> {
+        let cached: Vec<Option<Option<PathBuf>>> =
+            uids.iter().map(|uid| self.cached_lookup(uid)).collect();
+        let unknown: Vec<FileUid> = uids
+            .iter()
+            .zip(&cached)
+            .filter(|(_, loc)| loc.is_none())
+            .map(|(uid, _)| uid.clone())
+            .collect();
+        let mut fetched = match self.call(|conn| self.read_locations(conn, &unknown)) {
+            Some(locations) => {
+                let locations: Vec<Option<PathBuf>> = locations
+                    .into_iter()
+                    .map(|loc| loc.map(PathBuf::from))
+                    .collect();
+                self.remember(|lookups| {
+                    for (uid, loc) in unknown.iter().zip(&locations) {
+                        lookups.insert(uid.clone(), loc.clone());
+                    }
+                });
+                locations
+            }
+            None => {
+                let local = self.local.lock().unwrap();
+                unknown
+                    .iter()
+                    .map(|uid| local.files.get(uid).cloned())
+                    .collect()
+            }
+        }
+        .into_iter();
+        cached
+            .into_iter()
+            .map(|loc| loc.unwrap_or_else(|| fetched.next().flatten()))
+            .collect()
     }
-    pub async fn set_file_cache_loc(&self, uid: FileUid, loc: PathBuf) -> Result<(), ()> {
-        let mut conn = self.client.get_connection().unwrap();
-        let loc_str = loc.into_os_string().into_string().unwrap();
-        debug!("try to set key [{}], value [{}] in redis", &uid, &loc_str);
-        let _ = conn.set::<String, String, String>(uid.clone(), loc_str); // [TODO] Error handling
+    async fn set_file_cache_loc(&self, uid: FileUid, loc: PathBuf) -> Result<(), ()> {
+        debug!(
+            "try to set key [{}], value [{}] in redis",
+            &uid,
+            loc.display()
+        );
+        self.set_file_cache_locs(vec![(uid, loc)]).await
+    }
+    async fn set_file_cache_locs(&self, files: Vec<(FileUid, PathBuf)>) -> Result<(), ()> {
+        {
+            let mut local = self.local.lock().unwrap();
+            for (uid, loc) in &files {
+                local.files.insert(uid.clone(), loc.clone());
+            }
+        }
+        self.remember(|lookups| {
+            for (uid, loc) in &files {
+                lookups.insert(uid.clone(), Some(loc.clone()));
+            }
+        });
+        let changes: Vec<(FileUid, Option<PathBuf>)> = files
+            .into_iter()
+            .map(|(uid, loc)| (uid, Some(loc)))
+            .collect();
+        if self
+            .call(|conn| self.write_locations(conn, &changes))
+            .is_none()
+        {
+            self.local.lock().unwrap().pending.extend(changes);
+        }
         Ok(())
     }
-    pub async fn remove_file(&self, uid: FileUid) -> Result<(), ()> {
-        let mut conn = self.client.get_connection().unwrap();
+    async fn remove_file(&self, uid: FileUid) -> Result<(), ()> {
+        self.local.lock().unwrap().files.remove(&uid);
+        self.remember(|lookups| lookups.insert(uid.clone(), None));
         debug!("remove key [{}] in redis", &uid);
-        let _ = conn.del::<String, u8>(uid); // [TODO] Error handling
+        let change = [(uid, None)];
+        if self
+            .call(|conn| self.write_locations(conn, &change))
+            .is_none()
+        {
+            self.local.lock().unwrap().pending.extend(change);
+        }
         Ok(())
     }
-    async fn which_slot(&self, uid: FileUid) -> KeyslotId {
-        let mut conn = self.client.get_connection().unwrap();
-        let keyslot = redis::cmd("CLUSTER")
-            .arg("KEYSLOT")
-            .arg(uid)
-            .query::<KeyslotId>(&mut conn)
-            .unwrap();
-        keyslot
-    }
-    pub fn flush_all(&self) {
-        let mut conn = self.client.get_connection().unwrap();
-        let _ = redis::cmd("FLUSHALL")
-            .arg("SYNC")
-            .query::<redis::Value>(&mut conn)
-            .unwrap();
+    async fn remove_files(&self, uids: Vec<FileUid>) -> Result<(), ()> {
+        {
+            let mut local = self.local.lock().unwrap();
+            for uid in &uids {
+                local.files.remove(uid);
+            }
+        }
+        self.remember(|lookups| {
+            for uid in &uids {
+                lookups.insert(uid.clone(), None);
+            }
+        });
+        let changes: Vec<(FileUid, Option<PathBuf>)> =
+            uids.into_iter().map(|uid| (uid, None)).collect();
+        if self
+            .call(|conn| self.write_locations(conn, &changes))
+            .is_none()
+        {
+            self.local.lock().unwrap().pending.extend(changes);
+        }
+        Ok(())
+    }
+    async fn flush_all(&self) {
+        {
+            let mut local = self.local.lock().unwrap();
+            local.files.clear();
+            local.pending.clear();
+        }
+        self.remember(LocationCache::clear);
+        if self.namespace.is_some() {
+            let _ = self.forget_own_files().await;
+            return;
+        }
+        let _ = self.call(|conn| {
+            redis::cmd("FLUSHALL")
+                .arg("SYNC")
+                .query::<redis::Value>(conn)
+        });
+    }
+
+    async fn renew_leases(&self, files: Vec<(FileUid, PathBuf)>) -> Result<usize, StoreError> {
+        let Some(lease_secs) = self.lease_secs else {
+            return Ok(0);
+        };
+        self.call(|conn| match &self.namespace {
+            Some(namespace) => self.renew_hashes(conn, namespace, &files, lease_secs),
+            None => {
+                let changes: Vec<_> = files
+                    .iter()
+                    .map(|(uid, loc)| (uid.clone(), Some(loc.clone())))
+                    .collect();
+                self.write_locations(conn, &changes)
+            }
+        })
+        .map(|()| files.len())
+        .ok_or_else(|| StoreError::Backend(String::from("Redis cluster unreachable")))
+    }
+
+    fn forget_cached_locations(&self, prefix: &str) {
+        self.remember(|lookups| lookups.remove_prefix(prefix));
+    }
+
+    async fn release_file(&self, uid: FileUid) {
+        if self.namespace.is_some() {
+            let _ = self.remove_file(uid).await;
+        }
+    }
+
+    async fn forget_own_files(&self) -> Result<(), StoreError> {
+        let Some(namespace) = &self.namespace else {
+            return Ok(());
+        };
+        self.local.lock().unwrap().pending.clear();
+        self.remember(LocationCache::clear);
+        self.call(|conn| {
+            Self::delete_node_files(conn, &namespace.name, self.owner(), namespace.shards)
+        })
+        .ok_or_else(|| StoreError::Backend(String::from("Redis cluster unreachable")))
+    }
+
+    fn members(&self) -> Vec<ClusterMember> {
+        let mut members: Vec<ClusterMember> = Vec::new();
+        if let Some(standalone) = &self.standalone {
+            // Without slots; keys are placed on the ring.
+            members.extend(standalone.members.iter().map(|info| self.member(info, 0)));
+        }
+        for info in self.slot_to_node_mapping.values() {
+            match members.iter_mut().find(|m| m.node_id == info.node_id) {
+                Some(member) => member.slots += 1,
+                None => members.push(self.member(info, 1)),
+            }
+        }
+        members.sort_by(|a, b| (&a.endpoint, a.port).cmp(&(&b.endpoint, b.port)));
+        members
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> Result<i64, StoreError> {
+        let mut conn = self.connect()?;
+        Ok(redis::cmd("PUBLISH")
+            .arg(channel)
+            .arg(message)
+            .query::<i64>(&mut conn)?)
+    }
+
+    async fn load_ring(&self) -> Result<Option<String>, StoreError> {
+        self.call(|conn| conn.get::<_, Option<String>>(self.key(RING_KEY)))
+            .ok_or_else(|| StoreError::Backend(String::from("Redis cluster unreachable")))
+    }
+
+    async fn save_ring(&self, ring: &str) -> Result<(), StoreError> {
+        self.call(|conn| conn.set::<_, _, ()>(self.key(RING_KEY), ring))
+            .ok_or_else(|| StoreError::Backend(String::from("Redis cluster unreachable")))
+    }
+
+    async fn load_listing(&self, key: &str) -> Result<Option<String>, StoreError> {
+        self.call(|conn| {
+            let generation: u64 = conn
+                .get::<_, Option<u64>>(self.key(LISTINGS_GENERATION_KEY))?
+                .unwrap_or(0);
+            conn.get::<_, Option<String>>(self.key(&listing_key(generation, key)))
+        })
+        .ok_or_else(|| StoreError::Backend(String::from("Redis cluster unreachable")))
+    }
+
+    async fn save_listing(&self, key: &str, page: &str, ttl: Duration) -> Result<(), StoreError> {
+        self.call(|conn| {
+            let generation: u64 = conn
+                .get::<_, Option<u64>>(self.key(LISTINGS_GENERATION_KEY))?
+                .unwrap_or(0);
+            conn.set_ex::<_, _, ()>(
+                self.key(&listing_key(generation, key)),
+                page,
+                ttl.as_secs().max(1),
+            )
+        })
+        .ok_or_else(|| StoreError::Backend(String::from("Redis cluster unreachable")))
+    }
+
+    async fn drop_listings(&self) -> Result<(), StoreError> {
+        self.call(|conn| conn.incr::<_, _, u64>(self.key(LISTINGS_GENERATION_KEY), 1))
+            .map(|_| ())
+            .ok_or_else(|| StoreError::Backend(String::from("Redis cluster unreachable")))
     }
 }
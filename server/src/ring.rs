@@ -0,0 +1,380 @@
+// ring.rs
+//! Key placement on a consistent hash ring, as an alternative to Redis hash slots. Every
+//! node gets a number of virtual nodes on the ring, so giving a node more of them hands it
+//! a larger share of the keys. The ring is kept in the metadata store and only changes
+//! through the rebalancing operations below; each node reads it on startup and on every
+//! mapping refresh.
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+use crate::auth::AdminAccess;
+use crate::cache::{ClusterMember, ConcurrentDiskCache};
+use crate::metadata::{default_weight, StoreError};
+
+/// Virtual nodes a node of weight 1 gets when it joins the ring, unless configured
+/// otherwise.
+pub const DEFAULT_VNODES_PER_NODE: u32 = 128;
+
+/// How keys are assigned to nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Placement {
+    /// By the slot owners of the metadata store.
+    #[default]
+    Slots,
+    /// By the hash ring kept in the metadata store.
+    Ring,
+}
+
+impl FromStr for Placement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "slots" => Ok(Placement::Slots),
+            "ring" => Ok(Placement::Ring),
+            _ => Err(format!("unknown placement '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Placement {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RingError {
+    #[error("keys are placed by slot; set placement = \"ring\" to use the ring")]
+    Disabled,
+    #[error("node {0} is not on the ring")]
+    UnknownNode(String),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error("malformed ring in the metadata store: {0}")]
+    Malformed(#[from] serde_json::Error),
+}
+
+/// A physical node on the ring.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RingNode {
+    pub node_id: String,
+    pub endpoint: String,
+    pub port: u16,
+    pub vnodes: u32,
+    /// The weight the node had registered when its virtual nodes were last set.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+/// A node with the share of the key space it owns.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RingShare {
+    #[serde(flatten)]
+    pub node: RingNode,
+    pub share: f64,
+}
+
+/// A change to the ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RingOp {
+    /// Adds the cluster members missing from the ring with the default number of virtual
+    /// nodes times their weight, updates the addresses of the others and drops nodes that
+    /// left. Members whose weight changed get their virtual nodes recomputed the same way.
+    Sync,
+    /// Gives a node `vnodes` virtual nodes; 0 takes it off the ring until the next sync.
+    SetVnodes { node_id: String, vnodes: u32 },
+}
+
+/// FNV-1a followed by the SplitMix64 finalizer, which spreads the similar names of one
+/// node's virtual nodes over the whole ring. Fixed so that every node computes the same.
+fn ring_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Splits the ring at `boundaries` (sorted, without duplicates) and yields every arc as
+/// its end point and its share of the ring.
+fn arcs(boundaries: &[u64]) -> impl Iterator<Item = (u64, f64)> + '_ {
+    const RING_SIZE: f64 = u64::MAX as f64 + 1.0;
+    let last = boundaries.last().copied().unwrap_or_default();
+    boundaries.iter().enumerate().map(move |(i, &end)| {
+        let length = match i {
+            0 => (u64::MAX - last) as f64 + end as f64 + 1.0,
+            _ => (end - boundaries[i - 1]) as f64,
+        };
+        (end, length / RING_SIZE)
+    })
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashRing {
+    /// Sorted by id, so that equal rings compare equal.
+    nodes: Vec<RingNode>,
+    /// Positions of the virtual nodes with the index of their node, sorted.
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    pub fn new(nodes: Vec<RingNode>) -> Self {
+        let mut ring = HashRing {
+            nodes,
+            points: Vec::new(),
+        };
+        ring.rebuild();
+        ring
+    }
+
+    fn rebuild(&mut self) {
+        self.nodes.retain(|node| node.vnodes > 0);
+        self.nodes.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        self.points = self
+            .nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, node)| {
+                (0..node.vnodes).map(move |vnode| {
+                    let label = format!("{}#{}", node.node_id, vnode);
+                    (ring_hash(label.as_bytes()), index)
+                })
+            })
+            .collect();
+        self.points.sort_unstable();
+    }
+
+    pub fn nodes(&self) -> &[RingNode] {
+        &self.nodes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The node of the first virtual node at or after `hash`, wrapping around.
+    fn owner_at(&self, hash: u64) -> Option<&RingNode> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let index = self.points.partition_point(|(point, _)| *point < hash);
+        let (_, node) = self.points[index % self.points.len()];
+        Some(&self.nodes[node])
+    }
+
+    pub fn owner(&self, key: &str) -> Option<&RingNode> {
+        self.owner_at(ring_hash(key.as_bytes()))
+    }
+
+    fn boundaries(&self) -> Vec<u64> {
+        let mut boundaries: Vec<u64> = self.points.iter().map(|(point, _)| *point).collect();
+        boundaries.dedup();
+        boundaries
+    }
+
+    /// Every node with the share of the key space it owns.
+    pub fn shares(&self) -> Vec<RingShare> {
+        let mut shares: HashMap<&str, f64> = HashMap::new();
+        for (end, share) in arcs(&self.boundaries()) {
+            if let Some(owner) = self.owner_at(end) {
+                *shares.entry(&owner.node_id).or_default() += share;
+            }
+        }
+        self.nodes
+            .iter()
+            .map(|node| RingShare {
+                node: node.clone(),
+                share: shares
+                    .get(node.node_id.as_str())
+                    .copied()
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub fn apply(
+        &mut self,
+        op: RingOp,
+        members: &[ClusterMember],
+        default_vnodes: u32,
+    ) -> Result<(), RingError> {
+        match op {
+            RingOp::Sync => {
+                let nodes = members
+                    .iter()
+                    .map(|member| RingNode {
+                        node_id: member.node_id.clone(),
+                        endpoint: member.endpoint.clone(),
+                        port: member.port,
+                        vnodes: match self
+                            .nodes
+                            .iter()
+                            .find(|node| node.node_id == member.node_id)
+                        {
+                            Some(node) if node.weight == member.weight => node.vnodes,
+                            _ => default_vnodes.saturating_mul(member.weight),
+                        },
+                        weight: member.weight,
+                    })
+                    .collect();
+                self.nodes = nodes;
+            }
+            RingOp::SetVnodes { node_id, vnodes } => {
+                let node = self
+                    .nodes
+                    .iter_mut()
+                    .find(|node| node.node_id == node_id)
+                    .ok_or(RingError::UnknownNode(node_id))?;
+                node.vnodes = vnodes;
+            }
+        }
+        self.rebuild();
+        Ok(())
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(&self.nodes).unwrap()
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, serde_json::Error> {
+        Ok(HashRing::new(serde_json::from_str(encoded)?))
+    }
+}
+
+/// Share of the key space owned by a different node (or by none) in `after` than in
+/// `before`.
+pub fn moved_share(before: &HashRing, after: &HashRing) -> f64 {
+    let mut boundaries = before.boundaries();
+    boundaries.extend(after.boundaries());
+    boundaries.sort_unstable();
+    boundaries.dedup();
+    let owner = |ring: &HashRing, end| ring.owner_at(end).map(|node| node.node_id.clone());
+    arcs(&boundaries)
+        .filter(|(end, _)| owner(before, *end) != owner(after, *end))
+        .map(|(_, share)| share)
+        .sum()
+}
+
+/// A node's copy of the ring, as last read from the metadata store, with its own id.
+pub struct RingPlacement {
+    /// Virtual nodes a node gets when it joins the ring.
+    pub vnodes: u32,
+    /// Whether the ring is synced with the members whenever it is loaded.
+    pub syncs: bool,
+    current: RwLock<Option<(HashRing, String)>>,
+}
+
+impl RingPlacement {
+    pub fn new(vnodes: u32) -> Self {
+        RingPlacement {
+            vnodes,
+            syncs: false,
+            current: RwLock::new(None),
+        }
+    }
+
+    /// Has every load of the ring sync it with the members, as `RingOp::Sync` would.
+    pub fn syncing(mut self, syncs: bool) -> Self {
+        self.syncs = syncs;
+        self
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.current.read().unwrap().is_some()
+    }
+
+    /// The ring, if it has been loaded.
+    pub fn ring(&self) -> Option<HashRing> {
+        let current = self.current.read().unwrap();
+        current.as_ref().map(|(ring, _)| ring.clone())
+    }
+
+    /// Replaces the copy, finding this node among `members`; returns the share of the key
+    /// space that changed owner.
+    pub fn set(&self, ring: HashRing, members: &[ClusterMember]) -> f64 {
+        let self_id = members
+            .iter()
+            .find(|member| member.is_self)
+            .map(|member| member.node_id.clone())
+            .unwrap_or_default();
+        let mut current = self.current.write().unwrap();
+        let moved = match current.as_ref() {
+            Some((before, _)) => moved_share(before, &ring),
+            None => 0.0,
+        };
+        *current = Some((ring, self_id));
+        moved
+    }
+
+    /// The `(endpoint, port)` of the node owning `key`, or `None` if this node owns it. A
+    /// node that has not loaded the ring yet serves every key itself.
+    pub fn lookup(&self, key: &str) -> Option<(String, u16)> {
+        let current = self.current.read().unwrap();
+        let (ring, self_id) = current.as_ref()?;
+        ring.owner(key)
+            .filter(|owner| owner.node_id != *self_id)
+            .map(|owner| (owner.endpoint.clone(), owner.port))
+    }
+}
+
+fn ring_error(e: RingError) -> Custom<String> {
+    let status = match e {
+        RingError::Disabled | RingError::UnknownNode(_) => Status::NotFound,
+        RingError::Store(_) => Status::ServiceUnavailable,
+        RingError::Malformed(_) => Status::InternalServerError,
+    };
+    Custom(status, e.to_string())
+}
+
+async fn rebalance(cache: &ConcurrentDiskCache, op: RingOp) -> Result<String, Custom<String>> {
+    let moved = cache.rebalance_ring(op).await.map_err(ring_error)?;
+    Ok(format!(
+        "{:.2}% of the key space changed owner\n",
+        moved * 100.0
+    ))
+}
+
+/// The ring as this node last read it, with every node's share of the key space.
+#[get("/admin/ring")]
+pub async fn show_ring(
+    _admin: AdminAccess,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<Json<Vec<RingShare>>, Custom<String>> {
+    let ring = cache.ring().await.map_err(ring_error)?;
+    Ok(Json(ring.shares()))
+}
+
+/// Puts new cluster members on the ring and takes departed ones off.
+#[post("/admin/ring/sync")]
+pub async fn sync_ring(
+    _admin: AdminAccess,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<String, Custom<String>> {
+    rebalance(cache, RingOp::Sync).await
+}
+
+/// Sets how many virtual nodes `node_id` has, and so its share of the keys.
+#[post("/admin/ring/vnodes/<node_id>/<vnodes>")]
+pub async fn set_vnodes(
+    _admin: AdminAccess,
+    node_id: String,
+    vnodes: u32,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<String, Custom<String>> {
+    rebalance(cache, RingOp::SetVnodes { node_id, vnodes }).await
+}
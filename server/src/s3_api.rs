@@ -0,0 +1,439 @@
+// s3_api.rs
+//! A path-style subset of the S3 REST API (ListObjectsV2, GetObject and HeadObject), so
+//! S3 clients can use the node as their endpoint. Request signatures are not verified;
+//! access is governed by the read tokens like every other data route.
+use chrono::{SecondsFormat, TimeZone, Utc};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::RawXml;
+use rocket::response::{self, Responder, Response};
+use rocket::{get, head, FromForm, State};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::io::{self, Cursor};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::ReadAccess;
+use crate::cache::{ConcurrentDiskCache, GetFileOptions, GetFileResult};
+use crate::conditional::http_date;
+use crate::dashboard::escape;
+use crate::logging::RequestContext;
+use crate::rate_limit::ClientQuota;
+use crate::server::{serve_object, ServerConfig};
+use crate::storage::storage_connector::{ListRequest, ObjectInfo, ObjectListing, StorageConnector};
+use crate::util::hash;
+use crate::versioning::PinnedVersion;
+
+/// Keys per ListObjectsV2 page unless the client asks for fewer, as on S3.
+pub const MAX_KEYS: u32 = 1000;
+/// First path segments taken by the node's own routes, which can't name a bucket.
+pub const RESERVED_SEGMENTS: [&str; 7] = [
+    "cluster",
+    "s3",
+    "parquet",
+    "stats",
+    "dashboard",
+    "admin",
+    "clear",
+];
+/// Object metadata remembered for GetObject headers before the table is reset.
+const MAX_REMEMBERED_OBJECTS: usize = 100_000;
+
+/// Bucket names the API answers to and the metadata of objects served through it;
+/// managed as Rocket state.
+pub struct S3Api {
+    /// Each bucket with the prefix its keys carry in the cache.
+    buckets: Vec<(String, String)>,
+    /// A mock endpoint has no bucket name, so any name reaches it.
+    any_bucket: bool,
+    objects: Mutex<HashMap<String, ObjectInfo>>,
+}
+
+impl S3Api {
+    /// The top-level bucket serves cache keys as they are; a backend that strips its
+    /// prefix serves them under that prefix.
+    pub fn new(config: &ServerConfig) -> Self {
+        let mut buckets: Vec<(String, String)> = Vec::new();
+        let named = config
+            .bucket
+            .iter()
+            .map(|bucket| (bucket, String::new()))
+            .chain(config.backends.iter().filter_map(|backend| {
+                let prefix = if backend.strip_prefix {
+                    backend.prefix.clone()
+                } else {
+                    String::new()
+                };
+                backend.bucket.as_ref().map(|bucket| (bucket, prefix))
+            }));
+        for (bucket, prefix) in named {
+            if !buckets.iter().any(|(name, _)| name == bucket) {
+                buckets.push((bucket.clone(), prefix));
+            }
+        }
+        Self {
+            buckets,
+            any_bucket: config.use_mock_s3_endpoint.is_some(),
+            objects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// What to put in front of a key of `bucket` to get its cache key, or `None` for
+    /// buckets the node doesn't serve.
+    pub fn key_prefix(&self, bucket: &str) -> Option<&str> {
+        if RESERVED_SEGMENTS.contains(&bucket) {
+            return None;
+        }
+        match self.buckets.iter().find(|(name, _)| name == bucket) {
+            Some((_, prefix)) => Some(prefix),
+            None if self.any_bucket => Some(""),
+            None => None,
+        }
+    }
+
+    /// Metadata for GetObject headers, asked of the backend once per key. Objects are
+    /// treated as immutable, as everywhere else in the cache.
+    async fn object_info(
+        &self,
+        key: &str,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+    ) -> Option<ObjectInfo> {
+        if let Some(info) = self.objects.lock().unwrap().get(key) {
+            return Some(info.clone());
+        }
+        let info = connector.head_object(key).await.ok()?;
+        let mut objects = self.objects.lock().unwrap();
+        if objects.len() >= MAX_REMEMBERED_OBJECTS {
+            objects.clear();
+        }
+        objects.insert(key.to_string(), info.clone());
+        Some(info)
+    }
+}
+
+/// The bucket of an S3 API request. Forwards when the first segment names no bucket, so
+/// that e.g. `HEAD /s3/<key>` still reaches the GET route it always did.
+pub struct S3Bucket {
+    pub name: String,
+    pub key_prefix: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for S3Bucket {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        let name = match req.routed_segment(0) {
+            Some(name) => name,
+            None => return Outcome::Forward(Status::NotFound),
+        };
+        match req
+            .rocket()
+            .state::<S3Api>()
+            .and_then(|api| api.key_prefix(name))
+        {
+            Some(key_prefix) => Outcome::Success(S3Bucket {
+                name: name.to_string(),
+                key_prefix: key_prefix.to_string(),
+            }),
+            None => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+/// Query parameters of ListObjectsV2.
+#[derive(Debug, Default, FromForm)]
+pub struct ListParams {
+    #[field(name = "list-type")]
+    pub list_type: Option<u8>,
+    pub prefix: Option<String>,
+    pub delimiter: Option<String>,
+    #[field(name = "continuation-token")]
+    pub continuation_token: Option<String>,
+    #[field(name = "start-after")]
+    pub start_after: Option<String>,
+    #[field(name = "max-keys")]
+    pub max_keys: Option<u32>,
+}
+
+impl ListParams {
+    pub fn to_request(&self) -> ListRequest {
+        ListRequest {
+            prefix: self.prefix.clone().unwrap_or_default(),
+            delimiter: self.delimiter.clone().filter(|d| !d.is_empty()),
+            continuation_token: self.continuation_token.clone(),
+            start_after: self.start_after.clone(),
+            max_keys: self.max_keys.unwrap_or(MAX_KEYS).min(MAX_KEYS),
+        }
+    }
+}
+
+/// An S3 `<Error>` document.
+pub fn error_xml(code: &str, message: &str, resource: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>{}</Code>\
+         <Message>{}</Message><Resource>{}</Resource></Error>",
+        escape(code),
+        escape(message),
+        escape(resource)
+    )
+}
+
+fn error_response(
+    status: Status,
+    code: &str,
+    message: &str,
+    resource: &str,
+) -> (Status, RawXml<String>) {
+    (status, RawXml(error_xml(code, message, resource)))
+}
+
+fn iso8601(secs: i64) -> Option<String> {
+    Utc.timestamp_opt(secs, 0)
+        .single()
+        .map(|time| time.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+/// A ListObjectsV2 `<ListBucketResult>` for `listing`, whose keys are relative to
+/// `bucket`.
+pub fn list_bucket_result(bucket: &str, request: &ListRequest, listing: &ObjectListing) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+         <Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys>\
+         <IsTruncated>{}</IsTruncated>",
+        escape(bucket),
+        escape(&request.prefix),
+        listing.objects.len() + listing.common_prefixes.len(),
+        request.max_keys,
+        listing.next_continuation_token.is_some()
+    );
+    let optional = [
+        ("Delimiter", &request.delimiter),
+        ("ContinuationToken", &request.continuation_token),
+        ("NextContinuationToken", &listing.next_continuation_token),
+        ("StartAfter", &request.start_after),
+    ];
+    for (tag, value) in optional {
+        if let Some(value) = value {
+            let _ = write!(xml, "<{0}>{1}</{0}>", tag, escape(value));
+        }
+    }
+    for object in &listing.objects {
+        let _ = write!(xml, "<Contents><Key>{}</Key>", escape(&object.key));
+        if let Some(time) = object.last_modified.and_then(iso8601) {
+            let _ = write!(xml, "<LastModified>{}</LastModified>", time);
+        }
+        if let Some(e_tag) = &object.e_tag {
+            let _ = write!(xml, "<ETag>{}</ETag>", escape(e_tag));
+        }
+        let _ = write!(
+            xml,
+            "<Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            object.size
+        );
+    }
+    for prefix in &listing.common_prefixes {
+        let _ = write!(
+            xml,
+            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+            escape(prefix)
+        );
+    }
+    xml.push_str("</ListBucketResult>");
+    xml
+}
+
+/// Moves `request` from the bucket's keys to cache keys.
+pub fn to_cache_keys(request: &ListRequest, key_prefix: &str) -> ListRequest {
+    ListRequest {
+        prefix: format!("{}{}", key_prefix, request.prefix),
+        start_after: request
+            .start_after
+            .as_ref()
+            .map(|key| format!("{}{}", key_prefix, key)),
+        ..request.clone()
+    }
+}
+
+/// Moves `listing` from cache keys back to the bucket's keys.
+pub fn to_bucket_keys(mut listing: ObjectListing, key_prefix: &str) -> ObjectListing {
+    for object in &mut listing.objects {
+        if let Some(key) = object.key.strip_prefix(key_prefix) {
+            object.key = key.to_string();
+        }
+    }
+    for prefix in &mut listing.common_prefixes {
+        if let Some(stripped) = prefix.strip_prefix(key_prefix) {
+            *prefix = stripped.to_string();
+        }
+    }
+    listing
+}
+
+pub(crate) fn connector_for<'a>(
+    key: &str,
+    s3_connectors: &'a [Arc<dyn StorageConnector + Send + Sync>],
+) -> &'a Arc<dyn StorageConnector + Send + Sync> {
+    &s3_connectors[hash(&key.to_string()) % s3_connectors.len()]
+}
+
+/// ListObjectsV2, proxied to the backing store through the listing cache. Only
+/// `list-type=2` is supported.
+#[allow(clippy::too_many_arguments)]
+#[get("/<bucket>?<params..>", rank = 20)]
+pub async fn list_objects(
+    _auth: ReadAccess,
+    _quota: ClientQuota,
+    bucket: &str,
+    params: ListParams,
+    api: &State<S3Api>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> (Status, RawXml<String>) {
+    let key_prefix = match api.key_prefix(bucket) {
+        Some(key_prefix) => key_prefix,
+        None => {
+            return error_response(
+                Status::NotFound,
+                "NoSuchBucket",
+                "The specified bucket does not exist",
+                bucket,
+            )
+        }
+    };
+    if params.list_type != Some(2) {
+        return error_response(
+            Status::NotImplemented,
+            "NotImplemented",
+            "Only ListObjectsV2 (list-type=2) is supported",
+            bucket,
+        );
+    }
+    let request = params.to_request();
+    let cache_request = to_cache_keys(&request, key_prefix);
+    let connector = connector_for(&cache_request.prefix, s3_connectors);
+    match cache.list_page(&cache_request, connector).await {
+        Ok(listing) => (
+            Status::Ok,
+            RawXml(list_bucket_result(
+                bucket,
+                &request,
+                &to_bucket_keys(listing, key_prefix),
+            )),
+        ),
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => error_response(
+            Status::NotImplemented,
+            "NotImplemented",
+            &e.to_string(),
+            bucket,
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            error_response(Status::NotFound, "NoSuchBucket", &e.to_string(), bucket)
+        }
+        Err(e) => error_response(
+            Status::InternalServerError,
+            "InternalError",
+            &e.to_string(),
+            bucket,
+        ),
+    }
+}
+
+/// A cache response with the object's `ETag` and `Last-Modified`, which S3 clients
+/// expect on GetObject.
+pub struct WithObjectInfo(GetFileResult, Option<ObjectInfo>);
+
+impl<'r> Responder<'r, 'static> for WithObjectInfo {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.0.respond_to(req)?;
+        if let Some(info) = self.1 {
+            if let Some(date) = info.last_modified.and_then(http_date) {
+                response.set_header(Header::new("Last-Modified", date));
+            }
+            if let Some(e_tag) = info.e_tag {
+                response.set_header(Header::new("ETag", e_tag));
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// GetObject, with `Range` support, served through the cache.
+#[allow(clippy::too_many_arguments)]
+#[get("/<_>/<key..>", rank = 21)]
+pub async fn get_object(
+    _auth: ReadAccess,
+    _quota: ClientQuota,
+    bucket: S3Bucket,
+    key: PathBuf,
+    pinned: PinnedVersion,
+    context: RequestContext,
+    mut options: GetFileOptions,
+    api: &State<S3Api>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> WithObjectInfo {
+    let uid = pinned.key(&format!("{}{}", bucket.key_prefix, key.to_string_lossy()));
+    // The owning node answers through the same API, so its response carries the headers.
+    let mut redirect_path = format!("{}/{}", bucket.name, key.to_string_lossy());
+    if let Some(version_id) = &pinned.0 {
+        redirect_path = format!("{}?versionId={}", redirect_path, version_id);
+    }
+    options.redirect_path = Some(redirect_path);
+    let result = serve_object(context, uid.clone(), options, cache, s3_connectors).await;
+    let info = match result {
+        GetFileResult::Hit(_)
+        | GetFileResult::MemoryHit(_)
+        | GetFileResult::Encoded(..)
+        | GetFileResult::EncodedStream(..)
+        | GetFileResult::PassThrough(_)
+        | GetFileResult::Streaming(_)
+        | GetFileResult::Partial(_) => {
+            api.object_info(&uid, connector_for(&uid, s3_connectors))
+                .await
+        }
+        _ => None,
+    };
+    WithObjectInfo(result, info)
+}
+
+/// Headers of a HeadObject response. Rocket strips the body of HEAD responses but keeps
+/// a sized body's length, which becomes the `Content-Length`.
+pub struct ObjectHead(pub ObjectInfo);
+
+impl<'r> Responder<'r, 'static> for ObjectHead {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let info = self.0;
+        let mut response = Response::build();
+        response
+            .raw_header("Accept-Ranges", "bytes")
+            .sized_body(usize::try_from(info.size).ok(), Cursor::new(Vec::new()));
+        if let Some(date) = info.last_modified.and_then(http_date) {
+            response.raw_header("Last-Modified", date);
+        }
+        if let Some(e_tag) = info.e_tag {
+            response.raw_header("ETag", e_tag);
+        }
+        response.ok()
+    }
+}
+
+/// HeadObject, answered by the backing store.
+#[head("/<_>/<key..>", rank = 21)]
+pub async fn head_object(
+    _auth: ReadAccess,
+    _quota: ClientQuota,
+    bucket: S3Bucket,
+    key: PathBuf,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> Result<ObjectHead, Status> {
+    let uid = format!("{}{}", bucket.key_prefix, key.to_string_lossy());
+    match connector_for(&uid, s3_connectors).head_object(&uid).await {
+        Ok(info) => Ok(ObjectHead(info)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
@@ -0,0 +1,109 @@
+// scrub.rs
+//! Consistency checks between the cache directories, the shards' bookkeeping and the
+//! metadata store, which crashes and files deleted by hand leave out of step. Entries
+//! whose file is gone or has the wrong size are dropped, locations missing from the
+//! metadata store are recorded again, and files no shard knows of are deleted once they
+//! are old enough not to be a download in progress. Cache directories must therefore not
+//! hold anything else.
+use rocket::serde::json::Json;
+use rocket::{post, State};
+use serde::{Deserialize, Serialize};
+use std::io::Result as IoResult;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::auth::AdminAccess;
+use crate::cache::ConcurrentDiskCache;
+
+/// Untracked files younger than this are left alone: they may be downloads that are not
+/// recorded yet.
+pub const ORPHAN_GRACE: Duration = Duration::from_secs(10 * 60);
+
+/// Discrepancies found, and repaired, by one scrub or by every scrub since startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubReport {
+    /// Entries whose file was gone; they were dropped.
+    pub missing_files: u64,
+    /// Entries whose file had another size than recorded; they were deleted.
+    pub corrupt_files: u64,
+    /// Entries without a location in the metadata store; it was recorded again.
+    pub locations_restored: u64,
+    /// Files in a cache directory that no shard knew of; they were deleted.
+    pub orphaned_files: u64,
+    pub orphaned_bytes: u64,
+}
+
+impl ScrubReport {
+    pub fn add(&mut self, other: &ScrubReport) {
+        self.missing_files += other.missing_files;
+        self.corrupt_files += other.corrupt_files;
+        self.locations_restored += other.locations_restored;
+        self.orphaned_files += other.orphaned_files;
+        self.orphaned_bytes += other.orphaned_bytes;
+    }
+
+    pub fn discrepancies(&self) -> u64 {
+        self.missing_files + self.corrupt_files + self.locations_restored + self.orphaned_files
+    }
+}
+
+/// A regular file found in a cache directory.
+#[derive(Debug, Clone)]
+pub struct ScannedFile {
+    pub name: String,
+    pub len: u64,
+    /// Time since the file was last written.
+    pub age: Duration,
+}
+
+/// The regular files directly in `dir`.
+pub async fn scan_dir(dir: &Path) -> IoResult<Vec<ScannedFile>> {
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let now = SystemTime::now();
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            files.push(ScannedFile {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                len: metadata.len(),
+                age: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| now.duration_since(modified).ok())
+                    .unwrap_or_default(),
+            });
+        }
+        Ok(files)
+    })
+    .await?
+}
+
+/// Scrubs the cache every `interval` for as long as the node runs.
+pub async fn run(cache: Arc<ConcurrentDiskCache>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let report = cache.scrub().await;
+        if report.discrepancies() > 0 {
+            log::warn!(
+                "Scrub repaired {} discrepancies: {:?}",
+                report.discrepancies(),
+                report
+            );
+        }
+    }
+}
+
+/// Scrubs the cache now and returns what was repaired.
+#[post("/admin/scrub")]
+pub async fn scrub(
+    _admin: AdminAccess,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Json<ScrubReport> {
+    Json(cache.scrub().await)
+}
@@ -0,0 +1,68 @@
+// selftest.rs
+//! Checks run once as the node starts, before it serves. A node that can't write its
+//! cache directories, was given more cache than its disks hold, or can't reach its
+//! metadata or backing store stops there, with a message saying what to fix, rather
+//! than failing requests later.
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::cache::ConcurrentDiskCache;
+use crate::disks;
+use crate::health::check_backing_store;
+use crate::server::ServerConfig;
+use crate::storage::storage_connector::StorageConnector;
+
+/// Runs every check and returns a line per problem found; none means the node may start.
+pub async fn self_test(
+    config: &ServerConfig,
+    cache: &ConcurrentDiskCache,
+    connector: Option<&Arc<dyn StorageConnector + Send + Sync>>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (path, ok) in cache.check_disks().await {
+        if !ok {
+            problems.push(format!(
+                "cache directory {} is not writable; check that it exists and that the \
+                 node's user may write to it",
+                path.display()
+            ));
+        }
+    }
+    let budgets: Vec<(PathBuf, u64)> = match config.cache_dirs.is_empty() {
+        true => vec![(PathBuf::from(&config.cache_dir), config.max_size)],
+        false => config
+            .cache_dirs
+            .iter()
+            .map(|dir| (PathBuf::from(&dir.path), dir.max_size))
+            .collect(),
+    };
+    for (path, budget) in budgets {
+        match disks::capacity(&path) {
+            Ok(capacity) if budget > capacity => problems.push(format!(
+                "{} is given {} bytes of cache, more than the {} bytes of its file system; \
+                 lower max_size",
+                path.display(),
+                budget,
+                capacity
+            )),
+            _ => {}
+        }
+    }
+    if let Err(e) = cache.check_metadata().await {
+        problems.push(format!(
+            "metadata store ({:?}) did not answer: {}; check redis_addrs or etcd.endpoints, \
+             or set metadata_store = \"in-process\" for a single node",
+            config.metadata_store, e
+        ));
+    }
+    if let Some(connector) = connector {
+        if let Err(e) = check_backing_store(connector.as_ref()).await {
+            problems.push(format!(
+                "backing store did not answer: {}; check the bucket, region, endpoint and \
+                 credentials",
+                e
+            ));
+        }
+    }
+    problems
+}
@@ -1,118 +1,1481 @@
 extern crate fern;
 extern crate log;
-use crate::storage::mock_storage_connector::MockS3StorageConnector;
+use crate::shard_runtime::ShardRuntimeConfig;
+use crate::storage::metered_storage_connector::MeteredStorageConnector;
+use crate::storage::mock_storage_connector::{MockS3Config, MockS3StorageConnector};
+use crate::storage::routing_storage_connector::{BackendConfig, Route, RoutingStorageConnector};
 use crate::storage::s3_storage_connector::S3StorageConnector;
 use crate::storage::storage_connector::StorageConnector;
-use crate::util::hash;
+use crate::storage::throttled_storage_connector::{FetchLimiter, ThrottledStorageConnector};
+use crate::storage::timeout_storage_connector::TimeoutStorageConnector;
+use crate::util::{hash, host_port};
+#[cfg(feature = "fault-injection")]
+use crate::{faults, storage::faulty_storage_connector::FaultyStorageConnector};
+use log::info;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::RawHtml;
+use rocket::response::status::{BadRequest, Custom};
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
 use rocket::State;
-use rocket::{get, post, routes, Rocket};
+use rocket::{delete, get, post, routes, Responder, Rocket};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::cache::{self, ConcurrentDiskCache};
+use crate::access_log::AccessLog;
+use crate::admission::AdmissionPolicy;
+use crate::auth::{bearer_token, AdminAccess, AuthConfig, ReadAccess};
+use crate::cache::{self, CacheOptions, ConcurrentDiskCache, GetFileOptions};
+use crate::cache_service::CacheService;
+use crate::chunk::ByteRange;
+use crate::cluster::{self, NodeStats, PeerClient};
+use crate::compression::CompressionConfig;
+use crate::conditional::{Preconditions, Versioned};
+use crate::config::{load_config, ConfigError, ConfigUpdate};
+use crate::costs::{CostConfig, CostFairing, CostLedger, CostReport};
+use crate::dashboard::{render as render_dashboard, DASHBOARD_KEYS_PER_SHARD};
+use crate::deadline::TimeoutConfig;
+use crate::disk_io::{DiskBackend, DiskIo};
+use crate::disks::{CacheDirConfig, FREE_SPACE_CHECK_INTERVAL, PROBE_INTERVAL};
+use crate::download::DisconnectPolicy;
+use crate::egress::{EgressConfig, EgressFairing, EgressShaper};
+use crate::embedded_redis::{EmbeddedRedis, EmbeddedRedisConfig};
+use crate::encryption::EncryptionConfig;
+use crate::error::CacheError;
+use crate::etcd::{EtcdConfig, EtcdStore};
+use crate::eviction::{self, EvictionConfig};
+use crate::flight::FlightService;
+use crate::grpc::{self, GrpcConfig, GrpcServer};
+use crate::health::{self, HealthProbes};
+use crate::hotkeys::HotKeysReport;
+use crate::identity;
+use crate::invalidation::{self, InvalidationBus};
+use crate::listing;
+use crate::location_cache::LocationCacheConfig;
+use crate::logging::{LogFormat, RequestContext, RequestId, REQUEST_ID_HEADER};
+use crate::metadata::{default_weight, InProcessStore, MetadataBackend, MetadataStore};
+use crate::metrics::EfficiencyRow;
+use crate::policy::{PolicySet, PrefixPolicy};
+use crate::prefixes::PrefixUsage;
+use crate::priority::PRIORITY_HEADER;
+use crate::rate_limit::{ClientQuota, RateLimitConfig, RateLimiter};
+use crate::read_through::{self, ReadThrough};
+use crate::rebalance::{self, RebalanceConfig, Rebalancer};
+use crate::redis::{RedisMode, RedisServer};
+use crate::ring::{self, Placement, DEFAULT_VNODES_PER_NODE};
+use crate::s3_api::{self, S3Api};
+use crate::scrub;
+use crate::selftest::self_test;
+use crate::shadow::ShadowConfig;
+use crate::snapshot::{self, SnapshotConfig, Snapshots};
+use crate::telemetry::{SpanLogger, TraceContext, TracingConfig, TRACE_ID_HEADER};
+use crate::tenant::{TenantConfig, Tenants};
+use crate::tls::{
+    serve_tls, RelayOnly, RelayToken, TlsConfig, REAL_IP_HEADER, TLS_BACKEND_PORT_OFFSET,
+};
+use crate::versioning::PinnedVersion;
+use crate::warmup::{self, WarmUp, WarmUpConfig};
+use crate::writeback::{self, WriteBackConfig, WriteBackQueue};
+use rocket::fairing::AdHoc;
+use serde::Deserialize;
+use std::path::Path;
+use thiserror::Error;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info_span, Instrument};
 
+/// Keys listed by `/stats/hotkeys` unless `n` is given.
+const DEFAULT_HOT_KEYS: usize = 10;
+
+/// Clients that know an object is worth caching can send `X-Istziio-Admission: force`.
+pub const ADMISSION_HEADER: &str = "X-Istziio-Admission";
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GetFileOptions {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        let force_admit = req
+            .headers()
+            .get_one(ADMISSION_HEADER)
+            .map(|v| v.eq_ignore_ascii_case("force"))
+            .unwrap_or(false);
+        // Malformed or multi-range headers are ignored and the whole object is served.
+        let range = req
+            .headers()
+            .get_one("Range")
+            .and_then(|v| v.parse::<ByteRange>().ok());
+        let accept_encoding = req.headers().get_one("Accept-Encoding").map(String::from);
+        let tenant = match (req.rocket().state::<Tenants>(), bearer_token(req)) {
+            (Some(tenants), Some(token)) => tenants.by_token(token).map(String::from),
+            _ => None,
+        };
+        Outcome::Success(GetFileOptions {
+            force_admit,
+            range,
+            accept_encoding,
+            tenant,
+            redirect_path: None,
+            preconditions: Preconditions::from_headers(req.headers()),
+            priority: req
+                .headers()
+                .get_one(PRIORITY_HEADER)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Still 200 in local-only mode: the node serves, only without cluster coordination.
+/// 503 while a warm-up with `before_serving` runs.
 #[get("/")]
-fn health_check() -> &'static str {
-    "Healthy\n"
+async fn health_check(
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    warm_up: &State<Option<Arc<WarmUp>>>,
+) -> Result<&'static str, Custom<&'static str>> {
+    if warm_up
+        .as_ref()
+        .is_some_and(|warm_up| warm_up.holds_traffic())
+    {
+        Err(Custom(Status::ServiceUnavailable, "Warming up\n"))
+    } else if cache.is_degraded().await {
+        Ok("Degraded: metadata store unreachable, serving locally\n")
+    } else {
+        Ok("Healthy\n")
+    }
 }
 
 #[get("/stats")]
-async fn cache_stats(cache: &State<Arc<ConcurrentDiskCache>>) -> String {
-    cache.get_stats().await
+async fn cache_stats(
+    _auth: ReadAccess,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    fetch_limiter: &State<Option<Arc<FetchLimiter>>>,
+    egress: &State<Option<Arc<EgressShaper>>>,
+    embedded_redis: &State<Option<Arc<EmbeddedRedis>>>,
+) -> String {
+    let mut stats = cache.get_stats().await;
+    if let Some(limiter) = fetch_limiter.inner() {
+        stats.push_str(&limiter.summary());
+    }
+    if let Some(egress) = egress.inner() {
+        stats.push_str(&egress.summary());
+    }
+    if let Some(embedded_redis) = embedded_redis.inner() {
+        stats.push_str(&embedded_redis.summary());
+    }
+    stats
+}
+
+/// Node-wide totals in machine-readable form; what `/cluster/stats` collects from peers.
+#[get("/stats/json")]
+async fn stats_json(_auth: ReadAccess, cache: &State<Arc<ConcurrentDiskCache>>) -> Json<NodeStats> {
+    Json(NodeStats::from_snapshot(&cache.snapshot(0).await))
+}
+
+/// S3 requests and bytes by tenant and top-level prefix, with what they cost and what the
+/// cache saved.
+#[get("/stats/costs")]
+async fn costs(_auth: ReadAccess, ledger: &State<Arc<CostLedger>>) -> Json<CostReport> {
+    Json(ledger.report())
+}
+
+/// Bytes served from the cache against bytes fetched from S3 over the last minute, hour
+/// and day, with the requests saved and hit against miss latency.
+#[get("/stats/efficiency")]
+async fn efficiency(
+    _auth: ReadAccess,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Json<Vec<EfficiencyRow>> {
+    Json(cache.efficiency().await)
 }
 
+#[get("/stats/hotkeys?<n>")]
+async fn hot_keys(
+    _auth: ReadAccess,
+    n: Option<usize>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Json<HotKeysReport> {
+    Json(cache.hot_keys(n.unwrap_or(DEFAULT_HOT_KEYS)).await)
+}
+
+#[get("/stats/prefixes?<depth>")]
+async fn prefix_usage(
+    _auth: ReadAccess,
+    depth: Option<usize>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Json<Vec<PrefixUsage>> {
+    Json(cache.prefix_usage(depth.unwrap_or(1)).await)
+}
+
+#[get("/dashboard")]
+async fn dashboard(_auth: ReadAccess, cache: &State<Arc<ConcurrentDiskCache>>) -> RawHtml<String> {
+    let snapshot = cache.snapshot(DASHBOARD_KEYS_PER_SHARD).await;
+    RawHtml(render_dashboard(&snapshot))
+}
+
+#[allow(clippy::too_many_arguments)]
 #[get("/s3/<uid..>")]
 async fn get_file(
+    _auth: ReadAccess,
+    _quota: ClientQuota,
+    context: RequestContext,
     uid: PathBuf,
+    pinned: PinnedVersion,
+    options: GetFileOptions,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> Versioned {
+    // A pinned version is cached under its own key, which the owner serves as is.
+    let uid_str = pinned.key(&uid.to_string_lossy());
+    let result = serve_object(context, uid_str.clone(), options, cache, s3_connectors).await;
+    let version = match result {
+        cache::GetFileResult::Redirect(_) => None,
+        _ => cache.object_version(&uid_str).await,
+    };
+    Versioned(result, version)
+}
+
+/// Serves `uid` through the cache; shared by `/s3` and the S3-compatible API.
+pub(crate) async fn serve_object(
+    context: RequestContext,
+    uid_str: String,
+    options: GetFileOptions,
     cache: &State<Arc<ConcurrentDiskCache>>,
     s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
 ) -> cache::GetFileResult {
-    let uid_str = uid.to_string_lossy().to_string(); // Convert PathBuf to String correctly
+    if let Err(e) = read_through::check_bucket_key(&uid_str) {
+        return cache::GetFileResult::Error(e);
+    }
     let index = hash(&uid_str) % s3_connectors.len(); // Use the converted string
+    let s3_connector = s3_connectors[index].clone();
+    serve_object_from(context, uid_str, options, cache, s3_connector).await
+}
+
+/// Serves `uid` through the cache, fetching a miss through `s3_connector`.
+pub(crate) async fn serve_object_from(
+    mut context: RequestContext,
+    uid_str: String,
+    options: GetFileOptions,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connector: Arc<dyn StorageConnector + Send + Sync>,
+) -> cache::GetFileResult {
+    let span = info_span!(
+        "get_file",
+        trace_id = %context.trace.trace_id,
+        remote_parent = context.trace.parent_id.as_deref().unwrap_or_default(),
+        uid = %uid_str,
+    );
+    context.key = uid_str.clone();
+    let cache = cache.inner().clone();
+    let shard_cache = cache.clone();
+    let uid = uid_str.clone();
+    let task = async move {
+        context
+            .scope(
+                shard_cache
+                    .get_file(PathBuf::from(uid_str), s3_connector, options) // Use PathBuf from string
+                    .instrument(span),
+            )
+            .await
+    };
+    cache.on_shard_of(&uid, task).await
+}
+
+// Rocket can't match a static segment after `<path..>`, so the trailing `/metadata` is
+// checked by hand.
+#[get("/parquet/<path..>")]
+async fn get_parquet_metadata(
+    _auth: ReadAccess,
+    _quota: ClientQuota,
+    mut context: RequestContext,
+    path: PathBuf,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> cache::GetFileResult {
+    let uid = match path.file_name() {
+        Some(name) if name == "metadata" => path.parent().unwrap_or(&path),
+        _ => {
+            let key = path.to_string_lossy().to_string();
+            return cache::GetFileResult::Error(CacheError::not_found(key));
+        }
+    };
+    let uid_str = uid.to_string_lossy().to_string();
+    if let Err(e) = read_through::check_bucket_key(&uid_str) {
+        return cache::GetFileResult::Error(e);
+    }
+    let index = hash(&uid_str) % s3_connectors.len();
     let s3_connector = &s3_connectors[index];
 
-    cache
-        .inner()
-        .clone()
-        .get_file(PathBuf::from(uid_str), s3_connector.clone()) // Use PathBuf from string
-        .await
+    let span = info_span!(
+        "get_parquet_metadata",
+        trace_id = %context.trace.trace_id,
+        remote_parent = context.trace.parent_id.as_deref().unwrap_or_default(),
+        uid = %uid_str,
+    );
+    context.key = uid_str.clone();
+    let cache = cache.inner().clone();
+    let shard_cache = cache.clone();
+    let s3_connector = s3_connector.clone();
+    let uid = uid_str.clone();
+    let task = async move {
+        context
+            .scope(
+                shard_cache
+                    .get_parquet_metadata(PathBuf::from(uid_str), s3_connector)
+                    .instrument(span),
+            )
+            .await
+    };
+    cache.on_shard_of(&uid, task).await
 }
 
 #[post("/clear")]
-async fn clear(cache: &State<Arc<ConcurrentDiskCache>>) -> String {
-    cache.inner().clone().empty().await;
-    String::from("cleared")
+async fn clear(_admin: AdminAccess, cache: &State<Arc<ConcurrentDiskCache>>) -> String {
+    format!("{}\n", cache.empty().await)
+}
+
+/// Drops `key`, with its chunks and footer, from this node's cache.
+#[post("/admin/invalidate/<key..>")]
+async fn invalidate(
+    _admin: AdminAccess,
+    key: PathBuf,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> String {
+    let key = key.to_string_lossy();
+    let freed = cache.invalidate(&key).await;
+    format!("invalidated {}, freed {} bytes\n", key, freed)
+}
+
+/// Drops every key starting with `prefix` from this node's cache.
+#[post("/admin/invalidate-prefix/<prefix..>")]
+async fn invalidate_prefix(
+    _admin: AdminAccess,
+    prefix: PathBuf,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> String {
+    let prefix = prefix.to_string_lossy();
+    let freed = cache.invalidate_prefix(&prefix).await;
+    format!("invalidated {}*, freed {} bytes\n", prefix, freed)
+}
+
+#[derive(Responder)]
+enum DeleteResult {
+    Done(String),
+    Redirect(Box<Redirect>),
+}
+
+/// Drops `uid` from the cache of the node owning it. With `soft`, the object stays
+/// servable instead, but is revalidated against S3 before it is next served.
+#[delete("/s3/<uid..>?<soft>")]
+async fn delete_file(
+    _admin: AdminAccess,
+    uid: PathBuf,
+    soft: Option<bool>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> DeleteResult {
+    let key = uid.to_string_lossy().into_owned();
+    let soft = soft.unwrap_or(false);
+    let path = match soft {
+        true => format!("s3/{}?soft=true", key),
+        false => format!("s3/{}", key),
+    };
+    if let Some(url) = cache.owner_url(&key, &path).await {
+        // A 307 keeps the method, unlike the 303 that GETs are redirected with.
+        return DeleteResult::Redirect(Box::new(Redirect::temporary(url.to_string())));
+    }
+    if !soft {
+        let freed = cache.invalidate(&key).await;
+        return DeleteResult::Done(format!("invalidated {}, freed {} bytes\n", key, freed));
+    }
+    match cache.mark_stale(&key).await {
+        true => DeleteResult::Done(format!("marked {} stale\n", key)),
+        false => DeleteResult::Done(format!("{} is not cached\n", key)),
+    }
+}
+
+/// Re-reads slot ownership now instead of waiting for the next periodic refresh.
+#[post("/admin/refresh-mapping")]
+async fn refresh_mapping(
+    _admin: AdminAccess,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<String, Custom<String>> {
+    let moved = cache
+        .refresh_mapping()
+        .await
+        .map_err(|e| Custom(Status::ServiceUnavailable, e.to_string()))?;
+    Ok(format!("{} slots changed owner\n", moved))
+}
+
+/// Applies a `ConfigUpdate` to the running cache. The shard count is fixed at startup, so
+/// it is read back from the connector pool rather than taken from the request.
+#[post("/admin/config", data = "<update>")]
+async fn update_config(
+    _admin: AdminAccess,
+    update: Json<ConfigUpdate>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> Result<String, BadRequest<String>> {
+    let update = update.into_inner();
+    update
+        .validate(s3_connectors.len() as u64)
+        .map_err(|e| BadRequest(e.to_string()))?;
+    if let Ok(Some(level)) = update.log_level_filter() {
+        log::set_max_level(level);
+    }
+    if let Some(max_size) = update.max_size {
+        cache.set_max_size(max_size).await;
+    }
+    if let Some(policy) = update.admission_policy {
+        cache.set_admission_policy(policy).await;
+    }
+    if let Some(limit) = update.max_cacheable_object_size {
+        cache
+            .set_max_cacheable_object_size(Some(limit).filter(|limit| *limit > 0))
+            .await;
+    }
+    if let Some(memory_tier_size) = update.memory_tier_size {
+        cache.set_memory_tier_size(memory_tier_size).await;
+    }
+    if let Some(policies) = update.policies.clone() {
+        cache.set_policies(PolicySet::new(policies)).await;
+    }
+    if let Some(eviction) = update.eviction {
+        if cache.set_eviction(eviction).await {
+            tokio::spawn(eviction::run(cache.inner().clone()));
+        }
+    }
+    info!("Applied config update: {:?}", update);
+    Ok(String::from("updated"))
+}
+
+/// Resizes the cache to `max_size` bytes in total, evicting immediately when shrinking.
+#[post("/admin/resize/<max_size>")]
+async fn resize(
+    _admin: AdminAccess,
+    max_size: u64,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> Result<String, BadRequest<String>> {
+    let update = ConfigUpdate {
+        max_size: Some(max_size),
+        ..Default::default()
+    };
+    update
+        .validate(s3_connectors.len() as u64)
+        .map_err(|e| BadRequest(e.to_string()))?;
+    let evicted = cache.set_max_size(max_size).await;
+    Ok(format!(
+        "resized to {} bytes, evicted {} bytes\n",
+        max_size, evicted
+    ))
+}
+
+/// Why a node could not be set up.
+#[derive(Debug, Error)]
+pub enum StartupError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("failed to start {binary}: {source}")]
+    EmbeddedRedis {
+        binary: String,
+        source: std::io::Error,
+    },
+    #[error("failed to create the Redis client: {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("failed to load the node id: {0}")]
+    NodeId(std::io::Error),
+    #[error("failed to load encryption key from {path}: {source}")]
+    EncryptionKey {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("failed to open the write-back journal in {dir}: {source}")]
+    WriteBack { dir: String, source: std::io::Error },
+    #[error("failed to load the TLS certificates for peer requests: {0}")]
+    Tls(std::io::Error),
 }
 
 pub struct ServerNode {
     pub cache_manager: Arc<ConcurrentDiskCache>,
     pub s3_connectors: Vec<Arc<dyn StorageConnector + Send + Sync>>,
+    pub fetch_limiter: Option<Arc<FetchLimiter>>,
+    pub cost_ledger: Arc<CostLedger>,
+    pub embedded_redis: Option<Arc<EmbeddedRedis>>,
+    pub rebalancer: Option<Arc<Rebalancer>>,
+    pub write_back: Option<Arc<WriteBackQueue>>,
+    pub warm_up: Option<Arc<WarmUp>>,
+    pub snapshots: Option<Arc<Snapshots>>,
+    pub peers: PeerClient,
     config: ServerConfig,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ServerConfig {
     pub server_ip: String,
     pub redis_port: u16,
+    /// Port the web server listens on; defaults to `redis_port` plus
+    /// `PORT_OFFSET_TO_WEB_SERVER`.
+    pub web_port: Option<u16>,
+    /// `host:port` other nodes and redirected clients reach this node's web server at,
+    /// e.g. behind a load balancer or a container port mapping. Defaults to `server_ip`
+    /// and `web_port` when that is set; nodes advertising nothing are reached at their
+    /// Redis port plus `PORT_OFFSET_TO_WEB_SERVER`.
+    pub advertise_addr: Option<String>,
+    /// Hostname this node's web server is reached at, with `web_port`, when
+    /// `advertise_addr` is not set; redirects then carry a name that DNS resolves and
+    /// TLS certificates match rather than an IP.
+    pub advertise_host: Option<String>,
+    /// Redis cluster nodes to connect to; defaults to the local node at `redis_port`.
+    /// In standalone mode, the one Redis every node shares.
+    pub redis_addrs: Vec<String>,
+    /// `cluster` (the default) places keys by the slots of the Redis cluster;
+    /// `standalone` uses a single Redis without cluster support, e.g. for development,
+    /// and places keys on the ring of the nodes registered there whatever `placement`
+    /// says.
+    pub redis_mode: RedisMode,
+    /// Run this node's `redis-server` as a child process, restarted when it crashes and
+    /// stopped with the node; Redis is managed separately when unset.
+    pub embedded_redis: Option<EmbeddedRedisConfig>,
+    /// `redis` (the default) shares file locations and slot ownership through the Redis
+    /// cluster, `etcd` through the etcd cluster below; `in-process` runs a single node
+    /// without either.
+    pub metadata_store: MetadataBackend,
+    pub etcd: Option<EtcdConfig>,
+    /// Seconds between re-reads of slot ownership, so that resizing the cluster needs no
+    /// restart; 0 turns the background refresh off.
+    pub slot_refresh_interval_secs: u64,
+    /// `slots` (the default) places keys by the slot owners of the metadata store, `ring`
+    /// on a consistent hash ring kept there and changed through `/admin/ring`.
+    pub placement: Placement,
+    /// Virtual nodes a node of weight 1 gets when it joins the ring.
+    pub vnodes_per_node: u32,
+    /// This node's capacity relative to the others', e.g. its disk size in TB. With etcd
+    /// the node owns slots in proportion to it; on the ring, virtual nodes.
+    pub capacity_weight: u32,
+    /// Move cached objects to their new owner in the background when keys change hands;
+    /// needs `admin_token`, which peers use to hand each other objects.
+    pub rebalance: Option<RebalanceConfig>,
     pub cache_dir: String,
+    /// Cache directories on separate disks, used instead of `cache_dir` when set. Their
+    /// sizes add up to the node's capacity, replacing `max_size`.
+    pub cache_dirs: Vec<CacheDirConfig>,
     pub bucket: Option<String>,
     pub region_name: Option<String>,
     pub access_key: Option<String>,
     pub secret_key: Option<String>,
     pub use_mock_s3_endpoint: Option<String>,
+    /// Latency, bandwidth and errors the mock S3 connectors add, or objects they generate.
+    pub mock_s3: Option<MockS3Config>,
     pub max_size: u64,
     pub bucket_size: u64,
+    /// Run every shard's requests on a single-threaded runtime of its own.
+    pub shard_runtimes: Option<ShardRuntimeConfig>,
+    pub admission_policy: AdmissionPolicy,
+    pub max_cacheable_object_size: Option<u64>,
+    pub memory_tier_size: u64,
+    pub memory_tier_max_object_size: u64,
+    pub chunk_size: Option<u64>,
+    pub parquet_footer_prefetch: Option<u64>,
+    /// Misses on objects of at least this many bytes are sent to the client while they
+    /// download, rather than once they are cached; off when unset.
+    pub stream_fetches_from: Option<u64>,
+    /// Disk hits whose first chunk takes longer than this many milliseconds to read are
+    /// also requested from S3, and served from whichever copy arrives first; off when unset.
+    pub hedge_after_ms: Option<u64>,
+    /// `fill` (the default) finishes caching a streamed miss after its clients disconnect;
+    /// `abort` stops the download. Misses that aren't streamed are always finished.
+    pub on_client_disconnect: DisconnectPolicy,
+    /// How cached files are read and written; `io-uring` needs the `io-uring` feature and
+    /// Linux, and falls back to `tokio` elsewhere.
+    pub disk_io: DiskBackend,
+    /// Cached files of at least this many bytes are dropped from the OS page cache as they
+    /// are read and written, leaving memory to the memory tier; off when unset.
+    pub drop_page_cache_from: Option<u64>,
+    /// Evict in the background once a shard passes a high watermark, down to a low one,
+    /// instead of on the write path of misses; off when unset.
+    pub eviction: Option<EvictionConfig>,
+    /// Other eviction policies and sizes to simulate next to the cache, reporting the hit
+    /// ratio each would have had in the stats.
+    pub shadow_caches: Vec<ShadowConfig>,
+    /// Bytes of each cache directory's file system kept free for other processes: below
+    /// that the node evicts and stops caching on the disk; unchecked when unset.
+    pub free_space_reserve: Option<u64>,
+    /// Seconds between scrubs of the cache directories against the shards and the metadata
+    /// store; 0 (the default) leaves scrubbing to `/admin/scrub`. Untracked files in the
+    /// cache directories are deleted, so they must hold nothing else.
+    pub scrub_interval_secs: u64,
+    /// Accept `PUT /s3/<key>`, journal it locally and upload it to S3 in the background;
+    /// PUTs get a 404 when unset. PUTs need `admin_token`.
+    pub write_back: Option<WriteBackConfig>,
+    /// Fetch a manifest's keys and prefixes' objects at startup.
+    pub warm_up: Option<WarmUpConfig>,
+    /// Take snapshots of the cache into the backing store, and restore one at startup.
+    pub snapshots: Option<SnapshotConfig>,
+    /// URL prefixes `GET /fetch?src=<url>&key=<key>` may read from, e.g. a bucket's
+    /// `https://<bucket>.s3.<region>.amazonaws.com/`; `/fetch` gets a 404 when empty.
+    pub fetch_sources: Vec<String>,
+    /// Seconds `GET /list` keeps a page of the backing store's listing; 0 (the default)
+    /// lists the backing store on every request.
+    pub list_cache_ttl_secs: u64,
+    /// Also keep listing pages in Redis, so that the nodes share them; needs
+    /// `list_cache_ttl_secs`.
+    pub share_list_cache: bool,
+    pub compression: Option<CompressionConfig>,
+    /// Redis pub/sub channel carrying key and prefix invalidations between nodes; the node
+    /// neither listens nor publishes when unset.
+    pub invalidation_channel: Option<String>,
+    /// Prefix of this cluster's keys in Redis, under which every node keeps its file
+    /// locations per shard and drops its own on startup and shutdown. Clusters sharing a
+    /// Redis deployment need distinct ones; unset keeps the shared `istziio` layout.
+    pub redis_namespace: Option<String>,
+    /// Seconds a file location lives in Redis unless the node holding the file renews it,
+    /// which it does three times per lease; locations never expire when 0.
+    pub location_lease_secs: u64,
+    /// Answer repeated location lookups from memory for a short while instead of asking
+    /// Redis each time; off when unset.
+    pub location_cache: Option<LocationCacheConfig>,
+    /// Whether the node checks its cache directories, metadata store and backing store
+    /// as it starts, and refuses to start if one of them fails; on by default.
+    pub self_test: bool,
+    /// Bearer token for `/clear` and `/admin/*`; those routes are disabled when unset.
+    pub admin_token: Option<String>,
+    /// Bearer tokens accepted on `/s3`, `/parquet` and `/stats`; open when empty.
+    pub read_tokens: Vec<String>,
+    /// Upper bound on S3 fetches open at once across the node; unlimited when unset.
+    pub max_concurrent_s3_fetches: Option<usize>,
+    /// How long a miss waits for a free fetch slot before the client gets a 503.
+    pub s3_fetch_queue_timeout_ms: u64,
+    /// Upper bound on the fetch slots batch requests hold at once, so that some are always
+    /// left to interactive ones; all of them when unset.
+    pub max_batch_s3_fetches: Option<usize>,
+    /// Limits on the metadata lookup, S3 fetches and disk reads of a request; a request
+    /// that runs past one (or past its `X-Istziio-Timeout-Ms`) gets a 504.
+    pub timeouts: TimeoutConfig,
+    /// Per-client request rate limit on `/s3` and `/parquet`; requests over it get a 429.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Caps on the bytes per second sent by each `/s3` response and by all responses of a
+    /// priority class together; unshaped when unset.
+    pub egress: Option<EgressConfig>,
+    /// Prices for `/stats/costs`, which attributes backing-store traffic to tenants and
+    /// prefixes.
+    pub costs: CostConfig,
+    /// Serve HTTPS (optionally requiring client certificates) instead of plain HTTP.
+    pub tls: Option<TlsConfig>,
+    /// Serve the `proto/cache.proto` gRPC service next to the HTTP API.
+    pub grpc: Option<GrpcConfig>,
+    /// Encrypt cached files on disk with AES-256-GCM.
+    pub encryption: Option<EncryptionConfig>,
+    /// Report request spans (with their trace IDs) to the log, and to an OpenTelemetry
+    /// collector if one is set.
+    pub tracing: Option<TracingConfig>,
+    pub log_format: LogFormat,
+    /// File that gets one JSON line per `/s3` request.
+    pub access_log: Option<String>,
+    /// File that gets one request trace line per `/s3` request, for replaying traffic
+    /// against other configs with `cachesim`.
+    pub request_trace: Option<String>,
+    /// Rules for TTL, eviction priority, admission and quota by key prefix or glob; the
+    /// first matching rule applies.
+    pub policies: Vec<PrefixPolicy>,
+    /// Teams sharing the node, told apart by bearer token or key prefix, each with an
+    /// optional byte budget.
+    pub tenants: Vec<TenantConfig>,
+    /// Further buckets serving the keys under their prefix; the top-level bucket serves
+    /// the keys no backend claims.
+    pub backends: Vec<BackendConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            server_ip: String::from("localhost"),
+            redis_port: 6379,
+            web_port: None,
+            advertise_addr: None,
+            advertise_host: None,
+            redis_addrs: Vec::new(),
+            redis_mode: RedisMode::default(),
+            embedded_redis: None,
+            metadata_store: MetadataBackend::default(),
+            etcd: None,
+            slot_refresh_interval_secs: 30,
+            placement: Placement::default(),
+            vnodes_per_node: DEFAULT_VNODES_PER_NODE,
+            capacity_weight: default_weight(),
+            rebalance: None,
+            cache_dir: String::from("./cache_6379"),
+            cache_dirs: Vec::new(),
+            bucket: None,
+            region_name: None,
+            access_key: None,
+            secret_key: None,
+            use_mock_s3_endpoint: None,
+            mock_s3: None,
+            max_size: 192,
+            bucket_size: 3,
+            shard_runtimes: None,
+            admission_policy: AdmissionPolicy::default(),
+            max_cacheable_object_size: None,
+            memory_tier_size: 0,
+            memory_tier_max_object_size: 1024 * 1024,
+            chunk_size: None,
+            parquet_footer_prefetch: None,
+            stream_fetches_from: None,
+            hedge_after_ms: None,
+            on_client_disconnect: DisconnectPolicy::default(),
+            disk_io: DiskBackend::default(),
+            drop_page_cache_from: None,
+            eviction: None,
+            shadow_caches: Vec::new(),
+            free_space_reserve: None,
+            scrub_interval_secs: 0,
+            write_back: None,
+            warm_up: None,
+            snapshots: None,
+            fetch_sources: Vec::new(),
+            list_cache_ttl_secs: 0,
+            share_list_cache: false,
+            compression: None,
+            invalidation_channel: None,
+            redis_namespace: None,
+            location_lease_secs: 0,
+            location_cache: None,
+            self_test: true,
+            admin_token: None,
+            read_tokens: Vec::new(),
+            max_concurrent_s3_fetches: None,
+            s3_fetch_queue_timeout_ms: 10_000,
+            max_batch_s3_fetches: None,
+            timeouts: TimeoutConfig::default(),
+            rate_limit: None,
+            egress: None,
+            costs: CostConfig::default(),
+            tls: None,
+            grpc: None,
+            encryption: None,
+            tracing: None,
+            log_format: LogFormat::default(),
+            access_log: None,
+            request_trace: None,
+            policies: Vec::new(),
+            tenants: Vec::new(),
+            backends: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The address other nodes reach this one at; `localhost` stands for loopback.
+    pub fn advertised_ip(&self) -> String {
+        if self.server_ip == "localhost" {
+            String::from("127.0.0.1")
+        } else {
+            self.server_ip.clone()
+        }
+    }
+
+    /// The port the web server listens on.
+    pub fn web_port(&self) -> u16 {
+        self.web_port
+            .unwrap_or_else(|| cache::PORT_OFFSET_TO_WEB_SERVER + self.redis_port)
+    }
+
+    /// The `host:port` this node advertises for its web server, if any.
+    pub fn advertised_address(&self) -> Option<String> {
+        if self.advertise_addr.is_some() {
+            return self.advertise_addr.clone();
+        }
+        if self.advertise_host.is_none() && self.web_port.is_none() {
+            return None;
+        }
+        let host = self
+            .advertise_host
+            .clone()
+            .unwrap_or_else(|| self.advertised_ip());
+        Some(host_port(&host, self.web_port()))
+    }
+
+    /// Whether the nodes share a standalone Redis rather than a cluster.
+    pub fn is_redis_standalone(&self) -> bool {
+        self.metadata_store == MetadataBackend::Redis && self.redis_mode == RedisMode::Standalone
+    }
+
+    /// How keys are placed: always on the ring with a standalone Redis, which has no
+    /// slots.
+    pub fn placement(&self) -> Placement {
+        match self.is_redis_standalone() {
+            true => Placement::Ring,
+            false => self.placement,
+        }
+    }
+
+    /// The configured Redis nodes, or the local one at `redis_port`.
+    pub fn redis_addrs(&self) -> Vec<String> {
+        if self.redis_addrs.is_empty() {
+            vec![format!(
+                "redis://{}",
+                host_port(&self.server_ip, self.redis_port)
+            )]
+        } else {
+            self.redis_addrs.clone()
+        }
+    }
+
+    /// The top-level bucket (or mock endpoint) as a catch-all backend, if one is set.
+    pub fn default_backend(&self) -> Option<BackendConfig> {
+        if self.use_mock_s3_endpoint.is_none() && self.bucket.is_none() {
+            return None;
+        }
+        Some(BackendConfig {
+            prefix: String::new(),
+            strip_prefix: false,
+            bucket: self.bucket.clone(),
+            region_name: self.region_name.clone(),
+            access_key: self.access_key.clone(),
+            secret_key: self.secret_key.clone(),
+            use_mock_s3_endpoint: self.use_mock_s3_endpoint.clone(),
+        })
+    }
+}
+
+/// Connector for one backend; region and credentials it leaves out come from `config`.
+fn backend_connector(
+    backend: &BackendConfig,
+    config: &ServerConfig,
+) -> Result<Arc<dyn StorageConnector + Send + Sync>, ConfigError> {
+    if let Some(endpoint) = &backend.use_mock_s3_endpoint {
+        info!("Using Mock S3 Storage Connector.");
+        return Ok(Arc::new(
+            MockS3StorageConnector::new(endpoint.clone())
+                .with_config(config.mock_s3.clone().unwrap_or_default()),
+        ));
+    }
+    info!("Using Real S3 Storage Connector.");
+    let setting = |name: &str, own: &Option<String>, inherited: &Option<String>| {
+        own.clone().or_else(|| inherited.clone()).ok_or_else(|| {
+            ConfigError::Invalid(format!(
+                "backend '{}': {} required unless use_mock_s3_endpoint is set",
+                backend.prefix, name
+            ))
+        })
+    };
+    Ok(Arc::new(S3StorageConnector::new(
+        setting("bucket", &backend.bucket, &None)?,
+        setting("region_name", &backend.region_name, &config.region_name)?,
+        setting("access_key", &backend.access_key, &config.access_key)?,
+        setting("secret_key", &backend.secret_key, &config.secret_key)?,
+    )))
 }
 
 impl ServerNode {
-    pub fn new(config: ServerConfig) -> Self {
-        let mut s3_connectors = Vec::new();
+    pub fn new(config: ServerConfig) -> Result<Self, StartupError> {
+        // Up before anything talks to Redis.
+        let embedded_redis = match config.embedded_redis.clone() {
+            Some(embedded) => {
+                let binary = embedded.binary.clone();
+                let redis = EmbeddedRedis::start(embedded, config.redis_port, config.redis_mode)
+                    .map_err(|source| StartupError::EmbeddedRedis { binary, source })?;
+                Some(Arc::new(redis))
+            }
+            None => None,
+        };
+        let fetch_limiter = config.max_concurrent_s3_fetches.map(|max_concurrent| {
+            Arc::new(
+                FetchLimiter::new(
+                    max_concurrent,
+                    Duration::from_millis(config.s3_fetch_queue_timeout_ms),
+                )
+                .limiting_batch_to(config.max_batch_s3_fetches),
+            )
+        });
+        let cost_ledger = Arc::new(CostLedger::new(
+            config.costs,
+            Tenants::new(config.tenants.clone()),
+        ));
+        let mut s3_connectors: Vec<Arc<dyn StorageConnector + Send + Sync>> = Vec::new();
+        let default_backend = config.default_backend();
         for _ in 0..config.bucket_size {
+            let default_connector = default_backend
+                .as_ref()
+                .map(|backend| backend_connector(backend, &config))
+                .transpose()?;
+            let s3_connector: Arc<dyn StorageConnector + Send + Sync> = if config
+                .backends
+                .is_empty()
+            {
+                default_connector.ok_or_else(|| {
+                    ConfigError::Invalid(String::from("no S3 bucket or mock endpoint configured"))
+                })?
+            } else {
+                let routes = config
+                    .backends
+                    .iter()
+                    .map(|backend| {
+                        Ok(Route {
+                            prefix: backend.prefix.clone(),
+                            strip_prefix: backend.strip_prefix,
+                            connector: backend_connector(backend, &config)?,
+                        })
+                    })
+                    .collect::<Result<_, ConfigError>>()?;
+                Arc::new(RoutingStorageConnector::new(routes, default_connector))
+            };
+            // Outside the router, so that traffic is charged under the keys clients ask for.
+            let s3_connector: Arc<dyn StorageConnector + Send + Sync> = Arc::new(
+                MeteredStorageConnector::new(s3_connector, cost_ledger.clone()),
+            );
+            #[cfg(feature = "fault-injection")]
             let s3_connector: Arc<dyn StorageConnector + Send + Sync> =
-                if config.use_mock_s3_endpoint.is_some() {
-                    println!("Using Mock S3 Storage Connector.");
-                    Arc::new(MockS3StorageConnector::new(
-                        config.use_mock_s3_endpoint.clone().unwrap(),
-                    ))
-                } else {
-                    println!("Using Real S3 Storage Connector.");
-                    Arc::new(S3StorageConnector::new(
-                        config.bucket.clone().unwrap(),
-                        config.region_name.clone().unwrap(),
-                        config.access_key.clone().unwrap(),
-                        config.secret_key.clone().unwrap(),
-                    ))
-                };
-            s3_connectors.push(s3_connector);
+                Arc::new(FaultyStorageConnector::new(s3_connector));
+            let s3_connector: Arc<dyn StorageConnector + Send + Sync> = match &fetch_limiter {
+                Some(limiter) => Arc::new(ThrottledStorageConnector::new(
+                    s3_connector,
+                    limiter.clone(),
+                )),
+                None => s3_connector,
+            };
+            // Outermost, so that waiting for a fetch slot counts against the deadline.
+            s3_connectors.push(Arc::new(TimeoutStorageConnector::new(
+                s3_connector,
+                config.timeouts.s3_fetch(),
+            )));
         }
 
+        let metadata: Box<dyn MetadataStore> = match config.metadata_store {
+            MetadataBackend::Redis => {
+                let server = match config.redis_mode {
+                    RedisMode::Cluster => RedisServer::new(
+                        config.redis_addrs(),
+                        config.redis_port,
+                        config.capacity_weight,
+                    ),
+                    // Nodes renew their membership on every slot refresh.
+                    RedisMode::Standalone => RedisServer::standalone(
+                        &config.redis_addrs()[0],
+                        &config.advertised_ip(),
+                        config.redis_port,
+                        config.capacity_weight,
+                        Duration::from_secs(3 * config.slot_refresh_interval_secs),
+                    ),
+                }?;
+                let node_id = identity::load_or_create(Path::new(&config.cache_dir))
+                    .map_err(StartupError::NodeId)?;
+                let server = server.with_node_id(node_id);
+                let server = match config.redis_namespace.clone() {
+                    Some(namespace) => {
+                        server.with_namespace(namespace, config.bucket_size as usize)
+                    }
+                    None => server,
+                };
+                let server = match config.location_lease_secs {
+                    0 => server,
+                    lease_secs => server.with_location_lease(lease_secs),
+                };
+                let server = match config.location_cache {
+                    Some(location_cache) => server.with_location_cache(location_cache),
+                    None => server,
+                };
+                Box::new(match config.advertised_address() {
+                    Some(address) => server.with_advertised_address(address),
+                    None => server,
+                })
+            }
+            MetadataBackend::InProcess => Box::new(InProcessStore::new(config.redis_port)),
+            MetadataBackend::Etcd => {
+                let etcd = config.etcd.as_ref().ok_or_else(|| {
+                    ConfigError::Invalid(String::from("metadata_store = \"etcd\" needs [etcd]"))
+                })?;
+                let store = EtcdStore::new(
+                    etcd,
+                    &config.advertised_ip(),
+                    config.redis_port,
+                    config.capacity_weight,
+                );
+                Box::new(match config.advertised_address() {
+                    Some(address) => store.with_advertised_address(address),
+                    None => store,
+                })
+            }
+        };
+        let encryption = match &config.encryption {
+            Some(encryption) => {
+                Some(
+                    encryption
+                        .load_key()
+                        .map_err(|source| StartupError::EncryptionKey {
+                            path: encryption.key_file.clone(),
+                            source,
+                        })?,
+                )
+            }
+            None => None,
+        };
         let cache_manager = Arc::new(ConcurrentDiskCache::new(
             PathBuf::from(&config.cache_dir),
-            config.max_size,
+            config.total_max_size(),
             config.bucket_size,
-            vec![format!(
-                "redis://{}:{}",
-                config.server_ip, config.redis_port
-            )],
-            config.redis_port,
+            metadata,
+            CacheOptions {
+                admission_policy: config.admission_policy,
+                max_cacheable_object_size: config.max_cacheable_object_size,
+                memory_tier_size: config.memory_tier_size,
+                memory_tier_max_object_size: config.memory_tier_max_object_size,
+                chunk_size: config.chunk_size,
+                parquet_footer_prefetch: config.parquet_footer_prefetch,
+                stream_fetches_from: config.stream_fetches_from,
+                hedge_after: config.hedge_after_ms.map(Duration::from_millis),
+                on_client_disconnect: config.on_client_disconnect,
+                disk_io: DiskIo::new(config.disk_io)
+                    .dropping_page_cache_from(config.drop_page_cache_from)
+                    .timing_out_reads_after(config.timeouts.disk_read()),
+                timeouts: config.timeouts,
+                listing_ttl: (config.list_cache_ttl_secs > 0)
+                    .then(|| Duration::from_secs(config.list_cache_ttl_secs)),
+                share_listings: config.share_list_cache,
+                cache_dirs: config.cache_dirs.clone(),
+                eviction: config.eviction,
+                shadow_caches: config.shadow_caches.clone(),
+                free_space_reserve: config.free_space_reserve,
+                compression: config.compression.clone(),
+                redirect_https: config.tls.is_some(),
+                encryption,
+                policies: PolicySet::new(config.policies.clone()),
+                tenants: Tenants::new(config.tenants.clone()),
+                ring_vnodes: (config.placement() == Placement::Ring)
+                    .then_some(config.vnodes_per_node),
+                sync_ring: config.is_redis_standalone(),
+                shard_runtimes: config.shard_runtimes,
+            },
         ));
-        ServerNode {
+        let peers = match &config.tls {
+            Some(tls) => PeerClient::with_tls(tls).map_err(StartupError::Tls)?,
+            None => PeerClient::default(),
+        };
+        let rebalancer = match config.rebalance {
+            Some(rebalance) => {
+                let rebalancer = Rebalancer::new(rebalance, config.admin_token.clone());
+                Some(Arc::new(match &config.tls {
+                    Some(tls) => rebalancer.with_tls(tls).map_err(StartupError::Tls)?,
+                    None => rebalancer,
+                }))
+            }
+            None => None,
+        };
+        let write_back = match config.write_back.clone() {
+            Some(write_back) => {
+                let dir = write_back.dir.clone();
+                let queue = WriteBackQueue::open(write_back)
+                    .map_err(|source| StartupError::WriteBack { dir, source })?;
+                Some(Arc::new(queue))
+            }
+            None => None,
+        };
+        let warm_up = config
+            .warm_up
+            .clone()
+            .map(|warm_up| Arc::new(WarmUp::new(warm_up)));
+        let snapshots = config
+            .snapshots
+            .clone()
+            .map(|snapshots| Arc::new(Snapshots::new(snapshots)));
+        Ok(ServerNode {
             cache_manager,
             s3_connectors,
+            fetch_limiter,
+            cost_ledger,
+            embedded_redis,
+            rebalancer,
+            write_back,
+            warm_up,
+            snapshots,
+            peers,
             config,
+        })
+    }
+
+    /// Builds a node from a TOML config file with `ISTZIIO_*` environment overrides.
+    pub fn from_config_path(path: impl AsRef<Path>) -> Result<Self, StartupError> {
+        Self::new(load_config(path.as_ref())?)
+    }
+
+    /// Read tokens plus the tenants' tokens. Tenant tokens only take effect once read
+    /// tokens are required at all, so configuring tenants never closes an open node.
+    fn read_tokens(&self) -> Vec<String> {
+        let mut tokens = self.config.read_tokens.clone();
+        if !tokens.is_empty() {
+            tokens.extend(self.config.tenants.iter().flat_map(|t| t.tokens.clone()));
         }
+        tokens
     }
+
     pub fn build(&self) -> Rocket<rocket::Build> {
-        let rocket_port = cache::PORT_OFFSET_TO_WEB_SERVER + self.config.redis_port;
+        let rocket_port = self.config.web_port();
         let cache_state = self.cache_manager.clone();
         let s3_connector_state = self.s3_connectors.clone(); // Now cloning the vector of connectors
-        rocket::build()
-            .configure(
-                rocket::Config::figment()
-                    .merge(("address", &self.config.server_ip))
-                    .merge(("port", rocket_port)),
-            )
+
+        // With TLS, Rocket listens on loopback only and a TLS relay takes the public port,
+        // passing on each client's address.
+        let figment = match self.config.tls {
+            Some(_) => rocket::Config::figment()
+                .merge(("address", "127.0.0.1"))
+                .merge(("port", rocket_port + TLS_BACKEND_PORT_OFFSET))
+                .merge(("ip_header", REAL_IP_HEADER)),
+            None => rocket::Config::figment()
+                .merge(("address", self.config.server_ip.clone()))
+                .merge(("port", rocket_port)),
+        };
+        let mut rocket = rocket::build()
+            .configure(figment)
             .manage(cache_state)
             .manage(s3_connector_state)
-            .mount("/", routes![health_check, get_file, cache_stats, clear])
+            .manage(self.fetch_limiter.clone())
+            .manage(self.cost_ledger.clone())
+            .manage(self.embedded_redis.clone())
+            .manage(self.rebalancer.clone())
+            .manage(self.write_back.clone())
+            .manage(self.warm_up.clone())
+            .manage(HealthProbes::default())
+            .manage(self.snapshots.clone())
+            .manage(ReadThrough::new(
+                &self.config.fetch_sources,
+                self.fetch_limiter.clone(),
+                self.config.timeouts.s3_fetch(),
+            ))
+            .manage(AuthConfig {
+                admin_token: self.config.admin_token.clone(),
+                read_tokens: self.read_tokens(),
+            })
+            .manage(Tenants::new(self.config.tenants.clone()))
+            .manage(S3Api::new(&self.config))
+            .manage(self.peers.clone())
+            .manage(InvalidationBus {
+                channel: self.config.invalidation_channel.clone(),
+            })
+            .mount(
+                "/",
+                routes![
+                    health_check,
+                    health::live,
+                    health::ready,
+                    get_file,
+                    get_parquet_metadata,
+                    cache_stats,
+                    stats_json,
+                    costs,
+                    efficiency,
+                    hot_keys,
+                    prefix_usage,
+                    dashboard,
+                    clear,
+                    invalidate,
+                    invalidate_prefix,
+                    delete_file,
+                    refresh_mapping,
+                    update_config,
+                    resize,
+                    s3_api::list_objects,
+                    s3_api::get_object,
+                    s3_api::head_object,
+                    cluster::cluster_stats,
+                    cluster::cluster_clear,
+                    cluster::cluster_invalidate,
+                    invalidation::broadcast_invalidate,
+                    invalidation::broadcast_invalidate_prefix,
+                    ring::show_ring,
+                    ring::sync_ring,
+                    ring::set_vnodes,
+                    rebalance::receive,
+                    rebalance::rebalance_status,
+                    rebalance::start_rebalance,
+                    scrub::scrub,
+                    writeback::put_object,
+                    writeback::write_back_status,
+                    writeback::flush,
+                    warmup::warmup_status,
+                    snapshot::take_snapshot,
+                    read_through::fetch,
+                    listing::list
+                ],
+            )
+            .attach(CostFairing(self.cost_ledger.clone()))
+            .attach(AdHoc::on_response("Request ID", |req, res| {
+                Box::pin(async move {
+                    res.set_raw_header(REQUEST_ID_HEADER, RequestId::of(req).0);
+                })
+            }));
+        if let Some(rate_limit) = self.config.rate_limit {
+            rocket = rocket.manage(RateLimiter::new(rate_limit));
+        }
+        let egress = self
+            .config
+            .egress
+            .map(|egress| Arc::new(EgressShaper::new(egress)));
+        if let Some(shaper) = egress.clone() {
+            rocket = rocket.attach(EgressFairing(shaper));
+        }
+        rocket = rocket.manage(egress);
+        #[cfg(feature = "fault-injection")]
+        {
+            rocket = rocket.mount(
+                "/",
+                routes![
+                    faults::show_faults,
+                    faults::set_faults,
+                    faults::clear_faults
+                ],
+            );
+        }
+        if let Some(path) = self.config.access_log.clone() {
+            rocket = rocket.attach(AdHoc::try_on_ignite("Access log", |rocket| async move {
+                match AccessLog::open(Path::new(&path)) {
+                    Ok(access_log) => Ok(rocket.attach(access_log)),
+                    Err(e) => {
+                        log::error!("Failed to open access log {}: {}", path, e);
+                        Err(rocket)
+                    }
+                }
+            }));
+        }
+        if let Some(path) = self.config.request_trace.clone() {
+            rocket = rocket.attach(AdHoc::try_on_ignite("Request trace", |rocket| async move {
+                match AccessLog::trace(Path::new(&path)) {
+                    Ok(trace) => Ok(rocket.attach(trace)),
+                    Err(e) => {
+                        log::error!("Failed to open request trace {}: {}", path, e);
+                        Err(rocket)
+                    }
+                }
+            }));
+        }
+        if self.config.self_test {
+            let config = self.config.clone();
+            let cache = self.cache_manager.clone();
+            let connector = self.s3_connectors.first().cloned();
+            rocket = rocket.attach(AdHoc::try_on_ignite("Self-test", |rocket| async move {
+                let problems = self_test(&config, &cache, connector.as_ref()).await;
+                if problems.is_empty() {
+                    info!("Self-test passed");
+                    return Ok(rocket);
+                }
+                for problem in problems {
+                    log::error!("Self-test failed: {}", problem);
+                }
+                Err(rocket)
+            }));
+        }
+        if let Some(tracing) = self.config.tracing.clone() {
+            SpanLogger::install(tracing);
+            rocket = rocket.attach(AdHoc::on_response("Trace ID", |req, res| {
+                Box::pin(async move {
+                    let trace = TraceContext::of(req);
+                    res.set_raw_header(TRACE_ID_HEADER, trace.trace_id);
+                })
+            }));
+        }
+        if self.config.slot_refresh_interval_secs > 0 {
+            let interval = Duration::from_secs(self.config.slot_refresh_interval_secs);
+            let cache = self.cache_manager.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("Slot refresh", move |_| {
+                Box::pin(async move {
+                    tokio::spawn(async move {
+                        loop {
+                            tokio::time::sleep(interval).await;
+                            if let Err(e) = cache.refresh_mapping().await {
+                                log::warn!("Refreshing the slot mapping failed: {}", e);
+                            }
+                        }
+                    });
+                })
+            }));
+        }
+        let cache = self.cache_manager.clone();
+        rocket = rocket.attach(AdHoc::on_liftoff("Disk probe", |_| {
+            Box::pin(async move {
+                tokio::spawn(async move {
+                    loop {
+                        tokio::time::sleep(PROBE_INTERVAL).await;
+                        cache.probe_disks().await;
+                    }
+                });
+            })
+        }));
+        if self.config.free_space_reserve.is_some() {
+            let cache = self.cache_manager.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("Free space check", |_| {
+                Box::pin(async move {
+                    tokio::spawn(async move {
+                        loop {
+                            tokio::time::sleep(FREE_SPACE_CHECK_INTERVAL).await;
+                            cache.reserve_free_space().await;
+                        }
+                    });
+                })
+            }));
+        }
+        if self.config.scrub_interval_secs > 0 {
+            let cache = self.cache_manager.clone();
+            let interval = Duration::from_secs(self.config.scrub_interval_secs);
+            rocket = rocket.attach(AdHoc::on_liftoff("Scrubber", move |_| {
+                Box::pin(async move {
+                    tokio::spawn(scrub::run(cache, interval));
+                })
+            }));
+        }
+        if self.config.eviction.is_some() {
+            let cache = self.cache_manager.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("Background eviction", move |_| {
+                Box::pin(async move {
+                    tokio::spawn(eviction::run(cache));
+                })
+            }));
+        }
+        if let Some(rebalancer) = self.rebalancer.clone() {
+            let cache = self.cache_manager.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("Rebalancer", |_| {
+                Box::pin(async move {
+                    tokio::spawn(rebalancer.run(cache));
+                })
+            }));
+        }
+        if let Some(write_back) = self.write_back.clone() {
+            let connectors = self.s3_connectors.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("Write-back uploads", |_| {
+                Box::pin(async move {
+                    tokio::spawn(write_back.run(connectors));
+                })
+            }));
+        }
+        if let Some(warm_up) = self.warm_up.clone() {
+            let cache = self.cache_manager.clone();
+            let connectors = self.s3_connectors.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("Warm-up", |_| {
+                Box::pin(async move {
+                    tokio::spawn(warm_up.run(cache, connectors));
+                })
+            }));
+        }
+        let restore_from = self
+            .config
+            .snapshots
+            .as_ref()
+            .and_then(|snapshots| snapshots.restore_from.clone());
+        if let (Some(snapshots), Some(name)) = (self.snapshots.clone(), restore_from) {
+            let cache = self.cache_manager.clone();
+            let connectors = self.s3_connectors.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("Snapshot restore", |_| {
+                Box::pin(async move {
+                    tokio::spawn(async move {
+                        if let Err(e) = snapshots.restore(&name, &cache, &connectors).await {
+                            log::error!("Restoring snapshot {} failed: {}", name, e);
+                        }
+                    });
+                })
+            }));
+        }
+        if self.config.metadata_store == MetadataBackend::Etcd {
+            // Join the cluster right away rather than on the first request, so that peers
+            // start redirecting to this node as soon as it is up.
+            let cache = self.cache_manager.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("etcd membership", |_| {
+                Box::pin(async move {
+                    if cache.members().await.is_err() {
+                        log::error!("Failed to register with etcd; retrying on first request");
+                    }
+                })
+            }));
+        }
+        if let Some(channel) = self.config.invalidation_channel.clone() {
+            let addrs = self.config.redis_addrs();
+            let cache = self.cache_manager.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("Invalidation bus", |_| {
+                Box::pin(async move { invalidation::subscribe(addrs, channel, cache) })
+            }));
+        }
+        if self.config.location_lease_secs > 0 {
+            let cache = self.cache_manager.clone();
+            let interval = Duration::from_secs(self.config.location_lease_secs) / 3;
+            rocket = rocket.attach(AdHoc::on_liftoff("Lease renewal", move |_| {
+                Box::pin(async move {
+                    tokio::spawn(async move {
+                        loop {
+                            tokio::time::sleep(interval).await;
+                            if let Err(e) = cache.renew_location_leases().await {
+                                log::warn!("Failed to renew the leases on file locations: {}", e);
+                            }
+                        }
+                    });
+                })
+            }));
+        }
+        if self.config.redis_namespace.is_some() {
+            let cache = self.cache_manager.clone();
+            rocket = rocket.attach(AdHoc::on_shutdown("Redis cleanup", |_| {
+                Box::pin(async move { cache.forget_own_locations().await })
+            }));
+        }
+        if let Some(grpc) = self.config.grpc {
+            let service = CacheService::new(
+                self.cache_manager.clone(),
+                self.s3_connectors.clone(),
+                grpc.port_offset,
+            );
+            let mut server = GrpcServer::new(AuthConfig {
+                admin_token: self.config.admin_token.clone(),
+                read_tokens: self.read_tokens(),
+            })
+            .with_service(Arc::new(service));
+            if grpc.flight {
+                server = server.with_service(Arc::new(FlightService::new(
+                    self.cache_manager.clone(),
+                    self.s3_connectors.clone(),
+                    grpc.port_offset,
+                )));
+            }
+            let addr = host_port(
+                &self.config.server_ip,
+                rocket_port.saturating_add(grpc.port_offset),
+            );
+            let tls = self.config.tls.clone();
+            rocket = rocket.attach(AdHoc::on_liftoff("gRPC", |_| {
+                Box::pin(async move {
+                    let acceptor = match tls.map(|tls| tls.grpc_acceptor()).transpose() {
+                        Ok(acceptor) => acceptor,
+                        Err(e) => {
+                            log::error!("Failed to load TLS certificates for gRPC: {}", e);
+                            return;
+                        }
+                    };
+                    tokio::spawn(grpc::serve(Arc::new(server), addr, acceptor));
+                })
+            }));
+        }
+        if let Some(tls) = self.config.tls.clone() {
+            let public = host_port(&self.config.server_ip, rocket_port);
+            rocket = rocket
+                .attach(AdHoc::try_on_ignite("TLS", |rocket| async move {
+                    match tls
+                        .acceptor()
+                        .and_then(|acceptor| RelayToken::generate().map(|token| (acceptor, token)))
+                    {
+                        Ok((acceptor, token)) => Ok(rocket.manage(acceptor).manage(token)),
+                        Err(e) => {
+                            log::error!("Failed to load TLS certificates: {}", e);
+                            Err(rocket)
+                        }
+                    }
+                }))
+                .attach(RelayOnly)
+                .attach(AdHoc::on_liftoff("TLS relay", |rocket| {
+                    Box::pin(async move {
+                        let acceptor = rocket.state::<TlsAcceptor>().unwrap().clone();
+                        let token = rocket.state::<RelayToken>().unwrap().clone();
+                        let backend = (rocket.config().address, rocket.config().port).into();
+                        tokio::spawn(serve_tls(acceptor, public, backend, token));
+                    })
+                }));
+        }
+        rocket
     }
 }
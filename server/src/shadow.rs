@@ -0,0 +1,249 @@
+// shadow.rs
+//! Shadow caches: simulations of an eviction policy and size the node could run instead,
+//! fed the same accesses as the real cache. They keep keys and sizes only, never data, and
+//! count the hits and misses they would have had, so that e.g. doubling the capacity or
+//! switching to LFU can be judged from production traffic before buying hardware.
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::metrics::hit_ratio;
+
+/// Upper bound on the missed keys a shadow waits to learn the size of.
+const MAX_PENDING: usize = 65536;
+
+/// Which entry a shadow cache evicts to make room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ShadowPolicy {
+    /// The least recently used, as the real cache does.
+    #[default]
+    Lru,
+    /// The least frequently used, the least recently used of those first.
+    Lfu,
+}
+
+impl FromStr for ShadowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lru" => Ok(ShadowPolicy::Lru),
+            "lfu" => Ok(ShadowPolicy::Lfu),
+            _ => Err(format!("unknown shadow cache policy '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for ShadowPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ShadowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ShadowPolicy::Lru => "lru",
+            ShadowPolicy::Lfu => "lfu",
+        })
+    }
+}
+
+/// One `[[shadow_caches]]` entry.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShadowConfig {
+    /// Names the shadow in the stats.
+    pub name: String,
+    #[serde(default)]
+    pub policy: ShadowPolicy,
+    /// Bytes the shadow holds across the node; the real cache's `max_size` when unset.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+}
+
+impl ShadowConfig {
+    /// This config for a shard holding `shard_max_size` of the node's `max_size`, with the
+    /// shadow's budget split in the same proportion.
+    pub fn per_shard(&self, shard_max_size: u64, max_size: u64) -> ShadowConfig {
+        let budget = match self.max_size {
+            Some(total) => {
+                (total as u128 * shard_max_size as u128 / max_size.max(1) as u128) as u64
+            }
+            None => shard_max_size,
+        };
+        ShadowConfig {
+            max_size: Some(budget),
+            ..self.clone()
+        }
+    }
+}
+
+/// What a shadow cache would have done, summed over the shards.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShadowStats {
+    pub name: String,
+    pub policy: String,
+    pub max_size: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Percentage of accesses the shadow would have answered.
+    pub hit_ratio: f64,
+    pub current_size: u64,
+    pub entries: u64,
+}
+
+impl ShadowStats {
+    pub fn add(&mut self, other: &ShadowStats) {
+        self.max_size += other.max_size;
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.current_size += other.current_size;
+        self.entries += other.entries;
+        self.hit_ratio = hit_ratio((self.hits, self.misses));
+    }
+
+    /// Sums `stats` by shadow name, keeping the order of first appearance.
+    pub fn merge<'a>(stats: impl IntoIterator<Item = &'a ShadowStats>) -> Vec<ShadowStats> {
+        let mut merged: Vec<ShadowStats> = Vec::new();
+        for shadow in stats {
+            match merged.iter_mut().find(|m| m.name == shadow.name) {
+                Some(total) => total.add(shadow),
+                None => merged.push(shadow.clone()),
+            }
+        }
+        merged
+    }
+}
+
+struct ShadowEntry {
+    size: u64,
+    accesses: u64,
+    last_access: u64,
+}
+
+/// One shard's simulation of a policy and size.
+pub struct ShadowCache {
+    name: String,
+    policy: ShadowPolicy,
+    max_size: u64,
+    current_size: u64,
+    /// Counts accesses; orders entries by recency.
+    clock: u64,
+    entries: HashMap<String, ShadowEntry>,
+    /// `(rank, last access, key)`, the next to evict first. The rank is 0 under LRU and
+    /// the access count under LFU.
+    order: BTreeSet<(u64, u64, String)>,
+    /// Missed keys whose size was unknown at the time, inserted by `sized`.
+    pending: HashSet<String>,
+    hits: u64,
+    misses: u64,
+}
+
+impl ShadowCache {
+    /// `config.max_size` is this shadow's budget; see `ShadowConfig::per_shard`.
+    pub fn new(config: &ShadowConfig) -> Self {
+        Self {
+            name: config.name.clone(),
+            policy: config.policy,
+            max_size: config.max_size.unwrap_or(0),
+            current_size: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            order: BTreeSet::new(),
+            pending: HashSet::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn rank(&self, entry: &ShadowEntry) -> u64 {
+        match self.policy {
+            ShadowPolicy::Lru => 0,
+            ShadowPolicy::Lfu => entry.accesses,
+        }
+    }
+
+    /// Counts an access to `key` as a hit or a miss. A missed key is inserted once its
+    /// size is known, here or by `sized`.
+    pub fn record(&mut self, key: &str, size: Option<u64>) {
+        self.clock += 1;
+        if let Some(mut entry) = self.entries.remove(key) {
+            self.hits += 1;
+            self.order
+                .remove(&(self.rank(&entry), entry.last_access, key.to_string()));
+            entry.accesses += 1;
+            entry.last_access = self.clock;
+            self.order
+                .insert((self.rank(&entry), entry.last_access, key.to_string()));
+            self.entries.insert(key.to_string(), entry);
+            return;
+        }
+        self.misses += 1;
+        match size {
+            Some(size) => self.insert(key, size),
+            None if self.pending.len() < MAX_PENDING => {
+                self.pending.insert(key.to_string());
+            }
+            None => {}
+        }
+    }
+
+    /// Inserts `key` if it missed without a size, e.g. while it was streamed.
+    pub fn sized(&mut self, key: &str, size: u64) {
+        if self.pending.remove(key) {
+            self.insert(key, size);
+        }
+    }
+
+    /// Evicts as the policy would until `size` more bytes fit, then adds `key`.
+    fn insert(&mut self, key: &str, size: u64) {
+        self.pending.remove(key);
+        if size > self.max_size || self.entries.contains_key(key) {
+            return;
+        }
+        while self.current_size + size > self.max_size {
+            let Some(victim) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&victim.2) {
+                self.current_size -= evicted.size;
+            }
+        }
+        let entry = ShadowEntry {
+            size,
+            accesses: 1,
+            last_access: self.clock,
+        };
+        self.order
+            .insert((self.rank(&entry), entry.last_access, key.to_string()));
+        self.entries.insert(key.to_string(), entry);
+        self.current_size += size;
+    }
+
+    /// Forgets every entry; the counters are kept.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.pending.clear();
+        self.current_size = 0;
+    }
+
+    pub fn stats(&self) -> ShadowStats {
+        ShadowStats {
+            name: self.name.clone(),
+            policy: self.policy.to_string(),
+            max_size: self.max_size,
+            hits: self.hits,
+            misses: self.misses,
+            hit_ratio: hit_ratio((self.hits, self.misses)),
+            current_size: self.current_size,
+            entries: self.entries.len() as u64,
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// shard_runtime.rs
+//! Thread-per-shard execution. Every shard of the cache gets a single-threaded runtime on
+//! its own OS thread, optionally pinned to a core, and requests run on the runtime of the
+//! shard owning their key. A shard's mutex is then taken from one thread on the request
+//! path instead of being fought over by every worker of the shared runtime.
+use serde::Deserialize;
+use std::future::Future;
+use std::io::Result as IoResult;
+use std::sync::mpsc;
+use tokio::runtime::{Builder, Handle};
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShardRuntimeConfig {
+    /// Pin the thread of shard `i` to core `i` modulo the number of cores.
+    pub pin_cores: bool,
+}
+
+/// The runtimes of the shards, stopped when dropped.
+pub struct ShardRuntimes {
+    handles: Vec<Handle>,
+    /// Dropping these ends the threads.
+    _stop: Vec<oneshot::Sender<()>>,
+}
+
+impl ShardRuntimes {
+    /// Starts `count` runtimes, each on a thread of its own.
+    pub fn start(count: usize, config: ShardRuntimeConfig) -> IoResult<Self> {
+        let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        let mut handles = Vec::with_capacity(count);
+        let mut stop = Vec::with_capacity(count);
+        for index in 0..count {
+            let (handle_tx, handle_rx) = mpsc::channel();
+            let (stop_tx, stop_rx) = oneshot::channel::<()>();
+            std::thread::Builder::new()
+                .name(format!("shard-{}", index))
+                .spawn(move || {
+                    if config.pin_cores {
+                        pin_to_core(index % cores);
+                    }
+                    let runtime = match Builder::new_current_thread().enable_all().build() {
+                        Ok(runtime) => runtime,
+                        Err(e) => {
+                            let _ = handle_tx.send(Err(e));
+                            return;
+                        }
+                    };
+                    let _ = handle_tx.send(Ok(runtime.handle().clone()));
+                    let _ = runtime.block_on(stop_rx);
+                })?;
+            let handle = handle_rx
+                .recv()
+                .map_err(|_| std::io::Error::other("shard thread exited while starting"))??;
+            handles.push(handle);
+            stop.push(stop_tx);
+        }
+        Ok(Self {
+            handles,
+            _stop: stop,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Runs `task` on the runtime of `shard` and waits for its output.
+    pub async fn run<F>(&self, shard: usize, task: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        match self.handles[shard % self.handles.len()].spawn(task).await {
+            Ok(output) => output,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => panic!("the runtime of shard {} stopped", shard),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_core(core: usize) {
+    // SAFETY: the set is zeroed, then filled by the libc helpers before it is passed on.
+    let pinned = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    };
+    if !pinned {
+        log::warn!("Failed to pin a shard thread to core {}", core);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_core: usize) {
+    log::warn!("Pinning shard threads to cores is only supported on Linux");
+}
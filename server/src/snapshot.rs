@@ -0,0 +1,347 @@
+// snapshot.rs
+//! Snapshots of what a node caches, kept in the backing store, for pre-warming a new node
+//! from an existing one instead of from cold. `POST /admin/snapshot` writes a manifest of
+//! the node's cached objects under `snapshots.prefix`; with `data=true` it uploads their
+//! cached copies next to it. A node with `snapshots.restore_from` set reads that snapshot
+//! when it starts and caches the objects it owns, copied from the snapshot when it has
+//! them and fetched from their source otherwise.
+use bytes::Bytes;
+use log::{info, warn};
+use rocket::futures::{stream, StreamExt};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{post, State};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Result as IoResult};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::AdminAccess;
+use crate::cache::{ConcurrentDiskCache, GetFileOptions};
+use crate::s3_api::connector_for;
+use crate::storage::storage_connector::{read_stream_to_end, ObjectVersion, StorageConnector};
+use crate::warmup::warmed_bytes;
+
+/// Name of the manifest in a snapshot's directory; it is written last, so a snapshot
+/// with a manifest is complete.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// Where snapshots live in the backing store; snapshot `<name>` is under
+    /// `<prefix><name>/`.
+    pub prefix: String,
+    /// Snapshot to restore at startup.
+    pub restore_from: Option<String>,
+    /// Objects uploaded or restored at once.
+    pub concurrency: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            prefix: String::from("_istziio/snapshots/"),
+            restore_from: None,
+            concurrency: 4,
+        }
+    }
+}
+
+/// Whether `name` can name a snapshot: one non-empty path segment.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+/// An object of a snapshot, with the version S3 reported when it was cached.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotObject {
+    pub key: String,
+    pub e_tag: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub last_modified: Option<i64>,
+}
+
+impl SnapshotObject {
+    fn version(&self) -> ObjectVersion {
+        ObjectVersion {
+            e_tag: self.e_tag.clone(),
+            last_modified: self.last_modified,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub name: String,
+    pub taken_at: String,
+    /// Whether the cached copies were uploaded under `<name>/data/`.
+    pub with_data: bool,
+    /// Shard by shard, each shard's least recently used first.
+    pub objects: Vec<SnapshotObject>,
+}
+
+/// What `POST /admin/snapshot` wrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotSummary {
+    pub name: String,
+    pub objects: u64,
+    /// Bytes of the copies uploaded.
+    pub bytes: u64,
+    /// Copies that could not be uploaded; a restore fetches those from their source.
+    pub failed: u64,
+}
+
+/// What a restore did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreSummary {
+    /// Objects copied from the snapshot.
+    pub copied: u64,
+    /// Objects fetched from their source.
+    pub fetched: u64,
+    /// Objects another node owns, or cached here already.
+    pub skipped: u64,
+    pub failed: u64,
+    pub bytes: u64,
+}
+
+pub struct Snapshots {
+    config: SnapshotConfig,
+}
+
+impl Snapshots {
+    pub fn new(config: SnapshotConfig) -> Self {
+        Snapshots { config }
+    }
+
+    pub fn manifest_key(&self, name: &str) -> String {
+        format!("{}{}/{}", self.config.prefix, name, MANIFEST_NAME)
+    }
+
+    pub fn data_key(&self, name: &str, key: &str) -> String {
+        format!("{}{}/data/{}", self.config.prefix, name, key)
+    }
+
+    /// Writes a snapshot of the objects cached here, with their copies if `with_data`.
+    pub async fn take(
+        &self,
+        name: &str,
+        with_data: bool,
+        cache: &ConcurrentDiskCache,
+        connectors: &[Arc<dyn StorageConnector + Send + Sync>],
+    ) -> IoResult<SnapshotSummary> {
+        let objects = cache.cached_objects().await;
+        let summary = Mutex::new(SnapshotSummary {
+            name: name.to_string(),
+            objects: objects.len() as u64,
+            ..Default::default()
+        });
+        if with_data {
+            stream::iter(&objects)
+                .for_each_concurrent(self.config.concurrency.max(1), |(key, _)| {
+                    let summary = &summary;
+                    async move {
+                        // Evicted since the listing; the restore fetches it from its source.
+                        let (body, size) = match cache.open_object_sized(key).await {
+                            Some(opened) => opened,
+                            None => return,
+                        };
+                        let data_key = self.data_key(name, key);
+                        let uploaded = connector_for(&data_key, connectors)
+                            .put_stream(&data_key, body, size)
+                            .await;
+                        match uploaded {
+                            Ok(()) => summary.lock().unwrap().bytes += size,
+                            Err(e) => {
+                                warn!("Snapshot {}: uploading {} failed: {}", name, key, e);
+                                summary.lock().unwrap().failed += 1;
+                            }
+                        }
+                    }
+                })
+                .await;
+        }
+        let manifest = SnapshotManifest {
+            name: name.to_string(),
+            taken_at: chrono::Utc::now().to_rfc3339(),
+            with_data,
+            objects: objects
+                .into_iter()
+                .map(|(key, version)| {
+                    let version = version.unwrap_or_default();
+                    SnapshotObject {
+                        key,
+                        e_tag: version.e_tag,
+                        last_modified: version.last_modified,
+                    }
+                })
+                .collect(),
+        };
+        let manifest_key = self.manifest_key(name);
+        let encoded = Bytes::from(serde_json::to_vec(&manifest).map_err(io::Error::other)?);
+        let size = encoded.len() as u64;
+        connector_for(&manifest_key, connectors)
+            .put_stream(
+                &manifest_key,
+                Box::pin(stream::once(async { Ok(encoded) })),
+                size,
+            )
+            .await?;
+        let summary = summary.into_inner().unwrap();
+        info!(
+            "Snapshot {}: {} objects, {} bytes of copies, {} failed",
+            name, summary.objects, summary.bytes, summary.failed
+        );
+        Ok(summary)
+    }
+
+    /// Reads the manifest of the snapshot `name`.
+    pub async fn manifest(
+        &self,
+        name: &str,
+        connectors: &[Arc<dyn StorageConnector + Send + Sync>],
+    ) -> IoResult<SnapshotManifest> {
+        let manifest_key = self.manifest_key(name);
+        let object = connector_for(&manifest_key, connectors)
+            .fetch_stream(&manifest_key)
+            .await?;
+        let data = read_stream_to_end(object.stream).await?;
+        serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Caches the objects of the snapshot `name` that this node owns.
+    pub async fn restore(
+        &self,
+        name: &str,
+        cache: &ConcurrentDiskCache,
+        connectors: &[Arc<dyn StorageConnector + Send + Sync>],
+    ) -> IoResult<RestoreSummary> {
+        let manifest = self.manifest(name, connectors).await?;
+        info!(
+            "Restoring {} objects from snapshot {}",
+            manifest.objects.len(),
+            name
+        );
+        let summary = Mutex::new(RestoreSummary::default());
+        stream::iter(&manifest.objects)
+            .for_each_concurrent(self.config.concurrency.max(1), |object| {
+                let summary = &summary;
+                let manifest = &manifest;
+                async move {
+                    if !cache.owns(&object.key).await
+                        || cache.open_object(&object.key).await.is_some()
+                    {
+                        summary.lock().unwrap().skipped += 1;
+                        return;
+                    }
+                    if manifest.with_data {
+                        match self.copy(name, object, cache, connectors).await {
+                            Ok(bytes) => {
+                                let mut summary = summary.lock().unwrap();
+                                summary.copied += 1;
+                                summary.bytes += bytes;
+                                return;
+                            }
+                            Err(e) => warn!(
+                                "Snapshot {}: copying {} failed, fetching it instead: {}",
+                                name, object.key, e
+                            ),
+                        }
+                    }
+                    let options = GetFileOptions {
+                        force_admit: true,
+                        ..Default::default()
+                    };
+                    let connector = connector_for(&object.key, connectors).clone();
+                    let result = cache
+                        .get_file(PathBuf::from(&object.key), connector, options)
+                        .await;
+                    match warmed_bytes(result).await {
+                        Ok(Some(bytes)) => {
+                            let mut summary = summary.lock().unwrap();
+                            summary.fetched += 1;
+                            summary.bytes += bytes;
+                        }
+                        // Ownership moved since the check.
+                        Ok(None) => summary.lock().unwrap().skipped += 1,
+                        Err(e) => {
+                            warn!("Snapshot {}: fetching {} failed: {}", name, object.key, e);
+                            summary.lock().unwrap().failed += 1;
+                        }
+                    }
+                }
+            })
+            .await;
+        let summary = summary.into_inner().unwrap();
+        info!(
+            "Restored snapshot {}: {} objects copied, {} fetched ({} bytes), {} skipped, {} failed",
+            name, summary.copied, summary.fetched, summary.bytes, summary.skipped, summary.failed
+        );
+        Ok(summary)
+    }
+
+    /// Caches the snapshot's copy of `object`; returns its size.
+    async fn copy(
+        &self,
+        name: &str,
+        object: &SnapshotObject,
+        cache: &ConcurrentDiskCache,
+        connectors: &[Arc<dyn StorageConnector + Send + Sync>],
+    ) -> IoResult<u64> {
+        let data_key = self.data_key(name, &object.key);
+        let body = connector_for(&data_key, connectors)
+            .fetch_stream(&data_key)
+            .await?;
+        let staged = cache.stage(&object.key, body.stream).await?;
+        let size = staged.size();
+        if size > cache.max_cacheable_size(&object.key).await {
+            staged.discard().await;
+            return Err(io::Error::other("larger than this node caches"));
+        }
+        cache.ingest(&object.key, staged).await?;
+        cache
+            .record_object_version(&object.key, object.version())
+            .await;
+        Ok(size)
+    }
+}
+
+/// Writes a snapshot of this node's cache, named `name` or after the current time; with
+/// `data=true`, along with the cached copies.
+#[post("/admin/snapshot?<name>&<data>")]
+pub async fn take_snapshot(
+    _admin: AdminAccess,
+    name: Option<String>,
+    data: Option<bool>,
+    snapshots: &State<Option<Arc<Snapshots>>>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+    s3_connectors: &State<Vec<Arc<dyn StorageConnector + Send + Sync>>>,
+) -> Result<Json<SnapshotSummary>, Custom<String>> {
+    let snapshots = match snapshots.inner() {
+        Some(snapshots) => snapshots,
+        None => {
+            return Err(Custom(
+                Status::NotFound,
+                String::from("snapshots are not configured"),
+            ))
+        }
+    };
+    let name = name.unwrap_or_else(|| chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string());
+    if !valid_name(&name) {
+        return Err(Custom(
+            Status::BadRequest,
+            format!("invalid snapshot name '{}'", name),
+        ));
+    }
+    snapshots
+        .take(&name, data.unwrap_or(false), cache, s3_connectors)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            Custom(
+                Status::BadGateway,
+                format!("snapshot {} failed: {}", name, e),
+            )
+        })
+}
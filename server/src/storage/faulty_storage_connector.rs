@@ -0,0 +1,56 @@
+// server/src/storage/faulty_storage_connector.rs
+use async_trait::async_trait;
+use std::io::Result as IoResult;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::chunk::ByteRange;
+use crate::faults::{inject, FaultPoint};
+use crate::storage::storage_connector::{
+    FetchedObject, ListRequest, ObjectInfo, ObjectListing, ObjectStream, StorageConnector,
+};
+
+/// Wraps a connector so that requests to the backing store are delayed and fail as the
+/// `s3` fault in effect says.
+pub struct FaultyStorageConnector {
+    inner: Arc<dyn StorageConnector + Send + Sync>,
+}
+
+impl FaultyStorageConnector {
+    pub fn new(inner: Arc<dyn StorageConnector + Send + Sync>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl StorageConnector for FaultyStorageConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        inject(FaultPoint::S3).await?;
+        self.inner.fetch_stream(file_name).await
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        inject(FaultPoint::S3).await?;
+        self.inner.fetch_range(file_name, range).await
+    }
+
+    async fn head_object(&self, file_name: &str) -> IoResult<ObjectInfo> {
+        inject(FaultPoint::S3).await?;
+        self.inner.head_object(file_name).await
+    }
+
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        inject(FaultPoint::S3).await?;
+        self.inner.list_objects(request).await
+    }
+
+    async fn put_object(&self, file_name: &str, path: &Path) -> IoResult<()> {
+        inject(FaultPoint::S3).await?;
+        self.inner.put_object(file_name, path).await
+    }
+
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        inject(FaultPoint::S3).await?;
+        self.inner.put_stream(file_name, body, size).await
+    }
+}
@@ -0,0 +1,128 @@
+// server/src/storage/metered_storage_connector.rs
+use async_trait::async_trait;
+use bytes::Bytes;
+use rocket::futures::Stream;
+use std::io::Result as IoResult;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::chunk::ByteRange;
+use crate::costs::{current_tenant, CostLedger};
+use crate::storage::storage_connector::{
+    FetchedObject, ListRequest, ObjectInfo, ObjectListing, ObjectStream, StorageConnector,
+};
+
+/// Wraps a connector so that every request it sends, and the bytes it moves, are
+/// charged in a `CostLedger`. Only requests the backing store answered are counted.
+pub struct MeteredStorageConnector {
+    inner: Arc<dyn StorageConnector + Send + Sync>,
+    ledger: Arc<CostLedger>,
+}
+
+/// Bytes of a body read so far, charged when the body is dropped. The tenant is taken
+/// up front, as the body may be dropped outside the request that fetched it.
+struct BodyMeter {
+    ledger: Arc<CostLedger>,
+    tenant: Option<String>,
+    key: String,
+    bytes: u64,
+}
+
+impl Drop for BodyMeter {
+    fn drop(&mut self) {
+        let bytes = self.bytes;
+        self.ledger
+            .charge_to(self.tenant.as_deref(), &self.key, |traffic| {
+                traffic.bytes_fetched += bytes
+            });
+    }
+}
+
+/// A body counting the bytes read from it into its meter.
+struct MeteredBody {
+    inner: ObjectStream,
+    meter: BodyMeter,
+}
+
+impl Stream for MeteredBody {
+    type Item = IoResult<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let body = self.get_mut();
+        let chunk = body.inner.as_mut().poll_next(cx);
+        if let Poll::Ready(Some(Ok(bytes))) = &chunk {
+            body.meter.bytes += bytes.len() as u64;
+        }
+        chunk
+    }
+}
+
+impl MeteredStorageConnector {
+    pub fn new(inner: Arc<dyn StorageConnector + Send + Sync>, ledger: Arc<CostLedger>) -> Self {
+        Self { inner, ledger }
+    }
+
+    fn meter_body(&self, key: &str, object: FetchedObject) -> FetchedObject {
+        self.ledger.charge(key, |traffic| traffic.gets += 1);
+        let meter = BodyMeter {
+            ledger: self.ledger.clone(),
+            tenant: current_tenant(),
+            key: key.to_string(),
+            bytes: 0,
+        };
+        FetchedObject {
+            stream: Box::pin(MeteredBody {
+                inner: object.stream,
+                meter,
+            }),
+            ..object
+        }
+    }
+}
+
+#[async_trait]
+impl StorageConnector for MeteredStorageConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let object = self.inner.fetch_stream(file_name).await?;
+        Ok(self.meter_body(file_name, object))
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        let object = self.inner.fetch_range(file_name, range).await?;
+        Ok(self.meter_body(file_name, object))
+    }
+
+    async fn head_object(&self, file_name: &str) -> IoResult<ObjectInfo> {
+        let info = self.inner.head_object(file_name).await?;
+        self.ledger.charge(file_name, |traffic| traffic.heads += 1);
+        Ok(info)
+    }
+
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        let listing = self.inner.list_objects(request).await?;
+        self.ledger
+            .charge(&request.prefix, |traffic| traffic.lists += 1);
+        Ok(listing)
+    }
+
+    async fn put_object(&self, file_name: &str, path: &Path) -> IoResult<()> {
+        self.inner.put_object(file_name, path).await?;
+        let size = tokio::fs::metadata(path).await.map_or(0, |m| m.len());
+        self.ledger.charge(file_name, |traffic| {
+            traffic.puts += 1;
+            traffic.bytes_uploaded += size;
+        });
+        Ok(())
+    }
+
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        self.inner.put_stream(file_name, body, size).await?;
+        self.ledger.charge(file_name, |traffic| {
+            traffic.puts += 1;
+            traffic.bytes_uploaded += size;
+        });
+        Ok(())
+    }
+}
@@ -1,57 +1,228 @@
-use super::storage_connector::StorageConnector;
+use super::storage_connector::{FetchedObject, ObjectStream, ObjectVersion, StorageConnector};
+use super::url_storage_connector::{fetch_http, io_error_from_reqwest};
+use crate::chunk::ByteRange;
+use crate::rebalance::Throttle;
+use crate::util::hash;
+use crate::versioning::split_version;
 use async_trait::async_trait;
-use reqwest::{self, Error as ReqwestError};
-use rocket::futures::StreamExt;
+use bytes::Bytes;
+use rocket::futures::{stream, StreamExt};
+use serde::Deserialize;
 use std::io;
 use std::io::Result as IoResult;
-use std::path::{Path, PathBuf};
-use tokio::{fs::File, io::AsyncWriteExt};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_util::io::ReaderStream;
+
+/// Bytes of a generated object produced at a time.
+const GENERATED_CHUNK: u64 = 64 * 1024;
+
+/// How the mock imitates S3 rather than a server on loopback, for local benchmarks.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockS3Config {
+    /// Delay before every fetch returns its first byte.
+    pub first_byte_latency_ms: u64,
+    /// Cap on how fast each response body is read, like S3's per-connection throughput.
+    pub bytes_per_sec: Option<u64>,
+    /// Chance, from 0 to 1, that a fetch fails.
+    pub error_rate: f64,
+    /// Objects made up on the fly instead of fetched from the endpoint.
+    pub generate: Option<GeneratedObjects>,
+}
+
+/// Every key names an object whose size is drawn log-uniformly between `min_size` and
+/// `max_size` from a hash of the key, so it is the same on every fetch and every node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratedObjects {
+    pub min_size: u64,
+    pub max_size: u64,
+}
+
+impl FromStr for GeneratedObjects {
+    type Err = String;
+
+    /// Parses `<min size>-<max size>` in bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected <min size>-<max size>, got '{}'", s))?;
+        let parse = |v: &str| v.trim().parse::<u64>().map_err(|e| e.to_string());
+        Ok(GeneratedObjects {
+            min_size: parse(min)?,
+            max_size: parse(max)?,
+        })
+    }
+}
+
+impl GeneratedObjects {
+    pub fn size_of(&self, key: &str) -> u64 {
+        let draw = (hash(&key.to_string()) as u64 >> 11) as f64 / (1u64 << 53) as f64;
+        let (min, max) = (self.min_size.max(1) as f64, self.max_size as f64);
+        let size = (min.ln() + draw * (max.ln() - min.ln())).exp() as u64;
+        size.clamp(self.min_size, self.max_size)
+    }
+
+    /// Byte `offset` of the object whose key hashes to `seed`.
+    fn byte(seed: u64, offset: u64) -> u8 {
+        (seed.wrapping_add(offset.wrapping_mul(0x9e37_79b9)) >> 24) as u8
+    }
+
+    fn fetch(&self, key: &str, range: Option<ByteRange>) -> IoResult<FetchedObject> {
+        let size = self.size_of(key);
+        let (start, end) = match range {
+            Some(range) => range.resolve(size).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Requested range not satisfiable",
+                )
+            })?,
+            None => (0, size.saturating_sub(1)),
+        };
+        let length = if size == 0 { 0 } else { end - start + 1 };
+        let seed = hash(&key.to_string()) as u64;
+        let chunks = (start..start + length)
+            .step_by(GENERATED_CHUNK as usize)
+            .map(move |from| {
+                let to = (from + GENERATED_CHUNK).min(start + length);
+                Ok::<Bytes, io::Error>((from..to).map(|offset| Self::byte(seed, offset)).collect())
+            });
+        Ok(FetchedObject {
+            stream: Box::pin(stream::iter(chunks)),
+            content_length: Some(length),
+            object_size: Some(size),
+            version: ObjectVersion {
+                e_tag: Some(format!("\"{:016x}\"", seed)),
+                last_modified: None,
+            },
+        })
+    }
+}
+
 pub struct MockS3StorageConnector {
     s3_endpoint: String,
+    client: reqwest::Client,
+    config: MockS3Config,
+    /// State of the generator deciding which fetches fail.
+    seed: AtomicU64,
 }
 
 impl MockS3StorageConnector {
     pub fn new(s3_endpoint: String) -> Self {
-        Self { s3_endpoint }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            s3_endpoint,
+            client: reqwest::Client::new(),
+            config: MockS3Config::default(),
+            seed: AtomicU64::new(now.as_nanos() as u64),
+        }
+    }
+
+    /// Adds S3's latency, bandwidth and errors, or generates the objects.
+    pub fn with_config(mut self, config: MockS3Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// A uniform draw from [0, 1), from a splitmix64 sequence.
+    fn draw(&self) -> f64 {
+        const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut z = self
+            .seed
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    async fn get(&self, file_name: &str, range: Option<ByteRange>) -> IoResult<FetchedObject> {
+        if self.config.first_byte_latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(self.config.first_byte_latency_ms)).await;
+        }
+        if self.config.error_rate > 0.0 && self.draw() < self.config.error_rate {
+            return Err(io::Error::other("mock S3 failed the request (SlowDown)"));
+        }
+        let (object_key, version_id) = split_version(file_name);
+        let mut object = match &self.config.generate {
+            Some(generated) => generated.fetch(object_key, range)?,
+            None => {
+                let s3_file_url = format!("{}/{}", self.s3_endpoint, object_key);
+                let mut request = self.client.get(&s3_file_url);
+                if let Some(version_id) = version_id {
+                    request = request.query(&[("versionId", version_id)]);
+                }
+                fetch_http(request, range).await?
+            }
+        };
+        if let Some(bytes_per_sec) = self.config.bytes_per_sec {
+            object.stream = paced(object.stream, bytes_per_sec);
+        }
+        Ok(object)
     }
 }
 
+/// `stream`, read no faster than `bytes_per_sec`.
+fn paced(stream: ObjectStream, bytes_per_sec: u64) -> ObjectStream {
+    let throttle = Throttle::new(bytes_per_sec);
+    Box::pin(stream::unfold(
+        (stream, throttle),
+        |(mut stream, mut throttle)| async move {
+            let chunk = stream.next().await?;
+            if let Ok(bytes) = &chunk {
+                tokio::time::sleep(throttle.delay(bytes.len() as u64)).await;
+            }
+            Some((chunk, (stream, throttle)))
+        },
+    ))
+}
+
 #[async_trait]
 impl StorageConnector for MockS3StorageConnector {
-    async fn fetch_and_cache_file(
-        &self,
-        file_name: &str,
-        cache_path: &PathBuf,
-    ) -> IoResult<(PathBuf, u64)> {
-        let s3_file_url = format!("{}/{}", self.s3_endpoint, file_name);
-        let response = reqwest::get(&s3_file_url)
-            .await
-            .map_err(|e| io_error_from_reqwest(e))?;
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        self.get(file_name, None).await
+    }
 
-        if !response.status().is_success() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Failed to fetch file with status: {}", response.status()),
-            ));
-        }
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        self.get(file_name, Some(range)).await
+    }
 
-        let cache_file_path = cache_path.join(file_name);
-        let mut file = File::create(&cache_file_path).await?;
-        let mut file_size = 0u64;
-        // Stream the response body directly to the file
-        let mut stream = response.bytes_stream();
-        while let Some(chunk) = stream.next().await {
-            let data = chunk.map_err(|e| io_error_from_reqwest(e))?;
-            file_size += data.len() as u64;
-            file.write_all(&data).await?;
+    /// Generated objects stay what they are; uploads to them are accepted and dropped.
+    async fn put_object(&self, file_name: &str, path: &Path) -> IoResult<()> {
+        if self.config.generate.is_some() {
+            return Ok(());
         }
-        file.flush().await?;
-
-        Ok((Path::new("").join(file_name), file_size))
+        let file = tokio::fs::File::open(path).await?;
+        let size = file.metadata().await?.len();
+        self.put_stream(file_name, Box::pin(ReaderStream::new(file)), size)
+            .await
     }
-}
 
-// Helper function to map a `reqwest::Error` to `std::io::Error`
-fn io_error_from_reqwest(e: ReqwestError) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, e.to_string())
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        if self.config.generate.is_some() {
+            return Ok(());
+        }
+        let s3_file_url = format!("{}/{}", self.s3_endpoint, file_name);
+        let response = self
+            .client
+            .put(&s3_file_url)
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .body(reqwest::Body::wrap_stream(body))
+            .send()
+            .await
+            .map_err(io_error_from_reqwest)?;
+        if !response.status().is_success() {
+            return Err(io::Error::other(format!(
+                "Failed to upload file with status: {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
 }
@@ -1,4 +1,11 @@
 // server/src/storage/mod.rs
+#[cfg(feature = "fault-injection")]
+pub mod faulty_storage_connector;
+pub mod metered_storage_connector;
 pub mod mock_storage_connector;
+pub mod routing_storage_connector;
 pub mod s3_storage_connector;
 pub mod storage_connector;
+pub mod throttled_storage_connector;
+pub mod timeout_storage_connector;
+pub mod url_storage_connector;
@@ -0,0 +1,135 @@
+// server/src/storage/routing_storage_connector.rs
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::io::{self, Result as IoResult};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::chunk::ByteRange;
+use crate::storage::storage_connector::{
+    FetchedObject, ListRequest, ObjectInfo, ObjectListing, ObjectStream, StorageConnector,
+};
+
+/// A bucket (or mock endpoint) serving the keys under `prefix`, e.g. in TOML
+/// `[[backends]] prefix = "gold/" bucket = "lake-gold"`. Region and credentials left
+/// out are taken from the top-level settings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendConfig {
+    pub prefix: String,
+    /// Fetch `gold/a.parquet` as `a.parquet` from the backend.
+    #[serde(default)]
+    pub strip_prefix: bool,
+    pub bucket: Option<String>,
+    pub region_name: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub use_mock_s3_endpoint: Option<String>,
+}
+
+/// One entry of the routing table.
+pub struct Route {
+    pub prefix: String,
+    pub strip_prefix: bool,
+    pub connector: Arc<dyn StorageConnector + Send + Sync>,
+}
+
+/// Sends each key to the connector of the longest matching route prefix, or to the
+/// default connector when no route matches.
+pub struct RoutingStorageConnector {
+    routes: Vec<Route>,
+    default: Option<Arc<dyn StorageConnector + Send + Sync>>,
+}
+
+impl RoutingStorageConnector {
+    pub fn new(
+        mut routes: Vec<Route>,
+        default: Option<Arc<dyn StorageConnector + Send + Sync>>,
+    ) -> Self {
+        routes.sort_by_key(|route| std::cmp::Reverse(route.prefix.len()));
+        Self { routes, default }
+    }
+
+    /// The connector for `file_name` and the key to ask it for.
+    fn route<'a>(
+        &'a self,
+        file_name: &'a str,
+    ) -> IoResult<(&'a Arc<dyn StorageConnector + Send + Sync>, &'a str)> {
+        if let Some(route) = self
+            .routes
+            .iter()
+            .find(|route| file_name.starts_with(&route.prefix))
+        {
+            let key = if route.strip_prefix {
+                &file_name[route.prefix.len()..]
+            } else {
+                file_name
+            };
+            return Ok((&route.connector, key));
+        }
+        match &self.default {
+            Some(connector) => Ok((connector, file_name)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no backend configured for {}", file_name),
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageConnector for RoutingStorageConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let (connector, key) = self.route(file_name)?;
+        connector.fetch_stream(key).await
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        let (connector, key) = self.route(file_name)?;
+        connector.fetch_range(key, range).await
+    }
+
+    async fn head_object(&self, file_name: &str) -> IoResult<ObjectInfo> {
+        let (connector, key) = self.route(file_name)?;
+        let mut info = connector.head_object(key).await?;
+        info.key = file_name.to_string();
+        Ok(info)
+    }
+
+    async fn put_object(&self, file_name: &str, path: &Path) -> IoResult<()> {
+        let (connector, key) = self.route(file_name)?;
+        connector.put_object(key, path).await
+    }
+
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        let (connector, key) = self.route(file_name)?;
+        connector.put_stream(key, body, size).await
+    }
+
+    /// Lists through the backend owning `request.prefix`. A prefix spanning several
+    /// backends (e.g. the empty one) only lists the default backend.
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        let (connector, prefix) = self.route(&request.prefix)?;
+        // What the backend's keys lack compared to cache keys.
+        let stripped = &request.prefix[..request.prefix.len() - prefix.len()];
+        if stripped.is_empty() {
+            return connector.list_objects(request).await;
+        }
+        let inner = ListRequest {
+            prefix: prefix.to_string(),
+            start_after: request
+                .start_after
+                .as_deref()
+                .map(|key| key.strip_prefix(stripped).unwrap_or(key).to_string()),
+            ..request.clone()
+        };
+        let mut listing = connector.list_objects(&inner).await?;
+        for object in &mut listing.objects {
+            object.key.insert_str(0, stripped);
+        }
+        for common_prefix in &mut listing.common_prefixes {
+            common_prefix.insert_str(0, stripped);
+        }
+        Ok(listing)
+    }
+}
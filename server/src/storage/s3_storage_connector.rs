@@ -1,15 +1,116 @@
 use async_trait::async_trait;
-use aws_sdk_s3::{Client, Config, Credentials, Region};
-use log::debug;
-use rocket::futures::StreamExt;
+use aws_sdk_s3::model::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::{ByteStream, Client, Config, Credentials, Region};
+use bytes::Bytes;
+use log::{debug, warn};
+use rocket::futures::{stream, Stream, StreamExt, TryStreamExt};
+use std::convert::TryFrom;
 use std::io;
-use std::io::Result as IoResult;
-use std::path::{Path, PathBuf};
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use std::io::{Result as IoResult, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::time::Instant;
 
-use super::storage_connector::StorageConnector;
+use super::storage_connector::{
+    read_stream_to_end, FetchedObject, ListRequest, ObjectInfo, ObjectListing, ObjectStream,
+    ObjectVersion, StorageConnector,
+};
+use crate::chunk::{total_from_content_range, ByteRange};
+use crate::versioning::split_version;
+
+/// Objects at least this large are uploaded in parts.
+pub const MULTIPART_THRESHOLD: u64 = 64 << 20;
+/// Parts are this large unless the object needs more than `MAX_PARTS` of them.
+pub const PART_SIZE: u64 = 16 << 20;
+/// The most parts S3 takes for one object.
+pub const MAX_PARTS: u64 = 10_000;
+/// Parts of one object uploaded at the same time.
+const PART_CONCURRENCY: usize = 4;
+
+/// A slice of an object uploaded as one part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Part {
+    /// 1-based, as S3 numbers them.
+    pub number: i32,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Splits an object of `size` bytes into parts of `PART_SIZE`, or larger ones if
+/// `PART_SIZE` would take more than `MAX_PARTS`. The last part holds the remainder.
+pub fn plan_parts(size: u64) -> Vec<Part> {
+    let part_size = PART_SIZE.max(size.div_ceil(MAX_PARTS));
+    (0..size.div_ceil(part_size))
+        .map(|i| Part {
+            number: i as i32 + 1,
+            offset: i * part_size,
+            len: part_size.min(size - i * part_size),
+        })
+        .collect()
+}
+
+async fn read_part(path: &Path, part: Part) -> IoResult<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(part.offset)).await?;
+    let mut buf = vec![0; part.len as usize];
+    file.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// The parts of the file at `path`, read as they are uploaded.
+fn file_parts(path: &Path, size: u64) -> PartStream<'_> {
+    Box::pin(
+        stream::iter(plan_parts(size))
+            .then(move |part| async move { read_part(path, part).await.map(|buf| (part, buf)) }),
+    )
+}
+
+/// Cuts the `size` bytes of `body` into the parts `plan_parts` lays out. Fails if the
+/// body is shorter or longer than `size`.
+pub fn stream_parts(body: ObjectStream, size: u64) -> PartStream<'static> {
+    let parts = plan_parts(size).into_iter();
+    Box::pin(stream::try_unfold(
+        (body, parts, Bytes::new()),
+        move |(mut body, mut parts, mut rest)| async move {
+            let part = match parts.next() {
+                Some(part) => part,
+                None => loop {
+                    if !rest.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("body is longer than {} bytes", size),
+                        ));
+                    }
+                    match body.next().await {
+                        Some(chunk) => rest = chunk?,
+                        None => return Ok(None),
+                    }
+                },
+            };
+            let mut buf = Vec::with_capacity(part.len as usize);
+            while (buf.len() as u64) < part.len {
+                if rest.is_empty() {
+                    rest = match body.next().await {
+                        Some(chunk) => chunk?,
+                        None => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                format!("body is shorter than {} bytes", size),
+                            ))
+                        }
+                    };
+                }
+                let take = rest.len().min(part.len as usize - buf.len());
+                buf.extend_from_slice(&rest.split_to(take));
+            }
+            Ok(Some(((part, buf), (body, parts, rest))))
+        },
+    ))
+}
+
+/// Parts of an object with their contents, in order.
+pub type PartStream<'a> = Pin<Box<dyn Stream<Item = IoResult<(Part, Vec<u8>)>> + Send + 'a>>;
 
 pub struct S3StorageConnector {
     client: Client,
@@ -37,52 +138,59 @@ impl S3StorageConnector {
             bucket,
         }
     }
-}
 
-#[async_trait]
-impl StorageConnector for S3StorageConnector {
-    async fn fetch_and_cache_file(
+    async fn get_object(
         &self,
         file_name: &str,
-        cache_path: &PathBuf,
-    ) -> IoResult<(PathBuf, u64)> {
+        range: Option<ByteRange>,
+    ) -> IoResult<FetchedObject> {
         debug!(
             "Fetching object '{}' from S3 bucket '{}'",
             file_name, self.bucket
         );
-        // Assemble the object key with the file name
-        let object_key = file_name;
+        // Pinned versions are requested by their version ID
+        let (object_key, version_id) = split_version(file_name);
         let start = Instant::now();
         // Attempt to fetch the object from S3
-        let result = self
+        let mut request = self
             .client
             .get_object()
             .bucket(&self.bucket)
             .key(object_key)
-            .send()
-            .await;
+            .set_version_id(version_id.map(String::from));
+        if let Some(range) = range {
+            request = request.range(range.to_string());
+        }
+        let result = request.send().await;
 
         // Handle the case where the object does not exist
         match result {
             Ok(resp) => {
-                let cache_file_path = cache_path.join(file_name);
-                let mut file = File::create(&cache_file_path).await?;
-                let mut file_size = 0u64;
-                let mut stream = resp.body;
-                while let Some(chunk) = stream.next().await {
-                    let data =
-                        chunk.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-                    file_size += data.len() as u64;
-                    file.write_all(&data).await?;
-                }
-                file.flush().await?;
-                let duration = start.elapsed();
-
                 debug!(
-                    "Object '{}' fetched and cached successfully with size: {} bytes in {:?}",
-                    file_name, file_size, duration
+                    "Object '{}' opened for streaming in {:?}",
+                    file_name,
+                    start.elapsed()
                 );
-                Ok((Path::new("").join(file_name), file_size))
+                let content_length = u64::try_from(resp.content_length).ok();
+                let object_size = match range {
+                    Some(_) => resp
+                        .content_range
+                        .as_deref()
+                        .and_then(total_from_content_range),
+                    None => content_length,
+                };
+                Ok(FetchedObject {
+                    content_length,
+                    object_size,
+                    version: ObjectVersion {
+                        e_tag: resp.e_tag,
+                        last_modified: resp.last_modified.map(|t| t.secs()),
+                    },
+                    stream: Box::pin(
+                        resp.body
+                            .map(|chunk| chunk.map_err(|e| io::Error::other(e.to_string()))),
+                    ),
+                })
             }
             Err(aws_sdk_s3::SdkError::ServiceError { err, .. }) => {
                 match err.kind {
@@ -93,19 +201,251 @@ impl StorageConnector for S3StorageConnector {
                             "Object not found in S3",
                         ))
                     }
+                    _ if err.code() == Some("InvalidRange") => Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Requested range not satisfiable",
+                    )),
                     _ => {
                         // Handle other service errors
-                        Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!("Service error: {}", err),
-                        ))
+                        Err(io::Error::other(format!("Service error: {}", err)))
                     }
                 }
             }
             Err(e) => {
                 // Handle non-service errors
-                Err(io::Error::new(io::ErrorKind::Other, e.to_string()))
+                Err(io::Error::other(e.to_string()))
+            }
+        }
+    }
+
+    /// Uploads `parts` as `file_name`, `PART_CONCURRENCY` at a time. A failed upload is
+    /// aborted so S3 doesn't keep (and bill for) the parts already sent.
+    async fn put_multipart(&self, file_name: &str, parts: PartStream<'_>) -> IoResult<()> {
+        let start = Instant::now();
+        let upload_id = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(file_name)
+            .send()
+            .await
+            .map_err(|e| io::Error::other(e.to_string()))?
+            .upload_id
+            .ok_or_else(|| io::Error::other("S3 returned no upload ID"))?;
+        let upload_id = upload_id.as_str();
+        let mut part_count = 0;
+        let uploaded = parts
+            .map(|part| async move {
+                let (part, body) = part?;
+                self.upload_part(file_name, upload_id, part, body).await
+            })
+            .buffer_unordered(PART_CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await;
+        let completed = match uploaded {
+            Ok(mut completed) => {
+                part_count = completed.len();
+                completed.sort_by_key(|part| part.part_number);
+                self.client
+                    .complete_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(file_name)
+                    .upload_id(upload_id)
+                    .multipart_upload(
+                        CompletedMultipartUpload::builder()
+                            .set_parts(Some(completed))
+                            .build(),
+                    )
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| io::Error::other(e.to_string()))
+            }
+            Err(e) => Err(e),
+        };
+        match completed {
+            Ok(()) => {
+                debug!(
+                    "Uploaded '{}' in {} parts in {:?}",
+                    file_name,
+                    part_count,
+                    start.elapsed()
+                );
+                Ok(())
+            }
+            Err(e) => {
+                let aborted = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(file_name)
+                    .upload_id(upload_id)
+                    .send()
+                    .await;
+                if let Err(abort) = aborted {
+                    warn!(
+                        "Failed to abort the multipart upload of '{}' ({}): {}",
+                        file_name, upload_id, abort
+                    );
+                }
+                Err(e)
             }
         }
     }
+
+    async fn put_body(&self, file_name: &str, body: ByteStream) -> IoResult<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(file_name)
+            .body(body)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    async fn upload_part(
+        &self,
+        file_name: &str,
+        upload_id: &str,
+        part: Part,
+        body: Vec<u8>,
+    ) -> IoResult<CompletedPart> {
+        let resp = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(file_name)
+            .upload_id(upload_id)
+            .part_number(part.number)
+            .content_length(part.len as i64)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(CompletedPart::builder()
+            .set_e_tag(resp.e_tag)
+            .part_number(part.number)
+            .build())
+    }
+}
+
+#[async_trait]
+impl StorageConnector for S3StorageConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        self.get_object(file_name, None).await
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        self.get_object(file_name, Some(range)).await
+    }
+
+    async fn head_object(&self, file_name: &str) -> IoResult<ObjectInfo> {
+        let (object_key, version_id) = split_version(file_name);
+        let result = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(object_key)
+            .set_version_id(version_id.map(String::from))
+            .send()
+            .await;
+        match result {
+            Ok(resp) => Ok(ObjectInfo {
+                key: file_name.to_string(),
+                size: u64::try_from(resp.content_length).unwrap_or(0),
+                last_modified: resp.last_modified.map(|t| t.secs()),
+                e_tag: resp.e_tag,
+            }),
+            Err(aws_sdk_s3::SdkError::ServiceError { err, .. }) => match err.kind {
+                aws_sdk_s3::error::HeadObjectErrorKind::NotFound(_) => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "Object not found in S3",
+                )),
+                _ => Err(io::Error::other(format!("Service error: {}", err))),
+            },
+            Err(e) => Err(io::Error::other(e.to_string())),
+        }
+    }
+
+    async fn put_object(&self, file_name: &str, path: &Path) -> IoResult<()> {
+        debug!("Uploading '{}' to S3 bucket '{}'", file_name, self.bucket);
+        let size = tokio::fs::metadata(path).await?.len();
+        if size >= MULTIPART_THRESHOLD {
+            return self.put_multipart(file_name, file_parts(path, size)).await;
+        }
+        let body = ByteStream::from_path(path)
+            .await
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.put_body(file_name, body).await
+    }
+
+    /// Bodies below `MULTIPART_THRESHOLD` are buffered and sent in one PUT.
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        debug!("Uploading '{}' to S3 bucket '{}'", file_name, self.bucket);
+        if size >= MULTIPART_THRESHOLD {
+            return self
+                .put_multipart(file_name, stream_parts(body, size))
+                .await;
+        }
+        let data = read_stream_to_end(body).await?;
+        if data.len() as u64 != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("body is {} bytes, not {}", data.len(), size),
+            ));
+        }
+        self.put_body(file_name, ByteStream::from(data)).await
+    }
+
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        debug!(
+            "Listing '{}' in S3 bucket '{}'",
+            request.prefix, self.bucket
+        );
+        let mut call = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&request.prefix)
+            .max_keys(i32::try_from(request.max_keys).unwrap_or(i32::MAX));
+        if let Some(delimiter) = &request.delimiter {
+            call = call.delimiter(delimiter);
+        }
+        if let Some(token) = &request.continuation_token {
+            call = call.continuation_token(token);
+        }
+        if let Some(start_after) = &request.start_after {
+            call = call.start_after(start_after);
+        }
+        let resp = call
+            .send()
+            .await
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        let truncated = resp.is_truncated;
+        let next_continuation_token = resp.next_continuation_token.filter(|_| truncated);
+        Ok(ObjectListing {
+            objects: resp
+                .contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|object| {
+                    Some(ObjectInfo {
+                        key: object.key?,
+                        size: u64::try_from(object.size).unwrap_or(0),
+                        last_modified: object.last_modified.map(|t| t.secs()),
+                        e_tag: object.e_tag,
+                    })
+                })
+                .collect(),
+            common_prefixes: resp
+                .common_prefixes
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|prefix| prefix.prefix)
+                .collect(),
+            next_continuation_token,
+        })
+    }
 }
@@ -1,13 +1,178 @@
 // server/src/storage/storage_connector.rs
 use async_trait::async_trait;
-use std::io::Result as IoResult;
-use std::path::PathBuf;
+use bytes::Bytes;
+use rocket::futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use crate::chunk::ByteRange;
+
+/// A stream of object bytes coming straight from the backing store.
+pub type ObjectStream = Pin<Box<dyn Stream<Item = IoResult<Bytes>> + Send>>;
+
+/// An object opened on the backing store, along with what the store told us about it.
+pub struct FetchedObject {
+    pub stream: ObjectStream,
+    /// Length of this response body.
+    pub content_length: Option<u64>,
+    /// Size of the whole object, which differs from `content_length` for ranged fetches.
+    pub object_size: Option<u64>,
+    pub version: ObjectVersion,
+}
+
+/// What identifies a revision of an object, as reported by the backing store.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectVersion {
+    pub e_tag: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub last_modified: Option<i64>,
+}
+
+impl ObjectVersion {
+    /// Whether the store reported anything to tell revisions apart by.
+    pub fn is_known(&self) -> bool {
+        self.e_tag.is_some() || self.last_modified.is_some()
+    }
+
+    /// Whether `current` is another revision than this one, going by the ETags when both
+    /// are known and by the modification times otherwise.
+    pub fn superseded_by(&self, current: &ObjectVersion) -> bool {
+        match (&self.e_tag, &current.e_tag) {
+            (Some(held), Some(current)) => held != current,
+            _ => matches!(
+                (self.last_modified, current.last_modified),
+                (Some(held), Some(current)) if held != current
+            ),
+        }
+    }
+}
+
+/// Parameters of a ListObjectsV2 call.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ListRequest {
+    pub prefix: String,
+    pub delimiter: Option<String>,
+    pub continuation_token: Option<String>,
+    pub start_after: Option<String>,
+    pub max_keys: u32,
+}
+
+/// An object's metadata, as reported by a listing or a HEAD request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch.
+    pub last_modified: Option<i64>,
+    pub e_tag: Option<String>,
+}
+
+impl ObjectInfo {
+    pub fn version(&self) -> ObjectVersion {
+        ObjectVersion {
+            e_tag: self.e_tag.clone(),
+            last_modified: self.last_modified,
+        }
+    }
+}
+
+/// One page of a listing; more pages follow while `next_continuation_token` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectListing {
+    pub objects: Vec<ObjectInfo>,
+    pub common_prefixes: Vec<String>,
+    pub next_continuation_token: Option<String>,
+}
 
 #[async_trait]
 pub trait StorageConnector {
+    /// Opens the object as a byte stream without touching the local disk.
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject>;
+
+    /// Opens `range` of the object as a stream.
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject>;
+
+    /// Downloads the object into `cache_path` and returns its relative file name and size.
     async fn fetch_and_cache_file(
         &self,
         file_name: &str,
-        cache_path: &PathBuf,
-    ) -> IoResult<(PathBuf, u64)>;
+        cache_path: &Path,
+    ) -> IoResult<(PathBuf, u64)> {
+        let object = self.fetch_stream(file_name).await?;
+        write_stream_to_file(object.stream, file_name, cache_path).await
+    }
+
+    /// Size of `file_name`, probed with a one-byte ranged fetch. Stores that can report
+    /// the modification time and ETag should override this.
+    async fn head_object(&self, file_name: &str) -> IoResult<ObjectInfo> {
+        let size = match self.fetch_range(file_name, ByteRange::FromTo(0, 0)).await {
+            Ok(object) => object.object_size.unwrap_or(0),
+            // Only an empty object has no first byte.
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(ObjectInfo {
+            key: file_name.to_string(),
+            size,
+            last_modified: None,
+            e_tag: None,
+        })
+    }
+
+    /// Lists the objects under `request.prefix`, one page at a time.
+    async fn list_objects(&self, _request: &ListRequest) -> IoResult<ObjectListing> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this backing store cannot list objects",
+        ))
+    }
+
+    /// Uploads the file at `path` as the object `file_name`, replacing any object there.
+    async fn put_object(&self, _file_name: &str, _path: &Path) -> IoResult<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this backing store cannot store objects",
+        ))
+    }
+
+    /// Uploads the `size` bytes of `body` as the object `file_name`, replacing any object
+    /// there, without writing them to local disk first.
+    async fn put_stream(&self, _file_name: &str, _body: ObjectStream, _size: u64) -> IoResult<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this backing store cannot store objects",
+        ))
+    }
+}
+
+/// Drains `stream` into `cache_path/file_name`, returning the relative file name and size.
+pub async fn write_stream_to_file(
+    mut stream: ObjectStream,
+    file_name: &str,
+    cache_path: &Path,
+) -> IoResult<(PathBuf, u64)> {
+    let cache_file_path = cache_path.join(file_name);
+    // Every write to a tokio `File` is a round trip to the blocking pool, so batch them.
+    let mut file = BufWriter::new(File::create(&cache_file_path).await?);
+    let mut file_size = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let data = chunk?;
+        file_size += data.len() as u64;
+        file.write_all(&data).await?;
+    }
+    file.flush().await?;
+    Ok((Path::new("").join(file_name), file_size))
+}
+
+/// Collects a (small) stream into memory.
+pub async fn read_stream_to_end(mut stream: ObjectStream) -> IoResult<Vec<u8>> {
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    Ok(data)
 }
@@ -0,0 +1,213 @@
+// server/src/storage/throttled_storage_connector.rs
+use async_trait::async_trait;
+use rocket::futures::StreamExt;
+use std::io::{self, Result as IoResult};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::chunk::ByteRange;
+use crate::priority::{current_priority, RequestPriority};
+use crate::storage::storage_connector::{
+    FetchedObject, ListRequest, ObjectInfo, ObjectListing, ObjectStream, StorageConnector,
+};
+
+/// Caps the number of S3 fetches open at once across all connectors of a node. A fetch
+/// holds its permit until the response body has been fully consumed or dropped.
+///
+/// Interactive fetches queue for a slot in order. Batch fetches (see `priority`) only
+/// take a slot that no interactive fetch is queued for, and no more than `max_batch` of
+/// them are open at once.
+pub struct FetchLimiter {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    batch_slots: Arc<Semaphore>,
+    max_batch: usize,
+    /// Wakes batch fetches waiting for a slot when one is given back.
+    released: Arc<Notify>,
+    queue_timeout: Duration,
+    waiting: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl FetchLimiter {
+    pub fn new(max_concurrent: usize, queue_timeout: Duration) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            batch_slots: Arc::new(Semaphore::new(max_concurrent)),
+            max_batch: max_concurrent,
+            released: Arc::new(Notify::new()),
+            queue_timeout,
+            waiting: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Lets at most `max_batch` batch fetches hold a slot at once; all slots by default.
+    pub fn limiting_batch_to(mut self, max_batch: Option<usize>) -> Self {
+        if let Some(max_batch) = max_batch {
+            self.batch_slots = Arc::new(Semaphore::new(max_batch));
+            self.max_batch = max_batch;
+        }
+        self
+    }
+
+    /// Waits for a free fetch slot at the current request's priority, failing with
+    /// `TimedOut` after the queue timeout.
+    pub async fn acquire(&self) -> IoResult<FetchPermit> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let permit =
+            tokio::time::timeout(self.queue_timeout, self.acquire_as(current_priority())).await;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        match permit {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(closed)) => Err(io::Error::other(closed)),
+            Err(_) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "no S3 fetch slot became free within {:?}",
+                        self.queue_timeout
+                    ),
+                ))
+            }
+        }
+    }
+
+    async fn acquire_as(
+        &self,
+        priority: RequestPriority,
+    ) -> Result<FetchPermit, tokio::sync::AcquireError> {
+        if priority == RequestPriority::Interactive {
+            return Ok(FetchPermit {
+                slot: Some(self.semaphore.clone().acquire_owned().await?),
+                batch_slot: None,
+                released: self.released.clone(),
+            });
+        }
+        let batch_slot = self.batch_slots.clone().acquire_owned().await?;
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            // A slot given back goes to the interactive fetches queued for it first, so
+            // one is only free here when none are.
+            if let Ok(slot) = self.semaphore.clone().try_acquire_owned() {
+                return Ok(FetchPermit {
+                    slot: Some(slot),
+                    batch_slot: Some(batch_slot),
+                    released: self.released.clone(),
+                });
+            }
+            released.await;
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrent - self.semaphore.available_permits()
+    }
+
+    /// Batch fetches open or waiting for a free slot; at most `max_batch`.
+    pub fn batch_in_flight(&self) -> usize {
+        self.max_batch - self.batch_slots.available_permits()
+    }
+
+    /// Requests currently waiting for a fetch slot.
+    pub fn queue_depth(&self) -> u64 {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Requests that gave up after waiting for the whole queue timeout.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "S3 fetches: {} / {} in flight ({} batch), {} queued, {} rejected\n",
+            self.in_flight(),
+            self.max_concurrent,
+            self.batch_in_flight(),
+            self.queue_depth(),
+            self.rejected()
+        )
+    }
+}
+
+/// A fetch slot, given back when dropped.
+#[derive(Debug)]
+pub struct FetchPermit {
+    slot: Option<OwnedSemaphorePermit>,
+    batch_slot: Option<OwnedSemaphorePermit>,
+    released: Arc<Notify>,
+}
+
+impl Drop for FetchPermit {
+    fn drop(&mut self) {
+        // The slot goes back first, so that the batch fetches woken here can take it.
+        drop(self.slot.take());
+        drop(self.batch_slot.take());
+        self.released.notify_waiters();
+    }
+}
+
+/// Wraps a connector so that its fetches go through a shared `FetchLimiter`.
+pub struct ThrottledStorageConnector {
+    inner: Arc<dyn StorageConnector + Send + Sync>,
+    limiter: Arc<FetchLimiter>,
+}
+
+impl ThrottledStorageConnector {
+    pub fn new(inner: Arc<dyn StorageConnector + Send + Sync>, limiter: Arc<FetchLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+
+    fn hold_permit(object: FetchedObject, permit: FetchPermit) -> FetchedObject {
+        FetchedObject {
+            stream: Box::pin(object.stream.map(move |chunk| {
+                let _ = &permit;
+                chunk
+            })),
+            ..object
+        }
+    }
+}
+
+#[async_trait]
+impl StorageConnector for ThrottledStorageConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let permit = self.limiter.acquire().await?;
+        let object = self.inner.fetch_stream(file_name).await?;
+        Ok(Self::hold_permit(object, permit))
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        let permit = self.limiter.acquire().await?;
+        let object = self.inner.fetch_range(file_name, range).await?;
+        Ok(Self::hold_permit(object, permit))
+    }
+
+    async fn head_object(&self, file_name: &str) -> IoResult<ObjectInfo> {
+        let _permit = self.limiter.acquire().await?;
+        self.inner.head_object(file_name).await
+    }
+
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        let _permit = self.limiter.acquire().await?;
+        self.inner.list_objects(request).await
+    }
+
+    async fn put_object(&self, file_name: &str, path: &Path) -> IoResult<()> {
+        let _permit = self.limiter.acquire().await?;
+        self.inner.put_object(file_name, path).await
+    }
+
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        let _permit = self.limiter.acquire().await?;
+        self.inner.put_stream(file_name, body, size).await
+    }
+}
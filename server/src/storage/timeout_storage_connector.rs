@@ -0,0 +1,98 @@
+// server/src/storage/timeout_storage_connector.rs
+use async_trait::async_trait;
+use rocket::futures::{stream, StreamExt};
+use std::io::Result as IoResult;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::chunk::ByteRange;
+use crate::deadline::{bounded, timeout_error, Stage};
+use crate::storage::storage_connector::{
+    FetchedObject, ListRequest, ObjectInfo, ObjectListing, ObjectStream, StorageConnector,
+};
+
+/// Wraps a connector so that requests to the backing store give up after `timeout` or at
+/// the request's deadline. Bodies fail once no chunk arrived for `timeout`. Uploads run in
+/// the background and are not limited.
+pub struct TimeoutStorageConnector {
+    inner: Arc<dyn StorageConnector + Send + Sync>,
+    timeout: Option<Duration>,
+}
+
+impl TimeoutStorageConnector {
+    pub fn new(inner: Arc<dyn StorageConnector + Send + Sync>, timeout: Option<Duration>) -> Self {
+        Self { inner, timeout }
+    }
+
+    fn watch_body(&self, object: FetchedObject) -> FetchedObject {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return object,
+        };
+        let body: ObjectStream = Box::pin(stream::unfold(
+            Some(object.stream),
+            move |body| async move {
+                let mut body = body?;
+                match tokio::time::timeout(timeout, body.next()).await {
+                    Ok(chunk) => chunk.map(|chunk| (chunk, Some(body))),
+                    // Fail the body once and end it.
+                    Err(_) => Some((Err(timeout_error(Stage::S3Fetch, timeout, false)), None)),
+                }
+            },
+        ));
+        FetchedObject {
+            stream: body,
+            ..object
+        }
+    }
+}
+
+#[async_trait]
+impl StorageConnector for TimeoutStorageConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let object = bounded(
+            Stage::S3Fetch,
+            self.timeout,
+            self.inner.fetch_stream(file_name),
+        )
+        .await??;
+        Ok(self.watch_body(object))
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        let object = bounded(
+            Stage::S3Fetch,
+            self.timeout,
+            self.inner.fetch_range(file_name, range),
+        )
+        .await??;
+        Ok(self.watch_body(object))
+    }
+
+    async fn head_object(&self, file_name: &str) -> IoResult<ObjectInfo> {
+        bounded(
+            Stage::S3Fetch,
+            self.timeout,
+            self.inner.head_object(file_name),
+        )
+        .await?
+    }
+
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        bounded(
+            Stage::S3Fetch,
+            self.timeout,
+            self.inner.list_objects(request),
+        )
+        .await?
+    }
+
+    async fn put_object(&self, file_name: &str, path: &Path) -> IoResult<()> {
+        self.inner.put_object(file_name, path).await
+    }
+
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        self.inner.put_stream(file_name, body, size).await
+    }
+}
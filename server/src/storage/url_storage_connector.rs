@@ -0,0 +1,102 @@
+// server/src/storage/url_storage_connector.rs
+use super::storage_connector::{FetchedObject, ObjectVersion, StorageConnector};
+use crate::chunk::{total_from_content_range, ByteRange};
+use crate::conditional::parse_http_date;
+use async_trait::async_trait;
+use reqwest::header::{CONTENT_RANGE, ETAG, LAST_MODIFIED, RANGE};
+use reqwest::{RequestBuilder, StatusCode, Url};
+use rocket::futures::StreamExt;
+use std::io;
+use std::io::Result as IoResult;
+
+/// Reads a single object from a URL of its own, such as an S3 presigned URL, whatever
+/// key it is cached under.
+pub struct UrlStorageConnector {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl UrlStorageConnector {
+    pub fn new(client: reqwest::Client, url: Url) -> Self {
+        Self { client, url }
+    }
+}
+
+#[async_trait]
+impl StorageConnector for UrlStorageConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        fetch_http(self.client.get(self.url.clone()), None).await
+    }
+
+    async fn fetch_range(&self, _file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        fetch_http(self.client.get(self.url.clone()), Some(range)).await
+    }
+}
+
+/// Sends `request` for `range` of its body, or all of it, and opens the response.
+pub(crate) async fn fetch_http(
+    mut request: RequestBuilder,
+    range: Option<ByteRange>,
+) -> IoResult<FetchedObject> {
+    if let Some(range) = range {
+        request = request.header(RANGE, range.to_string());
+    }
+    let response = request.send().await.map_err(io_error_from_reqwest)?;
+
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Requested range not satisfiable",
+        ));
+    }
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", response.url().path()),
+        ));
+    }
+    if !response.status().is_success() {
+        return Err(io::Error::other(format!(
+            "Failed to fetch file with status: {}",
+            response.status()
+        )));
+    }
+    if range.is_some() && response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(io::Error::other("Backing store ignored the range request"));
+    }
+
+    let object_size = match range {
+        Some(_) => response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(total_from_content_range),
+        None => response.content_length(),
+    };
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let version = ObjectVersion {
+        e_tag: header(ETAG),
+        last_modified: header(LAST_MODIFIED).as_deref().and_then(parse_http_date),
+    };
+    Ok(FetchedObject {
+        content_length: response.content_length(),
+        object_size,
+        version,
+        stream: Box::pin(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(io_error_from_reqwest)),
+        ),
+    })
+}
+
+// Helper function to map a `reqwest::Error` to `std::io::Error`
+pub(crate) fn io_error_from_reqwest(e: reqwest::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
@@ -0,0 +1,491 @@
+// telemetry.rs
+use log::{info, warn};
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+/// W3C trace context header read from requests.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+/// Response header carrying the trace ID, so clients can quote it when reporting errors.
+pub const TRACE_ID_HEADER: &str = "X-Istziio-Trace-Id";
+
+/// Spans waiting to be exported; more are dropped until the collector catches up.
+const EXPORT_QUEUE_LEN: usize = 4096;
+/// Most spans sent to the collector in one request.
+const MAX_EXPORT_BATCH: usize = 512;
+/// How long a finished span waits for others to be exported with.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TracingConfig {
+    /// Spans that finish faster than this are not reported.
+    #[serde(default)]
+    pub min_span_ms: u64,
+    /// OpenTelemetry collector that also gets the spans of each trace, over OTLP/HTTP
+    /// (JSON), e.g. `http://collector:4318`.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` of the exported spans; `istziio` by default.
+    #[serde(default)]
+    pub service_name: Option<String>,
+}
+
+/// Trace a request belongs to: continued from its `traceparent` header, or a new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 hex digits.
+    pub trace_id: String,
+    /// Span of the caller that sent the request, 16 hex digits.
+    pub parent_id: Option<String>,
+}
+
+pub(crate) fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    // An all-zero ID is invalid; that is what we report if randomness is unavailable.
+    let _ = SystemRandom::new().fill(&mut buf);
+    hex::encode(buf)
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len
+        && s.bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Trace and span IDs are lowercase hex and never all zeros.
+fn is_hex_id(s: &str, len: usize) -> bool {
+    is_hex(s, len) && s.bytes().any(|b| b != b'0')
+}
+
+impl TraceContext {
+    pub fn new() -> Self {
+        Self {
+            trace_id: random_hex(16),
+            parent_id: None,
+        }
+    }
+
+    /// Parses `version-traceid-parentid-flags`. Returns `None` for malformed values.
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+        if !is_hex(version, 2)
+            || version == "ff"
+            || !is_hex_id(trace_id, 32)
+            || !is_hex_id(parent_id, 16)
+            || !is_hex(flags, 2)
+        {
+            return None;
+        }
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            parent_id: Some(parent_id.to_string()),
+        })
+    }
+}
+
+impl TraceContext {
+    /// The trace of `req`, as the request guard yields it.
+    pub fn of(req: &Request<'_>) -> Self {
+        // Cached so that the response fairing echoes the same trace.
+        req.local_cache(|| {
+            req.headers()
+                .get_one(TRACEPARENT_HEADER)
+                .and_then(TraceContext::from_traceparent)
+                .unwrap_or_default()
+        })
+        .clone()
+    }
+
+    /// `traceparent` for a request this node sends as part of the trace. The current span
+    /// is the parent when the span logger knows it; otherwise a new span ID stands in.
+    pub fn traceparent(&self) -> String {
+        let span_id = current_span_id(&self.trace_id).unwrap_or_else(|| random_hex(8));
+        format!("00-{}-{}-01", self.trace_id, span_id)
+    }
+}
+
+/// The exported ID of the current span, if it belongs to `trace_id`.
+fn current_span_id(trace_id: &str) -> Option<String> {
+    let id = tracing::Span::current().id()?;
+    tracing::dispatcher::get_default(|dispatch| {
+        let logger = dispatch.downcast_ref::<SpanLogger>()?;
+        let spans = logger.spans.lock().unwrap();
+        let span = spans.get(&id.into_u64())?;
+        (span.trace_id.as_deref() == Some(trace_id)).then(|| span.span_id.clone())
+    })
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TraceContext {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(TraceContext::of(req))
+    }
+}
+
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+    trace_id: Option<String>,
+    /// 16 hex digits, random so that spans of different nodes in one trace don't clash.
+    span_id: String,
+    /// Span ID of the parent: a local span, or the caller's for a span continuing a trace.
+    parent_id: Option<String>,
+    parent: Option<u64>,
+    started_at: SystemTime,
+    started: Instant,
+    refs: usize,
+}
+
+/// Collects `name=value` pairs and picks out the `trace_id` and `remote_parent` fields.
+struct FieldWriter<'a> {
+    fields: &'a mut Vec<(&'static str, String)>,
+    trace_id: &'a mut Option<String>,
+    remote_parent: &'a mut Option<String>,
+}
+
+impl FieldWriter<'_> {
+    fn write(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "trace_id" => *self.trace_id = Some(value),
+            "remote_parent" => *self.remote_parent = Some(value),
+            name => self.fields.push((name, value)),
+        }
+    }
+}
+
+impl Visit for FieldWriter<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if !value.is_empty() {
+            self.write(field, value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.write(field, format!("{:?}", value));
+    }
+}
+
+/// A finished span on its way to the collector.
+struct FinishedSpan {
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+    trace_id: String,
+    span_id: String,
+    parent_id: Option<String>,
+    started_at: SystemTime,
+    elapsed: Duration,
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// `spans` as an OTLP `ExportTraceServiceRequest` in its JSON encoding.
+fn otlp_request(service_name: &str, spans: &[FinishedSpan]) -> JsonValue {
+    let spans: Vec<JsonValue> = spans
+        .iter()
+        .map(|span| {
+            let attributes: Vec<JsonValue> = span
+                .fields
+                .iter()
+                .map(|(key, value)| json!({"key": key, "value": {"stringValue": value}}))
+                .collect();
+            json!({
+                "traceId": span.trace_id,
+                "spanId": span.span_id,
+                "parentSpanId": span.parent_id.as_deref().unwrap_or_default(),
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.started_at),
+                "endTimeUnixNano": unix_nanos(span.started_at + span.elapsed),
+                "attributes": attributes,
+            })
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": service_name}}],
+            },
+            "scopeSpans": [{"scope": {"name": "istziio"}, "spans": spans}],
+        }],
+    })
+}
+
+/// Sends spans to `endpoint` in batches until the span logger is dropped.
+async fn export_spans(
+    endpoint: String,
+    service_name: String,
+    mut spans: mpsc::Receiver<FinishedSpan>,
+) {
+    let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    let client = reqwest::Client::builder()
+        .timeout(EXPORT_TIMEOUT)
+        .build()
+        .unwrap();
+    let mut batch = Vec::new();
+    let mut open = true;
+    while open {
+        let flush = tokio::time::sleep(EXPORT_INTERVAL);
+        tokio::pin!(flush);
+        while batch.len() < MAX_EXPORT_BATCH {
+            tokio::select! {
+                span = spans.recv() => match span {
+                    Some(span) => batch.push(span),
+                    None => {
+                        open = false;
+                        break;
+                    }
+                },
+                _ = &mut flush => break,
+            }
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        let body = otlp_request(&service_name, &batch);
+        batch.clear();
+        let result = client.post(&url).json(&body).send().await;
+        match result.and_then(|response| response.error_for_status()) {
+            Ok(_) => {}
+            Err(e) => warn!("Exporting spans to {} failed: {}", url, e),
+        }
+    }
+}
+
+/// Starts exporting the spans sent to the returned queue on a thread of its own, so that
+/// exports neither wait for a runtime nor show up as spans themselves.
+fn start_exporter(endpoint: String, service_name: String) -> Option<mpsc::Sender<FinishedSpan>> {
+    let (sender, receiver) = mpsc::channel(EXPORT_QUEUE_LEN);
+    let spawned = std::thread::Builder::new()
+        .name(String::from("otlp-export"))
+        .spawn(move || {
+            let _quiet =
+                tracing::subscriber::set_default(tracing::subscriber::NoSubscriber::default());
+            match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime.block_on(export_spans(endpoint, service_name, receiver)),
+                Err(e) => warn!("Failed to start the span exporter: {}", e),
+            }
+        });
+    match spawned {
+        Ok(_) => Some(sender),
+        Err(e) => {
+            warn!("Failed to start the span exporter: {}", e);
+            None
+        }
+    }
+}
+
+thread_local! {
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Minimal `tracing` subscriber that reports every finished span as one log line with its
+/// trace ID, parent span and wall-clock duration. Events are left to `log`. With an OTLP
+/// endpoint, the spans of traces also go to that collector.
+pub struct SpanLogger {
+    min_duration: Duration,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    exporter: Option<mpsc::Sender<FinishedSpan>>,
+}
+
+impl SpanLogger {
+    pub fn new(config: TracingConfig) -> Self {
+        let service_name = config
+            .service_name
+            .unwrap_or_else(|| String::from("istziio"));
+        Self {
+            min_duration: Duration::from_millis(config.min_span_ms),
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+            exporter: config
+                .otlp_endpoint
+                .and_then(|endpoint| start_exporter(endpoint, service_name)),
+        }
+    }
+
+    /// Installs the logger as the process-wide subscriber; only the first call wins.
+    pub fn install(config: TracingConfig) {
+        if tracing::subscriber::set_global_default(Self::new(config)).is_err() {
+            log::debug!("A tracing subscriber is already installed");
+        }
+    }
+}
+
+impl Subscriber for SpanLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.is_span()
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let parent = if attrs.is_root() {
+            None
+        } else if let Some(parent) = attrs.parent() {
+            Some(parent.into_u64())
+        } else {
+            ENTERED.with(|entered| entered.borrow().last().copied())
+        };
+        let mut fields = Vec::new();
+        let mut trace_id = None;
+        let mut remote_parent = None;
+        attrs.record(&mut FieldWriter {
+            fields: &mut fields,
+            trace_id: &mut trace_id,
+            remote_parent: &mut remote_parent,
+        });
+        let mut spans = self.spans.lock().unwrap();
+        let local_parent = parent.and_then(|parent| spans.get(&parent));
+        if trace_id.is_none() {
+            trace_id = local_parent.and_then(|parent| parent.trace_id.clone());
+        }
+        let parent_id = local_parent
+            .map(|parent| parent.span_id.clone())
+            .or(remote_parent);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        spans.insert(
+            id,
+            SpanData {
+                metadata: attrs.metadata(),
+                name: attrs.metadata().name(),
+                fields,
+                trace_id,
+                span_id: random_hex(8),
+                parent_id,
+                parent,
+                started_at: SystemTime::now(),
+                started: Instant::now(),
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            let mut remote_parent = None;
+            values.record(&mut FieldWriter {
+                fields: &mut data.fields,
+                trace_id: &mut data.trace_id,
+                remote_parent: &mut remote_parent,
+            });
+            if data.parent.is_none() && remote_parent.is_some() {
+                data.parent_id = remote_parent;
+            }
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(pos) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(pos);
+            }
+        });
+    }
+
+    fn current_span(&self) -> Current {
+        let id = match ENTERED.with(|entered| entered.borrow().last().copied()) {
+            Some(id) => id,
+            None => return Current::none(),
+        };
+        match self.spans.lock().unwrap().get(&id) {
+            Some(data) => Current::new(Id::from_u64(id), data.metadata),
+            None => Current::none(),
+        }
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let data = match spans.get_mut(&span.into_u64()) {
+            Some(data) => data,
+            None => return false,
+        };
+        data.refs -= 1;
+        if data.refs > 0 {
+            return false;
+        }
+        let data = spans.remove(&span.into_u64()).unwrap();
+        drop(spans);
+        let elapsed = data.started.elapsed();
+        if elapsed < self.min_duration {
+            return true;
+        }
+        let mut fields = String::new();
+        for (name, value) in &data.fields {
+            let _ = write!(fields, " {}={}", name, value);
+        }
+        info!(
+            target: "trace",
+            "span={} trace_id={} span_id={} parent_id={} elapsed_ms={:.3}{}",
+            data.name,
+            data.trace_id.as_deref().unwrap_or("-"),
+            data.span_id,
+            data.parent_id.as_deref().unwrap_or("-"),
+            elapsed.as_secs_f64() * 1000.0,
+            fields
+        );
+        // Spans outside any trace, e.g. of libraries, are only logged.
+        if let (Some(exporter), Some(trace_id)) = (&self.exporter, data.trace_id) {
+            // A collector that falls behind loses spans rather than slowing requests down.
+            let _ = exporter.try_send(FinishedSpan {
+                name: data.name,
+                fields: data.fields,
+                trace_id,
+                span_id: data.span_id,
+                parent_id: data.parent_id,
+                started_at: data.started_at,
+                elapsed,
+            });
+        }
+        true
+    }
+}
@@ -0,0 +1,99 @@
+// tenant.rs
+use serde::Deserialize;
+
+use crate::auth::tokens_match;
+
+/// A team sharing the cache, e.g. in TOML
+/// `[[tenants]] name = "analytics" tokens = ["..."] prefixes = ["analytics/"]`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    pub name: String,
+    /// Bearer tokens identifying the tenant. They also grant read access.
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    /// Keys under these prefixes belong to the tenant when the request carries none of
+    /// its tokens.
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+    /// Bytes the tenant's cached entries may occupy on the node, split evenly between
+    /// shards. Unlimited when unset.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+/// All configured tenants, and the lookups that attribute requests to them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tenants {
+    tenants: Vec<TenantConfig>,
+}
+
+impl Tenants {
+    pub fn new(tenants: Vec<TenantConfig>) -> Self {
+        Self { tenants }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TenantConfig> {
+        self.tenants.iter()
+    }
+
+    /// The tenants with every budget divided between `shard_count` shards.
+    pub fn per_shard(&self, shard_count: u64) -> Self {
+        Self {
+            tenants: self
+                .tenants
+                .iter()
+                .map(|tenant| TenantConfig {
+                    max_bytes: tenant.max_bytes.map(|bytes| bytes / shard_count),
+                    ..tenant.clone()
+                })
+                .collect(),
+        }
+    }
+
+    pub fn by_token(&self, token: &str) -> Option<&str> {
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.tokens.iter().any(|t| tokens_match(token, t)))
+            .map(|tenant| tenant.name.as_str())
+    }
+
+    /// The tenant owning the longest prefix of `key`.
+    pub fn by_key(&self, key: &str) -> Option<&str> {
+        self.tenants
+            .iter()
+            .flat_map(|tenant| tenant.prefixes.iter().map(move |prefix| (prefix, tenant)))
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, tenant)| tenant.name.as_str())
+    }
+
+    /// The tenant a request for `key` is charged to: the one named by its token, else
+    /// the one owning the key's prefix.
+    pub fn resolve(&self, token_tenant: Option<&str>, key: &str) -> Option<String> {
+        token_tenant
+            .filter(|name| self.tenants.iter().any(|tenant| tenant.name == *name))
+            .or_else(|| self.by_key(key))
+            .map(String::from)
+    }
+
+    pub fn budget(&self, name: &str) -> Option<u64> {
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.name == name)
+            .and_then(|tenant| tenant.max_bytes)
+    }
+}
+
+/// Request counters of one tenant on one shard.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Logical bytes served to the tenant, from the cache or after a fetch.
+    pub bytes_served: u64,
+}
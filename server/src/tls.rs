@@ -0,0 +1,267 @@
+// tls.rs
+use hyper::client::HttpConnector;
+use hyper::header::HeaderValue;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Client, Request as HyperRequest, Response as HyperResponse, StatusCode, Uri};
+use log::{debug, info};
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Method, Status};
+use rocket::{get, routes, Build, Data, Request, Rocket};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Result as IoResult};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{
+    AllowAnyAuthenticatedClient, Certificate, NoClientAuth, PrivateKey, RootCertStore,
+    ServerConfig as RustlsServerConfig,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::auth::tokens_match;
+
+/// The plaintext Rocket listener sits this far above the public TLS port, bound to
+/// loopback only and serving only requests that come through the relay.
+pub const TLS_BACKEND_PORT_OFFSET: u16 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file with the server certificate chain.
+    pub certs: String,
+    /// PEM file with the server private key (PKCS#8, RSA or SEC1).
+    pub key: String,
+    /// PEM file with the CA that peer nodes and clients must present a certificate from.
+    /// Client certificates are not requested when unset.
+    pub client_ca: Option<String>,
+}
+
+fn invalid_data(path: &str, what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("no {} found in {}", what, path),
+    )
+}
+
+fn load_certs(path: &str) -> IoResult<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(Path::new(path))?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    if certs.is_empty() {
+        return Err(invalid_data(path, "certificate"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &str) -> IoResult<PrivateKey> {
+    let mut reader = BufReader::new(File::open(Path::new(path))?);
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+    Err(invalid_data(path, "private key"))
+}
+
+impl TlsConfig {
+    /// Loads the certificates and key, so that bad paths surface at startup.
+    pub fn acceptor(&self) -> IoResult<TlsAcceptor> {
+        Ok(TlsAcceptor::from(Arc::new(self.server_config()?)))
+    }
+
+    /// Like `acceptor`, negotiating HTTP/2 through ALPN as gRPC clients require.
+    pub fn grpc_acceptor(&self) -> IoResult<TlsAcceptor> {
+        let mut config = self.server_config()?;
+        config.set_protocols(&[b"h2".to_vec()]);
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    fn server_config(&self) -> IoResult<RustlsServerConfig> {
+        let verifier = match &self.client_ca {
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(ca_path)? {
+                    roots
+                        .add(&cert)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                }
+                AllowAnyAuthenticatedClient::new(roots)
+            }
+            None => NoClientAuth::new(),
+        };
+        let mut config = RustlsServerConfig::new(verifier);
+        config
+            .set_single_cert(load_certs(&self.certs)?, load_key(&self.key)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(config)
+    }
+
+    /// Builds a client for requests to peer nodes. With `client_ca` set, it presents this
+    /// node's certificate, whose key must then be PKCS#8, and trusts that CA as well.
+    pub fn peer_client(&self, builder: reqwest::ClientBuilder) -> IoResult<reqwest::Client> {
+        let invalid = |e: reqwest::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut builder = builder;
+        if let Some(ca_path) = &self.client_ca {
+            for cert in
+                reqwest::Certificate::from_pem_bundle(&fs::read(ca_path)?).map_err(invalid)?
+            {
+                builder = builder.add_root_certificate(cert);
+            }
+            let identity =
+                reqwest::Identity::from_pkcs8_pem(&fs::read(&self.certs)?, &fs::read(&self.key)?)
+                    .map_err(invalid)?;
+            builder = builder.identity(identity);
+        }
+        builder.build().map_err(invalid)
+    }
+}
+
+/// Header the relay passes each client's address in; Rocket's `ip_header` under TLS.
+pub const REAL_IP_HEADER: &str = "X-Real-IP";
+/// Header carrying the `RelayToken`.
+pub const RELAY_TOKEN_HEADER: &str = "X-Istziio-Relay";
+/// Where requests that did not come through the relay are sent to be refused.
+const UNRELAYED_PATH: &str = "/__unrelayed";
+
+/// Secret the relay presents with every request, so that the plaintext listener only
+/// serves what came in over TLS. Managed as Rocket state.
+#[derive(Clone)]
+pub struct RelayToken(pub String);
+
+impl RelayToken {
+    pub fn generate() -> IoResult<Self> {
+        let mut bytes = [0u8; 16];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| io::Error::other("no randomness available"))?;
+        Ok(Self(hex::encode(bytes)))
+    }
+}
+
+/// Refuses requests to the plaintext listener that lack the managed `RelayToken`, even
+/// from loopback.
+pub struct RelayOnly;
+
+#[rocket::async_trait]
+impl Fairing for RelayOnly {
+    fn info(&self) -> Info {
+        Info {
+            name: "Relayed requests only",
+            kind: Kind::Ignite | Kind::Request,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket.mount("/", routes![unrelayed]))
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let relayed = match (
+            req.rocket().state::<RelayToken>(),
+            req.headers().get_one(RELAY_TOKEN_HEADER),
+        ) {
+            (Some(expected), Some(given)) => tokens_match(given, &expected.0),
+            _ => false,
+        };
+        if !relayed {
+            req.set_method(Method::Get);
+            req.set_uri(Origin::parse(UNRELAYED_PATH).unwrap());
+        }
+    }
+}
+
+#[get("/__unrelayed")]
+fn unrelayed() -> Status {
+    Status::Forbidden
+}
+
+/// Terminates TLS on `public` and relays each request to the plaintext listener on
+/// `backend`, with the client's address in `REAL_IP_HEADER` and `token` in
+/// `RELAY_TOKEN_HEADER`. Runs until the listener fails to bind.
+pub async fn serve_tls(
+    acceptor: TlsAcceptor,
+    public: String,
+    backend: SocketAddr,
+    token: RelayToken,
+) {
+    let listener = match TcpListener::bind(&public).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Failed to bind TLS listener on {}: {}", public, e);
+            return;
+        }
+    };
+    info!("TLS listener on {} relaying to {}", public, backend);
+    let client = Client::new();
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                debug!("TLS accept failed: {}", e);
+                continue;
+            }
+        };
+        let (acceptor, client, token) = (acceptor.clone(), client.clone(), token.clone());
+        tokio::spawn(async move {
+            let tls_stream = match acceptor.accept(stream).await {
+                Ok(tls_stream) => tls_stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let service = service_fn(move |request| {
+                relay(client.clone(), backend, peer, token.clone(), request)
+            });
+            if let Err(e) = Http::new().serve_connection(tls_stream, service).await {
+                debug!("TLS connection with {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn relay(
+    client: Client<HttpConnector>,
+    backend: SocketAddr,
+    peer: SocketAddr,
+    token: RelayToken,
+    mut request: HyperRequest<Body>,
+) -> Result<HyperResponse<Body>, Infallible> {
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str());
+    let uri = match format!("http://{}{}", backend, path).parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(_) => return Ok(status_response(StatusCode::BAD_REQUEST)),
+    };
+    *request.uri_mut() = uri;
+    // Whatever the client sent in these headers is replaced.
+    let headers = request.headers_mut();
+    headers.insert(
+        REAL_IP_HEADER,
+        HeaderValue::from_str(&peer.ip().to_string()).unwrap(),
+    );
+    headers.insert(RELAY_TOKEN_HEADER, HeaderValue::from_str(&token.0).unwrap());
+    match client.request(request).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            debug!("Failed to reach backend {}: {}", backend, e);
+            Ok(status_response(StatusCode::BAD_GATEWAY))
+        }
+    }
+}
+
+fn status_response(status: StatusCode) -> HyperResponse<Body> {
+    let mut response = HyperResponse::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,525 @@
+// uring.rs
+//! A minimal io_uring driver: one thread owns a ring and runs the reads and writes that
+//! the async side sends it, many at a time, with one `io_uring_enter` per batch instead
+//! of a syscall (and a blocking-pool hop) per read or write.
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, Result as IoResult, SeekFrom};
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::sync::oneshot;
+
+/// Submission queue entries; the ring never has more operations than this in flight.
+const ENTRIES: u32 = 256;
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A mapped region of the ring, unmapped on drop.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: i32, len: usize, offset: i64) -> IoResult<Self> {
+        // SAFETY: a fresh shared mapping of the ring fd; the kernel checks len and offset.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    /// The `u32` the kernel shares at byte `offset`.
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        // SAFETY: offsets come from the kernel and point at aligned u32s inside the mapping.
+        unsafe { &*(self.ptr.add(offset as usize) as *const AtomicU32) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: ptr and len are exactly what mmap returned.
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/// The ring itself, owned by the driver thread.
+struct RawRing {
+    fd: i32,
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    params: Params,
+}
+
+// SAFETY: the mappings are only touched by the thread that owns the ring.
+unsafe impl Send for RawRing {}
+
+impl RawRing {
+    fn new(entries: u32) -> IoResult<Self> {
+        let mut params = Params::default();
+        // SAFETY: params is a valid io_uring_params the kernel fills in.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as i32;
+        let mapped = (|| {
+            let sq_len = params.sq_off.array as usize
+                + params.sq_entries as usize * std::mem::size_of::<u32>();
+            let cq_len = params.cq_off.cqes as usize
+                + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+            let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+            Ok::<_, io::Error>((
+                Mapping::new(fd, sq_len, IORING_OFF_SQ_RING)?,
+                Mapping::new(fd, cq_len, IORING_OFF_CQ_RING)?,
+                Mapping::new(fd, sqes_len, IORING_OFF_SQES)?,
+            ))
+        })();
+        match mapped {
+            Ok((sq, cq, sqes)) => Ok(RawRing {
+                fd,
+                sq,
+                cq,
+                sqes,
+                params,
+            }),
+            Err(e) => {
+                // SAFETY: fd is the ring we just created and nothing else refers to it.
+                unsafe { libc::close(fd) };
+                Err(e)
+            }
+        }
+    }
+
+    /// Queues an operation; the caller keeps the number in flight within `sq_entries`.
+    fn push(&mut self, opcode: u8, fd: i32, offset: u64, buf: *mut u8, len: u32, user_data: u64) {
+        let off = &self.params.sq_off;
+        let mask = self.params.sq_entries - 1;
+        let tail = self.sq.atomic(off.tail).load(Ordering::Relaxed);
+        let index = tail & mask;
+        let sqe = Sqe {
+            opcode,
+            flags: 0,
+            ioprio: 0,
+            fd,
+            off: offset,
+            addr: buf as u64,
+            len,
+            rw_flags: 0,
+            user_data,
+            buf_index: 0,
+            personality: 0,
+            splice_fd_in: 0,
+            addr3: 0,
+            pad: 0,
+        };
+        // SAFETY: index is within the sq_entries SQEs and the array slots mapped above.
+        unsafe {
+            ptr::write((self.sqes.ptr as *mut Sqe).add(index as usize), sqe);
+            ptr::write(
+                (self.sq.ptr.add(off.array as usize) as *mut u32).add(index as usize),
+                index,
+            );
+        }
+        self.sq
+            .atomic(off.tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Submits `to_submit` queued operations and waits for at least one completion.
+    fn enter(&self, to_submit: u32) -> IoResult<()> {
+        loop {
+            // SAFETY: plain io_uring_enter on our ring without a signal mask.
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd,
+                    to_submit,
+                    1u32,
+                    IORING_ENTER_GETEVENTS,
+                    ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            if ret >= 0 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    /// Takes every completion the kernel has posted, as `(user_data, result)`.
+    fn reap(&mut self, completed: &mut Vec<(u64, i32)>) {
+        let off = &self.params.cq_off;
+        let mask = self.params.cq_entries - 1;
+        let mut head = self.cq.atomic(off.head).load(Ordering::Relaxed);
+        let tail = self.cq.atomic(off.tail).load(Ordering::Acquire);
+        while head != tail {
+            // SAFETY: the entry at head is within the mapped CQEs and posted by the kernel.
+            let cqe = unsafe {
+                ptr::read(
+                    (self.cq.ptr.add(off.cqes as usize) as *const Cqe).add((head & mask) as usize),
+                )
+            };
+            completed.push((cqe.user_data, cqe.res));
+            head = head.wrapping_add(1);
+        }
+        self.cq.atomic(off.head).store(head, Ordering::Release);
+    }
+}
+
+impl Drop for RawRing {
+    fn drop(&mut self) {
+        // SAFETY: the ring fd is owned by this struct.
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Result of a read or write with the buffer handed back.
+pub type Completion = (IoResult<usize>, Vec<u8>);
+
+enum Kind {
+    Read,
+    Write,
+}
+
+/// A read into (or write from) the whole of `buf` at `offset`.
+struct Op {
+    kind: Kind,
+    file: Arc<File>,
+    offset: u64,
+    buf: Vec<u8>,
+    reply: oneshot::Sender<Completion>,
+}
+
+/// Handle to the driver thread; the thread exits once every handle is gone.
+pub struct Ring {
+    ops: mpsc::Sender<Op>,
+}
+
+impl Ring {
+    pub fn new() -> IoResult<Self> {
+        let raw = RawRing::new(ENTRIES)?;
+        let (ops, queue) = mpsc::channel();
+        std::thread::Builder::new()
+            .name(String::from("io-uring"))
+            .spawn(move || drive(raw, queue))?;
+        Ok(Ring { ops })
+    }
+
+    /// Writes up to `buf.len()` bytes at `offset`, returning how many were written.
+    pub async fn write(&self, file: Arc<File>, offset: u64, buf: Vec<u8>) -> Completion {
+        self.start(Kind::Write, file, offset, buf)
+            .await
+            .unwrap_or_else(|_| (Err(gone()), Vec::new()))
+    }
+
+    /// Hands an operation to the driver thread without waiting for it.
+    fn start(
+        &self,
+        kind: Kind,
+        file: Arc<File>,
+        offset: u64,
+        buf: Vec<u8>,
+    ) -> oneshot::Receiver<Completion> {
+        let (reply, done) = oneshot::channel();
+        let op = Op {
+            kind,
+            file,
+            offset,
+            buf,
+            reply,
+        };
+        if let Err(mpsc::SendError(op)) = self.ops.send(op) {
+            let _ = op.reply.send((Err(gone()), op.buf));
+        }
+        done
+    }
+}
+
+fn gone() -> io::Error {
+    io::Error::other("io_uring thread is gone")
+}
+
+/// Runs operations from `queue` until every `Ring` handle has been dropped.
+fn drive(mut ring: RawRing, queue: mpsc::Receiver<Op>) {
+    let capacity = ring.params.sq_entries as usize;
+    // Operations in flight by slot; a slot's index is its SQE's user data.
+    let mut slots: Vec<Option<Op>> = (0..capacity).map(|_| None).collect();
+    let mut free: Vec<usize> = (0..capacity).rev().collect();
+    let mut waiting = VecDeque::new();
+    let mut completed = Vec::new();
+    let mut open = true;
+    loop {
+        if free.len() == capacity && waiting.is_empty() {
+            if !open {
+                return;
+            }
+            match queue.recv() {
+                Ok(op) => waiting.push_back(op),
+                Err(_) => return,
+            }
+        }
+        loop {
+            match queue.try_recv() {
+                Ok(op) => waiting.push_back(op),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+        let mut submitted = 0;
+        while let Some(slot) = free.pop() {
+            let mut op = match waiting.pop_front() {
+                Some(op) => op,
+                None => {
+                    free.push(slot);
+                    break;
+                }
+            };
+            let opcode = match op.kind {
+                Kind::Read => IORING_OP_READ,
+                Kind::Write => IORING_OP_WRITE,
+            };
+            let len = u32::try_from(op.buf.len()).unwrap_or(u32::MAX);
+            let fd = op.file.as_raw_fd();
+            ring.push(opcode, fd, op.offset, op.buf.as_mut_ptr(), len, slot as u64);
+            slots[slot] = Some(op);
+            submitted += 1;
+        }
+        if let Err(e) = ring.enter(submitted) {
+            // The ring is unusable; fail everything rather than hang the callers.
+            log::error!("io_uring_enter failed: {}", e);
+            for op in slots
+                .iter_mut()
+                .filter_map(Option::take)
+                .chain(waiting.drain(..))
+            {
+                let _ = op
+                    .reply
+                    .send((Err(io::Error::new(e.kind(), e.to_string())), op.buf));
+            }
+            return;
+        }
+        completed.clear();
+        ring.reap(&mut completed);
+        for &(user_data, res) in &completed {
+            let slot = user_data as usize;
+            if let Some(mut op) = slots[slot].take() {
+                free.push(slot);
+                let result = if res < 0 {
+                    Err(io::Error::from_raw_os_error(-res))
+                } else {
+                    if let Kind::Read = op.kind {
+                        op.buf.truncate(res as usize);
+                    }
+                    Ok(res as usize)
+                };
+                let _ = op.reply.send((result, op.buf));
+            }
+        }
+    }
+}
+
+/// A file read through the ring, `chunk_size` bytes at a time.
+pub struct RingFile {
+    ring: Arc<Ring>,
+    file: Arc<File>,
+    /// Where the next read from disk starts.
+    offset: u64,
+    chunk_size: usize,
+    buffered: Vec<u8>,
+    consumed: usize,
+    pending: Option<oneshot::Receiver<Completion>>,
+}
+
+impl RingFile {
+    pub fn new(ring: Arc<Ring>, file: File, chunk_size: usize) -> Self {
+        RingFile {
+            ring,
+            file: Arc::new(file),
+            offset: 0,
+            chunk_size,
+            buffered: Vec::new(),
+            consumed: 0,
+            pending: None,
+        }
+    }
+
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size;
+    }
+
+    /// Position of the next byte handed to the reader.
+    fn position(&self) -> u64 {
+        self.offset - (self.buffered.len() - self.consumed) as u64
+    }
+}
+
+impl AsyncRead for RingFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        dst: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let this = &mut *self;
+        loop {
+            if this.consumed < this.buffered.len() {
+                let n = dst.remaining().min(this.buffered.len() - this.consumed);
+                dst.put_slice(&this.buffered[this.consumed..this.consumed + n]);
+                this.consumed += n;
+                return Poll::Ready(Ok(()));
+            }
+            if let Some(pending) = this.pending.as_mut() {
+                let (result, buf) = match Pin::new(pending).poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(completion) => {
+                        completion.unwrap_or_else(|_| (Err(gone()), Vec::new()))
+                    }
+                };
+                this.pending = None;
+                let n = result?;
+                this.offset += n as u64;
+                this.buffered = buf;
+                this.consumed = 0;
+                if n == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                continue;
+            }
+            let mut buf = std::mem::take(&mut this.buffered);
+            buf.resize(this.chunk_size, 0);
+            this.pending = Some(
+                this.ring
+                    .start(Kind::Read, this.file.clone(), this.offset, buf),
+            );
+        }
+    }
+}
+
+impl AsyncSeek for RingFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> IoResult<()> {
+        if self.pending.is_some() {
+            return Err(io::Error::other("seek while a read is in flight"));
+        }
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position().checked_add_signed(delta),
+            SeekFrom::End(delta) => self.file.metadata()?.len().checked_add_signed(delta),
+        };
+        self.offset = target.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.buffered.clear();
+        self.consumed = 0;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<IoResult<u64>> {
+        Poll::Ready(Ok(self.position()))
+    }
+}
@@ -2,6 +2,7 @@
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
+    net::Ipv6Addr,
 };
 
 pub type FileUid = String;
@@ -13,3 +14,56 @@ pub fn hash(uid: &FileUid) -> usize {
     uid.hash(&mut hasher);
     hasher.finish() as usize
 }
+
+/// Longest key a cached file is named after as it is.
+pub const MAX_PLAIN_NAME_LEN: usize = 200;
+
+/// Name of the file caching `key` in a cache directory. Keys of ASCII letters, digits and
+/// `-_.@=+,` keep their name, unless they start with `.` or are longer than
+/// `MAX_PLAIN_NAME_LEN`. Any other key, e.g. one with `/`, spaces, `#` or non-ASCII
+/// characters, is named `~` and the hex SHA-256 of the key, which no plain name starts
+/// with. The metadata store records the name under the key.
+pub fn disk_name(key: &str) -> String {
+    let plain = !key.is_empty()
+        && key.len() <= MAX_PLAIN_NAME_LEN
+        && !key.starts_with('.')
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.@=+,".contains(&b));
+    if plain {
+        return key.to_string();
+    }
+    let digest = ring::digest::digest(&ring::digest::SHA256, key.as_bytes());
+    format!("~{}", hex::encode(digest.as_ref()))
+}
+
+/// Name of a temporary file next to the cached file `name`, e.g. a new copy being
+/// written. It starts with `.`, which `disk_name` never does, so that it can't be the file
+/// of another key: `foo.incoming` is a key of its own.
+pub fn temp_name(name: &str, purpose: &str) -> String {
+    format!(".{}.{}", name, purpose)
+}
+
+/// `host:port`, with an IPv6 host in brackets as URLs and socket addresses write it.
+pub fn host_port(host: &str, port: u16) -> String {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Splits `host:port` into the host, without the brackets an IPv6 host needs, and the
+/// port. `None` if either is missing, or an IPv6 host is not in brackets.
+pub fn split_host_port(address: &str) -> Option<(&str, u16)> {
+    let (host, port) = address.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed
+            .strip_suffix(']')
+            .filter(|ip| ip.parse::<Ipv6Addr>().is_ok())?,
+        None if host.contains(':') => return None,
+        None => host,
+    };
+    (!host.is_empty()).then_some((host, port))
+}
@@ -0,0 +1,69 @@
+// versioning.rs
+//! Pinned object versions. In a versioned bucket, a request can ask for a specific version
+//! of an object with `?versionId=` or the `X-Istziio-Version-Id` header. The version
+//! becomes part of the cache key, `<key>@version-<id>`, so each version is cached,
+//! registered in the metadata store and evicted as an object of its own. Connectors split
+//! it off again to fetch that version from the backing store.
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+pub const VERSION_HEADER: &str = "X-Istziio-Version-Id";
+
+const VERSION_SEPARATOR: &str = "@version-";
+/// S3 version IDs are at most 1024 bytes long.
+const MAX_VERSION_ID_LEN: usize = 1024;
+
+/// Version IDs are kept to the characters S3 uses, as they end up in file names.
+pub fn is_valid_version_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_VERSION_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+}
+
+/// Cache key of version `version_id` of `uid`.
+pub fn versioned_key(uid: &str, version_id: &str) -> String {
+    format!("{}{}{}", uid, VERSION_SEPARATOR, version_id)
+}
+
+/// Splits a cache key into the object key and the version it pins, if any.
+pub fn split_version(key: &str) -> (&str, Option<&str>) {
+    match key.rsplit_once(VERSION_SEPARATOR) {
+        Some((uid, id)) if is_valid_version_id(id) => (uid, Some(id)),
+        _ => (key, None),
+    }
+}
+
+/// The version a request asks for; the query parameter wins over the header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PinnedVersion(pub Option<String>);
+
+impl PinnedVersion {
+    /// Cache key of `uid` at the requested version.
+    pub fn key(&self, uid: &str) -> String {
+        match &self.0 {
+            Some(id) => versioned_key(uid, id),
+            None => uid.to_string(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PinnedVersion {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let id = req
+            .query_value::<&str>("versionId")
+            .and_then(Result::ok)
+            .or_else(|| req.headers().get_one(VERSION_HEADER));
+        match id {
+            None => Outcome::Success(PinnedVersion(None)),
+            Some(id) if is_valid_version_id(id) => {
+                Outcome::Success(PinnedVersion(Some(id.to_string())))
+            }
+            Some(_) => Outcome::Error((Status::BadRequest, "malformed version ID")),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// warmup.rs
+//! Prefetching a known working set at startup, so that the first queries after a
+//! deployment don't all go to S3. The keys come from a manifest file and from listing
+//! prefixes of the backing store. Each node warms only the keys it owns, so every node can
+//! be given the same manifest. Fetches are paced under `max_bytes_per_sec` to leave room
+//! for client traffic; with `before_serving`, `GET /` answers 503 until the warm-up is
+//! done, so that load balancers hold traffic back until then.
+use log::{info, warn};
+use rocket::futures::{stream, StreamExt};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::{get, State};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::AdminAccess;
+use crate::cache::{ConcurrentDiskCache, GetFileOptions, GetFileResult};
+use crate::rebalance::Throttle;
+use crate::s3_api::{connector_for, MAX_KEYS};
+use crate::storage::storage_connector::{ListRequest, StorageConnector};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WarmUpConfig {
+    /// File of keys to fetch, one per line; blank lines and `#` comments are skipped.
+    pub manifest: Option<String>,
+    /// Prefixes whose every object is fetched, as listed by the backing store.
+    pub prefixes: Vec<String>,
+    /// Objects fetched at once.
+    pub concurrency: usize,
+    /// Upper bound on the bytes fetched per second.
+    pub max_bytes_per_sec: u64,
+    /// Report the node unhealthy until the warm-up is done.
+    pub before_serving: bool,
+}
+
+impl Default for WarmUpConfig {
+    fn default() -> Self {
+        WarmUpConfig {
+            manifest: None,
+            prefixes: Vec::new(),
+            concurrency: 4,
+            max_bytes_per_sec: 64 * 1024 * 1024,
+            before_serving: false,
+        }
+    }
+}
+
+/// Progress of the warm-up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarmUpStatus {
+    pub running: bool,
+    pub done: bool,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    /// Keys named by the manifest and the prefixes.
+    pub keys: u64,
+    /// Keys now cached on this node.
+    pub warmed: u64,
+    /// Keys another node owns.
+    pub skipped: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    pub last_error: Option<String>,
+}
+
+/// The keys of a manifest: one per line, skipping blank lines and `#` comments.
+pub fn read_keys(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+pub struct WarmUp {
+    config: WarmUpConfig,
+    status: Mutex<WarmUpStatus>,
+}
+
+impl WarmUp {
+    pub fn new(config: WarmUpConfig) -> Self {
+        WarmUp {
+            config,
+            status: Mutex::new(WarmUpStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> WarmUpStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Whether the node should not take traffic yet.
+    pub fn holds_traffic(&self) -> bool {
+        self.config.before_serving && !self.status.lock().unwrap().done
+    }
+
+    /// Fetches every key of the manifest and the prefixes that this node owns, once.
+    pub async fn run(
+        self: Arc<Self>,
+        cache: Arc<ConcurrentDiskCache>,
+        connectors: Vec<Arc<dyn StorageConnector + Send + Sync>>,
+    ) {
+        self.update(|status| {
+            status.running = true;
+            status.started_at = Some(chrono::Utc::now().to_rfc3339());
+        });
+        let keys = self.keys(&connectors).await;
+        info!("Warming up {} keys", keys.len());
+        self.update(|status| status.keys = keys.len() as u64);
+        let throttle = Mutex::new(Throttle::new(self.config.max_bytes_per_sec));
+        let warm_up = &*self;
+        stream::iter(keys)
+            .for_each_concurrent(self.config.concurrency.max(1), |key| {
+                let connector = connector_for(&key, &connectors).clone();
+                let cache = &cache;
+                let throttle = &throttle;
+                async move {
+                    let options = GetFileOptions {
+                        force_admit: true,
+                        ..Default::default()
+                    };
+                    let result = cache
+                        .get_file(PathBuf::from(&key), connector, options)
+                        .await;
+                    match warmed_bytes(result).await {
+                        Ok(Some(bytes)) => {
+                            warm_up.update(|status| {
+                                status.warmed += 1;
+                                status.bytes += bytes;
+                            });
+                            let delay = throttle.lock().unwrap().delay(bytes);
+                            tokio::time::sleep(delay).await;
+                        }
+                        Ok(None) => warm_up.update(|status| status.skipped += 1),
+                        Err(e) => {
+                            warn!("Warming up {} failed: {}", key, e);
+                            warm_up.update(|status| {
+                                status.failed += 1;
+                                status.last_error = Some(format!("{}: {}", key, e));
+                            });
+                        }
+                    }
+                }
+            })
+            .await;
+        let mut status = self.status.lock().unwrap();
+        status.running = false;
+        status.done = true;
+        status.finished_at = Some(chrono::Utc::now().to_rfc3339());
+        info!(
+            "Warm-up done: {} keys warmed ({} bytes), {} owned elsewhere, {} failed",
+            status.warmed, status.bytes, status.skipped, status.failed
+        );
+    }
+
+    /// The manifest's keys followed by the prefixes' objects, each once. A manifest or
+    /// prefix that can't be read is left out.
+    async fn keys(&self, connectors: &[Arc<dyn StorageConnector + Send + Sync>]) -> Vec<String> {
+        let mut keys = Vec::new();
+        if let Some(path) = &self.config.manifest {
+            match tokio::fs::read_to_string(path).await {
+                Ok(text) => keys = read_keys(&text),
+                Err(e) => self.fail(format!("reading {}: {}", path, e)),
+            }
+        }
+        for prefix in &self.config.prefixes {
+            let connector = connector_for(prefix, connectors);
+            let mut request = ListRequest {
+                prefix: prefix.clone(),
+                max_keys: MAX_KEYS,
+                ..Default::default()
+            };
+            loop {
+                match connector.list_objects(&request).await {
+                    Ok(page) => {
+                        keys.extend(page.objects.into_iter().map(|object| object.key));
+                        request.continuation_token = page.next_continuation_token;
+                    }
+                    Err(e) => {
+                        self.fail(format!("listing {}: {}", prefix, e));
+                        break;
+                    }
+                }
+                if request.continuation_token.is_none() {
+                    break;
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        keys.retain(|key| seen.insert(key.clone()));
+        keys
+    }
+
+    fn fail(&self, error: String) {
+        warn!("Warm-up: {}", error);
+        self.update(|status| status.last_error = Some(error));
+    }
+
+    fn update(&self, change: impl FnOnce(&mut WarmUpStatus)) {
+        change(&mut self.status.lock().unwrap());
+    }
+}
+
+/// The bytes a fetch brought onto this node, or `None` if another node owns the key.
+/// Objects sent while downloading are read to the end, which completes the download.
+pub(crate) async fn warmed_bytes(result: GetFileResult) -> Result<Option<u64>, String> {
+    let mut body = match result {
+        GetFileResult::Hit(file) | GetFileResult::Encoded(file, _) => return Ok(Some(file.len())),
+        GetFileResult::Redirect(_) => return Ok(None),
+        GetFileResult::Error(e) => return Err(e.to_string()),
+        GetFileResult::PassThrough(body)
+        | GetFileResult::Streaming(body)
+        | GetFileResult::EncodedStream(body, _) => body.0,
+        GetFileResult::MemoryHit(_) | GetFileResult::Partial(_) | GetFileResult::NotModified(_) => {
+            return Ok(Some(0))
+        }
+    };
+    let mut bytes = 0;
+    while let Some(chunk) = body.next().await {
+        bytes += chunk.map_err(|e| e.to_string())?.len() as u64;
+    }
+    Ok(Some(bytes))
+}
+
+/// Progress of the startup warm-up.
+#[get("/admin/warmup/status")]
+pub async fn warmup_status(
+    _admin: AdminAccess,
+    warm_up: &State<Option<Arc<WarmUp>>>,
+) -> Result<Json<WarmUpStatus>, Custom<String>> {
+    match warm_up.inner() {
+        Some(warm_up) => Ok(Json(warm_up.status())),
+        None => Err(Custom(
+            Status::NotFound,
+            String::from("warm-up is not configured"),
+        )),
+    }
+}
@@ -0,0 +1,582 @@
+// writeback.rs
+//! Write-back uploads. A `PUT /s3/<key>` is journaled to local disk and acknowledged once
+//! the journal entry is durable, so writers don't wait on S3. Background workers upload
+//! the entries, oldest first, and retry failed uploads with exponential backoff until S3
+//! takes them. The journal is replayed when the node starts, so acknowledged writes
+//! survive restarts. A newer PUT of a key replaces the key's older entries that are not
+//! being uploaded yet, and uploads of one key never overlap, so the last write wins in S3.
+//!
+//! Each entry is two files: `<seq>.data` holds the body and `<seq>.key` the key. The key
+//! file is written last and renamed into place, so an entry exists once its key file does.
+//!
+//! Bodies are journaled as sent, even when the cache encrypts what it stores, since the
+//! uploaders send them to S3 as they are. The journal therefore lives outside the cache
+//! directories and is readable by this node's user only: the directory is made 0700 and
+//! its files 0600.
+use log::{info, warn};
+use rocket::data::{Data, ToByteUnit};
+use rocket::futures::future::join_all;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
+use rocket::{get, post, put, Responder, State};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Notify;
+
+use crate::auth::AdminAccess;
+use crate::cache::ConcurrentDiskCache;
+use crate::read_through::check_bucket_key;
+use crate::storage::storage_connector::StorageConnector;
+use crate::util::hash;
+
+/// How long `/admin/write-back/flush` waits unless told otherwise.
+const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriteBackConfig {
+    /// Directory of the journal, kept private to this node's user. It must not be in a
+    /// cache directory, whose scrubber would delete the entries, and where encrypted
+    /// caches keep only sealed files.
+    pub dir: String,
+    /// Uploads running at once.
+    pub workers: usize,
+    /// Wait before retrying a failed upload; it doubles with every further failure.
+    pub retry_delay_ms: u64,
+    pub max_retry_delay_ms: u64,
+    /// Largest body a PUT may send; S3 takes up to 5 GiB in a single PUT.
+    pub max_object_size: u64,
+}
+
+impl Default for WriteBackConfig {
+    fn default() -> Self {
+        WriteBackConfig {
+            dir: String::from("./write_back"),
+            workers: 4,
+            retry_delay_ms: 1000,
+            max_retry_delay_ms: 60_000,
+            max_object_size: 5 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl WriteBackConfig {
+    /// Wait before the next attempt at an upload that failed `failures` times in a row.
+    pub fn retry_delay(&self, failures: u32) -> Duration {
+        let factor = 1u64 << failures.saturating_sub(1).min(32);
+        Duration::from_millis(
+            self.retry_delay_ms
+                .saturating_mul(factor)
+                .min(self.max_retry_delay_ms),
+        )
+    }
+}
+
+/// Depth of the queue, plus totals since the node started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteBackStatus {
+    /// Entries not uploaded yet, including those being uploaded.
+    pub depth: u64,
+    pub pending_bytes: u64,
+    pub uploading: u64,
+    /// Age of the oldest entry not uploaded yet.
+    pub oldest_ms: Option<u64>,
+    pub accepted: u64,
+    pub uploaded: u64,
+    pub bytes_uploaded: u64,
+    /// Entries dropped because a newer PUT of their key came in before they were uploaded.
+    pub superseded: u64,
+    pub failed_attempts: u64,
+    pub last_error: Option<String>,
+}
+
+/// A journaled PUT, as `WriteBackQueue::accept` returns it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journaled {
+    pub seq: u64,
+    pub size: u64,
+    /// The body; it is deleted once uploaded.
+    pub path: PathBuf,
+}
+
+#[derive(Debug)]
+struct Entry {
+    seq: u64,
+    key: String,
+    size: u64,
+    accepted_at: Instant,
+    uploading: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_seq: u64,
+    /// In journal order.
+    entries: VecDeque<Entry>,
+    totals: WriteBackStatus,
+}
+
+/// What a worker should do next.
+enum Next {
+    Upload(u64, String),
+    /// Nothing to upload before the given time, or before new work comes in.
+    Wait(Option<Instant>),
+}
+
+pub struct WriteBackQueue {
+    config: WriteBackConfig,
+    dir: PathBuf,
+    state: Mutex<QueueState>,
+    /// Wakes the workers when there may be something to upload.
+    wake: Notify,
+    /// Wakes flushes when an upload finished.
+    uploaded: Notify,
+}
+
+impl WriteBackQueue {
+    /// Opens the journal at `config.dir`, creating it if needed, and queues the entries it
+    /// holds. Incomplete entries left by a crash are deleted.
+    pub fn open(config: WriteBackConfig) -> IoResult<Self> {
+        let dir = PathBuf::from(&config.dir);
+        std::fs::create_dir_all(&dir)?;
+        restrict(&dir, 0o700)?;
+        let mut files = Vec::new();
+        let mut keys = HashMap::new();
+        for file in std::fs::read_dir(&dir)? {
+            let name = file?.file_name().to_string_lossy().into_owned();
+            if let Some(seq) = name.strip_suffix(".key").and_then(|seq| seq.parse().ok()) {
+                keys.insert(seq, std::fs::read_to_string(dir.join(&name))?);
+            }
+            files.push(name);
+        }
+        let now = Instant::now();
+        let mut entries = Vec::new();
+        for (seq, key) in keys {
+            let data = std::fs::metadata(dir.join(data_file(seq)));
+            match data {
+                Ok(data) => entries.push(Entry {
+                    seq,
+                    key,
+                    size: data.len(),
+                    accepted_at: data
+                        .modified()
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .and_then(|age| now.checked_sub(age))
+                        .unwrap_or(now),
+                    uploading: false,
+                    failures: 0,
+                    retry_at: None,
+                }),
+                Err(e) => warn!("Dropping write-back entry {} of {}: {}", seq, key, e),
+            }
+        }
+        // The newest entry of a key replaces the others, as it would have on arrival.
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.seq));
+        let mut seen = HashSet::new();
+        entries.retain(|entry| seen.insert(entry.key.clone()));
+        entries.reverse();
+        let kept: HashSet<String> = entries
+            .iter()
+            .flat_map(|entry| [data_file(entry.seq), key_file(entry.seq)])
+            .collect();
+        let mut next_seq = 0;
+        for name in files.iter().filter(|name| !kept.contains(*name)) {
+            if let Some(seq) = seq_of(name) {
+                next_seq = next_seq.max(seq + 1);
+            }
+            std::fs::remove_file(dir.join(name))?;
+        }
+        if let Some(last) = entries.last() {
+            next_seq = next_seq.max(last.seq + 1);
+            info!("Replaying {} write-back entries", entries.len());
+        }
+        Ok(WriteBackQueue {
+            config,
+            dir,
+            state: Mutex::new(QueueState {
+                next_seq,
+                entries: entries.into(),
+                totals: WriteBackStatus::default(),
+            }),
+            wake: Notify::new(),
+            uploaded: Notify::new(),
+        })
+    }
+
+    pub fn max_object_size(&self) -> u64 {
+        self.config.max_object_size
+    }
+
+    pub fn status(&self) -> WriteBackStatus {
+        let state = self.state.lock().unwrap();
+        WriteBackStatus {
+            depth: state.entries.len() as u64,
+            pending_bytes: state.entries.iter().map(|entry| entry.size).sum(),
+            uploading: state.entries.iter().filter(|entry| entry.uploading).count() as u64,
+            oldest_ms: state
+                .entries
+                .iter()
+                .map(|entry| entry.accepted_at)
+                .min()
+                .map(|accepted_at| accepted_at.elapsed().as_millis() as u64),
+            ..state.totals.clone()
+        }
+    }
+
+    /// Journals `body` as the new contents of `key` and queues it for upload. Fails with
+    /// `FileTooLarge` if the body is larger than `max_object_size`.
+    pub async fn accept(&self, key: &str, body: impl AsyncRead + Unpin) -> IoResult<Journaled> {
+        let seq = {
+            let mut state = self.state.lock().unwrap();
+            state.next_seq += 1;
+            state.next_seq - 1
+        };
+        let size = match self.journal(seq, key, body).await {
+            Ok(size) => size,
+            Err(e) => {
+                self.remove_files(seq).await;
+                let _ =
+                    tokio::fs::remove_file(self.dir.join(format!("{}.tmp", key_file(seq)))).await;
+                return Err(e);
+            }
+        };
+        let superseded = {
+            let mut state = self.state.lock().unwrap();
+            let mut superseded = Vec::new();
+            state.entries.retain(|entry| {
+                let replaced = entry.key == key && !entry.uploading;
+                if replaced {
+                    superseded.push(entry.seq);
+                }
+                !replaced
+            });
+            state.entries.push_back(Entry {
+                seq,
+                key: key.to_string(),
+                size,
+                accepted_at: Instant::now(),
+                uploading: false,
+                failures: 0,
+                retry_at: None,
+            });
+            state.totals.accepted += 1;
+            state.totals.superseded += superseded.len() as u64;
+            superseded
+        };
+        for seq in superseded {
+            self.remove_files(seq).await;
+        }
+        self.wake.notify_waiters();
+        Ok(Journaled {
+            seq,
+            size,
+            path: self.dir.join(data_file(seq)),
+        })
+    }
+
+    /// Writes entry `seq` and syncs it to disk; returns the size of the body.
+    async fn journal(&self, seq: u64, key: &str, body: impl AsyncRead + Unpin) -> IoResult<u64> {
+        let limit = self.config.max_object_size;
+        let mut data = create_private(&self.dir.join(data_file(seq))).await?;
+        let size = tokio::io::copy(&mut body.take(limit + 1), &mut data).await?;
+        if size > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!("{} is larger than the {} bytes a PUT may send", key, limit),
+            ));
+        }
+        data.sync_all().await?;
+        let staged = self.dir.join(format!("{}.tmp", key_file(seq)));
+        let mut key_record = create_private(&staged).await?;
+        key_record.write_all(key.as_bytes()).await?;
+        key_record.sync_all().await?;
+        tokio::fs::rename(&staged, self.dir.join(key_file(seq))).await?;
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || std::fs::File::open(dir)?.sync_all()).await??;
+        Ok(size)
+    }
+
+    /// Deletes entry `seq` from the journal, key file first.
+    async fn remove_files(&self, seq: u64) {
+        let _ = tokio::fs::remove_file(self.dir.join(key_file(seq))).await;
+        let _ = tokio::fs::remove_file(self.dir.join(data_file(seq))).await;
+    }
+
+    /// The oldest entry that is due and whose key is not being uploaded, marked as being
+    /// uploaded.
+    fn next(&self) -> Next {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let busy: HashSet<String> = state
+            .entries
+            .iter()
+            .filter(|entry| entry.uploading)
+            .map(|entry| entry.key.clone())
+            .collect();
+        let mut wake_at: Option<Instant> = None;
+        for entry in state.entries.iter_mut() {
+            if entry.uploading || busy.contains(&entry.key) {
+                continue;
+            }
+            match entry.retry_at {
+                Some(retry_at) if retry_at > now => {
+                    wake_at = Some(wake_at.map_or(retry_at, |at| at.min(retry_at)));
+                }
+                _ => {
+                    entry.uploading = true;
+                    return Next::Upload(entry.seq, entry.key.clone());
+                }
+            }
+        }
+        Next::Wait(wake_at)
+    }
+
+    /// Uploads with `config.workers` workers for as long as the node runs. Each key goes
+    /// through the connector that serves it.
+    pub async fn run(self: Arc<Self>, connectors: Vec<Arc<dyn StorageConnector + Send + Sync>>) {
+        let workers = (0..self.config.workers.max(1))
+            .map(|_| tokio::spawn(self.clone().work(connectors.clone())))
+            .collect::<Vec<_>>();
+        join_all(workers).await;
+    }
+
+    async fn work(self: Arc<Self>, connectors: Vec<Arc<dyn StorageConnector + Send + Sync>>) {
+        loop {
+            let notified = self.wake.notified();
+            tokio::pin!(notified);
+            // Registered before looking at the queue, so that no wake-up is missed.
+            notified.as_mut().enable();
+            match self.next() {
+                Next::Upload(seq, key) => {
+                    let connector = &connectors[hash(&key) % connectors.len()];
+                    self.upload(seq, &key, connector).await;
+                }
+                Next::Wait(wake_at) => {
+                    let retry = async {
+                        match wake_at {
+                            Some(at) => tokio::time::sleep_until(at.into()).await,
+                            None => std::future::pending().await,
+                        }
+                    };
+                    tokio::select! {
+                        _ = notified => {}
+                        _ = retry => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Uploads entry `seq` and drops it from the journal, or schedules a retry.
+    async fn upload(
+        &self,
+        seq: u64,
+        key: &str,
+        connector: &Arc<dyn StorageConnector + Send + Sync>,
+    ) {
+        let result = connector
+            .put_object(key, &self.dir.join(data_file(seq)))
+            .await;
+        if result.is_ok() {
+            self.remove_files(seq).await;
+        }
+        {
+            let mut state = self.state.lock().unwrap();
+            let position = state.entries.iter().position(|entry| entry.seq == seq);
+            match (result, position) {
+                (Ok(()), Some(position)) => {
+                    let entry = state.entries.remove(position).unwrap();
+                    state.totals.uploaded += 1;
+                    state.totals.bytes_uploaded += entry.size;
+                }
+                (Err(e), Some(position)) => {
+                    warn!("Uploading {} failed: {}", key, e);
+                    let entry = &mut state.entries[position];
+                    entry.uploading = false;
+                    entry.failures += 1;
+                    entry.retry_at = Some(Instant::now() + self.config.retry_delay(entry.failures));
+                    state.totals.failed_attempts += 1;
+                    state.totals.last_error = Some(format!("{}: {}", key, e));
+                }
+                (_, None) => {}
+            }
+        }
+        // A newer entry of the same key may be waiting for this one.
+        self.wake.notify_waiters();
+        self.uploaded.notify_waiters();
+    }
+
+    /// Retries every entry right away and waits until the entries accepted so far are
+    /// uploaded (or replaced by newer writes), or `timeout` passed. Returns whether they
+    /// were.
+    pub async fn flush(&self, timeout: Duration) -> bool {
+        let last = {
+            let mut state = self.state.lock().unwrap();
+            for entry in state.entries.iter_mut() {
+                entry.retry_at = None;
+            }
+            match state.entries.back() {
+                Some(entry) => entry.seq,
+                None => return true,
+            }
+        };
+        self.wake.notify_waiters();
+        let drained = async {
+            loop {
+                let notified = self.uploaded.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                let waiting = {
+                    let state = self.state.lock().unwrap();
+                    state.entries.iter().any(|entry| entry.seq <= last)
+                };
+                if !waiting {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, drained).await.is_ok()
+    }
+}
+
+fn data_file(seq: u64) -> String {
+    format!("{:020}.data", seq)
+}
+
+fn key_file(seq: u64) -> String {
+    format!("{:020}.key", seq)
+}
+
+/// Creates a journal file only this node's user may read.
+async fn create_private(path: &Path) -> IoResult<tokio::fs::File> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path).await
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path, mode: u32) -> IoResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn restrict(_path: &Path, _mode: u32) -> IoResult<()> {
+    Ok(())
+}
+
+/// Sequence number of a journal file, including the temporary ones.
+fn seq_of(name: &str) -> Option<u64> {
+    name.split('.').next()?.parse().ok()
+}
+
+/// Replaces the cached copy of `key` with the one just journaled, so that reads see the
+/// write before it reaches S3. A read that misses meanwhile may cache the old object
+/// again, in which case the key is just dropped.
+async fn cache_written(cache: &ConcurrentDiskCache, key: &str, journaled: &Journaled) {
+    cache.invalidate(key).await;
+    if journaled.size > cache.max_cacheable_size(key).await {
+        return;
+    }
+    let ingested = match tokio::fs::File::open(&journaled.path).await {
+        Ok(body) => match cache.stage_reader(key, body).await {
+            Ok(staged) => cache.ingest(key, staged).await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    match ingested {
+        Ok(true) => {}
+        Ok(false) => {
+            cache.invalidate(key).await;
+        }
+        Err(e) => info!("Failed to cache {} as written: {}", key, e),
+    }
+}
+
+#[derive(Responder)]
+pub enum PutResult {
+    #[response(status = 202)]
+    Accepted(String),
+    Redirect(Box<Redirect>),
+}
+
+fn not_configured() -> Custom<String> {
+    Custom(
+        Status::NotFound,
+        String::from("write-back is not configured"),
+    )
+}
+
+/// Journals an object for upload to S3 and caches it; the node owning the key takes it.
+#[put("/s3/<uid..>", data = "<body>")]
+pub async fn put_object(
+    _admin: AdminAccess,
+    uid: PathBuf,
+    body: Data<'_>,
+    write_back: &State<Option<Arc<WriteBackQueue>>>,
+    cache: &State<Arc<ConcurrentDiskCache>>,
+) -> Result<PutResult, Custom<String>> {
+    let queue = write_back.inner().as_ref().ok_or_else(not_configured)?;
+    let key = uid.to_string_lossy().into_owned();
+    check_bucket_key(&key).map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    if let Some(url) = cache.owner_url(&key, &format!("s3/{}", key)).await {
+        // A 307 keeps the method and body, unlike the 303 that GETs are redirected with.
+        return Ok(PutResult::Redirect(Box::new(Redirect::temporary(
+            url.to_string(),
+        ))));
+    }
+    let limit = queue.max_object_size() + 1;
+    let journaled = queue
+        .accept(&key, body.open(limit.bytes()))
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::FileTooLarge => Custom(Status::PayloadTooLarge, e.to_string()),
+            _ => Custom(Status::InternalServerError, e.to_string()),
+        })?;
+    cache_written(cache, &key, &journaled).await;
+    Ok(PutResult::Accepted(format!(
+        "accepted {} ({} bytes)\n",
+        key, journaled.size
+    )))
+}
+
+/// Depth of the write-back queue on this node.
+#[get("/admin/write-back")]
+pub async fn write_back_status(
+    _admin: AdminAccess,
+    write_back: &State<Option<Arc<WriteBackQueue>>>,
+) -> Result<Json<WriteBackStatus>, Custom<String>> {
+    let queue = write_back.inner().as_ref().ok_or_else(not_configured)?;
+    Ok(Json(queue.status()))
+}
+
+/// Uploads everything accepted so far now, answering once it is in S3; 504 if that takes
+/// longer than `timeout_secs`.
+#[post("/admin/write-back/flush?<timeout_secs>")]
+pub async fn flush(
+    _admin: AdminAccess,
+    timeout_secs: Option<u64>,
+    write_back: &State<Option<Arc<WriteBackQueue>>>,
+) -> Result<Custom<Json<WriteBackStatus>>, Custom<String>> {
+    let queue = write_back.inner().as_ref().ok_or_else(not_configured)?;
+    let timeout = timeout_secs.map_or(DEFAULT_FLUSH_TIMEOUT, Duration::from_secs);
+    let status = match queue.flush(timeout).await {
+        true => Status::Ok,
+        false => Status::GatewayTimeout,
+    };
+    Ok(Custom(status, Json(queue.status())))
+}
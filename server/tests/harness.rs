@@ -0,0 +1,216 @@
+//! A cluster of server nodes in one test process: each node has its own cache directory,
+//! generates its objects like mock S3 would, and owns a share of the slots of an in-process
+//! Redis cluster, or of the ring of the nodes registered in a standalone Redis. Redirects
+//! name the node they point to, as `node<i>`, so that requests can follow them across
+//! nodes.
+use istziio_server_node::mock_redis::MockRedis;
+use istziio_server_node::redis::RedisMode;
+use istziio_server_node::server::{ServerConfig, ServerNode};
+use istziio_server_node::storage::mock_storage_connector::{GeneratedObjects, MockS3Config};
+use rocket::http::{Header, Status};
+use rocket::local::blocking::{Client, LocalResponse};
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Admin token configured on the nodes.
+pub const ADMIN_TOKEN: &str = "harness-admin-token";
+
+/// Size of every generated object.
+pub const OBJECT_SIZE: u64 = 100;
+
+/// Redis port the first node sharing a standalone Redis registers under; it is never
+/// listened on.
+const STANDALONE_PORT: u16 = 7000;
+
+pub fn admin_auth() -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", ADMIN_TOKEN))
+}
+
+/// What a request came back with once its redirects were followed.
+pub struct Served {
+    /// The node that answered.
+    pub node: usize,
+    pub status: Status,
+    /// Nodes that redirected the request on its way.
+    pub hops: usize,
+}
+
+pub struct Cluster {
+    redis: Vec<MockRedis>,
+    nodes: Vec<ServerNode>,
+    pub clients: Vec<Client>,
+    dir: TempDir,
+}
+
+impl Cluster {
+    /// Starts `n` nodes, each with room for two objects.
+    pub fn start(n: usize) -> Self {
+        Self::start_with(n, |_| {})
+    }
+
+    /// Starts `n` nodes with their configs adjusted by `configure`.
+    pub fn start_with(n: usize, configure: impl Fn(&mut ServerConfig)) -> Self {
+        let redis = MockRedis::start_cluster(n, Duration::ZERO).expect("mock Redis cluster");
+        let mut cluster = Cluster {
+            redis: Vec::new(),
+            nodes: Vec::new(),
+            clients: Vec::new(),
+            dir: TempDir::new().expect("cache directory"),
+        };
+        for node in redis {
+            cluster.launch(node, &configure);
+        }
+        cluster.join();
+        cluster
+    }
+
+    fn config(&self, index: usize, redis: &MockRedis) -> ServerConfig {
+        ServerConfig {
+            server_ip: String::from("127.0.0.1"),
+            redis_port: redis.port(),
+            advertise_addr: Some(format!("node{}:8080", index)),
+            // Never listened on; deriving it from a high Redis port would overflow.
+            web_port: Some(8080),
+            cache_dir: self
+                .dir
+                .path()
+                .join(format!("node{}", index))
+                .to_string_lossy()
+                .into_owned(),
+            // Never reached: the objects are generated.
+            use_mock_s3_endpoint: Some(String::from("http://127.0.0.1:1")),
+            mock_s3: Some(MockS3Config {
+                generate: Some(GeneratedObjects {
+                    min_size: OBJECT_SIZE,
+                    max_size: OBJECT_SIZE,
+                }),
+                ..Default::default()
+            }),
+            max_size: 2 * OBJECT_SIZE + OBJECT_SIZE / 2,
+            bucket_size: 1,
+            admin_token: Some(ADMIN_TOKEN.into()),
+            redis_namespace: Some(String::from("harness")),
+            self_test: false,
+            ..Default::default()
+        }
+    }
+
+    fn launch(&mut self, redis: MockRedis, configure: &impl Fn(&mut ServerConfig)) {
+        let mut config = self.config(self.nodes.len(), &redis);
+        configure(&mut config);
+        self.redis.push(redis);
+        self.launch_with(config);
+    }
+
+    fn launch_with(&mut self, config: ServerConfig) {
+        let node = ServerNode::new(config).expect("valid server config");
+        let client = Client::tracked(node.build()).expect("valid rocket instance");
+        self.nodes.push(node);
+        self.clients.push(client);
+    }
+
+    /// Starts `n` nodes sharing one standalone Redis, each registered there under its
+    /// own Redis port, and has every node read the ring once all have joined it.
+    pub fn start_standalone(n: usize) -> Self {
+        let redis = MockRedis::start_standalone(Duration::ZERO).expect("mock Redis");
+        let mut cluster = Cluster {
+            redis: Vec::new(),
+            nodes: Vec::new(),
+            clients: Vec::new(),
+            dir: TempDir::new().expect("cache directory"),
+        };
+        for index in 0..n {
+            let mut config = cluster.config(index, &redis);
+            config.redis_mode = RedisMode::Standalone;
+            config.redis_addrs = vec![redis.url()];
+            config.redis_port = STANDALONE_PORT + index as u16;
+            cluster.launch_with(config);
+        }
+        cluster.redis.push(redis);
+        cluster.join();
+        cluster
+    }
+
+    /// Has every node join the cluster, which registers the address it advertises, then
+    /// re-read the members, so that each knows the addresses of those that joined after it.
+    fn join(&self) {
+        for client in &self.clients {
+            client.get("/healthz/ready").dispatch();
+        }
+        for client in &self.clients {
+            Self::refresh(client);
+        }
+    }
+
+    fn refresh(client: &Client) {
+        let response = client
+            .post("/admin/refresh-mapping")
+            .header(admin_auth())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    /// Adds a node, which takes an even share of the slots from the others, and has the
+    /// others re-read the slot ownership. Returns the new node's index.
+    pub fn add_node(&mut self) -> usize {
+        let redis = self.redis[0].add_node().expect("mock Redis node");
+        self.launch(redis, &|_| {});
+        let added = self.nodes.len() - 1;
+        // Registers the new node's address before the others look for it.
+        self.clients[added].get("/healthz/ready").dispatch();
+        for client in &self.clients[..added] {
+            Self::refresh(client);
+        }
+        added
+    }
+
+    /// The node a redirect to `location` points to.
+    fn target(location: &str) -> usize {
+        location
+            .split("://node")
+            .nth(1)
+            .and_then(|rest| rest.split(':').next())
+            .and_then(|index| index.parse().ok())
+            .unwrap_or_else(|| panic!("redirect to an unknown node: {}", location))
+    }
+
+    /// GETs `/s3/<key>` from node `from`, following redirects to the owner.
+    pub fn get(&self, from: usize, key: &str) -> Served {
+        self.follow(from, |client| client.get(format!("/s3/{}", key)).dispatch())
+    }
+
+    /// DELETEs `/s3/<key>` through node `from`, following redirects to the owner.
+    pub fn delete(&self, from: usize, key: &str) -> Served {
+        self.follow(from, |client| {
+            client
+                .delete(format!("/s3/{}", key))
+                .header(admin_auth())
+                .dispatch()
+        })
+    }
+
+    fn follow(&self, from: usize, send: impl Fn(&Client) -> LocalResponse<'_>) -> Served {
+        let mut node = from;
+        for hops in 0..self.nodes.len() {
+            let response = send(&self.clients[node]);
+            let status = response.status();
+            if status != Status::SeeOther && status != Status::TemporaryRedirect {
+                return Served { node, status, hops };
+            }
+            let location = response.headers().get_one("Location").unwrap_or_default();
+            node = Self::target(location);
+        }
+        panic!("redirect loop from node {}", from);
+    }
+
+    /// The node owning `key`, which caches it on the way.
+    pub fn owner(&self, key: &str) -> usize {
+        self.get(0, key).node
+    }
+
+    /// Whether node `node` lists `key` in its cache.
+    pub fn caches(&self, node: usize, key: &str) -> bool {
+        let stats = self.clients[node].get("/stats").dispatch().into_string();
+        stats.is_some_and(|stats| stats.contains(key))
+    }
+}
@@ -0,0 +1,100 @@
+use istziio_server_node::access_log::AccessLog;
+use istziio_server_node::logging::{record_outcome, RequestContext};
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use rocket::response::stream::ReaderStream;
+use rocket::{get, routes, Responder};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+#[get("/s3/<key..>", rank = 2)]
+async fn object(context: RequestContext, key: PathBuf) -> ReaderStream![Cursor<Vec<u8>>] {
+    let size = if key.ends_with("big.bin") { 4096 } else { 5 };
+    context.scope(async { record_outcome("miss") }).await;
+    ReaderStream::one(Cursor::new(vec![b'x'; size]))
+}
+
+#[derive(Responder)]
+#[response(status = 206)]
+struct Partial {
+    body: &'static str,
+    range: Header<'static>,
+}
+
+#[get("/s3/ranged/<_key..>", rank = 1)]
+fn ranged(_key: PathBuf) -> Partial {
+    Partial {
+        body: "xy",
+        range: Header::new("Content-Range", "bytes 0-1/100"),
+    }
+}
+
+#[get("/stats")]
+fn stats() -> &'static str {
+    "stats"
+}
+
+fn read_entries(path: &Path) -> Vec<serde_json::Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_access_log_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("access.log");
+    let client = Client::tracked(
+        rocket::build()
+            .attach(AccessLog::open(&path).unwrap())
+            .mount("/", routes![object, stats]),
+    )
+    .unwrap();
+
+    let response = client
+        .get("/s3/tables/big.bin")
+        .header(Header::new("X-Request-Id", "req-42"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_bytes().unwrap().len(), 4096);
+    client.get("/stats").dispatch();
+
+    let entries = read_entries(&path);
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry["request_id"], "req-42");
+    assert_eq!(entry["method"], "GET");
+    assert_eq!(entry["key"], "tables/big.bin");
+    assert_eq!(entry["status"], 200);
+    assert_eq!(entry["bytes"], 4096);
+    assert_eq!(entry["cache"], "miss");
+    assert!(entry["latency_ms"].as_f64().unwrap() >= 0.0);
+}
+
+#[test]
+fn test_request_trace() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace.csv");
+    let client = Client::tracked(
+        rocket::build()
+            .attach(AccessLog::trace(&path).unwrap())
+            .mount("/", routes![object, ranged, stats]),
+    )
+    .unwrap();
+    client.get("/s3/tables/a,b.bin").dispatch().into_bytes();
+    client.get("/s3/ranged/part").dispatch().into_bytes();
+    client.get("/stats").dispatch();
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<Vec<&str>> = text
+        .lines()
+        .map(|line| line.splitn(5, ',').collect())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0][0].parse::<i64>().unwrap() > 0);
+    assert_eq!(lines[0][1..], ["200", "5", "miss", "tables/a,b.bin"]);
+    // Ranged answers record the whole object's size.
+    assert_eq!(lines[1][1..], ["206", "100", "-", "ranged/part"]);
+}
@@ -0,0 +1,88 @@
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache};
+use istziio_server_node::config::parse_config;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::util::{host_port, split_host_port};
+use std::path::PathBuf;
+
+fn cache() -> ConcurrentDiskCache {
+    ConcurrentDiskCache::new(
+        PathBuf::from("unused"),
+        1000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    )
+}
+
+#[test]
+fn test_host_port() {
+    assert_eq!(host_port("10.0.0.1", 6379), "10.0.0.1:6379");
+    assert_eq!(host_port("cache-0.cache", 8080), "cache-0.cache:8080");
+    assert_eq!(host_port("fd00::1", 6379), "[fd00::1]:6379");
+
+    assert_eq!(split_host_port("10.0.0.1:6379"), Some(("10.0.0.1", 6379)));
+    assert_eq!(split_host_port("[fd00::1]:6379"), Some(("fd00::1", 6379)));
+    assert_eq!(
+        split_host_port("cache-0.cache:8080"),
+        Some(("cache-0.cache", 8080))
+    );
+    for bad in [
+        "fd00::1:6379",
+        "[fd00::1]",
+        "[cache-0]:8080",
+        ":8080",
+        "cache-0.cache",
+        "cache-0.cache:http",
+    ] {
+        assert_eq!(split_host_port(bad), None, "{}", bad);
+    }
+}
+
+#[test]
+fn test_redirects_to_ipv6_nodes() {
+    let cache = cache();
+    let url = cache
+        .peer_url("fd00::1", 6379, "/bucket/a.parquet")
+        .unwrap();
+    assert_eq!(url.as_str(), "http://[fd00::1]:26379/bucket/a.parquet");
+    let url = cache.peer_url("::1", 6379, "/bucket/a.parquet").unwrap();
+    assert_eq!(url.as_str(), "http://localhost:26379/bucket/a.parquet");
+    // A dual-stack node may report an IPv4 address in IPv6 form.
+    let url = cache
+        .peer_url("::ffff:10.0.0.1", 6379, "/bucket/a.parquet")
+        .unwrap();
+    assert_eq!(url.as_str(), "http://10.0.0.1:26379/bucket/a.parquet");
+}
+
+#[test]
+fn test_unaddressable_nodes_have_no_url() {
+    let cache = cache();
+    // Past the offset, the web port would overflow.
+    assert!(cache.peer_url("10.0.0.1", 60000, "/a.parquet").is_none());
+    assert!(cache.peer_url("not a host", 6379, "/a.parquet").is_none());
+}
+
+#[test]
+fn test_ipv6_config() {
+    let config = parse_config(
+        r#"
+        server_ip = "fd00::1"
+        redis_port = 6379
+        web_port = 8080
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.redis_addrs(), vec!["redis://[fd00::1]:6379"]);
+    assert_eq!(
+        config.advertised_address().as_deref(),
+        Some("[fd00::1]:8080")
+    );
+
+    let mock = "use_mock_s3_endpoint = \"http://localhost:6333\"\n";
+    let advertised = format!("{}advertise_addr = \"[fd00::1]:8080\"\n", mock);
+    assert!(parse_config(&advertised).unwrap().validate().is_ok());
+    assert!(parse_config(&advertised.replace("[fd00::1]", "fd00::1"))
+        .unwrap()
+        .validate()
+        .is_err());
+}
@@ -0,0 +1,28 @@
+use istziio_server_node::admission::{AdmissionController, AdmissionPolicy};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_parse_admission_policy() {
+    assert_eq!("always".parse(), Ok(AdmissionPolicy::Always));
+    assert_eq!(
+        "second-hit:60".parse(),
+        Ok(AdmissionPolicy::SecondHit {
+            window: Duration::from_secs(60)
+        })
+    );
+    assert!("second-hit:soon".parse::<AdmissionPolicy>().is_err());
+    assert!("lru".parse::<AdmissionPolicy>().is_err());
+}
+
+#[test]
+fn test_second_hit_admission() {
+    let mut controller = AdmissionController::new(AdmissionPolicy::SecondHit {
+        window: Duration::from_secs(10),
+    });
+    let start = Instant::now();
+    assert!(!controller.admit_at("scan.parquet", start));
+    assert!(controller.admit_at("scan.parquet", start + Duration::from_secs(5)));
+    // The ghost entry is consumed on admission and expires after the window.
+    assert!(!controller.admit_at("scan.parquet", start + Duration::from_secs(6)));
+    assert!(!controller.admit_at("scan.parquet", start + Duration::from_secs(30)));
+}
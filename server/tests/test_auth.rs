@@ -0,0 +1,64 @@
+use istziio_server_node::auth::{tokens_match, AdminAccess, AuthConfig, ReadAccess};
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use rocket::{get, routes};
+
+#[test]
+fn test_tokens_match() {
+    assert!(tokens_match("s3cret", "s3cret"));
+    assert!(!tokens_match("s3cret", "s3cree"));
+    assert!(!tokens_match("s3cre", "s3cret"));
+    assert!(!tokens_match("", "s3cret"));
+}
+
+#[get("/read")]
+fn read(_auth: ReadAccess) -> &'static str {
+    "ok"
+}
+
+#[get("/admin")]
+fn admin(_admin: AdminAccess) -> &'static str {
+    "ok"
+}
+
+fn launch(auth: AuthConfig) -> Client {
+    Client::tracked(
+        rocket::build()
+            .manage(auth)
+            .mount("/", routes![read, admin]),
+    )
+    .unwrap()
+}
+
+fn status(client: &Client, path: &'static str, token: Option<&str>) -> Status {
+    let mut request = client.get(path);
+    if let Some(token) = token {
+        request = request.header(Header::new("Authorization", format!("Bearer {}", token)));
+    }
+    request.dispatch().status()
+}
+
+#[test]
+fn test_access_guards() {
+    let client = launch(AuthConfig {
+        admin_token: Some("admin".into()),
+        read_tokens: vec!["reader".into()],
+    });
+    assert_eq!(status(&client, "/read", None), Status::Unauthorized);
+    assert_eq!(status(&client, "/read", Some("reader")), Status::Ok);
+    assert_eq!(status(&client, "/read", Some("admin")), Status::Ok);
+    assert_eq!(
+        status(&client, "/read", Some("other")),
+        Status::Unauthorized
+    );
+    assert_eq!(
+        status(&client, "/admin", Some("reader")),
+        Status::Unauthorized
+    );
+    assert_eq!(status(&client, "/admin", Some("admin")), Status::Ok);
+
+    // Without tokens the data routes are open and the admin routes are off.
+    let client = launch(AuthConfig::default());
+    assert_eq!(status(&client, "/read", None), Status::Ok);
+    assert_eq!(status(&client, "/admin", Some("admin")), Status::Forbidden);
+}
@@ -0,0 +1,61 @@
+use istziio_server_node::bootstrap::{cluster_init, parse_peer, BootstrapError};
+use istziio_server_node::mock_redis::MockRedis;
+use std::net::TcpListener;
+use std::time::Duration;
+
+#[test]
+fn test_parse_peer() {
+    assert_eq!(
+        parse_peer("node1:6379").unwrap(),
+        (String::from("node1"), 6379)
+    );
+    assert_eq!(
+        parse_peer("[::1]:7000").unwrap(),
+        (String::from("::1"), 7000)
+    );
+    assert!(matches!(
+        parse_peer("node1"),
+        Err(BootstrapError::BadPeer(_))
+    ));
+    assert!(matches!(
+        parse_peer(":6379"),
+        Err(BootstrapError::BadPeer(_))
+    ));
+    assert!(matches!(
+        parse_peer("node1:http"),
+        Err(BootstrapError::BadPeer(_))
+    ));
+}
+
+#[test]
+fn test_formed_cluster_is_left_as_is() {
+    let redis = MockRedis::start_cluster(3, Duration::ZERO).unwrap();
+    let peers: Vec<String> = redis
+        .iter()
+        .map(|node| format!("127.0.0.1:{}", node.port()))
+        .collect();
+    // The first peer finds the cluster formed; the others only wait for it.
+    for node in &redis {
+        cluster_init(&peers, node.port(), Duration::from_secs(5)).unwrap();
+    }
+    assert!(matches!(
+        cluster_init(&[], redis[0].port(), Duration::from_secs(5)),
+        Err(BootstrapError::NoPeers)
+    ));
+}
+
+#[test]
+fn test_unreachable_peer_times_out() {
+    let redis = MockRedis::start(Duration::ZERO).unwrap();
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let peers = vec![
+        format!("127.0.0.1:{}", redis.port()),
+        format!("127.0.0.1:{}", closed),
+    ];
+    let result = cluster_init(&peers, redis.port(), Duration::from_secs(1));
+    assert!(matches!(result, Err(BootstrapError::Timeout(..))));
+}
@@ -0,0 +1,35 @@
+use istziio_server_node::breaker::CircuitBreaker;
+use std::time::Duration;
+
+#[test]
+fn test_opens_after_threshold() {
+    let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+    assert!(!breaker.record_failure());
+    assert!(breaker.allow());
+    assert!(breaker.record_failure());
+    assert!(breaker.is_open());
+    assert!(!breaker.allow());
+    // Further failures keep it open without reporting it again.
+    assert!(!breaker.record_failure());
+}
+
+#[test]
+fn test_success_resets() {
+    let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+    breaker.record_failure();
+    assert!(!breaker.record_success());
+    // The count starts over, so one more failure doesn't trip it.
+    assert!(!breaker.record_failure());
+    assert!(!breaker.is_open());
+}
+
+#[test]
+fn test_trial_after_cooldown() {
+    let breaker = CircuitBreaker::new(1, Duration::ZERO);
+    assert!(breaker.record_failure());
+    assert!(breaker.is_open());
+    // The cooldown is over, so a trial call goes through and its success closes it.
+    assert!(breaker.allow());
+    assert!(breaker.record_success());
+    assert!(!breaker.is_open());
+}
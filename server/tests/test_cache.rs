@@ -0,0 +1,185 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    shard_budget, CacheOptions, ClearReport, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::{InProcessStore, MetadataStore};
+use istziio_server_node::priority::RequestPriority;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[test]
+fn test_shard_budget_adds_up() {
+    for (total, shards) in [(192, 3), (1000, 3), (7, 4), (5, 5)] {
+        let budgets = (0..shards)
+            .map(|index| shard_budget(total, shards, index))
+            .collect::<Vec<_>>();
+        assert_eq!(budgets.iter().sum::<u64>(), total);
+        let (min, max) = (budgets.iter().min(), budgets.iter().max());
+        assert!(max.unwrap() - min.unwrap() <= 1);
+    }
+}
+
+/// Every key is ten bytes.
+struct Bucket;
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Ok(FetchedObject {
+            content_length: Some(10),
+            object_size: Some(10),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(Bytes::from(vec![b'x'; 10]))])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[tokio::test]
+async fn test_hits_without_the_shard_lock_are_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        100,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let get = |key: &str| {
+        cache.get_file(
+            PathBuf::from(key),
+            Arc::new(Bucket),
+            GetFileOptions::default(),
+        )
+    };
+    assert!(matches!(get("a").await, GetFileResult::Hit(_)));
+    for _ in 0..3 {
+        assert!(matches!(get("a").await, GetFileResult::Hit(_)));
+    }
+
+    let snapshot = cache.snapshot(10).await;
+    let hits: u64 = snapshot
+        .shards
+        .iter()
+        .map(|shard| shard.stats.disk_hits)
+        .sum();
+    let misses: u64 = snapshot.shards.iter().map(|shard| shard.stats.misses).sum();
+    assert_eq!((hits, misses), (3, 1));
+    let hot = &cache.hot_keys(1).await.by_accesses[0];
+    assert_eq!((hot.key.as_str(), hot.accesses, hot.bytes), ("a", 4, 40));
+    assert_eq!(cache.debug_validate().await, Ok(()));
+}
+
+#[tokio::test]
+async fn test_clear_frees_this_nodes_files_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = InProcessStore::new(6379);
+    // Registered by another node sharing the store.
+    store
+        .set_file_cache_loc(String::from("elsewhere"), PathBuf::from("elsewhere"))
+        .await
+        .unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        100,
+        2,
+        Box::new(store),
+        CacheOptions::default(),
+    );
+    for key in ["a", "b", "c"] {
+        let result = cache
+            .get_file(
+                PathBuf::from(key),
+                Arc::new(Bucket),
+                GetFileOptions::default(),
+            )
+            .await;
+        assert!(matches!(result, GetFileResult::Hit(_)));
+    }
+
+    let report = cache.empty().await;
+    assert_eq!(
+        report,
+        ClearReport {
+            files: 3,
+            bytes: 30,
+            undeleted_files: 0,
+        }
+    );
+    assert_eq!(report.to_string(), "cleared 3 files, freed 30 bytes");
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    let snapshot = cache.snapshot(0).await;
+    assert!(snapshot.shards.iter().all(|shard| shard.current_size == 0));
+    assert_eq!(cache.debug_validate().await, Ok(()));
+
+    let metadata = cache.metadata.read().await;
+    assert_eq!(metadata.get_file(String::from("a")).await, None);
+    assert_eq!(
+        metadata.get_file(String::from("elsewhere")).await,
+        Some(PathBuf::from("elsewhere"))
+    );
+}
+
+#[tokio::test]
+async fn test_batch_fetches_are_evicted_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        30,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let get = |key: &str, priority: RequestPriority| {
+        cache.get_file(
+            PathBuf::from(key),
+            Arc::new(Bucket),
+            GetFileOptions {
+                priority,
+                ..GetFileOptions::default()
+            },
+        )
+    };
+    assert!(matches!(
+        get("a", RequestPriority::Interactive).await,
+        GetFileResult::Hit(_)
+    ));
+    assert!(matches!(
+        get("b", RequestPriority::Batch).await,
+        GetFileResult::Hit(_)
+    ));
+    assert!(matches!(
+        get("c", RequestPriority::Batch).await,
+        GetFileResult::Hit(_)
+    ));
+    // An interactive hit makes "c" newer than "a", and a batch hit leaves "a" as it was.
+    assert!(matches!(
+        get("c", RequestPriority::Interactive).await,
+        GetFileResult::Hit(_)
+    ));
+    assert!(matches!(
+        get("a", RequestPriority::Batch).await,
+        GetFileResult::Hit(_)
+    ));
+
+    assert!(matches!(
+        get("d", RequestPriority::Interactive).await,
+        GetFileResult::Hit(_)
+    ));
+    assert!(!dir.path().join("b").exists());
+    assert!(matches!(
+        get("e", RequestPriority::Interactive).await,
+        GetFileResult::Hit(_)
+    ));
+    assert!(!dir.path().join("a").exists());
+    assert!(dir.path().join("c").exists());
+    assert_eq!(cache.debug_validate().await, Ok(()));
+}
@@ -0,0 +1,188 @@
+use istziio_server_node::admission::AdmissionPolicy;
+use istziio_server_node::cache_core::{shard_index, slot_counts, CacheCore};
+use istziio_server_node::metadata::SLOT_COUNT;
+use istziio_server_node::policy::{PolicySet, PrefixPolicy, Priority};
+use std::time::{Duration, Instant};
+
+fn names(evicted: &[(String, u64)]) -> Vec<&str> {
+    evicted.iter().map(|(name, _)| name.as_str()).collect()
+}
+
+#[test]
+fn test_evicts_least_recently_used() {
+    let mut core = CacheCore::new(30, PolicySet::default(), AdmissionPolicy::Always);
+    let now = Instant::now();
+    for key in ["a", "b", "c"] {
+        assert!(core.access(key, 10, now).admitted);
+    }
+    assert!(core.access("a", 10, now).hit);
+    let access = core.access("d", 10, now);
+    assert!(!access.hit && access.admitted);
+    assert_eq!(names(&access.evicted), ["b"]);
+    assert_eq!(core.current_size(), 30);
+    assert_eq!(
+        core.entries()
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>(),
+        ["c", "a", "d"]
+    );
+
+    // Too large for the shard: served without being cached.
+    let access = core.access("huge", 31, now);
+    assert!(!access.admitted && access.evicted.is_empty());
+    let mut limited = CacheCore::new(30, PolicySet::default(), AdmissionPolicy::Always)
+        .limiting_objects_to(Some(5));
+    assert_eq!(limited.max_cacheable_size(), 5);
+    assert!(!limited.access("a", 10, now).admitted);
+}
+
+#[test]
+fn test_priorities_and_quotas() {
+    let policies = PolicySet::new(vec![
+        PrefixPolicy {
+            priority: Priority::Pinned,
+            ..PrefixPolicy::new("dim/")
+        },
+        PrefixPolicy {
+            priority: Priority::Low,
+            ..PrefixPolicy::new("tmp/")
+        },
+        PrefixPolicy {
+            max_bytes: Some(15),
+            ..PrefixPolicy::new("logs/")
+        },
+    ]);
+    let mut core = CacheCore::new(40, policies, AdmissionPolicy::Always);
+    let now = Instant::now();
+    for key in ["dim/nation", "fact/orders", "tmp/scratch", "logs/1"] {
+        core.access(key, 10, now);
+    }
+    // Low priority goes first, though it is not the least recently used.
+    assert_eq!(
+        names(&core.access("fact/lineitem", 10, now).evicted),
+        ["tmp/scratch"]
+    );
+    // The quota of logs/ evicts within logs/ only.
+    core.access("logs/2", 0, now);
+    assert_eq!(names(&core.access("logs/3", 10, now).evicted), ["logs/1"]);
+    // Pinned entries outlast everything else.
+    let evicted = core.make_room("fact/partsupp", 40);
+    assert!(!names(&evicted).contains(&"dim/nation"));
+    assert!(core.contains("dim/nation"));
+    assert_eq!(core.eviction_victim(), None);
+}
+
+#[test]
+fn test_quota_totals_follow_the_entries() {
+    let quota = |bytes| {
+        PolicySet::new(vec![PrefixPolicy {
+            max_bytes: Some(bytes),
+            ..PrefixPolicy::new("logs/")
+        }])
+    };
+    let mut core = CacheCore::new(100, quota(30), AdmissionPolicy::Always);
+    let now = Instant::now();
+    core.access("logs/1", 10, now);
+    core.access("logs/2", 10, now);
+    // A replaced entry counts with its new size only.
+    core.insert(String::from("logs/2"), 15);
+    core.access("fact/orders", 50, now);
+    assert_eq!(core.debug_validate(), Ok(()));
+    assert_eq!(core.quota_victim("logs/3", 5), None);
+    assert_eq!(core.quota_victim("logs/3", 6).as_deref(), Some("logs/1"));
+    core.remove("logs/1");
+    assert_eq!(core.quota_victim("logs/3", 15), None);
+    // New rules are counted afresh.
+    core.set_policies(quota(10));
+    assert_eq!(core.debug_validate(), Ok(()));
+    assert_eq!(core.quota_victim("logs/3", 1).as_deref(), Some("logs/2"));
+    core.clear();
+    assert_eq!(core.quota_victim("logs/3", 10), None);
+    assert_eq!(core.debug_validate(), Ok(()));
+}
+
+#[test]
+fn test_admission_follows_the_given_clock() {
+    let window = Duration::from_secs(300);
+    let mut core = CacheCore::new(
+        100,
+        PolicySet::default(),
+        AdmissionPolicy::SecondHit { window },
+    );
+    let start = Instant::now();
+    assert!(!core.access("a", 10, start).admitted);
+    assert!(!core.access("b", 10, start).admitted);
+    assert!(core.access("a", 10, start + window).admitted);
+    assert!(!core.access("b", 10, start + window * 2).admitted);
+    assert!(core.access("a", 10, start + window * 2).hit);
+    // Forced admission skips the policy, unless a prefix policy refuses the key.
+    assert!(core.admits("c", true, start));
+    let mut refusing = CacheCore::new(
+        100,
+        PolicySet::new(vec![PrefixPolicy {
+            admit: false,
+            ..PrefixPolicy::new("logs/")
+        }]),
+        AdmissionPolicy::Always,
+    );
+    assert!(!refusing.admits("logs/1", true, start));
+}
+
+#[test]
+fn test_charges_and_clear() {
+    let mut core = CacheCore::new(30, PolicySet::default(), AdmissionPolicy::Always);
+    core.insert(String::from("a"), 10);
+    core.insert(String::from("b"), 10);
+    core.touch("a");
+    assert_eq!(core.find(|_| true), Some(String::from("b")));
+    assert_eq!(core.remove("b"), Some((String::from("b"), 10)));
+    assert_eq!(core.remove("b"), None);
+    // Inserting a name again replaces the entry and its charge.
+    core.insert(String::from("a"), 10);
+    assert_eq!(core.current_size(), 10);
+    core.charge(5);
+    assert!(core.overflows(16));
+    core.refund(5);
+    assert!(!core.overflows(20));
+    assert_eq!(core.clear(), [(String::from("a"), 10)]);
+    assert!(core.is_empty());
+    assert_eq!(core.current_size(), 0);
+}
+
+#[test]
+fn test_sampled_eviction() {
+    let mut core = CacheCore::new(1000, PolicySet::default(), AdmissionPolicy::Always);
+    for i in 0..100 {
+        core.insert(format!("k{}", i), 10);
+    }
+    // Touched through the index, as a hit served without the shard lock does.
+    let index = core.index().clone();
+    for i in 0..10 {
+        index.touch(&format!("k{}", i));
+    }
+    let evicted = core.make_room("new", 500);
+    assert_eq!(evicted.len(), 50);
+    assert_eq!(core.current_size(), 500);
+    assert_eq!(core.debug_validate(), Ok(()));
+    // Victims come from samples, so they are older on average, not strictly the oldest.
+    let age = |names: Vec<String>| {
+        let ranks: Vec<usize> = names
+            .iter()
+            .map(|name| (name[1..].parse::<usize>().unwrap() + 90) % 100)
+            .collect();
+        ranks.iter().sum::<usize>() as f64 / ranks.len() as f64
+    };
+    let evicted_age = age(evicted.into_iter().map(|(name, _)| name).collect());
+    let kept_age = age(core.entries().into_iter().map(|(name, _)| name).collect());
+    assert!(evicted_age < kept_age, "{} vs {}", evicted_age, kept_age);
+}
+
+#[test]
+fn test_slot_counts_and_shards() {
+    let counts = slot_counts(&[1, 1, 2]);
+    assert_eq!(counts.iter().sum::<usize>(), SLOT_COUNT as usize);
+    assert_eq!(counts, [4096, 4096, 8192]);
+    assert!((0..100).all(|i| shard_index(&format!("key-{}", i), 3) < 3));
+    assert_eq!(shard_index("orders.csv", 7), shard_index("orders.csv", 7));
+}
@@ -0,0 +1,40 @@
+use istziio_server_node::cache::{CachedFile, SERVE_CHUNK_SIZE};
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use rocket::{get, routes, Build, Rocket, State};
+use std::path::PathBuf;
+
+struct Dir(PathBuf);
+
+#[get("/files/<name>")]
+async fn file(name: &str, dir: &State<Dir>) -> Option<CachedFile> {
+    CachedFile::open(dir.0.join(name)).await.ok()
+}
+
+fn server(dir: PathBuf) -> Rocket<Build> {
+    rocket::build().manage(Dir(dir)).mount("/", routes![file])
+}
+
+#[rocket::async_test]
+async fn test_cached_file_is_sent_sized() {
+    let dir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..3 * SERVE_CHUNK_SIZE + 7).map(|i| i as u8).collect();
+    std::fs::write(dir.path().join("object.json"), &data).unwrap();
+    let client = Client::tracked(server(dir.path().to_path_buf()))
+        .await
+        .unwrap();
+
+    let response = client.get("/files/object.json").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    assert_eq!(response.body().preset_size(), Some(data.len()));
+    assert_eq!(response.body().max_chunk_size(), SERVE_CHUNK_SIZE);
+    assert_eq!(response.into_bytes().await.unwrap(), data);
+
+    std::fs::write(dir.path().join("object"), b"").unwrap();
+    let response = client.get("/files/object").dispatch().await;
+    assert_eq!(response.content_type(), Some(ContentType::Binary));
+    assert!(response.into_bytes().await.unwrap().is_empty());
+    let response = client.get("/files/missing").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,29 @@
+use istziio_server_node::chunk::{chunk_bounds, total_from_content_range, ByteRange};
+
+#[test]
+fn test_parse_byte_range() {
+    assert_eq!("bytes=0-99".parse(), Ok(ByteRange::FromTo(0, 99)));
+    assert_eq!("bytes=100-".parse(), Ok(ByteRange::From(100)));
+    assert_eq!("bytes=-500".parse(), Ok(ByteRange::Suffix(500)));
+    assert!("bytes=0-1,5-6".parse::<ByteRange>().is_err());
+    assert!("bytes=9-1".parse::<ByteRange>().is_err());
+    assert!("items=0-1".parse::<ByteRange>().is_err());
+    assert_eq!(ByteRange::Suffix(500).to_string(), "bytes=-500");
+}
+
+#[test]
+fn test_resolve_byte_range() {
+    assert_eq!(ByteRange::FromTo(10, 5000).resolve(100), Some((10, 99)));
+    assert_eq!(ByteRange::From(99).resolve(100), Some((99, 99)));
+    assert_eq!(ByteRange::From(100).resolve(100), None);
+    assert_eq!(ByteRange::Suffix(500).resolve(100), Some((0, 99)));
+    assert_eq!(ByteRange::Suffix(0).resolve(100), None);
+}
+
+#[test]
+fn test_chunk_bounds() {
+    assert_eq!(chunk_bounds(0, 8, 20), (0, 7));
+    assert_eq!(chunk_bounds(2, 8, 20), (16, 19));
+    assert_eq!(total_from_content_range("bytes 0-7/20"), Some(20));
+    assert_eq!(total_from_content_range("bytes */*"), None);
+}
@@ -0,0 +1,117 @@
+use istziio_server_node::cache::{CacheSnapshot, ShardSnapshot, ShardStats};
+use istziio_server_node::cluster::{ClusterStats, NodeReport, NodeStats, PeerClient};
+use istziio_server_node::scrub::ScrubReport;
+use istziio_server_node::telemetry::TraceContext;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use url::Url;
+
+fn shard(index: usize, disk_hits: u64, misses: u64, current_size: u64) -> ShardSnapshot {
+    ShardSnapshot {
+        index,
+        current_size,
+        max_size: 1000,
+        files: 2,
+        stats: ShardStats {
+            disk_hits,
+            misses,
+            bytes_from_s3: 10,
+            ..ShardStats::default()
+        },
+        ..ShardSnapshot::default()
+    }
+}
+
+fn report(node_id: &str, stats: Option<NodeStats>) -> NodeReport {
+    NodeReport {
+        node_id: String::from(node_id),
+        address: String::from("localhost:26379"),
+        is_self: false,
+        error: stats.is_none().then(|| String::from("timed out")),
+        stats,
+    }
+}
+
+#[test]
+fn test_node_stats_sum_shards() {
+    let snapshot = CacheSnapshot {
+        taken_at: chrono::Utc::now(),
+        shards: vec![shard(0, 3, 1, 100), shard(1, 0, 4, 50)],
+        members: Vec::new(),
+        disks: Vec::new(),
+        scrubbed: ScrubReport {
+            missing_files: 2,
+            ..ScrubReport::default()
+        },
+    };
+    let stats = NodeStats::from_snapshot(&snapshot);
+    assert_eq!(stats.scrubbed.missing_files, 2);
+    assert_eq!(stats.disk_hits, 3);
+    assert_eq!(stats.misses, 5);
+    assert_eq!(stats.current_size, 150);
+    assert_eq!(stats.max_size, 2000);
+    assert_eq!(stats.files, 4);
+    assert_eq!(stats.bytes_from_s3, 20);
+    assert!((stats.hit_ratio - 37.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_cluster_total_skips_silent_nodes() {
+    let node = |hits, misses| NodeStats {
+        memory_hits: hits,
+        misses,
+        current_size: 10,
+        ..NodeStats::default()
+    };
+    let cluster = ClusterStats::new(vec![
+        report("a", Some(node(3, 1))),
+        report("b", Some(node(1, 3))),
+        report("c", None),
+    ]);
+    assert_eq!(cluster.total.memory_hits, 4);
+    assert_eq!(cluster.total.current_size, 20);
+    assert!((cluster.total.hit_ratio - 50.0).abs() < 1e-9);
+    assert_eq!(cluster.nodes[2].error.as_deref(), Some("timed out"));
+}
+
+#[tokio::test]
+async fn test_fan_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let up = Url::parse(&format!(
+        "http://{}/stats/json",
+        listener.local_addr().unwrap()
+    ))
+    .unwrap();
+    let trace = TraceContext::new();
+    let traceparent = format!("traceparent: 00-{}-", trace.trace_id);
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 4096];
+        let n = socket.read(&mut buf).await.unwrap();
+        let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+        let body = match (
+            request.contains("authorization: bearer t"),
+            request.contains(&traceparent),
+        ) {
+            (true, true) => "authorized, traced",
+            (true, false) => "authorized",
+            _ => "anonymous",
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+    });
+    let down = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap()
+    };
+
+    let answers = PeerClient::default()
+        .fan_out(reqwest::Method::GET, &[up, down], Some("t"), &trace)
+        .await;
+    assert_eq!(answers[0], Ok(String::from("authorized, traced")));
+    assert!(answers[1].is_err());
+}
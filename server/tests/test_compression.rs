@@ -0,0 +1,38 @@
+use istziio_server_node::compression::{
+    compress_file, decompress_file, CompressionCodec, CompressionConfig,
+};
+
+#[test]
+fn test_compression_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let content = "istziio ".repeat(4096);
+    for codec in [CompressionCodec::Zstd, CompressionCodec::Lz4] {
+        let path = dir.path().join(format!("{}.csv", codec.content_encoding()));
+        std::fs::write(&path, &content).unwrap();
+        let physical_size = compress_file(codec, &path).unwrap().unwrap();
+        assert!(physical_size < content.len() as u64);
+        assert_eq!(
+            &decompress_file(codec, &path).unwrap()[..],
+            content.as_bytes()
+        );
+    }
+}
+
+#[test]
+fn test_incompressible_file_is_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tiny.bin");
+    std::fs::write(&path, b"x").unwrap();
+    assert_eq!(compress_file(CompressionCodec::Zstd, &path).unwrap(), None);
+    assert_eq!(std::fs::read(&path).unwrap(), b"x");
+}
+
+#[test]
+fn test_compression_config() {
+    let config = CompressionConfig::new(CompressionCodec::Zstd);
+    assert!(config.applies_to("orders.csv", 1 << 20));
+    assert!(!config.applies_to("orders.csv", 10));
+    assert!(!config.applies_to("orders.csv.gz", 1 << 20));
+    assert!(CompressionCodec::Zstd.accepted_by("gzip, zstd;q=0.9"));
+    assert!(!CompressionCodec::Lz4.accepted_by("gzip, zstd"));
+}
@@ -0,0 +1,192 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, CachedFile, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::conditional::{
+    http_date, parse_http_date, NotModified, Preconditions, Versioned,
+};
+use istziio_server_node::error::CacheError;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{
+    FetchedObject, ObjectVersion, StorageConnector,
+};
+use rocket::futures::stream;
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket::{get, routes};
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const MODIFIED: i64 = 1_700_000_000;
+
+fn version() -> ObjectVersion {
+    ObjectVersion {
+        e_tag: Some(String::from("\"v1\"")),
+        last_modified: Some(MODIFIED),
+    }
+}
+
+/// Answers every fetch with the key itself, at version `"v1"`.
+struct VersionedConnector;
+
+#[async_trait]
+impl StorageConnector for VersionedConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: version(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn if_none_match(tags: &str) -> Preconditions {
+    Preconditions {
+        if_none_match: Some(String::from(tags)),
+        ..Preconditions::default()
+    }
+}
+
+fn if_modified_since(secs: i64) -> Preconditions {
+    Preconditions {
+        if_modified_since: Some(secs),
+        ..Preconditions::default()
+    }
+}
+
+#[test]
+fn test_http_dates() {
+    assert_eq!(
+        http_date(784111777).as_deref(),
+        Some("Sun, 06 Nov 1994 08:49:37 GMT")
+    );
+    assert_eq!(
+        parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+        Some(784111777)
+    );
+    assert_eq!(parse_http_date("yesterday"), None);
+}
+
+#[test]
+fn test_not_modified() {
+    let version = version();
+    assert!(if_none_match("\"v1\"").not_modified(&version));
+    assert!(if_none_match("\"v0\", W/\"v1\"").not_modified(&version));
+    assert!(if_none_match("*").not_modified(&version));
+    assert!(!if_none_match("\"v0\"").not_modified(&version));
+    assert!(if_modified_since(MODIFIED).not_modified(&version));
+    assert!(!if_modified_since(MODIFIED - 1).not_modified(&version));
+    // If-None-Match decides when both are sent.
+    let both = Preconditions {
+        if_modified_since: Some(MODIFIED),
+        ..if_none_match("\"v0\"")
+    };
+    assert!(!both.not_modified(&version));
+    // Without validators nothing matches.
+    assert!(!if_none_match("*").not_modified(&ObjectVersion::default()));
+    assert!(!Preconditions::default().not_modified(&version));
+}
+
+#[tokio::test]
+async fn test_cached_version_answers_conditional_gets() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let get = |preconditions: Preconditions| {
+        cache.get_file(
+            PathBuf::from("plan.parquet"),
+            Arc::new(VersionedConnector),
+            GetFileOptions {
+                preconditions,
+                ..GetFileOptions::default()
+            },
+        )
+    };
+    // A conditional miss is served in full.
+    assert!(matches!(
+        get(if_none_match("\"v1\"")).await,
+        GetFileResult::Hit(_)
+    ));
+    assert_eq!(cache.object_version("plan.parquet").await, Some(version()));
+
+    match get(if_none_match("\"v1\"")).await {
+        GetFileResult::NotModified(NotModified(held)) => assert_eq!(held, version()),
+        _ => panic!("expected 304"),
+    }
+    assert!(matches!(
+        get(if_modified_since(MODIFIED)).await,
+        GetFileResult::NotModified(_)
+    ));
+    assert!(matches!(
+        get(if_none_match("\"v0\"")).await,
+        GetFileResult::Hit(_)
+    ));
+
+    cache.invalidate("plan.parquet").await;
+    assert_eq!(cache.object_version("plan.parquet").await, None);
+    assert!(matches!(
+        get(if_none_match("\"v1\"")).await,
+        GetFileResult::Hit(_)
+    ));
+}
+
+#[get("/hit")]
+async fn hit() -> Versioned {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("object");
+    std::fs::write(&path, b"contents").unwrap();
+    let file = CachedFile::open(&path).await.unwrap();
+    Versioned(GetFileResult::Hit(file), Some(version()))
+}
+
+#[get("/missing")]
+fn missing() -> Versioned {
+    Versioned(
+        GetFileResult::Error(CacheError::not_found("missing")),
+        Some(version()),
+    )
+}
+
+#[get("/unchanged")]
+fn unchanged() -> Versioned {
+    Versioned(GetFileResult::NotModified(NotModified(version())), None)
+}
+
+#[test]
+fn test_version_headers() {
+    let client =
+        Client::tracked(rocket::build().mount("/", routes![hit, missing, unchanged])).unwrap();
+    let last_modified = http_date(MODIFIED).unwrap();
+
+    let response = client.get("/hit").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"v1\""));
+    assert_eq!(
+        response.headers().get_one("Last-Modified"),
+        Some(last_modified.as_str())
+    );
+    assert_eq!(response.into_string().as_deref(), Some("contents"));
+
+    let response = client.get("/missing").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(response.headers().get_one("ETag"), None);
+
+    let response = client.get("/unchanged").dispatch();
+    assert_eq!(response.status(), Status::NotModified);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"v1\""));
+    assert!(response.into_bytes().unwrap_or_default().is_empty());
+}
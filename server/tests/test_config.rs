@@ -0,0 +1,527 @@
+use istziio_server_node::admission::AdmissionPolicy;
+use istziio_server_node::compression::CompressionCodec;
+use istziio_server_node::config::{apply_env_overrides, parse_config, ConfigError, ConfigUpdate};
+use istziio_server_node::disk_io::DiskBackend;
+use istziio_server_node::download::DisconnectPolicy;
+use istziio_server_node::redis::RedisMode;
+use istziio_server_node::ring::Placement;
+use istziio_server_node::server::{ServerNode, StartupError};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[test]
+fn test_parse_config() {
+    let config = parse_config(
+        r#"
+        redis_port = 6380
+        redis_addrs = ["redis://10.0.0.1:6379", "redis://10.0.0.2:6379"]
+        max_size = 1073741824
+        bucket_size = 4
+        use_mock_s3_endpoint = "http://localhost:6333"
+        admission_policy = "second-hit:60"
+
+        [compression]
+        codec = "zstd"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.redis_port, 6380);
+    assert_eq!(config.redis_addrs.len(), 2);
+    assert_eq!(config.max_size, 1 << 30);
+    assert_eq!(
+        config.admission_policy,
+        AdmissionPolicy::SecondHit {
+            window: Duration::from_secs(60)
+        }
+    );
+    let compression = config.compression.as_ref().unwrap();
+    assert_eq!(compression.codec, CompressionCodec::Zstd);
+    assert_eq!(compression.min_size, 4096);
+    // Unspecified keys keep their defaults.
+    assert_eq!(config.server_ip, "localhost");
+    assert!(config.validate().is_ok());
+
+    assert!(parse_config("max_sise = 10").is_err());
+    assert!(parse_config("admission_policy = \"sometimes\"").is_err());
+}
+
+#[test]
+fn test_env_overrides() {
+    let env: HashMap<&str, &str> = [
+        ("ISTZIIO_MAX_SIZE", "4096"),
+        ("ISTZIIO_REDIS_ADDRS", "redis://a:1, redis://b:2"),
+        ("AWS_ACCESS_KEY_ID", "aws-key"),
+        ("ISTZIIO_SECRET_KEY", "prefixed-secret"),
+        ("AWS_SECRET_ACCESS_KEY", "aws-secret"),
+        ("ISTZIIO_ENCRYPTION_KEY_FILE", "/etc/istziio/cache.key"),
+        ("ISTZIIO_INVALIDATION_CHANNEL", "reloads"),
+        ("ISTZIIO_REDIS_NAMESPACE", "analytics"),
+        ("ISTZIIO_LOCATION_LEASE_SECS", "300"),
+        ("ISTZIIO_WEB_PORT", "8080"),
+        ("ISTZIIO_ADVERTISE_ADDR", "cache-0.cache:8080"),
+        ("ISTZIIO_ADVERTISE_HOST", "cache-0.cache"),
+        ("ISTZIIO_SELF_TEST", "false"),
+        ("ISTZIIO_LOCATION_CACHE_TTL_MS", "250"),
+        ("ISTZIIO_SLOT_REFRESH_INTERVAL_SECS", "0"),
+        ("ISTZIIO_PLACEMENT", "ring"),
+        ("ISTZIIO_VNODES_PER_NODE", "32"),
+        ("ISTZIIO_CAPACITY_WEIGHT", "4"),
+        ("ISTZIIO_REBALANCE_MAX_BYTES_PER_SEC", "1048576"),
+        ("ISTZIIO_STREAM_FETCHES_FROM", "8388608"),
+        ("ISTZIIO_HEDGE_AFTER_MS", "50"),
+        ("ISTZIIO_MAX_BATCH_S3_FETCHES", "8"),
+        ("ISTZIIO_EGRESS_BATCH_BYTES_PER_SEC", "1048576"),
+        ("ISTZIIO_EMBEDDED_REDIS_DIR", "/var/lib/istziio/redis"),
+        ("ISTZIIO_REDIS_MODE", "standalone"),
+        ("ISTZIIO_ON_CLIENT_DISCONNECT", "abort"),
+        ("ISTZIIO_S3_FETCH_TIMEOUT_MS", "5000"),
+        ("ISTZIIO_DISK_IO", "io-uring"),
+        ("ISTZIIO_DROP_PAGE_CACHE_FROM", "67108864"),
+        ("ISTZIIO_EVICTION_LOW_WATERMARK", "0.7"),
+        ("ISTZIIO_FREE_SPACE_RESERVE", "1073741824"),
+        ("ISTZIIO_SCRUB_INTERVAL_SECS", "3600"),
+        ("ISTZIIO_WRITE_BACK_DIR", "/var/lib/istziio/journal"),
+        (
+            "ISTZIIO_FETCH_SOURCES",
+            "https://a.s3.amazonaws.com/, https://b.s3.amazonaws.com/exports/",
+        ),
+        ("ISTZIIO_LIST_CACHE_TTL_SECS", "30"),
+        ("ISTZIIO_SHARE_LIST_CACHE", "true"),
+        ("ISTZIIO_WARM_UP_MANIFEST", "/etc/istziio/warm.txt"),
+        ("ISTZIIO_RESTORE_SNAPSHOT", "20261015T120000Z"),
+        ("ISTZIIO_MOCK_S3_LATENCY_MS", "30"),
+        ("ISTZIIO_PIN_SHARD_THREADS", "true"),
+        ("ISTZIIO_MOCK_S3_GENERATE", "1024-1048576"),
+        ("ISTZIIO_OTLP_ENDPOINT", "http://collector:4318"),
+        (
+            "ISTZIIO_CACHE_DIRS",
+            "/mnt/nvme0/cache:1000,/mnt/nvme1/cache:2000",
+        ),
+    ]
+    .iter()
+    .copied()
+    .collect();
+    let mut config = parse_config("max_size = 10").unwrap();
+    apply_env_overrides(&mut config, |var| env.get(var).map(|v| v.to_string())).unwrap();
+    assert_eq!(config.max_size, 4096);
+    assert_eq!(config.redis_addrs, vec!["redis://a:1", "redis://b:2"]);
+    assert_eq!(config.access_key.as_deref(), Some("aws-key"));
+    assert_eq!(config.secret_key.as_deref(), Some("prefixed-secret"));
+    assert_eq!(config.invalidation_channel.as_deref(), Some("reloads"));
+    assert_eq!(config.redis_namespace.as_deref(), Some("analytics"));
+    assert_eq!(config.location_lease_secs, 300);
+    assert_eq!(config.web_port(), 8080);
+    assert_eq!(config.advertise_addr.as_deref(), Some("cache-0.cache:8080"));
+    assert_eq!(config.advertise_host.as_deref(), Some("cache-0.cache"));
+    assert!(!config.self_test);
+    let location_cache = config.location_cache.unwrap();
+    assert_eq!(location_cache.ttl_ms, 250);
+    assert_eq!(location_cache.capacity, 100_000);
+    assert_eq!(config.slot_refresh_interval_secs, 0);
+    assert_eq!(config.placement, Placement::Ring);
+    assert_eq!(config.vnodes_per_node, 32);
+    assert_eq!(config.capacity_weight, 4);
+    assert_eq!(config.rebalance.unwrap().max_bytes_per_sec, 1048576);
+    assert_eq!(config.stream_fetches_from, Some(8388608));
+    assert_eq!(config.hedge_after_ms, Some(50));
+    assert_eq!(config.max_batch_s3_fetches, Some(8));
+    let egress = config.egress.unwrap();
+    assert_eq!(egress.batch_bytes_per_sec, Some(1048576));
+    assert_eq!(egress.per_request_bytes_per_sec, None);
+    let embedded_redis = config.embedded_redis.clone().unwrap();
+    assert_eq!(embedded_redis.dir, "/var/lib/istziio/redis");
+    assert_eq!(embedded_redis.binary, "redis-server");
+    assert_eq!(config.redis_mode, RedisMode::Standalone);
+    assert_eq!(config.on_client_disconnect, DisconnectPolicy::Abort);
+    assert_eq!(config.timeouts.s3_fetch_ms, Some(5000));
+    assert_eq!(config.timeouts.metadata_ms, None);
+    assert_eq!(config.disk_io, DiskBackend::IoUring);
+    assert_eq!(config.drop_page_cache_from, Some(67108864));
+    let eviction = config.eviction.unwrap();
+    assert_eq!(eviction.low_watermark, 0.7);
+    assert_eq!(eviction.high_watermark, 0.9);
+    assert_eq!(config.free_space_reserve, Some(1073741824));
+    assert_eq!(config.scrub_interval_secs, 3600);
+    let write_back = config.write_back.clone().unwrap();
+    assert_eq!(write_back.dir, "/var/lib/istziio/journal");
+    assert_eq!(write_back.workers, 4);
+    assert_eq!(config.fetch_sources.len(), 2);
+    assert_eq!(
+        config.fetch_sources[1],
+        "https://b.s3.amazonaws.com/exports/"
+    );
+    assert_eq!(config.list_cache_ttl_secs, 30);
+    assert!(config.share_list_cache);
+    let warm_up = config.warm_up.clone().unwrap();
+    assert_eq!(warm_up.manifest.as_deref(), Some("/etc/istziio/warm.txt"));
+    assert_eq!(warm_up.concurrency, 4);
+    let snapshots = config.snapshots.clone().unwrap();
+    assert_eq!(snapshots.restore_from.as_deref(), Some("20261015T120000Z"));
+    assert_eq!(snapshots.prefix, "_istziio/snapshots/");
+    assert!(config.shard_runtimes.unwrap().pin_cores);
+    let mock_s3 = config.mock_s3.clone().unwrap();
+    assert_eq!(mock_s3.first_byte_latency_ms, 30);
+    assert_eq!(mock_s3.bytes_per_sec, None);
+    assert_eq!(mock_s3.generate.unwrap().max_size, 1048576);
+    assert_eq!(config.cache_dirs.len(), 2);
+    assert_eq!(config.cache_dirs[1].path, "/mnt/nvme1/cache");
+    assert_eq!(config.total_max_size(), 3000);
+    assert_eq!(
+        config
+            .encryption
+            .as_ref()
+            .map(|encryption| encryption.key_file.as_str()),
+        Some("/etc/istziio/cache.key")
+    );
+    let tracing = config.tracing.clone().unwrap();
+    assert_eq!(
+        tracing.otlp_endpoint.as_deref(),
+        Some("http://collector:4318")
+    );
+    assert_eq!(tracing.min_span_ms, 0);
+
+    let err = apply_env_overrides(&mut config, |var| {
+        (var == "ISTZIIO_BUCKET_SIZE").then(|| "many".to_string())
+    })
+    .unwrap_err();
+    assert!(matches!(err, ConfigError::Env { .. }));
+}
+
+#[test]
+fn test_validate() {
+    let mock = "use_mock_s3_endpoint = \"http://localhost:6333\"\n";
+    assert!(parse_config(&format!("{}bucket_size = 0", mock))
+        .unwrap()
+        .validate()
+        .is_err());
+    assert!(parse_config(&format!("{}chunk_size = 0", mock))
+        .unwrap()
+        .validate()
+        .is_err());
+    assert!(parse_config(&format!("{}hedge_after_ms = 0", mock))
+        .unwrap()
+        .validate()
+        .is_err());
+    assert!(parse_config(&format!(
+        "{}max_concurrent_s3_fetches = 4\nmax_batch_s3_fetches = 8",
+        mock
+    ))
+    .unwrap()
+    .validate()
+    .is_err());
+    assert!(parse_config(&format!(
+        "{}[embedded_redis]\nhealth_check_interval_ms = 0",
+        mock
+    ))
+    .unwrap()
+    .validate()
+    .is_err());
+    assert!(
+        parse_config(&format!("{}[egress]\nbatch_bytes_per_sec = 0", mock))
+            .unwrap()
+            .validate()
+            .is_err()
+    );
+    assert!(
+        parse_config(&format!("{}[timeouts]\ns3_fetch_ms = 0", mock))
+            .unwrap()
+            .validate()
+            .is_err()
+    );
+    assert!(parse_config("bucket = \"b\"").unwrap().validate().is_err());
+    assert!(parse_config(&format!(
+        "{}placement = \"ring\"\nvnodes_per_node = 0",
+        mock
+    ))
+    .unwrap()
+    .validate()
+    .is_err());
+    assert!(parse_config("placement = \"rendezvous\"").is_err());
+    let standalone = format!("{}redis_mode = \"standalone\"\n", mock);
+    let config = parse_config(&standalone).unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.placement(), Placement::Ring);
+    for bad in [
+        "redis_addrs = [\"redis://a:6379\", \"redis://b:6379\"]",
+        "slot_refresh_interval_secs = 0",
+        "metadata_store = \"in-process\"",
+    ] {
+        assert!(parse_config(&format!("{}{}", standalone, bad))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+    assert!(parse_config("redis_mode = \"sentinel\"").is_err());
+    assert!(parse_config(&format!("{}capacity_weight = 0", mock))
+        .unwrap()
+        .validate()
+        .is_err());
+    let eviction = format!("{}[eviction]\n", mock);
+    assert!(parse_config(&eviction).unwrap().validate().is_ok());
+    for bad in [
+        "low_watermark = 0.95",
+        "high_watermark = 1.5",
+        "interval_ms = 0",
+    ] {
+        assert!(parse_config(&format!("{}{}", eviction, bad))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+    let rebalance = format!("{}[rebalance]\n", mock);
+    assert!(parse_config(&rebalance).unwrap().validate().is_err());
+    let rebalance = format!("admin_token = \"secret\"\n{}", rebalance);
+    assert!(parse_config(&rebalance).unwrap().validate().is_ok());
+    assert!(parse_config(&format!("{}max_bytes_per_sec = 0", rebalance))
+        .unwrap()
+        .validate()
+        .is_err());
+    let write_back = format!("{}[write_back]\n", mock);
+    assert!(parse_config(&write_back).unwrap().validate().is_err());
+    let write_back = format!("admin_token = \"secret\"\n{}", write_back);
+    assert!(parse_config(&write_back).unwrap().validate().is_ok());
+    for bad in [
+        "workers = 0",
+        "retry_delay_ms = 0",
+        "max_retry_delay_ms = 10",
+        "dir = \"./cache_6379\"",
+        "dir = \"./cache_6379/journal\"",
+    ] {
+        assert!(parse_config(&format!("{}{}", write_back, bad))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+    assert!(
+        parse_config(&format!("{}fetch_sources = [\"s3://bucket/\"]", mock))
+            .unwrap()
+            .validate()
+            .is_err()
+    );
+    let warm_up = format!("{}[warm_up]\n", mock);
+    assert!(parse_config(&warm_up).unwrap().validate().is_err());
+    let warm_up = format!("{}prefixes = [\"tables/\"]\n", warm_up);
+    assert!(parse_config(&warm_up).unwrap().validate().is_ok());
+    for bad in ["concurrency = 0", "max_bytes_per_sec = 0"] {
+        assert!(parse_config(&format!("{}{}", warm_up, bad))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+    let snapshots = format!("{}[snapshots]\n", mock);
+    assert!(parse_config(&snapshots).unwrap().validate().is_ok());
+    for bad in [
+        "prefix = \"snapshots\"",
+        "concurrency = 0",
+        "restore_from = \"a/b\"",
+    ] {
+        assert!(parse_config(&format!("{}{}", snapshots, bad))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+    let grpc = format!("{}[grpc]\n", mock);
+    assert!(parse_config(&grpc).unwrap().validate().is_ok());
+    assert!(parse_config(&format!("{}port_offset = 0", grpc))
+        .unwrap()
+        .validate()
+        .is_err());
+    let tracing = format!("{}[tracing]\n", mock);
+    assert!(parse_config(&format!(
+        "{}otlp_endpoint = \"http://collector:4318\"",
+        tracing
+    ))
+    .unwrap()
+    .validate()
+    .is_ok());
+    assert!(
+        parse_config(&format!("{}otlp_endpoint = \"collector:4318\"", tracing))
+            .unwrap()
+            .validate()
+            .is_err()
+    );
+    let mock_s3 = format!("{}[mock_s3]\nfirst_byte_latency_ms = 20\n", mock);
+    assert!(parse_config(&mock_s3).unwrap().validate().is_ok());
+    for bad in [
+        "error_rate = 1.5",
+        "bytes_per_sec = 0",
+        "generate = { min_size = 10, max_size = 1 }",
+    ] {
+        assert!(parse_config(&format!("{}{}", mock_s3, bad))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+    let shared = format!("{}share_list_cache = true\n", mock);
+    assert!(parse_config(&shared).unwrap().validate().is_err());
+    let shared = format!("{}list_cache_ttl_secs = 30\n", shared);
+    assert!(parse_config(&shared).unwrap().validate().is_ok());
+    assert!(
+        parse_config(&format!("{}metadata_store = \"in-process\"\n", shared))
+            .unwrap()
+            .validate()
+            .is_err()
+    );
+    let namespaced = format!("{}redis_namespace = \"analytics\"\n", mock);
+    assert!(parse_config(&namespaced).unwrap().validate().is_ok());
+    assert!(parse_config(&namespaced.replace("analytics", ""))
+        .unwrap()
+        .validate()
+        .is_err());
+    assert!(
+        parse_config(&format!("{}metadata_store = \"in-process\"\n", namespaced))
+            .unwrap()
+            .validate()
+            .is_err()
+    );
+    let cached = format!("{}[location_cache]\nttl_ms = 500\n", mock);
+    assert!(parse_config(&cached).unwrap().validate().is_ok());
+    assert!(parse_config(&cached.replace("500", "0"))
+        .unwrap()
+        .validate()
+        .is_err());
+    let advertised = format!("{}advertise_addr = \"cache-0.cache:8080\"\n", mock);
+    assert!(parse_config(&advertised).unwrap().validate().is_ok());
+    for bad in ["cache-0.cache", ":8080", "cache-0.cache:http"] {
+        assert!(parse_config(&advertised.replace("cache-0.cache:8080", bad))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+    let named = format!("{}advertise_host = \"cache-0.cache\"\n", mock);
+    let config = parse_config(&named).unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.advertised_address(),
+        Some(format!("cache-0.cache:{}", config.web_port()))
+    );
+    for bad in ["", "cache-0.cache:8080", "cache 0"] {
+        assert!(parse_config(&named.replace("cache-0.cache", bad))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+    let leased = format!("{}location_lease_secs = 60\n", mock);
+    assert!(parse_config(&leased).unwrap().validate().is_ok());
+    assert!(parse_config(&leased.replace("60", "2"))
+        .unwrap()
+        .validate()
+        .is_err());
+    assert!(
+        parse_config(&format!("{}metadata_store = \"in-process\"\n", leased))
+            .unwrap()
+            .validate()
+            .is_err()
+    );
+    let shadow = format!(
+        "{}[[shadow_caches]]\nname = \"double\"\nmax_size = 384\n",
+        mock
+    );
+    assert!(parse_config(&shadow).unwrap().validate().is_ok());
+    assert!(parse_config(&format!("{}policy = \"mru\"", shadow)).is_err());
+    for bad in [
+        format!(
+            "{}[[shadow_caches]]\nname = \"double\"\npolicy = \"lfu\"\n",
+            shadow
+        ),
+        shadow.replace("384", "2"),
+        shadow.replace("\"double\"", "\"\""),
+    ] {
+        assert!(parse_config(&bad).unwrap().validate().is_err());
+    }
+    let disks = format!(
+        "{}bucket_size = 3\nmax_size = 1\n[[cache_dirs]]\npath = \"/mnt/a\"\nmax_size = 3\n",
+        mock
+    );
+    let config = parse_config(&disks).unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.total_max_size(), 3);
+    let two_bytes_for_three_shards = disks.replace("max_size = 3", "max_size = 2");
+    assert!(parse_config(&two_bytes_for_three_shards)
+        .unwrap()
+        .validate()
+        .is_err());
+    let same_path_twice = format!("{}[[cache_dirs]]\npath = \"/mnt/a\"\nmax_size = 2\n", disks);
+    assert!(parse_config(&same_path_twice).unwrap().validate().is_err());
+    let more_disks_than_shards = disks.replace("bucket_size = 3", "bucket_size = 1")
+        + "[[cache_dirs]]\npath = \"/mnt/b\"\nmax_size = 2\n";
+    assert!(parse_config(&more_disks_than_shards)
+        .unwrap()
+        .validate()
+        .is_err());
+    let etcd = format!("{}metadata_store = \"etcd\"\n", mock);
+    assert!(parse_config(&etcd).unwrap().validate().is_err());
+    assert!(parse_config(&format!(
+        "{}server_ip = \"10.0.0.1\"\n[etcd]\nendpoints = [\"http://etcd:2379\"]",
+        etcd
+    ))
+    .unwrap()
+    .validate()
+    .is_ok());
+    assert!(parse_config(
+        "bucket = \"b\"\nregion_name = \"r\"\naccess_key = \"a\"\nsecret_key = \"s\""
+    )
+    .unwrap()
+    .validate()
+    .is_ok());
+}
+
+#[test]
+fn test_node_rejects_an_unusable_config() {
+    let config = parse_config(
+        r#"
+        metadata_store = "in-process"
+
+        [[backends]]
+        prefix = "gold/"
+        region_name = "us-east-1"
+        "#,
+    )
+    .unwrap();
+    match ServerNode::new(config) {
+        Err(StartupError::Config(ConfigError::Invalid(reason))) => {
+            assert!(reason.contains("bucket"), "{}", reason)
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("a backend without a bucket was accepted"),
+    }
+    let config = parse_config("metadata_store = \"in-process\"").unwrap();
+    assert!(matches!(
+        ServerNode::new(config),
+        Err(StartupError::Config(_))
+    ));
+}
+
+#[test]
+fn test_config_update() {
+    let update: ConfigUpdate = serde_json::from_str(
+        r#"{"max_size": 3000, "admission_policy": "always", "log_level": "info"}"#,
+    )
+    .unwrap();
+    assert_eq!(update.max_size, Some(3000));
+    assert_eq!(update.admission_policy, Some(AdmissionPolicy::Always));
+    assert!(update.validate(3).is_ok());
+    assert!(update.validate(4000).is_err());
+
+    let bad_level: ConfigUpdate = serde_json::from_str(r#"{"log_level": "loud"}"#).unwrap();
+    assert!(bad_level.validate(3).is_err());
+    assert!(serde_json::from_str::<ConfigUpdate>(r#"{"bucket_size": 4}"#).is_err());
+
+    let update: ConfigUpdate = serde_json::from_str(
+        r#"{
+            "policies": [{"pattern": "events/", "ttl_secs": 60}],
+            "eviction": {"high_watermark": 0.9, "low_watermark": 0.7}
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(update.policies.as_ref().unwrap()[0].ttl_secs, Some(60));
+    assert_eq!(update.eviction.unwrap().low_watermark, 0.7);
+    assert!(update.validate(3).is_ok());
+    let bad_ttl: ConfigUpdate =
+        serde_json::from_str(r#"{"policies": [{"pattern": "events/", "ttl_secs": 0}]}"#).unwrap();
+    assert!(bad_ttl.validate(3).is_err());
+    let bad_watermarks: ConfigUpdate =
+        serde_json::from_str(r#"{"eviction": {"high_watermark": 0.5, "low_watermark": 0.7}}"#)
+            .unwrap();
+    assert!(bad_watermarks.validate(3).is_err());
+}
@@ -0,0 +1,113 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::costs::{with_tenant, CostConfig, CostLedger};
+use istziio_server_node::storage::metered_storage_connector::MeteredStorageConnector;
+use istziio_server_node::storage::storage_connector::{
+    read_stream_to_end, FetchedObject, ListRequest, ObjectListing, StorageConnector,
+};
+use istziio_server_node::tenant::{TenantConfig, Tenants};
+use rocket::futures::{stream, StreamExt};
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::sync::Arc;
+
+struct Bucket;
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        if file_name.ends_with("missing") {
+            return Err(Error::new(ErrorKind::NotFound, "no such key"));
+        }
+        Ok(FetchedObject {
+            stream: Box::pin(stream::iter(vec![
+                Ok(Bytes::from_static(b"data")),
+                Ok(Bytes::from_static(b"more")),
+            ])),
+            content_length: Some(8),
+            object_size: Some(8),
+            version: Default::default(),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+
+    async fn list_objects(&self, _request: &ListRequest) -> IoResult<ObjectListing> {
+        Ok(ObjectListing::default())
+    }
+}
+
+fn ledger() -> Arc<CostLedger> {
+    Arc::new(CostLedger::new(
+        CostConfig {
+            get_per_1000: 1.0,
+            put_per_1000: 10.0,
+            transfer_per_gb: 0.0,
+        },
+        Tenants::new(vec![TenantConfig {
+            name: String::from("analytics"),
+            tokens: vec![String::from("secret")],
+            prefixes: vec![String::from("analytics/")],
+            max_bytes: None,
+        }]),
+    ))
+}
+
+#[tokio::test]
+async fn test_backing_store_traffic_is_charged_by_tenant_and_prefix() {
+    let ledger = ledger();
+    let connector = MeteredStorageConnector::new(Arc::new(Bucket), ledger.clone());
+
+    let object = connector.fetch_stream("analytics/a.parquet").await.unwrap();
+    assert_eq!(read_stream_to_end(object.stream).await.unwrap().len(), 8);
+    assert!(connector.fetch_stream("raw/missing").await.is_err());
+    // A tenant named by the request's token is charged whatever the prefix, for the
+    // bytes read before the body was dropped.
+    with_tenant(Some(String::from("analytics")), async {
+        let mut object = connector.fetch_stream("raw/b.parquet").await.unwrap();
+        object.stream.next().await.unwrap().unwrap();
+        drop(object);
+    })
+    .await;
+    connector
+        .list_objects(&ListRequest {
+            prefix: String::from("raw/2024/"),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    ledger.charge_to(None, "raw/c.parquet", |traffic| {
+        traffic.hits += 3;
+        traffic.bytes_saved += 300;
+    });
+
+    let report = ledger.report();
+    let row = |tenant: Option<&str>, prefix: &str| {
+        report
+            .rows
+            .iter()
+            .find(|row| row.tenant.as_deref() == tenant && row.prefix == prefix)
+            .unwrap()
+            .clone()
+    };
+    let analytics = row(Some("analytics"), "analytics/");
+    assert_eq!(analytics.traffic.gets, 1);
+    assert_eq!(analytics.traffic.bytes_fetched, 8);
+    let by_token = row(Some("analytics"), "raw/");
+    assert_eq!(by_token.traffic.gets, 1);
+    assert_eq!(by_token.traffic.bytes_fetched, 4);
+    let raw = row(None, "raw/");
+    assert_eq!(raw.traffic.gets, 0);
+    assert_eq!(raw.traffic.lists, 1);
+    assert_eq!(raw.traffic.hits, 3);
+    assert_eq!(raw.traffic.bytes_saved, 300);
+    assert!((raw.cost - 0.01).abs() < 1e-9);
+    assert!((raw.savings - 0.003).abs() < 1e-9);
+
+    // The LIST is the costliest row.
+    assert_eq!(report.rows[0].prefix, "raw/");
+    assert_eq!(report.total.traffic.gets, 2);
+    assert_eq!(report.total.traffic.hits, 3);
+}
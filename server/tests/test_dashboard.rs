@@ -0,0 +1,92 @@
+use istziio_server_node::cache::{
+    CacheSnapshot, ClusterMember, DiskSnapshot, ShardSnapshot, ShardStats,
+};
+use istziio_server_node::dashboard::{human_bytes, render};
+use istziio_server_node::disks::DiskStatus;
+use istziio_server_node::hotkeys::HotKey;
+use std::path::PathBuf;
+
+fn snapshot(members: Vec<ClusterMember>) -> CacheSnapshot {
+    CacheSnapshot {
+        taken_at: chrono::Utc::now(),
+        shards: vec![ShardSnapshot {
+            index: 0,
+            current_size: 512,
+            logical_size: 2048,
+            max_size: 1024,
+            files: 1,
+            stats: ShardStats {
+                disk_hits: 3,
+                misses: 1,
+                ..ShardStats::default()
+            },
+            hot_keys: vec![HotKey {
+                key: String::from("tables/<orders>.parquet"),
+                accesses: 7,
+                bytes: 3584,
+            }],
+            ..ShardSnapshot::default()
+        }],
+        members,
+        disks: Vec::new(),
+        scrubbed: Default::default(),
+    }
+}
+
+#[test]
+fn test_human_bytes() {
+    assert_eq!(human_bytes(0), "0 B");
+    assert_eq!(human_bytes(1023), "1023 B");
+    assert_eq!(human_bytes(1536), "1.5 KiB");
+    assert_eq!(human_bytes(3 << 30), "3.0 GiB");
+}
+
+#[test]
+fn test_dashboard_sections() {
+    let page = render(&snapshot(vec![ClusterMember {
+        node_id: String::from("abc123"),
+        endpoint: String::from("10.0.0.2"),
+        port: 6379,
+        slots: 16384,
+        weight: 2,
+        address: None,
+        is_self: true,
+    }]));
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("50.0%"));
+    assert!(page.contains("<td>75.0</td>"));
+    assert!(page.contains("tables/&lt;orders&gt;.parquet"));
+    assert!(!page.contains("<orders>"));
+    assert!(page.contains("<td>7</td><td>3.5 KiB</td>"));
+    assert!(page.contains("abc123 (this node)"));
+    assert!(page.contains("<td>16384</td><td>2</td>"));
+}
+
+#[test]
+fn test_dashboard_lists_disks() {
+    let disk = |path: &str, status| DiskSnapshot {
+        path: PathBuf::from(path),
+        max_size: 2048,
+        current_size: 1024,
+        status,
+        errors: 4,
+    };
+    let mut striped = snapshot(Vec::new());
+    striped.disks = vec![
+        disk("/mnt/nvme0", DiskStatus::Up),
+        disk("/mnt/nvme1", DiskStatus::Full),
+    ];
+    let page = render(&striped);
+    assert!(page.contains("<h2>Disks</h2>"));
+    assert!(page.contains("/mnt/nvme1</td><td>1.0 KiB</td><td>2.0 KiB</td><td>4</td><td>full</td>"));
+
+    let mut single = snapshot(Vec::new());
+    single.disks = vec![disk("./cache", DiskStatus::Up)];
+    assert!(!render(&single).contains("<h2>Disks</h2>"));
+}
+
+#[test]
+fn test_dashboard_before_slot_mapping() {
+    let page = render(&snapshot(Vec::new()));
+    assert!(page.contains("Slot mapping not loaded yet"));
+}
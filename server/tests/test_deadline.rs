@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::deadline::{bounded, is_timeout, parse_timeout, Stage};
+use istziio_server_node::disk_io::DiskIo;
+use istziio_server_node::logging::RequestContext;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use istziio_server_node::storage::timeout_storage_connector::TimeoutStorageConnector;
+use istziio_server_node::telemetry::TraceContext;
+use rocket::futures::{stream, StreamExt};
+use std::ffi::CString;
+use std::io::{self, Result as IoResult};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Never answers fetches; `stall_body` answers with one chunk and then goes quiet.
+struct HungConnector {
+    stall_body: bool,
+}
+
+#[async_trait]
+impl StorageConnector for HungConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        if !self.stall_body {
+            return std::future::pending().await;
+        }
+        let first = stream::iter(vec![Ok(Bytes::from_static(b"abc"))]);
+        Ok(FetchedObject {
+            content_length: Some(6),
+            object_size: Some(6),
+            version: Default::default(),
+            stream: Box::pin(first.chain(stream::pending())),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn with_deadline(after: Duration) -> RequestContext {
+    let mut context = RequestContext::new(String::from("req"), TraceContext::new(), String::new());
+    context.deadline = Some(Instant::now() + after);
+    context
+}
+
+#[test]
+fn test_parse_timeout() {
+    assert_eq!(parse_timeout("250"), Some(Duration::from_millis(250)));
+    assert_eq!(parse_timeout(" 0 "), Some(Duration::ZERO));
+    assert_eq!(parse_timeout("soon"), None);
+}
+
+#[tokio::test]
+async fn test_stage_limit() {
+    let e = bounded(
+        Stage::S3Fetch,
+        Some(Duration::from_millis(10)),
+        std::future::pending::<()>(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    assert!(is_timeout(&e));
+    assert_eq!(e.to_string(), "S3 fetch timed out after 10 ms");
+    assert!(!is_timeout(&io::Error::from(io::ErrorKind::TimedOut)));
+
+    let done = bounded(Stage::DiskRead, None, async { 7 }).await.unwrap();
+    assert_eq!(done, 7);
+}
+
+#[tokio::test]
+async fn test_deadline_cuts_stages_short() {
+    let e = with_deadline(Duration::from_millis(10))
+        .scope(bounded(
+            Stage::Metadata,
+            Some(Duration::from_secs(60)),
+            std::future::pending::<()>(),
+        ))
+        .await
+        .unwrap_err();
+    assert!(is_timeout(&e));
+    assert_eq!(
+        e.to_string(),
+        "request deadline passed during metadata lookup"
+    );
+}
+
+#[tokio::test]
+async fn test_hung_fetches_time_out() {
+    let hung = TimeoutStorageConnector::new(
+        Arc::new(HungConnector { stall_body: false }),
+        Some(Duration::from_millis(10)),
+    );
+    let e = hung.fetch_stream("a").await.err().unwrap();
+    assert!(is_timeout(&e));
+
+    let stalled = TimeoutStorageConnector::new(
+        Arc::new(HungConnector { stall_body: true }),
+        Some(Duration::from_millis(10)),
+    );
+    let mut body = stalled.fetch_stream("a").await.unwrap().stream;
+    assert_eq!(body.next().await.unwrap().unwrap(), "abc");
+    assert!(is_timeout(&body.next().await.unwrap().unwrap_err()));
+    assert!(body.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_timed_out_miss_is_a_504() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    // No stage limit: the client's deadline alone ends the fetch.
+    let connector = Arc::new(TimeoutStorageConnector::new(
+        Arc::new(HungConnector { stall_body: false }),
+        None,
+    ));
+    let result = with_deadline(Duration::from_millis(20))
+        .scope(cache.get_file(PathBuf::from("a.csv"), connector, GetFileOptions::default()))
+        .await;
+    match result {
+        GetFileResult::Error(e) => {
+            assert_eq!(e.code(), "timeout");
+            assert_eq!(
+                e.to_string(),
+                "a.csv: request deadline passed during S3 fetch"
+            );
+        }
+        _ => panic!("a miss past its deadline should get a 504"),
+    }
+}
+
+#[tokio::test]
+async fn test_stalled_disk_read_times_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stalled");
+    // Opening a FIFO blocks until a writer shows up, like a disk that stopped answering.
+    let fifo = CString::new(path.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+    let disk_io = DiskIo::default().timing_out_reads_after(Some(Duration::from_millis(20)));
+    let e = disk_io.open(&path, 4096).await.err().unwrap();
+    assert_eq!(e.to_string(), "disk read timed out after 20 ms");
+    // Let the abandoned open finish so the runtime can shut down.
+    drop(std::fs::OpenOptions::new().write(true).open(&path).unwrap());
+}
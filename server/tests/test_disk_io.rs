@@ -0,0 +1,123 @@
+use bytes::Bytes;
+use istziio_server_node::cache::{CachedFile, SERVE_CHUNK_SIZE};
+use istziio_server_node::disk_io::{DiskBackend, DiskIo};
+use rocket::futures::stream;
+use rocket::local::asynchronous::Client;
+use rocket::{get, routes, State};
+use std::io::{Result as IoResult, SeekFrom};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+#[test]
+fn test_parse_disk_backend() {
+    assert_eq!("tokio".parse(), Ok(DiskBackend::Tokio));
+    assert_eq!("io-uring".parse(), Ok(DiskBackend::IoUring));
+    assert!("aio".parse::<DiskBackend>().is_err());
+    assert_eq!(DiskIo::default().backend(), DiskBackend::Tokio);
+}
+
+/// Both backends; io_uring stands in for tokio where it isn't available.
+fn backends() -> Vec<DiskIo> {
+    vec![
+        DiskIo::new(DiskBackend::Tokio),
+        DiskIo::new(DiskBackend::IoUring),
+    ]
+}
+
+#[tokio::test]
+async fn test_write_and_read_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..3 * 1024 * 1024 + 5).map(|i| (i % 251) as u8).collect();
+    for (i, disk_io) in backends().into_iter().enumerate() {
+        let path = dir.path().join(format!("object-{}", i));
+        // Many small chunks, as a store sends them.
+        let chunks: Vec<IoResult<Bytes>> = data
+            .chunks(100 * 1000)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let size = disk_io
+            .write_stream(Box::pin(stream::iter(chunks)), &path)
+            .await
+            .unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        let (mut file, len) = disk_io.open(&path, 64 * 1024).await.unwrap();
+        assert_eq!(len, data.len() as u64);
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, data);
+
+        file.seek(SeekFrom::Start(1000)).await.unwrap();
+        let mut some = vec![0; 10];
+        file.read_exact(&mut some).await.unwrap();
+        assert_eq!(some, data[1000..1010]);
+        file.seek(SeekFrom::End(-3)).await.unwrap();
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail).await.unwrap();
+        assert_eq!(tail, data[data.len() - 3..]);
+    }
+}
+
+#[tokio::test]
+async fn test_dropping_page_cache_keeps_contents() {
+    let dir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..20 * 1024 * 1024 + 3).map(|i| (i % 241) as u8).collect();
+    for (i, disk_io) in backends().into_iter().enumerate() {
+        let disk_io = disk_io.dropping_page_cache_from(Some(1024 * 1024));
+        let path = dir.path().join(format!("object-{}", i));
+        let chunks = vec![Ok(Bytes::from(data.clone()))];
+        let size = disk_io
+            .write_stream(Box::pin(stream::iter(chunks)), &path)
+            .await
+            .unwrap();
+        assert_eq!(size, data.len() as u64);
+
+        let (mut file, _) = disk_io.open(&path, SERVE_CHUNK_SIZE).await.unwrap();
+        let mut head = vec![0; 9 * 1024 * 1024];
+        file.read_exact(&mut head).await.unwrap();
+        assert_eq!(head, data[..head.len()]);
+        // Seeking back re-reads pages already dropped.
+        file.seek(SeekFrom::Start(5)).await.unwrap();
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, data[5..]);
+    }
+}
+
+#[tokio::test]
+async fn test_failed_stream_fails_the_write() {
+    let dir = tempfile::tempdir().unwrap();
+    for disk_io in backends() {
+        let chunks = vec![
+            Ok(Bytes::from_static(b"partial")),
+            Err(std::io::Error::other("connection reset")),
+        ];
+        let written = disk_io
+            .write_stream(Box::pin(stream::iter(chunks)), &dir.path().join("a"))
+            .await;
+        assert!(written.is_err());
+    }
+}
+
+#[get("/file")]
+async fn file(path: &State<std::path::PathBuf>, disk_io: &State<DiskIo>) -> Option<CachedFile> {
+    CachedFile::open_with(path.inner(), disk_io).await.ok()
+}
+
+#[rocket::async_test]
+async fn test_cached_file_served_through_each_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("object");
+    let data: Vec<u8> = (0..2 * SERVE_CHUNK_SIZE + 11).map(|i| i as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    for disk_io in backends() {
+        let rocket = rocket::build()
+            .manage(path.clone())
+            .manage(disk_io)
+            .mount("/", routes![file]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client.get("/file").dispatch().await;
+        assert_eq!(response.body().preset_size(), Some(data.len()));
+        assert_eq!(response.into_bytes().await.unwrap(), data);
+    }
+}
@@ -0,0 +1,152 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::scrub::{scan_dir, ScrubReport};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use istziio_server_node::util::{disk_name, temp_name, MAX_PLAIN_NAME_LEN};
+use rocket::futures::{stream, StreamExt};
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Answers every fetch with the key itself.
+struct EchoConnector;
+
+#[async_trait]
+impl StorageConnector for EchoConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[test]
+fn test_disk_names() {
+    for plain in ["orders.csv", "part-0.parquet@chunk-3", "a=1,b+2_c"] {
+        assert_eq!(disk_name(plain), plain);
+    }
+    let long = "x".repeat(MAX_PLAIN_NAME_LEN + 1);
+    let odd = [
+        "reports/2024/q1.csv",
+        "q1 summary.csv",
+        "notes#1.txt",
+        "données.parquet",
+        ".hidden",
+        "..",
+        "",
+        long.as_str(),
+    ];
+    let names: Vec<String> = odd.iter().map(|key| disk_name(key)).collect();
+    for name in &names {
+        assert!(name.starts_with('~'));
+        assert_eq!(name.len(), 65);
+    }
+    for (i, name) in names.iter().enumerate() {
+        assert!(!names[i + 1..].contains(name));
+    }
+    assert_eq!(disk_name("notes#1.txt"), names[2]);
+}
+
+#[tokio::test]
+async fn test_keys_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        1_000_000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let deep = (0..300)
+        .map(|i| format!("d{}", i))
+        .collect::<Vec<_>>()
+        .join("/");
+    let keys = [
+        String::from("reports/2024/q1 summary #1.csv"),
+        String::from("données/ünïcode.parquet"),
+        deep,
+    ];
+    for _ in 0..2 {
+        for key in &keys {
+            let result = cache
+                .get_file(
+                    PathBuf::from(key),
+                    Arc::new(EchoConnector),
+                    GetFileOptions::default(),
+                )
+                .await;
+            assert!(matches!(result, GetFileResult::Hit(_)), "{}", key);
+        }
+    }
+    for key in &keys {
+        let mut body = Vec::new();
+        let mut stream = cache.open_object(key).await.unwrap();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(body, key.as_bytes());
+        assert!(dir.path().join(disk_name(key)).exists());
+    }
+    assert_eq!(scan_dir(dir.path()).await.unwrap().len(), 3);
+    assert_eq!(cache.scrub().await, ScrubReport::default());
+
+    assert!(cache.invalidate(&keys[0]).await > 0);
+    assert!(!dir.path().join(disk_name(&keys[0])).exists());
+}
+
+#[test]
+fn test_temp_names() {
+    for key in ["foo", "foo.incoming", "~foo", ".foo.incoming"] {
+        let name = temp_name(&disk_name(key), "incoming");
+        assert!(name.starts_with('.'));
+        assert_ne!(name, disk_name(&format!("{}.incoming", key)));
+        assert_ne!(name, disk_name(&name));
+    }
+}
+
+#[tokio::test]
+async fn test_staging_leaves_other_keys_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        1_000_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let result = cache
+        .get_file(
+            PathBuf::from("foo.incoming"),
+            Arc::new(EchoConnector),
+            GetFileOptions::default(),
+        )
+        .await;
+    assert!(matches!(result, GetFileResult::Hit(_)));
+    drop(result);
+
+    let body = Box::pin(stream::iter(vec![Ok(Bytes::from("new foo"))]));
+    let staged = cache.stage("foo", body).await.unwrap();
+    assert!(cache.ingest("foo", staged).await.unwrap());
+    for (key, expected) in [("foo", "new foo"), ("foo.incoming", "foo.incoming")] {
+        let mut body = Vec::new();
+        let mut stream = cache.open_object(key).await.unwrap();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(body, expected.as_bytes(), "{}", key);
+    }
+    assert_eq!(cache.scrub().await, ScrubReport::default());
+}
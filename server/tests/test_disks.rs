@@ -0,0 +1,280 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::disks::{
+    disk_error, free_space, is_disk_error, stripe_budgets, CacheDirConfig, DiskHealth, DiskStatus,
+    FAILURES_BEFORE_DOWN,
+};
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::io::{self, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Answers every fetch with the key itself.
+struct EchoConnector;
+
+#[async_trait]
+impl StorageConnector for EchoConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[test]
+fn test_stripe_budgets() {
+    // Equal disks split the budget evenly, as without striping.
+    assert_eq!(
+        stripe_budgets(&[100, 100], 4, 400),
+        vec![100, 100, 100, 100]
+    );
+    // Shards on a disk twice the size get twice the budget.
+    assert_eq!(stripe_budgets(&[100, 200], 4, 300), vec![50, 100, 50, 100]);
+    // Resizing the node scales every disk's share.
+    assert_eq!(stripe_budgets(&[100, 200], 2, 30), vec![10, 20]);
+    for (capacities, shards, total) in [(vec![7, 11, 13], 5, 31), (vec![1, 1, 1], 7, 100)] {
+        let budgets = stripe_budgets(&capacities, shards, total);
+        assert_eq!(budgets.len() as u64, shards);
+        assert_eq!(budgets.iter().sum::<u64>(), total);
+    }
+    assert_eq!(stripe_budgets(&[10], 3, 10), vec![4, 3, 3]);
+}
+
+#[test]
+fn test_parse_cache_dir() {
+    assert_eq!(
+        "/mnt/nvme0:1000".parse(),
+        Ok(CacheDirConfig {
+            path: String::from("/mnt/nvme0"),
+            max_size: 1000,
+        })
+    );
+    assert!("/mnt/nvme0".parse::<CacheDirConfig>().is_err());
+    assert!("/mnt/nvme0:big".parse::<CacheDirConfig>().is_err());
+}
+
+#[tokio::test]
+async fn test_disk_health() {
+    let dir = tempfile::tempdir().unwrap();
+    let health = DiskHealth::new(dir.path().to_path_buf());
+    let failure = io::Error::other("bad sector");
+    for _ in 1..FAILURES_BEFORE_DOWN {
+        health.record(Err(&failure));
+    }
+    health.record(Ok(()));
+    health.record(Err(&failure));
+    assert!(health.is_up());
+    for _ in 1..FAILURES_BEFORE_DOWN {
+        health.record(Err(&failure));
+    }
+    assert!(!health.is_up());
+    // Successes elsewhere don't bring it back; a probe does.
+    health.record(Ok(()));
+    assert!(!health.is_up());
+    assert!(health.probe().await);
+    assert!(health.is_up());
+
+    assert_eq!(health.errors(), 2 * u64::from(FAILURES_BEFORE_DOWN) - 1);
+
+    let gone = DiskHealth::new(dir.path().join("unmounted"));
+    assert!(!gone.probe().await);
+}
+
+#[tokio::test]
+async fn test_full_disk_is_down_right_away() {
+    let dir = tempfile::tempdir().unwrap();
+    let health = DiskHealth::new(dir.path().to_path_buf());
+    health.record(Err(&io::Error::from(io::ErrorKind::StorageFull)));
+    assert_eq!(health.status(), DiskStatus::Full);
+    // The temp dir has room again, so the probe succeeds.
+    assert!(health.probe().await);
+    assert_eq!(health.status(), DiskStatus::Up);
+}
+
+#[test]
+fn test_disk_errors_are_told_apart() {
+    let e = disk_error(io::Error::from(io::ErrorKind::StorageFull));
+    assert!(is_disk_error(&e));
+    assert_eq!(e.kind(), io::ErrorKind::StorageFull);
+    assert!(!is_disk_error(&io::Error::other("connection reset")));
+}
+
+fn files_in(dir: &Path) -> usize {
+    std::fs::read_dir(dir).unwrap().count()
+}
+
+fn striped_cache(dirs: &[PathBuf]) -> ConcurrentDiskCache {
+    ConcurrentDiskCache::new(
+        PathBuf::from("unused"),
+        3000,
+        4,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            cache_dirs: vec![
+                CacheDirConfig {
+                    path: dirs[0].to_string_lossy().into_owned(),
+                    max_size: 1000,
+                },
+                CacheDirConfig {
+                    path: dirs[1].to_string_lossy().into_owned(),
+                    max_size: 2000,
+                },
+            ],
+            ..Default::default()
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_objects_are_striped_over_disks() {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = [root.path().join("a"), root.path().join("b")];
+    let cache = striped_cache(&dirs);
+    let connector = Arc::new(EchoConnector);
+    let keys: Vec<String> = (0..40).map(|i| format!("part-{}.parquet", i)).collect();
+    for key in &keys {
+        let result = cache
+            .get_file(key.into(), connector.clone(), GetFileOptions::default())
+            .await;
+        assert!(matches!(result, GetFileResult::Hit(_)));
+    }
+    assert!(files_in(&dirs[0]) > 0);
+    assert!(files_in(&dirs[1]) > 0);
+    assert_eq!(files_in(&dirs[0]) + files_in(&dirs[1]), 40);
+
+    let disks = cache.disk_snapshots().await;
+    assert_eq!(disks[0].path, dirs[0]);
+    assert_eq!((disks[0].max_size, disks[1].max_size), (1000, 2000));
+    let cached: usize = keys.iter().map(String::len).sum();
+    assert_eq!(
+        disks.iter().map(|disk| disk.current_size).sum::<u64>(),
+        cached as u64
+    );
+    assert!(disks.iter().all(|disk| disk.status == DiskStatus::Up));
+    assert!(cache.get_stats().await.contains("% Used"));
+}
+
+#[tokio::test]
+async fn test_failed_disk_is_bypassed_until_probed() {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = [root.path().join("a"), root.path().join("b")];
+    let cache = striped_cache(&dirs);
+    let connector = Arc::new(EchoConnector);
+    std::fs::remove_dir_all(&dirs[0]).unwrap();
+
+    // Every miss is answered: from the good disk, or from S3 in place of the failed one.
+    let mut passed_through = 0;
+    for i in 0..60 {
+        let key = PathBuf::from(format!("part-{}.parquet", i));
+        match cache
+            .get_file(key, connector.clone(), GetFileOptions::default())
+            .await
+        {
+            GetFileResult::PassThrough(_) => passed_through += 1,
+            GetFileResult::Hit(_) => {}
+            _ => panic!("a failed disk must not fail requests"),
+        }
+    }
+    assert!(passed_through > 0);
+    let disks = cache.disk_snapshots().await;
+    assert_eq!(disks[0].status, DiskStatus::Failing);
+    assert!(disks[0].errors >= u64::from(FAILURES_BEFORE_DOWN));
+    assert_eq!(disks[1].status, DiskStatus::Up);
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!(stats.disks_down, 1);
+    assert_eq!(cache.probe_disks().await, 1);
+
+    std::fs::create_dir_all(&dirs[0]).unwrap();
+    assert_eq!(cache.probe_disks().await, 0);
+    let result = cache
+        .get_file(
+            PathBuf::from("part-0.parquet"),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+        .await;
+    assert!(matches!(result, GetFileResult::Hit(_)));
+}
+
+#[tokio::test]
+async fn test_unreadable_hit_is_served_from_s3() {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = [root.path().join("a"), root.path().join("b")];
+    let cache = striped_cache(&dirs);
+    let connector = Arc::new(EchoConnector);
+    let key = PathBuf::from("lost.parquet");
+    let get = || cache.get_file(key.clone(), connector.clone(), GetFileOptions::default());
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    for dir in &dirs {
+        let _ = std::fs::remove_file(dir.join("lost.parquet"));
+    }
+    assert!(matches!(get().await, GetFileResult::PassThrough(_)));
+}
+
+#[tokio::test]
+async fn test_free_space_reserve() {
+    let dir = tempfile::tempdir().unwrap();
+    let unchecked = DiskHealth::new(dir.path().to_path_buf());
+    assert_eq!(unchecked.free_space_deficit().unwrap(), 0);
+
+    let roomy = DiskHealth::new(dir.path().to_path_buf()).reserving(Some(1));
+    assert_eq!(roomy.free_space_deficit().unwrap(), 0);
+    assert!(roomy.is_up());
+
+    let free = free_space(dir.path()).unwrap();
+    let short = DiskHealth::new(dir.path().to_path_buf()).reserving(Some(free + (1 << 40)));
+    assert!(short.free_space_deficit().unwrap() >= 1 << 40);
+    assert_eq!(short.status(), DiskStatus::Full);
+    // The probe wants the reserve free, not just MIN_FREE_SPACE.
+    assert!(!short.probe().await);
+}
+
+#[tokio::test]
+async fn test_reserve_free_space_evicts_and_stops_caching() {
+    let dir = tempfile::tempdir().unwrap();
+    let free = free_space(dir.path()).unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        3000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            free_space_reserve: Some(free + (1 << 40)),
+            ..Default::default()
+        },
+    );
+    let connector = Arc::new(EchoConnector);
+    let get = |key: &str| {
+        cache.get_file(
+            PathBuf::from(key),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    for i in 0..10 {
+        get(&format!("part-{}.parquet", i)).await;
+    }
+    let evicted = cache.reserve_free_space().await;
+    assert_eq!(evicted, 10 * "part-0.parquet".len() as u64);
+    assert_eq!(cache.disk_snapshots().await[0].status, DiskStatus::Full);
+    assert!(matches!(
+        get("part-0.parquet").await,
+        GetFileResult::PassThrough(_)
+    ));
+    assert!(!dir.path().join("part-0.parquet").exists());
+}
@@ -0,0 +1,240 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::download::{is_abandoned, DisconnectPolicy, Download, Progress};
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{
+    FetchedObject, ObjectStream, StorageConnector,
+};
+use rocket::futures::{stream, StreamExt};
+use std::io::{self, Result as IoResult};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+type Chunks = UnboundedSender<IoResult<Bytes>>;
+
+/// A stream that yields what is sent on the returned channel, ending when it is dropped.
+fn channel_stream() -> (Chunks, ObjectStream) {
+    let (sender, receiver) = unbounded_channel();
+    let stream = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    (sender, Box::pin(stream))
+}
+
+/// Waits for the next chunk of `body`, failing if it doesn't come soon.
+async fn next_chunk(body: &mut ObjectStream) -> Option<IoResult<Bytes>> {
+    tokio::time::timeout(Duration::from_secs(5), body.next())
+        .await
+        .expect("no chunk arrived")
+}
+
+async fn read_rest(body: &mut ObjectStream) -> Vec<u8> {
+    let mut data = Vec::new();
+    while let Some(chunk) = next_chunk(body).await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    data
+}
+
+#[tokio::test]
+async fn test_readers_follow_the_download() {
+    let dir = tempfile::tempdir().unwrap();
+    let (download, writer) = Download::create(&dir.path().join("a")).await.unwrap();
+    let (chunks, stream) = channel_stream();
+    let written = tokio::spawn(writer.write(stream));
+    let mut early = download.reader().await.unwrap();
+
+    // The first bytes are readable long before the object is complete.
+    chunks.send(Ok(Bytes::from(vec![1; 300 * 1024]))).unwrap();
+    let first = next_chunk(&mut early).await.unwrap().unwrap();
+    assert!(!first.is_empty());
+    assert!(matches!(download.progress(), Progress::Writing(_)));
+
+    let mut late = download.reader().await.unwrap();
+    chunks.send(Ok(Bytes::from(vec![2; 1000]))).unwrap();
+    drop(chunks);
+    assert_eq!(written.await.unwrap().unwrap(), 300 * 1024 + 1000);
+    assert_eq!(download.progress(), Progress::Done(300 * 1024 + 1000));
+
+    let rest = read_rest(&mut early).await;
+    assert_eq!(first.len() + rest.len(), 300 * 1024 + 1000);
+    assert_eq!(rest.last(), Some(&2));
+    let all = read_rest(&mut late).await;
+    assert_eq!(all.len(), 300 * 1024 + 1000);
+    assert_eq!(all[0], 1);
+}
+
+#[tokio::test]
+async fn test_failed_download_fails_readers() {
+    let dir = tempfile::tempdir().unwrap();
+    let (download, writer) = Download::create(&dir.path().join("a")).await.unwrap();
+    let (chunks, stream) = channel_stream();
+    let mut reader = download.reader().await.unwrap();
+    chunks.send(Ok(Bytes::from_static(b"partial"))).unwrap();
+    chunks
+        .send(Err(io::Error::other("connection reset")))
+        .unwrap();
+    assert!(writer.write(stream).await.is_err());
+    assert_eq!(download.progress(), Progress::Failed);
+    assert!(next_chunk(&mut reader).await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn test_abandoned_download_is_aborted_or_filled() {
+    let dir = tempfile::tempdir().unwrap();
+    let (download, writer) = Download::create(&dir.path().join("a")).await.unwrap();
+    let (chunks, stream) = channel_stream();
+    let reader = download.reader().await.unwrap();
+    assert_eq!(download.readers(), 1);
+    let written = tokio::spawn(writer.on_disconnect(DisconnectPolicy::Abort).write(stream));
+    chunks.send(Ok(Bytes::from_static(b"first"))).unwrap();
+    drop(reader);
+    assert_eq!(download.readers(), 0);
+    chunks.send(Ok(Bytes::from_static(b"second"))).unwrap();
+    assert!(is_abandoned(&written.await.unwrap().unwrap_err()));
+    assert_eq!(download.progress(), Progress::Failed);
+
+    let (download, writer) = Download::create(&dir.path().join("b")).await.unwrap();
+    let (chunks, stream) = channel_stream();
+    drop(download.reader().await.unwrap());
+    let written = tokio::spawn(writer.on_disconnect(DisconnectPolicy::Fill).write(stream));
+    chunks.send(Ok(Bytes::from_static(b"first"))).unwrap();
+    drop(chunks);
+    assert_eq!(written.await.unwrap().unwrap(), 5);
+
+    assert_eq!("abort".parse(), Ok(DisconnectPolicy::Abort));
+    assert!("drop".parse::<DisconnectPolicy>().is_err());
+}
+
+/// Serves objects of `size` bytes, taking their bodies from `streams`.
+struct SlowConnector {
+    size: u64,
+    streams: Mutex<Vec<ObjectStream>>,
+    fetches: AtomicUsize,
+}
+
+#[async_trait]
+impl StorageConnector for SlowConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        Ok(FetchedObject {
+            stream: self.streams.lock().unwrap().pop().unwrap(),
+            content_length: Some(self.size),
+            object_size: Some(self.size),
+            version: Default::default(),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn streaming_body(result: GetFileResult) -> ObjectStream {
+    match result {
+        GetFileResult::Streaming(body) => body.0,
+        _ => panic!("expected the object to be streamed"),
+    }
+}
+
+#[tokio::test]
+async fn test_misses_stream_while_the_object_is_cached() {
+    let dir = tempfile::tempdir().unwrap();
+    let size = 512 * 1024;
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10 * 1024 * 1024,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            stream_fetches_from: Some(size),
+            ..Default::default()
+        },
+    );
+    let (chunks, stream) = channel_stream();
+    let connector = Arc::new(SlowConnector {
+        size,
+        streams: Mutex::new(vec![stream]),
+        fetches: AtomicUsize::new(0),
+    });
+    let key = PathBuf::from("big.parquet");
+    let get = || cache.get_file(key.clone(), connector.clone(), GetFileOptions::default());
+
+    let mut first = streaming_body(get().await);
+    chunks.send(Ok(Bytes::from(vec![7; 384 * 1024]))).unwrap();
+    assert!(!next_chunk(&mut first).await.unwrap().unwrap().is_empty());
+
+    // A second miss reads the same download rather than fetching again.
+    let mut second = streaming_body(get().await);
+    chunks.send(Ok(Bytes::from(vec![7; 128 * 1024]))).unwrap();
+    drop(chunks);
+    assert_eq!(read_rest(&mut second).await.len() as u64, size);
+    read_rest(&mut first).await;
+
+    // Once it has landed, the object is a plain disk hit.
+    let mut cached = false;
+    for _ in 0..100 {
+        if let GetFileResult::Hit(_) = get().await {
+            cached = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(cached);
+    assert_eq!(connector.fetches.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_disconnected_miss_stops_downloading() {
+    let dir = tempfile::tempdir().unwrap();
+    let size = 512 * 1024;
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10 * 1024 * 1024,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            stream_fetches_from: Some(size),
+            on_client_disconnect: DisconnectPolicy::Abort,
+            ..Default::default()
+        },
+    );
+    let (chunks, stream) = channel_stream();
+    let (_, retry) = channel_stream();
+    let connector = Arc::new(SlowConnector {
+        size,
+        streams: Mutex::new(vec![retry, stream]),
+        fetches: AtomicUsize::new(0),
+    });
+    let key = PathBuf::from("big.parquet");
+    let get = || cache.get_file(key.clone(), connector.clone(), GetFileOptions::default());
+
+    let mut body = streaming_body(get().await);
+    chunks.send(Ok(Bytes::from(vec![7; 300 * 1024]))).unwrap();
+    assert!(!next_chunk(&mut body).await.unwrap().unwrap().is_empty());
+    // The client goes away; the download stops at the next chunk from S3.
+    drop(body);
+    chunks.send(Ok(Bytes::from(vec![7; 1024]))).unwrap();
+    let mut aborted = false;
+    for _ in 0..100 {
+        if NodeStats::from_snapshot(&cache.snapshot(0).await).aborted_downloads == 1 {
+            aborted = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(aborted);
+    assert!(!dir.path().join("big.parquet").exists());
+
+    // Nothing was cached, so the next request downloads again.
+    drop(streaming_body(get().await));
+    assert_eq!(connector.fetches.load(Ordering::SeqCst), 2);
+}
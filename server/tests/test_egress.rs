@@ -0,0 +1,70 @@
+use istziio_server_node::egress::{EgressConfig, EgressShaper};
+use istziio_server_node::priority::RequestPriority;
+use rocket::response::{Body, Response};
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+
+fn body(len: usize) -> Body<'static> {
+    Response::build()
+        .sized_body(len, Cursor::new(vec![7u8; len]))
+        .finalize()
+        .body_mut()
+        .take()
+}
+
+/// Reads `body` 500 bytes at a time, as Rocket reads chunks.
+async fn drain(mut body: impl tokio::io::AsyncRead + Unpin) -> usize {
+    let mut buf = [0u8; 500];
+    let mut total = 0;
+    loop {
+        let n = body.read(&mut buf).await.unwrap();
+        if n == 0 {
+            return total;
+        }
+        total += n;
+    }
+}
+
+#[tokio::test]
+async fn test_responses_are_held_to_their_own_rate() {
+    let shaper = Arc::new(EgressShaper::new(EgressConfig {
+        per_request_bytes_per_sec: Some(10_000),
+        ..Default::default()
+    }));
+    let started = Instant::now();
+    let shaped = shaper.shape(RequestPriority::Interactive, body(2_000));
+    assert_eq!(shaper.usage(RequestPriority::Interactive).active, 1);
+    assert_eq!(drain(shaped).await, 2_000);
+    assert!(started.elapsed() >= Duration::from_millis(150));
+
+    let usage = shaper.usage(RequestPriority::Interactive);
+    assert_eq!(usage.active, 0);
+    assert_eq!(usage.total_bytes, 2_000);
+    assert_eq!(usage.limit, None);
+}
+
+#[tokio::test]
+async fn test_batch_responses_share_their_class_rate() {
+    let shaper = Arc::new(EgressShaper::new(EgressConfig {
+        batch_bytes_per_sec: Some(10_000),
+        ..Default::default()
+    }));
+    // Interactive responses are not held back by the batch limit.
+    let started = Instant::now();
+    drain(shaper.shape(RequestPriority::Interactive, body(4_000))).await;
+    assert!(started.elapsed() < Duration::from_millis(100));
+
+    let started = Instant::now();
+    let (a, b) = tokio::join!(
+        drain(shaper.shape(RequestPriority::Batch, body(2_000))),
+        drain(shaper.shape(RequestPriority::Batch, body(2_000)))
+    );
+    assert_eq!(a + b, 4_000);
+    assert!(started.elapsed() >= Duration::from_millis(350));
+    let usage = shaper.usage(RequestPriority::Batch);
+    assert_eq!(usage.total_bytes, 4_000);
+    assert_eq!(usage.limit, Some(10_000));
+    assert!(shaper.summary().contains("Egress (batch)"));
+}
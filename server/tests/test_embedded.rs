@@ -0,0 +1,148 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::CacheOptions;
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::embedded::Cache;
+use istziio_server_node::encryption::EncryptionKey;
+use istziio_server_node::storage::storage_connector::{
+    read_stream_to_end, FetchedObject, ObjectStream, StorageConnector,
+};
+use rocket::futures::stream;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Result as IoResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+
+/// A bucket in memory that counts fetches.
+#[derive(Default)]
+struct Bucket {
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+    fetches: AtomicUsize,
+}
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        let data = self.objects.lock().unwrap().get(file_name).cloned();
+        let data = data.ok_or_else(|| std::io::Error::from(ErrorKind::NotFound))?;
+        Ok(FetchedObject {
+            content_length: Some(data.len() as u64),
+            object_size: Some(data.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(Bytes::from(data))])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        let mut object = self.fetch_stream(file_name).await?;
+        let data = self.objects.lock().unwrap()[file_name].clone();
+        let (start, end) = range
+            .resolve(data.len() as u64)
+            .ok_or_else(|| std::io::Error::from(ErrorKind::InvalidInput))?;
+        let part = data[start as usize..=end as usize].to_vec();
+        object.content_length = Some(part.len() as u64);
+        object.stream = Box::pin(stream::iter(vec![Ok(Bytes::from(part))]));
+        Ok(object)
+    }
+
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        let data = read_stream_to_end(body).await?;
+        assert_eq!(data.len() as u64, size);
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(file_name.to_string(), data);
+        Ok(())
+    }
+}
+
+async fn read(cache: &Cache, key: &str) -> IoResult<Vec<u8>> {
+    let mut body = Vec::new();
+    cache.get(key).await?.read_to_end(&mut body).await?;
+    Ok(body)
+}
+
+async fn read_range(cache: &Cache, key: &str, range: ByteRange) -> IoResult<Vec<u8>> {
+    let mut body = Vec::new();
+    cache
+        .get_range(key, range)
+        .await?
+        .read_to_end(&mut body)
+        .await?;
+    Ok(body)
+}
+
+#[tokio::test]
+async fn test_embedded_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let bucket = Arc::new(Bucket::default());
+    bucket
+        .objects
+        .lock()
+        .unwrap()
+        .insert(String::from("tables/a"), b"0123456789".to_vec());
+    let cache = Cache::new(dir.path(), 1000, bucket.clone());
+
+    assert_eq!(read(&cache, "tables/a").await.unwrap(), b"0123456789");
+    assert_eq!(read(&cache, "tables/a").await.unwrap(), b"0123456789");
+    assert_eq!(bucket.fetches.load(Ordering::SeqCst), 1);
+
+    let range = |range| read_range(&cache, "tables/a", range);
+    assert_eq!(range(ByteRange::FromTo(2, 4)).await.unwrap(), b"234");
+    assert_eq!(range(ByteRange::Suffix(3)).await.unwrap(), b"789");
+    let past_the_end = range(ByteRange::From(10)).await.unwrap_err();
+    assert_eq!(past_the_end.kind(), ErrorKind::InvalidInput);
+    assert_eq!(bucket.fetches.load(Ordering::SeqCst), 1);
+
+    // Too large to cache: the range alone is read from the bucket.
+    let small_dir = tempfile::tempdir().unwrap();
+    let small = Cache::new(small_dir.path(), 4, bucket.clone());
+    assert_eq!(read(&small, "tables/a").await.unwrap(), b"0123456789");
+    let suffix = read_range(&small, "tables/a", ByteRange::Suffix(2)).await;
+    assert_eq!(suffix.unwrap(), b"89");
+
+    let missing = read(&cache, "tables/missing").await.unwrap_err();
+    assert_eq!(missing.kind(), ErrorKind::NotFound);
+
+    // A put reaches the bucket and replaces the cached copy.
+    assert_eq!(cache.put("tables/a", &b"new"[..]).await.unwrap(), 3);
+    assert_eq!(bucket.objects.lock().unwrap()["tables/a"], b"new");
+    let fetches = bucket.fetches.load(Ordering::SeqCst);
+    assert_eq!(read(&cache, "tables/a").await.unwrap(), b"new");
+    assert_eq!(bucket.fetches.load(Ordering::SeqCst), fetches);
+
+    assert_eq!(cache.invalidate("tables/a").await, 3);
+    assert_eq!(read(&cache, "tables/a").await.unwrap(), b"new");
+    assert_eq!(bucket.fetches.load(Ordering::SeqCst), fetches + 1);
+    assert_eq!(cache.inner().debug_validate().await, Ok(()));
+}
+
+#[tokio::test]
+async fn test_puts_are_cached_sealed() {
+    let dir = tempfile::tempdir().unwrap();
+    let bucket = Arc::new(Bucket::default());
+    let cache = Cache::with_options(
+        dir.path(),
+        1_000_000,
+        1,
+        bucket.clone(),
+        CacheOptions {
+            encryption: Some(Arc::new(EncryptionKey::from_bytes(&[7u8; 32]).unwrap())),
+            ..Default::default()
+        },
+    );
+    let body = "put by the engine, sealed here;".repeat(100);
+    let size = cache.put("tables/b", body.as_bytes()).await.unwrap();
+    assert_eq!(size, body.len() as u64);
+    assert_eq!(bucket.objects.lock().unwrap()["tables/b"], body.as_bytes());
+    for file in std::fs::read_dir(dir.path()).unwrap() {
+        let stored = std::fs::read(file.unwrap().path()).unwrap();
+        assert!(!stored
+            .windows(body.len())
+            .any(|window| window == body.as_bytes()));
+    }
+    assert_eq!(read(&cache, "tables/b").await.unwrap(), body.as_bytes());
+    assert_eq!(bucket.fetches.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,97 @@
+use istziio_server_node::embedded_redis::{EmbeddedRedis, EmbeddedRedisConfig};
+use istziio_server_node::redis::RedisMode;
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// A `redis-server` stand-in that never answers pings.
+fn fake_server(dir: &TempDir) -> EmbeddedRedisConfig {
+    let binary = dir.path().join("fake-redis-server");
+    std::fs::write(&binary, "#!/bin/sh\nexec sleep 60\n").unwrap();
+    std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+    EmbeddedRedisConfig {
+        binary: binary.to_string_lossy().into_owned(),
+        dir: dir.path().join("redis").to_string_lossy().into_owned(),
+        extra_config: vec![String::from("maxmemory 1gb")],
+        health_check_interval_ms: 20,
+        max_failed_health_checks: 1000,
+        restart_delay_ms: 10,
+    }
+}
+
+fn closed_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn alive(pid: u32) -> bool {
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .status()
+        .unwrap()
+        .success()
+}
+
+fn wait_for(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_crashed_server_is_restarted_and_stopped_with_the_node() {
+    let dir = TempDir::new().unwrap();
+    let config = fake_server(&dir);
+    let port = closed_port();
+    let redis = EmbeddedRedis::start(config.clone(), port, RedisMode::Cluster).unwrap();
+
+    let conf = std::fs::read_to_string(dir.path().join("redis/redis.conf")).unwrap();
+    assert!(conf.starts_with(&format!("port {}\n", port)));
+    assert!(conf.contains("cluster-enabled yes\n"));
+    assert!(conf.ends_with("maxmemory 1gb\n"));
+
+    let first = redis.status().pid.unwrap();
+    assert!(alive(first));
+    Command::new("kill")
+        .args(["-9", &first.to_string()])
+        .status()
+        .unwrap();
+    wait_for(|| redis.status().restarts == 1 && redis.status().pid.is_some());
+    let second = redis.status().pid.unwrap();
+    assert_ne!(first, second);
+    assert!(redis.summary().contains("1 restarts"));
+
+    drop(redis);
+    assert!(!alive(second));
+}
+
+#[test]
+fn test_unresponsive_server_is_restarted() {
+    let dir = TempDir::new().unwrap();
+    let redis = EmbeddedRedis::start(
+        EmbeddedRedisConfig {
+            max_failed_health_checks: 2,
+            ..fake_server(&dir)
+        },
+        closed_port(),
+        RedisMode::Cluster,
+    )
+    .unwrap();
+    wait_for(|| redis.status().restarts >= 1);
+    assert!(!redis.status().healthy);
+    assert!(redis.summary().contains("unhealthy"));
+}
+
+#[test]
+fn test_standalone_server_is_not_a_cluster_node() {
+    let conf = EmbeddedRedisConfig::default().redis_conf(6380, RedisMode::Standalone);
+    assert!(conf.starts_with("port 6380\n"));
+    assert!(!conf.contains("cluster-"));
+}
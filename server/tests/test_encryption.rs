@@ -0,0 +1,363 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::auth::AuthConfig;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::compression::{CompressionCodec, CompressionConfig};
+use istziio_server_node::encryption::{
+    plaintext_len, seal, seal_file, seal_stream, sealed_len, DecryptingReader, EncryptionConfig,
+    EncryptionKey, SEGMENT_SIZE,
+};
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::rebalance;
+use istziio_server_node::storage::storage_connector::{
+    FetchedObject, ObjectStream, StorageConnector,
+};
+use rocket::futures::{stream, StreamExt};
+use rocket::http::{Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::routes;
+use std::io::{Read, Result as IoResult, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+fn key() -> Arc<EncryptionKey> {
+    Arc::new(EncryptionKey::from_bytes(&[7u8; 32]).unwrap())
+}
+
+fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn test_encryption_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let segment = SEGMENT_SIZE as usize;
+    for len in [0, 1, segment - 1, segment, segment + 1, 3 * segment + 17] {
+        let source = dir.path().join(format!("{}.plain", len));
+        let path = dir.path().join(format!("{}.bin", len));
+        let plaintext = content(len);
+        std::fs::write(&source, &plaintext).unwrap();
+        let sealed_size = seal_file(key(), &source, &path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), sealed_size);
+        assert_eq!(sealed_len(len as u64), sealed_size);
+        assert_eq!(plaintext_len(sealed_size), len as u64);
+        assert_ne!(std::fs::read(&path).unwrap(), plaintext);
+
+        let mut reader = DecryptingReader::open(key(), &path).unwrap();
+        assert_eq!(reader.plaintext_len(), len as u64);
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+}
+
+#[test]
+fn test_decrypting_reader_seeks() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("object.bin");
+    let plaintext = content(3 * SEGMENT_SIZE as usize + 100);
+    std::fs::write(&path, seal(key(), &plaintext).unwrap()).unwrap();
+
+    let mut reader = DecryptingReader::open(key(), &path).unwrap();
+    for offset in [SEGMENT_SIZE * 2 + 5, 10, SEGMENT_SIZE - 3] {
+        reader.seek(SeekFrom::Start(offset)).unwrap();
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], plaintext[offset as usize..offset as usize + 8]);
+    }
+    reader.seek(SeekFrom::End(-4)).unwrap();
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail[..], plaintext[plaintext.len() - 4..]);
+}
+
+#[tokio::test]
+async fn test_sealed_stream_matches_sealed_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let plaintext = content(2 * SEGMENT_SIZE as usize + 5);
+    // Chunks that straddle segment boundaries.
+    let chunks: Vec<IoResult<Bytes>> = plaintext
+        .chunks(40_000)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+        .collect();
+    let mut sealed = Vec::new();
+    let mut stream = seal_stream(key(), Box::pin(stream::iter(chunks)));
+    while let Some(chunk) = stream.next().await {
+        sealed.extend_from_slice(&chunk.unwrap());
+    }
+    assert_eq!(sealed.len() as u64, sealed_len(plaintext.len() as u64));
+
+    let path = dir.path().join("object.bin");
+    std::fs::write(&path, &sealed).unwrap();
+    let mut decrypted = Vec::new();
+    DecryptingReader::open(key(), &path)
+        .unwrap()
+        .read_to_end(&mut decrypted)
+        .unwrap();
+    assert_eq!(decrypted, plaintext);
+}
+
+#[test]
+fn test_tampering_is_detected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("object.bin");
+    let sealed = seal(key(), &content(2 * SEGMENT_SIZE as usize + 1)).unwrap();
+
+    let mut flipped = sealed.clone();
+    flipped[40] ^= 1;
+    std::fs::write(&path, &flipped).unwrap();
+    let mut buf = Vec::new();
+    assert!(DecryptingReader::open(key(), &path)
+        .unwrap()
+        .read_to_end(&mut buf)
+        .is_err());
+
+    // Dropping the last segment leaves a file that still ends on a segment boundary.
+    let truncated = &sealed[..sealed.len() - 17];
+    std::fs::write(&path, truncated).unwrap();
+    let mut buf = Vec::new();
+    assert!(DecryptingReader::open(key(), &path)
+        .unwrap()
+        .read_to_end(&mut buf)
+        .is_err());
+
+    std::fs::write(&path, &sealed).unwrap();
+    let other_key = Arc::new(EncryptionKey::from_bytes(&[8u8; 32]).unwrap());
+    let mut buf = Vec::new();
+    assert!(DecryptingReader::open(other_key, &path)
+        .unwrap()
+        .read_to_end(&mut buf)
+        .is_err());
+}
+
+#[test]
+fn test_encryption_key_loading() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_file = dir.path().join("cache.key");
+    std::fs::write(&key_file, format!("{}\n", "ab".repeat(32))).unwrap();
+    let config = EncryptionConfig {
+        key_file: key_file.to_str().unwrap().to_string(),
+    };
+    assert!(config.load_key().is_ok());
+
+    std::fs::write(&key_file, "ab".repeat(16)).unwrap();
+    assert!(config.load_key().is_err());
+    std::fs::write(&key_file, "zz".repeat(32)).unwrap();
+    assert!(config.load_key().is_err());
+}
+
+/// Serves one object whose body is fed through a channel.
+struct ChannelConnector {
+    size: u64,
+    body: Mutex<Option<ObjectStream>>,
+}
+
+#[async_trait]
+impl StorageConnector for ChannelConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Ok(FetchedObject {
+            stream: self.body.lock().unwrap().take().unwrap(),
+            content_length: Some(self.size),
+            object_size: Some(self.size),
+            version: Default::default(),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn channel_stream() -> (UnboundedSender<IoResult<Bytes>>, ObjectStream) {
+    let (sender, receiver) = unbounded_channel();
+    let stream = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    (sender, Box::pin(stream))
+}
+
+/// Whether any file under `dir` holds `needle`, and how many bytes they hold in all.
+fn scan(dir: &Path, needle: &[u8]) -> (bool, u64) {
+    let (mut found, mut total) = (false, 0);
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let data = std::fs::read(entry.unwrap().path()).unwrap_or_default();
+        found |= data.windows(needle.len()).any(|window| window == needle);
+        total += data.len() as u64;
+    }
+    (found, total)
+}
+
+#[tokio::test]
+async fn test_cached_objects_never_reach_the_disk_in_the_clear() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = b"plaintext that must stay off the disk;";
+    let plaintext: Vec<u8> = marker.iter().copied().cycle().take(300 * 1024).collect();
+    let mut compression = CompressionConfig::new(CompressionCodec::Zstd);
+    compression.min_size = 0;
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10 * 1024 * 1024,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            encryption: Some(key()),
+            compression: Some(compression),
+            stream_fetches_from: Some(0),
+            ..Default::default()
+        },
+    ));
+    let (chunks, body) = channel_stream();
+    let connector = Arc::new(ChannelConnector {
+        size: plaintext.len() as u64,
+        body: Mutex::new(Some(body)),
+    });
+    let get = {
+        let (cache, connector) = (cache.clone(), connector.clone());
+        tokio::spawn(async move {
+            cache
+                .get_file(
+                    PathBuf::from("table/part-0.csv"),
+                    connector,
+                    GetFileOptions::default(),
+                )
+                .await
+        })
+    };
+
+    // Half the object has arrived and part of it is on disk, all of it sealed.
+    chunks
+        .send(Ok(Bytes::copy_from_slice(&plaintext[..150 * 1024])))
+        .unwrap();
+    let mut written = false;
+    for _ in 0..100 {
+        let (found, total) = scan(dir.path(), marker);
+        assert!(!found);
+        if total > 0 {
+            written = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(written);
+
+    chunks
+        .send(Ok(Bytes::copy_from_slice(&plaintext[150 * 1024..])))
+        .unwrap();
+    drop(chunks);
+    let body = match get.await.unwrap() {
+        GetFileResult::PassThrough(body) => body.0,
+        _ => panic!("expected the object to be decrypted as it is read"),
+    };
+    let served: Vec<u8> = body.map(|chunk| chunk.unwrap().to_vec()).concat().await;
+    assert_eq!(served, plaintext);
+    // Compressed and sealed again, still without a plaintext copy.
+    let (found, total) = scan(dir.path(), marker);
+    assert!(!found);
+    assert!(total < plaintext.len() as u64 / 10);
+}
+
+#[tokio::test]
+async fn test_staged_copies_are_sealed() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10 * 1024 * 1024,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            encryption: Some(key()),
+            ..Default::default()
+        },
+    );
+    let marker = b"written by a client, never in the clear;";
+    let plaintext: Vec<u8> = marker.iter().copied().cycle().take(100 * 1024).collect();
+    let body = Box::pin(stream::iter(vec![Ok(Bytes::from(plaintext.clone()))]));
+    let staged = cache.stage("uploads/a.bin", body).await.unwrap();
+    assert_eq!(staged.size(), plaintext.len() as u64);
+    assert!(!scan(dir.path(), marker).0);
+    let read_back: Vec<u8> = staged
+        .open()
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap().to_vec())
+        .concat()
+        .await;
+    assert_eq!(read_back, plaintext);
+
+    assert!(cache.ingest("uploads/a.bin", staged).await.unwrap());
+    assert!(!scan(dir.path(), marker).0);
+    let cached: Vec<u8> = cache
+        .open_object("uploads/a.bin")
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap().to_vec())
+        .concat()
+        .await;
+    assert_eq!(cached, plaintext);
+}
+
+#[tokio::test]
+async fn test_migrated_objects_are_sealed() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10 * 1024 * 1024,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            encryption: Some(key()),
+            ..Default::default()
+        },
+    ));
+    let client = Client::tracked(
+        rocket::build()
+            .manage(AuthConfig {
+                admin_token: Some(String::from("secret")),
+                ..Default::default()
+            })
+            .manage(cache.clone())
+            .mount("/", routes![rebalance::receive]),
+    )
+    .await
+    .unwrap();
+    let marker = b"handed over by a peer, never in the clear;";
+    let plaintext: Vec<u8> = marker.iter().copied().cycle().take(200 * 1024).collect();
+    let response = client
+        .put("/admin/migrate/moved/a.bin")
+        .header(Header::new("Authorization", "Bearer secret"))
+        .body(&plaintext)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(!scan(dir.path(), marker).0);
+    let cached: Vec<u8> = cache
+        .open_object("moved/a.bin")
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap().to_vec())
+        .concat()
+        .await;
+    assert_eq!(cached, plaintext);
+
+    let too_large = vec![0u8; 11 * 1024 * 1024];
+    let response = client
+        .put("/admin/migrate/moved/b.bin")
+        .header(Header::new("Authorization", "Bearer secret"))
+        .body(&too_large)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    let cached: Vec<String> = cache
+        .cached_objects()
+        .await
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(cached, vec![String::from("moved/a.bin")]);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
@@ -0,0 +1,135 @@
+use async_trait::async_trait;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::error::CacheError;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use rocket::{get, routes};
+use std::io::{self, Result as IoResult};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Fails every fetch with an error of `kind`.
+struct FailingConnector {
+    kind: io::ErrorKind,
+}
+
+#[async_trait]
+impl StorageConnector for FailingConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Err(io::Error::new(self.kind, "boom"))
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[tokio::test]
+async fn test_fetch_errors_are_typed() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    for (kind, status, code) in [
+        (io::ErrorKind::NotFound, Status::NotFound, "not_found"),
+        (io::ErrorKind::Other, Status::BadGateway, "upstream_error"),
+        (
+            io::ErrorKind::TimedOut,
+            Status::ServiceUnavailable,
+            "overloaded",
+        ),
+        (
+            io::ErrorKind::InvalidInput,
+            Status::RangeNotSatisfiable,
+            "range_not_satisfiable",
+        ),
+    ] {
+        let connector = Arc::new(FailingConnector { kind });
+        let result = cache
+            .get_file(PathBuf::from("a.csv"), connector, GetFileOptions::default())
+            .await;
+        match result {
+            GetFileResult::Error(e) => {
+                assert_eq!(e.status(), status);
+                assert_eq!(e.code(), code);
+                assert_eq!(e.key(), "a.csv");
+            }
+            _ => panic!("a failed fetch of {:?} should be an error", kind),
+        }
+    }
+}
+
+#[get("/missing")]
+fn missing() -> GetFileResult {
+    GetFileResult::Error(CacheError::not_found("orders.csv"))
+}
+
+#[get("/upstream")]
+fn upstream() -> GetFileResult {
+    GetFileResult::Error(CacheError::Upstream {
+        key: String::from("orders.csv"),
+        reason: String::from("connection reset"),
+    })
+}
+
+#[test]
+fn test_error_responses_are_json() {
+    let client = Client::tracked(rocket::build().mount("/", routes![missing, upstream])).unwrap();
+
+    let response = client.get("/missing").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(body["code"], "not_found");
+    assert_eq!(body["key"], "orders.csv");
+    assert_eq!(
+        body["message"],
+        "orders.csv does not exist in the backing store"
+    );
+
+    let response = client.get("/upstream").dispatch();
+    assert_eq!(response.status(), Status::BadGateway);
+    let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+    assert_eq!(body["code"], "upstream_error");
+    assert_eq!(
+        body["message"],
+        "fetching orders.csv from the backing store failed: connection reset"
+    );
+}
+
+#[tokio::test]
+async fn test_non_utf8_keys_are_rejected() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let connector = Arc::new(FailingConnector {
+        kind: io::ErrorKind::Other,
+    });
+    let uid = PathBuf::from(std::ffi::OsStr::from_bytes(b"orders\xff.csv"));
+    match cache
+        .get_file(uid, connector, GetFileOptions::default())
+        .await
+    {
+        GetFileResult::Error(e @ CacheError::InvalidKey { .. }) => {
+            assert_eq!(e.status(), Status::BadRequest);
+            assert_eq!(e.code(), "invalid_key");
+        }
+        _ => panic!("a key that is not UTF-8 should be rejected"),
+    }
+}
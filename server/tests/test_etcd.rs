@@ -0,0 +1,179 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use istziio_server_node::etcd::{EtcdConfig, EtcdStore};
+use istziio_server_node::metadata::{key_slot, MetadataStore, SLOT_COUNT};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+type Kv = Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>;
+
+fn field(body: &Value, name: &str) -> Option<Vec<u8>> {
+    BASE64.decode(body[name].as_str()?).ok()
+}
+
+/// Answers one request the way the etcd v3 JSON gateway would.
+async fn answer(mut socket: TcpStream, kv: Kv) {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let (head_len, content_length) = loop {
+        let n = socket.read(&mut chunk).await.unwrap();
+        if n == 0 {
+            return;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .map_or(0, |len| len.trim().parse().unwrap());
+            break (end + 4, length);
+        }
+    };
+    while buf.len() < head_len + content_length {
+        let n = socket.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request_line = String::from_utf8_lossy(&buf)
+        .lines()
+        .next()
+        .unwrap()
+        .to_string();
+    let path = request_line.split(' ').nth(1).unwrap().to_string();
+    let body: Value = serde_json::from_slice(&buf[head_len..]).unwrap();
+    let key = field(&body, "key").unwrap_or_default();
+    let range_end = field(&body, "range_end");
+    let in_range = |k: &Vec<u8>| match &range_end {
+        Some(end) => k >= &key && k < end,
+        None => k == &key,
+    };
+    let reply = match path.as_str() {
+        "/v3/lease/grant" => json!({ "ID": "7", "TTL": "10" }),
+        "/v3/lease/keepalive" => json!({ "result": { "ID": "7", "TTL": "10" } }),
+        "/v3/kv/put" => {
+            let value = field(&body, "value").unwrap_or_default();
+            kv.lock().unwrap().insert(key, value);
+            json!({})
+        }
+        "/v3/kv/range" => {
+            let kvs: Vec<Value> = kv
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(k, _)| in_range(k))
+                .map(|(k, v)| json!({ "key": BASE64.encode(k), "value": BASE64.encode(v) }))
+                .collect();
+            json!({ "kvs": kvs })
+        }
+        "/v3/kv/deleterange" => {
+            kv.lock().unwrap().retain(|k, _| !in_range(k));
+            json!({})
+        }
+        // Watches never report a change here.
+        _ => return,
+    };
+    let reply = reply.to_string();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.len(),
+        reply
+    );
+    let _ = socket.write_all(response.as_bytes()).await;
+}
+
+async fn spawn_gateway(kv: Kv) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(answer(socket, kv.clone()));
+        }
+    });
+    url
+}
+
+#[tokio::test]
+async fn test_etcd_store() {
+    let kv: Kv = Default::default();
+    // A peer that registered earlier.
+    kv.lock().unwrap().insert(
+        b"/istziio/nodes/10.0.0.2:6379".to_vec(),
+        br#"{"endpoint":"10.0.0.2","port":6379}"#.to_vec(),
+    );
+    let gateway = spawn_gateway(kv.clone()).await;
+    let config = EtcdConfig {
+        endpoints: vec![gateway],
+        ..EtcdConfig::default()
+    };
+    let mut store = EtcdStore::new(&config, "10.0.0.1", 6379, 1);
+    assert!(!store.is_initialized());
+    store.initialize().await.unwrap();
+    assert!(store.is_initialized());
+    assert!(kv
+        .lock()
+        .unwrap()
+        .contains_key(b"/istziio/nodes/10.0.0.1:6379".as_slice()));
+
+    let members = store.members();
+    assert_eq!(members.len(), 2);
+    assert!(members[0].is_self);
+    assert_eq!(members[0].node_id, "10.0.0.1:6379");
+    assert_eq!(members[0].slots + members[1].slots, SLOT_COUNT as usize);
+
+    // The lower half of the slots is ours, the upper half the peer's.
+    let (mine, theirs) = ("bar", "foo");
+    assert!(key_slot(mine) < SLOT_COUNT / 2 && key_slot(theirs) >= SLOT_COUNT / 2);
+    assert_eq!(store.location_lookup(String::from(mine)).await, None);
+    assert_eq!(
+        store.location_lookup(String::from(theirs)).await,
+        Some((String::from("10.0.0.2"), 6379))
+    );
+
+    store
+        .set_file_cache_loc(String::from("a"), PathBuf::from("a"))
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_file(String::from("a")).await,
+        Some(PathBuf::from("a"))
+    );
+    store.flush_all().await;
+    assert_eq!(store.get_file(String::from("a")).await, None);
+    // Flushing file locations leaves the membership alone.
+    assert_eq!(kv.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_etcd_slots_follow_weights() {
+    let kv: Kv = Default::default();
+    // A peer three times our size.
+    kv.lock().unwrap().insert(
+        b"/istziio/nodes/10.0.0.2:6379".to_vec(),
+        br#"{"endpoint":"10.0.0.2","port":6379,"weight":3}"#.to_vec(),
+    );
+    let gateway = spawn_gateway(kv.clone()).await;
+    let config = EtcdConfig {
+        endpoints: vec![gateway],
+        ..EtcdConfig::default()
+    };
+    let mut store = EtcdStore::new(&config, "10.0.0.1", 6379, 1);
+    store.initialize().await.unwrap();
+
+    let members = store.members();
+    assert_eq!(members[0].weight, 1);
+    assert_eq!(members[1].weight, 3);
+    assert_eq!(members[0].slots, SLOT_COUNT as usize / 4);
+    assert_eq!(members[1].slots, SLOT_COUNT as usize * 3 / 4);
+
+    // "bar" is in the lower half but beyond our quarter.
+    assert!(key_slot("bar") >= SLOT_COUNT / 4);
+    assert_eq!(
+        store.location_lookup(String::from("bar")).await,
+        Some((String::from("10.0.0.2"), 6379))
+    );
+}
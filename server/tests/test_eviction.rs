@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::eviction::EvictionConfig;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::io::Result as IoResult;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Answers every fetch with ten bytes.
+struct TenBytes;
+
+#[async_trait]
+impl StorageConnector for TenBytes {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Ok(FetchedObject {
+            content_length: Some(10),
+            object_size: Some(10),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(Bytes::from_static(b"0123456789"))])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+/// A single 100-byte shard.
+fn cache(dir: &Path, eviction: Option<EvictionConfig>) -> ConcurrentDiskCache {
+    ConcurrentDiskCache::new(
+        dir.to_path_buf(),
+        100,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            eviction,
+            ..Default::default()
+        },
+    )
+}
+
+async fn fetch(cache: &ConcurrentDiskCache, key: &str) -> GetFileResult {
+    cache
+        .get_file(
+            PathBuf::from(key),
+            Arc::new(TenBytes),
+            GetFileOptions::default(),
+        )
+        .await
+}
+
+fn watermarks() -> EvictionConfig {
+    EvictionConfig {
+        high_watermark: 0.9,
+        low_watermark: 0.5,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_marks() {
+    let config = EvictionConfig::default();
+    assert_eq!(config.high_mark(1000), 900);
+    assert_eq!(config.low_mark(1000), 800);
+    assert_eq!(watermarks().low_mark(101), 50);
+}
+
+#[tokio::test]
+async fn test_background_eviction_down_to_low_watermark() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = cache(dir.path(), Some(watermarks()));
+    for i in 0..8 {
+        fetch(&cache, &format!("k{}", i)).await;
+    }
+    // 80 bytes are below the high watermark.
+    assert_eq!(cache.evict_in_background().await, 0);
+    fetch(&cache, "k8").await;
+    fetch(&cache, "k9").await;
+    assert_eq!(cache.evict_in_background().await, 50);
+    assert!(!dir.path().join("k0").exists());
+    assert!(!dir.path().join("k4").exists());
+    // The most recently used entries stay.
+    assert!(matches!(fetch(&cache, "k9").await, GetFileResult::Hit(_)));
+    assert!(matches!(fetch(&cache, "k5").await, GetFileResult::Hit(_)));
+
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!(stats.current_size, 50);
+    assert_eq!(stats.evictions, 5);
+    assert_eq!(stats.emergency_evictions, 0);
+}
+
+#[tokio::test]
+async fn test_emergency_eviction_when_background_falls_behind() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = cache(dir.path(), Some(watermarks()));
+    for i in 0..12 {
+        fetch(&cache, &format!("k{}", i)).await;
+    }
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!(stats.current_size, 100);
+    assert_eq!(stats.emergency_evictions, 2);
+}
+
+#[tokio::test]
+async fn test_no_background_eviction_without_watermarks() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = cache(dir.path(), None);
+    for i in 0..12 {
+        fetch(&cache, &format!("k{}", i)).await;
+    }
+    assert_eq!(cache.evict_in_background().await, 0);
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!(stats.evictions, 2);
+    assert_eq!(stats.emergency_evictions, 0);
+}
@@ -0,0 +1,160 @@
+#![cfg(feature = "fault-injection")]
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::auth::AuthConfig;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::faults::{self, Fault, FaultConfig};
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::faulty_storage_connector::FaultyStorageConnector;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::routes;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Every key is ten bytes.
+struct Bucket;
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Ok(FetchedObject {
+            content_length: Some(10),
+            object_size: Some(10),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(Bytes::from(vec![b'x'; 10]))])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+async fn get(cache: &ConcurrentDiskCache, key: &str) -> GetFileResult {
+    cache
+        .get_file(
+            PathBuf::from(key),
+            Arc::new(FaultyStorageConnector::new(Arc::new(Bucket))),
+            GetFileOptions::default(),
+        )
+        .await
+}
+
+// Faults apply to the whole process, so everything that sets them runs in this one test.
+#[tokio::test]
+async fn test_injected_faults() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        100,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let failing = Fault {
+        error_rate: 1.0,
+        latency_ms: 0,
+    };
+
+    faults::set(FaultConfig {
+        s3: failing,
+        ..FaultConfig::default()
+    });
+    assert!(matches!(get(&cache, "a").await, GetFileResult::Error(_)));
+
+    // A failed disk write serves the object from S3 without caching it.
+    faults::set(FaultConfig {
+        disk_write: failing,
+        ..FaultConfig::default()
+    });
+    assert!(matches!(
+        get(&cache, "a").await,
+        GetFileResult::PassThrough(_)
+    ));
+
+    faults::set(FaultConfig {
+        s3: Fault {
+            error_rate: 0.0,
+            latency_ms: 50,
+        },
+        ..FaultConfig::default()
+    });
+    let started = Instant::now();
+    assert!(matches!(get(&cache, "a").await, GetFileResult::Hit(_)));
+    assert!(started.elapsed() >= Duration::from_millis(50));
+
+    faults::clear();
+    assert_eq!(faults::current(), FaultConfig::default());
+    assert!(matches!(get(&cache, "b").await, GetFileResult::Hit(_)));
+
+    // The same, through the admin endpoint.
+    let client = Client::tracked(
+        rocket::build()
+            .manage(AuthConfig {
+                admin_token: Some(String::from("admin")),
+                read_tokens: Vec::new(),
+            })
+            .mount(
+                "/",
+                routes![
+                    faults::show_faults,
+                    faults::set_faults,
+                    faults::clear_faults
+                ],
+            ),
+    )
+    .await
+    .unwrap();
+    let admin = || Header::new("Authorization", "Bearer admin");
+    let put = |body: &'static str| {
+        client
+            .put("/admin/faults")
+            .header(admin())
+            .header(ContentType::JSON)
+            .body(body)
+    };
+    assert_eq!(
+        put(r#"{"s3": {"error_rate": 2}}"#)
+            .dispatch()
+            .await
+            .status(),
+        Status::BadRequest
+    );
+    assert_eq!(faults::current(), FaultConfig::default());
+    assert_eq!(
+        put(r#"{"redis": {"latency_ms": 1500}, "peer": {"error_rate": 0.5}}"#)
+            .dispatch()
+            .await
+            .status(),
+        Status::Ok
+    );
+    let shown: FaultConfig = client
+        .get("/admin/faults")
+        .header(admin())
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(shown.redis.latency_ms, 1500);
+    assert_eq!(shown.peer.error_rate, 0.5);
+    assert_eq!(shown, faults::current());
+    assert_eq!(
+        client.delete("/admin/faults").dispatch().await.status(),
+        Status::Unauthorized
+    );
+    client
+        .delete("/admin/faults")
+        .header(admin())
+        .dispatch()
+        .await;
+    assert_eq!(faults::current(), FaultConfig::default());
+}
@@ -0,0 +1,97 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::priority::{with_priority, RequestPriority};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use istziio_server_node::storage::throttled_storage_connector::{
+    FetchLimiter, ThrottledStorageConnector,
+};
+use rocket::futures::stream;
+use std::io::{ErrorKind, Result as IoResult};
+use std::sync::Arc;
+use std::time::Duration;
+
+struct StaticConnector;
+
+#[async_trait]
+impl StorageConnector for StaticConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Ok(FetchedObject {
+            stream: Box::pin(stream::iter(vec![Ok(Bytes::from_static(b"data"))])),
+            content_length: Some(4),
+            object_size: Some(4),
+            version: Default::default(),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[tokio::test]
+async fn test_fetch_limiter_rejects_after_queue_timeout() {
+    let limiter = FetchLimiter::new(1, Duration::from_millis(20));
+    let permit = limiter.acquire().await.unwrap();
+    assert_eq!(limiter.in_flight(), 1);
+    let err = limiter.acquire().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert_eq!(limiter.rejected(), 1);
+    assert_eq!(limiter.queue_depth(), 0);
+    drop(permit);
+    assert!(limiter.acquire().await.is_ok());
+}
+
+#[tokio::test]
+async fn test_throttled_connector_holds_slot_until_body_is_dropped() {
+    let limiter = Arc::new(FetchLimiter::new(1, Duration::from_millis(20)));
+    let connector = ThrottledStorageConnector::new(Arc::new(StaticConnector), limiter.clone());
+    let object = connector.fetch_stream("a.csv").await.unwrap();
+    assert_eq!(limiter.in_flight(), 1);
+    assert!(connector.fetch_stream("b.csv").await.is_err());
+    drop(object);
+    assert_eq!(limiter.in_flight(), 0);
+    assert!(connector.fetch_stream("b.csv").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_batch_fetches_wait_for_interactive_ones() {
+    let limiter = Arc::new(FetchLimiter::new(1, Duration::from_secs(5)));
+    let held = limiter.acquire().await.unwrap();
+    let batch = tokio::spawn({
+        let limiter = limiter.clone();
+        with_priority(RequestPriority::Batch, async move {
+            limiter.acquire().await.map(|_permit| ())
+        })
+    });
+    tokio::task::yield_now().await;
+    let interactive = tokio::spawn({
+        let limiter = limiter.clone();
+        async move { limiter.acquire().await }
+    });
+    while limiter.queue_depth() < 2 {
+        tokio::task::yield_now().await;
+    }
+    drop(held);
+    let permit = interactive.await.unwrap().unwrap();
+    assert!(!batch.is_finished());
+    assert_eq!(limiter.batch_in_flight(), 1);
+    drop(permit);
+    batch.await.unwrap().unwrap();
+    assert_eq!(limiter.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn test_batch_fetches_leave_slots_to_interactive_ones() {
+    let limiter = FetchLimiter::new(2, Duration::from_millis(20)).limiting_batch_to(Some(1));
+    let batch = with_priority(RequestPriority::Batch, limiter.acquire())
+        .await
+        .unwrap();
+    let err = with_priority(RequestPriority::Batch, limiter.acquire())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(limiter.acquire().await.is_ok());
+    drop(batch);
+    assert_eq!(limiter.batch_in_flight(), 0);
+}
@@ -0,0 +1,217 @@
+use arrow::array::{Array, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::ipc::reader::StreamReader;
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::{Body, Client, Request};
+use istziio_server_node::auth::AuthConfig;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::flight::{FlightService, FlightTicket};
+use istziio_server_node::grpc::{self, Code, GrpcServer};
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::protobuf::{self, fields};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use rocket::futures::stream;
+use std::io::{Cursor, Error, ErrorKind, Result as IoResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// `table.parquet`: 30 rows of `id` and `name`, in row groups of 10.
+fn table() -> Vec<u8> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("name", DataType::Utf8, false),
+    ]));
+    let ids: Vec<i64> = (0..30).collect();
+    let names: Vec<String> = ids.iter().map(|id| format!("row-{}", id)).collect();
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(ids)),
+            Arc::new(StringArray::from(names)),
+        ],
+    )
+    .unwrap();
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(10)
+        .build();
+    let mut data = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut data, schema, Some(properties)).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    data
+}
+
+/// Holds `table.parquet` and counts whole-object fetches.
+#[derive(Default)]
+struct Bucket {
+    fetches: AtomicUsize,
+}
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        if file_name != "table.parquet" {
+            return Err(Error::new(ErrorKind::NotFound, "no such key"));
+        }
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        let body = Bytes::from(table());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        let mut object = self.fetch_stream(file_name).await?;
+        let data = table();
+        let (start, end) = range
+            .resolve(data.len() as u64)
+            .ok_or_else(|| Error::from(ErrorKind::InvalidInput))?;
+        let part = Bytes::from(data[start as usize..=end as usize].to_vec());
+        object.content_length = Some(part.len() as u64);
+        object.stream = Box::pin(stream::iter(vec![Ok(part)]));
+        Ok(object)
+    }
+}
+
+async fn serve(port: u16, bucket: Arc<Bucket>) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().join("cache"),
+        10_000_000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ));
+    let service = FlightService::new(cache, vec![bucket], 2000);
+    let server = GrpcServer::new(AuthConfig::default()).with_service(Arc::new(service));
+    tokio::spawn(grpc::serve(
+        Arc::new(server),
+        format!("127.0.0.1:{}", port),
+        None,
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    dir
+}
+
+/// Calls DoGet with `ticket`; returns the batches it sent and the `grpc-status`.
+async fn do_get(port: u16, ticket: &FlightTicket) -> (Vec<RecordBatch>, u32) {
+    let mut request = Vec::new();
+    protobuf::put_bytes(&mut request, 1, &serde_json::to_vec(ticket).unwrap());
+    let mut body = vec![0];
+    body.extend_from_slice(&(request.len() as u32).to_be_bytes());
+    body.extend_from_slice(&request);
+    let request = Request::post(format!(
+        "http://127.0.0.1:{}/arrow.flight.protocol.FlightService/DoGet",
+        port
+    ))
+    .header("content-type", "application/grpc")
+    .body(Body::from(body))
+    .unwrap();
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    let mut response = client.request(request).await.unwrap();
+    let mut data = BytesMut::new();
+    while let Some(chunk) = response.body_mut().data().await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    let trailers = response.body_mut().trailers().await.unwrap().unwrap();
+    let status = trailers["grpc-status"].to_str().unwrap().parse().unwrap();
+    if data.is_empty() {
+        return (Vec::new(), status);
+    }
+    // Lay the FlightData out as an IPC stream, which is what Flight clients do too.
+    let mut ipc = Vec::new();
+    while data.has_remaining() {
+        assert_eq!(data.get_u8(), 0);
+        let len = data.get_u32() as usize;
+        let message = data.split_to(len);
+        let (mut header, mut body) = (&[][..], &[][..]);
+        for field in fields(&message) {
+            match field.unwrap() {
+                (2, value) => header = value.as_bytes().unwrap(),
+                (1000, value) => body = value.as_bytes().unwrap(),
+                _ => {}
+            }
+        }
+        let padding = (8 - header.len() % 8) % 8;
+        ipc.extend_from_slice(&u32::MAX.to_le_bytes());
+        ipc.extend_from_slice(&((header.len() + padding) as u32).to_le_bytes());
+        ipc.extend_from_slice(header);
+        ipc.resize(ipc.len() + padding, 0);
+        ipc.extend_from_slice(body);
+    }
+    ipc.extend_from_slice(&u32::MAX.to_le_bytes());
+    ipc.extend_from_slice(&0u32.to_le_bytes());
+    let batches = StreamReader::try_new(Cursor::new(ipc), None)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    (batches, status)
+}
+
+fn ticket(row_groups: Option<Vec<usize>>, columns: Option<Vec<&str>>) -> FlightTicket {
+    FlightTicket {
+        key: "table.parquet".into(),
+        row_groups,
+        columns: columns.map(|columns| columns.into_iter().map(String::from).collect()),
+    }
+}
+
+#[tokio::test]
+async fn test_do_get() {
+    let port = 28_511;
+    let bucket = Arc::new(Bucket::default());
+    let _dir = serve(port, bucket.clone()).await;
+
+    let (batches, status) = do_get(port, &ticket(None, None)).await;
+    assert_eq!(status, Code::Ok as u32);
+    let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+    assert_eq!(rows, 30);
+    assert_eq!(batches[0].num_columns(), 2);
+
+    let (batches, status) = do_get(port, &ticket(Some(vec![1]), Some(vec!["name"]))).await;
+    assert_eq!(status, Code::Ok as u32);
+    assert_eq!(batches.len(), 1);
+    let batch = &batches[0];
+    assert_eq!(batch.schema().field(0).name(), "name");
+    assert_eq!(batch.num_columns(), 1);
+    let names = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(names.len(), 10);
+    assert_eq!(names.value(0), "row-10");
+    assert_eq!(names.value(9), "row-19");
+
+    // Both reads were served from the one cached copy.
+    assert_eq!(bucket.fetches.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_do_get_rejects_bad_tickets() {
+    let port = 28_512;
+    let _dir = serve(port, Arc::new(Bucket::default())).await;
+
+    let (_, status) = do_get(port, &ticket(Some(vec![3]), None)).await;
+    assert_eq!(status, Code::OutOfRange as u32);
+    let (_, status) = do_get(port, &ticket(None, Some(vec!["price"]))).await;
+    assert_eq!(status, Code::InvalidArgument as u32);
+    let missing = FlightTicket {
+        key: "missing.parquet".into(),
+        row_groups: None,
+        columns: None,
+    };
+    let (_, status) = do_get(port, &missing).await;
+    assert_eq!(status, Code::NotFound as u32);
+}
@@ -0,0 +1,18 @@
+use istziio_server_node::footer::{footer_key, footer_len, is_footer_key};
+
+#[test]
+fn test_footer_len() {
+    let mut tail = b"metadata".to_vec();
+    tail.extend_from_slice(&8u32.to_le_bytes());
+    tail.extend_from_slice(b"PAR1");
+    assert_eq!(footer_len(&tail), Some(16));
+    assert_eq!(footer_len(b"PAR1"), None);
+    assert_eq!(footer_len(b"\x08\x00\x00\x00PAR2"), None);
+}
+
+#[test]
+fn test_footer_key() {
+    let key = footer_key("warehouse/orders/part-0.parquet");
+    assert!(is_footer_key(&key));
+    assert!(!is_footer_key("warehouse/orders/part-0.parquet"));
+}
@@ -0,0 +1,214 @@
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::{Body, Client, Request};
+use istziio_server_node::auth::AuthConfig;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache};
+use istziio_server_node::cache_service::{CacheService, CHUNK_LEN};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::grpc::{self, Code, GrpcServer};
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::protobuf::{self, fields};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Objects whose bytes count up from their first; `missing` does not exist.
+struct Bucket;
+
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        if file_name == "missing" {
+            return Err(Error::new(ErrorKind::NotFound, "no such key"));
+        }
+        let body = Bytes::from(contents(CHUNK_LEN + CHUNK_LEN / 2));
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, range: ByteRange) -> IoResult<FetchedObject> {
+        let mut object = self.fetch_stream(file_name).await?;
+        let data = contents(CHUNK_LEN + CHUNK_LEN / 2);
+        let (start, end) = range
+            .resolve(data.len() as u64)
+            .ok_or_else(|| Error::from(ErrorKind::InvalidInput))?;
+        let part = Bytes::from(data[start as usize..=end as usize].to_vec());
+        object.content_length = Some(part.len() as u64);
+        object.stream = Box::pin(stream::iter(vec![Ok(part)]));
+        Ok(object)
+    }
+}
+
+/// Serves the cache service on `port`, requiring `tokens` when there are any.
+async fn serve(port: u16, tokens: &[&str]) -> (TempDir, Arc<ConcurrentDiskCache>) {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().join("cache"),
+        10_000_000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ));
+    let service = CacheService::new(cache.clone(), vec![Arc::new(Bucket)], 2000);
+    let server = GrpcServer::new(AuthConfig {
+        admin_token: None,
+        read_tokens: tokens.iter().map(|token| token.to_string()).collect(),
+    })
+    .with_service(Arc::new(service));
+    tokio::spawn(grpc::serve(
+        Arc::new(server),
+        format!("127.0.0.1:{}", port),
+        None,
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    (dir, cache)
+}
+
+/// Calls `method` with `request`; returns the response messages and the `grpc-status`.
+async fn call(port: u16, method: &str, request: &[u8], token: Option<&str>) -> (Vec<Vec<u8>>, u32) {
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    let mut body = vec![0];
+    body.extend_from_slice(&(request.len() as u32).to_be_bytes());
+    body.extend_from_slice(request);
+    let mut builder = Request::post(format!("http://127.0.0.1:{}/{}", port, method))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers");
+    if let Some(token) = token {
+        builder = builder.header("authorization", format!("Bearer {}", token));
+    }
+    let mut response = client
+        .request(builder.body(Body::from(body)).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let mut data = BytesMut::new();
+    while let Some(chunk) = response.body_mut().data().await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    let trailers = response.body_mut().trailers().await.unwrap().unwrap();
+    let status = trailers["grpc-status"].to_str().unwrap().parse().unwrap();
+    let mut messages = Vec::new();
+    while data.has_remaining() {
+        assert_eq!(data.get_u8(), 0);
+        let len = data.get_u32() as usize;
+        messages.push(data.split_to(len).to_vec());
+    }
+    (messages, status)
+}
+
+fn key_request(key: &str) -> Vec<u8> {
+    let mut request = Vec::new();
+    protobuf::put_string(&mut request, 1, key);
+    request
+}
+
+/// The `chunk` field of each `GetObjectResponse`, joined.
+fn chunks(messages: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for message in messages {
+        for field in fields(message) {
+            let (number, value) = field.unwrap();
+            assert_eq!(number, 1);
+            data.extend_from_slice(value.as_bytes().unwrap());
+        }
+    }
+    data
+}
+
+#[tokio::test]
+async fn test_get_object() {
+    let port = 28_501;
+    let (_dir, cache) = serve(port, &[]).await;
+    let method = "istziio.cache.v1.Cache/GetObject";
+
+    let (messages, status) = call(port, method, &key_request("t/part-0"), None).await;
+    assert_eq!(status, Code::Ok as u32);
+    assert_eq!(messages.len(), 2);
+    assert_eq!(chunks(&messages), contents(CHUNK_LEN + CHUNK_LEN / 2));
+    assert!(cache.is_cached("t/part-0").await);
+
+    let mut range = Vec::new();
+    protobuf::put_uint64(&mut range, 1, 10);
+    protobuf::put_uint64(&mut range, 2, 19);
+    let mut request = key_request("t/part-0");
+    protobuf::put_message(&mut request, 2, &range);
+    let (messages, status) = call(port, method, &request, None).await;
+    assert_eq!(status, Code::Ok as u32);
+    assert_eq!(chunks(&messages), contents(20)[10..]);
+
+    let (messages, status) = call(port, method, &key_request("missing"), None).await;
+    assert_eq!(status, Code::NotFound as u32);
+    assert!(messages.is_empty());
+
+    // Keys of objects read from URLs can't be asked for as bucket keys.
+    let key = "@fetch/https/example.com:443/t/part-0";
+    let (_, status) = call(port, method, &key_request(key), None).await;
+    assert_eq!(status, Code::InvalidArgument as u32);
+}
+
+#[tokio::test]
+async fn test_probe_prefetch_and_stats() {
+    let port = 28_502;
+    let _node = serve(port, &[]).await;
+    let probe = |key| async move {
+        let (messages, status) = call(
+            port,
+            "istziio.cache.v1.Cache/Probe",
+            &key_request(key),
+            None,
+        )
+        .await;
+        assert_eq!(status, Code::Ok as u32);
+        let (number, value) = fields(&messages[0]).next().unwrap().unwrap();
+        assert_eq!(number, 1);
+        value.as_bool().unwrap()
+    };
+    assert!(!probe("a").await);
+
+    let mut request = Vec::new();
+    protobuf::put_string(&mut request, 1, "a");
+    protobuf::put_string(&mut request, 1, "missing");
+    let (messages, status) = call(port, "istziio.cache.v1.Cache/Prefetch", &request, None).await;
+    assert_eq!(status, Code::Ok as u32);
+    let outcome: Vec<(u32, String)> = fields(&messages[0])
+        .map(|field| {
+            let (number, value) = field.unwrap();
+            (number, value.as_str().unwrap().to_string())
+        })
+        .collect();
+    assert_eq!(outcome, [(1, "a".into()), (3, "missing".into())]);
+    assert!(probe("a").await);
+
+    let (messages, status) = call(port, "istziio.cache.v1.Cache/Stats", &[], None).await;
+    assert_eq!(status, Code::Ok as u32);
+    let (_, text) = fields(&messages[0]).next().unwrap().unwrap();
+    assert!(!text.as_str().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_calls_need_a_read_token() {
+    let port = 28_503;
+    let _node = serve(port, &["reader"]).await;
+    let method = "istziio.cache.v1.Cache/Stats";
+    let (_, status) = call(port, method, &[], None).await;
+    assert_eq!(status, Code::Unauthenticated as u32);
+    let (_, status) = call(port, method, &[], Some("wrong")).await;
+    assert_eq!(status, Code::Unauthenticated as u32);
+    let (_, status) = call(port, method, &[], Some("reader")).await;
+    assert_eq!(status, Code::Ok as u32);
+
+    let (_, status) = call(port, "istziio.cache.v1.Cache/Put", &[], Some("reader")).await;
+    assert_eq!(status, Code::Unimplemented as u32);
+}
@@ -0,0 +1,136 @@
+use async_trait::async_trait;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::health::{self, HealthProbes};
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use istziio_server_node::warmup::WarmUp;
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::routes;
+use serde_json::Value;
+use std::io::{self, Result as IoResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Fails every request with `kind`, counting them.
+struct FailingConnector {
+    kind: io::ErrorKind,
+    requests: AtomicUsize,
+}
+
+#[async_trait]
+impl StorageConnector for FailingConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        Err(io::Error::new(self.kind, "boom"))
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+async fn client(cache: Arc<ConcurrentDiskCache>, connector: Arc<FailingConnector>) -> Client {
+    let connectors: Vec<Arc<dyn StorageConnector + Send + Sync>> = vec![connector];
+    let rocket = rocket::build()
+        .manage(cache)
+        .manage(connectors)
+        .manage(None::<Arc<WarmUp>>)
+        .manage(HealthProbes::default())
+        .mount("/", routes![health::live, health::ready]);
+    Client::tracked(rocket).await.unwrap()
+}
+
+fn cache_in(dir: &std::path::Path) -> Arc<ConcurrentDiskCache> {
+    Arc::new(ConcurrentDiskCache::new(
+        dir.to_path_buf(),
+        1000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ))
+}
+
+/// The status and the body of a probe, with the names of the checks that failed.
+async fn probe(client: &Client, path: &str) -> (Status, Value, Vec<String>) {
+    let response = client.get(path).dispatch().await;
+    let status = response.status();
+    let body: Value = response.into_json().await.unwrap();
+    let failing = body["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|check| !check["ok"].as_bool().unwrap())
+        .map(|check| check["name"].as_str().unwrap().to_string())
+        .collect();
+    (status, body, failing)
+}
+
+#[rocket::async_test]
+async fn test_ready_when_every_dependency_answers() {
+    let dir = tempfile::tempdir().unwrap();
+    let connector = Arc::new(FailingConnector {
+        kind: io::ErrorKind::NotFound,
+        requests: AtomicUsize::new(0),
+    });
+    let client = client(cache_in(dir.path()), connector.clone()).await;
+
+    let (status, body, failing) = probe(&client, "/healthz/ready").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["ok"], true);
+    let names: Vec<&str> = body["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|check| check["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["metadata", "disks", "s3"]);
+    assert!(failing.is_empty());
+
+    // The backing store answer is reused rather than asked for on every probe.
+    let (status, _, _) = probe(&client, "/healthz/ready").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(connector.requests.load(Ordering::Relaxed), 1);
+
+    let (status, body, _) = probe(&client, "/healthz/live").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["ok"], true);
+}
+
+#[rocket::async_test]
+async fn test_not_ready_without_credentials_or_disks() {
+    let dir = tempfile::tempdir().unwrap();
+    let connector = Arc::new(FailingConnector {
+        kind: io::ErrorKind::PermissionDenied,
+        requests: AtomicUsize::new(0),
+    });
+    let cache_dir = dir.path().join("cache");
+    let client = client(cache_in(&cache_dir), connector).await;
+
+    let (status, body, failing) = probe(&client, "/healthz/ready").await;
+    assert_eq!(status, Status::ServiceUnavailable);
+    assert_eq!(body["ok"], false);
+    assert_eq!(failing, vec!["s3"]);
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+    let (status, _, failing) = probe(&client, "/healthz/ready").await;
+    assert_eq!(status, Status::ServiceUnavailable);
+    assert_eq!(failing, vec!["disks", "s3"]);
+
+    // Neither makes the process itself unhealthy.
+    let (status, _, _) = probe(&client, "/healthz/live").await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[tokio::test]
+async fn test_stuck_lock_is_not_responsive() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = cache_in(dir.path());
+    assert!(cache.is_responsive(Duration::from_millis(100)).await);
+    let held = cache.metadata.write().await;
+    assert!(!cache.is_responsive(Duration::from_millis(100)).await);
+    drop(held);
+    assert!(cache.is_responsive(Duration::from_millis(100)).await);
+}
@@ -0,0 +1,124 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::disk_io::DiskIo;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::ffi::CString;
+use std::io::{Result as IoResult, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Answers every fetch with the key itself.
+struct EchoConnector;
+
+#[async_trait]
+impl StorageConnector for EchoConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn hedging_cache(dir: &Path, after: Duration) -> ConcurrentDiskCache {
+    ConcurrentDiskCache::new(
+        dir.to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            hedge_after: Some(after),
+            ..Default::default()
+        },
+    )
+}
+
+/// Replaces `path` with a FIFO: opening it blocks until a writer shows up, like a disk
+/// that stopped answering.
+fn stall(path: &Path) {
+    std::fs::remove_file(path).unwrap();
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+}
+
+#[tokio::test]
+async fn test_primed_file_reads_like_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data");
+    let body: Vec<u8> = (0..100u8).collect();
+    std::fs::write(&path, &body).unwrap();
+    let (mut file, len) = DiskIo::default().open_primed(&path, 30).await.unwrap();
+    assert_eq!(len, 100);
+    let mut start = [0; 10];
+    file.read_exact(&mut start).await.unwrap();
+    assert_eq!(start[..], body[..10]);
+    // Seeking from the current position accounts for the bytes read ahead.
+    file.seek(SeekFrom::Current(5)).await.unwrap();
+    let mut rest = Vec::new();
+    file.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(rest, body[15..]);
+    file.seek(SeekFrom::Start(0)).await.unwrap();
+    rest.clear();
+    file.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(rest, body);
+}
+
+#[tokio::test]
+async fn test_fast_disk_is_not_hedged() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = hedging_cache(dir.path(), Duration::from_secs(10));
+    let connector = Arc::new(EchoConnector);
+    let get = || {
+        cache.get_file(
+            PathBuf::from("a.csv"),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!((stats.hedged_reads, stats.hedges_won), (0, 0));
+}
+
+#[tokio::test]
+async fn test_stalled_disk_is_hedged_to_s3() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = hedging_cache(dir.path(), Duration::from_millis(20));
+    let connector = Arc::new(EchoConnector);
+    let get = || {
+        cache.get_file(
+            PathBuf::from("a.csv"),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    let path = dir.path().join("a.csv");
+    stall(&path);
+
+    assert!(matches!(get().await, GetFileResult::PassThrough(_)));
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!((stats.hedged_reads, stats.hedges_won), (1, 1));
+    assert_eq!(stats.disk_hits, 1);
+
+    // Let the abandoned open finish so the runtime can shut down.
+    drop(std::fs::OpenOptions::new().write(true).open(&path).unwrap());
+}
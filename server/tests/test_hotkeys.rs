@@ -0,0 +1,68 @@
+use istziio_server_node::hotkeys::{merge_top, CountMinSketch, HotKey, HotKeys, TopK};
+
+#[test]
+fn test_count_min_never_undercounts() {
+    let mut sketch = CountMinSketch::new(16, 3);
+    for i in 0..200 {
+        sketch.add(&format!("key-{}", i), 1);
+    }
+    for _ in 0..50 {
+        sketch.add("hot", 1);
+    }
+    assert!(sketch.estimate("hot") >= 50);
+    assert!(sketch.estimate("key-7") >= 1);
+    sketch.clear();
+    assert_eq!(sketch.estimate("hot"), 0);
+}
+
+#[test]
+fn test_top_k_replaces_the_coldest() {
+    let mut top = TopK::new(2);
+    top.offer("a", 1);
+    top.offer("b", 2);
+    top.offer("a", 3);
+    // "b" is now the coldest and is replaced by a hotter newcomer.
+    top.offer("c", 5);
+    top.offer("d", 1);
+    assert_eq!(
+        top.ranked(),
+        vec![(String::from("c"), 5), (String::from("a"), 3)]
+    );
+}
+
+#[test]
+fn test_hot_keys_rank_by_accesses_and_bytes() {
+    let mut hot = HotKeys::new(4);
+    for _ in 0..10 {
+        hot.record("dim/nation.parquet", 100);
+    }
+    hot.record("fact/orders.parquet", 1 << 20);
+    for i in 0..20 {
+        hot.record(&format!("tmp/{}", i), 1);
+    }
+    let by_accesses = hot.by_accesses(1);
+    assert_eq!(
+        by_accesses,
+        vec![HotKey {
+            key: String::from("dim/nation.parquet"),
+            accesses: 10,
+            bytes: 1000,
+        }]
+    );
+    assert_eq!(hot.by_bytes(1)[0].key, "fact/orders.parquet");
+}
+
+#[test]
+fn test_merge_top_across_shards() {
+    let key = |name: &str, accesses| HotKey {
+        key: String::from(name),
+        accesses,
+        bytes: 0,
+    };
+    let merged = merge_top(
+        vec![vec![key("a", 5), key("b", 1)], vec![key("c", 3)]],
+        2,
+        |k| k.accesses,
+    );
+    assert_eq!(merged, vec![key("a", 5), key("c", 3)]);
+}
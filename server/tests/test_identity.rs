@@ -0,0 +1,35 @@
+use istziio_server_node::identity::{is_uuid, load_or_create, new_uuid, NODE_ID_FILE};
+
+#[test]
+fn test_new_uuid() {
+    let id = new_uuid().unwrap();
+    assert!(is_uuid(&id), "{}", id);
+    // Version 4, RFC 4122 variant.
+    assert_eq!(&id[14..15], "4");
+    assert!("89ab".contains(&id[19..20]));
+    assert_ne!(new_uuid().unwrap(), id);
+
+    assert!(!is_uuid("6F1C2A8E-0B7D-4C3E-9A51-2D8E4F7B1C90"));
+    assert!(!is_uuid("6f1c2a8e0b7d4c3e9a512d8e4f7b1c90"));
+    assert!(!is_uuid(""));
+}
+
+#[test]
+fn test_node_id_is_kept_across_restarts() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache");
+    let id = load_or_create(&cache_dir).unwrap();
+    assert!(is_uuid(&id));
+    assert_eq!(load_or_create(&cache_dir).unwrap(), id);
+    assert_eq!(
+        std::fs::read_dir(&cache_dir).unwrap().count(),
+        1,
+        "only {} is left behind",
+        NODE_ID_FILE
+    );
+
+    // A damaged id is not silently replaced, which would orphan the node's entries.
+    std::fs::write(cache_dir.join(NODE_ID_FILE), "not an id\n").unwrap();
+    let e = load_or_create(&cache_dir).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+}
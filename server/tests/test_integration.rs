@@ -1,6 +1,7 @@
 use rocket::http::Status;
 
 mod utils;
+use utils::admin_auth;
 
 #[test]
 fn test_healthy() {
@@ -28,7 +29,7 @@ fn test_clear() {
     let stats = response.into_string().unwrap();
     assert!(stats.contains("test2"));
 
-    let response = client_1.post("/clear").dispatch();
+    let response = client_1.post("/clear").header(admin_auth()).dispatch();
     assert_eq!(response.status(), Status::Ok);
     let response = client_1.get("/stats").dispatch();
     let stats = response.into_string().unwrap();
@@ -51,11 +52,11 @@ fn test_get_file() {
     let response = client_3.get("/s3/test6.txt").dispatch();
     assert_eq!(response.status(), Status::SeeOther);
 
-    let response = client_1.post("/clear").dispatch();
+    let response = client_1.post("/clear").header(admin_auth()).dispatch();
     assert_eq!(response.status(), Status::Ok);
-    let response = client_2.post("/clear").dispatch();
+    let response = client_2.post("/clear").header(admin_auth()).dispatch();
     assert_eq!(response.status(), Status::Ok);
-    let response = client_3.post("/clear").dispatch();
+    let response = client_3.post("/clear").header(admin_auth()).dispatch();
     assert_eq!(response.status(), Status::Ok);
 }
 
@@ -63,7 +64,7 @@ fn test_get_file() {
 fn test_evict() {
     let (_, [client_1, _, _]) = utils::launch_server_node_size_3(true);
 
-    let response = client_1.post("/clear").dispatch();
+    let response = client_1.post("/clear").header(admin_auth()).dispatch();
     assert_eq!(response.status(), Status::Ok);
     let response = client_1.get("/s3/test6.txt").dispatch();
     assert_eq!(response.status(), Status::Ok);
@@ -82,7 +83,7 @@ fn test_s3_connector() {
     let (_, [client_1, _, _]) = utils::launch_server_node_size_3(false);
     let response: rocket::local::blocking::LocalResponse = client_1.get("/s3/test2.txt").dispatch();
     assert_eq!(response.status(), Status::Ok);
-    let response = client_1.post("/clear").dispatch();
+    let response = client_1.post("/clear").header(admin_auth()).dispatch();
     assert_eq!(response.status(), Status::Ok);
 }
 
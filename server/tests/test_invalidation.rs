@@ -0,0 +1,26 @@
+use istziio_server_node::invalidation::Invalidation;
+
+#[test]
+fn test_message_round_trip() {
+    for invalidation in [
+        Invalidation::Key(String::from("dir/a b.parquet")),
+        Invalidation::Prefix(String::from("tables/orders/")),
+        Invalidation::Prefix(String::new()),
+    ] {
+        assert_eq!(
+            Invalidation::parse(&invalidation.encode()),
+            Some(invalidation)
+        );
+    }
+}
+
+#[test]
+fn test_malformed_messages() {
+    assert_eq!(Invalidation::parse("key"), None);
+    assert_eq!(Invalidation::parse("key "), None);
+    assert_eq!(Invalidation::parse("drop a.parquet"), None);
+    assert_eq!(
+        Invalidation::parse("prefix dir/"),
+        Some(Invalidation::Prefix(String::from("dir/")))
+    );
+}
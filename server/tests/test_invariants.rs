@@ -0,0 +1,204 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::admission::AdmissionPolicy;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache, GetFileOptions};
+use istziio_server_node::cache_core::CacheCore;
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::policy::PolicySet;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use istziio_server_node::util::disk_name;
+use proptest::prelude::*;
+use rocket::futures::stream;
+use std::collections::HashMap;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Debug, Clone)]
+enum CoreOp {
+    Access(u8, u64),
+    Touch(u8),
+    Remove(u8),
+    Charge(u64),
+    Refund(u64),
+    Resize(u64),
+}
+
+fn core_op() -> impl Strategy<Value = CoreOp> {
+    prop_oneof![
+        4 => (0..16u8, 1..=40u64).prop_map(|(key, size)| CoreOp::Access(key, size)),
+        1 => (0..16u8).prop_map(CoreOp::Touch),
+        1 => (0..16u8).prop_map(CoreOp::Remove),
+        1 => (1..=20u64).prop_map(CoreOp::Charge),
+        1 => (1..=20u64).prop_map(CoreOp::Refund),
+        1 => (20..=150u64).prop_map(CoreOp::Resize),
+    ]
+}
+
+proptest! {
+    // Checked against a map of what should be cached: hits are exactly the tracked keys,
+    // and only tracked entries are evicted, with the size they were admitted with.
+    #[test]
+    fn test_core_accounting(ops in prop::collection::vec(core_op(), 1..200)) {
+        let mut core = CacheCore::new(100, PolicySet::default(), AdmissionPolicy::Always);
+        let mut expected: HashMap<String, u64> = HashMap::new();
+        let mut stray = 0;
+        let now = Instant::now();
+        for op in ops {
+            let evicted = match op {
+                CoreOp::Access(key, size) => {
+                    let key = format!("k{}", key);
+                    let access = core.access(&key, size, now);
+                    prop_assert_eq!(access.hit, expected.contains_key(&key));
+                    if access.admitted {
+                        expected.insert(key, size);
+                    }
+                    access.evicted
+                }
+                CoreOp::Touch(key) => {
+                    core.touch(&format!("k{}", key));
+                    Vec::new()
+                }
+                CoreOp::Remove(key) => core.remove(&format!("k{}", key)).into_iter().collect(),
+                CoreOp::Charge(bytes) => {
+                    core.charge(bytes);
+                    stray += bytes;
+                    Vec::new()
+                }
+                CoreOp::Refund(bytes) => {
+                    core.refund(bytes);
+                    stray -= bytes.min(stray);
+                    Vec::new()
+                }
+                CoreOp::Resize(max_size) => {
+                    core.set_max_size(max_size);
+                    core.make_room("", 0)
+                }
+            };
+            for (name, size) in evicted {
+                prop_assert_eq!(expected.remove(&name), Some(size));
+            }
+            prop_assert_eq!(core.debug_validate(), Ok(()));
+            prop_assert_eq!(core.stray_size(), stray);
+            prop_assert!(core.current_size() - stray <= core.max_size());
+            let tracked: HashMap<String, u64> = core.entries().into_iter().collect();
+            prop_assert_eq!(&tracked, &expected);
+        }
+    }
+}
+
+/// Object `k<n>` has `5 + 13n % 40` bytes.
+struct Bucket;
+
+fn object_size(key: &str) -> u64 {
+    let n: u64 = key[1..].parse().unwrap();
+    5 + 13 * n % 40
+}
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let size = object_size(file_name);
+        Ok(FetchedObject {
+            content_length: Some(size),
+            object_size: Some(size),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(Bytes::from(vec![
+                b'x';
+                size as usize
+            ]))])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CacheOp {
+    Get(u8),
+    Ingest(u8),
+    Invalidate(u8),
+    Forget(u8),
+    Resize(u64),
+    /// The file disappears behind the cache's back and a scrub notices.
+    LoseFile(u8),
+}
+
+fn cache_op() -> impl Strategy<Value = CacheOp> {
+    prop_oneof![
+        5 => (0..12u8).prop_map(CacheOp::Get),
+        2 => (0..12u8).prop_map(CacheOp::Ingest),
+        1 => (0..12u8).prop_map(CacheOp::Invalidate),
+        1 => (0..12u8).prop_map(CacheOp::Forget),
+        1 => (40..=200u64).prop_map(CacheOp::Resize),
+        1 => (0..12u8).prop_map(CacheOp::LoseFile),
+    ]
+}
+
+async fn apply(cache: &ConcurrentDiskCache, dir: &std::path::Path, op: CacheOp) {
+    match op {
+        CacheOp::Get(key) => {
+            cache
+                .get_file(
+                    PathBuf::from(format!("k{}", key)),
+                    Arc::new(Bucket),
+                    GetFileOptions::default(),
+                )
+                .await;
+        }
+        CacheOp::Ingest(key) => {
+            let key = format!("k{}", key);
+            let body = Bytes::from(vec![b'y'; object_size(&key) as usize]);
+            let staged = cache
+                .stage(&key, Box::pin(stream::iter(vec![Ok(body)])))
+                .await
+                .unwrap();
+            cache.ingest(&key, staged).await.unwrap();
+        }
+        CacheOp::Invalidate(key) => {
+            cache.invalidate(&format!("k{}", key)).await;
+        }
+        CacheOp::Forget(key) => {
+            cache.forget(&format!("k{}", key)).await;
+        }
+        CacheOp::Resize(max_size) => {
+            cache.set_max_size(max_size).await;
+        }
+        CacheOp::LoseFile(key) => {
+            let _ = tokio::fs::remove_file(dir.join(disk_name(&format!("k{}", key)))).await;
+            cache.scrub().await;
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_disk_cache_accounting(ops in prop::collection::vec(cache_op(), 1..60)) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let result: Result<(), String> = runtime.block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = ConcurrentDiskCache::new(
+                dir.path().to_path_buf(),
+                120,
+                2,
+                Box::new(InProcessStore::new(6379)),
+                CacheOptions::default(),
+            );
+            for op in ops {
+                apply(&cache, dir.path(), op.clone()).await;
+                cache
+                    .debug_validate()
+                    .await
+                    .map_err(|e| format!("after {:?}: {}", op, e))?;
+            }
+            Ok(())
+        });
+        prop_assert_eq!(result, Ok(()));
+    }
+}
@@ -0,0 +1,263 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::auth::AuthConfig;
+use istziio_server_node::cache::ClusterMember;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache, GetFileOptions};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::listing::{self, Listing, ListingCache};
+use istziio_server_node::metadata::{InProcessStore, MetadataStore, StoreError};
+use istziio_server_node::storage::storage_connector::{
+    FetchedObject, ListRequest, ObjectInfo, ObjectListing, StorageConnector,
+};
+use istziio_server_node::util::FileUid;
+use rocket::futures::stream;
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::routes;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A bucket of two partition files and a subdirectory; records the listings asked for.
+#[derive(Default)]
+struct PartitionConnector {
+    requests: Mutex<Vec<ListRequest>>,
+}
+
+#[async_trait]
+impl StorageConnector for PartitionConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        self.requests.lock().unwrap().push(request.clone());
+        let object = |name: &str, size| ObjectInfo {
+            key: format!("{}{}", request.prefix, name),
+            size,
+            last_modified: Some(1_700_000_000),
+            e_tag: None,
+        };
+        Ok(ObjectListing {
+            objects: vec![object("a.parquet", 10), object("b.parquet", 20)],
+            common_prefixes: vec![format!("{}month=1/", request.prefix)],
+            next_continuation_token: None,
+        })
+    }
+}
+
+fn request(prefix: &str) -> ListRequest {
+    ListRequest {
+        prefix: prefix.to_string(),
+        delimiter: Some(String::from("/")),
+        max_keys: 1000,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_listing_cache() {
+    let off = ListingCache::new(None);
+    off.insert(request("a/"), ObjectListing::default());
+    assert!(off.get(&request("a/")).is_none());
+
+    let listings = ListingCache::new(Some(Duration::from_millis(50)));
+    for prefix in ["", "a/", "a/b/", "c/"] {
+        listings.insert(request(prefix), ObjectListing::default());
+    }
+    assert!(listings.get(&request("a/")).is_some());
+    // A new key under a/b/ changes the top level, a/ and a/b/.
+    listings.invalidate("a/b/part-0.parquet");
+    assert!(listings.get(&request("a/")).is_none());
+    assert!(listings.get(&request("c/")).is_some());
+    assert_eq!(listings.len(), 1);
+    listings.insert(request("a/b/c/"), ObjectListing::default());
+    listings.invalidate_prefix("a/b");
+    assert_eq!(listings.len(), 1);
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert!(listings.get(&request("c/")).is_none());
+}
+
+#[tokio::test]
+async fn test_list_marks_cached_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            listing_ttl: Some(Duration::from_secs(60)),
+            ..Default::default()
+        },
+    ));
+    let connector = Arc::new(PartitionConnector::default());
+    let connectors: Vec<Arc<dyn StorageConnector + Send + Sync>> = vec![connector.clone()];
+    let client = Client::tracked(
+        rocket::build()
+            .manage(AuthConfig::default())
+            .manage(cache.clone())
+            .manage(connectors)
+            .mount("/", routes![listing::list]),
+    )
+    .await
+    .unwrap();
+    cache
+        .get_file(
+            PathBuf::from("year=2024/a.parquet"),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+        .await;
+
+    let list = || async {
+        let response = client.get("/list/year=2024").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<Listing>().await.unwrap()
+    };
+    let listing = list().await;
+    assert_eq!(listing.prefix, "year=2024/");
+    let flags: Vec<(&str, u64, bool)> = listing
+        .objects
+        .iter()
+        .map(|object| (object.key.as_str(), object.size, object.cached))
+        .collect();
+    assert_eq!(
+        flags,
+        [
+            ("year=2024/a.parquet", 10, true),
+            ("year=2024/b.parquet", 20, false)
+        ]
+    );
+    assert_eq!(listing.common_prefixes, ["year=2024/month=1/"]);
+    assert_eq!(connector.requests.lock().unwrap()[0], request("year=2024/"));
+
+    // The page is cached; the flags are not.
+    cache
+        .get_file(
+            PathBuf::from("year=2024/b.parquet"),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+        .await;
+    assert!(list().await.objects.iter().all(|object| object.cached));
+    assert_eq!(connector.requests.lock().unwrap().len(), 1);
+    assert_eq!(cache.cached_listings(), 1);
+
+    cache.invalidate("year=2024/c.parquet").await;
+    list().await;
+    assert_eq!(connector.requests.lock().unwrap().len(), 2);
+
+    let response = client.get("/list/?delimiter=").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        connector.requests.lock().unwrap()[2],
+        ListRequest {
+            delimiter: None,
+            ..request("")
+        }
+    );
+}
+
+/// One in-process store standing in for the Redis cluster of several nodes.
+struct SharedStore(Arc<InProcessStore>);
+
+#[async_trait]
+impl MetadataStore for SharedStore {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+    async fn initialize(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+    async fn location_lookup(&self, _uid: FileUid) -> Option<(String, u16)> {
+        None
+    }
+    async fn get_file(&self, uid: FileUid) -> Option<PathBuf> {
+        self.0.get_file(uid).await
+    }
+    async fn set_file_cache_loc(&self, uid: FileUid, loc: PathBuf) -> Result<(), ()> {
+        self.0.set_file_cache_loc(uid, loc).await
+    }
+    async fn remove_file(&self, uid: FileUid) -> Result<(), ()> {
+        self.0.remove_file(uid).await
+    }
+    async fn flush_all(&self) {
+        self.0.flush_all().await
+    }
+    fn members(&self) -> Vec<ClusterMember> {
+        self.0.members()
+    }
+    async fn load_listing(&self, key: &str) -> Result<Option<String>, StoreError> {
+        self.0.load_listing(key).await
+    }
+    async fn save_listing(&self, key: &str, page: &str, ttl: Duration) -> Result<(), StoreError> {
+        self.0.save_listing(key, page, ttl).await
+    }
+    async fn drop_listings(&self) -> Result<(), StoreError> {
+        self.0.drop_listings().await
+    }
+}
+
+#[tokio::test]
+async fn test_nodes_share_listings() {
+    let store = Arc::new(InProcessStore::new(6379));
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    let nodes: Vec<ConcurrentDiskCache> = dirs
+        .iter()
+        .map(|dir| {
+            ConcurrentDiskCache::new(
+                dir.path().to_path_buf(),
+                10_000,
+                1,
+                Box::new(SharedStore(store.clone())),
+                CacheOptions {
+                    listing_ttl: Some(Duration::from_secs(60)),
+                    share_listings: true,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+    let connector = Arc::new(PartitionConnector::default());
+    let dyn_connector: Arc<dyn StorageConnector + Send + Sync> = connector.clone();
+    let calls = || connector.requests.lock().unwrap().len();
+
+    let page = nodes[0]
+        .list_page(&request("year=2024/"), &dyn_connector)
+        .await
+        .unwrap();
+    let shared = nodes[1]
+        .list_page(&request("year=2024/"), &dyn_connector)
+        .await
+        .unwrap();
+    assert_eq!(shared, page);
+    assert_eq!(calls(), 1);
+    // Pages differing in any parameter are kept apart.
+    let next = ListRequest {
+        start_after: Some(String::from("year=2024/a.parquet")),
+        ..request("year=2024/")
+    };
+    nodes[1].list_page(&next, &dyn_connector).await.unwrap();
+    assert_eq!(calls(), 2);
+
+    // An invalidation on one node reaches the shared pages.
+    nodes[0].invalidate("year=2024/c.parquet").await;
+    nodes[0]
+        .list_page(&request("year=2024/"), &dyn_connector)
+        .await
+        .unwrap();
+    assert_eq!(calls(), 3);
+}
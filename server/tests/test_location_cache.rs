@@ -0,0 +1,42 @@
+use istziio_server_node::location_cache::{LocationCache, LocationCacheConfig};
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn cache(capacity: usize, ttl_ms: u64) -> LocationCache {
+    LocationCache::new(LocationCacheConfig { capacity, ttl_ms })
+}
+
+#[test]
+fn test_location_cache_evicts_least_recently_used() {
+    let mut lookups = cache(2, 60_000);
+    lookups.insert(String::from("a"), Some(PathBuf::from("a.bin")));
+    lookups.insert(String::from("b"), None);
+    // Touch "a" so that "b" becomes the eviction victim.
+    assert_eq!(lookups.get("a"), Some(Some(PathBuf::from("a.bin"))));
+    lookups.insert(String::from("c"), Some(PathBuf::from("c.bin")));
+    assert_eq!(lookups.get("b"), None);
+    assert_eq!(lookups.len(), 2);
+    // A known absence is an answer too.
+    lookups.insert(String::from("b"), None);
+    assert_eq!(lookups.get("b"), Some(None));
+    assert_eq!(lookups.hits_and_misses(), (2, 1));
+}
+
+#[test]
+fn test_location_cache_expires_and_invalidates() {
+    let mut lookups = cache(10, 20);
+    lookups.insert(String::from("t/a"), Some(PathBuf::from("a.bin")));
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(lookups.get("t/a"), None);
+    assert!(lookups.is_empty());
+
+    let mut lookups = cache(10, 60_000);
+    for key in ["t/a", "t/b", "u/a"] {
+        lookups.insert(String::from(key), None);
+    }
+    lookups.remove_prefix("t/");
+    assert_eq!(lookups.get("t/a"), None);
+    assert_eq!(lookups.get("u/a"), Some(None));
+    lookups.remove("u/a");
+    assert!(lookups.is_empty());
+}
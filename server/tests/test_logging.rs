@@ -0,0 +1,71 @@
+use istziio_server_node::logging::{
+    format_json, format_pretty, record_outcome, record_shard, LogFormat, RequestContext,
+};
+use istziio_server_node::telemetry::TraceContext;
+use log::{Level, Record};
+
+fn render(format: LogFormat) -> String {
+    let kvs = [("size", 42)];
+    let args = format_args!("fetched from S3");
+    let record = Record::builder()
+        .args(args)
+        .level(Level::Debug)
+        .target("istziio_server_node::cache")
+        .key_values(&kvs)
+        .build();
+    match format {
+        LogFormat::Pretty => format_pretty(record.args(), &record),
+        LogFormat::Json => format_json("10.0.0.1:6379", record.args(), &record),
+    }
+}
+
+#[test]
+fn test_log_format_parsing() {
+    assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+    assert_eq!("pretty".parse::<LogFormat>(), Ok(LogFormat::Pretty));
+    assert!("xml".parse::<LogFormat>().is_err());
+}
+
+#[test]
+fn test_lines_outside_a_request() {
+    assert_eq!(
+        render(LogFormat::Pretty),
+        "[istziio_server_node::cache][DEBUG] fetched from S3 size=42"
+    );
+    let line: serde_json::Value = serde_json::from_str(&render(LogFormat::Json)).unwrap();
+    assert_eq!(line["msg"], "fetched from S3");
+    assert_eq!(line["level"], "DEBUG");
+    assert_eq!(line["node"], "10.0.0.1:6379");
+    assert_eq!(line["size"], "42");
+    assert!(line.get("request_id").is_none());
+}
+
+#[tokio::test]
+async fn test_lines_carry_request_context() {
+    let context = RequestContext::new(
+        String::from("req-1"),
+        TraceContext {
+            trace_id: String::from("4bf92f3577b34da6a3ce929d0e0e4736"),
+            parent_id: None,
+        },
+        String::from("tables/orders.parquet"),
+    );
+    let (pretty, json) = context
+        .scope(async {
+            record_shard(2);
+            record_outcome("miss");
+            (render(LogFormat::Pretty), render(LogFormat::Json))
+        })
+        .await;
+    assert_eq!(
+        pretty,
+        "[istziio_server_node::cache][DEBUG] fetched from S3 request_id=req-1 \
+         trace_id=4bf92f3577b34da6a3ce929d0e0e4736 key=tables/orders.parquet shard=2 \
+         outcome=miss size=42"
+    );
+    let line: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(line["request_id"], "req-1");
+    assert_eq!(line["key"], "tables/orders.parquet");
+    assert_eq!(line["shard"], "2");
+    assert_eq!(line["outcome"], "miss");
+}
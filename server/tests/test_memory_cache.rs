@@ -0,0 +1,41 @@
+use bytes::Bytes;
+use istziio_server_node::memory_cache::MemoryCache;
+
+#[test]
+fn test_memory_cache_evicts_least_recently_used() {
+    let mut memory = MemoryCache::new(10, 4);
+    memory.insert("a", Bytes::from_static(b"aaaa"));
+    memory.insert("b", Bytes::from_static(b"bbbb"));
+    // Touch "a" so that "b" becomes the eviction victim.
+    assert_eq!(memory.get("a"), Some(Bytes::from_static(b"aaaa")));
+    memory.insert("c", Bytes::from_static(b"cccc"));
+    assert!(memory.get("b").is_none());
+    assert!(memory.get("a").is_some());
+    assert!(memory.get("c").is_some());
+    assert_eq!(memory.current_size(), 8);
+}
+
+#[test]
+fn test_memory_cache_rejects_large_objects() {
+    let mut memory = MemoryCache::new(10, 4);
+    memory.insert("big", Bytes::from_static(b"too large"));
+    assert!(memory.is_empty());
+
+    let mut disabled = MemoryCache::new(0, 4);
+    disabled.insert("a", Bytes::from_static(b"a"));
+    assert!(!disabled.is_enabled());
+    assert!(disabled.is_empty());
+}
+
+#[test]
+fn test_memory_cache_shrinks_on_resize() {
+    let mut memory = MemoryCache::new(12, 4);
+    memory.insert("a", Bytes::from_static(b"aaaa"));
+    memory.insert("b", Bytes::from_static(b"bbbb"));
+    memory.insert("c", Bytes::from_static(b"cccc"));
+    memory.get("a");
+    memory.set_max_size(8);
+    assert_eq!(memory.current_size(), 8);
+    assert!(memory.get("b").is_none());
+    assert!(memory.get("a").is_some());
+}
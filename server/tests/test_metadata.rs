@@ -0,0 +1,112 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache, GetFileOptions};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::metadata::{
+    key_slot, InProcessStore, MetadataBackend, MetadataStore, SLOT_COUNT,
+};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Answers every fetch with the key itself.
+struct EchoConnector;
+
+#[async_trait]
+impl StorageConnector for EchoConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[tokio::test]
+async fn test_in_process_store() {
+    let store = InProcessStore::new(6379);
+    assert!(store.is_initialized());
+    assert_eq!(store.location_lookup(String::from("a.parquet")).await, None);
+
+    let members = store.members();
+    assert_eq!(members.len(), 1);
+    assert!(members[0].is_self);
+    assert_eq!(members[0].slots, SLOT_COUNT as usize);
+
+    store
+        .set_file_cache_loc(String::from("a"), PathBuf::from("a"))
+        .await
+        .unwrap();
+    store
+        .set_file_cache_loc(String::from("b"), PathBuf::from("b"))
+        .await
+        .unwrap();
+    assert_eq!(
+        store.get_file(String::from("a")).await,
+        Some(PathBuf::from("a"))
+    );
+    store.remove_file(String::from("a")).await.unwrap();
+    assert_eq!(store.get_file(String::from("a")).await, None);
+    store.flush_all().await;
+    assert_eq!(store.get_file(String::from("b")).await, None);
+    assert!(store.publish("channel", "key a").is_err());
+}
+
+#[tokio::test]
+async fn test_single_node_cache_without_redis() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        1024,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let connector: Arc<dyn StorageConnector + Send + Sync> = Arc::new(EchoConnector);
+    for key in [
+        "tbl-a.parquet",
+        "tbl-b.parquet",
+        "tbl-a.parquet",
+        "c.parquet",
+    ] {
+        cache
+            .get_file(
+                PathBuf::from(key),
+                connector.clone(),
+                GetFileOptions::default(),
+            )
+            .await;
+    }
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!((stats.disk_hits, stats.misses), (1, 3));
+    assert_eq!(stats.files, 3);
+
+    assert_eq!(cache.invalidate_prefix("tbl-").await, 26);
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!(stats.files, 1);
+    assert_eq!(stats.current_size, 9);
+}
+
+#[test]
+fn test_metadata_backend_names() {
+    assert_eq!("redis".parse(), Ok(MetadataBackend::Redis));
+    assert_eq!("in-process".parse(), Ok(MetadataBackend::InProcess));
+    assert_eq!("etcd".parse(), Ok(MetadataBackend::Etcd));
+    assert!("zookeeper".parse::<MetadataBackend>().is_err());
+}
+
+#[test]
+fn test_key_slot_matches_redis() {
+    assert_eq!(key_slot("foo"), 12182);
+    assert_eq!(key_slot("{user1000}.following"), key_slot("user1000"));
+}
@@ -0,0 +1,119 @@
+use istziio_server_node::metrics::{
+    hit_ratio, EfficiencyRow, EfficiencyTotals, EfficiencyWindows, LatencyWindow, RollingCounts,
+    SlidingTotals, EFFICIENCY_WINDOWS, LATENCY_SAMPLES, RECENT_WINDOW_MINUTES,
+};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_latency_percentiles() {
+    let mut window = LatencyWindow::default();
+    assert_eq!(window.percentiles(&[50.0]), None);
+    assert_eq!(window.summary(), "-");
+    for ms in (1..=100).rev() {
+        window.record(Duration::from_millis(ms));
+    }
+    assert_eq!(
+        window.percentiles(&[50.0, 95.0, 99.0, 100.0]),
+        Some(vec![
+            Duration::from_millis(50),
+            Duration::from_millis(95),
+            Duration::from_millis(99),
+            Duration::from_millis(100),
+        ])
+    );
+    assert_eq!(window.summary(), "50.00/95.00/99.00");
+}
+
+#[test]
+fn test_latency_window_keeps_recent_samples() {
+    let mut window = LatencyWindow::default();
+    for _ in 0..LATENCY_SAMPLES {
+        window.record(Duration::from_secs(10));
+    }
+    for _ in 0..LATENCY_SAMPLES {
+        window.record(Duration::from_millis(1));
+    }
+    assert_eq!(window.len(), LATENCY_SAMPLES);
+    assert_eq!(
+        window.percentiles(&[100.0]),
+        Some(vec![Duration::from_millis(1)])
+    );
+
+    let mut other = LatencyWindow::default();
+    other.record(Duration::from_millis(7));
+    assert_eq!(
+        LatencyWindow::merged([&window, &other]).len(),
+        LATENCY_SAMPLES + 1
+    );
+}
+
+#[test]
+fn test_rolling_counts_expire() {
+    let mut counts = RollingCounts::default();
+    let start = Instant::now();
+    let minute = Duration::from_secs(60);
+    counts.record_at(true, start);
+    counts.record_at(false, start);
+    counts.record_at(true, start + minute);
+    assert_eq!(counts.totals_at(start + minute), (2, 1));
+    assert_eq!(
+        hit_ratio(counts.totals_at(start + minute)),
+        2.0 / 3.0 * 100.0
+    );
+
+    let later = start + minute * RECENT_WINDOW_MINUTES as u32;
+    assert_eq!(counts.totals_at(later), (1, 0));
+    counts.record_at(false, later + minute);
+    assert_eq!(counts.totals_at(later + minute), (0, 1));
+    assert_eq!(hit_ratio((0, 0)), 0.0);
+}
+
+#[test]
+fn test_sliding_totals_slide_by_a_slot() {
+    let mut totals = SlidingTotals::new(5, 12);
+    let start = Instant::now();
+    let slot = Duration::from_secs(5);
+    totals.record_at(start, |t| t.hits += 1);
+    totals.record_at(start + slot, |t| t.bytes_from_origin += 100);
+    assert_eq!(totals.totals_at(start + slot).hits, 1);
+    assert_eq!(totals.totals_at(start + slot).bytes_from_origin, 100);
+
+    // A minute later the first slot has left the window.
+    let later = start + slot * 12;
+    assert_eq!(totals.totals_at(later).hits, 0);
+    assert_eq!(totals.totals_at(later).bytes_from_origin, 100);
+    totals.record_at(later + slot, |t| t.misses += 1);
+    assert_eq!(
+        totals.totals_at(later + slot),
+        EfficiencyTotals {
+            misses: 1,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_efficiency_rows() {
+    let mut windows = EfficiencyWindows::default();
+    windows.record_hit(300, Duration::from_millis(2));
+    windows.record_hit(100, Duration::from_millis(4));
+    windows.record_miss();
+    windows.record_fetch(100, Duration::from_millis(30));
+    let totals = windows.totals();
+    assert_eq!(totals.len(), EFFICIENCY_WINDOWS.len());
+    assert!(totals.iter().all(|t| *t == totals[0]));
+
+    let row = EfficiencyRow::new("1m", &totals[0]);
+    assert_eq!(row.hits, 2);
+    assert_eq!(row.origin_requests_avoided, 2);
+    assert_eq!(row.bytes_from_cache, 400);
+    assert_eq!(row.bytes_from_origin, 100);
+    assert_eq!(row.byte_hit_ratio, 80.0);
+    assert_eq!(row.hit_ratio, 2.0 / 3.0 * 100.0);
+    assert!((row.avg_hit_latency_ms.unwrap() - 3.0).abs() < 1e-9);
+    assert!((row.avg_miss_latency_ms.unwrap() - 30.0).abs() < 1e-9);
+    assert_eq!(
+        EfficiencyRow::new("1h", &EfficiencyTotals::default()).avg_hit_latency_ms,
+        None
+    );
+}
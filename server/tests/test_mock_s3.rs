@@ -0,0 +1,88 @@
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::storage::mock_storage_connector::{
+    GeneratedObjects, MockS3Config, MockS3StorageConnector,
+};
+use istziio_server_node::storage::storage_connector::{read_stream_to_end, StorageConnector};
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+fn generated(config: MockS3Config) -> MockS3StorageConnector {
+    // Nothing listens here; generated objects never reach the endpoint.
+    MockS3StorageConnector::new(String::from("http://127.0.0.1:9")).with_config(MockS3Config {
+        generate: Some("1000-100000".parse().unwrap()),
+        ..config
+    })
+}
+
+#[tokio::test]
+async fn test_generated_objects() {
+    assert!("10".parse::<GeneratedObjects>().is_err());
+    let sizes: GeneratedObjects = "1000-100000".parse().unwrap();
+    let all: Vec<u64> = (0..200)
+        .map(|n| sizes.size_of(&format!("k{}", n)))
+        .collect();
+    assert!(all.iter().all(|size| (1000..=100000).contains(size)));
+    // Log-uniform: about half the objects are below the geometric mean.
+    let small = all.iter().filter(|&&size| size < 10000).count();
+    assert!((60..140).contains(&small), "{}", small);
+
+    let s3 = generated(MockS3Config::default());
+    let object = s3.fetch_stream("tables/a.parquet").await.unwrap();
+    let size = sizes.size_of("tables/a.parquet");
+    assert_eq!(object.object_size, Some(size));
+    assert_eq!(object.content_length, Some(size));
+    assert!(object.version.e_tag.is_some());
+    let body = read_stream_to_end(object.stream).await.unwrap();
+    assert_eq!(body.len() as u64, size);
+    let again = s3.fetch_stream("tables/a.parquet").await.unwrap();
+    assert_eq!(read_stream_to_end(again.stream).await.unwrap(), body);
+
+    let range = s3
+        .fetch_range("tables/a.parquet", ByteRange::FromTo(100, 199))
+        .await
+        .unwrap();
+    assert_eq!(range.content_length, Some(100));
+    assert_eq!(range.object_size, Some(size));
+    assert_eq!(
+        read_stream_to_end(range.stream).await.unwrap(),
+        &body[100..200]
+    );
+    let past_the_end = s3
+        .fetch_range("tables/a.parquet", ByteRange::From(size))
+        .await;
+    assert_eq!(past_the_end.err().unwrap().kind(), ErrorKind::InvalidInput);
+    assert_eq!(s3.head_object("tables/a.parquet").await.unwrap().size, size);
+}
+
+#[tokio::test]
+async fn test_shaping() {
+    let failing = generated(MockS3Config {
+        error_rate: 1.0,
+        ..MockS3Config::default()
+    });
+    assert!(failing.fetch_stream("a").await.is_err());
+
+    let slow = generated(MockS3Config {
+        first_byte_latency_ms: 100,
+        ..MockS3Config::default()
+    });
+    let started = Instant::now();
+    slow.fetch_stream("a").await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+
+    let narrow = generated(MockS3Config {
+        bytes_per_sec: Some(200_000),
+        ..MockS3Config::default()
+    });
+    let object = narrow.fetch_stream("b").await.unwrap();
+    let size = object.object_size.unwrap();
+    let started = Instant::now();
+    read_stream_to_end(object.stream).await.unwrap();
+    let expected = Duration::from_secs_f64(size as f64 / 200_000.0);
+    assert!(
+        started.elapsed() + Duration::from_millis(20) >= expected,
+        "{} bytes in {:?}",
+        size,
+        started.elapsed()
+    );
+}
@@ -0,0 +1,105 @@
+use rocket::http::Status;
+
+mod harness;
+use harness::Cluster;
+
+#[test]
+fn test_requests_are_redirected_to_the_owner() {
+    let cluster = Cluster::start(3);
+    for i in 0..30 {
+        let key = format!("redirect/{}.parquet", i);
+        let owner = cluster.owner(&key);
+        for from in 0..3 {
+            let served = cluster.get(from, &key);
+            assert_eq!(served.status, Status::Ok);
+            assert_eq!(served.node, owner);
+            assert_eq!(served.hops, usize::from(from != owner));
+        }
+        assert!(cluster.caches(owner, &key));
+        assert!((0..3)
+            .filter(|&node| node != owner)
+            .all(|node| !cluster.caches(node, &key)));
+    }
+}
+
+#[test]
+fn test_owner_evicts_its_oldest_objects() {
+    let cluster = Cluster::start(2);
+    let keys: Vec<String> = (0..100)
+        .map(|i| format!("evict/{}.parquet", i))
+        .filter(|key| cluster.owner(key) == 1)
+        .take(3)
+        .collect();
+    assert_eq!(keys.len(), 3);
+    // Looking up the owners cached the keys; start over with an empty node 1.
+    let response = cluster.clients[1]
+        .post("/clear")
+        .header(harness::admin_auth())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    for key in &keys {
+        assert_eq!(cluster.get(0, key).status, Status::Ok);
+    }
+    // Node 1 has room for two objects.
+    assert!(!cluster.caches(1, &keys[0]));
+    assert!(cluster.caches(1, &keys[1]));
+    assert!(cluster.caches(1, &keys[2]));
+    assert!(!cluster.caches(0, &keys[2]));
+}
+
+#[test]
+fn test_deletes_reach_the_owner() {
+    let cluster = Cluster::start(3);
+    let key = (0..100)
+        .map(|i| format!("delete/{}.parquet", i))
+        .find(|key| cluster.owner(key) == 2)
+        .unwrap();
+    assert!(cluster.caches(2, &key));
+
+    let deleted = cluster.delete(0, &key);
+    assert_eq!(deleted.status, Status::Ok);
+    assert_eq!((deleted.node, deleted.hops), (2, 1));
+    assert!(!cluster.caches(2, &key));
+    // The next read fetches it again.
+    assert_eq!(cluster.get(1, &key).node, 2);
+    assert!(cluster.caches(2, &key));
+}
+
+#[test]
+fn test_scale_out_moves_keys_to_the_new_node() {
+    let mut cluster = Cluster::start(2);
+    let keys: Vec<String> = (0..60).map(|i| format!("scale/{}.parquet", i)).collect();
+    let before: Vec<usize> = keys.iter().map(|key| cluster.owner(key)).collect();
+    assert!(before.contains(&0) && before.contains(&1));
+
+    let added = cluster.add_node();
+    assert_eq!(added, 2);
+    let after: Vec<usize> = keys.iter().map(|key| cluster.owner(key)).collect();
+    assert!(after.contains(&added));
+    assert_ne!(before, after);
+    // Every node agrees on where the keys went.
+    for (key, owner) in keys.iter().zip(&after) {
+        for from in 0..3 {
+            assert_eq!(cluster.get(from, key).node, *owner);
+        }
+    }
+}
+
+#[test]
+fn test_standalone_redis_places_keys_on_the_ring() {
+    let cluster = Cluster::start_standalone(3);
+    let keys: Vec<String> = (0..30)
+        .map(|i| format!("standalone/{}.parquet", i))
+        .collect();
+    let owners: Vec<usize> = keys.iter().map(|key| cluster.owner(key)).collect();
+    assert!((0..3).all(|node| owners.contains(&node)));
+    for (key, &owner) in keys.iter().zip(&owners) {
+        for from in 0..3 {
+            let served = cluster.get(from, key);
+            assert_eq!(served.status, Status::Ok);
+            assert_eq!(served.node, owner);
+            assert_eq!(served.hops, usize::from(from != owner));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use bytes::Bytes;
+use istziio_server_node::storage::s3_storage_connector::{
+    plan_parts, stream_parts, Part, MAX_PARTS, MULTIPART_THRESHOLD, PART_SIZE,
+};
+use istziio_server_node::storage::storage_connector::ObjectStream;
+use rocket::futures::{stream, TryStreamExt};
+use std::io;
+
+/// `len` bytes in chunks of `chunk` bytes.
+fn body(len: u64, chunk: usize) -> ObjectStream {
+    let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    let chunks: Vec<io::Result<Bytes>> = data
+        .chunks(chunk)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+        .collect();
+    Box::pin(stream::iter(chunks))
+}
+
+#[test]
+fn test_plan_parts() {
+    assert!(plan_parts(0).is_empty());
+    let size = MULTIPART_THRESHOLD + 5;
+    let parts = plan_parts(size);
+    assert_eq!(parts.len() as u64, MULTIPART_THRESHOLD / PART_SIZE + 1);
+    assert_eq!(
+        parts[0],
+        Part {
+            number: 1,
+            offset: 0,
+            len: PART_SIZE
+        }
+    );
+    assert_eq!(
+        *parts.last().unwrap(),
+        Part {
+            number: parts.len() as i32,
+            offset: MULTIPART_THRESHOLD,
+            len: 5
+        }
+    );
+}
+
+#[test]
+fn test_parts_cover_the_object() {
+    for size in [1, PART_SIZE, 3 * PART_SIZE - 1, PART_SIZE * MAX_PARTS + 1] {
+        let parts = plan_parts(size);
+        assert!(parts.len() as u64 <= MAX_PARTS);
+        let mut next = 0;
+        for (i, part) in parts.iter().enumerate() {
+            assert_eq!(part.number as usize, i + 1);
+            assert_eq!(part.offset, next);
+            next += part.len;
+        }
+        assert_eq!(next, size);
+    }
+}
+
+#[tokio::test]
+async fn test_streamed_parts() {
+    let size = MULTIPART_THRESHOLD + 5;
+    // Chunks that straddle part boundaries.
+    let parts: Vec<(Part, Vec<u8>)> = stream_parts(body(size, 1_000_003), size)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(
+        parts.iter().map(|(part, _)| *part).collect::<Vec<_>>(),
+        plan_parts(size)
+    );
+    for (part, data) in &parts {
+        assert_eq!(data.len() as u64, part.len);
+        assert_eq!(data[0], (part.offset % 251) as u8);
+    }
+
+    let short: io::Result<Vec<_>> = stream_parts(body(size - 1, 1 << 20), size)
+        .try_collect()
+        .await;
+    assert_eq!(short.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    let long: io::Result<Vec<_>> = stream_parts(body(size + 1, 1 << 20), size)
+        .try_collect()
+        .await;
+    assert_eq!(long.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,109 @@
+use istziio_server_node::config::parse_config;
+use istziio_server_node::policy::{PolicySet, PrefixPolicy, Priority};
+use std::time::Duration;
+
+#[test]
+fn test_pattern_matching() {
+    let prefix = PrefixPolicy::new("dim/");
+    assert!(prefix.matches("dim/nation.parquet"));
+    assert!(!prefix.matches("fact/dim/nation.parquet"));
+
+    let glob = PrefixPolicy::new("tmp/*");
+    assert!(glob.matches("tmp/a/b.csv"));
+    assert!(!glob.matches("tmpx/a.csv"));
+
+    let glob = PrefixPolicy::new("*/part-?.parquet");
+    assert!(glob.matches("orders/2024/part-3.parquet"));
+    assert!(!glob.matches("orders/part-10.parquet"));
+    assert!(PrefixPolicy::new("*.log").matches(".log"));
+}
+
+#[test]
+fn test_first_matching_rule_applies() {
+    let policies = PolicySet::new(vec![
+        PrefixPolicy {
+            admit: false,
+            ..PrefixPolicy::new("logs/")
+        },
+        PrefixPolicy {
+            priority: Priority::Pinned,
+            ..PrefixPolicy::new("dim/")
+        },
+        PrefixPolicy {
+            ttl_secs: Some(600),
+            priority: Priority::Low,
+            max_bytes: Some(1000),
+            ..PrefixPolicy::new("*")
+        },
+    ]);
+    assert!(!policies.admits("logs/app.log"));
+    assert!(policies.admits("dim/nation.parquet"));
+    assert_eq!(policies.priority("dim/nation.parquet"), Priority::Pinned);
+    // Chunks and footers follow the policy of their object.
+    assert_eq!(
+        policies.priority("dim/nation.parquet@chunk-2"),
+        Priority::Pinned
+    );
+    assert_eq!(policies.rule_index("dim/nation.parquet@footer"), Some(1));
+    assert_eq!(
+        policies.ttl("tmp/scratch.parquet"),
+        Some(Duration::from_secs(600))
+    );
+    assert_eq!(
+        policies.per_shard(3).for_key("tmp/x").unwrap().max_bytes,
+        Some(333)
+    );
+    assert!(Priority::Low < Priority::Normal && Priority::High < Priority::Pinned);
+}
+
+#[test]
+fn test_policies_in_config() {
+    let config = parse_config(
+        r#"
+        use_mock_s3_endpoint = "http://localhost:6333"
+
+        [[policies]]
+        pattern = "tmp/*"
+        ttl_secs = 600
+        priority = "low"
+
+        [[policies]]
+        pattern = "logs/"
+        admit = false
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.policies.len(), 2);
+    assert_eq!(config.policies[0].priority, Priority::Low);
+    assert!(config.policies[0].admit);
+    assert!(!config.policies[1].admit);
+    assert!(config.validate().is_ok());
+
+    let config = parse_config(
+        r#"
+        use_mock_s3_endpoint = "http://localhost:6333"
+        [[policies]]
+        pattern = "tmp/*"
+        ttl_secs = 0
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+    assert!(parse_config("[[policies]]\npattern = \"a\"\npriority = \"urgent\"").is_err());
+}
+
+#[test]
+fn test_refresh_ahead_needs_a_longer_ttl() {
+    let policy = |rule: &str| {
+        parse_config(&format!(
+            "use_mock_s3_endpoint = \"http://localhost:6333\"\n[[policies]]\npattern = \"hot/\"\n{}",
+            rule
+        ))
+        .unwrap()
+        .validate()
+    };
+    assert!(policy("ttl_secs = 60\nrefresh_ahead_secs = 5").is_ok());
+    assert!(policy("refresh_ahead_secs = 5").is_err());
+    assert!(policy("ttl_secs = 60\nrefresh_ahead_secs = 60").is_err());
+    assert!(policy("ttl_secs = 60\nrefresh_ahead_secs = 0").is_err());
+}
@@ -0,0 +1,65 @@
+use istziio_server_node::prefixes::{
+    object_key, prefix_of, usage_by_prefix, PrefixAccesses, PrefixUsage, MAX_PREFIX_DEPTH,
+};
+
+#[test]
+fn test_prefix_of() {
+    let key = "warehouse/orders/2024/part-0.parquet";
+    assert_eq!(prefix_of(key, 0), "");
+    assert_eq!(prefix_of(key, 1), "warehouse/");
+    assert_eq!(prefix_of(key, 2), "warehouse/orders/");
+    assert_eq!(prefix_of(key, 5), "warehouse/orders/2024/");
+    assert_eq!(prefix_of("top.parquet", 2), "");
+}
+
+#[test]
+fn test_object_key() {
+    assert_eq!(object_key("a/b.parquet@chunk-3"), "a/b.parquet");
+    assert_eq!(object_key("a/b.parquet@footer"), "a/b.parquet");
+    assert_eq!(object_key("a/user@example"), "a/user@example");
+}
+
+#[test]
+fn test_usage_by_prefix() {
+    let mut accesses = PrefixAccesses::default();
+    for key in [
+        "warehouse/orders/1.parquet",
+        "warehouse/orders/1.parquet",
+        "warehouse/nation/1.parquet",
+    ] {
+        accesses.record(key);
+    }
+    let entries = [
+        ("warehouse/orders/1.parquet@chunk-0", 100),
+        ("warehouse/orders/1.parquet@chunk-1", 100),
+        ("warehouse/nation/1.parquet", 10),
+    ];
+    let rows = usage_by_prefix(2, entries.iter().copied(), accesses.at_depth(2));
+    assert_eq!(
+        rows,
+        vec![
+            PrefixUsage {
+                prefix: String::from("warehouse/orders/"),
+                cached_bytes: 200,
+                entries: 2,
+                accesses: 2,
+            },
+            PrefixUsage {
+                prefix: String::from("warehouse/nation/"),
+                cached_bytes: 10,
+                entries: 1,
+                accesses: 1,
+            },
+        ]
+    );
+
+    let rows = usage_by_prefix(
+        MAX_PREFIX_DEPTH + 3,
+        entries.iter().copied(),
+        accesses.at_depth(MAX_PREFIX_DEPTH + 3),
+    );
+    assert_eq!(rows.len(), 2);
+    let rows = usage_by_prefix(1, entries.iter().copied(), accesses.at_depth(1));
+    assert_eq!(rows[0].accesses, 3);
+    assert_eq!(rows[0].cached_bytes, 210);
+}
@@ -0,0 +1,95 @@
+use istziio_server_node::auth::AuthConfig;
+use istziio_server_node::rate_limit::{
+    ClientQuota, RateLimitConfig, RateLimiter, MAX_TRACKED_CLIENTS,
+};
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use rocket::{get, routes};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_token_bucket_per_client() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        requests_per_sec: 2.0,
+        burst: 3.0,
+    });
+    let start = Instant::now();
+    for _ in 0..3 {
+        assert!(limiter.check_at("ip:10.0.0.1", start));
+    }
+    assert!(!limiter.check_at("ip:10.0.0.1", start));
+    // Other clients have their own bucket.
+    assert!(limiter.check_at("ip:10.0.0.2", start));
+    // Two tokens per second come back, capped at the burst size.
+    let later = start + Duration::from_millis(500);
+    assert!(limiter.check_at("ip:10.0.0.1", later));
+    assert!(!limiter.check_at("ip:10.0.0.1", later));
+    let much_later = start + Duration::from_secs(60);
+    for _ in 0..3 {
+        assert!(limiter.check_at("ip:10.0.0.1", much_later));
+    }
+    assert!(!limiter.check_at("ip:10.0.0.1", much_later));
+}
+
+#[test]
+fn test_clients_beyond_the_limit_share_a_bucket() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        requests_per_sec: 1.0,
+        burst: 2.0,
+    });
+    let start = Instant::now();
+    for i in 0..MAX_TRACKED_CLIENTS {
+        assert!(limiter.check_at(&format!("ip:{}", i), start));
+    }
+    assert_eq!(limiter.tracked_clients(), MAX_TRACKED_CLIENTS);
+    // Newcomers draw from one bucket rather than evicting anyone.
+    assert!(limiter.check_at("ip:new-1", start));
+    assert!(limiter.check_at("ip:new-2", start));
+    assert!(!limiter.check_at("ip:new-3", start));
+    assert!(!limiter.check_at("ip:new-1", start));
+    assert_eq!(limiter.tracked_clients(), MAX_TRACKED_CLIENTS);
+
+    // Once the tracked clients' buckets have refilled, a sweep drops them.
+    let later = start + Duration::from_secs(2);
+    assert!(limiter.check_at("ip:new-3", later));
+    assert_eq!(limiter.tracked_clients(), 1);
+    assert!(limiter.check_at("ip:new-3", later));
+    assert!(!limiter.check_at("ip:new-3", later));
+}
+
+#[get("/data")]
+fn data(_quota: ClientQuota) -> &'static str {
+    "ok"
+}
+
+fn status(client: &Client, token: &str) -> Status {
+    client
+        .get("/data")
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .dispatch()
+        .status()
+}
+
+#[test]
+fn test_only_known_tokens_get_their_own_bucket() {
+    let client = Client::tracked(
+        rocket::build()
+            .manage(RateLimiter::new(RateLimitConfig {
+                requests_per_sec: 0.001,
+                burst: 1.0,
+            }))
+            .manage(AuthConfig {
+                admin_token: Some("admin".into()),
+                read_tokens: vec!["reader".into()],
+            })
+            .mount("/", routes![data]),
+    )
+    .unwrap();
+    // Made-up tokens all draw from the client's address.
+    assert_eq!(status(&client, "made-up-1"), Status::Ok);
+    assert_eq!(status(&client, "made-up-2"), Status::TooManyRequests);
+    // Known tokens have a bucket each.
+    assert_eq!(status(&client, "reader"), Status::Ok);
+    assert_eq!(status(&client, "reader"), Status::TooManyRequests);
+    assert_eq!(status(&client, "admin"), Status::Ok);
+}
@@ -0,0 +1,169 @@
+use istziio_server_node::auth::AuthConfig;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache};
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::read_through::{
+    self, check_bucket_key, fetch_key, is_fetch_key, parse_source, ReadThrough,
+};
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::routes;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use url::Url;
+
+fn sources(sources: &[&str]) -> ReadThrough {
+    let sources: Vec<String> = sources.iter().map(|s| s.to_string()).collect();
+    ReadThrough::new(&sources, None, None)
+}
+
+#[test]
+fn test_allowed_sources() {
+    assert!(parse_source("https://b.s3.amazonaws.com/exports/").is_ok());
+    assert!(parse_source("ftp://b.s3.amazonaws.com/").is_err());
+    assert!(parse_source("exports/").is_err());
+
+    let read_through = sources(&["https://b.s3.amazonaws.com/exports/"]);
+    let allowed = |src: &str| read_through.is_allowed(&Url::parse(src).unwrap());
+    assert!(allowed(
+        "https://b.s3.amazonaws.com/exports/orders.csv?X-Amz-Signature=abc"
+    ));
+    assert!(allowed("https://b.s3.amazonaws.com:443/exports/orders.csv"));
+    assert!(!allowed("http://b.s3.amazonaws.com/exports/orders.csv"));
+    assert!(!allowed("https://c.s3.amazonaws.com/exports/orders.csv"));
+    assert!(!allowed("https://b.s3.amazonaws.com/private/orders.csv"));
+    assert!(!allowed(
+        "https://b.s3.amazonaws.com/exports/../private/orders.csv"
+    ));
+
+    // Without a trailing `/`, a source still ends at a segment boundary.
+    let read_through = sources(&["https://b.s3.amazonaws.com/daily"]);
+    let allowed = |src: &str| read_through.is_allowed(&Url::parse(src).unwrap());
+    assert!(allowed("https://b.s3.amazonaws.com/daily"));
+    assert!(allowed("https://b.s3.amazonaws.com/daily/orders.csv"));
+    assert!(!allowed(
+        "https://b.s3.amazonaws.com/daily-private/orders.csv"
+    ));
+    assert!(!allowed("https://b.s3.amazonaws.com/dailyorders.csv"));
+}
+
+#[test]
+fn test_fetch_keys() {
+    let key = |src: &str| fetch_key(&Url::parse(src).unwrap());
+    assert_eq!(
+        key("https://b.s3.amazonaws.com/exports/orders.csv?X-Amz-Signature=abc"),
+        "@fetch/https/b.s3.amazonaws.com:443/exports/orders.csv"
+    );
+    assert_eq!(
+        key("https://b.s3.amazonaws.com/exports/orders.csv?X-Amz-Signature=def"),
+        key("https://b.s3.amazonaws.com:443/exports/orders.csv")
+    );
+    assert_ne!(
+        key("http://b.s3.amazonaws.com/exports/orders.csv"),
+        key("https://b.s3.amazonaws.com/exports/orders.csv")
+    );
+    assert!(is_fetch_key(&key("https://b.s3.amazonaws.com/a")));
+    assert!(!is_fetch_key("exports/orders.csv"));
+    assert!(check_bucket_key(&key("https://b.s3.amazonaws.com/a")).is_err());
+    assert!(check_bucket_key("exports/orders.csv").is_ok());
+}
+
+/// Answers every request with `body`, counting them.
+async fn serve(body: &'static str) -> (Url, Arc<AtomicU32>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    let requests = Arc::new(AtomicU32::new(0));
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    (url, requests)
+}
+
+#[tokio::test]
+async fn test_fetch_caches_under_src() {
+    let (origin, requests) = serve("1,2,3").await;
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ));
+    let exports = origin.join("exports/").unwrap();
+    let client = Client::tracked(
+        rocket::build()
+            .manage(AuthConfig::default())
+            .manage(sources(&[exports.as_str()]))
+            .manage(cache.clone())
+            .mount("/", routes![read_through::fetch]),
+    )
+    .await
+    .unwrap();
+    let fetch = |src: Url| {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("src", src.as_str())
+            .finish();
+        format!("/fetch?{}", query)
+    };
+    let src = exports
+        .join("orders.csv?X-Amz-Signature=abc&X-Amz-Expires=60")
+        .unwrap();
+
+    let response = client.get(fetch(src.clone())).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"v1\""));
+    assert_eq!(response.into_string().await.unwrap(), "1,2,3");
+    // Another signature for the same object is a hit.
+    let resigned = exports.join("orders.csv?X-Amz-Signature=def").unwrap();
+    let response = client.get(fetch(resigned)).dispatch().await;
+    assert_eq!(response.into_string().await.unwrap(), "1,2,3");
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    // The object is cached under its own key, not one the bucket could have.
+    assert!(cache.is_cached(&fetch_key(&src)).await);
+    assert!(!cache.is_cached("orders.csv").await);
+
+    let outside = origin.join("private/orders.csv").unwrap();
+    let response = client.get(fetch(outside)).dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_fetch_needs_sources() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ));
+    let client = Client::tracked(
+        rocket::build()
+            .manage(AuthConfig::default())
+            .manage(sources(&[]))
+            .manage(cache)
+            .mount("/", routes![read_through::fetch]),
+    )
+    .await
+    .unwrap();
+    let response = client
+        .get("/fetch?src=https%3A%2F%2Fexample.com%2Fa")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,283 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ClusterMember, ConcurrentDiskCache, GetFileOptions, PORT_OFFSET_TO_WEB_SERVER,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::{MetadataStore, StoreError};
+use istziio_server_node::rebalance::{RebalanceConfig, Rebalancer};
+use istziio_server_node::storage::storage_connector::{
+    FetchedObject, ObjectStream, StorageConnector,
+};
+use istziio_server_node::util::FileUid;
+use rocket::futures::{stream, TryStreamExt};
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Owns every key until `owner` is set, after which that node owns them all. The owner
+/// advertises `address` as its web server, if set.
+#[derive(Default)]
+struct HandOverStore {
+    owner: Arc<Mutex<Option<(String, u16)>>>,
+    address: Arc<Mutex<Option<String>>>,
+}
+
+#[async_trait]
+impl MetadataStore for HandOverStore {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+    async fn initialize(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+    async fn location_lookup(&self, _uid: FileUid) -> Option<(String, u16)> {
+        self.owner.lock().unwrap().clone()
+    }
+    async fn get_file(&self, _uid: FileUid) -> Option<PathBuf> {
+        None
+    }
+    async fn set_file_cache_loc(&self, _uid: FileUid, _loc: PathBuf) -> Result<(), ()> {
+        Ok(())
+    }
+    async fn remove_file(&self, _uid: FileUid) -> Result<(), ()> {
+        Ok(())
+    }
+    async fn flush_all(&self) {}
+    fn members(&self) -> Vec<ClusterMember> {
+        let Some((endpoint, port)) = self.owner.lock().unwrap().clone() else {
+            return Vec::new();
+        };
+        vec![ClusterMember {
+            node_id: String::from("owner"),
+            endpoint,
+            port,
+            slots: 0,
+            weight: 1,
+            address: self.address.lock().unwrap().clone(),
+            is_self: false,
+        }]
+    }
+}
+
+struct EchoConnector;
+
+#[async_trait]
+impl StorageConnector for EchoConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.repeat(100));
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn cache_in(dir: &std::path::Path, store: HandOverStore) -> ConcurrentDiskCache {
+    ConcurrentDiskCache::new(
+        dir.to_path_buf(),
+        1024 * 1024,
+        2,
+        Box::new(store),
+        CacheOptions::default(),
+    )
+}
+
+async fn fill(cache: &ConcurrentDiskCache, keys: &[&str]) {
+    let connector: Arc<dyn StorageConnector + Send + Sync> = Arc::new(EchoConnector);
+    for key in keys {
+        cache
+            .get_file(
+                PathBuf::from(key),
+                connector.clone(),
+                GetFileOptions::default(),
+            )
+            .await;
+    }
+}
+
+fn body(data: &'static [u8]) -> ObjectStream {
+    Box::pin(stream::iter(vec![Ok(Bytes::from_static(data))]))
+}
+
+async fn read_all(body: ObjectStream) -> Vec<u8> {
+    let chunks: Vec<Bytes> = body.try_collect().await.unwrap();
+    chunks.concat()
+}
+
+/// A peer answering every request with `status`; yields each raw request it got.
+async fn fake_peer(status: &'static str) -> (u16, tokio::sync::mpsc::UnboundedReceiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (requests, received) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // Bodies are streamed, so they arrive chunked.
+            while !request.ends_with(b"0\r\n\r\n") {
+                match socket.read(&mut buf).await.unwrap() {
+                    0 => break,
+                    n => request.extend_from_slice(&buf[..n]),
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                status
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            let _ = requests.send(String::from_utf8_lossy(&request).into_owned());
+        }
+    });
+    (port, received)
+}
+
+#[tokio::test]
+async fn test_ingest_and_forget() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = cache_in(dir.path(), HandOverStore::default());
+    assert!(cache.open_object("a.parquet").await.is_none());
+
+    // Only the cached copy is left behind.
+    let files = || std::fs::read_dir(dir.path()).unwrap().count();
+    let staged = cache.stage("a.parquet", body(b"moved here")).await.unwrap();
+    assert_eq!(staged.size(), 10);
+    assert!(cache.ingest("a.parquet", staged).await.unwrap());
+    assert_eq!(files(), 1);
+    let cached = cache.open_object("a.parquet").await.unwrap();
+    assert_eq!(read_all(cached).await, b"moved here");
+
+    // A second copy is dropped rather than replacing the first.
+    let staged = cache.stage("a.parquet", body(b"stale")).await.unwrap();
+    assert!(!cache.ingest("a.parquet", staged).await.unwrap());
+    assert_eq!(files(), 1);
+
+    assert_eq!(cache.forget("a.parquet").await, 10);
+    assert!(cache.open_object("a.parquet").await.is_none());
+    assert_eq!(cache.forget("a.parquet").await, 0);
+}
+
+#[tokio::test]
+async fn test_pass_moves_objects_to_their_owner() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = HandOverStore::default();
+    let owner = store.owner.clone();
+    let cache = cache_in(dir.path(), store);
+    let keys = ["a.parquet", "b.parquet", "c.parquet"];
+    fill(&cache, &keys).await;
+    assert!(cache.misplaced_objects().await.is_empty());
+
+    let (port, mut received) = fake_peer("200 OK").await;
+    *owner.lock().unwrap() = Some((String::from("127.0.0.1"), port - PORT_OFFSET_TO_WEB_SERVER));
+    assert_eq!(cache.misplaced_objects().await.len(), keys.len());
+
+    let config = RebalanceConfig {
+        max_bytes_per_sec: 1024 * 1024,
+    };
+    let rebalancer = Rebalancer::new(config, Some(String::from("secret")));
+    assert_eq!(rebalancer.pass(&cache).await, 0);
+    let status = rebalancer.status();
+    assert!(!status.running);
+    assert_eq!((status.found, status.moved, status.failed), (3, 3, 0));
+    assert_eq!(status.bytes_moved, 3 * 900);
+    assert!(status.finished_at.is_some());
+    for key in keys {
+        assert!(cache.open_object(key).await.is_none());
+    }
+    let mut paths = Vec::new();
+    for _ in keys {
+        let request = received.recv().await.unwrap();
+        assert!(request
+            .to_lowercase()
+            .contains("authorization: bearer secret"));
+        paths.push(request.split(' ').nth(1).unwrap().to_string());
+        assert!(request.contains(&paths.last().unwrap()["/admin/migrate/".len()..].repeat(2)));
+    }
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            "/admin/migrate/a.parquet",
+            "/admin/migrate/b.parquet",
+            "/admin/migrate/c.parquet"
+        ]
+    );
+
+    // Nothing is left to move.
+    assert_eq!(rebalancer.pass(&cache).await, 0);
+    let status = rebalancer.status();
+    assert_eq!((status.passes, status.found, status.moved), (2, 0, 0));
+    assert_eq!(status.total_moved, 3);
+}
+
+#[tokio::test]
+async fn test_failed_transfers_stay_for_the_next_pass() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = HandOverStore::default();
+    let owner = store.owner.clone();
+    let cache = cache_in(dir.path(), store);
+    fill(&cache, &["a.parquet"]).await;
+
+    let (port, _received) = fake_peer("503 Service Unavailable").await;
+    *owner.lock().unwrap() = Some((String::from("127.0.0.1"), port - PORT_OFFSET_TO_WEB_SERVER));
+    let rebalancer = Rebalancer::new(RebalanceConfig::default(), Some(String::from("secret")));
+    assert_eq!(rebalancer.pass(&cache).await, 1);
+    let status = rebalancer.status();
+    assert_eq!((status.moved, status.failed), (0, 1));
+    assert!(status.last_error.unwrap().starts_with("503"));
+    assert!(cache.open_object("a.parquet").await.is_some());
+
+    // An owner that won't take the object gets it dropped here too.
+    let (port, _received) = fake_peer("413 Payload Too Large").await;
+    *owner.lock().unwrap() = Some((String::from("127.0.0.1"), port - PORT_OFFSET_TO_WEB_SERVER));
+    assert_eq!(rebalancer.pass(&cache).await, 0);
+    assert!(cache.open_object("a.parquet").await.is_none());
+}
+
+#[tokio::test]
+async fn test_transfers_go_to_the_advertised_address() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = HandOverStore::default();
+    let (owner, address) = (store.owner.clone(), store.address.clone());
+    let cache = cache_in(dir.path(), store);
+    fill(&cache, &["a.parquet"]).await;
+
+    // Nothing listens at the owner's Redis port plus the offset.
+    let (port, mut received) = fake_peer("200 OK").await;
+    *owner.lock().unwrap() = Some((String::from("10.255.255.1"), 7000));
+    *address.lock().unwrap() = Some(format!("127.0.0.1:{}", port));
+    cache.refresh_mapping().await.unwrap();
+
+    let rebalancer = Rebalancer::new(RebalanceConfig::default(), Some(String::from("secret")));
+    assert_eq!(rebalancer.pass(&cache).await, 0);
+    assert_eq!(rebalancer.status().moved, 1);
+    let request = received.recv().await.unwrap();
+    assert!(request.starts_with("PUT /admin/migrate/a.parquet"));
+}
+
+#[tokio::test]
+async fn test_transfers_go_to_owners_known_by_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = HandOverStore::default();
+    let owner = store.owner.clone();
+    let cache = cache_in(dir.path(), store);
+    fill(&cache, &["a.parquet"]).await;
+
+    // The store registered the owner by name rather than by IP.
+    let (port, mut received) = fake_peer("200 OK").await;
+    *owner.lock().unwrap() = Some((String::from("localhost"), port - PORT_OFFSET_TO_WEB_SERVER));
+    let rebalancer = Rebalancer::new(RebalanceConfig::default(), Some(String::from("secret")));
+    assert_eq!(rebalancer.pass(&cache).await, 0);
+    assert_eq!(rebalancer.status().moved, 1);
+    let request = received.recv().await.unwrap();
+    assert!(request.starts_with("PUT /admin/migrate/a.parquet"));
+}
@@ -0,0 +1,242 @@
+use istziio_server_node::location_cache::LocationCacheConfig;
+use istziio_server_node::metadata::MetadataStore;
+use istziio_server_node::mock_redis::MockRedis;
+use istziio_server_node::redis::{files_key, moved_slots, NodeInfo, RedisServer};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn node(id: &str) -> NodeInfo {
+    NodeInfo {
+        node_id: String::from(id),
+        endpoint: String::from("10.0.0.1"),
+        port: 6379,
+    }
+}
+
+#[test]
+fn test_moved_slots() {
+    let before: HashMap<i16, NodeInfo> = (0..4)
+        .map(|slot| (slot, node(if slot < 2 { "a" } else { "b" })))
+        .collect();
+    assert_eq!(moved_slots(&before, &before), 0);
+
+    // Slot 1 moves to b, slot 3 is no longer served, slot 4 is new.
+    let mut after = before.clone();
+    after.insert(1, node("b"));
+    after.remove(&3);
+    after.insert(4, node("c"));
+    assert_eq!(moved_slots(&before, &after), 3);
+}
+
+#[tokio::test]
+async fn test_serves_locally_without_redis() {
+    // Bound and dropped, so nothing listens there.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let store = RedisServer::new(vec![format!("redis://127.0.0.1:{}", port)], port, 1).unwrap();
+    assert!(!store.is_degraded());
+
+    let loc = PathBuf::from("a.parquet");
+    store
+        .set_file_cache_loc(String::from("a.parquet"), loc.clone())
+        .await
+        .unwrap();
+    assert_eq!(store.get_file(String::from("a.parquet")).await, Some(loc));
+    store.remove_file(String::from("a.parquet")).await.unwrap();
+    assert!(store.is_degraded());
+    assert_eq!(store.get_file(String::from("a.parquet")).await, None);
+    assert_eq!(store.location_lookup(String::from("b.parquet")).await, None);
+}
+
+#[tokio::test]
+async fn test_batches_take_one_round_trip() {
+    let redis = MockRedis::start(Duration::ZERO).unwrap();
+    for store in [
+        RedisServer::new(vec![redis.url()], redis.port(), 1).unwrap(),
+        RedisServer::new(vec![redis.url()], redis.port(), 1)
+            .unwrap()
+            .with_namespace(String::from("test"), 3)
+            .with_location_lease(60),
+    ] {
+        // Opens the connection the calls below reuse.
+        assert_eq!(store.get_file(String::from("warm-up")).await, None);
+
+        let files: Vec<(String, PathBuf)> = (0..100)
+            .map(|i| {
+                (
+                    format!("t/{}.parquet", i),
+                    PathBuf::from(format!("{}.bin", i)),
+                )
+            })
+            .collect();
+        let before = redis.round_trips();
+        store.set_file_cache_locs(files.clone()).await.unwrap();
+        assert_eq!(redis.round_trips() - before, 1);
+
+        let mut uids: Vec<String> = files.iter().map(|(uid, _)| uid.clone()).collect();
+        uids.push(String::from("missing"));
+        let before = redis.round_trips();
+        let locations = store.get_files(uids).await;
+        assert_eq!(redis.round_trips() - before, 1);
+        assert_eq!(locations.len(), 101);
+        assert_eq!(locations[7], Some(PathBuf::from("7.bin")));
+        assert_eq!(locations[100], None);
+
+        let before = redis.round_trips();
+        store
+            .remove_file(String::from("t/7.parquet"))
+            .await
+            .unwrap();
+        assert_eq!(store.get_file(String::from("t/7.parquet")).await, None);
+        assert_eq!(redis.round_trips() - before, 2);
+        assert!(!store.is_degraded());
+    }
+}
+
+#[tokio::test]
+async fn test_repeated_lookups_skip_redis() {
+    let redis = MockRedis::start(Duration::ZERO).unwrap();
+    let store = RedisServer::new(vec![redis.url()], redis.port(), 1)
+        .unwrap()
+        .with_location_cache(LocationCacheConfig::default());
+    let uid = String::from("a.parquet");
+    assert_eq!(store.get_file(uid.clone()).await, None);
+
+    let before = redis.round_trips();
+    assert_eq!(store.get_file(uid.clone()).await, None);
+    // Writes through this node update the answer.
+    store
+        .set_file_cache_loc(uid.clone(), PathBuf::from("a.bin"))
+        .await
+        .unwrap();
+    let after_write = redis.round_trips();
+    assert_eq!(after_write - before, 1);
+    assert_eq!(
+        store.get_files(vec![uid.clone()]).await,
+        vec![Some(PathBuf::from("a.bin"))]
+    );
+    assert_eq!(
+        store.get_file(uid.clone()).await,
+        Some(PathBuf::from("a.bin"))
+    );
+    assert_eq!(redis.round_trips(), after_write);
+
+    // As an invalidation from the bus would.
+    store.forget_cached_locations("a.");
+    assert_eq!(store.get_file(uid).await, Some(PathBuf::from("a.bin")));
+    assert_eq!(redis.round_trips() - after_write, 1);
+    assert_eq!(store.location_cache_hits_and_misses(), Some((3, 2)));
+}
+
+#[tokio::test]
+async fn test_standalone_nodes_register_in_plain_keys() {
+    let redis = MockRedis::start_standalone(Duration::ZERO).unwrap();
+    let store = |port| {
+        RedisServer::standalone(&redis.url(), "10.0.0.1", port, 1, Duration::from_secs(60)).unwrap()
+    };
+    let (mut a, mut b) = (store(7000), store(7001));
+    // Neither asks for slots, which a standalone Redis has none of.
+    a.initialize().await.unwrap();
+    b.initialize().await.unwrap();
+    assert_eq!(a.members().len(), 1);
+
+    // A node that registered long ago is dropped.
+    let mut conn = redis::Client::open(redis.url())
+        .unwrap()
+        .get_connection()
+        .unwrap();
+    redis::cmd("HSET")
+        .arg("istziio:members")
+        .arg("10.0.0.2:7000")
+        .arg(0)
+        .query::<()>(&mut conn)
+        .unwrap();
+    assert_eq!(a.refresh().await.unwrap(), 0);
+    let members = a.members();
+    let ids: Vec<&str> = members.iter().map(|m| m.node_id.as_str()).collect();
+    assert_eq!(ids, ["10.0.0.1:7000", "10.0.0.1:7001"]);
+    assert!(members[0].is_self && !members[1].is_self);
+    assert_eq!(
+        (members[1].endpoint.as_str(), members[1].port),
+        ("10.0.0.1", 7001)
+    );
+    assert!(members.iter().all(|m| m.slots == 0));
+    let registered: HashMap<String, u64> = redis::cmd("HGETALL")
+        .arg("istziio:members")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(registered.len(), 2);
+
+    // Locations are kept as with a cluster.
+    let loc = PathBuf::from("a.bin");
+    a.set_file_cache_loc(String::from("a.parquet"), loc.clone())
+        .await
+        .unwrap();
+    assert_eq!(b.get_file(String::from("a.parquet")).await, Some(loc));
+    assert!(!a.is_degraded());
+}
+
+#[tokio::test]
+async fn test_node_id_outlives_the_redis_id() {
+    let redis = MockRedis::start(Duration::ZERO).unwrap();
+    let node_id = "6f1c2a8e-0b7d-4c3e-9a51-2d8e4f7b1c90";
+    let mut conn = redis::Client::open(redis.url())
+        .unwrap()
+        .get_connection()
+        .unwrap();
+    // What the node registered before its Redis came back under a new id.
+    let hset = |key: &str, field: &str, value: &str| {
+        redis::cmd("HSET").arg(key).arg(field).arg(value).clone()
+    };
+    for cmd in [
+        hset("test:identities", node_id, "previous-id"),
+        hset("test:weights", "previous-id", "1"),
+        hset("test:nodes", "previous-id", "2"),
+        hset(&files_key("test", "previous-id", 1), "a.parquet", "a.bin"),
+    ] {
+        cmd.query::<()>(&mut conn).unwrap();
+    }
+
+    let mut store = RedisServer::new(vec![redis.url()], redis.port(), 1)
+        .unwrap()
+        .with_namespace(String::from("test"), 2)
+        .with_node_id(String::from(node_id));
+    store.initialize().await.unwrap();
+    let hgetall = |conn: &mut redis::Connection, key: &str| -> HashMap<String, String> {
+        redis::cmd("HGETALL").arg(key).query(conn).unwrap()
+    };
+    assert_eq!(
+        hgetall(&mut conn, "test:identities"),
+        HashMap::from([(node_id.to_string(), redis.id().to_string())])
+    );
+    assert!(!hgetall(&mut conn, "test:weights").contains_key("previous-id"));
+    assert_eq!(
+        hgetall(&mut conn, "test:nodes"),
+        HashMap::from([(node_id.to_string(), String::from("2"))])
+    );
+    assert!(hgetall(&mut conn, &files_key("test", "previous-id", 1)).is_empty());
+
+    // Locations are registered under the node id, and found through it.
+    let loc = PathBuf::from("b.bin");
+    store
+        .set_file_cache_loc(String::from("b.parquet"), loc.clone())
+        .await
+        .unwrap();
+    let registered: usize = (0..2)
+        .map(|shard| hgetall(&mut conn, &files_key("test", node_id, shard)).len())
+        .sum();
+    assert_eq!(registered, 1);
+    assert_eq!(store.get_file(String::from("b.parquet")).await, Some(loc));
+
+    // It is a member under its Redis id, so its locations are not collected.
+    assert_eq!(store.refresh().await.unwrap(), 0);
+    let mut restarted = RedisServer::new(vec![redis.url()], redis.port(), 1)
+        .unwrap()
+        .with_namespace(String::from("test"), 2);
+    restarted.initialize().await.unwrap();
+    assert!(hgetall(&mut conn, "test:nodes").contains_key(node_id));
+}
@@ -0,0 +1,374 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::config::parse_config;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::policy::{PolicySet, PrefixPolicy};
+use istziio_server_node::storage::storage_connector::{
+    FetchedObject, ObjectInfo, ObjectVersion, StorageConnector,
+};
+use rocket::futures::stream;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Serves every key as its current generation, `v1`, `v2`, ..., and counts the requests.
+#[derive(Default)]
+struct RewrittenConnector {
+    generation: AtomicU64,
+    fetches: AtomicU64,
+    heads: AtomicU64,
+}
+
+impl RewrittenConnector {
+    fn version(&self) -> ObjectVersion {
+        ObjectVersion {
+            e_tag: Some(format!("\"v{}\"", self.generation.load(Ordering::SeqCst))),
+            last_modified: None,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageConnector for RewrittenConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        let body = Bytes::from(format!("v{}", self.generation.load(Ordering::SeqCst)));
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: self.version(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+
+    async fn head_object(&self, file_name: &str) -> IoResult<ObjectInfo> {
+        self.heads.fetch_add(1, Ordering::SeqCst);
+        Ok(ObjectInfo {
+            key: file_name.to_string(),
+            size: 2,
+            last_modified: None,
+            e_tag: self.version().e_tag,
+        })
+    }
+}
+
+#[test]
+fn test_superseded_versions() {
+    let version = |e_tag: Option<&str>, last_modified: Option<i64>| ObjectVersion {
+        e_tag: e_tag.map(String::from),
+        last_modified,
+    };
+    let held = version(Some("\"a\""), Some(100));
+    assert!(!held.superseded_by(&version(Some("\"a\""), Some(200))));
+    assert!(held.superseded_by(&version(Some("\"b\""), Some(100))));
+    // Without both ETags the modification times decide.
+    assert!(held.superseded_by(&version(None, Some(200))));
+    assert!(!held.superseded_by(&version(None, Some(100))));
+    assert!(!held.superseded_by(&version(None, None)));
+}
+
+#[test]
+fn test_revalidation_policy() {
+    let policies = PolicySet::new(vec![PrefixPolicy {
+        revalidate_after_secs: Some(30),
+        ..PrefixPolicy::new("tables/")
+    }]);
+    assert_eq!(
+        policies.revalidate_after("tables/orders.parquet@chunk-1"),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(policies.revalidate_after("logs/app.log"), None);
+
+    let config = parse_config(
+        r#"
+        use_mock_s3_endpoint = "http://localhost:6333"
+        [[policies]]
+        pattern = "tables/"
+        revalidate_after_secs = 0
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+
+    let config = parse_config(
+        r#"
+        use_mock_s3_endpoint = "http://localhost:6333"
+        [[policies]]
+        pattern = "tables/"
+        stale_while_revalidate_secs = 0
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_changed_objects_are_fetched_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            policies: PolicySet::new(vec![PrefixPolicy {
+                revalidate_after_secs: Some(1),
+                ..PrefixPolicy::new("orders")
+            }]),
+            ..Default::default()
+        },
+    );
+    let connector = Arc::new(RewrittenConnector::default());
+    let get = |key: &str| {
+        cache.get_file(
+            PathBuf::from(key),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    let cached = || std::fs::read_to_string(dir.path().join("orders.parquet")).unwrap();
+    let counts = || {
+        (
+            connector.fetches.load(Ordering::SeqCst),
+            connector.heads.load(Ordering::SeqCst),
+        )
+    };
+
+    assert!(matches!(get("orders.parquet").await, GetFileResult::Hit(_)));
+    assert!(matches!(
+        get("lineitem.parquet").await,
+        GetFileResult::Hit(_)
+    ));
+    assert!(matches!(get("orders.parquet").await, GetFileResult::Hit(_)));
+    assert_eq!(counts(), (2, 0));
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    // Unchanged: one HEAD, and the cached copy is served.
+    assert!(matches!(get("orders.parquet").await, GetFileResult::Hit(_)));
+    assert_eq!(counts(), (2, 1));
+
+    connector.generation.fetch_add(1, Ordering::SeqCst);
+    // Validated just now, so the rewrite goes unnoticed for a while.
+    get("orders.parquet").await;
+    assert_eq!((counts(), cached()), ((2, 1), String::from("v0")));
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(matches!(get("orders.parquet").await, GetFileResult::Hit(_)));
+    assert_eq!((counts(), cached()), ((3, 2), String::from("v1")));
+    // Keys without the policy are never checked.
+    get("lineitem.parquet").await;
+    assert_eq!(counts(), (3, 2));
+
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!((stats.revalidations, stats.stale_revalidations), (2, 1));
+}
+
+#[tokio::test]
+async fn test_soft_invalidated_objects_are_revalidated_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let connector = Arc::new(RewrittenConnector::default());
+    let get = || {
+        cache.get_file(
+            PathBuf::from("orders.parquet"),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    let cached = || std::fs::read_to_string(dir.path().join("orders.parquet")).unwrap();
+    let counts = || {
+        (
+            connector.fetches.load(Ordering::SeqCst),
+            connector.heads.load(Ordering::SeqCst),
+        )
+    };
+
+    assert!(!cache.mark_stale("orders.parquet").await);
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    assert_eq!(counts(), (1, 0));
+
+    // Unchanged in S3: served from the cache after one check.
+    assert!(cache.mark_stale("orders.parquet").await);
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    assert_eq!(counts(), (1, 1));
+    assert_eq!(cached(), "v0");
+
+    // Rewritten: fetched again.
+    connector.generation.fetch_add(1, Ordering::SeqCst);
+    assert!(cache.mark_stale("orders.parquet").await);
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    assert_eq!(counts(), (2, 2));
+    assert_eq!(cached(), "v1");
+}
+
+/// Waits up to five seconds for `done`, which a background refresh makes true.
+async fn eventually(done: impl Fn() -> bool) {
+    for _ in 0..500 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the background refresh did not finish");
+}
+
+#[tokio::test]
+async fn test_stale_copies_are_served_while_refreshing() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            policies: PolicySet::new(vec![
+                PrefixPolicy {
+                    stale_while_revalidate_secs: Some(60),
+                    ..PrefixPolicy::new("dashboards-")
+                },
+                PrefixPolicy {
+                    ttl_secs: Some(1),
+                    stale_while_revalidate_secs: Some(60),
+                    ..PrefixPolicy::new("events-")
+                },
+            ]),
+            ..Default::default()
+        },
+    );
+    let connector = Arc::new(RewrittenConnector::default());
+    let get = |key: &str| {
+        cache.get_file(
+            PathBuf::from(key),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    let cached = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap_or_default();
+    let fetches = || connector.fetches.load(Ordering::SeqCst);
+
+    // A soft-invalidated object that changed is answered from the cache at once.
+    assert!(matches!(get("dashboards-a").await, GetFileResult::Hit(_)));
+    connector.generation.fetch_add(1, Ordering::SeqCst);
+    assert!(cache.mark_stale("dashboards-a").await);
+    assert!(matches!(get("dashboards-a").await, GetFileResult::Hit(_)));
+    eventually(|| fetches() == 2).await;
+    eventually(|| cached("dashboards-a") == "v1").await;
+    assert_eq!(connector.heads.load(Ordering::SeqCst), 1);
+
+    // So is an object past its TTL, within the window.
+    assert!(matches!(get("events-b").await, GetFileResult::Hit(_)));
+    assert_eq!(fetches(), 3);
+    connector.generation.fetch_add(1, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(matches!(get("events-b").await, GetFileResult::Hit(_)));
+    eventually(|| fetches() == 4).await;
+    eventually(|| cached("events-b") == "v2").await;
+    // Refetched, the object is fresh again.
+    get("events-b").await;
+    assert_eq!(fetches(), 4);
+
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!(stats.stale_hits, 2);
+}
+
+#[tokio::test]
+async fn test_hot_entries_are_refreshed_before_they_expire() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            policies: PolicySet::new(vec![PrefixPolicy {
+                ttl_secs: Some(2),
+                refresh_ahead_secs: Some(1),
+                ..PrefixPolicy::new("hot")
+            }]),
+            ..Default::default()
+        },
+    );
+    let connector = Arc::new(RewrittenConnector::default());
+    let get = || {
+        cache.get_file(
+            PathBuf::from("hot.parquet"),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    let cached = || std::fs::read_to_string(dir.path().join("hot.parquet")).unwrap_or_default();
+    let fetches = || connector.fetches.load(Ordering::SeqCst);
+
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    // Not yet within a second of expiring.
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    assert_eq!(fetches(), 1);
+
+    connector.generation.fetch_add(1, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    eventually(|| fetches() == 2 && cached() == "v1").await;
+
+    // Past the first copy's TTL, the refreshed copy is still a hit.
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert!(matches!(get().await, GetFileResult::Hit(_)));
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!(stats.misses, 1);
+    assert!(stats.refreshes_ahead >= 1);
+    assert_eq!(cache.debug_validate().await, Ok(()));
+}
+
+#[tokio::test]
+async fn test_ttls_set_at_runtime_expire_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let connector = Arc::new(RewrittenConnector::default());
+    let get = |key: &str| {
+        cache.get_file(
+            PathBuf::from(key),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    let fetches = || connector.fetches.load(Ordering::SeqCst);
+
+    cache
+        .set_policies(PolicySet::new(vec![PrefixPolicy {
+            ttl_secs: Some(1),
+            ..PrefixPolicy::new("events-")
+        }]))
+        .await;
+    assert!(matches!(get("events-a").await, GetFileResult::Hit(_)));
+    get("events-a").await;
+    assert_eq!(fetches(), 1);
+    // Hits check the new TTL, even those that would skip the shard lock.
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(matches!(get("events-a").await, GetFileResult::Hit(_)));
+    assert_eq!(fetches(), 2);
+    assert_eq!(cache.debug_validate().await, Ok(()));
+}
@@ -0,0 +1,274 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ClusterMember, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::{MetadataStore, StoreError};
+use istziio_server_node::ring::{moved_share, HashRing, RingError, RingNode, RingOp};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use istziio_server_node::util::FileUid;
+use rocket::futures::stream;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+fn node(id: &str, vnodes: u32) -> RingNode {
+    RingNode {
+        node_id: id.to_string(),
+        endpoint: String::from("127.0.0.1"),
+        port: 6379,
+        vnodes,
+        weight: 1,
+    }
+}
+
+fn member(id: &str, endpoint: &str, port: u16, is_self: bool) -> ClusterMember {
+    ClusterMember {
+        node_id: id.to_string(),
+        endpoint: endpoint.to_string(),
+        port,
+        slots: 0,
+        weight: 1,
+        address: None,
+        is_self,
+    }
+}
+
+fn share_of(ring: &HashRing, id: &str) -> f64 {
+    ring.shares()
+        .into_iter()
+        .find(|share| share.node.node_id == id)
+        .map_or(0.0, |share| share.share)
+}
+
+#[test]
+fn test_owner_is_stable() {
+    let ring = HashRing::new(vec![node("a", 64), node("b", 64), node("c", 64)]);
+    let reordered = HashRing::new(vec![node("c", 64), node("a", 64), node("b", 64)]);
+    assert_eq!(ring, reordered);
+    for key in ["x.parquet", "y.parquet", "tbl/z.parquet"] {
+        assert_eq!(ring.owner(key), reordered.owner(key));
+    }
+    assert_eq!(HashRing::decode(&ring.encode()).unwrap(), ring);
+    assert_eq!(HashRing::default().owner("x.parquet"), None);
+
+    let total: f64 = ring.shares().iter().map(|share| share.share).sum();
+    assert!((total - 1.0).abs() < 1e-9);
+    assert_eq!(moved_share(&ring, &reordered), 0.0);
+}
+
+#[test]
+fn test_vnodes_weight_shares() {
+    let ring = HashRing::new(vec![node("a", 256), node("b", 256), node("c", 512)]);
+    let (a, c) = (share_of(&ring, "a"), share_of(&ring, "c"));
+    assert!((0.15..0.35).contains(&a), "a owns {}", a);
+    assert!((0.4..0.6).contains(&c), "c owns {}", c);
+}
+
+#[test]
+fn test_rebalancing_moves_only_what_changes_hands() {
+    let members = vec![
+        member("a", "127.0.0.1", 6379, true),
+        member("b", "127.0.0.1", 6380, false),
+        member("c", "127.0.0.1", 6381, false),
+    ];
+    let mut ring = HashRing::default();
+    ring.apply(RingOp::Sync, &members[..2], 128).unwrap();
+    let before = ring.clone();
+    ring.apply(RingOp::Sync, &members, 128).unwrap();
+    // The newcomer takes its share and nothing else moves.
+    let moved = moved_share(&before, &ring);
+    assert!((moved - share_of(&ring, "c")).abs() < 1e-9);
+    assert!((0.2..0.45).contains(&moved), "moved {}", moved);
+
+    let before = ring.clone();
+    let drained = share_of(&ring, "b");
+    ring.apply(
+        RingOp::SetVnodes {
+            node_id: String::from("b"),
+            vnodes: 0,
+        },
+        &members,
+        128,
+    )
+    .unwrap();
+    assert_eq!(ring.nodes().len(), 2);
+    assert!((moved_share(&before, &ring) - drained).abs() < 1e-9);
+
+    assert!(matches!(
+        ring.apply(
+            RingOp::SetVnodes {
+                node_id: String::from("z"),
+                vnodes: 1,
+            },
+            &members,
+            128,
+        ),
+        Err(RingError::UnknownNode(_))
+    ));
+}
+
+#[test]
+fn test_sync_sizes_nodes_by_weight() {
+    let mut members = vec![
+        member("a", "127.0.0.1", 6379, true),
+        member("b", "127.0.0.1", 6380, false),
+    ];
+    members[1].weight = 2;
+    let mut ring = HashRing::default();
+    ring.apply(RingOp::Sync, &members, 100).unwrap();
+    assert_eq!(ring.nodes()[0].vnodes, 100);
+    assert_eq!(ring.nodes()[1].vnodes, 200);
+    let b = share_of(&ring, "b");
+    assert!((0.55..0.78).contains(&b), "b owns {}", b);
+
+    // A manual adjustment survives syncs until the node's weight changes.
+    let adjust = RingOp::SetVnodes {
+        node_id: String::from("a"),
+        vnodes: 150,
+    };
+    ring.apply(adjust, &members, 100).unwrap();
+    ring.apply(RingOp::Sync, &members, 100).unwrap();
+    assert_eq!(ring.nodes()[0].vnodes, 150);
+    members[0].weight = 4;
+    ring.apply(RingOp::Sync, &members, 100).unwrap();
+    assert_eq!(ring.nodes()[0].vnodes, 400);
+
+    // Rings saved before weights were recorded read as weight 1.
+    let legacy = r#"[{"node_id":"a","endpoint":"127.0.0.1","port":6379,"vnodes":8}]"#;
+    assert_eq!(HashRing::decode(legacy).unwrap().nodes()[0].weight, 1);
+}
+
+/// Two nodes that never redirect by slot, so redirects can only come from the ring.
+struct TwoNodeStore {
+    ring: Mutex<Option<String>>,
+}
+
+#[async_trait]
+impl MetadataStore for TwoNodeStore {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+    async fn initialize(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+    async fn location_lookup(&self, _uid: FileUid) -> Option<(String, u16)> {
+        None
+    }
+    async fn get_file(&self, _uid: FileUid) -> Option<PathBuf> {
+        None
+    }
+    async fn set_file_cache_loc(&self, _uid: FileUid, _loc: PathBuf) -> Result<(), ()> {
+        Ok(())
+    }
+    async fn remove_file(&self, _uid: FileUid) -> Result<(), ()> {
+        Ok(())
+    }
+    async fn flush_all(&self) {}
+    fn members(&self) -> Vec<ClusterMember> {
+        vec![
+            member("a", "127.0.0.1", 6379, true),
+            member("b", "127.0.0.2", 6380, false),
+        ]
+    }
+    async fn load_ring(&self) -> Result<Option<String>, StoreError> {
+        Ok(self.ring.lock().unwrap().clone())
+    }
+    async fn save_ring(&self, ring: &str) -> Result<(), StoreError> {
+        *self.ring.lock().unwrap() = Some(ring.to_string());
+        Ok(())
+    }
+}
+
+struct EchoConnector;
+
+#[async_trait]
+impl StorageConnector for EchoConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[tokio::test]
+async fn test_cache_places_keys_on_the_ring() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        1024 * 1024,
+        2,
+        Box::new(TwoNodeStore {
+            ring: Mutex::new(None),
+        }),
+        CacheOptions {
+            ring_vnodes: Some(64),
+            ..Default::default()
+        },
+    );
+    let connector: Arc<dyn StorageConnector + Send + Sync> = Arc::new(EchoConnector);
+    let keys: Vec<String> = (0..40).map(|i| format!("key-{}.parquet", i)).collect();
+    let mut redirected = 0;
+    for key in &keys {
+        let result = cache
+            .get_file(
+                PathBuf::from(key),
+                connector.clone(),
+                GetFileOptions::default(),
+            )
+            .await;
+        if matches!(result, GetFileResult::Redirect(_)) {
+            redirected += 1;
+        }
+    }
+    // The first request created the ring with both nodes on it.
+    let ring = cache.ring().await.unwrap();
+    assert_eq!(ring.nodes().len(), 2);
+    let owned_by_b = keys
+        .iter()
+        .filter(|key| ring.owner(key).unwrap().node_id == "b")
+        .count();
+    assert!(owned_by_b > 0 && owned_by_b < keys.len());
+    assert_eq!(redirected, owned_by_b);
+
+    // Draining the peer hands every key to this node.
+    let moved = cache
+        .rebalance_ring(RingOp::SetVnodes {
+            node_id: String::from("b"),
+            vnodes: 0,
+        })
+        .await
+        .unwrap();
+    assert!(moved > 0.0);
+    let result = cache
+        .get_file(
+            PathBuf::from(&keys[0]),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+        .await;
+    assert!(!matches!(result, GetFileResult::Redirect(_)));
+
+    let slots = ConcurrentDiskCache::new(
+        dir.path().join("slots"),
+        1024,
+        1,
+        Box::new(TwoNodeStore {
+            ring: Mutex::new(None),
+        }),
+        CacheOptions::default(),
+    );
+    assert!(matches!(
+        slots.rebalance_ring(RingOp::Sync).await,
+        Err(RingError::Disabled)
+    ));
+}
@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::config::parse_config;
+use istziio_server_node::storage::routing_storage_connector::{Route, RoutingStorageConnector};
+use istziio_server_node::storage::storage_connector::{
+    read_stream_to_end, FetchedObject, StorageConnector,
+};
+use rocket::futures::stream;
+use std::io::{ErrorKind, Result as IoResult};
+use std::sync::Arc;
+
+/// Answers every fetch with `<name>:<key>`.
+struct NamedConnector(&'static str);
+
+#[async_trait]
+impl StorageConnector for NamedConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(format!("{}:{}", self.0, file_name));
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn route(prefix: &str, strip_prefix: bool, name: &'static str) -> Route {
+    Route {
+        prefix: String::from(prefix),
+        strip_prefix,
+        connector: Arc::new(NamedConnector(name)),
+    }
+}
+
+async fn fetch(connector: &RoutingStorageConnector, key: &str) -> IoResult<String> {
+    let object = connector.fetch_stream(key).await?;
+    Ok(String::from_utf8(read_stream_to_end(object.stream).await?).unwrap())
+}
+
+#[tokio::test]
+async fn test_routes_by_longest_prefix() {
+    let connector = RoutingStorageConnector::new(
+        vec![
+            route("gold/", true, "gold"),
+            route("gold/archive/", false, "archive"),
+        ],
+        Some(Arc::new(NamedConnector("default"))),
+    );
+    assert_eq!(
+        fetch(&connector, "gold/a.parquet").await.unwrap(),
+        "gold:a.parquet"
+    );
+    assert_eq!(
+        fetch(&connector, "gold/archive/a.parquet").await.unwrap(),
+        "archive:gold/archive/a.parquet"
+    );
+    assert_eq!(
+        fetch(&connector, "raw/a.csv").await.unwrap(),
+        "default:raw/a.csv"
+    );
+}
+
+#[tokio::test]
+async fn test_unrouted_key_without_default() {
+    let connector = RoutingStorageConnector::new(vec![route("gold/", false, "gold")], None);
+    let err = fetch(&connector, "raw/a.csv").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn test_backends_in_config() {
+    let config = parse_config(
+        r#"
+        region_name = "us-east-1"
+        access_key = "AK"
+        secret_key = "SK"
+
+        [[backends]]
+        prefix = "gold/"
+        bucket = "lake-gold"
+
+        [[backends]]
+        prefix = "raw/"
+        bucket = "lake-raw"
+        access_key = "AK-RAW"
+        secret_key = "SK-RAW"
+        strip_prefix = true
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.backends.len(), 2);
+    assert!(config.backends[1].strip_prefix);
+    // Backends inherit the top-level region and credentials; no top-level bucket needed.
+    assert!(config.validate().is_ok());
+    assert_eq!(config.default_backend(), None);
+
+    let config =
+        parse_config("[[backends]]\nprefix = \"gold/\"\nbucket = \"lake-gold\"\n").unwrap();
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,231 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::s3_api::{
+    head_object, list_bucket_result, list_objects, to_bucket_keys, to_cache_keys, S3Api,
+};
+use istziio_server_node::server::ServerConfig;
+use istziio_server_node::storage::routing_storage_connector::{
+    BackendConfig, Route, RoutingStorageConnector,
+};
+use istziio_server_node::storage::storage_connector::{
+    FetchedObject, ListRequest, ObjectInfo, ObjectListing, StorageConnector,
+};
+use rocket::futures::stream;
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket::routes;
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// Holds a fixed set of objects and lists them all in one page.
+struct Listing(Vec<&'static str>);
+
+#[async_trait]
+impl StorageConnector for Listing {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        if !self.0.contains(&file_name) {
+            return Err(Error::new(ErrorKind::NotFound, "no such key"));
+        }
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        Ok(ObjectListing {
+            objects: self
+                .0
+                .iter()
+                .filter(|key| key.starts_with(&request.prefix))
+                .map(|key| ObjectInfo {
+                    key: key.to_string(),
+                    size: key.len() as u64,
+                    last_modified: Some(0),
+                    e_tag: Some(String::from("\"abc\"")),
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+}
+
+fn config() -> ServerConfig {
+    ServerConfig {
+        bucket: Some(String::from("lake")),
+        backends: vec![BackendConfig {
+            prefix: String::from("gold/"),
+            strip_prefix: true,
+            bucket: Some(String::from("lake-gold")),
+            region_name: None,
+            access_key: None,
+            secret_key: None,
+            use_mock_s3_endpoint: None,
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_bucket_key_prefixes() {
+    let api = S3Api::new(&config());
+    assert_eq!(api.key_prefix("lake"), Some(""));
+    assert_eq!(api.key_prefix("lake-gold"), Some("gold/"));
+    assert_eq!(api.key_prefix("other"), None);
+
+    let mock = S3Api::new(&ServerConfig {
+        use_mock_s3_endpoint: Some(String::from("http://localhost:6333")),
+        ..Default::default()
+    });
+    assert_eq!(mock.key_prefix("anything"), Some(""));
+    assert_eq!(mock.key_prefix("s3"), None);
+    assert_eq!(mock.key_prefix("stats"), None);
+}
+
+#[test]
+fn test_list_bucket_result() {
+    let request = ListRequest {
+        prefix: String::from("a&b/"),
+        delimiter: Some(String::from("/")),
+        max_keys: 2,
+        ..Default::default()
+    };
+    let listing = ObjectListing {
+        objects: vec![ObjectInfo {
+            key: String::from("a&b/x<1>.parquet"),
+            size: 42,
+            last_modified: Some(1_700_000_000),
+            e_tag: Some(String::from("\"etag\"")),
+        }],
+        common_prefixes: vec![String::from("a&b/dir/")],
+        next_continuation_token: Some(String::from("token")),
+    };
+    let xml = list_bucket_result("lake", &request, &listing);
+    assert!(xml.contains("<Name>lake</Name><Prefix>a&amp;b/</Prefix><KeyCount>2</KeyCount>"));
+    assert!(xml.contains("<MaxKeys>2</MaxKeys><IsTruncated>true</IsTruncated>"));
+    assert!(xml.contains("<Delimiter>/</Delimiter>"));
+    assert!(xml.contains("<NextContinuationToken>token</NextContinuationToken>"));
+    assert!(xml.contains("<Key>a&amp;b/x&lt;1&gt;.parquet</Key>"));
+    assert!(xml.contains("<LastModified>2023-11-14T22:13:20.000Z</LastModified>"));
+    assert!(xml.contains("<ETag>&quot;etag&quot;</ETag><Size>42</Size>"));
+    assert!(xml.contains("<CommonPrefixes><Prefix>a&amp;b/dir/</Prefix></CommonPrefixes>"));
+    assert!(!xml.contains("<ContinuationToken>"));
+}
+
+#[test]
+fn test_bucket_keys_round_trip() {
+    let request = ListRequest {
+        prefix: String::from("2024/"),
+        start_after: Some(String::from("2024/a")),
+        ..Default::default()
+    };
+    let cache_request = to_cache_keys(&request, "gold/");
+    assert_eq!(cache_request.prefix, "gold/2024/");
+    assert_eq!(cache_request.start_after.as_deref(), Some("gold/2024/a"));
+
+    let listing = ObjectListing {
+        objects: vec![ObjectInfo {
+            key: String::from("gold/2024/b"),
+            size: 1,
+            last_modified: None,
+            e_tag: None,
+        }],
+        common_prefixes: vec![String::from("gold/2024/c/")],
+        next_continuation_token: None,
+    };
+    let listing = to_bucket_keys(listing, "gold/");
+    assert_eq!(listing.objects[0].key, "2024/b");
+    assert_eq!(listing.common_prefixes, vec!["2024/c/"]);
+}
+
+#[tokio::test]
+async fn test_routing_lists_with_stripped_prefix() {
+    let connector = RoutingStorageConnector::new(
+        vec![Route {
+            prefix: String::from("gold/"),
+            strip_prefix: true,
+            connector: Arc::new(Listing(vec!["a.parquet", "b.parquet"])),
+        }],
+        Some(Arc::new(Listing(vec!["silver/c.parquet"]))),
+    );
+    let listing = connector
+        .list_objects(&ListRequest {
+            prefix: String::from("gold/a"),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let keys: Vec<_> = listing.objects.iter().map(|o| o.key.as_str()).collect();
+    assert_eq!(keys, vec!["gold/a.parquet"]);
+
+    let listing = connector
+        .list_objects(&ListRequest::default())
+        .await
+        .unwrap();
+    assert_eq!(listing.objects[0].key, "silver/c.parquet");
+}
+
+fn launch() -> (Client, TempDir) {
+    let connectors: Vec<Arc<dyn StorageConnector + Send + Sync>> =
+        vec![Arc::new(Listing(vec!["gold/a.parquet", "empty"]))];
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ));
+    let client = Client::tracked(
+        rocket::build()
+            .manage(S3Api::new(&config()))
+            .manage(cache)
+            .manage(connectors)
+            .mount("/", routes![list_objects, head_object]),
+    )
+    .unwrap();
+    (client, dir)
+}
+
+#[test]
+fn test_list_objects_route() {
+    let (client, _dir) = launch();
+    let response = client.get("/lake-gold?list-type=2&prefix=a").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    assert!(body.contains("<Key>a.parquet</Key>"));
+
+    let response = client.get("/lake-gold").dispatch();
+    assert_eq!(response.status(), Status::NotImplemented);
+    let response = client.get("/nope?list-type=2").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert!(response.into_string().unwrap().contains("NoSuchBucket"));
+}
+
+#[test]
+fn test_head_object_route() {
+    let (client, _dir) = launch();
+    let response = client.head("/lake-gold/a.parquet").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    // Sent as the Content-Length: the size of "gold/a.parquet".
+    assert_eq!(response.body().preset_size(), Some(14));
+    assert_eq!(
+        client.head("/lake/missing").dispatch().status(),
+        Status::NotFound
+    );
+    assert_eq!(
+        client.head("/nope/a.parquet").dispatch().status(),
+        Status::NotFound
+    );
+}
@@ -0,0 +1,131 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::identity::NODE_ID_FILE;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::scrub::{scan_dir, ScrubReport, ORPHAN_GRACE};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::fs::File;
+use std::io::Result as IoResult;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Answers every fetch with the key itself.
+struct EchoConnector;
+
+#[async_trait]
+impl StorageConnector for EchoConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn cache(dir: &Path) -> ConcurrentDiskCache {
+    ConcurrentDiskCache::new(
+        dir.to_path_buf(),
+        10_000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    )
+}
+
+async fn fetch(cache: &ConcurrentDiskCache, key: &str) -> GetFileResult {
+    cache
+        .get_file(
+            PathBuf::from(key),
+            Arc::new(EchoConnector),
+            GetFileOptions::default(),
+        )
+        .await
+}
+
+/// Writes `name` into `dir` with a modification time `ORPHAN_GRACE` in the past.
+fn write_stale(dir: &Path, name: &str, contents: &[u8]) {
+    std::fs::write(dir.join(name), contents).unwrap();
+    File::options()
+        .write(true)
+        .open(dir.join(name))
+        .unwrap()
+        .set_modified(SystemTime::now() - ORPHAN_GRACE * 2)
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_consistent_cache_needs_no_repair() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = cache(dir.path());
+    for i in 0..6 {
+        fetch(&cache, &format!("part-{}.parquet", i)).await;
+    }
+    assert_eq!(cache.scrub().await, ScrubReport::default());
+    assert_eq!(scan_dir(dir.path()).await.unwrap().len(), 6);
+}
+
+#[tokio::test]
+async fn test_scrub_repairs_bookkeeping() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = cache(dir.path());
+    for key in ["deleted.parquet", "truncated.parquet", "intact.parquet"] {
+        assert!(matches!(fetch(&cache, key).await, GetFileResult::Hit(_)));
+    }
+    std::fs::remove_file(dir.path().join("deleted.parquet")).unwrap();
+    std::fs::write(dir.path().join("truncated.parquet"), b"trunc").unwrap();
+    // Left behind by a crash: a download that was never recorded, and a scratch file.
+    write_stale(dir.path(), "crashed.parquet", b"0123456789");
+    write_stale(dir.path(), "intact.parquet.compressing", b"01234");
+    // The node's id is not a cache file.
+    write_stale(dir.path(), NODE_ID_FILE, b"id");
+    // A location lost by the metadata store, e.g. on a Redis restart.
+    let _ = cache
+        .metadata
+        .read()
+        .await
+        .remove_file(String::from("intact.parquet"))
+        .await;
+    // A download in progress is too young to be touched.
+    std::fs::write(dir.path().join("downloading.parquet"), b"01").unwrap();
+
+    let report = cache.scrub().await;
+    assert_eq!(
+        report,
+        ScrubReport {
+            missing_files: 1,
+            corrupt_files: 1,
+            locations_restored: 1,
+            orphaned_files: 2,
+            orphaned_bytes: 15,
+        }
+    );
+    assert!(!dir.path().join("truncated.parquet").exists());
+    assert!(!dir.path().join("crashed.parquet").exists());
+    assert!(dir.path().join("downloading.parquet").exists());
+    assert!(dir.path().join(NODE_ID_FILE).exists());
+
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!(stats.files, 1);
+    assert_eq!(stats.current_size, "intact.parquet".len() as u64);
+    assert_eq!(stats.scrubbed, report);
+    // The dropped entries are fetched again rather than failing.
+    assert!(matches!(
+        fetch(&cache, "deleted.parquet").await,
+        GetFileResult::Hit(_)
+    ));
+    assert_eq!(cache.scrub().await, ScrubReport::default());
+}
@@ -0,0 +1,98 @@
+use async_trait::async_trait;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::{InProcessStore, MetadataStore};
+use istziio_server_node::redis::RedisServer;
+use istziio_server_node::selftest::self_test;
+use istziio_server_node::server::ServerConfig;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use std::io::{self, Result as IoResult};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Fails every request with `kind`.
+struct FailingConnector(io::ErrorKind);
+
+#[async_trait]
+impl StorageConnector for FailingConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Err(io::Error::new(self.0, "boom"))
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn config(dir: &Path, max_size: u64) -> ServerConfig {
+    ServerConfig {
+        cache_dir: dir.display().to_string(),
+        max_size,
+        bucket_size: 2,
+        ..Default::default()
+    }
+}
+
+fn cache(config: &ServerConfig, store: Box<dyn MetadataStore>) -> ConcurrentDiskCache {
+    ConcurrentDiskCache::new(
+        config.cache_dir.clone().into(),
+        config.max_size,
+        config.bucket_size,
+        store,
+        CacheOptions::default(),
+    )
+}
+
+fn connector(kind: io::ErrorKind) -> Arc<dyn StorageConnector + Send + Sync> {
+    Arc::new(FailingConnector(kind))
+}
+
+#[tokio::test]
+async fn test_passes_on_a_working_node() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(dir.path(), 1000);
+    let cache = cache(&config, Box::new(InProcessStore::new(6379)));
+    let connector = connector(io::ErrorKind::NotFound);
+    assert!(self_test(&config, &cache, Some(&connector))
+        .await
+        .is_empty());
+    assert!(self_test(&config, &cache, None).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_reports_each_problem() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache");
+    let config = config(&cache_dir, u64::MAX / 2);
+    let cache = cache(&config, Box::new(InProcessStore::new(6379)));
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+    let connector = connector(io::ErrorKind::PermissionDenied);
+
+    let problems = self_test(&config, &cache, Some(&connector)).await;
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].contains("is not writable"));
+    assert!(problems[1].contains("backing store did not answer"));
+
+    // Once the directory is back, its size is checked against the file system.
+    std::fs::create_dir_all(&cache_dir).unwrap();
+    let problems = self_test(&config, &cache, None).await;
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(problems[0].contains("lower max_size"));
+}
+
+#[tokio::test]
+async fn test_unreachable_redis_is_reported() {
+    // Bound and dropped, so nothing listens there.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(dir.path(), 1000);
+    let store = RedisServer::new(vec![format!("redis://127.0.0.1:{}", port)], port, 1).unwrap();
+    let cache = cache(&config, Box::new(store));
+    let problems = self_test(&config, &cache, None).await;
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(problems[0].starts_with("metadata store (Redis) did not answer"));
+}
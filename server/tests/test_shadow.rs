@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache, GetFileOptions};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::shadow::{ShadowCache, ShadowConfig, ShadowPolicy, ShadowStats};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Every key is ten bytes.
+struct Bucket;
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Ok(FetchedObject {
+            content_length: Some(10),
+            object_size: Some(10),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(Bytes::from(vec![b'x'; 10]))])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+fn shadow(policy: ShadowPolicy, max_size: u64) -> ShadowCache {
+    ShadowCache::new(&ShadowConfig {
+        name: policy.to_string(),
+        policy,
+        max_size: Some(max_size),
+    })
+}
+
+#[test]
+fn test_shadow_policies() {
+    // "hot" is read between every other key; three ten-byte entries fit.
+    let accesses = [
+        "hot", "a", "hot", "b", "hot", "c", "hot", "a", "hot", "d", "hot", "b",
+    ];
+    let mut lru = shadow(ShadowPolicy::Lru, 30);
+    let mut lfu = shadow(ShadowPolicy::Lfu, 30);
+    let mut small = shadow(ShadowPolicy::Lru, 20);
+    for key in accesses {
+        for cache in [&mut lru, &mut lfu, &mut small] {
+            cache.record(key, Some(10));
+        }
+    }
+    let (lru, lfu, small) = (lru.stats(), lfu.stats(), small.stats());
+    assert_eq!((lru.hits, lru.misses), (5, 7));
+    assert_eq!((lfu.hits, lfu.misses), (5, 7));
+    assert_eq!((small.hits, small.misses), (5, 7));
+    assert_eq!((lru.entries, lru.current_size), (3, 30));
+    assert_eq!((small.entries, small.current_size), (2, 20));
+    assert_eq!(lfu.policy, "lfu");
+
+    // A scan pushes the hot key out under LRU but not under LFU.
+    let mut lru = shadow(ShadowPolicy::Lru, 30);
+    let mut lfu = shadow(ShadowPolicy::Lfu, 30);
+    for cache in [&mut lru, &mut lfu] {
+        for _ in 0..3 {
+            cache.record("hot", Some(10));
+        }
+        for key in ["s1", "s2", "s3", "s4", "hot"] {
+            cache.record(key, Some(10));
+        }
+    }
+    assert_eq!(lru.stats().hits, 2);
+    assert_eq!(lfu.stats().hits, 3);
+}
+
+#[test]
+fn test_shadow_sizes() {
+    let mut cache = shadow(ShadowPolicy::Lru, 30);
+    // Too large to ever fit, and a miss whose size comes later.
+    cache.record("huge", Some(31));
+    cache.record("streamed", None);
+    cache.record("huge", Some(31));
+    cache.sized("streamed", 10);
+    cache.sized("never-missed", 10);
+    cache.record("streamed", None);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (1, 3));
+    assert_eq!((stats.entries, stats.current_size), (1, 10));
+    cache.clear();
+    assert_eq!(cache.stats().entries, 0);
+    assert_eq!(cache.stats().hits, 1);
+
+    let config = ShadowConfig {
+        name: String::from("double"),
+        policy: ShadowPolicy::Lfu,
+        max_size: Some(2000),
+    };
+    assert_eq!(config.per_shard(250, 1000).max_size, Some(500));
+    let same_size = ShadowConfig {
+        max_size: None,
+        ..config
+    };
+    assert_eq!(same_size.per_shard(250, 1000).max_size, Some(250));
+}
+
+#[test]
+fn test_merge_shadow_stats() {
+    let stats = |name: &str, hits, misses| ShadowStats {
+        name: String::from(name),
+        hits,
+        misses,
+        max_size: 10,
+        ..ShadowStats::default()
+    };
+    let merged = ShadowStats::merge(&[stats("a", 1, 3), stats("b", 2, 0), stats("a", 3, 1)]);
+    assert_eq!(merged.len(), 2);
+    assert_eq!(
+        (merged[0].hits, merged[0].misses, merged[0].max_size),
+        (4, 4, 20)
+    );
+    assert_eq!(merged[0].hit_ratio, 50.0);
+    assert_eq!(merged[1].name, "b");
+}
+
+#[tokio::test]
+async fn test_shadows_follow_the_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        30,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            shadow_caches: vec![
+                ShadowConfig {
+                    name: String::from("same"),
+                    ..Default::default()
+                },
+                ShadowConfig {
+                    name: String::from("double"),
+                    max_size: Some(60),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        },
+    );
+    // Four objects cycled through a cache holding three: LRU misses every time.
+    for _ in 0..2 {
+        for key in ["a", "b", "c", "d"] {
+            cache
+                .get_file(
+                    PathBuf::from(key),
+                    Arc::new(Bucket),
+                    GetFileOptions::default(),
+                )
+                .await;
+        }
+    }
+    let shadows = cache.shadow_stats().await;
+    assert_eq!((shadows[0].hits, shadows[0].misses), (0, 8));
+    assert_eq!((shadows[1].hits, shadows[1].misses), (4, 4));
+    assert_eq!(shadows[1].max_size, 60);
+
+    let stats = NodeStats::from_snapshot(&cache.snapshot(0).await);
+    assert_eq!((stats.disk_hits, stats.misses), (0, 8));
+    assert_eq!(stats.shadows, shadows);
+    assert!(cache.get_stats().await.contains("double"));
+}
@@ -0,0 +1,109 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::cache_core::shard_index;
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::shard_runtime::{ShardRuntimeConfig, ShardRuntimes};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use rocket::futures::stream;
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn thread_name() -> String {
+    std::thread::current()
+        .name()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[tokio::test]
+async fn test_tasks_run_on_their_shard_thread() {
+    let runtimes = ShardRuntimes::start(3, ShardRuntimeConfig::default()).unwrap();
+    assert_eq!(runtimes.len(), 3);
+    assert_eq!(runtimes.run(1, async { thread_name() }).await, "shard-1");
+    assert_eq!(runtimes.run(4, async { thread_name() }).await, "shard-1");
+    let first = runtimes.run(0, async { std::thread::current().id() }).await;
+    assert_eq!(
+        runtimes.run(0, async { std::thread::current().id() }).await,
+        first
+    );
+    assert_ne!(thread_name(), "shard-0");
+
+    // Timers and spawned tasks work on a shard runtime.
+    let slept = runtimes
+        .run(2, async {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            tokio::spawn(async { thread_name() }).await.unwrap()
+        })
+        .await;
+    assert_eq!(slept, "shard-2");
+
+    let pinned = ShardRuntimes::start(2, ShardRuntimeConfig { pin_cores: true }).unwrap();
+    assert_eq!(pinned.run(1, async { 1 + 1 }).await, 2);
+}
+
+/// Every key is ten bytes.
+struct Bucket;
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Ok(FetchedObject {
+            content_length: Some(10),
+            object_size: Some(10),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(Bytes::from(vec![b'x'; 10]))])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[tokio::test]
+async fn test_cache_requests_run_on_the_owning_shard() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        400,
+        4,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            shard_runtimes: Some(ShardRuntimeConfig::default()),
+            ..CacheOptions::default()
+        },
+    ));
+    for key in ["a", "b", "c", "d", "e"] {
+        let shard_cache = cache.clone();
+        let (thread, result) = cache
+            .on_shard_of(key, async move {
+                let result = shard_cache
+                    .get_file(
+                        PathBuf::from(key),
+                        Arc::new(Bucket),
+                        GetFileOptions::default(),
+                    )
+                    .await;
+                (thread_name(), matches!(result, GetFileResult::Hit(_)))
+            })
+            .await;
+        assert_eq!(thread, format!("shard-{}", shard_index(key, 4)));
+        assert!(result);
+    }
+
+    // Without shard runtimes tasks run where they are awaited.
+    let shared = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        400,
+        4,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let here = thread_name();
+    assert_eq!(shared.on_shard_of("a", async { thread_name() }).await, here);
+}
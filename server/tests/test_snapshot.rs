@@ -0,0 +1,243 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache, GetFileOptions};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::compression::{CompressionCodec, CompressionConfig};
+use istziio_server_node::encryption::EncryptionKey;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::snapshot::{valid_name, SnapshotConfig, Snapshots};
+use istziio_server_node::storage::storage_connector::{
+    read_stream_to_end, FetchedObject, ObjectStream, ObjectVersion, StorageConnector,
+};
+use rocket::futures::{stream, StreamExt};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+/// Objects in memory, taking uploads; counts fetches outside the snapshots.
+#[derive(Default)]
+struct Bucket {
+    objects: Mutex<HashMap<String, Bytes>>,
+    source_fetches: AtomicU32,
+}
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        if !file_name.starts_with("_istziio/") {
+            self.source_fetches.fetch_add(1, Ordering::SeqCst);
+        }
+        let body = self
+            .objects
+            .lock()
+            .unwrap()
+            .get(file_name)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, file_name.to_string()))?;
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: ObjectVersion {
+                e_tag: Some(format!("\"{}\"", body.len())),
+                last_modified: None,
+            },
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+
+    async fn put_stream(&self, file_name: &str, body: ObjectStream, size: u64) -> IoResult<()> {
+        let body = Bytes::from(read_stream_to_end(body).await?);
+        assert_eq!(body.len() as u64, size);
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(file_name.to_string(), body);
+        Ok(())
+    }
+}
+
+fn node() -> (ConcurrentDiskCache, TempDir) {
+    node_with(CacheOptions::default())
+}
+
+fn node_with(options: CacheOptions) -> (ConcurrentDiskCache, TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        1_000_000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        options,
+    );
+    (cache, dir)
+}
+
+/// Whether any file under `dir` contains `needle`.
+fn on_disk(dir: &Path, needle: &[u8]) -> bool {
+    std::fs::read_dir(dir).unwrap().any(|file| {
+        let path = file.unwrap().path();
+        path.is_file()
+            && std::fs::read(&path)
+                .unwrap()
+                .windows(needle.len())
+                .any(|window| window == needle)
+    })
+}
+
+async fn read(cache: &ConcurrentDiskCache, key: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    let mut stream = cache.open_object(key).await.unwrap();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk.unwrap());
+    }
+    body
+}
+
+#[test]
+fn test_snapshot_names() {
+    assert!(valid_name("20261015T120000Z"));
+    for bad in ["", ".", "..", "a/b"] {
+        assert!(!valid_name(bad));
+    }
+}
+
+#[tokio::test]
+async fn test_snapshot_and_restore() {
+    let bucket = Arc::new(Bucket::default());
+    let keys = ["orders.csv", "lineitem/part 0.parquet"];
+    for key in keys {
+        bucket
+            .objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), Bytes::from(key.repeat(3)));
+    }
+    let connectors: Vec<Arc<dyn StorageConnector + Send + Sync>> = vec![bucket.clone()];
+    let snapshots = Snapshots::new(SnapshotConfig::default());
+
+    let (old, _old_dir) = node();
+    for key in keys {
+        old.get_file(
+            PathBuf::from(key),
+            bucket.clone(),
+            GetFileOptions::default(),
+        )
+        .await;
+    }
+    let summary = snapshots
+        .take("full", true, &old, &connectors)
+        .await
+        .unwrap();
+    assert_eq!(summary.objects, 2);
+    assert_eq!(summary.failed, 0);
+    assert_eq!(summary.bytes, (keys[0].len() + keys[1].len()) as u64 * 3);
+    assert!(bucket
+        .objects
+        .lock()
+        .unwrap()
+        .contains_key("_istziio/snapshots/full/data/lineitem/part 0.parquet"));
+    snapshots
+        .take("keys", false, &old, &connectors)
+        .await
+        .unwrap();
+    let manifest = snapshots.manifest("keys", &connectors).await.unwrap();
+    assert!(!manifest.with_data);
+    assert_eq!(manifest.objects.len(), 2);
+    assert_eq!(manifest.objects[0].e_tag.as_deref(), Some("\"30\""));
+    assert_eq!(bucket.source_fetches.load(Ordering::SeqCst), 2);
+
+    // A snapshot with data is restored without going to the source.
+    let (copied, _copied_dir) = node();
+    let restored = snapshots
+        .restore("full", &copied, &connectors)
+        .await
+        .unwrap();
+    assert_eq!(
+        (restored.copied, restored.fetched, restored.failed),
+        (2, 0, 0)
+    );
+    assert_eq!(bucket.source_fetches.load(Ordering::SeqCst), 2);
+    for key in keys {
+        assert_eq!(read(&copied, key).await, key.repeat(3).as_bytes());
+        assert_eq!(
+            copied.object_version(key).await,
+            old.object_version(key).await
+        );
+    }
+    // Restoring again finds everything cached.
+    let again = snapshots
+        .restore("full", &copied, &connectors)
+        .await
+        .unwrap();
+    assert_eq!(again.skipped, 2);
+
+    // Without data, the keys are fetched from the source.
+    let (fetched, _fetched_dir) = node();
+    let restored = snapshots
+        .restore("keys", &fetched, &connectors)
+        .await
+        .unwrap();
+    assert_eq!((restored.copied, restored.fetched), (0, 2));
+    assert_eq!(bucket.source_fetches.load(Ordering::SeqCst), 4);
+    assert_eq!(read(&fetched, keys[0]).await, keys[0].repeat(3).as_bytes());
+
+    assert!(snapshots
+        .restore("none", &fetched, &connectors)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_encrypted_snapshots() {
+    let marker = "kept sealed on both nodes;";
+    let body = marker.repeat(1000);
+    let bucket = Arc::new(Bucket::default());
+    bucket
+        .objects
+        .lock()
+        .unwrap()
+        .insert(String::from("orders.csv"), Bytes::from(body.clone()));
+    let connectors: Vec<Arc<dyn StorageConnector + Send + Sync>> = vec![bucket.clone()];
+    let snapshots = Snapshots::new(SnapshotConfig::default());
+    let options = || CacheOptions {
+        encryption: Some(Arc::new(EncryptionKey::from_bytes(&[7u8; 32]).unwrap())),
+        compression: Some(CompressionConfig {
+            min_size: 0,
+            ..CompressionConfig::new(CompressionCodec::Zstd)
+        }),
+        ..Default::default()
+    };
+
+    let (old, _old_dir) = node_with(options());
+    old.get_file(
+        PathBuf::from("orders.csv"),
+        bucket.clone(),
+        GetFileOptions::default(),
+    )
+    .await;
+    let summary = snapshots
+        .take("sealed", true, &old, &connectors)
+        .await
+        .unwrap();
+    assert_eq!((summary.bytes, summary.failed), (body.len() as u64, 0));
+    assert_eq!(
+        bucket.objects.lock().unwrap()["_istziio/snapshots/sealed/data/orders.csv"],
+        body.as_bytes()
+    );
+
+    let (copied, copied_dir) = node_with(options());
+    let restored = snapshots
+        .restore("sealed", &copied, &connectors)
+        .await
+        .unwrap();
+    assert_eq!((restored.copied, restored.failed), (1, 0));
+    assert!(!on_disk(copied_dir.path(), marker.as_bytes()));
+    assert_eq!(read(&copied, "orders.csv").await, body.as_bytes());
+}
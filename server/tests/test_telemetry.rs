@@ -0,0 +1,140 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use istziio_server_node::telemetry::{SpanLogger, TraceContext, TracingConfig};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{info_span, Instrument};
+
+#[test]
+fn test_traceparent_parsing() {
+    let trace =
+        TraceContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .unwrap();
+    assert_eq!(trace.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(trace.parent_id.as_deref(), Some("00f067aa0ba902b7"));
+
+    for malformed in [
+        "",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+    ] {
+        assert_eq!(TraceContext::from_traceparent(malformed), None);
+    }
+
+    let fresh = TraceContext::new();
+    assert_eq!(fresh.trace_id.len(), 32);
+    assert_eq!(fresh.parent_id, None);
+    assert_ne!(fresh.trace_id, TraceContext::new().trace_id);
+}
+
+#[tokio::test]
+async fn test_span_logger_tracks_nested_spans() {
+    let logger = SpanLogger::new(TracingConfig::default());
+    let _guard = tracing::subscriber::set_default(logger);
+    let root = info_span!("get_file", trace_id = "4bf92f3577b34da6a3ce929d0e0e4736");
+    async {
+        async {}.instrument(info_span!("redis_lookup")).await;
+        tokio::task::yield_now()
+            .instrument(info_span!("s3_fetch"))
+            .await;
+    }
+    .instrument(root.clone())
+    .await;
+    assert!(!root.is_disabled());
+    drop(root);
+}
+
+/// A collector that passes on the path and body of every request it gets.
+fn collector() -> (String, mpsc::UnboundedReceiver<(String, Value)>) {
+    let (sender, received) = mpsc::unbounded_channel();
+    let make = make_service_fn(move |_| {
+        let sender = sender.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let sender = sender.clone();
+                async move {
+                    let path = req.uri().path().to_string();
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    let _ = sender.send((path, serde_json::from_slice(&body).unwrap()));
+                    Ok::<_, Infallible>(Response::new(Body::empty()))
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let endpoint = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    (endpoint, received)
+}
+
+#[tokio::test]
+async fn test_spans_are_exported() {
+    let (endpoint, mut received) = collector();
+    let logger = SpanLogger::new(TracingConfig {
+        otlp_endpoint: Some(endpoint),
+        service_name: Some(String::from("node-a")),
+        ..TracingConfig::default()
+    });
+    let trace =
+        TraceContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .unwrap();
+    let traceparent = {
+        let _guard = tracing::subscriber::set_default(logger);
+        let root = info_span!(
+            "get_file",
+            trace_id = %trace.trace_id,
+            remote_parent = trace.parent_id.as_deref().unwrap_or_default(),
+            uid = "a/b",
+        );
+        info_span!("untraced").in_scope(|| {});
+        async {
+            async {}.instrument(info_span!("redis_lookup")).await;
+            trace.traceparent()
+        }
+        .instrument(root)
+        .await
+    };
+
+    // Dropping the logger sends what is left.
+    let (path, request) = tokio::time::timeout(Duration::from_secs(10), received.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(path, "/v1/traces");
+    let resource = &request["resourceSpans"][0];
+    assert_eq!(
+        resource["resource"]["attributes"][0]["value"]["stringValue"],
+        "node-a"
+    );
+    let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+    assert_eq!(spans.len(), 2);
+    let (lookup, root) = (&spans[0], &spans[1]);
+    assert_eq!(lookup["name"], "redis_lookup");
+    assert_eq!(root["name"], "get_file");
+    for span in spans {
+        assert_eq!(span["traceId"], trace.trace_id.as_str());
+    }
+    assert_eq!(root["parentSpanId"], "00f067aa0ba902b7");
+    assert_eq!(lookup["parentSpanId"], root["spanId"]);
+    assert_eq!(root["attributes"][0]["key"], "uid");
+    assert_eq!(root["attributes"][0]["value"]["stringValue"], "a/b");
+
+    // Requests sent from within a span name it as their parent.
+    let root_id = root["spanId"].as_str().unwrap();
+    assert_eq!(traceparent, format!("00-{}-{}-01", trace.trace_id, root_id));
+    assert!(TraceContext::from_traceparent(&traceparent).is_some());
+}
+
+#[test]
+fn test_traceparent_outside_spans() {
+    let trace = TraceContext::new();
+    let traceparent = trace.traceparent();
+    let parsed = TraceContext::from_traceparent(&traceparent).unwrap();
+    assert_eq!(parsed.trace_id, trace.trace_id);
+    assert_ne!(trace.traceparent(), traceparent);
+}
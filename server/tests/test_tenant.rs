@@ -0,0 +1,142 @@
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache, GetFileOptions};
+use istziio_server_node::cluster::NodeStats;
+use istziio_server_node::config::parse_config;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::mock_storage_connector::{
+    GeneratedObjects, MockS3Config, MockS3StorageConnector,
+};
+use istziio_server_node::tenant::{TenantConfig, Tenants};
+use rocket::http::Header;
+use rocket::local::blocking::Client;
+use rocket::{get, routes};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn tenants() -> Tenants {
+    Tenants::new(vec![
+        TenantConfig {
+            name: String::from("analytics"),
+            tokens: vec![String::from("t-analytics")],
+            prefixes: vec![String::from("warehouse/")],
+            max_bytes: Some(1000),
+        },
+        TenantConfig {
+            name: String::from("ml"),
+            tokens: vec![String::from("t-ml")],
+            prefixes: vec![String::from("warehouse/features/")],
+            max_bytes: None,
+        },
+    ])
+}
+
+#[test]
+fn test_tenant_resolution() {
+    let tenants = tenants();
+    assert_eq!(tenants.by_token("t-ml"), Some("ml"));
+    assert_eq!(tenants.by_token("t-unknown"), None);
+    // The longest matching prefix wins.
+    assert_eq!(tenants.by_key("warehouse/features/a.parquet"), Some("ml"));
+    assert_eq!(
+        tenants.by_key("warehouse/orders.parquet"),
+        Some("analytics")
+    );
+    assert_eq!(tenants.by_key("logs/app.log"), None);
+    // A token takes precedence over the key.
+    assert_eq!(
+        tenants.resolve(Some("ml"), "warehouse/orders.parquet"),
+        Some(String::from("ml"))
+    );
+    assert_eq!(tenants.resolve(None, "logs/app.log"), None);
+    assert_eq!(tenants.per_shard(3).budget("analytics"), Some(333));
+    assert_eq!(tenants.budget("ml"), None);
+}
+
+#[get("/tenant")]
+fn tenant_of(options: GetFileOptions) -> String {
+    options.tenant.unwrap_or_default()
+}
+
+#[test]
+fn test_tenant_from_bearer_token() {
+    let client = Client::tracked(
+        rocket::build()
+            .manage(tenants())
+            .mount("/", routes![tenant_of]),
+    )
+    .unwrap();
+    let response = client
+        .get("/tenant")
+        .header(Header::new("Authorization", "Bearer t-analytics"))
+        .dispatch();
+    assert_eq!(response.into_string().unwrap(), "analytics");
+    assert_eq!(client.get("/tenant").dispatch().into_string().unwrap(), "");
+}
+
+#[test]
+fn test_tenants_in_config() {
+    let mock = "use_mock_s3_endpoint = \"http://localhost:6333\"\n";
+    let config = parse_config(&format!(
+        "{}[[tenants]]\nname = \"analytics\"\nprefixes = [\"warehouse/\"]\nmax_bytes = 96\n",
+        mock
+    ))
+    .unwrap();
+    assert_eq!(config.tenants[0].max_bytes, Some(96));
+    assert!(config.validate().is_ok());
+
+    let repeated = format!(
+        "{}[[tenants]]\nname = \"a\"\n[[tenants]]\nname = \"a\"\n",
+        mock
+    );
+    assert!(parse_config(&repeated).unwrap().validate().is_err());
+}
+
+#[tokio::test]
+async fn test_tenants_evict_their_own_entries_within_budget() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            tenants: Tenants::new(vec![TenantConfig {
+                name: String::from("analytics"),
+                tokens: Vec::new(),
+                prefixes: vec![String::from("warehouse/")],
+                max_bytes: Some(250),
+            }]),
+            ..Default::default()
+        },
+    );
+    let connector = Arc::new(
+        MockS3StorageConnector::new(String::from("http://127.0.0.1:1")).with_config(MockS3Config {
+            generate: Some(GeneratedObjects {
+                min_size: 100,
+                max_size: 100,
+            }),
+            ..Default::default()
+        }),
+    );
+    let get = |key: &str| {
+        cache.get_file(
+            PathBuf::from(key),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+    };
+    let misses = || async { NodeStats::from_snapshot(&cache.snapshot(0).await).misses };
+
+    for key in ["warehouse/a", "warehouse/b", "other/x", "warehouse/c"] {
+        get(key).await;
+    }
+    assert_eq!(misses().await, 4);
+    // The third warehouse object displaced the first, and nothing else.
+    for key in ["warehouse/b", "warehouse/c", "other/x"] {
+        get(key).await;
+    }
+    assert_eq!(misses().await, 4);
+    get("warehouse/a").await;
+    assert_eq!(misses().await, 5);
+    cache.invalidate("warehouse/b").await;
+    assert_eq!(cache.debug_validate().await, Ok(()));
+}
@@ -0,0 +1,146 @@
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response};
+use istziio_server_node::tls::{
+    serve_tls, RelayOnly, RelayToken, TlsConfig, REAL_IP_HEADER, RELAY_TOKEN_HEADER,
+};
+use rocket::http::{Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::{get, routes};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+
+fn fixture(name: &str) -> String {
+    format!("{}/{}", FIXTURES, name)
+}
+
+fn tls_config(client_ca: Option<String>) -> TlsConfig {
+    TlsConfig {
+        certs: fixture("server.pem"),
+        key: fixture("server.key"),
+        client_ca,
+    }
+}
+
+/// Starts a backend that answers with the relay headers it got behind a TLS relay, and
+/// returns the relay's address.
+async fn start_relay(config: TlsConfig) -> SocketAddr {
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = backend.accept().await {
+            let service = service_fn(|request: Request<Body>| async move {
+                let header = |name| {
+                    request
+                        .headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let body = format!("{} {}", header(REAL_IP_HEADER), header(RELAY_TOKEN_HEADER));
+                Ok::<_, Infallible>(Response::new(Body::from(body)))
+            });
+            tokio::spawn(Http::new().serve_connection(stream, service));
+        }
+    });
+    let public = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let acceptor = config.acceptor().unwrap();
+    let token = RelayToken(String::from("relay-secret"));
+    tokio::spawn(serve_tls(acceptor, public.to_string(), backend_addr, token));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    public
+}
+
+/// A client trusting the test CA, without a certificate of its own.
+fn client() -> reqwest::Client {
+    let ca = reqwest::Certificate::from_pem(&std::fs::read(fixture("ca.pem")).unwrap()).unwrap();
+    reqwest::Client::builder()
+        .add_root_certificate(ca)
+        .build()
+        .unwrap()
+}
+
+async fn get(client: &reqwest::Client, addr: SocketAddr) -> reqwest::Result<String> {
+    client
+        .get(format!("https://localhost:{}/", addr.port()))
+        .header(REAL_IP_HEADER, "203.0.113.9")
+        .header(RELAY_TOKEN_HEADER, "forged")
+        .send()
+        .await?
+        .text()
+        .await
+}
+
+#[tokio::test]
+async fn test_tls_relay_passes_the_client_address() {
+    let addr = start_relay(tls_config(None)).await;
+    // Whatever the client claims in the relay headers is replaced.
+    assert_eq!(
+        get(&client(), addr).await.unwrap(),
+        "127.0.0.1 relay-secret"
+    );
+}
+
+#[tokio::test]
+async fn test_tls_relay_requires_client_certificate() {
+    let config = tls_config(Some(fixture("ca.pem")));
+    let addr = start_relay(config.clone()).await;
+    assert!(get(&client(), addr).await.is_err());
+    // Peers present the node's own certificate.
+    let peer = config.peer_client(reqwest::Client::builder()).unwrap();
+    assert_eq!(get(&peer, addr).await.unwrap(), "127.0.0.1 relay-secret");
+}
+
+#[get("/ping")]
+fn ping() -> &'static str {
+    "pong"
+}
+
+#[tokio::test]
+async fn test_plaintext_listener_only_serves_the_relay() {
+    let rocket = rocket::build()
+        .manage(RelayToken(String::from("relay-secret")))
+        .attach(RelayOnly)
+        .mount("/", routes![ping]);
+    let client = Client::tracked(rocket).await.unwrap();
+    assert_eq!(
+        client.get("/ping").dispatch().await.status(),
+        Status::Forbidden
+    );
+    let forged = client
+        .post("/ping")
+        .header(Header::new(RELAY_TOKEN_HEADER, "forged"))
+        .dispatch()
+        .await;
+    assert_eq!(forged.status(), Status::Forbidden);
+    let relayed = client
+        .get("/ping")
+        .header(Header::new(RELAY_TOKEN_HEADER, "relay-secret"))
+        .dispatch()
+        .await;
+    assert_eq!(relayed.status(), Status::Ok);
+    assert_eq!(relayed.into_string().await.unwrap(), "pong");
+}
+
+#[test]
+fn test_tls_config_rejects_missing_files() {
+    let config = TlsConfig {
+        certs: fixture("missing.pem"),
+        ..tls_config(None)
+    };
+    assert!(config.acceptor().is_err());
+    let config = TlsConfig {
+        key: fixture("ca.pem"),
+        ..tls_config(None)
+    };
+    assert!(config.acceptor().is_err());
+}
@@ -0,0 +1,175 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::footer::is_parquet_key;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::policy::{PolicySet, PrefixPolicy, Priority};
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use istziio_server_node::versioning::{
+    is_valid_version_id, split_version, versioned_key, PinnedVersion, VERSION_HEADER,
+};
+use rocket::futures::stream;
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use rocket::{get, routes};
+use std::io::Result as IoResult;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Answers every fetch with the name it was asked for, and remembers the names.
+#[derive(Default)]
+struct RecordingConnector {
+    fetched: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl StorageConnector for RecordingConnector {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        self.fetched.lock().unwrap().push(file_name.to_string());
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+}
+
+#[test]
+fn test_versioned_keys() {
+    let key = versioned_key("tables/orders.parquet", "3HL4kqtJlcpXroDTDmJ.rmSpXd3dIbrHY");
+    assert_eq!(
+        key,
+        "tables/orders.parquet@version-3HL4kqtJlcpXroDTDmJ.rmSpXd3dIbrHY"
+    );
+    assert_eq!(
+        split_version(&key),
+        (
+            "tables/orders.parquet",
+            Some("3HL4kqtJlcpXroDTDmJ.rmSpXd3dIbrHY")
+        )
+    );
+    assert_eq!(
+        split_version("tables/orders.parquet"),
+        ("tables/orders.parquet", None)
+    );
+    // Chunks of a pinned version are not versions themselves.
+    assert_eq!(split_version("a@version-1@chunk-0").1, None);
+
+    assert!(is_valid_version_id("null"));
+    assert!(!is_valid_version_id(""));
+    assert!(!is_valid_version_id("../../etc"));
+    assert!(!is_valid_version_id(&"a".repeat(1025)));
+}
+
+#[test]
+fn test_pinned_versions_follow_their_object() {
+    let policies = PolicySet::new(vec![PrefixPolicy {
+        priority: Priority::Pinned,
+        ..PrefixPolicy::new("*.parquet")
+    }]);
+    let key = versioned_key("dim/nation.parquet", "7");
+    assert_eq!(policies.priority(&key), Priority::Pinned);
+    assert_eq!(
+        policies.priority(&format!("{}@chunk-1", key)),
+        Priority::Pinned
+    );
+    assert!(is_parquet_key(&key));
+}
+
+#[tokio::test]
+async fn test_versions_are_cached_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        10_000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let connector = Arc::new(RecordingConnector::default());
+    let keys = [
+        String::from("orders.csv"),
+        versioned_key("orders.csv", "1"),
+        versioned_key("orders.csv", "2"),
+    ];
+    for key in keys.iter().chain(keys.iter()) {
+        let result = cache
+            .get_file(
+                PathBuf::from(key),
+                connector.clone(),
+                GetFileOptions::default(),
+            )
+            .await;
+        assert!(matches!(result, GetFileResult::Hit(_)));
+    }
+    // One fetch per version, each asking for its own version.
+    assert_eq!(*connector.fetched.lock().unwrap(), keys);
+    for key in &keys {
+        assert_eq!(std::fs::read_to_string(dir.path().join(key)).unwrap(), *key);
+    }
+
+    // Invalidating the key leaves the pinned versions, which never change, alone.
+    cache.invalidate("orders.csv").await;
+    assert!(!dir.path().join("orders.csv").exists());
+    assert!(dir.path().join(&keys[1]).exists());
+}
+
+#[test]
+fn test_redirects_keep_the_version() {
+    let cache = ConcurrentDiskCache::new(
+        PathBuf::from("unused"),
+        1000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    );
+    let url = cache
+        .peer_url("127.0.0.1", 6379, "bucket/orders.csv?versionId=7")
+        .unwrap();
+    assert_eq!(url.path(), "/bucket/orders.csv");
+    assert_eq!(url.query(), Some("versionId=7"));
+}
+
+#[get("/key/<uid>")]
+fn key(uid: &str, pinned: PinnedVersion) -> String {
+    pinned.key(uid)
+}
+
+#[test]
+fn test_pinned_version_guard() {
+    let client = Client::tracked(rocket::build().mount("/", routes![key])).unwrap();
+    let get = |uri: &str, header: Option<&str>| {
+        let mut request = client.get(uri.to_string());
+        if let Some(id) = header {
+            request = request.header(Header::new(VERSION_HEADER, id.to_string()));
+        }
+        let response = request.dispatch();
+        (
+            response.status(),
+            response.into_string().unwrap_or_default(),
+        )
+    };
+    assert_eq!(get("/key/a", None), (Status::Ok, String::from("a")));
+    assert_eq!(
+        get("/key/a?versionId=7", None),
+        (Status::Ok, String::from("a@version-7"))
+    );
+    assert_eq!(
+        get("/key/a", Some("8")),
+        (Status::Ok, String::from("a@version-8"))
+    );
+    assert_eq!(
+        get("/key/a?versionId=7", Some("8")),
+        (Status::Ok, String::from("a@version-7"))
+    );
+    assert_eq!(get("/key/a?versionId=a%2Fb", None).0, Status::BadRequest);
+}
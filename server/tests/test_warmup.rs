@@ -0,0 +1,129 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use istziio_server_node::cache::{CacheOptions, ConcurrentDiskCache};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{
+    FetchedObject, ListRequest, ObjectInfo, ObjectListing, StorageConnector,
+};
+use istziio_server_node::warmup::{read_keys, WarmUp, WarmUpConfig};
+use rocket::futures::stream;
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::sync::Arc;
+
+/// Objects whose body is their key, except `missing`; lists two objects per prefix, a
+/// page at a time.
+struct Bucket;
+
+#[async_trait]
+impl StorageConnector for Bucket {
+    async fn fetch_stream(&self, file_name: &str) -> IoResult<FetchedObject> {
+        if file_name == "missing" {
+            return Err(Error::new(ErrorKind::NotFound, "no such key"));
+        }
+        let body = Bytes::from(file_name.to_string());
+        Ok(FetchedObject {
+            content_length: Some(body.len() as u64),
+            object_size: Some(body.len() as u64),
+            version: Default::default(),
+            stream: Box::pin(stream::iter(vec![Ok(body)])),
+        })
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+
+    async fn list_objects(&self, request: &ListRequest) -> IoResult<ObjectListing> {
+        let (name, next) = match request.continuation_token.as_deref() {
+            None => ("part-0", Some(String::from("page-2"))),
+            Some(_) => ("part-1", None),
+        };
+        Ok(ObjectListing {
+            objects: vec![ObjectInfo {
+                key: format!("{}{}", request.prefix, name),
+                size: 1,
+                last_modified: None,
+                e_tag: None,
+            }],
+            common_prefixes: Vec::new(),
+            next_continuation_token: next,
+        })
+    }
+}
+
+#[test]
+fn test_read_keys() {
+    let manifest = "# hot tables\norders.csv\n\n  lineitem/part-0.parquet  \n";
+    assert_eq!(
+        read_keys(manifest),
+        ["orders.csv", "lineitem/part-0.parquet"]
+    );
+}
+
+#[tokio::test]
+async fn test_warm_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().join("cache"),
+        1_000_000,
+        2,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ));
+    let manifest = dir.path().join("manifest.txt");
+    std::fs::write(
+        &manifest,
+        "orders.csv\nmissing\n# comment\norders.csv\nt/part-0\n",
+    )
+    .unwrap();
+    let warm_up = Arc::new(WarmUp::new(WarmUpConfig {
+        manifest: Some(manifest.to_string_lossy().into_owned()),
+        prefixes: vec![String::from("t/")],
+        before_serving: true,
+        ..Default::default()
+    }));
+    assert!(warm_up.holds_traffic());
+
+    warm_up
+        .clone()
+        .run(cache.clone(), vec![Arc::new(Bucket)])
+        .await;
+    let status = warm_up.status();
+    assert!(status.done && !status.running);
+    assert!(!warm_up.holds_traffic());
+    // orders.csv is named twice, and t/part-0 by the manifest and the prefix; each counts once.
+    assert_eq!(status.keys, 4);
+    assert_eq!(status.warmed, 3);
+    assert_eq!(status.failed, 1);
+    assert_eq!(
+        status.bytes,
+        ("orders.csv".len() + 2 * "t/part-0".len()) as u64
+    );
+    assert!(status.last_error.unwrap().starts_with("missing: "));
+    for key in ["orders.csv", "t/part-0", "t/part-1"] {
+        assert!(cache.open_object(key).await.is_some(), "{}", key);
+    }
+}
+
+#[tokio::test]
+async fn test_warm_up_without_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        dir.path().to_path_buf(),
+        1_000_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ));
+    let warm_up = Arc::new(WarmUp::new(WarmUpConfig {
+        manifest: Some(dir.path().join("absent").to_string_lossy().into_owned()),
+        ..Default::default()
+    }));
+    assert!(!warm_up.holds_traffic());
+    warm_up.clone().run(cache, vec![Arc::new(Bucket)]).await;
+    let status = warm_up.status();
+    assert!(status.done);
+    assert_eq!(status.keys, 0);
+    assert!(status.last_error.unwrap().starts_with("reading "));
+}
@@ -0,0 +1,374 @@
+use async_trait::async_trait;
+use istziio_server_node::auth::AuthConfig;
+use istziio_server_node::cache::{
+    CacheOptions, ConcurrentDiskCache, GetFileOptions, GetFileResult,
+};
+use istziio_server_node::chunk::ByteRange;
+use istziio_server_node::encryption::EncryptionKey;
+use istziio_server_node::metadata::InProcessStore;
+use istziio_server_node::storage::storage_connector::{FetchedObject, StorageConnector};
+use istziio_server_node::writeback::{self, WriteBackConfig, WriteBackQueue, WriteBackStatus};
+use rocket::futures::StreamExt;
+use rocket::http::{Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::routes;
+use std::io::{self, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Takes uploads after refusing the first `failures`; has nothing to fetch.
+#[derive(Default)]
+struct UploadConnector {
+    failures: AtomicU32,
+    uploads: Mutex<Vec<(String, String)>>,
+}
+
+impl UploadConnector {
+    fn failing(failures: u32) -> Arc<Self> {
+        let connector = Self::default();
+        connector.failures.store(failures, Ordering::SeqCst);
+        Arc::new(connector)
+    }
+
+    fn uploads(&self) -> Vec<(String, String)> {
+        self.uploads.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl StorageConnector for UploadConnector {
+    async fn fetch_stream(&self, _file_name: &str) -> IoResult<FetchedObject> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "not in S3"))
+    }
+
+    async fn fetch_range(&self, file_name: &str, _range: ByteRange) -> IoResult<FetchedObject> {
+        self.fetch_stream(file_name).await
+    }
+
+    async fn put_object(&self, file_name: &str, path: &Path) -> IoResult<()> {
+        let refuse = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if refuse {
+            return Err(io::Error::other("503 Slow Down"));
+        }
+        let body = tokio::fs::read_to_string(path).await?;
+        self.uploads
+            .lock()
+            .unwrap()
+            .push((file_name.to_string(), body));
+        Ok(())
+    }
+}
+
+fn config(dir: &Path) -> WriteBackConfig {
+    WriteBackConfig {
+        dir: dir.to_string_lossy().into_owned(),
+        retry_delay_ms: 10,
+        ..WriteBackConfig::default()
+    }
+}
+
+fn start(queue: &Arc<WriteBackQueue>, connector: &Arc<UploadConnector>) {
+    let connectors: Vec<Arc<dyn StorageConnector + Send + Sync>> = vec![connector.clone()];
+    tokio::spawn(queue.clone().run(connectors));
+}
+
+fn journal_files(dir: &Path) -> usize {
+    std::fs::read_dir(dir).unwrap().count()
+}
+
+#[test]
+fn test_retry_delay() {
+    let config = WriteBackConfig::default();
+    assert_eq!(config.retry_delay(1), Duration::from_secs(1));
+    assert_eq!(config.retry_delay(3), Duration::from_secs(4));
+    assert_eq!(config.retry_delay(40), Duration::from_secs(60));
+}
+
+#[tokio::test]
+async fn test_accepted_writes_are_uploaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let queue = Arc::new(WriteBackQueue::open(config(dir.path())).unwrap());
+    queue.accept("a.csv", &b"one"[..]).await.unwrap();
+    let journaled = queue.accept("b.csv", &b"two!"[..]).await.unwrap();
+    assert_eq!(journaled.size, 4);
+    assert_eq!(std::fs::read(&journaled.path).unwrap(), b"two!");
+    let status = queue.status();
+    assert_eq!((status.depth, status.pending_bytes), (2, 7));
+
+    let connector = Arc::new(UploadConnector::default());
+    start(&queue, &connector);
+    assert!(queue.flush(Duration::from_secs(5)).await);
+    let mut uploads = connector.uploads();
+    uploads.sort();
+    assert_eq!(
+        uploads,
+        vec![
+            (String::from("a.csv"), String::from("one")),
+            (String::from("b.csv"), String::from("two!"))
+        ]
+    );
+    let status = queue.status();
+    assert_eq!(
+        status,
+        WriteBackStatus {
+            accepted: 2,
+            uploaded: 2,
+            bytes_uploaded: 7,
+            ..WriteBackStatus::default()
+        }
+    );
+    assert_eq!(journal_files(dir.path()), 0);
+}
+
+#[tokio::test]
+async fn test_newer_writes_replace_queued_ones() {
+    let dir = tempfile::tempdir().unwrap();
+    let queue = Arc::new(WriteBackQueue::open(config(dir.path())).unwrap());
+    queue.accept("k", &b"v1"[..]).await.unwrap();
+    queue.accept("k", &b"v2"[..]).await.unwrap();
+    let status = queue.status();
+    assert_eq!((status.depth, status.superseded), (1, 1));
+    assert_eq!(journal_files(dir.path()), 2);
+
+    let connector = Arc::new(UploadConnector::default());
+    start(&queue, &connector);
+    assert!(queue.flush(Duration::from_secs(5)).await);
+    assert_eq!(
+        connector.uploads(),
+        vec![(String::from("k"), String::from("v2"))]
+    );
+}
+
+#[tokio::test]
+async fn test_failed_uploads_are_retried() {
+    let dir = tempfile::tempdir().unwrap();
+    let queue = Arc::new(WriteBackQueue::open(config(dir.path())).unwrap());
+    let connector = UploadConnector::failing(2);
+    start(&queue, &connector);
+    queue.accept("k", &b"v"[..]).await.unwrap();
+    assert!(queue.flush(Duration::from_secs(5)).await);
+    assert_eq!(connector.uploads().len(), 1);
+    let status = queue.status();
+    assert_eq!((status.uploaded, status.failed_attempts), (1, 2));
+    assert_eq!(status.last_error.as_deref(), Some("k: 503 Slow Down"));
+
+    let stuck = UploadConnector::failing(u32::MAX);
+    let queue = Arc::new(WriteBackQueue::open(config(dir.path())).unwrap());
+    start(&queue, &stuck);
+    queue.accept("k", &b"v"[..]).await.unwrap();
+    assert!(!queue.flush(Duration::from_millis(100)).await);
+    assert_eq!(queue.status().depth, 1);
+}
+
+#[tokio::test]
+async fn test_journal_is_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let queue = WriteBackQueue::open(config(dir.path())).unwrap();
+        queue.accept("a", &b"1"[..]).await.unwrap();
+        queue.accept("b", &b"2"[..]).await.unwrap();
+        queue.accept("a", &b"3"[..]).await.unwrap();
+    }
+    // A crash in the middle of a PUT leaves a body without its key.
+    std::fs::write(dir.path().join("00000000000000000007.data"), "partial").unwrap();
+
+    let queue = Arc::new(WriteBackQueue::open(config(dir.path())).unwrap());
+    assert_eq!(queue.status().depth, 2);
+    assert_eq!(journal_files(dir.path()), 4);
+    assert!(queue.accept("c", &b"4"[..]).await.unwrap().seq > 7);
+
+    let connector = Arc::new(UploadConnector::default());
+    start(&queue, &connector);
+    assert!(queue.flush(Duration::from_secs(5)).await);
+    let mut uploads = connector.uploads();
+    uploads.sort();
+    assert_eq!(
+        uploads,
+        vec![
+            (String::from("a"), String::from("3")),
+            (String::from("b"), String::from("2")),
+            (String::from("c"), String::from("4"))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_oversized_bodies_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let queue = WriteBackQueue::open(WriteBackConfig {
+        max_object_size: 3,
+        ..config(dir.path())
+    })
+    .unwrap();
+    let err = queue.accept("k", &b"abcd"[..]).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+    assert_eq!(queue.status().depth, 0);
+    assert_eq!(journal_files(dir.path()), 0);
+}
+
+#[tokio::test]
+async fn test_put_route() {
+    let root = tempfile::tempdir().unwrap();
+    let journal = root.path().join("journal");
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        root.path().join("cache"),
+        10_000,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions::default(),
+    ));
+    let queue = Arc::new(
+        WriteBackQueue::open(WriteBackConfig {
+            max_object_size: 16,
+            ..config(&journal)
+        })
+        .unwrap(),
+    );
+    let client = Client::tracked(
+        rocket::build()
+            .manage(AuthConfig {
+                admin_token: Some(String::from("admin")),
+                read_tokens: Vec::new(),
+            })
+            .manage(Some(queue.clone()))
+            .manage(cache.clone())
+            .mount(
+                "/",
+                routes![
+                    writeback::put_object,
+                    writeback::write_back_status,
+                    writeback::flush
+                ],
+            ),
+    )
+    .await
+    .unwrap();
+    let admin = || Header::new("Authorization", "Bearer admin");
+
+    let response = client.put("/s3/orders.csv").body("1,2,3").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client
+        .put("/s3/orders.csv")
+        .header(admin())
+        .body("1,2,3")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Accepted);
+    let response = client
+        .put("/s3/big.csv")
+        .header(admin())
+        .body("x".repeat(17))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+
+    let status: WriteBackStatus = client
+        .get("/admin/write-back")
+        .header(admin())
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!((status.depth, status.accepted), (1, 1));
+
+    // Reads see the write before it reaches S3.
+    let connector = Arc::new(UploadConnector::default());
+    match cache
+        .get_file(
+            PathBuf::from("orders.csv"),
+            connector.clone(),
+            GetFileOptions::default(),
+        )
+        .await
+    {
+        GetFileResult::Hit(file) => assert_eq!(file.len(), 5),
+        _ => panic!("the written object should be cached"),
+    }
+
+    start(&queue, &connector);
+    let response = client
+        .post("/admin/write-back/flush?timeout_secs=5")
+        .header(admin())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        connector.uploads(),
+        vec![(String::from("orders.csv"), String::from("1,2,3"))]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_journal_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let journal = dir.path().join("journal");
+    std::fs::create_dir(&journal).unwrap();
+    std::fs::set_permissions(&journal, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let queue = WriteBackQueue::open(config(&journal)).unwrap();
+    let journaled = queue.accept("a.csv", &b"one"[..]).await.unwrap();
+    let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&journal), 0o700);
+    for file in std::fs::read_dir(&journal).unwrap() {
+        assert_eq!(mode(&file.unwrap().path()), 0o600);
+    }
+    assert_eq!(std::fs::read(&journaled.path).unwrap(), b"one");
+}
+
+#[tokio::test]
+async fn test_written_objects_are_cached_sealed() {
+    let root = tempfile::tempdir().unwrap();
+    let cache_dir = root.path().join("cache");
+    let cache = Arc::new(ConcurrentDiskCache::new(
+        cache_dir.clone(),
+        1024 * 1024,
+        1,
+        Box::new(InProcessStore::new(6379)),
+        CacheOptions {
+            encryption: Some(Arc::new(EncryptionKey::from_bytes(&[7u8; 32]).unwrap())),
+            ..Default::default()
+        },
+    ));
+    let queue = Arc::new(WriteBackQueue::open(config(&root.path().join("journal"))).unwrap());
+    let client = Client::tracked(
+        rocket::build()
+            .manage(AuthConfig {
+                admin_token: Some(String::from("admin")),
+                read_tokens: Vec::new(),
+            })
+            .manage(Some(queue))
+            .manage(cache.clone())
+            .mount("/", routes![writeback::put_object]),
+    )
+    .await
+    .unwrap();
+    let marker = "written back, never cached in the clear;";
+    let response = client
+        .put("/s3/orders.csv")
+        .header(Header::new("Authorization", "Bearer admin"))
+        .body(marker.repeat(100))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Accepted);
+    for file in std::fs::read_dir(&cache_dir).unwrap() {
+        let contents = std::fs::read(file.unwrap().path()).unwrap();
+        assert!(!contents
+            .windows(marker.len())
+            .any(|window| window == marker.as_bytes()));
+    }
+    let cached: Vec<u8> = cache
+        .open_object("orders.csv")
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap().to_vec())
+        .concat()
+        .await;
+    assert_eq!(cached, marker.repeat(100).into_bytes());
+}
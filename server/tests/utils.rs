@@ -1,7 +1,15 @@
 use istziio_server_node::server::{ServerConfig, ServerNode};
+use rocket::http::Header;
 use rocket::local::blocking::Client;
 use std::env;
 
+/// Admin token configured on the test nodes, needed for `/clear`.
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+pub fn admin_auth() -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", ADMIN_TOKEN))
+}
+
 pub fn get_server_config_mocks3(redis_port: u16) -> ServerConfig {
     ServerConfig {
         server_ip: String::from("127.0.0.1"),
@@ -14,6 +22,10 @@ pub fn get_server_config_mocks3(redis_port: u16) -> ServerConfig {
         secret_key: None,
         max_size: 192,
         bucket_size: 3,
+        admin_token: Some(ADMIN_TOKEN.into()),
+        // The tests check Redis and S3 themselves, as they need them.
+        self_test: false,
+        ..Default::default()
     }
 }
 
@@ -33,6 +45,10 @@ pub fn get_server_config_s3(
         secret_key: Some(aws_secret_key),
         max_size: 192,
         bucket_size: 3,
+        admin_token: Some(ADMIN_TOKEN.into()),
+        // The tests check Redis and S3 themselves, as they need them.
+        self_test: false,
+        ..Default::default()
     }
 }
 
@@ -54,9 +70,9 @@ pub fn launch_server_node_size_3(mocks3: bool) -> ([ServerNode; 3], [Client; 3])
         )
     };
 
-    let node_1 = ServerNode::new(config_1);
-    let node_2 = ServerNode::new(config_2);
-    let node_3 = ServerNode::new(config_3);
+    let node_1 = ServerNode::new(config_1).expect("valid server config");
+    let node_2 = ServerNode::new(config_2).expect("valid server config");
+    let node_3 = ServerNode::new(config_3).expect("valid server config");
 
     let client_1 = Client::tracked(node_1.build()).expect("valid rocket instance");
     let client_2 = Client::tracked(node_2.build()).expect("valid rocket instance");